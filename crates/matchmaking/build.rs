@@ -2,6 +2,9 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
     tonic_prost_build::configure()
         .build_client(true)
         .build_server(true)
-        .compile_protos(&["protos/matchmaking.proto"], &["protos"])?;
+        .compile_protos(
+            &["protos/matchmaking.proto", "protos/matchmaking_v2.proto"],
+            &["protos"],
+        )?;
     Ok(())
 }
@@ -0,0 +1,25 @@
+CREATE TABLE IF NOT EXISTS match_records (
+    match_id UUID PRIMARY KEY,
+    host_id UUID NOT NULL,
+    region TEXT NOT NULL,
+    playlist TEXT NOT NULL,
+    mission TEXT NOT NULL,
+    difficulty INTEGER NOT NULL,
+    won BOOLEAN NOT NULL,
+    player_ids UUID[] NOT NULL,
+    completed_at TIMESTAMPTZ NOT NULL
+);
+
+CREATE INDEX IF NOT EXISTS match_records_completed_at ON match_records (completed_at);
+
+CREATE TABLE IF NOT EXISTS rating_changes (
+    match_id UUID NOT NULL REFERENCES match_records (match_id),
+    player_id UUID NOT NULL,
+    rating_before DOUBLE PRECISION NOT NULL,
+    uncertainty_before DOUBLE PRECISION NOT NULL,
+    rating_after DOUBLE PRECISION NOT NULL,
+    uncertainty_after DOUBLE PRECISION NOT NULL,
+    PRIMARY KEY (match_id, player_id)
+);
+
+CREATE INDEX IF NOT EXISTS rating_changes_player_id ON rating_changes (player_id);
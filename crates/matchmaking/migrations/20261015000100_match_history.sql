@@ -0,0 +1,3 @@
+CREATE INDEX IF NOT EXISTS match_records_player_ids ON match_records USING GIN (player_ids);
+
+CREATE INDEX IF NOT EXISTS match_records_history ON match_records (completed_at DESC, match_id DESC);
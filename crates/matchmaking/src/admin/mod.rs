@@ -0,0 +1,237 @@
+//! Operations of the admin RPCs called by the `matchmaking-admin` binary, so the runbook no longer
+//! needs `redis-cli` and manual bitcode decoding: queue sizes, the queued state of a player,
+//! force-removing a player, the matches in a state, and ticks requested from the leader worker.
+//!
+//! Requested ticks are polled by the leader every [`TICK_POLL`], see
+//! [`crate::rpc::worker::MatchmakingWorker::wait_next_run`].
+
+use std::time::Duration;
+
+use redis::{AsyncCommands, RedisError, aio::MultiplexedConnection};
+use tracing::error;
+use uuid::Uuid;
+
+use crate::{
+    audit::{self, Action, Actor, AuditEvent},
+    codec,
+    lifecycle::MatchState,
+    namespace,
+    rpc::{
+        BACKFILL_QUEUE, CLOSED_MATCHES, CREATE_MATCH_QUEUE, Match, OPEN_MATCHES, PLAYER_QUEUE,
+        QueuedPlayer, RAID_QUEUE, VERSUS_QUEUE,
+    },
+    snapshot, store,
+};
+
+/// Set by [`request_tick`], taken by the leader worker
+pub const TICK_REQUEST_KEY: &str = "worker:tick";
+/// How often the leader checks for a requested tick while it waits for the next run
+pub const TICK_POLL: Duration = Duration::from_secs(1);
+/// Requests left unanswered, e.g. while no worker runs, expire
+const TICK_REQUEST_TTL: u64 = 60;
+
+#[derive(Debug, thiserror::Error)]
+pub enum Error {
+    #[error(transparent)]
+    Redis(#[from] RedisError),
+    #[error(transparent)]
+    BitcodeDeser(#[from] codec::Error),
+}
+
+impl From<Error> for tonic::Status {
+    fn from(_: Error) -> Self {
+        Self::internal("Failed to load the matchmaking state")
+    }
+}
+
+pub fn tick_request_key() -> String {
+    namespace::key(TICK_REQUEST_KEY)
+}
+
+/// Entries of a queue, `key` is relative to the namespace
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct QueueSize {
+    pub key: String,
+    pub size: usize,
+}
+
+/// Sizes of the queues and of the open and closed match sets, by key
+pub async fn queue_sizes(conn: &mut MultiplexedConnection) -> Result<Vec<QueueSize>, Error> {
+    let prefix = namespace::key("");
+    let mut keys = Vec::new();
+    for pattern in [
+        format!("{PLAYER_QUEUE}:*"),
+        format!("{CREATE_MATCH_QUEUE}:*"),
+        format!("{BACKFILL_QUEUE}:*"),
+        format!("{VERSUS_QUEUE}:*"),
+        format!("{RAID_QUEUE}:*"),
+        format!("{OPEN_MATCHES}:*"),
+        CLOSED_MATCHES.to_string(),
+    ] {
+        keys.extend(snapshot::scan_keys(conn, &namespace::key(pattern)).await?);
+    }
+
+    let mut sizes = Vec::new();
+    for key in keys {
+        // backfill slots are hashes next to the backfill queues
+        let kind: String = redis::cmd("TYPE").arg(&key).query_async(conn).await?;
+        if kind != "zset" {
+            continue;
+        }
+        let size: usize = conn.zcard(&key).await?;
+        sizes.push(QueueSize {
+            key: key.strip_prefix(&prefix).unwrap_or(&key).to_string(),
+            size,
+        });
+    }
+
+    Ok(sizes)
+}
+
+/// Queued state of `player_id`, `None` when it is not queued
+pub async fn queued_player(
+    conn: &mut MultiplexedConnection,
+    player_id: &Uuid,
+) -> Result<Option<QueuedPlayer>, Error> {
+    let Some(data) = store::player_data(conn, player_id).await? else {
+        return Ok(None);
+    };
+
+    Ok(Some(codec::decode(&data)?))
+}
+
+/// Removes `player_id` from every queue on behalf of `actor`, the worker drops it from the open
+/// match it was pencilled into. Returns whether the player was queued.
+pub async fn remove_player(
+    conn: &mut MultiplexedConnection,
+    player_id: &Uuid,
+    actor: Actor,
+    now: i64,
+) -> Result<bool, Error> {
+    if !store::remove_player(conn, player_id).await? {
+        return Ok(false);
+    }
+    let removed =
+        AuditEvent::new(Action::PlayerLeft, actor, player_id, now).with_reason("force_removed");
+    audit::emit(conn, &removed).await;
+
+    Ok(true)
+}
+
+/// Matches indexed in `state`, expired matches are skipped and undecodable ones are logged and
+/// skipped
+pub async fn matches_in_state(
+    conn: &mut MultiplexedConnection,
+    state: MatchState,
+) -> Result<Vec<Match>, Error> {
+    let states: Vec<(Uuid, String)> = conn.hgetall(store::match_state_index()).await?;
+    let match_ids: Vec<Uuid> = states
+        .into_iter()
+        .filter(|(_, name)| store::parse_state(name) == Some(state))
+        .map(|(match_id, _)| match_id)
+        .collect();
+    if match_ids.is_empty() {
+        return Ok(Vec::new());
+    }
+
+    let matches = store::matches_data(conn, &match_ids).await?;
+
+    Ok(match_ids
+        .iter()
+        .zip(matches)
+        .filter_map(|(match_id, data)| {
+            codec::decode(&data?)
+                .inspect_err(|err| error!("failed to decode match `{match_id}`: {err}"))
+                .ok()
+        })
+        .collect())
+}
+
+/// Asks the leader worker to run a tick without waiting for its interval
+pub async fn request_tick(conn: &mut MultiplexedConnection) -> Result<(), Error> {
+    conn.set_ex(tick_request_key(), 1, TICK_REQUEST_TTL)
+        .await
+        .map_err(Error::from)
+}
+
+/// Takes a requested tick, `true` for a single caller per request
+pub async fn take_tick_request(conn: &mut MultiplexedConnection) -> Result<bool, Error> {
+    let taken: usize = conn.del(tick_request_key()).await?;
+
+    Ok(taken > 0)
+}
+
+#[cfg(test)]
+mod tests {
+    use skillratings::mhth::MhthRating;
+
+    use super::*;
+    use crate::{
+        lifecycle::Lifecycle,
+        rpc::{match_id_key, matchmaking::Player, player_queue_key, server::TWELVE_MINUTES},
+        test_support::{create_redis, redis_client},
+    };
+
+    #[tokio::test]
+    async fn inspect_and_remove_queued_players() {
+        let container = create_redis(6379).await;
+        let host = container.get_host().await.unwrap();
+        let port = container.get_host_port_ipv4(6379).await.unwrap();
+        let client = redis_client(host.to_string(), port);
+        let mut conn = client.get_multiplexed_async_connection().await.unwrap();
+        let player: QueuedPlayer = (
+            Uuid::new_v4(),
+            Player {
+                region: "CAN".to_string(),
+                ..Default::default()
+            },
+            MhthRating::default(),
+        )
+            .into();
+        let encoded = codec::encode(&player);
+        let mut pipe = redis::pipe();
+        store::put_player(&mut pipe, &player, &encoded, 200);
+        pipe.zadd(player_queue_key(&player), &encoded, player.join_time)
+            .ignore();
+        let mut forming = Match::host(&player, &[]).unwrap();
+        forming.lifecycle = Lifecycle::forming(0);
+        store::put_match(&mut pipe, &forming, TWELVE_MINUTES);
+        store::index_match(&mut pipe, &forming);
+        let corrupted = Uuid::new_v4();
+        pipe.hset(match_id_key(&corrupted), store::DATA, b"corrupted")
+            .ignore()
+            .hset(store::match_state_index(), corrupted, "forming")
+            .ignore();
+        pipe.query_async(&mut conn).await.map(|_: ()| ()).unwrap();
+
+        let sizes = queue_sizes(&mut conn).await.unwrap();
+        let queued = queued_player(&mut conn, &player.player_id).await.unwrap();
+        let open = matches_in_state(&mut conn, MatchState::Forming)
+            .await
+            .unwrap();
+        let removed = remove_player(&mut conn, &player.player_id, Actor::Worker, 0)
+            .await
+            .unwrap();
+        let removed_again = remove_player(&mut conn, &player.player_id, Actor::Worker, 0)
+            .await
+            .unwrap();
+        let sizes_after = queue_sizes(&mut conn).await.unwrap();
+        request_tick(&mut conn).await.unwrap();
+        let taken = take_tick_request(&mut conn).await.unwrap();
+        let taken_again = take_tick_request(&mut conn).await.unwrap();
+
+        assert_eq!(
+            sizes,
+            vec![QueueSize {
+                key: player_queue_key(&player),
+                size: 1
+            }]
+        );
+        assert_eq!(queued.unwrap().player_id, player.player_id);
+        assert_eq!(open.len(), 1);
+        assert_eq!(open[0].id, forming.id);
+        assert!(removed && !removed_again);
+        assert!(sizes_after.is_empty());
+        assert!(taken && !taken_again);
+    }
+}
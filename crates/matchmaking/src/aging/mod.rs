@@ -0,0 +1,91 @@
+//! Wait-time aging. The soft constraints of a queued player (skill window, ping tier, trust,
+//! content, input, language and voice preferences) relax with its wait, the last of them after
+//! [`RELAXED_AFTER_SECS`]. Regions setting `max_wait_secs` in their tuning age their players
+//! faster, so every soft constraint is relaxed once a player waited `max_wait_secs`, and aged
+//! players are picked before the players who joined after them.
+
+use crate::rpc::QueuedPlayer;
+
+/// Wait after which the default thresholds relaxed every soft constraint
+pub const RELAXED_AFTER_SECS: i64 = 4 * 60;
+
+/// Aging of a region, see [`crate::regions::tuning::RegionTuning::aging`]
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Aging {
+    /// Longest wait before every soft constraint of a player is relaxed, `None` keeps the
+    /// default thresholds
+    pub max_wait_secs: Option<i64>,
+}
+
+impl Aging {
+    /// Time the constraints of `player` are checked at: `now` without aging, otherwise its wait
+    /// is stretched so the thresholds relaxing last are reached after `max_wait_secs`
+    pub fn aged_now(&self, player: &QueuedPlayer, now: i64) -> i64 {
+        let Some(max_wait) = self.max_wait_secs.filter(|max_wait| *max_wait > 0) else {
+            return now;
+        };
+        let relaxed = RELAXED_AFTER_SECS.max(player.params.input_pool_secs);
+        let waited = now - player.join_time;
+        if max_wait >= relaxed || waited <= 0 {
+            return now;
+        }
+
+        player.join_time + waited * relaxed / max_wait
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use uuid::Uuid;
+
+    use super::*;
+    use crate::rpc::{Match, matchmaking::Player, worker::can_match::more_than_minutes};
+
+    fn player(join_time: i64) -> QueuedPlayer {
+        let mut player: QueuedPlayer =
+            (Uuid::new_v4(), Player::default(), Default::default()).into();
+        player.join_time = join_time;
+        player
+    }
+
+    #[test]
+    fn aged_players_relax_by_the_max_wait() {
+        let aging = Aging {
+            max_wait_secs: Some(60),
+        };
+        let waiting = player(1_000);
+
+        assert_eq!(Aging::default().aged_now(&waiting, 1_060), 1_060);
+        assert_eq!(
+            aging.aged_now(&waiting, 1_030),
+            1_000 + RELAXED_AFTER_SECS / 2
+        );
+        assert!(more_than_minutes(
+            3,
+            waiting.join_time,
+            aging.aged_now(&waiting, 1_060)
+        ));
+        // slower than the default thresholds
+        let patient = Aging {
+            max_wait_secs: Some(600),
+        };
+        assert_eq!(patient.aged_now(&waiting, 1_060), 1_060);
+    }
+
+    #[test]
+    fn aged_players_fit_any_match() {
+        let aging = Aging {
+            max_wait_secs: Some(60),
+        };
+        let mut hosting = Match::host(&player(0), &[]).unwrap();
+        hosting.players[0].mission_types = vec!["escort".to_string()];
+        let mut picky = player(1_000);
+        picky.trust = 0.;
+        picky.mission_types = vec!["hunt".to_string()];
+
+        assert!(!hosting.is_trust_fit(&picky, 1_060));
+        assert!(!hosting.is_content_fit(&picky, 1_060));
+        assert!(hosting.is_trust_fit(&picky, aging.aged_now(&picky, 1_060)));
+        assert!(hosting.is_content_fit(&picky, aging.aged_now(&picky, 1_060)));
+    }
+}
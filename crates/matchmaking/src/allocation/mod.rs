@@ -0,0 +1,280 @@
+//! Dedicated game server allocation. A ready match is given a game server by
+//! Agones or GameLift before its Nakama match is created.
+
+use bitcode::{Decode, Encode};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use tracing::error;
+
+use crate::rpc::Match;
+
+/// Env var selecting the backend, `agones` or `gamelift`. Matches get no game server when unset.
+pub const ALLOCATOR_VAR: &str = "ALLOCATOR";
+/// Agones allocator service, e.g. `https://agones-allocator:443`
+pub const AGONES_URL_VAR: &str = "AGONES_ALLOCATOR_URL";
+pub const AGONES_NAMESPACE_VAR: &str = "AGONES_NAMESPACE";
+pub const AGONES_FLEET_VAR: &str = "AGONES_FLEET";
+/// GameLift endpoint, requests are signed by a SigV4 signing proxy in front of it
+pub const GAMELIFT_URL_VAR: &str = "GAMELIFT_ENDPOINT";
+pub const GAMELIFT_FLEET_VAR: &str = "GAMELIFT_FLEET_ID";
+
+#[derive(Debug, thiserror::Error)]
+pub enum Error {
+    #[error("unknown {ALLOCATOR_VAR} `{0}`, expected `agones` or `gamelift`")]
+    UnknownAllocator(String),
+    #[error("`{0}` not set")]
+    MissingVar(&'static str),
+    #[error("allocation request failed: {0}")]
+    Request(#[from] reqwest::Error),
+    #[error("no game server available")]
+    Exhausted,
+}
+
+/// Address players connect to
+#[derive(Debug, Clone, Serialize, Deserialize, Encode, Decode, PartialEq, Eq)]
+pub struct GameServer {
+    pub host: String,
+    pub port: u16,
+}
+
+impl GameServer {
+    pub fn address(&self) -> String {
+        format!("{}:{}", self.host, self.port)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Allocator {
+    Agones {
+        url: String,
+        namespace: String,
+        fleet: String,
+    },
+    GameLift {
+        url: String,
+        fleet_id: String,
+    },
+}
+
+impl Allocator {
+    /// Backend selected by [`ALLOCATOR_VAR`], `None` when unset
+    pub fn from_env() -> Result<Option<Self>, Error> {
+        let var = |name: &'static str| std::env::var(name).map_err(|_| Error::MissingVar(name));
+        let Ok(allocator) = std::env::var(ALLOCATOR_VAR) else {
+            return Ok(None);
+        };
+
+        match allocator.as_str() {
+            "agones" => Ok(Some(Self::Agones {
+                url: var(AGONES_URL_VAR)?,
+                namespace: var(AGONES_NAMESPACE_VAR).unwrap_or_else(|_| "default".to_string()),
+                fleet: var(AGONES_FLEET_VAR)?,
+            })),
+            "gamelift" => Ok(Some(Self::GameLift {
+                url: var(GAMELIFT_URL_VAR)?,
+                fleet_id: var(GAMELIFT_FLEET_VAR)?,
+            })),
+            _ => Err(Error::UnknownAllocator(allocator)),
+        }
+    }
+
+    /// Requests a game server for `ready`
+    pub async fn allocate(
+        &self,
+        http_client: &reqwest::Client,
+        ready: &Match,
+    ) -> Result<GameServer, Error> {
+        match self {
+            Self::Agones {
+                url,
+                namespace,
+                fleet,
+            } => agones(http_client, url, namespace, fleet, ready).await,
+            Self::GameLift { url, fleet_id } => gamelift(http_client, url, fleet_id, ready).await,
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct AgonesAllocation {
+    address: String,
+    #[serde(default)]
+    ports: Vec<AgonesPort>,
+}
+
+#[derive(Debug, Deserialize)]
+struct AgonesPort {
+    port: u16,
+}
+
+async fn agones(
+    http_client: &reqwest::Client,
+    url: &str,
+    namespace: &str,
+    fleet: &str,
+    ready: &Match,
+) -> Result<GameServer, Error> {
+    let allocation: AgonesAllocation = http_client
+        .post(format!("{url}/gameserverallocation"))
+        .json(&json!({
+            "namespace": namespace,
+            "gameServerSelectors": [{ "matchLabels": { "agones.dev/fleet": fleet } }],
+            "metadata": { "labels": { "mhth/match": ready.id.to_string() } },
+        }))
+        .send()
+        .await
+        .inspect_err(|err| error!("Agones Request Error: {err:?}"))?
+        .error_for_status()
+        .inspect_err(|err| error!("Agones Allocation Error: {err:?}"))?
+        .json()
+        .await?;
+    let port = allocation.ports.first().ok_or(Error::Exhausted)?.port;
+
+    Ok(GameServer {
+        host: allocation.address,
+        port,
+    })
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "PascalCase")]
+struct GameLiftSession {
+    game_session: GameLiftGameSession,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "PascalCase")]
+struct GameLiftGameSession {
+    ip_address: String,
+    #[serde(default)]
+    dns_name: Option<String>,
+    port: u16,
+}
+
+async fn gamelift(
+    http_client: &reqwest::Client,
+    url: &str,
+    fleet_id: &str,
+    ready: &Match,
+) -> Result<GameServer, Error> {
+    let session: GameLiftSession = http_client
+        .post(url)
+        .header("X-Amz-Target", "GameLift.CreateGameSession")
+        .header("Content-Type", "application/x-amz-json-1.1")
+        .body(
+            json!({
+                "FleetId": fleet_id,
+                "MaximumPlayerSessionCount": ready.params.max_players,
+                "IdempotencyToken": ready.id.to_string(),
+                "Location": ready.location(),
+            })
+            .to_string(),
+        )
+        .send()
+        .await
+        .inspect_err(|err| error!("GameLift Request Error: {err:?}"))?
+        .error_for_status()
+        .inspect_err(|err| error!("GameLift Session Error: {err:?}"))?
+        .json()
+        .await?;
+    let session = session.game_session;
+
+    Ok(GameServer {
+        host: session
+            .dns_name
+            .filter(|dns| !dns.is_empty())
+            .unwrap_or(session.ip_address),
+        port: session.port,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use httpmock::prelude::*;
+    use skillratings::mhth::MhthRating;
+    use uuid::Uuid;
+
+    use super::*;
+    use crate::rpc::{QueuedPlayer, matchmaking::Player};
+
+    #[tokio::test]
+    async fn agones_allocation() {
+        let server = MockServer::start_async().await;
+        let ready = ready_match();
+        let mock = server
+            .mock_async(|when, then| {
+                when.method(POST).path("/gameserverallocation").json_body_includes(
+                    r#"{"gameServerSelectors": [{"matchLabels": {"agones.dev/fleet": "mhth"}}]}"#,
+                );
+                then.status(200).json_body(json!({
+                    "gameServerName": "mhth-abc",
+                    "address": "10.0.0.7",
+                    "ports": [{ "name": "default", "port": 7654 }],
+                }));
+            })
+            .await;
+        let allocator = Allocator::Agones {
+            url: server.base_url(),
+            namespace: "default".to_string(),
+            fleet: "mhth".to_string(),
+        };
+
+        let allocated = allocator
+            .allocate(&reqwest::Client::new(), &ready)
+            .await
+            .unwrap();
+
+        mock.assert_async().await;
+        assert_eq!(allocated.address(), "10.0.0.7:7654");
+    }
+
+    #[tokio::test]
+    async fn gamelift_session() {
+        let server = MockServer::start_async().await;
+        let ready = ready_match();
+        let mock = server
+            .mock_async(|when, then| {
+                when.method(POST)
+                    .path("/")
+                    .header("X-Amz-Target", "GameLift.CreateGameSession");
+                then.status(200).json_body(json!({
+                    "GameSession": {
+                        "GameSessionId": "arn:session",
+                        "IpAddress": "52.1.2.3",
+                        "DnsName": "ec2-52-1-2-3.compute.amazonaws.com",
+                        "Port": 7777,
+                    }
+                }));
+            })
+            .await;
+        let allocator = Allocator::GameLift {
+            url: server.url("/"),
+            fleet_id: "fleet-123".to_string(),
+        };
+
+        let allocated = allocator
+            .allocate(&reqwest::Client::new(), &ready)
+            .await
+            .unwrap();
+
+        mock.assert_async().await;
+        assert_eq!(
+            allocated.address(),
+            "ec2-52-1-2-3.compute.amazonaws.com:7777"
+        );
+    }
+
+    fn ready_match() -> Match {
+        let host: QueuedPlayer = (
+            Uuid::new_v4(),
+            Player {
+                region: "CAN".to_string(),
+                ..Default::default()
+            },
+            MhthRating::default(),
+        )
+            .into();
+
+        Match::host(&host, &[]).unwrap()
+    }
+}
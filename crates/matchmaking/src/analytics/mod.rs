@@ -0,0 +1,221 @@
+//! Queue statistics aggregated by the worker every tick, e.g. players queued per region and
+//! difficulty, their wait and skill spread. Ticks are kept in a rolling window so dashboards
+//! read them with the `GetQueueAnalytics` admin RPC instead of scraping the queues.
+
+use std::collections::BTreeMap;
+
+use bitcode::{Decode, Encode};
+use redis::{AsyncCommands, RedisError, aio::MultiplexedConnection};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    codec, namespace,
+    rpc::{
+        QueuedPlayer,
+        matchmaking::{QueueAnalytics, TickAnalytics},
+    },
+};
+
+pub const ANALYTICS_KEY: &str = "analytics:ticks";
+/// Ticks kept, an hour of ticks at the default worker interval
+pub const ANALYTICS_WINDOW: isize = 120;
+
+#[derive(Debug, thiserror::Error)]
+pub enum Error {
+    #[error(transparent)]
+    Redis(#[from] RedisError),
+    #[error(transparent)]
+    BitcodeDeser(#[from] codec::Error),
+}
+
+impl From<Error> for tonic::Status {
+    fn from(_: Error) -> Self {
+        Self::internal("Failed to load queue analytics")
+    }
+}
+
+/// Players waiting in the queues of a region at a difficulty
+#[derive(Debug, Clone, Serialize, Deserialize, Encode, Decode, PartialEq)]
+pub struct QueueStats {
+    pub region: String,
+    pub difficulty: i32,
+    pub players: u64,
+    pub average_wait_secs: f64,
+    pub longest_wait_secs: i64,
+    /// Standard deviation of the player ratings
+    pub skill_spread: f64,
+}
+
+/// Statistics of a worker tick
+#[derive(Debug, Clone, Serialize, Deserialize, Encode, Decode, PartialEq)]
+pub struct TickStats {
+    pub at: i64,
+    pub matches_formed: u64,
+    pub queues: Vec<QueueStats>,
+}
+
+impl codec::Versioned for TickStats {}
+
+pub fn analytics_key() -> String {
+    namespace::key(ANALYTICS_KEY)
+}
+
+/// Groups the queued players by region and difficulty
+pub fn aggregate(players: &[QueuedPlayer], matches_formed: u64, now: i64) -> TickStats {
+    let mut groups: BTreeMap<(&str, i32), Vec<&QueuedPlayer>> = BTreeMap::new();
+    for player in players {
+        groups
+            .entry((player.region.as_str(), player.difficulty))
+            .or_default()
+            .push(player);
+    }
+    let queues = groups
+        .into_iter()
+        .map(|((region, difficulty), players)| {
+            let count = players.len() as f64;
+            let waits: Vec<i64> = players
+                .iter()
+                .map(|player| (now - player.join_time).max(0))
+                .collect();
+            let mean = players.iter().map(|p| p.skillrating.rating).sum::<f64>() / count;
+            let variance = players
+                .iter()
+                .map(|p| (p.skillrating.rating - mean).powi(2))
+                .sum::<f64>()
+                / count;
+
+            QueueStats {
+                region: region.to_string(),
+                difficulty,
+                players: players.len() as u64,
+                average_wait_secs: waits.iter().sum::<i64>() as f64 / count,
+                longest_wait_secs: waits.iter().copied().max().unwrap_or_default(),
+                skill_spread: variance.sqrt(),
+            }
+        })
+        .collect();
+
+    TickStats {
+        at: now,
+        matches_formed,
+        queues,
+    }
+}
+
+/// Appends the tick, dropping the ticks older than [`ANALYTICS_WINDOW`]
+pub async fn record(conn: &mut MultiplexedConnection, stats: &TickStats) -> Result<(), Error> {
+    redis::pipe()
+        .atomic()
+        .zadd(analytics_key(), codec::encode(stats), stats.at)
+        .ignore()
+        .zremrangebyrank(analytics_key(), 0, -ANALYTICS_WINDOW - 1)
+        .ignore()
+        .query_async(conn)
+        .await
+        .map(|_: ()| ())
+        .map_err(Error::from)
+}
+
+/// Last recorded tick, `None` before the first one
+pub async fn latest(conn: &mut MultiplexedConnection) -> Result<Option<TickStats>, Error> {
+    let ticks: Vec<Vec<u8>> = conn.zrange(analytics_key(), -1, -1).await?;
+
+    ticks
+        .first()
+        .map(|tick| codec::decode(tick).map_err(Error::from))
+        .transpose()
+}
+
+/// Ticks recorded since `since`, oldest first
+pub async fn since(conn: &mut MultiplexedConnection, since: i64) -> Result<Vec<TickStats>, Error> {
+    let ticks: Vec<Vec<u8>> = conn.zrangebyscore(analytics_key(), since, "+inf").await?;
+
+    ticks
+        .iter()
+        .map(|tick| codec::decode(tick).map_err(Error::from))
+        .collect()
+}
+
+impl From<QueueStats> for QueueAnalytics {
+    fn from(value: QueueStats) -> Self {
+        Self {
+            region: value.region,
+            difficulty: value.difficulty,
+            players: value.players,
+            average_wait_secs: value.average_wait_secs,
+            longest_wait_secs: value.longest_wait_secs,
+            skill_spread: value.skill_spread,
+        }
+    }
+}
+
+impl From<TickStats> for TickAnalytics {
+    fn from(value: TickStats) -> Self {
+        Self {
+            at: value.at,
+            matches_formed: value.matches_formed,
+            queues: value.queues.into_iter().map(Into::into).collect(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use skillratings::mhth::MhthRating;
+    use uuid::Uuid;
+
+    use super::*;
+    use crate::rpc::matchmaking::Player;
+
+    fn player(region: &str, difficulty: i32, rating: f64, join_time: i64) -> QueuedPlayer {
+        let player: QueuedPlayer = (
+            Uuid::new_v4(),
+            Player {
+                region: region.to_string(),
+                difficulty,
+                ..Default::default()
+            },
+            MhthRating {
+                rating,
+                ..Default::default()
+            },
+        )
+            .into();
+
+        player.joined_at(join_time)
+    }
+
+    #[test]
+    fn players_are_grouped_by_region_and_difficulty() {
+        let players = vec![
+            player("eu", 1, 20., 10),
+            player("eu", 1, 30., 30),
+            player("eu", 2, 25., 40),
+            player("us", 1, 25., 50),
+        ];
+
+        let stats = aggregate(&players, 3, 50);
+
+        assert_eq!(stats.matches_formed, 3);
+        assert_eq!(stats.queues.len(), 3);
+        assert_eq!(
+            stats.queues[0],
+            QueueStats {
+                region: "eu".to_string(),
+                difficulty: 1,
+                players: 2,
+                average_wait_secs: 30.,
+                longest_wait_secs: 40,
+                skill_spread: 5.,
+            }
+        );
+        assert_eq!(stats.queues[1].skill_spread, 0.);
+        assert_eq!(stats.queues[2].region, "us");
+        assert_eq!(stats.queues[2].average_wait_secs, 0.);
+    }
+
+    #[test]
+    fn empty_queues_have_no_stats() {
+        assert!(aggregate(&[], 0, 50).queues.is_empty());
+    }
+}
@@ -0,0 +1,390 @@
+//! Audit trail of queue and match operations: players queued and timed out, matches closed,
+//! started and cancelled, and admin actions. Each [`AuditEvent`] records who acted, on what and
+//! when, so support can reconstruct why a player did not get a match.
+//!
+//! [`AUDIT_SINK_VAR`] selects the sink: a Redis stream capped at [`AUDIT_MAXLEN`] entries
+//! (default), the `audit` tracing target, or nothing. The Redis sink also appends each event to
+//! the stream of every player involved, so [`history`] pages one key instead of scanning the
+//! whole trail. Failing to audit never fails the audited
+//! operation, the error is logged instead.
+
+use std::{fmt::Display, str::FromStr};
+
+use redis::{
+    AsyncCommands, RedisError,
+    aio::MultiplexedConnection,
+    streams::{StreamId, StreamMaxlen, StreamRangeReply},
+};
+use tracing::{error, info};
+use uuid::Uuid;
+
+use crate::{namespace, rpc::Match};
+
+/// Env var selecting the sink: `redis`, `log` or `off`
+pub const AUDIT_SINK_VAR: &str = "AUDIT_SINK";
+pub const AUDIT_STREAM_KEY: &str = "audit";
+/// Entries kept in the stream, trimmed approximately
+pub const AUDIT_MAXLEN: usize = 100_000;
+/// Entries kept in the stream of each player, trimmed approximately
+pub const PLAYER_AUDIT_MAXLEN: usize = 1_000;
+/// Streams of players without new events expire after a month
+pub const PLAYER_AUDIT_TTL: i64 = 30 * 24 * 60 * 60;
+
+#[derive(Debug, thiserror::Error)]
+pub enum Error {
+    #[error(transparent)]
+    Redis(#[from] RedisError),
+    #[error("invalid audit entry `{0}`")]
+    InvalidEntry(String),
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Sink {
+    #[default]
+    Redis,
+    Log,
+    Off,
+}
+
+impl Sink {
+    pub fn from_env() -> Self {
+        match std::env::var(AUDIT_SINK_VAR).as_deref().map(str::trim) {
+            Ok("log") => Self::Log,
+            Ok("off") => Self::Off,
+            _ => Self::Redis,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Action {
+    PlayerQueued,
+    /// Left the queue, e.g. its entry expired or it missed a ready-check
+    PlayerLeft,
+    /// Players closed into a match
+    Matched,
+    MatchStarted,
+    MatchCancelled,
+    /// An admin RPC, the target names what changed
+    Admin,
+    /// A queue started starving, the target is its region and difficulty
+    QueueStarved,
+}
+
+impl Action {
+    pub const fn as_str(self) -> &'static str {
+        match self {
+            Self::PlayerQueued => "player_queued",
+            Self::PlayerLeft => "player_left",
+            Self::Matched => "matched",
+            Self::MatchStarted => "match_started",
+            Self::MatchCancelled => "match_cancelled",
+            Self::Admin => "admin",
+            Self::QueueStarved => "queue_starved",
+        }
+    }
+}
+
+impl FromStr for Action {
+    type Err = Error;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value {
+            "player_queued" => Ok(Self::PlayerQueued),
+            "player_left" => Ok(Self::PlayerLeft),
+            "matched" => Ok(Self::Matched),
+            "match_started" => Ok(Self::MatchStarted),
+            "match_cancelled" => Ok(Self::MatchCancelled),
+            "admin" => Ok(Self::Admin),
+            "queue_starved" => Ok(Self::QueueStarved),
+            _ => Err(Error::InvalidEntry(value.to_string())),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Actor {
+    Player(Uuid),
+    /// User id of the admin session
+    Admin(String),
+    Worker,
+}
+
+impl Display for Actor {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Player(player_id) => write!(f, "player:{player_id}"),
+            Self::Admin(user_id) => write!(f, "admin:{user_id}"),
+            Self::Worker => f.write_str("worker"),
+        }
+    }
+}
+
+impl FromStr for Actor {
+    type Err = Error;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value.split_once(':') {
+            Some(("player", player_id)) => Uuid::from_str(player_id)
+                .map(Self::Player)
+                .map_err(|_| Error::InvalidEntry(value.to_string())),
+            Some(("admin", user_id)) => Ok(Self::Admin(user_id.to_string())),
+            None if value == "worker" => Ok(Self::Worker),
+            _ => Err(Error::InvalidEntry(value.to_string())),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AuditEvent {
+    pub action: Action,
+    pub actor: Actor,
+    /// Player id, match id or admin resource acted on
+    pub target: String,
+    /// Players involved, e.g. the players of a match
+    pub players: Vec<Uuid>,
+    pub at: i64,
+    /// Why it happened, e.g. `timeout` or `ready_check`
+    pub reason: String,
+}
+
+impl AuditEvent {
+    pub fn new(action: Action, actor: Actor, target: impl Display, at: i64) -> Self {
+        Self {
+            action,
+            actor,
+            target: target.to_string(),
+            players: Vec::new(),
+            at,
+            reason: String::new(),
+        }
+    }
+
+    /// `action` of the worker on `a_match`, involving its players
+    pub fn for_match(action: Action, a_match: &Match, at: i64) -> Self {
+        Self::new(action, Actor::Worker, a_match.id, at)
+            .with_players(a_match.players.iter().map(|player| player.player_id))
+    }
+
+    pub fn with_players(mut self, players: impl IntoIterator<Item = Uuid>) -> Self {
+        self.players = players.into_iter().collect();
+        self
+    }
+
+    pub fn with_reason(mut self, reason: impl Into<String>) -> Self {
+        self.reason = reason.into();
+        self
+    }
+
+    /// Whether `player_id` acted, was acted on or was involved
+    pub fn involves(&self, player_id: &Uuid) -> bool {
+        self.actor == Actor::Player(*player_id)
+            || self.target == player_id.to_string()
+            || self.players.contains(player_id)
+    }
+
+    /// Players that acted, were acted on or were involved, without duplicates
+    fn involved(&self) -> Vec<Uuid> {
+        let mut involved = self.players.clone();
+        if let Actor::Player(player_id) = self.actor {
+            involved.push(player_id);
+        }
+        if let Ok(player_id) = Uuid::from_str(&self.target) {
+            involved.push(player_id);
+        }
+        involved.sort_unstable();
+        involved.dedup();
+
+        involved
+    }
+
+    fn from_entry(entry: &StreamId) -> Result<Self, Error> {
+        let field = |name: &str| entry.get::<String>(name).unwrap_or_default();
+
+        Ok(Self {
+            action: field("action").parse()?,
+            actor: field("actor").parse()?,
+            target: field("target"),
+            players: field("players")
+                .split(',')
+                .filter_map(|player_id| Uuid::from_str(player_id).ok())
+                .collect(),
+            at: field("at")
+                .parse()
+                .map_err(|_| Error::InvalidEntry(entry.id.clone()))?,
+            reason: field("reason"),
+        })
+    }
+
+    fn fields(&self) -> [(&'static str, String); 6] {
+        [
+            ("action", self.action.as_str().to_string()),
+            ("actor", self.actor.to_string()),
+            ("target", self.target.clone()),
+            (
+                "players",
+                self.players
+                    .iter()
+                    .map(Uuid::to_string)
+                    .collect::<Vec<_>>()
+                    .join(","),
+            ),
+            ("at", self.at.to_string()),
+            ("reason", self.reason.clone()),
+        ]
+    }
+}
+
+pub fn audit_key() -> String {
+    namespace::key(AUDIT_STREAM_KEY)
+}
+
+/// Events involving `player_id`, see [`history`]
+pub fn player_audit_key(player_id: &Uuid) -> String {
+    namespace::key(format!("{AUDIT_STREAM_KEY}:{player_id}"))
+}
+
+/// Events of a player, oldest first
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct HistoryPage {
+    pub events: Vec<AuditEvent>,
+    /// Cursor of the older events, `None` on the last page
+    pub before: Option<String>,
+}
+
+/// Records `event` in the sink of [`AUDIT_SINK_VAR`], logging failures
+pub async fn emit(conn: &mut MultiplexedConnection, event: &AuditEvent) {
+    match Sink::from_env() {
+        Sink::Redis => {
+            if let Err(err) = record(conn, event).await {
+                error!("failed to audit `{}`: {err}", event.action.as_str());
+            }
+        }
+        Sink::Log => info!(
+            target: "audit",
+            action = event.action.as_str(),
+            actor = %event.actor,
+            target_id = event.target,
+            players = ?event.players,
+            at = event.at,
+            reason = event.reason,
+        ),
+        Sink::Off => {}
+    }
+}
+
+/// Appends `event` to the audit stream and to the streams of the players involved
+pub async fn record(conn: &mut MultiplexedConnection, event: &AuditEvent) -> Result<(), Error> {
+    let fields = event.fields();
+    let mut pipe = redis::pipe();
+    pipe.atomic()
+        .xadd_maxlen(
+            audit_key(),
+            StreamMaxlen::Approx(AUDIT_MAXLEN),
+            "*",
+            &fields,
+        )
+        .ignore();
+    for player_id in event.involved() {
+        let key = player_audit_key(&player_id);
+        pipe.xadd_maxlen(
+            &key,
+            StreamMaxlen::Approx(PLAYER_AUDIT_MAXLEN),
+            "*",
+            &fields,
+        )
+        .ignore()
+        .expire(&key, PLAYER_AUDIT_TTL)
+        .ignore();
+    }
+
+    pipe.query_async(conn).await.map_err(Error::from)
+}
+
+/// Latest `count` events involving `player_id`, older than the `before` cursor of a previous
+/// page when set
+pub async fn history(
+    conn: &mut MultiplexedConnection,
+    player_id: &Uuid,
+    before: Option<&str>,
+    count: usize,
+) -> Result<HistoryPage, Error> {
+    // exclusive range, the cursor itself was the oldest event of the previous page
+    let end = before.map_or_else(|| "+".to_string(), |before| format!("({before}"));
+    let reply: StreamRangeReply = conn
+        .xrevrange_count(player_audit_key(player_id), end, "-", count)
+        .await?;
+    let before = (reply.ids.len() == count)
+        .then(|| reply.ids.last().map(|entry| entry.id.clone()))
+        .flatten();
+    let mut events = reply
+        .ids
+        .iter()
+        .map(AuditEvent::from_entry)
+        .collect::<Result<Vec<_>, _>>()?;
+    events.reverse();
+
+    Ok(HistoryPage { events, before })
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+    use crate::test_support::{create_redis, redis_client};
+
+    #[test]
+    fn actors_round_trip() {
+        let player_id = Uuid::new_v4();
+        for actor in [
+            Actor::Player(player_id),
+            Actor::Admin("ops".to_string()),
+            Actor::Worker,
+        ] {
+            assert_eq!(actor.to_string().parse::<Actor>().unwrap(), actor);
+        }
+        assert!("player:not-a-uuid".parse::<Actor>().is_err());
+    }
+
+    #[tokio::test]
+    async fn history_of_a_player() {
+        let container = create_redis(6379).await;
+        let host = container.get_host().await.unwrap();
+        let port = container.get_host_port_ipv4(6379).await.unwrap();
+        let client = redis_client(host.to_string(), port);
+        let mut conn = client.get_multiplexed_async_connection().await.unwrap();
+        let (player_id, other_id, match_id) = (Uuid::new_v4(), Uuid::new_v4(), Uuid::new_v4());
+
+        let queued = AuditEvent::new(
+            Action::PlayerQueued,
+            Actor::Player(player_id),
+            player_id,
+            10,
+        );
+        let other = AuditEvent::new(Action::PlayerQueued, Actor::Player(other_id), other_id, 11);
+        let matched = AuditEvent::new(Action::Matched, Actor::Worker, match_id, 20)
+            .with_players([other_id, player_id]);
+        let cancelled = AuditEvent::new(Action::MatchCancelled, Actor::Worker, match_id, 30)
+            .with_players([other_id, player_id])
+            .with_reason("ready_check");
+        for event in [&queued, &other, &matched, &cancelled] {
+            record(&mut conn, event).await.unwrap();
+        }
+
+        let all = history(&mut conn, &player_id, None, 10).await.unwrap();
+        let latest = history(&mut conn, &player_id, None, 2).await.unwrap();
+        let older = history(&mut conn, &player_id, latest.before.as_deref(), 2)
+            .await
+            .unwrap();
+
+        assert_eq!(
+            all.events,
+            vec![queued.clone(), matched.clone(), cancelled.clone()]
+        );
+        assert_eq!(all.before, None);
+        assert_eq!(latest.events, vec![matched, cancelled]);
+        assert!(latest.before.is_some());
+        assert_eq!(older.events, vec![queued]);
+        assert_eq!(older.before, None);
+    }
+}
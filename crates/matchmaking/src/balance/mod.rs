@@ -0,0 +1,187 @@
+//! Splits the players of a formed match into two squads whose expected scores against each
+//! other are as even as possible. Parties stay in one squad unless that leaves the squads uneven.
+
+use skillratings::mhth::{MhthConfig, MhthRating, expected_score_multi_team};
+use uuid::Uuid;
+
+use crate::rpc::{Match, QueuedPlayer};
+
+/// Players a squad needs, smaller matches play as a single squad
+pub const MIN_SQUAD_SIZE: usize = 2;
+/// Every split is evaluated, so larger matches are not balanced
+pub const MAX_BALANCED_PLAYERS: usize = 16;
+
+/// Player indexes of each party in the match, solo players are parties of one
+pub(crate) fn parties(players: &[QueuedPlayer]) -> Vec<Vec<usize>> {
+    let mut party_of: Vec<usize> = (0..players.len()).collect();
+    for (index, player) in players.iter().enumerate() {
+        for member_id in &player.party_ids {
+            let Some(member) = players
+                .iter()
+                .position(|p| p.player_id.to_string() == *member_id)
+            else {
+                continue;
+            };
+            let (from, to) = (party_of[member], party_of[index]);
+            for party in &mut party_of {
+                if *party == from {
+                    *party = to;
+                }
+            }
+        }
+    }
+
+    let mut parties: Vec<Vec<usize>> = Vec::new();
+    let mut leaders = Vec::new();
+    for (index, party) in party_of.into_iter().enumerate() {
+        match leaders.iter().position(|leader| *leader == party) {
+            Some(position) => parties[position].push(index),
+            None => {
+                leaders.push(party);
+                parties.push(vec![index]);
+            }
+        }
+    }
+
+    parties
+}
+
+/// Gap between the expected scores of the squads
+fn expected_gap(first: &[MhthRating], second: &[MhthRating]) -> f64 {
+    let expected = expected_score_multi_team(&[first, second], &MhthConfig::new());
+
+    (expected[0] - expected[1]).abs()
+}
+
+/// Most even split of `groups` into two squads whose sizes differ by at most one
+pub(crate) fn best_split(
+    players: &[QueuedPlayer],
+    groups: &[Vec<usize>],
+) -> Option<[Vec<usize>; 2]> {
+    let mut best: Option<(f64, [Vec<usize>; 2])> = None;
+    // the first group always plays in the first squad, mirrored splits are skipped
+    for mask in 0..1usize << (groups.len() - 1) {
+        let mut squads: [Vec<usize>; 2] = [Vec::new(), Vec::new()];
+        for (index, group) in groups.iter().enumerate() {
+            let squad = if index > 0 && mask & (1 << (index - 1)) != 0 {
+                1
+            } else {
+                0
+            };
+            squads[squad].extend(group);
+        }
+        if squads[0].len().abs_diff(squads[1].len()) > 1 {
+            continue;
+        }
+
+        let ratings = squads.each_ref().map(|squad| {
+            squad
+                .iter()
+                .map(|i| players[*i].skillrating)
+                .collect::<Vec<_>>()
+        });
+        let gap = expected_gap(&ratings[0], &ratings[1]);
+        if best.as_ref().is_none_or(|(best_gap, _)| gap < *best_gap) {
+            best = Some((gap, squads));
+        }
+    }
+
+    best.map(|(_, squads)| squads)
+}
+
+/// Player ids of each squad, empty when the players do not fill two squads
+pub fn squads(players: &[QueuedPlayer]) -> Vec<Vec<Uuid>> {
+    if players.len() < 2 * MIN_SQUAD_SIZE || players.len() > MAX_BALANCED_PLAYERS {
+        return Vec::new();
+    }
+    let solo: Vec<Vec<usize>> = (0..players.len()).map(|index| vec![index]).collect();
+    let Some(split) = best_split(players, &parties(players)).or_else(|| best_split(players, &solo))
+    else {
+        return Vec::new();
+    };
+
+    split
+        .into_iter()
+        .map(|squad| squad.into_iter().map(|i| players[i].player_id).collect())
+        .collect()
+}
+
+impl Match {
+    /// Assigns the players to squads, see [`squads`]
+    pub fn balance(&mut self) {
+        self.squads = squads(&self.players);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::rpc::matchmaking::Player;
+
+    fn player(rating: f64) -> QueuedPlayer {
+        (
+            Uuid::new_v4(),
+            Player::default(),
+            MhthRating {
+                rating,
+                ..Default::default()
+            },
+        )
+            .into()
+    }
+
+    fn squad_ratings(players: &[QueuedPlayer], squad: &[Uuid]) -> f64 {
+        players
+            .iter()
+            .filter(|p| squad.contains(&p.player_id))
+            .map(|p| p.skillrating.rating)
+            .sum()
+    }
+
+    #[test]
+    fn small_matches_are_one_squad() {
+        let players = vec![player(20.), player(30.), player(25.)];
+
+        assert!(squads(&players).is_empty());
+    }
+
+    #[test]
+    fn strongest_and_weakest_play_together() {
+        let players = vec![player(40.), player(35.), player(15.), player(10.)];
+
+        let squads = squads(&players);
+
+        assert_eq!(squads.len(), 2);
+        assert_eq!(squads[0].len(), 2);
+        assert_eq!(squad_ratings(&players, &squads[0]), 50.);
+        assert_eq!(squad_ratings(&players, &squads[1]), 50.);
+    }
+
+    #[test]
+    fn parties_stay_together() {
+        let mut players = vec![player(40.), player(35.), player(15.), player(10.)];
+        let strongest = players[0].player_id;
+        players[1].party_ids = vec![strongest.to_string()];
+
+        let squads = squads(&players);
+        let party_squad = squads
+            .iter()
+            .find(|squad| squad.contains(&strongest))
+            .unwrap();
+
+        assert!(party_squad.contains(&players[1].player_id));
+    }
+
+    #[test]
+    fn oversized_parties_are_split() {
+        let mut players = vec![player(40.), player(35.), player(15.), player(10.)];
+        let host = players[0].player_id.to_string();
+        for member in &mut players[1..] {
+            member.party_ids = vec![host.clone()];
+        }
+
+        let squads = squads(&players);
+
+        assert_eq!(squad_ratings(&players, &squads[0]), 50.);
+    }
+}
@@ -0,0 +1,88 @@
+//! Operations on the matchmaking state through the admin RPCs, see [`matchmaking::admin`].
+//!
+//! - `matchmaking-admin queues` lists the size of every queue
+//! - `matchmaking-admin player <id>` prints the queued state of a player
+//! - `matchmaking-admin remove <id>` removes a player from every queue
+//! - `matchmaking-admin matches <state>` lists the matches in a state, `open` lists the forming
+//!   matches and `closed` the ready ones
+//! - `matchmaking-admin tick` asks the leader worker to run a tick now
+//!
+//! Calls the server at `MATCHMAKING_URL` with the admin session of `MATCHMAKING_ADMIN_TOKEN`, so
+//! every operation is authorized and audited like the other admin RPCs.
+
+use std::str::FromStr;
+
+use matchmaking::{client::MatchmakingClient, profile};
+use uuid::Uuid;
+
+const USAGE: &str =
+    "usage: matchmaking-admin <queues | player <id> | remove <id> | matches <state> | tick>";
+/// Nakama session of a user of `ADMIN_USER_IDS`
+const ADMIN_TOKEN_VAR: &str = "MATCHMAKING_ADMIN_TOKEN";
+
+#[tokio::main]
+async fn main() -> anyhow::Result<()> {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    let Some(command) = args.first() else {
+        anyhow::bail!(USAGE);
+    };
+    let argument = || args.get(1).ok_or_else(|| anyhow::anyhow!(USAGE));
+    profile::load()?;
+    let url =
+        std::env::var("MATCHMAKING_URL").unwrap_or_else(|_| "http://127.0.0.1:50051".to_string());
+    let token = std::env::var(ADMIN_TOKEN_VAR)
+        .map_err(|_| anyhow::anyhow!("{ADMIN_TOKEN_VAR} must hold an admin session token"))?;
+    let client = MatchmakingClient::connect(url, &token).await?;
+
+    match command.as_str() {
+        "queues" => {
+            for queue in client.queue_sizes().await?.queues {
+                println!("{}\t{}", queue.size, queue.key);
+            }
+        }
+        "player" => {
+            let player_id = Uuid::from_str(argument()?)?;
+            let queued = client.queued_player(player_id.to_string()).await?;
+            if queued.queued {
+                println!("{}", queued.state);
+            } else {
+                println!("Player `{player_id}` is not queued");
+            }
+            if !queued.match_id.is_empty() {
+                println!("Matched into `{}`", queued.match_id);
+            }
+        }
+        "remove" => {
+            let player_id = Uuid::from_str(argument()?)?;
+            if client
+                .remove_queued_player(player_id.to_string())
+                .await?
+                .removed
+            {
+                println!("Removed `{player_id}` from the queues");
+            } else {
+                println!("Player `{player_id}` is not queued");
+            }
+        }
+        "matches" => {
+            let state = match argument()?.as_str() {
+                "open" => "forming",
+                "closed" => "ready",
+                state => state,
+            };
+            for a_match in client.list_matches(state).await?.matches {
+                println!(
+                    "{}\t{}\thost {}\t{} players",
+                    a_match.match_id, a_match.region, a_match.host_id, a_match.players
+                );
+            }
+        }
+        "tick" => {
+            client.request_tick().await?;
+            println!("Tick requested, the leader worker runs it within a second");
+        }
+        _ => anyhow::bail!("unknown command `{command}`\n{USAGE}"),
+    }
+
+    Ok(())
+}
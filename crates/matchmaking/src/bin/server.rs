@@ -1,64 +1,172 @@
-use std::{net::ToSocketAddrs, str::FromStr, sync::Arc};
+use std::{str::FromStr, sync::Arc};
 
 use matchmaking::{
+    allocation::Allocator,
+    audit::{self, Action, Actor, AuditEvent},
+    chaos,
+    clock::{Clock, SystemClock},
+    codec, config, experiments, geoip,
     internal_clients::InternalClients,
+    leaderboard::SeasonLeaderboard,
     nakama::NakamaClient,
+    namespace, playlists, probes, profile, records, regions, rolls,
     rpc::{
-        server::{MatchmakingServer, MatchmakingServiceServer, auth::check_auth},
+        server::{
+            MatchmakingServer, jwks,
+            listener::{self, Listeners},
+            middleware::Middlewares,
+        },
         worker::MatchmakingWorker,
     },
+    secrets, sessions,
+    starvation::StarvationPolicy,
+    store,
+    validation::NakamaValidator,
 };
-use tokio::time::{self, Duration};
-use tonic::transport::Server;
-use tracing::error;
-
-const WORKER_EXECUTION_INTERVAL: Duration = Duration::from_secs(30);
+use tokio::net::TcpListener;
+use tracing::{error, info, warn};
 
 #[tokio::main]
 async fn main() -> anyhow::Result<()> {
-    let mut interval = time::interval(WORKER_EXECUTION_INTERVAL);
+    let app_env = profile::load()?;
     let log_level = std::env::var("LOG_LEVEL")
         .ok()
         .and_then(to_log_level)
-        .unwrap_or(tracing::Level::DEBUG);
+        .unwrap_or_else(|| app_env.log_level());
     tracing_subscriber::fmt()
         .with_max_level(log_level)
         .try_init()
         .unwrap();
+    info!("Starting with the `{app_env}` profile");
+    namespace::set_from_env();
+    codec::set_from_env()?;
+    geoip::install_from_env()?;
+    let secrets_provider = secrets::Provider::from_env()?;
+    secrets::refresh(&reqwest::Client::new(), &secrets_provider).await?;
     let clients = InternalClients::try_from_env()?;
-    let nakama_client = Arc::new(
-        NakamaClient::try_new()?
-            .authenticate(clients.http_client())
-            .await?,
-    );
+    let nakama_client = NakamaClient::try_new()?;
+    let nakama_client = match nakama_client
+        .clone()
+        .authenticate(clients.http_client())
+        .await
+    {
+        Ok(authenticated) => authenticated,
+        Err(err) => {
+            let Some(legacy) = nakama_client.legacy_fallback()? else {
+                return Err(err.into());
+            };
+            warn!("Nakama authentication failed, retrying with the deprecated password derivation");
+            legacy.authenticate(clients.http_client()).await?
+        }
+    };
+    let nakama_client = Arc::new(nakama_client);
     let redis_conn = clients
         .redis
         .get_multiplexed_tokio_connection()
         .await
         .inspect_err(|err| error!("Redis failed to connect: {err}"))?;
+    store::check_server_version(&mut redis_conn.clone()).await?;
+    let clock: Arc<dyn Clock> = Arc::new(SystemClock::from_env()?);
+    if let Ok(path) = std::env::var(config::CONFIG_PATH_VAR) {
+        let config = config::load_file(path)?;
+        config::set_config(&mut redis_conn.clone(), &config).await?;
+        let loaded = AuditEvent::new(
+            Action::Admin,
+            Actor::Worker,
+            "config",
+            clock.time_since_epoch(),
+        )
+        .with_reason("load_config");
+        audit::emit(&mut redis_conn.clone(), &loaded).await;
+    }
+    if let Ok(path) = std::env::var("REGION_TUNING_PATH") {
+        let tunings = regions::tuning::load_file(path)?;
+        regions::tuning::set_tunings(&mut redis_conn.clone(), &tunings).await?;
+    }
+    if let Ok(path) = std::env::var("PLAYLISTS_PATH") {
+        let playlists = playlists::load_file(path)?;
+        playlists::set_playlists(&mut redis_conn.clone(), &playlists).await?;
+    }
+    if let Ok(path) = std::env::var("EXPERIMENTS_PATH") {
+        let experiments = experiments::load_file(path)?;
+        experiments::set_experiments(&mut redis_conn.clone(), &experiments).await?;
+    }
+    if let Ok(path) = std::env::var("ROLL_TABLE_PATH") {
+        let table = rolls::load_file(path)?;
+        rolls::set_table(&mut redis_conn.clone(), &table).await?;
+    }
+    sessions::sync(&mut redis_conn.clone()).await?;
+    tokio::spawn(sessions::sync_periodically(redis_conn.clone()));
     let http_client = Arc::new(clients.http_client);
+    if let Some(source) = jwks::Source::from_env() {
+        jwks::refresh(&http_client, &source).await?;
+        let http_client = http_client.clone();
+        tokio::spawn(async move { jwks::refresh_periodically(&http_client, source).await });
+    }
+    if secrets_provider != secrets::Provider::Env {
+        let http_client = http_client.clone();
+        tokio::spawn(
+            async move { secrets::refresh_periodically(&http_client, secrets_provider).await },
+        );
+    }
+    let chaos = chaos::ChaosConfig::from_env()?;
+    #[cfg(feature = "chaos")]
+    if chaos.is_active() && chaos::install(chaos) {
+        warn!("Injecting failures into Redis and Nakama calls: {chaos:?}");
+    }
+    #[cfg(not(feature = "chaos"))]
+    if chaos.is_active() {
+        warn!("CHAOS_* vars are set but the server was built without the `chaos` feature");
+    }
+    regions::bootstrap_from_env(&mut redis_conn.clone(), clock.time_since_epoch()).await?;
+    let records = records::from_env().await?;
+    let middlewares = Middlewares::from_env()?;
     let matchmaking_server = MatchmakingServer {
         redis: redis_conn.clone(),
         http_client: http_client.clone(),
         nakama_client: nakama_client.clone(),
+        clock: clock.clone(),
+        records,
+        leaderboard: SeasonLeaderboard::from_env(),
+        health: Default::default(),
+        observability: Default::default(),
+        validator: Arc::new(NakamaValidator::from_env(
+            nakama_client.clone(),
+            http_client.clone(),
+        )?),
     };
-    let mut matchmaking_worker = MatchmakingWorker::new(redis_conn, http_client, nakama_client);
+    let worker_heartbeat = probes::Heartbeat::default();
+    let probes = Arc::new(probes::Probes {
+        redis: redis_conn.clone(),
+        http_client: http_client.clone(),
+        nakama_client: nakama_client.clone(),
+        worker: worker_heartbeat.clone(),
+        worker_stale_after: probes::worker_stale_after(),
+    });
+    let probes_listener = TcpListener::bind(("0.0.0.0", probes::port())).await?;
+    tokio::spawn(probes::serve(probes_listener, probes));
+    let mut matchmaking_worker =
+        MatchmakingWorker::new(redis_conn, http_client, nakama_client, clock);
+    matchmaking_worker.allocator = Allocator::from_env()?;
+    matchmaking_worker.starvation = StarvationPolicy::from_env()?;
 
     tokio::spawn(async move {
-        interval.tick().await;
-
+        // the interval is refreshed with the config on every run
         loop {
-            interval.tick().await;
+            matchmaking_worker.wait_next_run().await;
             if let Err(err) = matchmaking_worker.run().await {
-                error!("matchmaking worker: {err:?}");
+                error!("matchmaking worker: {err}");
             }
+            worker_heartbeat.beat();
         }
     });
 
-    let server = MatchmakingServiceServer::with_interceptor(matchmaking_server, check_auth);
-    Server::builder()
-        .add_service(server)
-        .serve("0.0.0.0:50051".to_socket_addrs().unwrap().next().unwrap())
+    Listeners::from_env()
+        .serve(
+            matchmaking_server,
+            &middlewares,
+            listener::shutdown_signal(),
+        )
         .await?;
     Ok(())
 }
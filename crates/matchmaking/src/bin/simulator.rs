@@ -0,0 +1,167 @@
+//! Load test driving a synthetic population through `join_queue` against a running server and
+//! printing the wait-time and fairness report, see [`matchmaking::simulation`]. Every simulated
+//! player gets a Nakama account, so the server loads them like real players.
+
+use std::{sync::Arc, time::Duration};
+
+use matchmaking::{
+    client::{MatchmakingClient, QueueEvents},
+    nakama::{Authenticated, NakamaClient},
+    rpc::{
+        matchmaking::{JoinQueueStatus, queue_event::Event},
+        server::auth,
+    },
+    simulation::{self, Outcome, Report, SimulatedParty, SimulationConfig},
+};
+use tokio::{
+    task::JoinSet,
+    time::{self, Instant},
+};
+use tonic::transport::Channel;
+use tracing::{error, info};
+
+/// Session lifetime past the simulation timeout
+const SESSION_MARGIN_SECS: i64 = 60;
+
+#[derive(Clone)]
+struct Simulator {
+    channel: Channel,
+    nakama_client: Arc<NakamaClient<Authenticated>>,
+    http_client: Arc<reqwest::Client>,
+    /// Nakama game API, where player accounts are created
+    api_url: String,
+    timeout: Duration,
+}
+
+#[tokio::main]
+async fn main() -> anyhow::Result<()> {
+    tracing_subscriber::fmt()
+        .with_max_level(tracing::Level::INFO)
+        .try_init()
+        .unwrap();
+    let config = match std::env::var("SIMULATION_PATH") {
+        Ok(path) => simulation::load_file(path)?,
+        Err(_) => SimulationConfig::default(),
+    };
+    let http_client = Arc::new(reqwest::Client::new());
+    let nakama_client = Arc::new(NakamaClient::try_new()?.authenticate(&http_client).await?);
+    let target =
+        std::env::var("MATCHMAKING_URL").unwrap_or_else(|_| "http://127.0.0.1:50051".to_string());
+    let simulator = Simulator {
+        channel: Channel::from_shared(target)?.connect().await?,
+        nakama_client,
+        http_client,
+        api_url: std::env::var("NAKAMA_API_URL")
+            .unwrap_or_else(|_| "http://127.0.0.1:7350".to_string()),
+        timeout: Duration::from_secs(config.timeout_secs),
+    };
+
+    let parties = simulation::population(&config);
+    info!(
+        "Simulating {} players in {} parties",
+        config.players,
+        parties.len()
+    );
+    let interval = Duration::from_secs_f64(1. / config.join_rate);
+    let mut runs = JoinSet::new();
+    for party in parties {
+        let simulator = simulator.clone();
+        runs.spawn(async move { simulator.run(party).await });
+        time::sleep(interval).await;
+    }
+    let mut outcomes = Vec::with_capacity(config.players);
+    while let Some(run) = runs.join_next().await {
+        outcomes.extend(run?);
+    }
+
+    println!("{}", Report::new(&outcomes));
+    Ok(())
+}
+
+impl Simulator {
+    async fn run(&self, party: SimulatedParty) -> Vec<Outcome> {
+        match self.queue(&party).await {
+            Ok(outcomes) => outcomes,
+            Err(err) => {
+                error!("Simulated party failed to queue: {err}");
+                vec![Outcome::Failed; party.members.len()]
+            }
+        }
+    }
+
+    /// Forms the party, watches the queue of every member and queues the host
+    async fn queue(&self, party: &SimulatedParty) -> anyhow::Result<Vec<Outcome>> {
+        let mut players = Vec::with_capacity(party.members.len());
+        for member in &party.members {
+            let user_id = self
+                .nakama_client
+                .create_player(self.http_client.clone(), &self.api_url, &member.custom_id)
+                .await?;
+            let now = chrono::Utc::now().timestamp();
+            let expires_at = now + self.timeout.as_secs() as i64 + SESSION_MARGIN_SECS;
+            let token = auth::sign_session(&user_id, now, expires_at);
+            let client = MatchmakingClient::new(self.channel.clone(), &token)?;
+            players.push((user_id, client));
+        }
+        let (host_id, host) = &players[0];
+        for (member_id, member) in &players[1..] {
+            let party_id = host.invite_to_party(host_id, member_id).await?.party_id;
+            member.accept_invite(member_id, party_id).await?;
+        }
+        let mut streams = Vec::with_capacity(players.len());
+        for (player_id, client) in &players {
+            streams.push(client.watch_queue(player_id).await?);
+        }
+
+        let joined_at = Instant::now();
+        let deadline = joined_at + self.timeout;
+        let joined = host.join_queue(party.join_request(host_id)).await?;
+        if joined.status() != JoinQueueStatus::Queued {
+            anyhow::bail!("`{host_id}` was not queued: {}", joined.detail);
+        }
+        let mut waits = JoinSet::new();
+        for (index, (stream, member)) in streams.into_iter().zip(&party.members).enumerate() {
+            let rating = member.rating;
+            waits.spawn(async move { (index, wait(stream, rating, joined_at, deadline).await) });
+        }
+        let mut outcomes = waits.join_all().await;
+        outcomes.sort_by_key(|(index, _)| *index);
+
+        Ok(outcomes.into_iter().map(|(_, outcome)| outcome).collect())
+    }
+}
+
+/// Waits for the queue event ending the wait of a player
+async fn wait(
+    mut events: QueueEvents,
+    rating: f64,
+    joined_at: Instant,
+    deadline: Instant,
+) -> Outcome {
+    loop {
+        let event = match time::timeout_at(deadline, events.next_event()).await {
+            Err(_) => return Outcome::TimedOut,
+            Ok(Ok(Some(event))) => event,
+            Ok(Ok(None) | Err(_)) => return Outcome::Failed,
+        };
+        match event {
+            Event::MatchFound(found) => {
+                return Outcome::Matched {
+                    rating,
+                    wait_secs: joined_at.elapsed().as_secs_f64(),
+                    match_id: found.match_id,
+                    win_probability: found.win_probability,
+                };
+            }
+            Event::QueueTimeout(_) => return Outcome::TimedOut,
+            Event::MatchFailed(_) | Event::PartyDisbanded(_) => return Outcome::Failed,
+            Event::ReadyCheck(check) => {
+                if events.confirm_ready(check.match_id).await.is_err() {
+                    return Outcome::Failed;
+                }
+            }
+            Event::KickedFromLobby(kicked) if !kicked.requeued => return Outcome::Failed,
+            Event::QueuePosition(_) | Event::PartyHostChanged(_) | Event::KickedFromLobby(_) => {}
+        }
+    }
+}
@@ -0,0 +1,37 @@
+//! Saves the matchmaking state of Redis to a file or restores it, see [`matchmaking::snapshot`].
+//!
+//! `snapshot save <path>` and `snapshot restore <path>`, Redis and the namespace are read from
+//! the same env vars as the server.
+
+use matchmaking::{
+    codec, internal_clients::InternalClients, namespace, profile, secrets, snapshot,
+};
+
+#[tokio::main]
+async fn main() -> anyhow::Result<()> {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    let [command, path] = args.as_slice() else {
+        anyhow::bail!("usage: snapshot <save|restore> <path>");
+    };
+    profile::load()?;
+    namespace::set_from_env();
+    codec::set_from_env()?;
+    secrets::refresh(&reqwest::Client::new(), &secrets::Provider::from_env()?).await?;
+    let clients = InternalClients::try_from_env()?;
+    let mut conn = clients.redis().await?;
+
+    match command.as_str() {
+        "save" => {
+            let snapshot = snapshot::take(&mut conn, chrono::Utc::now().timestamp()).await?;
+            snapshot::save_file(path, &snapshot)?;
+            println!("Saved {} keys to {path}", snapshot.entries.len());
+        }
+        "restore" => {
+            let restored = snapshot::restore(&mut conn, &snapshot::load_file(path)?).await?;
+            println!("Restored {restored} keys from {path}");
+        }
+        _ => anyhow::bail!("unknown command `{command}`, expected `save` or `restore`"),
+    }
+
+    Ok(())
+}
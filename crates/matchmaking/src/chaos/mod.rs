@@ -0,0 +1,198 @@
+//! Failure injection for resilience testing, compiled in with the `chaos` feature. The queue
+//! scans of the worker and the authenticated Nakama calls fail at a configured rate and are
+//! delayed by a configured latency, so worker recovery, match start retries and dead-lettering
+//! can be exercised against healthy backends. Without the feature the hooks always succeed.
+
+use redis::RedisError;
+
+use crate::{nakama, rng::Rng};
+
+pub const REDIS_FAILURE_RATE_VAR: &str = "CHAOS_REDIS_FAILURE_RATE";
+pub const REDIS_LATENCY_MS_VAR: &str = "CHAOS_REDIS_LATENCY_MS";
+pub const NAKAMA_FAILURE_RATE_VAR: &str = "CHAOS_NAKAMA_FAILURE_RATE";
+pub const NAKAMA_LATENCY_MS_VAR: &str = "CHAOS_NAKAMA_LATENCY_MS";
+pub const SEED_VAR: &str = "CHAOS_SEED";
+
+#[derive(Debug, thiserror::Error)]
+pub enum Error {
+    #[error("invalid chaos config: {0}")]
+    Invalid(String),
+}
+
+/// Faults injected into the calls of a backend
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct Fault {
+    /// Share of the calls failing, between `0.0` and `1.0`
+    pub failure_rate: f64,
+    /// Delay added before every call
+    pub latency_ms: u64,
+}
+
+impl Fault {
+    pub fn is_active(&self) -> bool {
+        self.failure_rate > 0. || self.latency_ms > 0
+    }
+
+    /// Draws whether a call fails
+    pub fn fails(&self, rng: &mut Rng) -> bool {
+        self.failure_rate > 0. && rng.unit() < self.failure_rate
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct ChaosConfig {
+    pub redis: Fault,
+    pub nakama: Fault,
+    /// Seed of the failure draws, so a failing run can be reproduced
+    pub seed: u64,
+}
+
+impl ChaosConfig {
+    /// Reads the faults from the `CHAOS_*` env vars, unset vars inject nothing
+    pub fn from_env() -> Result<Self, Error> {
+        let config = Self {
+            redis: Fault {
+                failure_rate: env_var(REDIS_FAILURE_RATE_VAR)?,
+                latency_ms: env_var(REDIS_LATENCY_MS_VAR)?,
+            },
+            nakama: Fault {
+                failure_rate: env_var(NAKAMA_FAILURE_RATE_VAR)?,
+                latency_ms: env_var(NAKAMA_LATENCY_MS_VAR)?,
+            },
+            seed: env_var(SEED_VAR)?,
+        };
+        config.validate()?;
+
+        Ok(config)
+    }
+
+    pub fn validate(&self) -> Result<(), Error> {
+        for (name, fault) in [("redis", self.redis), ("nakama", self.nakama)] {
+            if !(0. ..=1.).contains(&fault.failure_rate) {
+                return Err(Error::Invalid(format!(
+                    "{name} failure rate must be between 0 and 1"
+                )));
+            }
+        }
+
+        Ok(())
+    }
+
+    pub fn is_active(&self) -> bool {
+        self.redis.is_active() || self.nakama.is_active()
+    }
+}
+
+fn env_var<T: std::str::FromStr + Default>(name: &str) -> Result<T, Error> {
+    match std::env::var(name) {
+        Ok(value) => value
+            .parse()
+            .map_err(|_| Error::Invalid(format!("`{name}` is not valid"))),
+        Err(_) => Ok(T::default()),
+    }
+}
+
+#[cfg(feature = "chaos")]
+mod injector {
+    use std::{
+        sync::{Mutex, OnceLock},
+        time::Duration,
+    };
+
+    use super::{ChaosConfig, Fault, Rng};
+
+    #[derive(Debug)]
+    struct Chaos {
+        config: ChaosConfig,
+        rng: Mutex<Rng>,
+    }
+
+    static CHAOS: OnceLock<Chaos> = OnceLock::new();
+
+    /// Installs the faults of the process, returns `false` when they were already installed
+    pub fn install(config: ChaosConfig) -> bool {
+        CHAOS
+            .set(Chaos {
+                config,
+                rng: Mutex::new(Rng::new(config.seed)),
+            })
+            .is_ok()
+    }
+
+    /// Waits the latency of the fault, returns whether the call fails
+    pub(super) async fn inject(fault: fn(&ChaosConfig) -> Fault) -> bool {
+        let Some(chaos) = CHAOS.get() else {
+            return false;
+        };
+        let fault = fault(&chaos.config);
+        if fault.latency_ms > 0 {
+            tokio::time::sleep(Duration::from_millis(fault.latency_ms)).await;
+        }
+        let mut rng = chaos
+            .rng
+            .lock()
+            .unwrap_or_else(std::sync::PoisonError::into_inner);
+
+        fault.fails(&mut rng)
+    }
+}
+
+#[cfg(feature = "chaos")]
+pub use injector::install;
+
+/// Hook of the Redis call sites, fails with an IO error when a failure is injected
+pub async fn redis() -> Result<(), RedisError> {
+    #[cfg(feature = "chaos")]
+    if injector::inject(|config| config.redis).await {
+        return Err(RedisError::from((
+            redis::ErrorKind::IoError,
+            "injected failure",
+        )));
+    }
+
+    Ok(())
+}
+
+/// Hook of the Nakama call sites
+pub async fn nakama() -> Result<(), nakama::Error> {
+    #[cfg(feature = "chaos")]
+    if injector::inject(|config| config.nakama).await {
+        return Err(nakama::Error::Injected);
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn failure_rate_decides_the_failures() {
+        let mut rng = Rng::new(7);
+        let fails = |failure_rate: f64, rng: &mut Rng| {
+            let fault = Fault {
+                failure_rate,
+                latency_ms: 0,
+            };
+            (0..1000).filter(|_| fault.fails(rng)).count()
+        };
+
+        assert_eq!(fails(0., &mut rng), 0);
+        assert_eq!(fails(1., &mut rng), 1000);
+        assert!((200..300).contains(&fails(0.25, &mut rng)));
+    }
+
+    #[test]
+    fn failure_rates_above_one_are_invalid() {
+        let mut config = ChaosConfig::default();
+        assert!(config.validate().is_ok());
+        assert!(!config.is_active());
+
+        config.nakama.latency_ms = 50;
+        assert!(config.is_active());
+
+        config.redis.failure_rate = 1.5;
+        assert!(matches!(config.validate(), Err(Error::Invalid(_))));
+    }
+}
@@ -0,0 +1,157 @@
+//! Per-match claims. Operations changing the players of a match, the ready-check replacements,
+//! backfill, lobby kicks and the removal of expired players, first claim the match with a
+//! `SET NX` key expiring after [`CLAIM_TTL`], then reload it with [`acquire_forming`] or
+//! [`acquire_active`], so two workers cannot hand the same last slot to two players nor write
+//! a stale copy over each other. A match whose claim is held is skipped until the next run.
+//! Holders release the claim when they fail midway, it expires on its own when they crash.
+//!
+//! Reconciliation dissolves matches no worker holds in memory and takes no claim.
+
+use std::{sync::LazyLock, time::Duration};
+
+use redis::{
+    AsyncCommands, ExistenceCheck, RedisError, Script, SetExpiry, SetOptions,
+    aio::MultiplexedConnection,
+};
+use uuid::Uuid;
+
+use crate::{
+    codec, namespace,
+    rpc::{Match, active_match_key},
+    store,
+};
+
+pub const CLAIM_KEY: &str = "match:claim";
+/// Longest a claim is held, well above the time to fill a match
+pub const CLAIM_TTL: Duration = Duration::from_secs(10);
+
+/// Drops the claim only when held by the caller
+static RELEASE: LazyLock<Script> = LazyLock::new(|| {
+    Script::new(
+        r"
+        if redis.call('GET', KEYS[1]) == ARGV[1] then
+            return redis.call('DEL', KEYS[1])
+        end
+        return 0
+        ",
+    )
+});
+
+pub fn claim_key(match_id: &Uuid) -> String {
+    namespace::key(format_args!("{CLAIM_KEY}:{match_id}"))
+}
+
+/// Claim held on a match, see [`acquire`]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Claim {
+    pub match_id: Uuid,
+    token: Uuid,
+}
+
+/// Claims `match_id` for `ttl`, `None` when another operation holds it
+pub async fn acquire(
+    conn: &mut MultiplexedConnection,
+    match_id: &Uuid,
+    ttl: Duration,
+) -> Result<Option<Claim>, RedisError> {
+    let token = Uuid::new_v4();
+    let options = SetOptions::default()
+        .conditional_set(ExistenceCheck::NX)
+        .with_expiration(SetExpiry::PX(ttl.as_millis() as u64));
+    let set: Option<String> = conn
+        .set_options(claim_key(match_id), token.to_string(), options)
+        .await?;
+
+    Ok(set.map(|_| Claim {
+        match_id: *match_id,
+        token,
+    }))
+}
+
+/// Claims the forming match `match_id` and reloads it with the updates of the previous holders,
+/// `None` when the claim is held or the match is gone
+pub async fn acquire_forming(
+    conn: &mut MultiplexedConnection,
+    match_id: &Uuid,
+) -> Result<Option<(Claim, Match)>, RedisError> {
+    let Some(claim) = acquire(conn, match_id, CLAIM_TTL).await? else {
+        return Ok(None);
+    };
+    let data = store::match_data(conn, match_id).await;
+
+    reload(conn, claim, data).await
+}
+
+/// Claims the active match `match_id` and reloads it, see [`acquire_forming`]
+pub async fn acquire_active(
+    conn: &mut MultiplexedConnection,
+    match_id: &Uuid,
+) -> Result<Option<(Claim, Match)>, RedisError> {
+    let Some(claim) = acquire(conn, match_id, CLAIM_TTL).await? else {
+        return Ok(None);
+    };
+    let data = conn.get(active_match_key(match_id)).await;
+
+    reload(conn, claim, data).await
+}
+
+/// Decodes the match loaded under `claim`, releasing it when the match is gone or failed to load
+async fn reload(
+    conn: &mut MultiplexedConnection,
+    claim: Claim,
+    data: Result<Option<Vec<u8>>, RedisError>,
+) -> Result<Option<(Claim, Match)>, RedisError> {
+    match data.map(|data| data.and_then(|bits| codec::decode::<Match>(&bits).ok())) {
+        Ok(Some(reloaded)) => Ok(Some((claim, reloaded))),
+        Ok(None) => release(conn, claim).await.map(|()| None),
+        Err(err) => {
+            release(conn, claim).await?;
+            Err(err)
+        }
+    }
+}
+
+/// Releases `claim`, nothing when it expired and another operation claimed the match since
+pub async fn release(conn: &mut MultiplexedConnection, claim: Claim) -> Result<(), RedisError> {
+    RELEASE
+        .key(claim_key(&claim.match_id))
+        .arg(claim.token.to_string())
+        .invoke_async(conn)
+        .await
+        .map(|_: i64| ())
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+    use crate::test_support::{create_redis, redis_client};
+
+    #[tokio::test]
+    async fn one_claim_per_match() {
+        let container = create_redis(6379).await;
+        let host = container.get_host().await.unwrap();
+        let port = container.get_host_port_ipv4(6379).await.unwrap();
+        let client = redis_client(host.to_string(), port);
+        let mut conn = client.get_multiplexed_async_connection().await.unwrap();
+        let (match_id, other_id) = (Uuid::new_v4(), Uuid::new_v4());
+
+        let claim = acquire(&mut conn, &match_id, CLAIM_TTL).await.unwrap();
+        let contended = acquire(&mut conn, &match_id, CLAIM_TTL).await.unwrap();
+        let other = acquire(&mut conn, &other_id, CLAIM_TTL).await.unwrap();
+        let stale = Claim {
+            match_id,
+            token: Uuid::new_v4(),
+        };
+        release(&mut conn, stale).await.unwrap();
+        let still_held = acquire(&mut conn, &match_id, CLAIM_TTL).await.unwrap();
+        release(&mut conn, claim.clone().unwrap()).await.unwrap();
+        let released = acquire(&mut conn, &match_id, CLAIM_TTL).await.unwrap();
+
+        assert!(claim.is_some());
+        assert_eq!(contended, None);
+        assert!(other.is_some());
+        assert_eq!(still_held, None);
+        assert!(released.is_some());
+    }
+}
@@ -0,0 +1,228 @@
+//! Nakama groups as clans. Players joining with a `clan_id` queue with the tag of their group,
+//! hosts invite their clan roster to their party with `CreateClanParty`, and matches of clan
+//! playlists pull queued players of the host's clan before strangers.
+
+use std::sync::Arc;
+
+use bitcode::{Decode, Encode};
+use serde::{Deserialize, Serialize};
+use tonic::Code;
+use tonic_types::{ErrorDetails, FieldViolation, StatusExt};
+use uuid::Uuid;
+
+use crate::{
+    nakama::{
+        self, Authenticated, NakamaClient,
+        endpoints::{GROUP_JOIN_REQUEST, Group},
+    },
+    playlists::Playlist,
+    rpc::{Match, QueuedPlayer},
+};
+
+#[derive(Debug, thiserror::Error)]
+pub enum Error {
+    #[error("player is not a member of clan `{0}`")]
+    NotMember(String),
+    #[error("playlist `{0}` is played with a clan")]
+    ClanRequired(String),
+    #[error(transparent)]
+    Nakama(#[from] nakama::Error),
+}
+
+impl From<Error> for tonic::Status {
+    fn from(value: Error) -> Self {
+        match value {
+            Error::NotMember(_) => Self::with_error_details(
+                Code::InvalidArgument,
+                value.to_string(),
+                ErrorDetails::with_bad_request(vec![FieldViolation::new(
+                    "clan_id",
+                    value.to_string(),
+                )]),
+            ),
+            Error::ClanRequired(_) => Self::failed_precondition(value.to_string()),
+            Error::Nakama(_) => Self::internal("Failed to load clan"),
+        }
+    }
+}
+
+/// Nakama group of a queued player
+#[derive(Debug, Clone, Serialize, Deserialize, Encode, Decode, PartialEq, Eq)]
+pub struct Clan {
+    pub id: String,
+    /// `tag` of the group metadata, its name when unset
+    pub tag: String,
+}
+
+impl From<&Group> for Clan {
+    fn from(group: &Group) -> Self {
+        let tag = serde_json::from_str::<serde_json::Value>(&group.metadata)
+            .ok()
+            .and_then(|metadata| metadata.get("tag")?.as_str().map(str::to_string))
+            .filter(|tag| !tag.is_empty())
+            .unwrap_or_else(|| group.name.clone());
+
+        Self {
+            id: group.id.clone(),
+            tag,
+        }
+    }
+}
+
+/// Clan `clan_id` of `player_id`, `None` when the player joins without clan
+pub async fn resolve(
+    nakama_client: &NakamaClient<Authenticated>,
+    http_client: Arc<reqwest::Client>,
+    player_id: &Uuid,
+    clan_id: &str,
+) -> Result<Option<Clan>, Error> {
+    if clan_id.is_empty() {
+        return Ok(None);
+    }
+    let groups = nakama_client
+        .get_user_groups(http_client, &player_id.to_string())
+        .await?;
+
+    groups
+        .iter()
+        .find(|joined| joined.group.id == clan_id && joined.state < GROUP_JOIN_REQUEST)
+        .map(|joined| Some(Clan::from(&joined.group)))
+        .ok_or_else(|| Error::NotMember(clan_id.to_string()))
+}
+
+/// Members of the clan `clan_id`, without the players who only requested to join
+pub async fn roster(
+    nakama_client: &NakamaClient<Authenticated>,
+    http_client: Arc<reqwest::Client>,
+    clan_id: &str,
+) -> Result<Vec<Uuid>, Error> {
+    let members = nakama_client
+        .get_group_members(http_client, clan_id)
+        .await?;
+
+    Ok(members
+        .iter()
+        .filter(|member| member.state < GROUP_JOIN_REQUEST)
+        .filter_map(|member| Uuid::parse_str(&member.user.id).ok())
+        .collect())
+}
+
+/// Clan playlists are only played by players of a clan
+pub fn check_playlist(playlist: Option<&Playlist>, clan: Option<&Clan>) -> Result<(), Error> {
+    match playlist {
+        Some(playlist) if playlist.clan && clan.is_none() => {
+            Err(Error::ClanRequired(playlist.id.clone()))
+        }
+        _ => Ok(()),
+    }
+}
+
+/// Clan whose members fill `a_match` first, the clan of its host in a clan playlist
+pub fn preferred_clan<'a>(a_match: &'a Match, clan_playlists: &[String]) -> Option<&'a str> {
+    if !clan_playlists.contains(&a_match.playlist) {
+        return None;
+    }
+
+    a_match
+        .host_player()
+        .and_then(|host| host.clan.as_ref())
+        .map(|clan| clan.id.as_str())
+}
+
+/// Moves the candidates of `clan` ahead of the others, both keep their order
+pub fn members_first<T>(
+    candidates: &mut [T],
+    clan: Option<&str>,
+    player: impl Fn(&T) -> &QueuedPlayer,
+) {
+    let Some(clan) = clan else {
+        return;
+    };
+    candidates.sort_by_key(|candidate| {
+        player(candidate)
+            .clan
+            .as_ref()
+            .is_none_or(|joined| joined.id != clan)
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use skillratings::mhth::MhthRating;
+
+    use super::*;
+    use crate::{playlists::Schedule, rpc::matchmaking::Player};
+
+    fn queued(join_mode: i32, clan: Option<&str>) -> QueuedPlayer {
+        let player: QueuedPlayer = (
+            Uuid::new_v4(),
+            Player {
+                join_mode,
+                region: "CAN".to_string(),
+                ..Default::default()
+            },
+            MhthRating::default(),
+        )
+            .into();
+        player.with_clan(clan.map(|id| Clan {
+            id: id.to_string(),
+            tag: id.to_uppercase(),
+        }))
+    }
+
+    #[test]
+    fn clan_tags_come_from_group_metadata() {
+        let mut group = Group {
+            id: "group_id".to_string(),
+            name: "Hunters".to_string(),
+            metadata: r#"{"tag": "HNT"}"#.to_string(),
+        };
+        assert_eq!(Clan::from(&group).tag, "HNT");
+
+        group.metadata = "{}".to_string();
+        assert_eq!(Clan::from(&group).tag, "Hunters");
+    }
+
+    #[test]
+    fn clan_playlists_fill_from_the_host_clan() {
+        let mut a_match = Match::host(&queued(0, Some("hnt")), &[]).unwrap();
+        let mut candidates = vec![
+            queued(1, None),
+            queued(1, Some("other")),
+            queued(1, Some("hnt")),
+        ];
+        let stranger = candidates[0].player_id;
+        let member = candidates[2].player_id;
+
+        assert_eq!(preferred_clan(&a_match, &["clans".to_string()]), None);
+        a_match.playlist = "clans".to_string();
+        let clan = preferred_clan(&a_match, &["clans".to_string()]);
+        assert_eq!(clan, Some("hnt"));
+
+        members_first(&mut candidates, clan, |player| player);
+        assert_eq!(candidates[0].player_id, member);
+        assert_eq!(candidates[1].player_id, stranger);
+    }
+
+    #[test]
+    fn clan_playlists_require_a_clan() {
+        let playlist = Playlist {
+            id: "clans".to_string(),
+            name: "Clan hunts".to_string(),
+            schedules: vec![Schedule::Always],
+            raid: None,
+            clan: true,
+        };
+        let clan = Clan {
+            id: "group_id".to_string(),
+            tag: "HNT".to_string(),
+        };
+
+        assert!(matches!(
+            check_playlist(Some(&playlist), None),
+            Err(Error::ClanRequired(_))
+        ));
+        assert!(check_playlist(Some(&playlist), Some(&clan)).is_ok());
+        assert!(check_playlist(None, None).is_ok());
+    }
+}
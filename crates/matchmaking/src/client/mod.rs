@@ -0,0 +1,817 @@
+//! Typed client of the matchmaking service for game servers and tooling. It attaches the session
+//! token and a request id to every call, retries calls failed by an unavailable server, answers
+//! the heartbeats measuring the player ping and turns the queue events into a stream of
+//! [`Event`]s.
+//!
+//! ```no_run
+//! # async fn run(token: &str) -> Result<(), matchmaking::client::Error> {
+//! use matchmaking::client::{MatchmakingClient, PlayerBuilder};
+//!
+//! let client = MatchmakingClient::connect("http://127.0.0.1:50051", token).await?;
+//! let mut events = client.watch_queue("player-id").await?;
+//! client
+//!     .join_queue(PlayerBuilder::new("player-id").region("EU").ping(40))
+//!     .await?;
+//! let found = events.wait_for_match().await?;
+//! println!("joining {}", found.match_id);
+//! # Ok(())
+//! # }
+//! ```
+
+use std::{future::Future, time::Duration};
+
+use tokio::sync::mpsc;
+use tokio_stream::wrappers::ReceiverStream;
+use tonic::{
+    Code, Request, Response, Status, Streaming,
+    metadata::{AsciiMetadataValue, errors::InvalidMetadataValue},
+    transport::Channel,
+};
+use uuid::Uuid;
+
+use crate::{
+    progression::Loadout,
+    rpc::{
+        matchmaking::{
+            AcceptRequeueRequest, AcceptRequeueResponse, ClanPartyRequest, ConfirmReadyRequest,
+            ConfirmReadyResponse, HeartbeatAck, InputDevice, JoinMode, JoinQueueResponse,
+            KickFromLobbyRequest, KickFromLobbyResponse, ListMatchesRequest, ListMatchesResponse,
+            MatchFound, MatchHistoryRequest, MatchHistoryResponse, MatchSettings,
+            MatchmakingPreferences, PartyInviteRequest, PartyMode, PartyRequest, PartyResponse,
+            Player, PreferencesRequest, PreferencesResponse, QueueEvent, QueueSizesRequest,
+            QueueSizesResponse, QueueType, QueuedPlayerRequest, QueuedPlayerResponse,
+            RejoinMatchRequest, RejoinMatchResponse, RemoveQueuedPlayerRequest,
+            RemoveQueuedPlayerResponse, RequestTickRequest, RequestTickResponse,
+            RequeuePartyRequest, RequeuePartyResponse, SetPreferencesRequest, VoiceChat,
+            WatchQueueRequest, matchmaking_service_client::MatchmakingServiceClient,
+            queue_event::Event,
+        },
+        server::request_id::REQUEST_ID_HEADER,
+    },
+    validation::CLIENT_VERSION_HEADER,
+};
+
+#[derive(Debug, thiserror::Error)]
+pub enum Error {
+    #[error("invalid matchmaking url: {0}")]
+    InvalidUrl(String),
+    #[error("failed to connect to the matchmaking service: {0}")]
+    Transport(#[from] tonic::transport::Error),
+    #[error(transparent)]
+    Status(#[from] Status),
+    #[error("session token is not a valid header value: {0}")]
+    InvalidToken(#[from] InvalidMetadataValue),
+    #[error("failed to serialize the loadout: {0}")]
+    Loadout(#[from] serde_json::Error),
+    #[error("match `{0}` could not be started")]
+    MatchFailed(String),
+    #[error("queue entry expired")]
+    QueueTimeout,
+    #[error("party `{0}` was disbanded")]
+    PartyDisbanded(String),
+    #[error("kicked from the lobby of match `{0}`")]
+    KickedFromLobby(String),
+    #[error("queue event stream closed")]
+    StreamClosed,
+}
+
+/// Retries of the calls failed by an unavailable server, waiting `initial_backoff` doubled for
+/// every attempt up to `max_backoff`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RetryPolicy {
+    /// Attempts of a call, `1` never retries
+    pub max_attempts: u32,
+    pub initial_backoff: Duration,
+    pub max_backoff: Duration,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        Self {
+            max_attempts: 3,
+            initial_backoff: Duration::from_millis(200),
+            max_backoff: Duration::from_secs(5),
+        }
+    }
+}
+
+impl RetryPolicy {
+    pub const NONE: Self = Self {
+        max_attempts: 1,
+        initial_backoff: Duration::ZERO,
+        max_backoff: Duration::ZERO,
+    };
+
+    /// Wait before retry `retry`, starting at `0`
+    pub fn backoff(&self, retry: u32) -> Duration {
+        self.initial_backoff
+            .saturating_mul(2u32.saturating_pow(retry))
+            .min(self.max_backoff)
+    }
+
+    /// Calls failed before reaching the handlers, they are safe to send again
+    pub fn is_retryable(status: &Status) -> bool {
+        matches!(status.code(), Code::Unavailable | Code::Aborted)
+    }
+}
+
+/// Builds the [`Player`] of a `join_queue` call
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct PlayerBuilder {
+    player: Player,
+    loadout: Option<Loadout>,
+}
+
+impl PlayerBuilder {
+    pub fn new(player_id: impl Into<String>) -> Self {
+        Self {
+            player: Player {
+                player_id: player_id.into(),
+                join_mode: JoinMode::JoinOrCreateRoom.into(),
+                ..Default::default()
+            },
+            loadout: None,
+        }
+    }
+
+    #[must_use]
+    pub fn region(mut self, region: impl Into<String>) -> Self {
+        self.player.region = region.into();
+        self
+    }
+
+    #[must_use]
+    pub const fn ping(mut self, ping: i32) -> Self {
+        self.player.ping = ping;
+        self
+    }
+
+    #[must_use]
+    pub const fn difficulty(mut self, difficulty: i32) -> Self {
+        self.player.difficulty = difficulty;
+        self
+    }
+
+    #[must_use]
+    pub fn join_mode(mut self, join_mode: JoinMode) -> Self {
+        self.player.set_join_mode(join_mode);
+        self
+    }
+
+    /// Queues with the confirmed members of the party hosted by the player
+    #[must_use]
+    pub fn party(mut self, member_ids: impl IntoIterator<Item = impl Into<String>>) -> Self {
+        self.player.party_member_id = member_ids.into_iter().map(Into::into).collect();
+        let party_mode = if self.player.party_member_id.is_empty() {
+            PartyMode::Solo
+        } else {
+            PartyMode::Party
+        };
+        self.player.set_party_mode(party_mode);
+        self
+    }
+
+    #[must_use]
+    pub fn playlist(mut self, playlist: impl Into<String>) -> Self {
+        self.player.playlist = playlist.into();
+        self
+    }
+
+    #[must_use]
+    pub fn mission_types(
+        mut self,
+        mission_types: impl IntoIterator<Item = impl Into<String>>,
+    ) -> Self {
+        self.player.mission_types = mission_types.into_iter().map(Into::into).collect();
+        self
+    }
+
+    #[must_use]
+    pub fn maps(mut self, maps: impl IntoIterator<Item = impl Into<String>>) -> Self {
+        self.player.maps = maps.into_iter().map(Into::into).collect();
+        self
+    }
+
+    #[must_use]
+    pub fn input_device(mut self, input_device: InputDevice) -> Self {
+        self.player.set_input_device(input_device);
+        self
+    }
+
+    #[must_use]
+    pub fn queue_type(mut self, queue_type: QueueType) -> Self {
+        self.player.set_queue_type(queue_type);
+        self
+    }
+
+    /// Modifiers of the hosted match, the player must host it
+    #[must_use]
+    pub fn match_settings(mut self, match_settings: MatchSettings) -> Self {
+        self.player.match_settings = Some(match_settings);
+        self
+    }
+
+    /// Nakama group the player queues with
+    #[must_use]
+    pub fn clan(mut self, clan_id: impl Into<String>) -> Self {
+        self.player.clan_id = clan_id.into();
+        self
+    }
+
+    #[must_use]
+    pub fn voice_chat(mut self, voice_chat: VoiceChat) -> Self {
+        self.player.set_voice_chat(voice_chat);
+        self
+    }
+
+    /// Preferred languages as ISO 639-1 codes
+    #[must_use]
+    pub fn languages(mut self, languages: impl IntoIterator<Item = impl Into<String>>) -> Self {
+        self.player.languages = languages.into_iter().map(Into::into).collect();
+        self
+    }
+
+    /// Measured ping to a datacenter, the match is hosted in the datacenter of its players with
+    /// the lowest worst ping
+    #[must_use]
+    pub fn datacenter_ping(mut self, datacenter: impl Into<String>, ping: i32) -> Self {
+        self.player.datacenter_pings.insert(datacenter.into(), ping);
+        self
+    }
+
+    /// Fill matches one difficulty easier or harder when the chosen one is starved
+    #[must_use]
+    pub const fn adjacent_difficulty(mut self, adjacent_difficulty: bool) -> Self {
+        self.player.adjacent_difficulty = adjacent_difficulty;
+        self
+    }
+
+    #[must_use]
+    pub fn loadout(mut self, loadout: Loadout) -> Self {
+        self.loadout = Some(loadout);
+        self
+    }
+
+    pub fn build(self) -> Result<Player, Error> {
+        let mut player = self.player;
+        if let Some(loadout) = self.loadout.filter(|loadout| !loadout.is_empty()) {
+            player.loadout_config = serde_json::to_string(&loadout)?;
+        }
+
+        Ok(player)
+    }
+}
+
+impl From<Player> for PlayerBuilder {
+    fn from(value: Player) -> Self {
+        Self {
+            player: value,
+            loadout: None,
+        }
+    }
+}
+
+/// Queue events of a player, see [`MatchmakingClient::watch_queue`]
+#[derive(Debug)]
+pub struct QueueEvents {
+    stream: Streaming<QueueEvent>,
+    /// Confirms the ready-checks met by [`QueueEvents::wait_for_match`]
+    client: MatchmakingClient,
+    player_id: String,
+}
+
+impl QueueEvents {
+    /// Next event, `None` once the server closed the stream
+    pub async fn next_event(&mut self) -> Result<Option<Event>, Error> {
+        while let Some(event) = self.stream.message().await? {
+            if let Some(event) = event.event {
+                return Ok(Some(event));
+            }
+        }
+
+        Ok(None)
+    }
+
+    /// Confirms the player is ready for the match `match_id`
+    pub async fn confirm_ready(&self, match_id: String) -> Result<ConfirmReadyResponse, Error> {
+        self.client
+            .confirm_ready(self.player_id.clone(), match_id)
+            .await
+    }
+
+    /// Waits for the match of the player and confirms its ready-checks, skipping queue positions,
+    /// party host changes and kicks that requeued the player
+    pub async fn wait_for_match(&mut self) -> Result<MatchFound, Error> {
+        loop {
+            match self.next_event().await? {
+                Some(Event::MatchFound(found)) => return Ok(found),
+                Some(Event::MatchFailed(failed)) => {
+                    return Err(Error::MatchFailed(failed.match_id));
+                }
+                Some(Event::QueueTimeout(_)) => return Err(Error::QueueTimeout),
+                Some(Event::PartyDisbanded(disbanded)) => {
+                    return Err(Error::PartyDisbanded(disbanded.party_id));
+                }
+                Some(Event::KickedFromLobby(kicked)) if !kicked.requeued => {
+                    return Err(Error::KickedFromLobby(kicked.match_id));
+                }
+                Some(Event::ReadyCheck(check)) => {
+                    self.confirm_ready(check.match_id).await?;
+                }
+                Some(
+                    Event::QueuePosition(_)
+                    | Event::PartyHostChanged(_)
+                    | Event::KickedFromLobby(_),
+                ) => {}
+                None => return Err(Error::StreamClosed),
+            }
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct MatchmakingClient {
+    inner: MatchmakingServiceClient<Channel>,
+    token: AsciiMetadataValue,
+    /// Checked by the server before queueing, see [`crate::validation`]
+    client_version: Option<AsciiMetadataValue>,
+    retry: RetryPolicy,
+}
+
+impl MatchmakingClient {
+    pub async fn connect(url: impl Into<String>, token: &str) -> Result<Self, Error> {
+        let channel = Channel::from_shared(url.into())
+            .map_err(|err| Error::InvalidUrl(err.to_string()))?
+            .connect()
+            .await?;
+
+        Self::new(channel, token)
+    }
+
+    /// Client of an open channel, channels are cheap to share between clients of many players
+    pub fn new(channel: Channel, token: &str) -> Result<Self, Error> {
+        Ok(Self {
+            inner: MatchmakingServiceClient::new(channel),
+            token: token.parse()?,
+            client_version: None,
+            retry: RetryPolicy::default(),
+        })
+    }
+
+    #[must_use]
+    pub const fn with_retry(mut self, retry: RetryPolicy) -> Self {
+        self.retry = retry;
+        self
+    }
+
+    /// Sends the version of the client build with every call
+    pub fn with_client_version(mut self, version: &str) -> Result<Self, Error> {
+        self.client_version = Some(version.parse()?);
+        Ok(self)
+    }
+
+    /// Replaces the session token, e.g. once it was refreshed
+    pub fn set_token(&mut self, token: &str) -> Result<(), Error> {
+        self.token = token.parse()?;
+        Ok(())
+    }
+
+    /// Generated client for the calls without a typed method, requests still need the token
+    pub const fn inner(&mut self) -> &mut MatchmakingServiceClient<Channel> {
+        &mut self.inner
+    }
+
+    fn request<T>(&self, message: T, request_id: &AsciiMetadataValue) -> Request<T> {
+        let mut request = Request::new(message);
+        let metadata = request.metadata_mut();
+        metadata.insert("authorization", self.token.clone());
+        metadata.insert(REQUEST_ID_HEADER, request_id.clone());
+        if let Some(version) = &self.client_version {
+            metadata.insert(CLIENT_VERSION_HEADER, version.clone());
+        }
+
+        request
+    }
+
+    /// Sends `message` until it succeeds, fails with a status that is not retryable or runs
+    /// out of attempts. Retries keep the request id, so the server logs tie them together.
+    async fn call<T, R, F, Fut>(&self, message: T, call: F) -> Result<R, Error>
+    where
+        T: Clone,
+        F: Fn(MatchmakingServiceClient<Channel>, Request<T>) -> Fut,
+        Fut: Future<Output = Result<Response<R>, Status>>,
+    {
+        let request_id = new_request_id();
+        let mut retry = 0;
+        loop {
+            let request = self.request(message.clone(), &request_id);
+            match call(self.inner.clone(), request).await {
+                Ok(response) => return Ok(response.into_inner()),
+                Err(status)
+                    if RetryPolicy::is_retryable(&status)
+                        && retry + 1 < self.retry.max_attempts =>
+                {
+                    tokio::time::sleep(self.retry.backoff(retry)).await;
+                    retry += 1;
+                }
+                Err(status) => return Err(status.into()),
+            }
+        }
+    }
+
+    /// Answers the heartbeat probes of the server until either side closes the stream, so the
+    /// player queues with the ping measured by the server. Run it in its own task.
+    pub async fn heartbeat(&self) -> Result<(), Error> {
+        let (tx, rx) = mpsc::channel(16);
+        let request = self.request(ReceiverStream::new(rx), &new_request_id());
+        let mut probes = self.inner.clone().heartbeat(request).await?.into_inner();
+        while let Some(probe) = probes.message().await? {
+            let ack = HeartbeatAck {
+                sequence: probe.sequence,
+            };
+            if tx.send(ack).await.is_err() {
+                break;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Queues the player, joining again replaces the queue entry and keeps its position. Joins
+    /// refused by the server answer with the `JoinQueueStatus` saying why instead of an error
+    pub async fn join_queue(
+        &self,
+        player: impl Into<PlayerBuilder>,
+    ) -> Result<JoinQueueResponse, Error> {
+        let player = player.into().build()?;
+
+        self.call(player, |mut client, request| async move {
+            client.join_queue(request).await
+        })
+        .await
+    }
+
+    /// Subscribes to the queue events of the player, subscribe before joining the queue so no
+    /// event is missed
+    pub async fn watch_queue(&self, player_id: impl Into<String>) -> Result<QueueEvents, Error> {
+        let player_id = player_id.into();
+        let watch = WatchQueueRequest {
+            player_id: player_id.clone(),
+        };
+        let stream = self
+            .call(watch, |mut client, request| async move {
+                client.watch_queue(request).await
+            })
+            .await?;
+
+        Ok(QueueEvents {
+            stream,
+            client: self.clone(),
+            player_id,
+        })
+    }
+
+    pub async fn invite_to_party(
+        &self,
+        player_id: impl Into<String>,
+        invitee_id: impl Into<String>,
+    ) -> Result<PartyResponse, Error> {
+        let invite = PartyInviteRequest {
+            player_id: player_id.into(),
+            invitee_id: invitee_id.into(),
+        };
+
+        self.call(invite, |mut client, request| async move {
+            client.invite_to_party(request).await
+        })
+        .await
+    }
+
+    /// Invites the members of the clan `clan_id` to the party of `player_id`
+    pub async fn create_clan_party(
+        &self,
+        player_id: impl Into<String>,
+        clan_id: impl Into<String>,
+    ) -> Result<PartyResponse, Error> {
+        let request = ClanPartyRequest {
+            player_id: player_id.into(),
+            clan_id: clan_id.into(),
+        };
+
+        self.call(request, |mut client, request| async move {
+            client.create_clan_party(request).await
+        })
+        .await
+    }
+
+    pub async fn accept_invite(
+        &self,
+        player_id: impl Into<String>,
+        party_id: impl Into<String>,
+    ) -> Result<PartyResponse, Error> {
+        let accept = PartyRequest {
+            player_id: player_id.into(),
+            party_id: party_id.into(),
+        };
+
+        self.call(accept, |mut client, request| async move {
+            client.accept_invite(request).await
+        })
+        .await
+    }
+
+    pub async fn leave_party(
+        &self,
+        player_id: impl Into<String>,
+        party_id: impl Into<String>,
+    ) -> Result<PartyResponse, Error> {
+        let leave = PartyRequest {
+            player_id: player_id.into(),
+            party_id: party_id.into(),
+        };
+
+        self.call(leave, |mut client, request| async move {
+            client.leave_party(request).await
+        })
+        .await
+    }
+
+    /// Removes `kicked_id` from the forming match hosted by `player_id`
+    pub async fn kick_from_lobby(
+        &self,
+        player_id: impl Into<String>,
+        match_id: impl Into<String>,
+        kicked_id: impl Into<String>,
+    ) -> Result<KickFromLobbyResponse, Error> {
+        let kick = KickFromLobbyRequest {
+            player_id: player_id.into(),
+            match_id: match_id.into(),
+            kicked_id: kicked_id.into(),
+        };
+
+        self.call(kick, |mut client, request| async move {
+            client.kick_from_lobby(request).await
+        })
+        .await
+    }
+
+    /// Answers the `ReadyCheck` event of the match `match_id`
+    pub async fn confirm_ready(
+        &self,
+        player_id: impl Into<String>,
+        match_id: impl Into<String>,
+    ) -> Result<ConfirmReadyResponse, Error> {
+        let confirm = ConfirmReadyRequest {
+            player_id: player_id.into(),
+            match_id: match_id.into(),
+        };
+
+        self.call(confirm, |mut client, request| async move {
+            client.confirm_ready(request).await
+        })
+        .await
+    }
+
+    /// Queues the players who finished the match `match_id` again as the party of its host
+    pub async fn requeue_party(
+        &self,
+        player_id: impl Into<String>,
+        match_id: impl Into<String>,
+    ) -> Result<RequeuePartyResponse, Error> {
+        let requeue = RequeuePartyRequest {
+            player_id: player_id.into(),
+            match_id: match_id.into(),
+        };
+
+        self.call(requeue, |mut client, request| async move {
+            client.requeue_party(request).await
+        })
+        .await
+    }
+
+    /// Agrees to be queued again by the host of the completed match `match_id`
+    pub async fn accept_requeue(
+        &self,
+        player_id: impl Into<String>,
+        match_id: impl Into<String>,
+    ) -> Result<AcceptRequeueResponse, Error> {
+        let accept = AcceptRequeueRequest {
+            player_id: player_id.into(),
+            match_id: match_id.into(),
+        };
+
+        self.call(accept, |mut client, request| async move {
+            client.accept_requeue(request).await
+        })
+        .await
+    }
+
+    pub async fn rejoin_match(
+        &self,
+        player_id: impl Into<String>,
+    ) -> Result<RejoinMatchResponse, Error> {
+        let rejoin = RejoinMatchRequest {
+            player_id: player_id.into(),
+        };
+
+        self.call(rejoin, |mut client, request| async move {
+            client.rejoin_match(request).await
+        })
+        .await
+    }
+
+    pub async fn preferences(
+        &self,
+        player_id: impl Into<String>,
+    ) -> Result<PreferencesResponse, Error> {
+        let preferences = PreferencesRequest {
+            player_id: player_id.into(),
+        };
+
+        self.call(preferences, |mut client, request| async move {
+            client.get_preferences(request).await
+        })
+        .await
+    }
+
+    /// Replaces the preferences filling the fields the joins of the player leave unset
+    pub async fn set_preferences(
+        &self,
+        player_id: impl Into<String>,
+        preferences: MatchmakingPreferences,
+    ) -> Result<PreferencesResponse, Error> {
+        let preferences = SetPreferencesRequest {
+            player_id: player_id.into(),
+            preferences: Some(preferences),
+        };
+
+        self.call(preferences, |mut client, request| async move {
+            client.set_preferences(request).await
+        })
+        .await
+    }
+
+    /// Page of the completed matches of the player, pass the `next_cursor` of the response in
+    /// the request of the next page
+    pub async fn match_history(
+        &self,
+        history: MatchHistoryRequest,
+    ) -> Result<MatchHistoryResponse, Error> {
+        self.call(history, |mut client, request| async move {
+            client.get_match_history(request).await
+        })
+        .await
+    }
+
+    /// Admin only, sizes of the queues and of the open and closed match sets
+    pub async fn queue_sizes(&self) -> Result<QueueSizesResponse, Error> {
+        self.call(QueueSizesRequest {}, |mut client, request| async move {
+            client.get_queue_sizes(request).await
+        })
+        .await
+    }
+
+    /// Admin only, decoded queue entry of the player and the match it was matched into
+    pub async fn queued_player(
+        &self,
+        player_id: impl Into<String>,
+    ) -> Result<QueuedPlayerResponse, Error> {
+        let queued = QueuedPlayerRequest {
+            player_id: player_id.into(),
+        };
+
+        self.call(queued, |mut client, request| async move {
+            client.get_queued_player(request).await
+        })
+        .await
+    }
+
+    /// Admin only, removes the player from every queue
+    pub async fn remove_queued_player(
+        &self,
+        player_id: impl Into<String>,
+    ) -> Result<RemoveQueuedPlayerResponse, Error> {
+        let remove = RemoveQueuedPlayerRequest {
+            player_id: player_id.into(),
+        };
+
+        self.call(remove, |mut client, request| async move {
+            client.remove_queued_player(request).await
+        })
+        .await
+    }
+
+    /// Admin only, matches in `state`, e.g. `forming`
+    pub async fn list_matches(
+        &self,
+        state: impl Into<String>,
+    ) -> Result<ListMatchesResponse, Error> {
+        let list = ListMatchesRequest {
+            state: state.into(),
+        };
+
+        self.call(list, |mut client, request| async move {
+            client.list_matches(request).await
+        })
+        .await
+    }
+
+    /// Admin only, asks the leader worker to run a tick without waiting for its interval
+    pub async fn request_tick(&self) -> Result<RequestTickResponse, Error> {
+        self.call(RequestTickRequest {}, |mut client, request| async move {
+            client.request_tick(request).await
+        })
+        .await
+    }
+}
+
+fn new_request_id() -> AsciiMetadataValue {
+    Uuid::new_v4()
+        .to_string()
+        .parse()
+        .expect("uuids are valid header values")
+}
+
+#[cfg(test)]
+mod tests {
+    use uuid::Uuid;
+
+    use super::*;
+
+    #[test]
+    fn builder_sets_the_party_and_loadout() {
+        let item = Uuid::new_v4();
+        let player = PlayerBuilder::new("host")
+            .region("EU")
+            .ping(40)
+            .party(["member"])
+            .input_device(InputDevice::Controller)
+            .loadout(Loadout {
+                items: vec![item],
+                skills: Vec::new(),
+            })
+            .build()
+            .unwrap();
+
+        assert_eq!(player.player_id, "host");
+        assert_eq!(player.region, "EU");
+        assert_eq!(player.party_mode(), PartyMode::Party);
+        assert_eq!(player.join_mode(), JoinMode::JoinOrCreateRoom);
+        assert_eq!(player.input_device(), InputDevice::Controller);
+        assert_eq!(
+            Loadout::parse(&player.loadout_config).unwrap().items,
+            vec![item]
+        );
+    }
+
+    #[test]
+    fn empty_loadouts_are_the_default_loadout() {
+        let player = PlayerBuilder::new("player")
+            .loadout(Loadout::default())
+            .build()
+            .unwrap();
+
+        assert!(player.loadout_config.is_empty());
+        assert_eq!(player.party_mode(), PartyMode::Solo);
+    }
+
+    #[test]
+    fn backoff_doubles_up_to_max() {
+        let retry = RetryPolicy::default();
+
+        assert_eq!(retry.backoff(0), Duration::from_millis(200));
+        assert_eq!(retry.backoff(2), Duration::from_millis(800));
+        assert_eq!(retry.backoff(40), retry.max_backoff);
+        assert!(RetryPolicy::is_retryable(&Status::unavailable("down")));
+        assert!(!RetryPolicy::is_retryable(&Status::invalid_argument("bad")));
+    }
+
+    #[tokio::test]
+    async fn requests_carry_the_token_and_request_id() {
+        let channel = Channel::from_static("http://127.0.0.1:50051").connect_lazy();
+        let client = MatchmakingClient::new(channel, "token")
+            .unwrap()
+            .with_client_version("1.4.2")
+            .unwrap();
+        let request_id: AsciiMetadataValue = "request".parse().unwrap();
+
+        let request = client.request((), &request_id);
+
+        assert_eq!(request.metadata().get("authorization").unwrap(), "token");
+        assert_eq!(
+            request.metadata().get(REQUEST_ID_HEADER).unwrap(),
+            "request"
+        );
+        assert_eq!(
+            request.metadata().get(CLIENT_VERSION_HEADER).unwrap(),
+            "1.4.2"
+        );
+        assert!(
+            MatchmakingClient::new(
+                Channel::from_static("http://127.0.0.1:50051").connect_lazy(),
+                "bad\ntoken"
+            )
+            .is_err()
+        );
+    }
+}
@@ -0,0 +1,131 @@
+//! Time source of the server and the worker. Queue join times are seconds since
+//! the game epoch, so wait windows can be tested with a [`FakeClock`].
+
+use std::{fmt::Debug, sync::Mutex};
+
+use chrono::{DateTime, NaiveDate, NaiveDateTime, TimeDelta, Utc};
+
+/// Env var with the game epoch as `YYYY-MM-DD`, changing it shifts the join times of queued players
+pub const EPOCH_VAR: &str = "GAME_EPOCH";
+pub const DEFAULT_EPOCH: NaiveDate = NaiveDate::from_ymd_opt(2025, 1, 1).unwrap();
+
+#[derive(Debug, thiserror::Error)]
+pub enum Error {
+    #[error("invalid {EPOCH_VAR} `{0}`: {1}")]
+    InvalidEpoch(String, chrono::ParseError),
+}
+
+pub trait Clock: Debug + Send + Sync {
+    fn now(&self) -> DateTime<Utc>;
+
+    /// Midnight UTC of the first game day
+    fn epoch(&self) -> NaiveDateTime;
+
+    /// Seconds since the epoch, the unit of queue join times
+    fn time_since_epoch(&self) -> i64 {
+        self.now()
+            .naive_utc()
+            .signed_duration_since(self.epoch())
+            .num_seconds()
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SystemClock {
+    epoch: NaiveDateTime,
+}
+
+impl SystemClock {
+    pub const fn new(epoch: NaiveDate) -> Self {
+        Self {
+            epoch: epoch.and_time(chrono::NaiveTime::MIN),
+        }
+    }
+
+    /// Reads the epoch from [`EPOCH_VAR`], defaults to [`DEFAULT_EPOCH`]
+    pub fn from_env() -> Result<Self, Error> {
+        let Ok(epoch) = std::env::var(EPOCH_VAR) else {
+            return Ok(Self::default());
+        };
+        let date = NaiveDate::parse_from_str(&epoch, "%Y-%m-%d")
+            .map_err(|err| Error::InvalidEpoch(epoch, err))?;
+
+        Ok(Self::new(date))
+    }
+}
+
+impl Default for SystemClock {
+    fn default() -> Self {
+        Self::new(DEFAULT_EPOCH)
+    }
+}
+
+impl Clock for SystemClock {
+    fn now(&self) -> DateTime<Utc> {
+        Utc::now()
+    }
+
+    fn epoch(&self) -> NaiveDateTime {
+        self.epoch
+    }
+}
+
+/// Clock standing still until advanced, for tests of wait windows
+#[derive(Debug)]
+pub struct FakeClock {
+    now: Mutex<DateTime<Utc>>,
+    epoch: NaiveDateTime,
+}
+
+impl FakeClock {
+    pub fn at(now: DateTime<Utc>) -> Self {
+        Self {
+            now: Mutex::new(now),
+            epoch: SystemClock::default().epoch,
+        }
+    }
+
+    pub fn advance(&self, delta: TimeDelta) {
+        let mut now = self
+            .now
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner());
+        *now += delta;
+    }
+}
+
+impl Clock for FakeClock {
+    fn now(&self) -> DateTime<Utc> {
+        *self
+            .now
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+
+    fn epoch(&self) -> NaiveDateTime {
+        self.epoch
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn seconds_since_epoch() {
+        let epoch = DEFAULT_EPOCH.and_time(chrono::NaiveTime::MIN).and_utc();
+        let clock = FakeClock::at(epoch + TimeDelta::minutes(2));
+
+        assert_eq!(clock.time_since_epoch(), 120);
+        clock.advance(TimeDelta::seconds(30));
+        assert_eq!(clock.time_since_epoch(), 150);
+        assert!(SystemClock::default().time_since_epoch() > 22810515);
+    }
+
+    #[test]
+    fn custom_epoch() {
+        let clock = SystemClock::new(NaiveDate::from_ymd_opt(2030, 1, 1).unwrap());
+
+        assert!(clock.time_since_epoch() < SystemClock::default().time_since_epoch());
+    }
+}
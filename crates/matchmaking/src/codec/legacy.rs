@@ -0,0 +1,102 @@
+//! Frozen layouts of the payloads written before envelopes existed, decoded by
+//! [`Versioned::upgrade`](super::Versioned::upgrade) as version `0` and converted to the current
+//! types. Never change these structs.
+
+use bitcode::{Decode, Encode};
+use skillratings::mhth::MhthRating;
+use uuid::Uuid;
+
+use crate::{
+    experiments::MatchParams,
+    lifecycle::Lifecycle,
+    rpc::{Match, MatchKind, QueuedPlayer, RegionSource},
+};
+
+/// [`QueuedPlayer`] of the first release
+#[derive(Debug, Clone, Encode, Decode)]
+pub struct QueuedPlayerV0 {
+    pub player_id: Uuid,
+    pub skillrating: MhthRating,
+    pub region: String,
+    pub ping: i32,
+    pub difficulty: i32,
+    pub join_mode: i32,
+    pub party_mode: i32,
+    pub party_ids: Vec<String>,
+    pub join_time: i64,
+}
+
+impl From<QueuedPlayerV0> for QueuedPlayer {
+    fn from(value: QueuedPlayerV0) -> Self {
+        Self {
+            player_id: value.player_id,
+            skillrating: value.skillrating,
+            region: value.region,
+            ping: value.ping,
+            difficulty: value.difficulty,
+            join_mode: value.join_mode,
+            party_mode: value.party_mode,
+            party_ids: value.party_ids,
+            join_time: value.join_time,
+            trust: 1.,
+            playlist: String::new(),
+            mission_types: Vec::new(),
+            maps: Vec::new(),
+            experiments: Vec::new(),
+            params: MatchParams::default(),
+            skill_band: 0,
+            smurf: false,
+            request_id: String::new(),
+            input_device: 0,
+            region_source: RegionSource::Declared,
+            queue_type: 0,
+            match_settings: None,
+            clan: None,
+            languages: Vec::new(),
+            voice_chat: 0,
+            datacenter_pings: Vec::new(),
+            adjacent_difficulty: false,
+        }
+    }
+}
+
+/// [`Match`] of the first release
+#[derive(Debug, Clone, Encode, Decode)]
+pub struct MatchV0 {
+    pub id: Uuid,
+    pub players: Vec<QueuedPlayerV0>,
+    pub region: String,
+    pub host_id: Uuid,
+}
+
+impl From<MatchV0> for Match {
+    fn from(value: MatchV0) -> Self {
+        // the difficulty was the one of the host
+        let difficulty = value
+            .players
+            .iter()
+            .find(|player| player.player_id == value.host_id)
+            .map(|host| host.difficulty)
+            .unwrap_or_default();
+
+        Self {
+            id: value.id,
+            players: value.players.into_iter().map(Into::into).collect(),
+            region: value.region,
+            host_id: value.host_id,
+            playlist: String::new(),
+            experiments: Vec::new(),
+            params: MatchParams::default(),
+            lifecycle: Lifecycle::default(),
+            nakama_match_id: None,
+            game_server: None,
+            squads: Vec::new(),
+            kind: MatchKind::Cooperative,
+            challenge: None,
+            datacenter: None,
+            difficulty,
+            settings: None,
+            voice_chat: 0,
+        }
+    }
+}
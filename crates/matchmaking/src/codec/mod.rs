@@ -0,0 +1,647 @@
+//! Encoding of the payloads stored in Redis. Payloads are bitcode, sealed with AES-256-GCM when
+//! [`KEYS_VAR`] is set, so queued player records (ids, party links, skill) are not readable from
+//! Redis. Sealed payloads are tagged with the id of their key, the first key seals new payloads
+//! and every key opens them, so keys can be rotated without draining the queues. Once keys are
+//! set unsealed payloads are rejected, so payloads written to Redis by anyone without the keys
+//! are not decoded. They are only read until [`PLAINTEXT_UNTIL_VAR`], while the payloads
+//! written before encryption was enabled drain.
+//!
+//! Queue members are removed by value, so sealing is deterministic: the nonce is derived from
+//! the payload. Equal payloads seal to the same bytes, which only tells that they are equal.
+//!
+//! Every payload is wrapped in an envelope with the schema version of its type, see
+//! [`Versioned`]. Servers of a new release decode the payloads written by the previous one
+//! while a deploy rolls out. Payloads above [`COMPRESSION_THRESHOLD`] are compressed with LZ4,
+//! the first envelope byte flags them so small payloads skip decompression.
+
+use std::sync::OnceLock;
+
+use bitcode::{DecodeOwned, Encode};
+use chrono::{DateTime, Utc};
+use hmac::{Hmac, Mac};
+use ring::aead::{AES_256_GCM, Aad, LessSafeKey, NONCE_LEN, Nonce, UnboundKey};
+use sha2::Sha256;
+use uuid::Uuid;
+
+pub mod legacy;
+
+/// Env var with the keys as `id:hex,id:hex`, payloads are not sealed when unset
+pub const KEYS_VAR: &str = "REDIS_ENCRYPTION_KEYS";
+/// End of the window unsealed payloads are still read in after enabling encryption, RFC 3339,
+/// e.g. `2027-01-01T00:00:00Z`. Unsealed payloads are rejected when unset
+pub const PLAINTEXT_UNTIL_VAR: &str = "REDIS_PLAINTEXT_UNTIL";
+/// First byte of a sealed payload, followed by the key id length, the key id, the nonce and
+/// the ciphertext
+const SEALED: u8 = 0xa5;
+/// First byte of an envelope, followed by the schema version and the bitcode payload
+const ENVELOPE: u8 = 0xb1;
+/// First byte of an envelope with an LZ4 compressed payload
+const COMPRESSED: u8 = 0xb2;
+/// Encoded size from which payloads are compressed, e.g. full matches and raids
+pub const COMPRESSION_THRESHOLD: usize = 256;
+const KEY_LEN: usize = 32;
+
+static KEYRING: OnceLock<Keyring> = OnceLock::new();
+
+#[derive(Debug, thiserror::Error)]
+pub enum Error {
+    #[error(transparent)]
+    Bitcode(#[from] bitcode::Error),
+    #[error("payload sealed with unknown key `{0}`")]
+    UnknownKey(String),
+    #[error("sealed payload failed authentication")]
+    Unauthenticated,
+    #[error("payload is not sealed")]
+    Unsealed,
+    #[error("invalid `{PLAINTEXT_UNTIL_VAR}` `{0}`, expected RFC 3339")]
+    InvalidPlaintextUntil(String),
+    #[error("encryption keys are not valid: {0}")]
+    InvalidKeys(&'static str),
+    #[error("payload schema version {0} is not supported")]
+    UnsupportedVersion(u8),
+    #[error(transparent)]
+    Decompress(#[from] lz4_flex::block::DecompressError),
+}
+
+/// Payload stored in Redis. When the encoded layout of a type changes, e.g. a field is added,
+/// bump `VERSION`, freeze the previous layout in [`legacy`] and decode it in `upgrade`.
+pub trait Versioned: Encode {
+    /// Schema version written in the envelope of new payloads
+    const VERSION: u8 = 1;
+
+    /// Decodes a payload written with an older `version`. Payloads written before envelopes
+    /// existed are version `0`, by default with the layout of version `1`.
+    fn upgrade(version: u8, payload: &[u8]) -> Result<Self, Error>
+    where
+        Self: DecodeOwned,
+    {
+        match version {
+            0 => Ok(bitcode::decode(payload)?),
+            _ => Err(Error::UnsupportedVersion(version)),
+        }
+    }
+}
+
+impl Versioned for Vec<String> {}
+impl Versioned for [String] {}
+impl Versioned for (Uuid, u64) {}
+
+struct Key {
+    id: String,
+    aead: LessSafeKey,
+    nonce_key: Hmac<Sha256>,
+}
+
+impl Key {
+    /// Derives the encryption and nonce keys from `secret`
+    fn new(id: &str, secret: &[u8]) -> Result<Self, Error> {
+        if id.is_empty() || id.len() > usize::from(u8::MAX) {
+            return Err(Error::InvalidKeys("key ids must be 1 to 255 bytes"));
+        }
+        if secret.len() != KEY_LEN {
+            return Err(Error::InvalidKeys("keys must be 32 bytes"));
+        }
+        let derive = |label: &[u8]| {
+            let mut mac = Hmac::<Sha256>::new_from_slice(secret).expect("HMAC takes any key size");
+            mac.update(label);
+            mac.finalize().into_bytes()
+        };
+        let aead = UnboundKey::new(&AES_256_GCM, &derive(b"mhth:redis:encrypt"))
+            .map_err(|_| Error::InvalidKeys("keys must be 32 bytes"))?;
+        let nonce_key = Hmac::<Sha256>::new_from_slice(&derive(b"mhth:redis:nonce"))
+            .expect("HMAC takes any key size");
+
+        Ok(Self {
+            id: id.to_string(),
+            aead: LessSafeKey::new(aead),
+            nonce_key,
+        })
+    }
+
+    fn nonce(&self, plaintext: &[u8]) -> [u8; NONCE_LEN] {
+        let mut mac = self.nonce_key.clone();
+        mac.update(plaintext);
+        let mut nonce = [0; NONCE_LEN];
+        nonce.copy_from_slice(&mac.finalize().into_bytes()[..NONCE_LEN]);
+
+        nonce
+    }
+}
+
+/// Keys sealing and opening payloads, the first one seals
+pub struct Keyring {
+    keys: Vec<Key>,
+    /// Unsealed payloads are read until then
+    plaintext_until: Option<DateTime<Utc>>,
+}
+
+impl Keyring {
+    /// Parses `id:hex,id:hex`, every key is 32 bytes
+    pub fn parse(config: &str) -> Result<Self, Error> {
+        let keys = config
+            .split(',')
+            .map(str::trim)
+            .filter(|entry| !entry.is_empty())
+            .map(|entry| {
+                let (id, secret) = entry
+                    .split_once(':')
+                    .ok_or(Error::InvalidKeys("keys must be `id:hex`"))?;
+                Key::new(id, &hex(secret)?)
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+        if keys.is_empty() {
+            return Err(Error::InvalidKeys("no key configured"));
+        }
+
+        Ok(Self {
+            keys,
+            plaintext_until: None,
+        })
+    }
+
+    /// Reads unsealed payloads until `until`, see [`PLAINTEXT_UNTIL_VAR`]
+    #[must_use]
+    pub const fn with_plaintext_until(mut self, until: DateTime<Utc>) -> Self {
+        self.plaintext_until = Some(until);
+        self
+    }
+
+    fn reads_plaintext(&self, now: DateTime<Utc>) -> bool {
+        self.plaintext_until.is_some_and(|until| now < until)
+    }
+
+    pub fn seal(&self, plaintext: &[u8]) -> Vec<u8> {
+        let key = &self.keys[0];
+        let nonce = key.nonce(plaintext);
+        let mut ciphertext = plaintext.to_vec();
+        key.aead
+            .seal_in_place_append_tag(
+                Nonce::assume_unique_for_key(nonce),
+                Aad::from(key.id.as_bytes()),
+                &mut ciphertext,
+            )
+            .expect("payloads are smaller than the AES-GCM limit");
+
+        let mut sealed = Vec::with_capacity(2 + key.id.len() + NONCE_LEN + ciphertext.len());
+        sealed.push(SEALED);
+        sealed.push(key.id.len() as u8);
+        sealed.extend_from_slice(key.id.as_bytes());
+        sealed.extend_from_slice(&nonce);
+        sealed.extend_from_slice(&ciphertext);
+
+        sealed
+    }
+
+    pub fn open(&self, sealed: &[u8]) -> Result<Vec<u8>, Error> {
+        let [SEALED, id_len, rest @ ..] = sealed else {
+            return Err(Error::Unauthenticated);
+        };
+        let id_len = usize::from(*id_len);
+        if rest.len() < id_len + NONCE_LEN {
+            return Err(Error::Unauthenticated);
+        }
+        let (id, rest) = rest.split_at(id_len);
+        let (nonce, ciphertext) = rest.split_at(NONCE_LEN);
+        let key = self
+            .keys
+            .iter()
+            .find(|key| key.id.as_bytes() == id)
+            .ok_or_else(|| Error::UnknownKey(String::from_utf8_lossy(id).into_owned()))?;
+        let nonce = Nonce::try_assume_unique_for_key(nonce).map_err(|_| Error::Unauthenticated)?;
+
+        let mut plaintext = ciphertext.to_vec();
+        let len = key
+            .aead
+            .open_in_place(nonce, Aad::from(id), &mut plaintext)
+            .map_err(|_| Error::Unauthenticated)?
+            .len();
+        plaintext.truncate(len);
+
+        Ok(plaintext)
+    }
+}
+
+fn hex(secret: &str) -> Result<Vec<u8>, Error> {
+    if !secret.len().is_multiple_of(2) {
+        return Err(Error::InvalidKeys("keys must be hex encoded"));
+    }
+    (0..secret.len())
+        .step_by(2)
+        .map(|i| {
+            secret
+                .get(i..i + 2)
+                .and_then(|byte| u8::from_str_radix(byte, 16).ok())
+                .ok_or(Error::InvalidKeys("keys must be hex encoded"))
+        })
+        .collect()
+}
+
+/// Sets the keyring once, before any payload is encoded.
+/// Returns `false` when a keyring was already set.
+pub fn set(keyring: Keyring) -> bool {
+    KEYRING.set(keyring).is_ok()
+}
+
+/// Reads the keys from [`KEYS_VAR`] and their migration window from [`PLAINTEXT_UNTIL_VAR`],
+/// returns `false` when encryption is disabled
+pub fn set_from_env() -> Result<bool, Error> {
+    let Ok(config) = std::env::var(KEYS_VAR) else {
+        return Ok(false);
+    };
+    let keyring = Keyring::parse(&config)?;
+    let keyring = match std::env::var(PLAINTEXT_UNTIL_VAR) {
+        Ok(until) => keyring.with_plaintext_until(
+            DateTime::parse_from_rfc3339(&until)
+                .map_err(|_| Error::InvalidPlaintextUntil(until))?
+                .with_timezone(&Utc),
+        ),
+        Err(_) => keyring,
+    };
+
+    Ok(set(keyring))
+}
+
+pub fn encode<T: Versioned + ?Sized>(value: &T) -> Vec<u8> {
+    encode_with(KEYRING.get(), value)
+}
+
+pub fn decode<T: Versioned + DecodeOwned>(bytes: &[u8]) -> Result<T, Error> {
+    decode_with(KEYRING.get(), bytes, Utc::now())
+}
+
+fn encode_with<T: Versioned + ?Sized>(keyring: Option<&Keyring>, value: &T) -> Vec<u8> {
+    let payload = bitcode::encode(value);
+    let compressed = (payload.len() >= COMPRESSION_THRESHOLD)
+        .then(|| lz4_flex::compress_prepend_size(&payload))
+        .filter(|compressed| compressed.len() < payload.len());
+    let encoded = match compressed {
+        Some(compressed) => [vec![COMPRESSED, T::VERSION], compressed].concat(),
+        None => [vec![ENVELOPE, T::VERSION], payload].concat(),
+    };
+    match keyring {
+        Some(keyring) => keyring.seal(&encoded),
+        None => encoded,
+    }
+}
+
+/// Once keys are set only sealed payloads are decoded, unsealed ones until the end of their
+/// migration window at `now`
+fn decode_with<T: Versioned + DecodeOwned>(
+    keyring: Option<&Keyring>,
+    bytes: &[u8],
+    now: DateTime<Utc>,
+) -> Result<T, Error> {
+    let Some(keyring) = keyring else {
+        return open_envelope(bytes);
+    };
+    match keyring.open(bytes) {
+        Ok(plaintext) => open_envelope(&plaintext),
+        // written before encryption was enabled
+        Err(err) if keyring.reads_plaintext(now) => open_envelope(bytes).map_err(|_| err),
+        Err(_) if bytes.first() != Some(&SEALED) => Err(Error::Unsealed),
+        Err(err) => Err(err),
+    }
+}
+
+fn open_envelope<T: Versioned + DecodeOwned>(bytes: &[u8]) -> Result<T, Error> {
+    let decoded = match bytes {
+        [ENVELOPE, version, payload @ ..] => decode_version(*version, payload),
+        [COMPRESSED, version, payload @ ..] => lz4_flex::decompress_size_prepended(payload)
+            .map_err(Error::from)
+            .and_then(|payload| decode_version(*version, &payload)),
+        _ => return T::upgrade(0, bytes),
+    };
+    // written before envelopes existed, starting with an envelope byte by chance
+    decoded.or_else(|err| T::upgrade(0, bytes).map_err(|_| err))
+}
+
+fn decode_version<T: Versioned + DecodeOwned>(version: u8, payload: &[u8]) -> Result<T, Error> {
+    if version == T::VERSION {
+        Ok(bitcode::decode(payload)?)
+    } else {
+        T::upgrade(version, payload)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bitcode::Decode;
+    use skillratings::mhth::MhthRating;
+
+    use super::*;
+    use crate::{
+        clans::Clan,
+        datacenter::{DatacenterPing, Placement},
+        experiments::MatchParams,
+        lifecycle::Lifecycle,
+        match_settings::MatchSettings,
+        rpc::{Match, MatchKind, QueuedPlayer, RegionSource, matchmaking::Player},
+    };
+
+    const PINNED_PLAYER: &str = "b1010a008000000000000000394000000000000000f03f000000000000002040026575042804020401040001057061727479066400000000000000f03f00010468756e74000000000000000000e03f0496022c010604067806020003726571040101040101010103666f670106070104636c616e03484e54010270740401010765752d77657374041e01";
+    const PINNED_PLAYER_V0: &str = "0a008000000000000000394000000000000000f03f0000000000000020400265750428040204010400010570617274790664";
+    const PINNED_MATCH: &str = "b1010800000002010a008000000000000000394000000000000000f03f000000000000002040026575042804020401040001057061727479066400000000000000f03f00010468756e74000000000000000000e03f0496022c010604067806020003726571040101040101010103666f670106070104636c616e03484e54010270740401010765752d77657374041e010265750a0080000000000000000000e03f0496022c0106040678010006640000000000010765752d77657374041e040201010103666f670106070401";
+
+    const PINNED_MATCH_V0: &str = "0800000002010a008000000000000000394000000000000000f03f00000000000000204002657504280402040104000105706172747906640265750a0080";
+
+    const CURRENT: &str = "2:000102030405060708090a0b0c0d0e0f101112131415161718191a1b1c1d1e1f";
+    const PREVIOUS: &str = "1:1f1e1d1c1b1a191817161514131211100f0e0d0c0b0a09080706050403020100";
+
+    fn player() -> QueuedPlayer {
+        (Uuid::new_v4(), Player::default(), Default::default()).into()
+    }
+
+    #[test]
+    fn sealed_payloads_round_trip() {
+        let keyring = Keyring::parse(CURRENT).unwrap();
+        let player = player();
+
+        let sealed = encode_with(Some(&keyring), &player);
+
+        assert_eq!(sealed[0], SEALED);
+        assert_ne!(sealed, bitcode::encode(&player));
+        assert_eq!(sealed, encode_with(Some(&keyring), &player));
+        assert_eq!(
+            decode_with::<QueuedPlayer>(Some(&keyring), &sealed, Utc::now()).unwrap(),
+            player
+        );
+    }
+
+    #[test]
+    fn rotated_keys_open_older_payloads() {
+        let previous = Keyring::parse(PREVIOUS).unwrap();
+        let rotated = Keyring::parse(&format!("{CURRENT},{PREVIOUS}")).unwrap();
+        let player = player();
+
+        let sealed = encode_with(Some(&previous), &player);
+
+        assert_eq!(
+            decode_with::<QueuedPlayer>(Some(&rotated), &sealed, Utc::now()).unwrap(),
+            player
+        );
+        assert!(matches!(
+            decode_with::<QueuedPlayer>(Some(&Keyring::parse(CURRENT).unwrap()), &sealed, Utc::now()),
+            Err(Error::UnknownKey(id)) if id == "1"
+        ));
+    }
+
+    #[test]
+    fn tampered_payloads_fail() {
+        let keyring = Keyring::parse(CURRENT).unwrap();
+        let mut sealed = encode_with(Some(&keyring), &player());
+        let last = sealed.len() - 1;
+        sealed[last] ^= 1;
+
+        assert!(matches!(
+            decode_with::<QueuedPlayer>(Some(&keyring), &sealed, Utc::now()),
+            Err(Error::Unauthenticated)
+        ));
+    }
+
+    #[test]
+    fn plain_payloads_are_read_during_their_migration_window() {
+        let now = Utc::now();
+        let migrating = Keyring::parse(CURRENT)
+            .unwrap()
+            .with_plaintext_until(now + chrono::Duration::days(1));
+        let player = player();
+
+        let plain = encode_with(None, &player);
+
+        assert_eq!(plain[..2], [ENVELOPE, QueuedPlayer::VERSION]);
+        assert_eq!(
+            decode_with::<QueuedPlayer>(Some(&migrating), &plain, now).unwrap(),
+            player
+        );
+        assert!(matches!(
+            decode_with::<QueuedPlayer>(Some(&migrating), &plain, now + chrono::Duration::days(2)),
+            Err(Error::Unsealed)
+        ));
+    }
+
+    #[test]
+    fn plain_payloads_are_rejected_once_sealed() {
+        let keyring = Keyring::parse(CURRENT).unwrap();
+        let plain = encode_with(None, &player());
+        let legacy = hex_bytes(PINNED_PLAYER_V0);
+
+        assert!(matches!(
+            decode_with::<QueuedPlayer>(Some(&keyring), &plain, Utc::now()),
+            Err(Error::Unsealed)
+        ));
+        assert!(decode_with::<QueuedPlayer>(Some(&keyring), &legacy, Utc::now()).is_err());
+    }
+
+    #[test]
+    fn large_payloads_are_compressed() {
+        let keyring = Keyring::parse(CURRENT).unwrap();
+        let regions: Vec<String> = (0..64).map(|i| format!("region-{}", i % 4)).collect();
+
+        let compressed = encode_with(None, &regions);
+        let sealed = encode_with(Some(&keyring), &regions);
+
+        assert_eq!(compressed[..2], [COMPRESSED, 1]);
+        assert!(compressed.len() < bitcode::encode(&regions).len());
+        assert_eq!(
+            decode_with::<Vec<String>>(None, &compressed, Utc::now()).unwrap(),
+            regions
+        );
+        assert_eq!(
+            decode_with::<Vec<String>>(Some(&keyring), &sealed, Utc::now()).unwrap(),
+            regions
+        );
+        assert_eq!(encode_with(None, &pinned_player())[0], ENVELOPE);
+    }
+
+    #[test]
+    fn payloads_without_envelope_are_version_zero() {
+        let player =
+            decode_with::<QueuedPlayer>(None, &hex_bytes(PINNED_PLAYER_V0), Utc::now()).unwrap();
+        let a_match = decode_with::<Match>(None, &hex_bytes(PINNED_MATCH_V0), Utc::now()).unwrap();
+
+        assert_eq!(player, upgraded_player());
+        assert_eq!(a_match.players, vec![upgraded_player()]);
+        assert_eq!(a_match.difficulty, upgraded_player().difficulty);
+        assert_eq!(a_match.lifecycle, Lifecycle::default());
+    }
+
+    fn pinned_player() -> QueuedPlayer {
+        QueuedPlayer {
+            player_id: Uuid::from_u128(1),
+            skillrating: MhthRating {
+                rating: 25.,
+                loadout_modifier: 1.,
+                uncertainty: 8.,
+            },
+            region: "eu".to_string(),
+            ping: 40,
+            difficulty: 2,
+            join_mode: 1,
+            party_mode: 0,
+            party_ids: vec!["party".to_string()],
+            join_time: 100,
+            trust: 1.,
+            playlist: String::new(),
+            mission_types: vec!["hunt".to_string()],
+            maps: Vec::new(),
+            experiments: Vec::new(),
+            params: MatchParams {
+                skill_window: 0.5,
+                ping_threshold: 150,
+                max_ping: 300,
+                max_players: 4,
+                input_pool_secs: 120,
+            },
+            skill_band: 2,
+            smurf: false,
+            request_id: "req".to_string(),
+            input_device: 1,
+            region_source: RegionSource::Detected,
+            queue_type: 1,
+            match_settings: Some(MatchSettings {
+                friendly_fire: true,
+                mutators: vec!["fog".to_string()],
+                mission_seed: Some(7),
+            }),
+            clan: Some(Clan {
+                id: "clan".to_string(),
+                tag: "HNT".to_string(),
+            }),
+            languages: vec!["pt".to_string()],
+            voice_chat: 1,
+            datacenter_pings: vec![DatacenterPing {
+                datacenter: "eu-west".to_string(),
+                ping: 30,
+            }],
+            adjacent_difficulty: true,
+        }
+    }
+
+    /// [`pinned_player`] decoded from its payload of the first release
+    fn upgraded_player() -> QueuedPlayer {
+        QueuedPlayer {
+            mission_types: Vec::new(),
+            params: MatchParams::default(),
+            skill_band: 0,
+            request_id: String::new(),
+            input_device: 0,
+            region_source: RegionSource::Declared,
+            queue_type: 0,
+            match_settings: None,
+            clan: None,
+            languages: Vec::new(),
+            voice_chat: 0,
+            datacenter_pings: Vec::new(),
+            adjacent_difficulty: false,
+            ..pinned_player()
+        }
+    }
+
+    fn hex_string(bytes: &[u8]) -> String {
+        bytes.iter().map(|byte| format!("{byte:02x}")).collect()
+    }
+
+    fn hex_bytes(hex: &str) -> Vec<u8> {
+        (0..hex.len())
+            .step_by(2)
+            .map(|i| u8::from_str_radix(&hex[i..i + 2], 16).unwrap())
+            .collect()
+    }
+
+    /// Changing these bytes breaks the payloads of running servers, bump the version instead
+    #[test]
+    fn queued_player_wire_format_is_pinned() {
+        assert_eq!(
+            hex_string(&encode_with(None, &pinned_player())),
+            PINNED_PLAYER
+        );
+    }
+
+    #[test]
+    fn match_wire_format_is_pinned() {
+        let pinned = Match {
+            id: Uuid::from_u128(2),
+            players: vec![pinned_player()],
+            region: "eu".to_string(),
+            host_id: Uuid::from_u128(1),
+            playlist: String::new(),
+            experiments: Vec::new(),
+            params: pinned_player().params,
+            lifecycle: Lifecycle::forming(100),
+            nakama_match_id: None,
+            game_server: None,
+            squads: Vec::new(),
+            kind: MatchKind::Cooperative,
+            challenge: None,
+            datacenter: Some(Placement {
+                datacenter: "eu-west".to_string(),
+                worst_ping: 30,
+            }),
+            difficulty: 2,
+            settings: pinned_player().match_settings,
+            voice_chat: 1,
+        };
+
+        assert_eq!(hex_string(&encode_with(None, &pinned)), PINNED_MATCH);
+    }
+
+    #[derive(Debug, PartialEq, Encode, Decode)]
+    struct RecordV1 {
+        id: u64,
+    }
+
+    impl Versioned for RecordV1 {}
+
+    #[derive(Debug, PartialEq, Encode, Decode)]
+    struct Record {
+        id: u64,
+        region: String,
+    }
+
+    impl Versioned for Record {
+        const VERSION: u8 = 2;
+
+        fn upgrade(version: u8, payload: &[u8]) -> Result<Self, Error> {
+            match version {
+                0 | 1 => {
+                    let RecordV1 { id } = bitcode::decode(payload)?;
+                    Ok(Self {
+                        id,
+                        region: "eu".to_string(),
+                    })
+                }
+                _ => Err(Error::UnsupportedVersion(version)),
+            }
+        }
+    }
+
+    #[test]
+    fn older_versions_are_upgraded() {
+        let old = encode_with(None, &RecordV1 { id: 7 });
+        let upgraded = Record {
+            id: 7,
+            region: "eu".to_string(),
+        };
+
+        assert_eq!(
+            decode_with::<Record>(None, &old, Utc::now()).unwrap(),
+            upgraded
+        );
+        assert_eq!(
+            decode_with::<Record>(None, &encode_with(None, &upgraded), Utc::now()).unwrap(),
+            upgraded
+        );
+        assert!(matches!(
+            decode_with::<RecordV1>(None, &encode_with(None, &upgraded), Utc::now()),
+            Err(Error::UnsupportedVersion(2))
+        ));
+    }
+
+    #[test]
+    fn invalid_keys() {
+        assert!(Keyring::parse("").is_err());
+        assert!(Keyring::parse("1:00ff").is_err());
+        assert!(Keyring::parse(&CURRENT.replace(':', "")).is_err());
+        assert!(Keyring::parse(&CURRENT.replace("0a", "zz")).is_err());
+    }
+}
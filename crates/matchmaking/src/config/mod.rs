@@ -0,0 +1,324 @@
+use std::{path::Path, time::Duration};
+
+use bitcode::{Decode, Encode};
+use redis::{AsyncCommands, RedisError, aio::MultiplexedConnection};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    codec,
+    experiments::MatchParams,
+    namespace,
+    rpc::{Match, matchmaking::ReloadConfigResponse},
+    versus,
+};
+
+pub const CONFIG_KEY: &str = "match:config";
+/// Env var with the path of the JSON config file
+pub const CONFIG_PATH_VAR: &str = "MATCHMAKING_CONFIG_PATH";
+/// Env var with the worker interval of the default config, set by the profile files
+pub const WORKER_INTERVAL_VAR: &str = "WORKER_INTERVAL_SECS";
+
+#[derive(Debug, thiserror::Error)]
+pub enum Error {
+    #[error("invalid matchmaking config: {0}")]
+    Invalid(&'static str),
+    #[error("failed to read matchmaking config: {0}")]
+    Io(#[from] std::io::Error),
+    #[error(transparent)]
+    Json(#[from] serde_json::Error),
+    #[error(transparent)]
+    Redis(#[from] RedisError),
+    #[error(transparent)]
+    BitcodeDeser(#[from] codec::Error),
+}
+
+impl From<Error> for tonic::Status {
+    fn from(value: Error) -> Self {
+        match value {
+            Error::Invalid(_) | Error::Json(_) => Self::failed_precondition(value.to_string()),
+            Error::Io(_) | Error::Redis(_) | Error::BitcodeDeser(_) => {
+                Self::internal("Failed to load matchmaking config")
+            }
+        }
+    }
+}
+
+/// Tunable matchmaking parameters, picked up by the server and worker without restart
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, Encode, Decode, PartialEq)]
+#[serde(default)]
+pub struct MatchmakingConfig {
+    pub skill_window: f64,
+    pub ping_threshold: i32,
+    pub max_ping: i32,
+    pub max_players: usize,
+    pub worker_interval_secs: u64,
+    /// Conservative rating range of each queue shard, applied to new queue entries
+    pub skill_band_width: f64,
+    /// Queue entries loaded per Redis command by the worker
+    pub scan_batch_size: usize,
+    /// Queue entries the worker reads per queue and tick, the next tick resumes after them
+    pub scan_budget: usize,
+    /// Players per team of a PvP match, see [`crate::versus`]
+    pub versus_team_size: usize,
+    /// Lowest quality of a PvP match, between `0.0` and `1.0`
+    pub versus_min_quality: f64,
+    /// Hardest mission difficulty, difficulties start at `0`
+    pub max_difficulty: i32,
+    /// Success probability the recommended difficulty is closest to, see [`crate::environment`]
+    pub target_success: f64,
+    /// Loadout modifier added by every rarity tier of an equipped item, see [`crate::progression`]
+    pub loadout_tier_modifier: f64,
+    /// Highest loadout modifier of a player
+    pub max_loadout_modifier: f64,
+    /// Seconds players wait for a lobby of their input device, `0` mixes inputs right away
+    pub input_pool_secs: i64,
+    /// Seconds the open slots of a friends-only room wait for friends of the host, see
+    /// [`crate::friends`]
+    pub friends_fill_secs: i64,
+    /// Matches the worker forms per tick, the next tick forms the rest, see
+    /// [`crate::rpc::worker::budget`]
+    pub tick_match_budget: usize,
+    /// Redis commands the worker issues per tick reading queues and writing matches
+    pub tick_command_budget: usize,
+}
+
+impl codec::Versioned for MatchmakingConfig {}
+
+impl MatchmakingConfig {
+    pub const DEFAULT: Self = Self {
+        skill_window: MatchParams::DEFAULT.skill_window,
+        ping_threshold: MatchParams::DEFAULT.ping_threshold,
+        max_ping: MatchParams::DEFAULT.max_ping,
+        max_players: MatchParams::DEFAULT.max_players,
+        worker_interval_secs: 30,
+        skill_band_width: 5.,
+        scan_batch_size: 500,
+        scan_budget: 5000,
+        versus_team_size: 2,
+        versus_min_quality: 0.8,
+        max_difficulty: 5,
+        target_success: 0.6,
+        loadout_tier_modifier: 0.5,
+        max_loadout_modifier: 3.,
+        input_pool_secs: MatchParams::DEFAULT.input_pool_secs,
+        friends_fill_secs: 120,
+        tick_match_budget: 500,
+        tick_command_budget: 50_000,
+    };
+
+    /// Defaults with the worker interval of [`WORKER_INTERVAL_VAR`]
+    pub fn from_env() -> Self {
+        let worker_interval_secs = std::env::var(WORKER_INTERVAL_VAR)
+            .ok()
+            .and_then(|secs| secs.parse().ok())
+            .filter(|secs| *secs > 0)
+            .unwrap_or(Self::DEFAULT.worker_interval_secs);
+
+        Self {
+            worker_interval_secs,
+            ..Self::DEFAULT
+        }
+    }
+
+    /// Base parameters of every player, experiment buckets override them
+    pub const fn params(&self) -> MatchParams {
+        MatchParams {
+            skill_window: self.skill_window,
+            ping_threshold: self.ping_threshold,
+            max_ping: self.max_ping,
+            max_players: self.max_players,
+            input_pool_secs: self.input_pool_secs,
+        }
+    }
+
+    pub const fn worker_interval(&self) -> Duration {
+        Duration::from_secs(self.worker_interval_secs)
+    }
+
+    pub fn validate(self) -> Result<Self, Error> {
+        if self.worker_interval_secs == 0 {
+            return Err(Error::Invalid("worker interval must be at least 1 second"));
+        }
+        if !(1..=Match::MAX_PLAYERS).contains(&self.max_players) {
+            return Err(Error::Invalid(
+                "max players must be between 1 and MAX_PLAYERS",
+            ));
+        }
+        if self.ping_threshold > self.max_ping {
+            return Err(Error::Invalid("ping threshold must not exceed max ping"));
+        }
+        if self.skill_band_width <= 0. {
+            return Err(Error::Invalid("skill band width must be positive"));
+        }
+        if self.scan_batch_size == 0 || self.scan_budget < self.scan_batch_size {
+            return Err(Error::Invalid(
+                "scan budget must be at least one scan batch",
+            ));
+        }
+        if !(1..=versus::MAX_TEAM_SIZE).contains(&self.versus_team_size) {
+            return Err(Error::Invalid(
+                "versus team size must be between 1 and MAX_TEAM_SIZE",
+            ));
+        }
+        if !(0. ..=1.).contains(&self.versus_min_quality) {
+            return Err(Error::Invalid(
+                "versus min quality must be between 0.0 and 1.0",
+            ));
+        }
+        if self.max_difficulty < 0 {
+            return Err(Error::Invalid("max difficulty must not be negative"));
+        }
+        if !(0. ..=1.).contains(&self.target_success) {
+            return Err(Error::Invalid("target success must be between 0.0 and 1.0"));
+        }
+        if self.loadout_tier_modifier < 0. || self.max_loadout_modifier < 0. {
+            return Err(Error::Invalid("loadout modifiers must not be negative"));
+        }
+        if self.skill_window < 0. {
+            return Err(Error::Invalid("skill window must not be negative"));
+        }
+        if self.input_pool_secs < 0 {
+            return Err(Error::Invalid("input pool wait must not be negative"));
+        }
+        if self.friends_fill_secs < 0 {
+            return Err(Error::Invalid("friends fill wait must not be negative"));
+        }
+        if self.tick_match_budget == 0 || self.tick_command_budget == 0 {
+            return Err(Error::Invalid("tick budgets must be at least 1"));
+        }
+
+        Ok(self)
+    }
+}
+
+impl Default for MatchmakingConfig {
+    fn default() -> Self {
+        Self::DEFAULT
+    }
+}
+
+impl From<MatchmakingConfig> for ReloadConfigResponse {
+    fn from(value: MatchmakingConfig) -> Self {
+        Self {
+            skill_window: value.skill_window,
+            ping_threshold: value.ping_threshold,
+            max_ping: value.max_ping,
+            max_players: value.max_players as u32,
+            worker_interval_secs: value.worker_interval_secs,
+            skill_band_width: value.skill_band_width,
+            scan_batch_size: value.scan_batch_size as u32,
+            scan_budget: value.scan_budget as u32,
+            versus_team_size: value.versus_team_size as u32,
+            versus_min_quality: value.versus_min_quality,
+            max_difficulty: value.max_difficulty,
+            target_success: value.target_success,
+            loadout_tier_modifier: value.loadout_tier_modifier,
+            max_loadout_modifier: value.max_loadout_modifier,
+            input_pool_secs: value.input_pool_secs,
+            friends_fill_secs: value.friends_fill_secs,
+            tick_match_budget: value.tick_match_budget as u64,
+            tick_command_budget: value.tick_command_budget as u64,
+        }
+    }
+}
+
+/// Reads the config from a JSON file, missing values keep the defaults
+pub fn load_file(path: impl AsRef<Path>) -> Result<MatchmakingConfig, Error> {
+    let config = std::fs::read_to_string(path)?;
+
+    serde_json::from_str::<MatchmakingConfig>(&config)?.validate()
+}
+
+pub async fn set_config(
+    conn: &mut MultiplexedConnection,
+    config: &MatchmakingConfig,
+) -> Result<(), Error> {
+    conn.set(namespace::key(CONFIG_KEY), codec::encode(config))
+        .await
+        .map_err(Error::from)
+}
+
+/// Current config, [`MatchmakingConfig::from_env`] when none was stored
+pub async fn get_config(conn: &mut MultiplexedConnection) -> Result<MatchmakingConfig, Error> {
+    let encoded: Option<Vec<u8>> = conn.get(namespace::key(CONFIG_KEY)).await?;
+
+    Ok(encoded
+        .map(|encoded| codec::decode(&encoded))
+        .transpose()?
+        .unwrap_or_else(MatchmakingConfig::from_env))
+}
+
+/// Stores the config file content when [`CONFIG_PATH_VAR`] is set,
+/// returns the config in use.
+pub async fn reload(conn: &mut MultiplexedConnection) -> Result<MatchmakingConfig, Error> {
+    let Ok(path) = std::env::var(CONFIG_PATH_VAR) else {
+        return get_config(conn).await;
+    };
+    let config = load_file(path)?;
+    set_config(conn, &config).await?;
+
+    Ok(config)
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+    use crate::test_support::{create_redis, redis_client};
+
+    #[test]
+    fn config_from_json() {
+        let config: MatchmakingConfig =
+            serde_json::from_str(r#"{"max_ping": 250, "worker_interval_secs": 10}"#).unwrap();
+
+        assert_eq!(config.max_ping, 250);
+        assert_eq!(config.max_players, Match::MAX_PLAYERS);
+        assert_eq!(config.worker_interval(), Duration::from_secs(10));
+        assert!(config.validate().is_ok());
+    }
+
+    #[test]
+    fn invalid_config() {
+        let mut config = MatchmakingConfig::DEFAULT;
+        config.worker_interval_secs = 0;
+        assert!(matches!(config.validate(), Err(Error::Invalid(_))));
+
+        let mut config = MatchmakingConfig::DEFAULT;
+        config.max_players = Match::MAX_PLAYERS + 1;
+        assert!(matches!(config.validate(), Err(Error::Invalid(_))));
+
+        let mut config = MatchmakingConfig::DEFAULT;
+        config.ping_threshold = config.max_ping + 1;
+        assert!(matches!(config.validate(), Err(Error::Invalid(_))));
+
+        let mut config = MatchmakingConfig::DEFAULT;
+        config.skill_band_width = 0.;
+        assert!(matches!(config.validate(), Err(Error::Invalid(_))));
+
+        let mut config = MatchmakingConfig::DEFAULT;
+        config.scan_budget = config.scan_batch_size - 1;
+        assert!(matches!(config.validate(), Err(Error::Invalid(_))));
+
+        let mut config = MatchmakingConfig::DEFAULT;
+        config.tick_match_budget = 0;
+        assert!(matches!(config.validate(), Err(Error::Invalid(_))));
+    }
+
+    #[tokio::test]
+    async fn stored_config() {
+        let container = create_redis(6379).await;
+        let host = container.get_host().await.unwrap();
+        let port = container.get_host_port_ipv4(6379).await.unwrap();
+        let client = redis_client(host.to_string(), port);
+        let mut conn = client.get_multiplexed_async_connection().await.unwrap();
+        let mut config = MatchmakingConfig::DEFAULT;
+        config.skill_window = 0.4;
+
+        let default = get_config(&mut conn).await.unwrap();
+        set_config(&mut conn, &config).await.unwrap();
+        let stored = get_config(&mut conn).await.unwrap();
+
+        assert_eq!(default, MatchmakingConfig::DEFAULT);
+        assert_eq!(stored, config);
+    }
+}
@@ -0,0 +1,204 @@
+//! Datacenter of a match. Players report their ping to each datacenter they can reach, and a
+//! match that fills is hosted in the datacenter every player reached with the lowest worst-case
+//! ping, instead of the region of its host. Ties are broken by the average ping. Only the
+//! datacenters configured for the region of the match are candidates, see
+//! [`crate::regions::tuning::RegionTuning::datacenters`]. Matches whose players share no
+//! datacenter of their region keep being placed by their region.
+
+use std::collections::HashMap;
+
+use bitcode::{Decode, Encode};
+use serde::{Deserialize, Serialize};
+
+use crate::rpc::{Match, QueuedPlayer};
+
+/// Datacenters kept per player, the closest ones
+pub const MAX_DATACENTERS: usize = 16;
+/// Longest ping accepted from a report, longer pings are ignored
+pub const MAX_REPORTED_PING: i32 = 1_000;
+
+/// Ping of a player to a datacenter, in milliseconds
+#[derive(Debug, Clone, Serialize, Deserialize, Encode, Decode, PartialEq, Eq)]
+pub struct DatacenterPing {
+    pub datacenter: String,
+    pub ping: i32,
+}
+
+/// Datacenter chosen for a match
+#[derive(Debug, Clone, Serialize, Deserialize, Encode, Decode, PartialEq, Eq)]
+pub struct Placement {
+    pub datacenter: String,
+    /// Highest ping of the players to the datacenter
+    pub worst_ping: i32,
+}
+
+/// Reported pings by datacenter name, keeping the [`MAX_DATACENTERS`] closest valid ones sorted
+/// by name. Names are trimmed and lowercased.
+pub fn reported(pings: HashMap<String, i32>) -> Vec<DatacenterPing> {
+    let mut closest: HashMap<String, i32> = HashMap::new();
+    for (datacenter, ping) in pings {
+        let datacenter = datacenter.trim().to_lowercase();
+        if datacenter.is_empty() || !(0..=MAX_REPORTED_PING).contains(&ping) {
+            continue;
+        }
+        closest
+            .entry(datacenter)
+            .and_modify(|closest| *closest = (*closest).min(ping))
+            .or_insert(ping);
+    }
+    let mut pings: Vec<DatacenterPing> = closest
+        .into_iter()
+        .map(|(datacenter, ping)| DatacenterPing { datacenter, ping })
+        .collect();
+    pings.sort_by(|a, b| {
+        a.ping
+            .cmp(&b.ping)
+            .then_with(|| a.datacenter.cmp(&b.datacenter))
+    });
+    pings.truncate(MAX_DATACENTERS);
+    pings.sort_by(|a, b| a.datacenter.cmp(&b.datacenter));
+
+    pings
+}
+
+/// Datacenter of `datacenters` reached by every player minimizing the worst ping, `None` when a
+/// player reported none of them or the players share none
+pub fn select(players: &[QueuedPlayer], datacenters: &[String]) -> Option<Placement> {
+    let (first, others) = players.split_first()?;
+
+    first
+        .datacenter_pings
+        .iter()
+        .filter(|candidate| datacenters.contains(&candidate.datacenter))
+        .filter_map(|candidate| {
+            let mut worst_ping = candidate.ping;
+            let mut total = i64::from(candidate.ping);
+            for player in others {
+                let ping = player
+                    .datacenter_pings
+                    .iter()
+                    .find(|reported| reported.datacenter == candidate.datacenter)?
+                    .ping;
+                worst_ping = worst_ping.max(ping);
+                total += i64::from(ping);
+            }
+            Some((worst_ping, total, &candidate.datacenter))
+        })
+        .min()
+        .map(|(worst_ping, _, datacenter)| Placement {
+            datacenter: datacenter.clone(),
+            worst_ping,
+        })
+}
+
+impl Match {
+    /// Places the match in one of the `datacenters` of its region, see [`select`]
+    pub fn place(&mut self, datacenters: &[String]) {
+        self.datacenter = select(&self.players, datacenters);
+    }
+
+    /// Datacenter of the match, or its region when it was not placed
+    pub fn location(&self) -> &str {
+        self.datacenter
+            .as_ref()
+            .map_or(&self.region, |placement| &placement.datacenter)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use skillratings::mhth::MhthRating;
+    use uuid::Uuid;
+
+    use super::*;
+    use crate::rpc::matchmaking::Player;
+
+    fn player(pings: &[(&str, i32)]) -> QueuedPlayer {
+        (
+            Uuid::new_v4(),
+            Player {
+                region: "eu".to_string(),
+                datacenter_pings: pings
+                    .iter()
+                    .map(|(datacenter, ping)| (datacenter.to_string(), *ping))
+                    .collect(),
+                ..Default::default()
+            },
+            MhthRating::default(),
+        )
+            .into()
+    }
+
+    #[test]
+    fn reports_are_normalized() {
+        let pings = reported(HashMap::from([
+            (" EU-West ".to_string(), 40),
+            ("eu-west".to_string(), 30),
+            ("eu-north".to_string(), -1),
+            ("".to_string(), 10),
+            ("us-east".to_string(), 90),
+        ]));
+
+        assert_eq!(
+            pings,
+            vec![
+                DatacenterPing {
+                    datacenter: "eu-west".to_string(),
+                    ping: 30
+                },
+                DatacenterPing {
+                    datacenter: "us-east".to_string(),
+                    ping: 90
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn worst_ping_is_minimized() {
+        let players = vec![
+            player(&[("eu-west", 20), ("eu-central", 45), ("us-east", 110)]),
+            player(&[("eu-west", 140), ("eu-central", 50), ("us-east", 60)]),
+            player(&[("eu-central", 35), ("us-east", 100)]),
+        ];
+
+        let datacenters = ["eu-west", "eu-central", "us-east"].map(str::to_string);
+
+        assert_eq!(
+            select(&players, &datacenters),
+            Some(Placement {
+                datacenter: "eu-central".to_string(),
+                worst_ping: 50
+            })
+        );
+    }
+
+    #[test]
+    fn only_datacenters_of_the_region_are_selected() {
+        let players = vec![
+            player(&[("eu-west", 20), ("attacker-dc", 1)]),
+            player(&[("eu-west", 30), ("attacker-dc", 1)]),
+        ];
+
+        assert_eq!(
+            select(&players, &["eu-west".to_string()]),
+            Some(Placement {
+                datacenter: "eu-west".to_string(),
+                worst_ping: 30
+            })
+        );
+        assert_eq!(select(&players, &[]), None);
+    }
+
+    #[test]
+    fn unplaced_matches_keep_their_region() {
+        let host = player(&[("eu-west", 20)]);
+        let guest = player(&[]);
+        let mut a_match = Match::host(&host, &[guest]).unwrap();
+
+        a_match.place(&["eu-west".to_string()]);
+
+        assert_eq!(a_match.datacenter, None);
+        assert_eq!(a_match.location(), "eu");
+    }
+}
@@ -0,0 +1,219 @@
+//! End-to-end harness: Redis runs in a container, Nakama is a stub serving the endpoints the
+//! matchmaker calls, the gRPC services listen on a random port behind the default middleware and
+//! the worker ticks in the background. Scenarios drive real players through
+//! [`crate::client::MatchmakingClient`], so the seams between the server, the worker and the
+//! notifications are covered together.
+
+mod scenarios;
+
+use std::{marker::PhantomData, sync::Arc, time::Duration};
+
+use httpmock::{
+    Method::{GET, POST},
+    Mock, MockServer,
+};
+use serde_json::json;
+use testcontainers::{ContainerAsync, GenericImage};
+use tokio::{net::TcpListener, task::JoinHandle};
+use tonic::transport::{Channel, Server, server::TcpIncoming};
+use uuid::Uuid;
+
+use crate::{
+    client::MatchmakingClient,
+    clock::SystemClock,
+    nakama::{
+        Authenticated, NakamaClient,
+        endpoints::{ACCOUNT_PATH, CREATE_MATCH_PATH, HEALTHCHECK_PATH},
+    },
+    records::NoRecords,
+    regions::tuning::{self, RegionTuning, RegionTunings},
+    rpc::{
+        server::{
+            MatchmakingServer, MatchmakingServiceServer, MatchmakingServiceV2Server, auth,
+            middleware::Middlewares,
+        },
+        worker::MatchmakingWorker,
+    },
+    test_support::create_redis,
+    validation::NoValidation,
+};
+
+/// Region of the scenarios, its matches close with [`MATCH_SIZE`] players
+pub const REGION: &str = "CAN";
+pub const MATCH_SIZE: usize = 2;
+/// Id of every match created by the Nakama stub
+pub const NAKAMA_MATCH_ID: &str = "nakama.match";
+/// Interval of the background worker, far below the configured one so scenarios run quickly
+pub const TICK: Duration = Duration::from_millis(200);
+/// Longest a scenario waits on the events of a player
+pub const SCENARIO_TIMEOUT: Duration = Duration::from_secs(30);
+
+const SESSION_SECS: i64 = 600;
+
+/// Redis, the Nakama stub and the gRPC server of a scenario, dropped with it
+pub struct Harness {
+    _redis: ContainerAsync<GenericImage>,
+    pub nakama: MockServer,
+    pub channel: Channel,
+    pub worker: MatchmakingWorker,
+    server: JoinHandle<()>,
+}
+
+impl Harness {
+    pub async fn start() -> Self {
+        let redis = create_redis(6379).await;
+        let host = redis.get_host().await.unwrap();
+        let port = redis.get_host_port_ipv4(6379).await.unwrap();
+        let conn = redis::Client::open(format!("redis://{host}:{port}"))
+            .unwrap()
+            .get_multiplexed_async_connection()
+            .await
+            .unwrap();
+        crate::regions::set_regions(conn.clone(), &[REGION.to_string()])
+            .await
+            .unwrap();
+        let tunings = RegionTunings(
+            [(
+                REGION.to_string(),
+                RegionTuning {
+                    max_players: Some(MATCH_SIZE),
+                    ..Default::default()
+                },
+            )]
+            .into(),
+        );
+        tuning::set_tunings(&mut conn.clone(), &tunings)
+            .await
+            .unwrap();
+
+        let nakama = MockServer::start_async().await;
+        stub_nakama(&nakama).await;
+        let nakama_client = Arc::new(auth_client(nakama.address().port()));
+        let http_client = Arc::new(reqwest::Client::new());
+        let matchmaking_server = MatchmakingServer {
+            redis: conn.clone(),
+            http_client: http_client.clone(),
+            nakama_client: nakama_client.clone(),
+            clock: Arc::new(SystemClock::default()),
+            records: Arc::new(NoRecords),
+            leaderboard: None,
+            health: Default::default(),
+            observability: Default::default(),
+            validator: Arc::new(NoValidation),
+        };
+        let worker = MatchmakingWorker::new(
+            conn.clone(),
+            http_client,
+            nakama_client,
+            Arc::new(SystemClock::default()),
+        );
+
+        let listener = TcpListener::bind(("127.0.0.1", 0)).await.unwrap();
+        let address = listener.local_addr().unwrap();
+        let layers = Middlewares::default().layers(conn.clone());
+        let server = tokio::spawn(async move {
+            Server::builder()
+                .layer(layers)
+                .add_service(MatchmakingServiceV2Server::new(matchmaking_server.clone()))
+                .add_service(MatchmakingServiceServer::new(matchmaking_server))
+                .serve_with_incoming(TcpIncoming::from(listener))
+                .await
+                .unwrap();
+        });
+        let channel = Channel::from_shared(format!("http://{address}"))
+            .unwrap()
+            .connect()
+            .await
+            .unwrap();
+
+        Self {
+            _redis: redis,
+            nakama,
+            channel,
+            worker,
+            server,
+        }
+    }
+
+    /// New player and its client, signed in with a session the way Nakama signs them
+    pub fn player(&self) -> (String, MatchmakingClient) {
+        let player_id = Uuid::new_v4().to_string();
+        let now = chrono::Utc::now().timestamp();
+        let token = auth::sign_session(&player_id, now, now + SESSION_SECS);
+        let client = MatchmakingClient::new(self.channel.clone(), &token).unwrap();
+
+        (player_id, client)
+    }
+
+    /// Runs the worker every [`TICK`] until the returned task is aborted
+    pub fn spawn_worker(&self) -> JoinHandle<()> {
+        let mut worker = self.worker.clone();
+        tokio::spawn(async move {
+            loop {
+                if let Err(err) = worker.run().await {
+                    tracing::error!("matchmaking worker: {err}");
+                }
+                tokio::time::sleep(TICK).await;
+            }
+        })
+    }
+
+    /// Mock of the Nakama `create_match` RPC, to assert on the matches created
+    pub async fn create_match_mock(&self) -> Mock<'_> {
+        self.nakama
+            .mock_async(|when, then| {
+                when.method(POST).path(CREATE_MATCH_PATH.1);
+                then.status(200)
+                    .header("content-type", "application/json")
+                    .json_body(json!({
+                        "body": json!({ "match_id": NAKAMA_MATCH_ID }).to_string(),
+                        "error_message": ""
+                    }));
+            })
+            .await
+    }
+}
+
+impl Drop for Harness {
+    fn drop(&mut self) {
+        self.server.abort();
+    }
+}
+
+/// Healthcheck and accounts, accounts are a year old so no player is a new account
+async fn stub_nakama(nakama: &MockServer) {
+    nakama
+        .mock_async(|when, then| {
+            when.method(POST).path(HEALTHCHECK_PATH.1);
+            then.status(200)
+                .header("content-type", "application/json")
+                .json_body(json!({"body": "{\"success\": true}", "error_message": ""}));
+        })
+        .await;
+    let created_at = (chrono::Utc::now() - chrono::Duration::days(365)).to_rfc3339();
+    nakama
+        .mock_async(|when, then| {
+            when.method(GET).path_prefix(format!("{}/", ACCOUNT_PATH.1));
+            then.status(200)
+                .header("content-type", "application/json")
+                .json_body(json!({"account": {"user": {
+                    "id": Uuid::nil().to_string(),
+                    "create_time": created_at
+                }}}));
+        })
+        .await;
+}
+
+fn auth_client(port: u16) -> NakamaClient<Authenticated> {
+    NakamaClient {
+        username: "username".to_string(),
+        password: "password".to_string(),
+        token: Some("super_random_token".to_string()),
+        url: format!("http://127.0.0.1:{port}"),
+        server_key_name: "defaultkey".to_string(),
+        server_key_value: "server_key".to_string(),
+        encryption_key: "encryption_key".to_string(),
+        legacy_until: None,
+        _state: PhantomData::<Authenticated>,
+    }
+}
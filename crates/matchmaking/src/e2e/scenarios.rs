@@ -0,0 +1,84 @@
+use tokio::time::timeout;
+
+use super::*;
+use crate::{
+    client::PlayerBuilder,
+    rpc::{
+        matchmaking::{
+            HealthCheckRequest, JoinMode, RejoinMatchRequest,
+            matchmaking_service_client::MatchmakingServiceClient,
+        },
+        server::healthcheck::ServingStatus,
+    },
+};
+
+#[tokio::test]
+async fn host_and_joiner_are_matched_and_started() {
+    let harness = Harness::start().await;
+    let create_match = harness.create_match_mock().await;
+    let (host_id, host) = harness.player();
+    let (joiner_id, joiner) = harness.player();
+    let mut host_events = host.watch_queue(&host_id).await.unwrap();
+    let mut joiner_events = joiner.watch_queue(&joiner_id).await.unwrap();
+
+    host.join_queue(
+        PlayerBuilder::new(&host_id)
+            .region(REGION)
+            .ping(20)
+            .join_mode(JoinMode::CreateRoom),
+    )
+    .await
+    .unwrap();
+    joiner
+        .join_queue(
+            PlayerBuilder::new(&joiner_id)
+                .region(REGION)
+                .ping(30)
+                .join_mode(JoinMode::JoinRoom),
+        )
+        .await
+        .unwrap();
+    let worker = harness.spawn_worker();
+    let (host_found, joiner_found) = timeout(SCENARIO_TIMEOUT, async {
+        tokio::join!(host_events.wait_for_match(), joiner_events.wait_for_match())
+    })
+    .await
+    .unwrap();
+    let rejoined = joiner.rejoin_match(&joiner_id).await.unwrap();
+    worker.abort();
+
+    let host_found = host_found.unwrap();
+    let joiner_found = joiner_found.unwrap();
+    create_match.assert_async().await;
+    assert_eq!(host_found.match_id, joiner_found.match_id);
+    assert_eq!(host_found.host_id, host_id);
+    assert_eq!(host_found.region, REGION);
+    assert_eq!(joiner_found.nakama_match_id, NAKAMA_MATCH_ID);
+    assert_eq!(rejoined.match_id, host_found.match_id);
+    assert_eq!(rejoined.nakama_match_id, NAKAMA_MATCH_ID);
+    assert_eq!(rejoined.player_ids.len(), MATCH_SIZE);
+    assert!(rejoined.player_ids.contains(&host_id) && rejoined.player_ids.contains(&joiner_id));
+}
+
+#[tokio::test]
+async fn health_checks_pass_the_auth_policies() {
+    let harness = Harness::start().await;
+    let mut client = MatchmakingServiceClient::new(harness.channel.clone());
+
+    let health = client
+        .check(HealthCheckRequest {
+            service: "matchmaking".to_string(),
+        })
+        .await
+        .unwrap()
+        .into_inner();
+    let unauthenticated = client
+        .rejoin_match(RejoinMatchRequest {
+            player_id: Uuid::new_v4().to_string(),
+        })
+        .await
+        .unwrap_err();
+
+    assert_eq!(health.status, i32::from(ServingStatus::Serving));
+    assert_eq!(unauthenticated.code(), tonic::Code::Unauthenticated);
+}
@@ -0,0 +1,655 @@
+//! Catalog of environment entity ratings, e.g. the bosses, drones and bots of a mission at a
+//! difficulty. Managed by admins, match results are rated against the real environment strength
+//! instead of a rating derived from the difficulty alone. Every result also moves the entity
+//! ratings, designers compare them to the rating they set to spot over- or under-tuned content.
+
+use std::sync::LazyLock;
+
+use bitcode::{Decode, Encode};
+use redis::{AsyncCommands, RedisError, Script, aio::MultiplexedConnection};
+use serde::{Deserialize, Serialize};
+use skillratings::{
+    Outcomes,
+    mhth::{MhthConfig, MhthRating, expected_team_vs_environment, mhth_team_vs_environment},
+};
+use tracing::error;
+
+use crate::{
+    codec, namespace,
+    rpc::{
+        Match,
+        matchmaking::{self, EnvironmentResponse, EnvironmentSummary},
+    },
+    smurf,
+};
+
+pub const ENVIRONMENT_KEY: &str = "environment";
+/// Weight of a match result in the entity ratings, the rest is kept from the current rating
+pub const RESULT_WEIGHT: f64 = 0.1;
+/// Times a result is applied again when the environment changed while it was applied
+pub const RECORD_ATTEMPTS: usize = 5;
+
+/// Replaces the environment (`KEYS[1]`) with `ARGV[2]` only while it still holds `ARGV[1]`
+static COMPARE_AND_SET: LazyLock<Script> = LazyLock::new(|| {
+    Script::new(
+        r"
+        if redis.call('GET', KEYS[1]) == ARGV[1] then
+            redis.call('SET', KEYS[1], ARGV[2])
+            return 1
+        end
+        return 0
+        ",
+    )
+});
+
+#[derive(Debug, thiserror::Error)]
+pub enum Error {
+    #[error("invalid environment: {0}")]
+    Invalid(&'static str),
+    #[error("mission `{0}` is not one of the match missions")]
+    MissionNotAllowed(String),
+    #[error("environment of `{0}` changed while recording a result")]
+    Contended(String),
+    #[error(transparent)]
+    Redis(#[from] RedisError),
+    #[error(transparent)]
+    BitcodeDeser(#[from] codec::Error),
+}
+
+impl From<Error> for tonic::Status {
+    fn from(value: Error) -> Self {
+        match value {
+            Error::Invalid(_) | Error::MissionNotAllowed(_) => {
+                Self::invalid_argument(value.to_string())
+            }
+            Error::Contended(_) => Self::aborted(value.to_string()),
+            Error::Redis(_) | Error::BitcodeDeser(_) => {
+                Self::internal("Failed to load environment ratings")
+            }
+        }
+    }
+}
+
+/// Rated entity of a mission
+#[derive(Debug, Clone, Encode, Decode, PartialEq)]
+pub struct Entity {
+    pub id: String,
+    /// e.g. `boss`, `drone` or `bot`
+    pub kind: String,
+    /// Live rating, updated by the match results
+    pub rating: MhthRating,
+    /// Rating set by the designers
+    pub baseline: MhthRating,
+}
+
+impl From<matchmaking::EnvironmentEntity> for Entity {
+    fn from(value: matchmaking::EnvironmentEntity) -> Self {
+        let rating = MhthRating {
+            rating: value.rating,
+            loadout_modifier: value.loadout_modifier,
+            uncertainty: value.uncertainty,
+        };
+
+        Self {
+            id: value.id,
+            kind: value.kind,
+            rating,
+            baseline: rating,
+        }
+    }
+}
+
+impl From<&Entity> for matchmaking::EnvironmentEntity {
+    fn from(value: &Entity) -> Self {
+        Self {
+            id: value.id.clone(),
+            kind: value.kind.clone(),
+            rating: value.rating.rating,
+            loadout_modifier: value.rating.loadout_modifier,
+            uncertainty: value.rating.uncertainty,
+            baseline_rating: value.baseline.rating,
+        }
+    }
+}
+
+/// Entities of a mission at a difficulty
+#[derive(Debug, Clone, Default, Encode, Decode, PartialEq)]
+pub struct Environment {
+    pub mission: String,
+    pub difficulty: i32,
+    pub entities: Vec<Entity>,
+    /// Match results the entity ratings learned from
+    pub matches: u64,
+}
+
+impl codec::Versioned for Environment {}
+
+impl Environment {
+    pub fn validate(self) -> Result<Self, Error> {
+        if self.mission.is_empty() {
+            return Err(Error::Invalid("mission is required"));
+        }
+        if self.entities.iter().any(|entity| entity.id.is_empty()) {
+            return Err(Error::Invalid("entity id is required"));
+        }
+        if self.entities.iter().enumerate().any(|(index, entity)| {
+            self.entities[..index]
+                .iter()
+                .any(|other| other.id == entity.id)
+        }) {
+            return Err(Error::Invalid("entity ids must be unique"));
+        }
+        if !self.entities.iter().all(|entity| {
+            entity.rating.rating.is_finite()
+                && entity.rating.loadout_modifier.is_finite()
+                && entity.rating.uncertainty.is_finite()
+                && entity.rating.uncertainty > 0.
+        }) {
+            return Err(Error::Invalid(
+                "ratings must be finite with a positive uncertainty",
+            ));
+        }
+
+        Ok(self)
+    }
+
+    /// Strength of the environment as a single opponent, the average of its entities.
+    /// Falls back to [`smurf::environment_rating`] when no entity is rated.
+    pub fn rating(&self) -> MhthRating {
+        if self.entities.is_empty() {
+            return smurf::environment_rating(self.difficulty);
+        }
+        let count = self.entities.len() as f64;
+        let mean = |value: fn(&MhthRating) -> f64| {
+            self.entities
+                .iter()
+                .map(|entity| value(&entity.rating))
+                .sum::<f64>()
+                / count
+        };
+
+        MhthRating {
+            rating: mean(|rating| rating.rating),
+            loadout_modifier: mean(|rating| rating.loadout_modifier),
+            uncertainty: mean(|rating| rating.uncertainty),
+        }
+    }
+
+    /// Ratings opposing `team`, the environment rating faces every player when no entity is rated
+    pub fn opponents(&self, team: &[MhthRating]) -> Vec<MhthRating> {
+        if self.entities.is_empty() {
+            vec![self.rating(); team.len()]
+        } else {
+            self.entities.iter().map(|entity| entity.rating).collect()
+        }
+    }
+
+    /// Probability of `team` completing the mission
+    pub fn expected_success(&self, team: &[MhthRating]) -> f64 {
+        expected_team_vs_environment(team, &self.opponents(team), &MhthConfig::new()).0
+    }
+
+    /// Moves the entity ratings [`RESULT_WEIGHT`] of the way towards their ratings after
+    /// playing against `team`
+    pub fn record_result(&mut self, team: &[MhthRating], won: bool) {
+        let outcome = if won {
+            Outcomes::SUCCESSFUL
+        } else {
+            Outcomes::FAILURE
+        };
+        let ratings: Vec<MhthRating> = self.entities.iter().map(|entity| entity.rating).collect();
+        let (_, updated) = mhth_team_vs_environment(team, &ratings, &outcome, &MhthConfig::new());
+
+        for (entity, updated) in self.entities.iter_mut().zip(updated) {
+            entity.rating = smooth(&entity.rating, &updated);
+        }
+        self.matches += 1;
+    }
+}
+
+/// Difficulty of `environments` whose expected success of `team` is closest to `target`,
+/// the easiest one on ties
+pub fn recommend(
+    environments: &[Environment],
+    team: &[MhthRating],
+    target: f64,
+) -> Option<(i32, f64)> {
+    environments
+        .iter()
+        .map(|environment| (environment.difficulty, environment.expected_success(team)))
+        .min_by(|(da, a), (db, b)| {
+            (a - target)
+                .abs()
+                .total_cmp(&(b - target).abs())
+                .then(da.cmp(db))
+        })
+}
+
+/// Environments of a mission from difficulty `0` to `max_difficulty`
+pub async fn difficulties(
+    conn: &mut MultiplexedConnection,
+    mission: &str,
+    max_difficulty: i32,
+) -> Result<Vec<Environment>, Error> {
+    let keys: Vec<String> = (0..=max_difficulty)
+        .map(|difficulty| environment_key(mission, difficulty))
+        .collect();
+    let data: Vec<Option<Vec<u8>>> = conn.mget(&keys).await?;
+
+    (0..=max_difficulty)
+        .zip(data)
+        .map(|(difficulty, data)| match data {
+            Some(data) => Ok(codec::decode(&data)?),
+            None => Ok(Environment {
+                mission: mission.to_string(),
+                difficulty,
+                ..Default::default()
+            }),
+        })
+        .collect()
+}
+
+/// `current` moved [`RESULT_WEIGHT`] of the way towards `updated`
+fn smooth(current: &MhthRating, updated: &MhthRating) -> MhthRating {
+    let towards = |current: f64, updated: f64| RESULT_WEIGHT.mul_add(updated - current, current);
+
+    MhthRating {
+        rating: towards(current.rating, updated.rating),
+        loadout_modifier: current.loadout_modifier,
+        uncertainty: towards(current.uncertainty, updated.uncertainty),
+    }
+}
+
+/// Challenge level of a match, shown to its players
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, Encode, Decode, PartialEq)]
+pub struct Challenge {
+    /// Expected success of the players against the environment
+    pub win_probability: f64,
+    /// Environment strength as a single opponent, see [`Environment::rating`]
+    pub environment: MhthRating,
+    pub entities: usize,
+}
+
+impl From<&Challenge> for EnvironmentSummary {
+    fn from(value: &Challenge) -> Self {
+        Self {
+            rating: value.environment.rating,
+            uncertainty: value.environment.uncertainty,
+            entities: value.entities as u32,
+        }
+    }
+}
+
+impl Environment {
+    pub fn challenge(&self, team: &[MhthRating]) -> Challenge {
+        Challenge {
+            win_probability: self.expected_success(team),
+            environment: self.rating(),
+            entities: self.entities.len(),
+        }
+    }
+}
+
+impl Match {
+    /// Mission played, the first mission type of the host, empty when the host accepts any
+    pub fn mission(&self) -> &str {
+        self.host_player()
+            .and_then(|host| host.mission_types.first())
+            .map_or("", String::as_str)
+    }
+
+    /// Checks `mission` is one of the mission types of the host, any mission when it accepts any
+    pub fn check_mission(&self, mission: &str) -> Result<(), Error> {
+        let allowed = self
+            .host_player()
+            .map_or(&[][..], |host| host.mission_types.as_slice());
+        if allowed.is_empty() || allowed.iter().any(|allowed| allowed == mission) {
+            Ok(())
+        } else {
+            Err(Error::MissionNotAllowed(mission.to_string()))
+        }
+    }
+}
+
+/// Challenge of `formed` against the environment of its mission, `None` for PvP matches
+pub async fn challenge(
+    conn: &mut MultiplexedConnection,
+    formed: &Match,
+) -> Result<Option<Challenge>, Error> {
+    if formed.is_versus() {
+        return Ok(None);
+    }
+    let environment = get_environment(conn, formed.mission(), formed.difficulty).await?;
+    let team: Vec<MhthRating> = formed.players.iter().map(|p| p.skillrating).collect();
+
+    Ok(Some(environment.challenge(&team)))
+}
+
+/// [`challenge`] of a match being closed, stored on it so starting it and its fairness metrics
+/// reuse it. `None` when the environment failed to load, the match loads it again when it starts
+pub async fn closing_challenge(
+    conn: &mut MultiplexedConnection,
+    closed: &Match,
+) -> Option<Challenge> {
+    challenge(conn, closed).await.unwrap_or_else(|err| {
+        error!(
+            "failed to load the challenge of match `{}`: {err}",
+            closed.id
+        );
+        None
+    })
+}
+
+impl From<&Environment> for EnvironmentResponse {
+    fn from(value: &Environment) -> Self {
+        Self {
+            mission: value.mission.clone(),
+            difficulty: value.difficulty,
+            entities: value.entities.iter().map(Into::into).collect(),
+            matches: value.matches,
+        }
+    }
+}
+
+pub fn environment_key(mission: &str, difficulty: i32) -> String {
+    namespace::key(format_args!("{ENVIRONMENT_KEY}:{mission}:{difficulty}"))
+}
+
+pub async fn set_environment(
+    conn: &mut MultiplexedConnection,
+    environment: &Environment,
+) -> Result<(), Error> {
+    conn.set(
+        environment_key(&environment.mission, environment.difficulty),
+        codec::encode(environment),
+    )
+    .await
+    .map_err(Error::from)
+}
+
+/// Environment of a mission at a difficulty, without entities when none was rated
+pub async fn get_environment(
+    conn: &mut MultiplexedConnection,
+    mission: &str,
+    difficulty: i32,
+) -> Result<Environment, Error> {
+    let data: Option<Vec<u8>> = conn.get(environment_key(mission, difficulty)).await?;
+
+    match data {
+        Some(data) => Ok(codec::decode(&data)?),
+        None => Ok(Environment {
+            mission: mission.to_string(),
+            difficulty,
+            ..Default::default()
+        }),
+    }
+}
+
+/// Updates the rated entities of a mission with a match result of `team`,
+/// `None` when the mission has no rated entity. The result is applied with
+/// [`COMPARE_AND_SET`], again on the new ratings when another result or an admin changed them
+/// meanwhile
+pub async fn record_result(
+    conn: &mut MultiplexedConnection,
+    mission: &str,
+    difficulty: i32,
+    team: &[MhthRating],
+    won: bool,
+) -> Result<Option<Environment>, Error> {
+    let key = environment_key(mission, difficulty);
+    for _ in 0..RECORD_ATTEMPTS {
+        let Some(data): Option<Vec<u8>> = conn.get(&key).await? else {
+            return Ok(None);
+        };
+        let mut environment: Environment = codec::decode(&data)?;
+        if environment.entities.is_empty() || team.is_empty() {
+            return Ok(None);
+        }
+        environment.record_result(team, won);
+        let set: bool = COMPARE_AND_SET
+            .key(&key)
+            .arg(data)
+            .arg(codec::encode(&environment))
+            .invoke_async(conn)
+            .await?;
+        if set {
+            return Ok(Some(environment));
+        }
+    }
+
+    Err(Error::Contended(mission.to_string()))
+}
+
+pub async fn delete_environment(
+    conn: &mut MultiplexedConnection,
+    mission: &str,
+    difficulty: i32,
+) -> Result<(), Error> {
+    conn.del(environment_key(mission, difficulty))
+        .await
+        .map_err(Error::from)
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+    use crate::test_support::{create_redis, redis_client};
+
+    fn entity(id: &str, rating: f64) -> Entity {
+        let rating = MhthRating {
+            rating,
+            ..Default::default()
+        };
+
+        Entity {
+            id: id.to_string(),
+            kind: "boss".to_string(),
+            rating,
+            baseline: rating,
+        }
+    }
+
+    #[test]
+    fn environment_rating_averages_entities() {
+        let environment = Environment {
+            mission: "hunt".to_string(),
+            difficulty: 2,
+            entities: vec![entity("warden", 40.), entity("drone", 20.)],
+            matches: 0,
+        };
+
+        assert_eq!(environment.rating().rating, 30.);
+        assert_eq!(
+            Environment {
+                entities: Vec::new(),
+                ..environment
+            }
+            .rating(),
+            smurf::environment_rating(2)
+        );
+    }
+
+    #[test]
+    fn results_move_ratings_slowly() {
+        let mut environment = Environment {
+            mission: "hunt".to_string(),
+            difficulty: 2,
+            entities: vec![entity("warden", 40.)],
+            matches: 0,
+        };
+        let team = [MhthRating::default(); 4];
+        let (_, updated) = mhth_team_vs_environment(
+            &team,
+            &[environment.entities[0].rating],
+            &Outcomes::SUCCESSFUL,
+            &MhthConfig::new(),
+        );
+
+        environment.record_result(&team, true);
+
+        let warden = &environment.entities[0];
+        assert!(warden.rating.rating < 40.);
+        assert!(warden.rating.rating > updated[0].rating);
+        assert_eq!(warden.baseline.rating, 40.);
+        assert_eq!(environment.matches, 1);
+    }
+
+    #[test]
+    fn recommended_difficulty_is_closest_to_target() {
+        let team = [MhthRating::default(); 3];
+        let environments: Vec<Environment> = (0..=5)
+            .map(|difficulty| Environment {
+                difficulty,
+                ..Default::default()
+            })
+            .collect();
+
+        let (difficulty, success) = recommend(&environments, &team, 0.3).unwrap();
+
+        for environment in &environments {
+            assert!((environment.expected_success(&team) - 0.3).abs() >= (success - 0.3).abs());
+        }
+        assert!(environments[0].expected_success(&team) > environments[5].expected_success(&team));
+        assert!(difficulty > 0);
+        assert_eq!(recommend(&environments, &team, 1.).unwrap().0, 0);
+    }
+
+    #[test]
+    fn challenge_summarizes_the_environment() {
+        let environment = Environment {
+            mission: "hunt".to_string(),
+            difficulty: 2,
+            entities: vec![entity("warden", 40.), entity("drone", 20.)],
+            matches: 0,
+        };
+        let team = [MhthRating::default(); 2];
+
+        let challenge = environment.challenge(&team);
+
+        assert_eq!(
+            challenge.win_probability,
+            environment.expected_success(&team)
+        );
+        assert_eq!(challenge.environment.rating, 30.);
+        assert_eq!(challenge.entities, 2);
+        assert_eq!(EnvironmentSummary::from(&challenge).entities, 2);
+    }
+
+    #[test]
+    fn reported_mission_is_a_host_mission() {
+        let host = |mission_types: Vec<String>| -> crate::rpc::QueuedPlayer {
+            (
+                uuid::Uuid::new_v4(),
+                matchmaking::Player {
+                    mission_types,
+                    ..Default::default()
+                },
+                MhthRating::default(),
+            )
+                .into()
+        };
+        let picky = Match::host(&host(vec!["hunt".to_string()]), &[]).unwrap();
+        let any = Match::host(&host(Vec::new()), &[]).unwrap();
+
+        assert!(picky.check_mission("hunt").is_ok());
+        assert!(matches!(
+            picky.check_mission("escort"),
+            Err(Error::MissionNotAllowed(mission)) if mission == "escort"
+        ));
+        assert!(any.check_mission("escort").is_ok());
+    }
+
+    #[test]
+    fn invalid_environments() {
+        let environment = |entities| Environment {
+            mission: "hunt".to_string(),
+            difficulty: 1,
+            entities,
+            matches: 0,
+        };
+
+        assert!(environment(vec![entity("warden", 40.)]).validate().is_ok());
+        assert!(
+            environment(vec![entity("warden", 40.), entity("warden", 20.)])
+                .validate()
+                .is_err()
+        );
+        assert!(environment(vec![entity("", 40.)]).validate().is_err());
+        assert!(
+            environment(vec![entity("warden", f64::NAN)])
+                .validate()
+                .is_err()
+        );
+    }
+
+    #[tokio::test]
+    async fn environment_catalog() {
+        let container = create_redis(6379).await;
+        let host = container.get_host().await.unwrap();
+        let port = container.get_host_port_ipv4(6379).await.unwrap();
+        let client = redis_client(host.to_string(), port);
+        let mut conn = client.get_multiplexed_async_connection().await.unwrap();
+        let environment = Environment {
+            mission: "hunt".to_string(),
+            difficulty: 3,
+            entities: vec![entity("warden", 40.)],
+            matches: 0,
+        };
+        let team = [MhthRating::default(); 4];
+
+        set_environment(&mut conn, &environment).await.unwrap();
+        let stored = get_environment(&mut conn, "hunt", 3).await.unwrap();
+        let other_difficulty = get_environment(&mut conn, "hunt", 4).await.unwrap();
+        let recorded = record_result(&mut conn, "hunt", 3, &team, true)
+            .await
+            .unwrap()
+            .unwrap();
+        let unrated = record_result(&mut conn, "escort", 3, &team, true)
+            .await
+            .unwrap();
+        delete_environment(&mut conn, "hunt", 3).await.unwrap();
+        let deleted = get_environment(&mut conn, "hunt", 3).await.unwrap();
+
+        assert_eq!(stored, environment);
+        assert_eq!(recorded.matches, 1);
+        assert!(recorded.entities[0].rating.rating < 40.);
+        assert_eq!(recorded.entities[0].baseline.rating, 40.);
+        assert!(unrated.is_none());
+        assert!(other_difficulty.entities.is_empty());
+        assert!(deleted.entities.is_empty());
+    }
+
+    #[tokio::test]
+    async fn concurrent_results_are_all_recorded() {
+        let container = create_redis(6379).await;
+        let host = container.get_host().await.unwrap();
+        let port = container.get_host_port_ipv4(6379).await.unwrap();
+        let client = redis_client(host.to_string(), port);
+        let mut conn = client.get_multiplexed_async_connection().await.unwrap();
+        let environment = Environment {
+            mission: "hunt".to_string(),
+            difficulty: 3,
+            entities: vec![entity("warden", 40.)],
+            matches: 0,
+        };
+        let team = [MhthRating::default(); 4];
+        set_environment(&mut conn, &environment).await.unwrap();
+
+        // a result is retried at most once per result racing it, within the attempts
+        let results: Vec<_> = (0..RECORD_ATTEMPTS - 1)
+            .map(|won| {
+                let mut conn = conn.clone();
+                tokio::spawn(async move {
+                    record_result(&mut conn, "hunt", 3, &team, won % 2 == 0).await
+                })
+            })
+            .collect();
+        for result in results {
+            result.await.unwrap().unwrap();
+        }
+        let stored = get_environment(&mut conn, "hunt", 3).await.unwrap();
+
+        assert_eq!(stored.matches, RECORD_ATTEMPTS as u64 - 1);
+    }
+}
@@ -0,0 +1,314 @@
+use std::path::Path;
+
+use bitcode::{Decode, Encode};
+use redis::{AsyncCommands, RedisError, aio::MultiplexedConnection};
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+use crate::{namespace, rpc::Match};
+
+pub const EXPERIMENTS_KEY: &str = "match:experiments";
+
+#[derive(Debug, thiserror::Error)]
+pub enum Error {
+    #[error("failed to read experiments config: {0}")]
+    Io(#[from] std::io::Error),
+    #[error(transparent)]
+    Json(#[from] serde_json::Error),
+    #[error(transparent)]
+    Redis(#[from] RedisError),
+    #[error(transparent)]
+    BitcodeDeser(#[from] bitcode::Error),
+}
+
+impl From<Error> for tonic::Status {
+    fn from(_: Error) -> Self {
+        Self::internal("Failed to load experiments")
+    }
+}
+
+/// Parameters used to decide if a player fits a match
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, Encode, Decode, PartialEq)]
+pub struct MatchParams {
+    /// Max relative distance between a backfilled player's skill and the match average
+    pub skill_window: f64,
+    /// Players below this ping are matched right away
+    pub ping_threshold: i32,
+    /// Players above this ping are never matched
+    pub max_ping: i32,
+    /// Players needed to close a match, at most [`Match::MAX_PLAYERS`]
+    pub max_players: usize,
+    /// Seconds players wait for a lobby of their input device before mixing inputs
+    pub input_pool_secs: i64,
+}
+
+impl MatchParams {
+    pub const DEFAULT: Self = Self {
+        skill_window: Match::BACKFILL_SKILL_WINDOW,
+        ping_threshold: 150,
+        max_ping: 300,
+        max_players: Match::MAX_PLAYERS,
+        input_pool_secs: 120,
+    };
+}
+
+impl Default for MatchParams {
+    fn default() -> Self {
+        Self::DEFAULT
+    }
+}
+
+/// Parameters changed by a bucket, missing values keep the defaults
+#[derive(Debug, Clone, Default, Serialize, Deserialize, PartialEq)]
+#[serde(default)]
+pub struct Overrides {
+    pub skill_window: Option<f64>,
+    pub ping_threshold: Option<i32>,
+    pub max_ping: Option<i32>,
+    pub max_players: Option<usize>,
+}
+
+impl Overrides {
+    pub fn apply(&self, params: &mut MatchParams) {
+        if let Some(skill_window) = self.skill_window {
+            params.skill_window = skill_window;
+        }
+        if let Some(ping_threshold) = self.ping_threshold {
+            params.ping_threshold = ping_threshold;
+        }
+        if let Some(max_ping) = self.max_ping {
+            params.max_ping = max_ping;
+        }
+        if let Some(max_players) = self.max_players {
+            params.max_players = max_players.clamp(1, Match::MAX_PLAYERS);
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct Bucket {
+    pub id: String,
+    /// Share of the players assigned to this bucket, relative to the other buckets
+    pub weight: u32,
+    #[serde(default)]
+    pub overrides: Overrides,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct Experiment {
+    pub key: String,
+    pub buckets: Vec<Bucket>,
+}
+
+impl Experiment {
+    /// Bucket of a player, the same player always lands in the same bucket
+    pub fn bucket(&self, player_id: &Uuid) -> Option<&Bucket> {
+        let total = self
+            .buckets
+            .iter()
+            .map(|bucket| u64::from(bucket.weight))
+            .sum::<u64>();
+        if total == 0 {
+            return None;
+        }
+        let mut roll = bucket_hash(player_id, &self.key) % total;
+
+        self.buckets.iter().find(|bucket| {
+            let weight = u64::from(bucket.weight);
+            if roll < weight {
+                true
+            } else {
+                roll -= weight;
+                false
+            }
+        })
+    }
+}
+
+/// Buckets of a player in every experiment and the resulting parameters
+#[derive(Debug, Clone, PartialEq)]
+pub struct Assignment {
+    /// `{experiment}:{bucket}` ids
+    pub buckets: Vec<String>,
+    pub params: MatchParams,
+}
+
+/// Applies the overrides of the player buckets to the `base` parameters
+pub fn assign(experiments: &[Experiment], base: MatchParams, player_id: &Uuid) -> Assignment {
+    let assignment = Assignment {
+        buckets: Vec::new(),
+        params: base,
+    };
+
+    experiments
+        .iter()
+        .filter_map(|experiment| Some((experiment, experiment.bucket(player_id)?)))
+        .fold(assignment, |mut assignment, (experiment, bucket)| {
+            assignment
+                .buckets
+                .push(format!("{}:{}", experiment.key, bucket.id));
+            bucket.overrides.apply(&mut assignment.params);
+            assignment
+        })
+}
+
+/// FNV-1a, stable across releases unlike the std hasher
+fn bucket_hash(player_id: &Uuid, experiment_key: &str) -> u64 {
+    player_id
+        .as_bytes()
+        .iter()
+        .chain(experiment_key.as_bytes())
+        .fold(0xcbf2_9ce4_8422_2325, |hash, byte| {
+            (hash ^ u64::from(*byte)).wrapping_mul(0x0100_0000_01b3)
+        })
+}
+
+/// Reads experiment definitions from a JSON config file
+pub fn load_file(path: impl AsRef<Path>) -> Result<Vec<Experiment>, Error> {
+    let config = std::fs::read_to_string(path)?;
+
+    Ok(serde_json::from_str(&config)?)
+}
+
+pub async fn set_experiments(
+    conn: &mut MultiplexedConnection,
+    experiments: &[Experiment],
+) -> Result<(), Error> {
+    conn.set(
+        namespace::key(EXPERIMENTS_KEY),
+        bitcode::serialize(experiments)?,
+    )
+    .await
+    .map_err(Error::from)
+}
+
+pub async fn get_experiments(conn: &mut MultiplexedConnection) -> Result<Vec<Experiment>, Error> {
+    let encoded: Option<Vec<u8>> = conn.get(namespace::key(EXPERIMENTS_KEY)).await?;
+
+    Ok(encoded
+        .map(|encoded| bitcode::deserialize(&encoded))
+        .transpose()?
+        .unwrap_or_default())
+}
+
+pub async fn assignment(
+    conn: &mut MultiplexedConnection,
+    base: MatchParams,
+    player_id: &Uuid,
+) -> Result<Assignment, Error> {
+    Ok(assign(&get_experiments(conn).await?, base, player_id))
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+    use crate::test_support::{create_redis, redis_client};
+
+    #[test]
+    fn deterministic_buckets() {
+        let experiment = wide_window_experiment();
+        let player_id = Uuid::new_v4();
+
+        let bucket = experiment.bucket(&player_id).unwrap();
+        for _ in 0..10 {
+            assert_eq!(experiment.bucket(&player_id), Some(bucket));
+        }
+    }
+
+    #[test]
+    fn buckets_follow_weights() {
+        let experiment = wide_window_experiment();
+
+        let wide = (0..1000)
+            .filter(|_| experiment.bucket(&Uuid::new_v4()).unwrap().id == "wide")
+            .count();
+
+        assert!((150..350).contains(&wide), "{wide}");
+    }
+
+    #[test]
+    fn assignment_applies_overrides() {
+        let mut experiment = wide_window_experiment();
+        experiment.buckets.retain(|bucket| bucket.id == "wide");
+        let player_id = Uuid::new_v4();
+
+        let assignment = assign(&[experiment], MatchParams::DEFAULT, &player_id);
+
+        assert_eq!(assignment.buckets, vec!["skill-window:wide".to_string()]);
+        assert_eq!(assignment.params.skill_window, 0.5);
+        assert_eq!(assignment.params.max_players, Match::MAX_PLAYERS);
+        assert!(
+            assign(&[], MatchParams::DEFAULT, &player_id)
+                .buckets
+                .is_empty()
+        );
+    }
+
+    #[test]
+    fn experiments_from_config() {
+        let config = r#"[{
+            "key": "small-matches",
+            "buckets": [
+                {"id": "control", "weight": 1},
+                {"id": "duos", "weight": 1, "overrides": {"max_players": 2, "max_ping": 200}}
+            ]
+        }]"#;
+
+        let experiments: Vec<Experiment> = serde_json::from_str(config).unwrap();
+        let mut params = MatchParams::default();
+        experiments[0].buckets[1].overrides.apply(&mut params);
+
+        assert_eq!(experiments[0].buckets[0].overrides, Overrides::default());
+        assert_eq!(params.max_players, 2);
+        assert_eq!(params.max_ping, 200);
+    }
+
+    #[tokio::test]
+    async fn stored_experiments() {
+        let container = create_redis(6379).await;
+        let host = container.get_host().await.unwrap();
+        let port = container.get_host_port_ipv4(6379).await.unwrap();
+        let client = redis_client(host.to_string(), port);
+        let mut conn = client.get_multiplexed_async_connection().await.unwrap();
+        let experiments = vec![wide_window_experiment()];
+        let player_id = Uuid::new_v4();
+
+        let empty = assignment(&mut conn, MatchParams::DEFAULT, &player_id)
+            .await
+            .unwrap();
+        set_experiments(&mut conn, &experiments).await.unwrap();
+        let loaded = get_experiments(&mut conn).await.unwrap();
+        let assigned = assignment(&mut conn, MatchParams::DEFAULT, &player_id)
+            .await
+            .unwrap();
+
+        assert!(empty.buckets.is_empty());
+        assert_eq!(loaded, experiments);
+        assert_eq!(
+            assigned,
+            assign(&experiments, MatchParams::DEFAULT, &player_id)
+        );
+    }
+
+    fn wide_window_experiment() -> Experiment {
+        Experiment {
+            key: "skill-window".to_string(),
+            buckets: vec![
+                Bucket {
+                    id: "control".to_string(),
+                    weight: 3,
+                    overrides: Overrides::default(),
+                },
+                Bucket {
+                    id: "wide".to_string(),
+                    weight: 1,
+                    overrides: Overrides {
+                        skill_window: Some(0.5),
+                        ..Default::default()
+                    },
+                },
+            ],
+        }
+    }
+}
@@ -0,0 +1,146 @@
+//! Friends-only lobbies. Hosts joining with `JoinMode::CreateFriendsRoom` create a room whose open
+//! slots are only filled by their Nakama friends. The worker loads the friends of the host and
+//! caches them for [`FRIENDS_CACHE_SECS`]. Once the match formed more than
+//! [`MatchmakingConfig::friends_fill_secs`] ago, its slots are filled by anyone.
+
+use std::{collections::HashSet, sync::Arc};
+
+use redis::{AsyncCommands, RedisError, aio::MultiplexedConnection};
+use uuid::Uuid;
+
+use crate::{
+    codec,
+    config::MatchmakingConfig,
+    lifecycle::MatchState,
+    nakama::{self, Authenticated, NakamaClient, endpoints::FRIEND_MUTUAL},
+    namespace,
+    rpc::{Match, QueuedPlayer, matchmaking::JoinMode},
+};
+
+pub const FRIENDS: &str = "friends";
+/// Seconds the friends of a host are cached
+pub const FRIENDS_CACHE_SECS: u64 = 300;
+
+#[derive(Debug, thiserror::Error)]
+pub enum Error {
+    #[error(transparent)]
+    Nakama(#[from] nakama::Error),
+    #[error(transparent)]
+    Redis(#[from] RedisError),
+    #[error(transparent)]
+    BitcodeDeser(#[from] codec::Error),
+}
+
+impl QueuedPlayer {
+    /// Does the player host its own room?
+    pub fn creates_room(&self) -> bool {
+        self.join_mode == i32::from(JoinMode::CreateRoom) || self.is_friends_only()
+    }
+
+    pub fn is_friends_only(&self) -> bool {
+        self.join_mode == i32::from(JoinMode::CreateFriendsRoom)
+    }
+}
+
+/// Cached friends of a player
+pub fn friends_key(player_id: &Uuid) -> String {
+    namespace::key(format_args!("{FRIENDS}:{player_id}"))
+}
+
+/// Are the open slots of `a_match` still reserved to the friends of its host at `now`?
+pub fn is_friends_only(a_match: &Match, now: i64, fill_secs: i64) -> bool {
+    let formed_at = a_match
+        .lifecycle
+        .entered_at(MatchState::Forming)
+        .unwrap_or(now);
+
+    a_match
+        .host_player()
+        .is_some_and(QueuedPlayer::is_friends_only)
+        && now - formed_at < fill_secs
+}
+
+/// Mutual Nakama friends of `player_id`, cached for [`FRIENDS_CACHE_SECS`]
+pub async fn friends(
+    conn: &mut MultiplexedConnection,
+    nakama_client: &NakamaClient<Authenticated>,
+    http_client: Arc<reqwest::Client>,
+    player_id: &Uuid,
+) -> Result<HashSet<Uuid>, Error> {
+    let key = friends_key(player_id);
+    let cached: Option<Vec<u8>> = conn.get(&key).await?;
+    let friend_ids: Vec<String> = match cached {
+        Some(cached) => codec::decode(&cached)?,
+        None => {
+            let friend_ids: Vec<String> = nakama_client
+                .get_friends(http_client, &player_id.to_string())
+                .await?
+                .into_iter()
+                .filter(|friend| friend.state == FRIEND_MUTUAL)
+                .map(|friend| friend.user.id)
+                .collect();
+            conn.set_ex(&key, codec::encode(&friend_ids), FRIENDS_CACHE_SECS)
+                .await
+                .map(|_: ()| ())?;
+            friend_ids
+        }
+    };
+
+    Ok(friend_ids
+        .iter()
+        .filter_map(|id| Uuid::parse_str(id).ok())
+        .collect())
+}
+
+/// Friends of the host while `a_match` is friends-only, see [`is_friends_only`]
+pub async fn reserved_for(
+    conn: &mut MultiplexedConnection,
+    nakama_client: &NakamaClient<Authenticated>,
+    http_client: Arc<reqwest::Client>,
+    a_match: &Match,
+    config: &MatchmakingConfig,
+    now: i64,
+) -> Result<Option<HashSet<Uuid>>, Error> {
+    if !is_friends_only(a_match, now, config.friends_fill_secs) {
+        return Ok(None);
+    }
+
+    friends(conn, nakama_client, http_client, &a_match.host_id)
+        .await
+        .map(Some)
+}
+
+#[cfg(test)]
+mod tests {
+    use skillratings::mhth::MhthRating;
+
+    use super::*;
+    use crate::{lifecycle::Lifecycle, rpc::matchmaking::Player};
+
+    fn queued(join_mode: JoinMode) -> QueuedPlayer {
+        (
+            Uuid::new_v4(),
+            Player {
+                join_mode: join_mode.into(),
+                region: "CAN".to_string(),
+                ..Default::default()
+            },
+            MhthRating::default(),
+        )
+            .into()
+    }
+
+    #[test]
+    fn friends_only_rooms_open_after_the_fill_time() {
+        let host = queued(JoinMode::CreateFriendsRoom);
+        let mut a_match = Match::host(&host, &[]).unwrap();
+        a_match.lifecycle = Lifecycle::forming(100);
+
+        assert!(host.creates_room());
+        assert!(is_friends_only(&a_match, 150, 60));
+        assert!(!is_friends_only(&a_match, 160, 60));
+
+        let public = Match::host(&queued(JoinMode::CreateRoom), &[]).unwrap();
+        assert!(!is_friends_only(&public, 100, 60));
+    }
+}
@@ -0,0 +1,421 @@
+//! Region of the players that declare none, or one the matchmaker does not serve. Their region
+//! is looked up by client address in a GeoIP table mapping networks to matchmaking regions. The
+//! table is read from [`GEOIP_PATH_VAR`] once at startup and kept in memory, so joining the queue
+//! makes no external lookup. Without it the embedded [`DEFAULT_TABLE`] maps the `/8` and `/12`
+//! blocks of each regional internet registry to a region. Players keep their declared region
+//! when nothing matches.
+//!
+//! The client address is the peer of the call, or the address a [`TRUSTED_PROXIES_VAR`] load
+//! balancer forwarded, so clients can not pick their region by forging [`FORWARDED_FOR_HEADER`].
+
+use std::{
+    collections::{BTreeMap, HashMap},
+    net::IpAddr,
+    path::Path,
+    str::FromStr,
+    sync::OnceLock,
+};
+
+use redis::{AsyncCommands, RedisError, aio::MultiplexedConnection};
+use tonic::Request;
+
+use crate::{codec, regions::regions_key, rpc::RegionSource};
+
+/// Env var with the path of the JSON GeoIP table, `{ "<network>": "<region>" }`
+pub const GEOIP_PATH_VAR: &str = "GEOIP_PATH";
+/// Env var with the comma separated networks of the load balancers, e.g. `10.0.0.0/8`.
+/// [`FORWARDED_FOR_HEADER`] is ignored when unset
+pub const TRUSTED_PROXIES_VAR: &str = "TRUSTED_PROXIES";
+/// Set by the load balancers, each one appends the address it received the call from
+pub const FORWARDED_FOR_HEADER: &str = "x-forwarded-for";
+/// Table used without [`GEOIP_PATH_VAR`], registry blocks of the `US`, `EU`, `SOUTH_AMERICA`,
+/// `ASIA` and `AFRICA` regions
+pub const DEFAULT_TABLE: &str = include_str!("default.json");
+
+static TABLE: OnceLock<GeoIpTable> = OnceLock::new();
+static TRUSTED_PROXIES: OnceLock<TrustedProxies> = OnceLock::new();
+
+#[derive(Debug, thiserror::Error)]
+pub enum Error {
+    #[error("invalid network `{0}`")]
+    InvalidNetwork(String),
+    #[error("failed to read GeoIP table: {0}")]
+    Io(#[from] std::io::Error),
+    #[error(transparent)]
+    Json(#[from] serde_json::Error),
+    #[error(transparent)]
+    Redis(#[from] RedisError),
+    #[error(transparent)]
+    BitcodeDeser(#[from] codec::Error),
+}
+
+impl From<Error> for tonic::Status {
+    fn from(value: Error) -> Self {
+        match value {
+            Error::InvalidNetwork(_) | Error::Io(_) | Error::Json(_) => {
+                Self::failed_precondition(value.to_string())
+            }
+            Error::Redis(_) | Error::BitcodeDeser(_) => Self::internal("Failed to load regions"),
+        }
+    }
+}
+
+/// Network in CIDR notation, a bare address is a network of one
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Network {
+    pub addr: IpAddr,
+    pub prefix: u8,
+}
+
+impl FromStr for Network {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || Error::InvalidNetwork(s.to_string());
+        let (addr, prefix) = s.trim().split_once('/').unwrap_or((s.trim(), ""));
+        let addr: IpAddr = addr.parse().map_err(|_| invalid())?;
+        let width = width(&addr);
+        let prefix = if prefix.is_empty() {
+            width
+        } else {
+            prefix.parse().map_err(|_| invalid())?
+        };
+        if prefix > width {
+            return Err(invalid());
+        }
+
+        Ok(Self { addr, prefix })
+    }
+}
+
+impl Network {
+    pub fn contains(&self, ip: IpAddr) -> bool {
+        let ip = ip.to_canonical();
+        ip.is_ipv4() == self.addr.is_ipv4()
+            && masked(&ip, self.prefix) == masked(&self.addr, self.prefix)
+    }
+}
+
+const fn width(addr: &IpAddr) -> u8 {
+    match addr {
+        IpAddr::V4(_) => 32,
+        IpAddr::V6(_) => 128,
+    }
+}
+
+/// Bits of the first `prefix` bits of the address
+fn masked(addr: &IpAddr, prefix: u8) -> u128 {
+    let bits = match addr {
+        IpAddr::V4(addr) => u128::from(addr.to_bits()),
+        IpAddr::V6(addr) => addr.to_bits(),
+    };
+    bits.checked_shr(u32::from(width(addr) - prefix))
+        .unwrap_or_default()
+}
+
+/// Networks by prefix, looked up longest prefix first
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct GeoIpTable {
+    v4: BTreeMap<u8, HashMap<u128, String>>,
+    v6: BTreeMap<u8, HashMap<u128, String>>,
+}
+
+impl GeoIpTable {
+    pub fn insert(&mut self, network: Network, region: String) {
+        let networks = match network.addr {
+            IpAddr::V4(_) => &mut self.v4,
+            IpAddr::V6(_) => &mut self.v6,
+        };
+        networks
+            .entry(network.prefix)
+            .or_default()
+            .insert(masked(&network.addr, network.prefix), region);
+    }
+
+    /// Region of the most specific network containing `ip`
+    pub fn lookup(&self, ip: IpAddr) -> Option<&str> {
+        let ip = ip.to_canonical();
+        let networks = match ip {
+            IpAddr::V4(_) => &self.v4,
+            IpAddr::V6(_) => &self.v6,
+        };
+        networks
+            .iter()
+            .rev()
+            .find_map(|(prefix, regions)| regions.get(&masked(&ip, *prefix)))
+            .map(String::as_str)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.v4.is_empty() && self.v6.is_empty()
+    }
+
+    pub fn from_json(json: &str) -> Result<Self, Error> {
+        let networks: BTreeMap<String, String> = serde_json::from_str(json)?;
+        let mut table = Self::default();
+        for (network, region) in networks {
+            table.insert(network.parse()?, region);
+        }
+
+        Ok(table)
+    }
+
+    pub fn load_file(path: impl AsRef<Path>) -> Result<Self, Error> {
+        Self::from_json(&std::fs::read_to_string(path)?)
+    }
+
+    /// [`DEFAULT_TABLE`]
+    pub fn embedded() -> Self {
+        Self::from_json(DEFAULT_TABLE).expect("embedded GeoIP table is valid")
+    }
+
+    /// Region the player queues in and where it came from. The declared region is kept when the
+    /// matchmaker serves it, `served` empty means regions are not configured and every region is
+    /// served.
+    pub fn resolve(
+        &self,
+        declared: &str,
+        served: &[String],
+        ip: Option<IpAddr>,
+    ) -> (String, RegionSource) {
+        let is_served = |region: &str| served.is_empty() || served.iter().any(|r| r == region);
+        if !declared.is_empty() && is_served(declared) {
+            return (declared.to_string(), RegionSource::Declared);
+        }
+
+        match ip.and_then(|ip| self.lookup(ip)) {
+            Some(detected) if is_served(detected) => (detected.to_string(), RegionSource::Detected),
+            _ => (declared.to_string(), RegionSource::Declared),
+        }
+    }
+}
+
+/// Networks of the load balancers allowed to forward the client address
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct TrustedProxies(Vec<Network>);
+
+impl FromStr for TrustedProxies {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        s.split(',')
+            .filter(|network| !network.trim().is_empty())
+            .map(str::parse)
+            .collect::<Result<_, _>>()
+            .map(Self)
+    }
+}
+
+impl TrustedProxies {
+    pub fn contains(&self, ip: IpAddr) -> bool {
+        self.0.iter().any(|network| network.contains(ip))
+    }
+
+    /// Address of the client of a call from `peer`. Addresses of `forwarded` are read from the
+    /// last one while the hop that appended it is trusted, so the addresses a client prepends
+    /// are never taken.
+    pub fn client(&self, peer: IpAddr, forwarded: Option<&str>) -> IpAddr {
+        let mut client = peer;
+        for hop in forwarded.unwrap_or_default().rsplit(',') {
+            if !self.contains(client) {
+                break;
+            }
+            let Ok(hop) = hop.trim().parse() else {
+                break;
+            };
+            client = hop;
+        }
+
+        client
+    }
+}
+
+/// Installs the table once, before the server starts.
+/// Returns `false` when a table was already installed.
+pub fn install(table: GeoIpTable) -> bool {
+    TABLE.set(table).is_ok()
+}
+
+/// Installs the proxies once, before the server starts.
+/// Returns `false` when proxies were already installed.
+pub fn install_proxies(proxies: TrustedProxies) -> bool {
+    TRUSTED_PROXIES.set(proxies).is_ok()
+}
+
+/// Reads the table from [`GEOIP_PATH_VAR`], else the embedded one, and the proxies from
+/// [`TRUSTED_PROXIES_VAR`]
+pub fn install_from_env() -> Result<(), Error> {
+    let table = match std::env::var(GEOIP_PATH_VAR) {
+        Ok(path) => GeoIpTable::load_file(path)?,
+        Err(_) => GeoIpTable::embedded(),
+    };
+    install(table);
+    if let Ok(proxies) = std::env::var(TRUSTED_PROXIES_VAR) {
+        install_proxies(proxies.parse()?);
+    }
+
+    Ok(())
+}
+
+/// Installed table, the embedded one when none was installed
+pub fn table() -> &'static GeoIpTable {
+    TABLE.get_or_init(GeoIpTable::embedded)
+}
+
+/// Installed proxies, none when they were not installed
+pub fn trusted_proxies() -> &'static TrustedProxies {
+    TRUSTED_PROXIES.get_or_init(TrustedProxies::default)
+}
+
+/// Address of the client, see [`TrustedProxies::client`]. Calls over the Unix socket have no
+/// peer address, so none.
+pub fn client_ip<T>(request: &Request<T>) -> Option<IpAddr> {
+    let forwarded = request
+        .metadata()
+        .get(FORWARDED_FOR_HEADER)
+        .and_then(|forwarded| forwarded.to_str().ok());
+
+    request
+        .remote_addr()
+        .map(|peer| trusted_proxies().client(peer.ip(), forwarded))
+}
+
+/// Regions matched by the workers, see [`crate::regions::set_regions`]
+pub async fn served_regions(conn: &mut MultiplexedConnection) -> Result<Vec<String>, Error> {
+    let encoded: Option<Vec<u8>> = conn.get(regions_key()).await?;
+
+    Ok(encoded
+        .map(|encoded| codec::decode(&encoded))
+        .transpose()?
+        .unwrap_or_default())
+}
+
+#[cfg(test)]
+mod tests {
+    use tonic::transport::server::TcpConnectInfo;
+
+    use super::*;
+
+    fn table() -> GeoIpTable {
+        GeoIpTable::from_json(
+            r#"{
+                "10.0.0.0/8": "EU",
+                "10.1.0.0/16": "US",
+                "2001:db8::/32": "SOUTH_AMERICA",
+                "0.0.0.0/0": "CAN"
+            }"#,
+        )
+        .unwrap()
+    }
+
+    fn ip(ip: &str) -> Option<IpAddr> {
+        ip.parse().ok()
+    }
+
+    #[test]
+    fn most_specific_network_wins() {
+        let table = table();
+
+        assert_eq!(table.lookup(ip("10.1.2.3").unwrap()), Some("US"));
+        assert_eq!(table.lookup(ip("10.2.2.3").unwrap()), Some("EU"));
+        assert_eq!(table.lookup(ip("192.168.0.1").unwrap()), Some("CAN"));
+        assert_eq!(table.lookup(ip("::ffff:10.1.0.9").unwrap()), Some("US"));
+        assert_eq!(
+            table.lookup(ip("2001:db8::1").unwrap()),
+            Some("SOUTH_AMERICA")
+        );
+        assert_eq!(table.lookup(ip("2001:db9::1").unwrap()), None);
+    }
+
+    #[test]
+    fn invalid_networks_are_rejected() {
+        assert!("10.0.0.0/33".parse::<Network>().is_err());
+        assert!("10.0.0/8".parse::<Network>().is_err());
+        assert_eq!(
+            "10.0.0.1".parse::<Network>().unwrap().prefix,
+            32,
+            "a bare address is a network of one"
+        );
+        assert!(GeoIpTable::from_json(r#"{ "eu": "EU" }"#).is_err());
+    }
+
+    #[test]
+    fn unknown_regions_are_detected() {
+        let table = table();
+        let served = ["EU".to_string(), "US".to_string()];
+
+        assert_eq!(
+            table.resolve("EU", &served, ip("10.1.0.1")),
+            ("EU".to_string(), RegionSource::Declared)
+        );
+        assert_eq!(
+            table.resolve("", &served, ip("10.1.0.1")),
+            ("US".to_string(), RegionSource::Detected)
+        );
+        assert_eq!(
+            table.resolve("MOON", &served, ip("10.2.0.1")),
+            ("EU".to_string(), RegionSource::Detected)
+        );
+        assert_eq!(
+            table.resolve("MOON", &served, ip("192.168.0.1")),
+            ("MOON".to_string(), RegionSource::Declared),
+            "regions that are not served are not detected"
+        );
+        assert_eq!(
+            table.resolve("MOON", &[], None),
+            ("MOON".to_string(), RegionSource::Declared)
+        );
+    }
+
+    #[test]
+    fn embedded_table_maps_registry_blocks() {
+        let table = GeoIpTable::embedded();
+
+        assert_eq!(table.lookup(ip("24.0.0.1").unwrap()), Some("US"));
+        assert_eq!(table.lookup(ip("81.2.69.160").unwrap()), Some("EU"));
+        assert_eq!(
+            table.lookup(ip("200.1.1.1").unwrap()),
+            Some("SOUTH_AMERICA")
+        );
+        assert_eq!(table.lookup(ip("2a02:1::1").unwrap()), Some("EU"));
+        assert_eq!(table.lookup(ip("10.0.0.1").unwrap()), None);
+    }
+
+    #[test]
+    fn forwarded_addresses_are_read_from_trusted_proxies() {
+        let proxies: TrustedProxies = "10.0.0.0/8, 172.16.0.1".parse().unwrap();
+        let lb = ip("10.0.0.2").unwrap();
+        let client = ip("203.0.113.7").unwrap();
+
+        assert_eq!(proxies.client(client, Some("198.51.100.1")), client);
+        assert_eq!(proxies.client(lb, Some("203.0.113.7")), client);
+        assert_eq!(
+            proxies.client(lb, Some("198.51.100.1, 203.0.113.7, 172.16.0.1")),
+            client,
+            "addresses prepended by the client are not taken"
+        );
+        assert_eq!(proxies.client(lb, Some("spoofed")), lb);
+        assert_eq!(proxies.client(lb, None), lb);
+        assert_eq!(
+            TrustedProxies::default().client(lb, Some("203.0.113.7")),
+            lb
+        );
+        assert!("10.0.0.0/40".parse::<TrustedProxies>().is_err());
+    }
+
+    #[test]
+    fn peer_address_is_the_client() {
+        let mut request = Request::new(());
+        request.metadata_mut().insert(
+            FORWARDED_FOR_HEADER,
+            "10.1.0.1, 172.16.0.1".parse().unwrap(),
+        );
+        assert_eq!(client_ip(&request), None);
+
+        request.extensions_mut().insert(TcpConnectInfo {
+            local_addr: None,
+            remote_addr: "203.0.113.7:4000".parse().ok(),
+        });
+        assert_eq!(client_ip(&request), ip("203.0.113.7"));
+    }
+}
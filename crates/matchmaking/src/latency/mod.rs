@@ -0,0 +1,119 @@
+//! Round trip time measured by the server on the `Heartbeat` stream. Pings sent by the clients
+//! are trivially spoofed, so players with a measured round trip queue with it instead of the
+//! `ping` of their request. Samples are smoothed like the TCP SRTT, so a single late heartbeat
+//! does not move a player out of its matches.
+//!
+//! The queue entry keeps a snapshot of the round trip taken when the player joined: later samples
+//! only update [`RTT_KEY`] and are picked up the next time the player joins the queue.
+
+use bitcode::{Decode, Encode};
+use redis::{AsyncCommands, RedisError, aio::MultiplexedConnection};
+use uuid::Uuid;
+
+use crate::{codec, namespace};
+
+pub const RTT_KEY: &str = "latency:rtt";
+/// Measurements expire once the player stopped sending heartbeats for this long
+pub const RTT_TTL: u64 = 600;
+/// Weight of a new sample in the smoothed round trip
+pub const SMOOTHING: f64 = 0.125;
+
+#[derive(Debug, thiserror::Error)]
+pub enum Error {
+    #[error(transparent)]
+    Redis(#[from] RedisError),
+    #[error(transparent)]
+    BitcodeDeser(#[from] codec::Error),
+}
+
+impl From<Error> for tonic::Status {
+    fn from(_: Error) -> Self {
+        Self::internal("Failed to load measured ping")
+    }
+}
+
+/// Smoothed round trip of a player, in milliseconds
+#[derive(Debug, Clone, Copy, Default, Encode, Decode, PartialEq)]
+pub struct Rtt {
+    pub smoothed_ms: f64,
+    pub samples: u32,
+}
+
+impl codec::Versioned for Rtt {}
+
+impl Rtt {
+    /// Adds a sample, the first sample is taken as is
+    #[must_use]
+    pub fn observe(self, sample_ms: f64) -> Self {
+        let smoothed_ms = if self.samples == 0 {
+            sample_ms
+        } else {
+            self.smoothed_ms + SMOOTHING * (sample_ms - self.smoothed_ms)
+        };
+
+        Self {
+            smoothed_ms,
+            samples: self.samples.saturating_add(1),
+        }
+    }
+
+    /// Ping used by the matchmaker
+    pub const fn ping(&self) -> i32 {
+        self.smoothed_ms.round() as i32
+    }
+}
+
+pub fn rtt_key(player_id: &Uuid) -> String {
+    namespace::key(format_args!("{RTT_KEY}:{player_id}"))
+}
+
+/// Measured round trip of the player, `None` when it sent no recent heartbeat
+pub async fn measured(
+    conn: &mut MultiplexedConnection,
+    player_id: &Uuid,
+) -> Result<Option<Rtt>, Error> {
+    let encoded: Option<Vec<u8>> = conn.get(rtt_key(player_id)).await?;
+
+    Ok(encoded.map(|encoded| codec::decode(&encoded)).transpose()?)
+}
+
+/// Smooths a new sample into the measured round trip of the player. Only the heartbeat stream
+/// of the player writes it, so the read and write need no transaction.
+pub async fn record(
+    conn: &mut MultiplexedConnection,
+    player_id: &Uuid,
+    sample_ms: f64,
+) -> Result<Rtt, Error> {
+    let rtt = measured(conn, player_id)
+        .await?
+        .unwrap_or_default()
+        .observe(sample_ms);
+    let _: () = conn
+        .set_ex(rtt_key(player_id), codec::encode(&rtt), RTT_TTL)
+        .await?;
+
+    Ok(rtt)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn first_sample_is_taken_as_is() {
+        let rtt = Rtt::default().observe(42.);
+
+        assert_eq!(rtt.ping(), 42);
+        assert_eq!(rtt.samples, 1);
+    }
+
+    #[test]
+    fn spikes_are_smoothed() {
+        let rtt = (0..20).fold(Rtt::default(), |rtt, _| rtt.observe(40.));
+        let spiked = rtt.observe(400.);
+
+        assert_eq!(rtt.ping(), 40);
+        assert_eq!(spiked.ping(), 85);
+        assert_eq!(spiked.samples, 21);
+    }
+}
@@ -0,0 +1,105 @@
+//! Leader election between worker replicas. Only the holder of the lease runs
+//! matchmaking ticks, so replicas never create the same match twice.
+
+use std::{sync::LazyLock, time::Duration};
+
+use redis::{RedisError, Script, aio::MultiplexedConnection};
+use uuid::Uuid;
+
+use crate::namespace;
+
+pub const LEADER_KEY: &str = "worker:leader";
+/// Worker intervals a lease survives without renewal
+pub const LEASE_INTERVALS: u32 = 3;
+
+/// Renews the lease of the current leader, or takes a free lease
+static ACQUIRE: LazyLock<Script> = LazyLock::new(|| {
+    Script::new(
+        r"
+        local leader = redis.call('GET', KEYS[1])
+        if leader == ARGV[1] then
+            redis.call('PEXPIRE', KEYS[1], ARGV[2])
+            return 1
+        end
+        if not leader then
+            redis.call('SET', KEYS[1], ARGV[1], 'PX', ARGV[2])
+            return 1
+        end
+        return 0
+        ",
+    )
+});
+
+/// Drops the lease only when held by the caller
+static RELEASE: LazyLock<Script> = LazyLock::new(|| {
+    Script::new(
+        r"
+        if redis.call('GET', KEYS[1]) == ARGV[1] then
+            return redis.call('DEL', KEYS[1])
+        end
+        return 0
+        ",
+    )
+});
+
+pub fn leader_key() -> String {
+    namespace::key(LEADER_KEY)
+}
+
+/// Returns `true` while `instance_id` holds the lease
+pub async fn acquire(
+    conn: &mut MultiplexedConnection,
+    instance_id: &Uuid,
+    lease: Duration,
+) -> Result<bool, RedisError> {
+    ACQUIRE
+        .key(leader_key())
+        .arg(instance_id.to_string())
+        .arg(lease.as_millis() as u64)
+        .invoke_async(conn)
+        .await
+}
+
+/// Hands the lease over before shutdown, instead of waiting for it to expire
+pub async fn release(
+    conn: &mut MultiplexedConnection,
+    instance_id: &Uuid,
+) -> Result<bool, RedisError> {
+    RELEASE
+        .key(leader_key())
+        .arg(instance_id.to_string())
+        .invoke_async(conn)
+        .await
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+    use crate::test_support::{create_redis, redis_client};
+
+    #[tokio::test]
+    async fn single_leader() {
+        let container = create_redis(6379).await;
+        let host = container.get_host().await.unwrap();
+        let port = container.get_host_port_ipv4(6379).await.unwrap();
+        let client = redis_client(host.to_string(), port);
+        let mut conn = client.get_multiplexed_async_connection().await.unwrap();
+        let (first, second) = (Uuid::new_v4(), Uuid::new_v4());
+        let lease = Duration::from_secs(10);
+
+        let first_elected = acquire(&mut conn, &first, lease).await.unwrap();
+        let second_elected = acquire(&mut conn, &second, lease).await.unwrap();
+        let first_renewed = acquire(&mut conn, &first, lease).await.unwrap();
+        let second_released = release(&mut conn, &second).await.unwrap();
+        let first_released = release(&mut conn, &first).await.unwrap();
+        let second_took_over = acquire(&mut conn, &second, lease).await.unwrap();
+
+        assert!(first_elected);
+        assert!(!second_elected);
+        assert!(first_renewed);
+        assert!(!second_released);
+        assert!(first_released);
+        assert!(second_took_over);
+    }
+}
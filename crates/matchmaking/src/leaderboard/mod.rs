@@ -0,0 +1,251 @@
+//! Season leaderboards in Nakama. When [`LEADERBOARD_ID_VAR`] is set, the ratings stored by a
+//! match result, see [`crate::ratings`], are written to the leaderboard of the current season in
+//! the background, so the in-game leaderboard reads the players' standing from Nakama. The score
+//! is the conservative rating, the skill the player is almost surely above, so new players with an
+//! uncertain rating do not top the board.
+
+use std::{sync::Arc, time::Duration};
+
+use redis::aio::MultiplexedConnection;
+use skillratings::mhth::MhthRating;
+use tracing::{error, warn};
+use uuid::Uuid;
+
+use crate::{
+    nakama::{
+        Authenticated, NakamaClient,
+        endpoints::{LEADERBOARD_OPERATOR_SET, WriteLeaderboardRecordRequest},
+    },
+    ratings,
+};
+
+/// Env var with the id of the leaderboard, ratings are not published when unset
+pub const LEADERBOARD_ID_VAR: &str = "NAKAMA_LEADERBOARD_ID";
+/// Env var with the current season, defaults to [`DEFAULT_SEASON`]
+pub const LEADERBOARD_SEASON_VAR: &str = "LEADERBOARD_SEASON";
+pub const DEFAULT_SEASON: &str = "1";
+/// Writes of a rating before its leaderboard update is dropped
+pub const PUBLISH_ATTEMPTS: u32 = 3;
+/// Wait before the first retry, doubled on each retry
+const RETRY_DELAY: Duration = Duration::from_millis(500);
+
+/// Scores are the conservative rating in hundredths, Nakama scores are integers
+const SCORE_SCALE: f64 = 100.;
+
+/// Leaderboard receiving the ratings of a season, each season writes to its own board
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SeasonLeaderboard {
+    pub leaderboard_id: String,
+    pub season: String,
+}
+
+impl SeasonLeaderboard {
+    /// Leaderboard of [`LEADERBOARD_ID_VAR`], `None` when unset
+    pub fn from_env() -> Option<Self> {
+        let leaderboard_id = std::env::var(LEADERBOARD_ID_VAR)
+            .ok()
+            .filter(|id| !id.trim().is_empty())?;
+        let season = std::env::var(LEADERBOARD_SEASON_VAR)
+            .ok()
+            .filter(|season| !season.trim().is_empty())
+            .unwrap_or_else(|| DEFAULT_SEASON.to_string());
+
+        Some(Self {
+            leaderboard_id,
+            season,
+        })
+    }
+
+    /// Nakama id of the board of the season, e.g. `ranked-s3`
+    pub fn board_id(&self) -> String {
+        format!("{}-s{}", self.leaderboard_id, self.season)
+    }
+
+    /// Record replacing the score of the player with `rating`
+    pub fn record(&self, player_id: &Uuid, rating: &MhthRating) -> WriteLeaderboardRecordRequest {
+        WriteLeaderboardRecordRequest {
+            leaderboard_id: self.board_id(),
+            owner_id: player_id.to_string(),
+            score: score(rating),
+            operator: LEADERBOARD_OPERATOR_SET.to_string(),
+        }
+    }
+
+    /// Publishes the stored ratings of `player_ids` in a background task, see [`Self::publish`]
+    pub fn spawn_publish(
+        &self,
+        conn: MultiplexedConnection,
+        nakama_client: Arc<NakamaClient<Authenticated>>,
+        http_client: Arc<reqwest::Client>,
+        player_ids: Vec<Uuid>,
+    ) {
+        let leaderboard = self.clone();
+        tokio::spawn(async move {
+            let mut conn = conn;
+            leaderboard
+                .publish(&mut conn, &nakama_client, http_client, &player_ids)
+                .await;
+        });
+    }
+
+    /// Writes the stored ratings of `player_ids`, retrying each up to [`PUBLISH_ATTEMPTS`] times.
+    /// A Nakama outage only loses the leaderboard update.
+    pub async fn publish(
+        &self,
+        conn: &mut MultiplexedConnection,
+        nakama_client: &NakamaClient<Authenticated>,
+        http_client: Arc<reqwest::Client>,
+        player_ids: &[Uuid],
+    ) {
+        for player_id in player_ids {
+            let rating = match ratings::get_rating(conn, player_id).await {
+                Ok(Some(rating)) => rating,
+                Ok(None) => continue,
+                Err(err) => {
+                    error!("Failed to load rating of `{player_id}`: {err}");
+                    continue;
+                }
+            };
+            let record = self.record(player_id, &rating);
+            let mut delay = RETRY_DELAY;
+            for attempt in 1..=PUBLISH_ATTEMPTS {
+                let Err(err) = nakama_client
+                    .write_leaderboard_record(http_client.clone(), &record)
+                    .await
+                else {
+                    break;
+                };
+                if attempt == PUBLISH_ATTEMPTS {
+                    error!(
+                        "Failed to publish rating of `{player_id}` to `{}`: {err}",
+                        self.board_id()
+                    );
+                    break;
+                }
+                warn!("Retrying rating of `{player_id}` after: {err}");
+                tokio::time::sleep(delay).await;
+                delay *= 2;
+            }
+        }
+    }
+}
+
+/// Leaderboard score of `rating`: its conservative rating, never below zero
+pub fn score(rating: &MhthRating) -> i64 {
+    let conservative = rating.rating - 3. * rating.uncertainty;
+
+    (conservative.max(0.) * SCORE_SCALE).round() as i64
+}
+
+#[cfg(test)]
+mod tests {
+    use httpmock::{Method::POST, MockServer};
+
+    use super::*;
+    use crate::{
+        records::RatingChange,
+        test_support::{create_redis, redis_client},
+    };
+
+    #[test]
+    fn scores_are_conservative_ratings() {
+        let settled = MhthRating {
+            rating: 40.,
+            uncertainty: 2.5,
+            ..Default::default()
+        };
+        let uncertain = MhthRating {
+            rating: 45.,
+            uncertainty: 8.,
+            ..Default::default()
+        };
+
+        assert_eq!(score(&settled), 3250);
+        assert!(score(&uncertain) < score(&settled));
+        assert_eq!(score(&MhthRating::default()), 0);
+    }
+
+    #[test]
+    fn records_are_scoped_to_the_season() {
+        let leaderboard = SeasonLeaderboard {
+            leaderboard_id: "ranked".to_string(),
+            season: "3".to_string(),
+        };
+        let player_id = Uuid::new_v4();
+        let rating = MhthRating {
+            rating: 30.,
+            uncertainty: 5.,
+            ..Default::default()
+        };
+
+        let record = leaderboard.record(&player_id, &rating);
+
+        assert_eq!(record.leaderboard_id, "ranked-s3");
+        assert_eq!(record.owner_id, player_id.to_string());
+        assert_eq!(record.score, 1500);
+        assert_eq!(record.operator, LEADERBOARD_OPERATOR_SET);
+    }
+
+    #[tokio::test]
+    async fn stored_ratings_are_published_with_retries() {
+        let container = create_redis(6379).await;
+        let host = container.get_host().await.unwrap();
+        let port = container.get_host_port_ipv4(6379).await.unwrap();
+        let client = redis_client(host.to_string(), port);
+        let mut conn = client.get_multiplexed_async_connection().await.unwrap();
+        let server = MockServer::start_async().await;
+        let nakama_client = auth_client(server.address().port());
+        let leaderboard = SeasonLeaderboard {
+            leaderboard_id: "ranked".to_string(),
+            season: "1".to_string(),
+        };
+        let (rated, unrated) = (Uuid::new_v4(), Uuid::new_v4());
+        let mut pipe = redis::pipe();
+        ratings::apply(
+            &mut pipe,
+            &[RatingChange {
+                match_id: Uuid::new_v4(),
+                player_id: rated,
+                before: MhthRating::default(),
+                after: MhthRating {
+                    rating: 40.,
+                    uncertainty: 2.5,
+                    ..Default::default()
+                },
+            }],
+        );
+        pipe.query_async::<()>(&mut conn).await.unwrap();
+
+        let failing = server
+            .mock_async(|when, then| {
+                when.method(POST)
+                    .path("/v2/console/api/endpoints/rpc/write_leaderboard_record");
+                then.status(503);
+            })
+            .await;
+        leaderboard
+            .publish(
+                &mut conn,
+                &nakama_client,
+                Arc::new(reqwest::Client::new()),
+                &[rated, unrated],
+            )
+            .await;
+
+        failing.assert_calls_async(PUBLISH_ATTEMPTS as usize).await;
+    }
+
+    fn auth_client(port: u16) -> NakamaClient<Authenticated> {
+        NakamaClient {
+            username: "username".to_string(),
+            password: "password".to_string(),
+            token: Some("super_random_token".to_string()),
+            url: format!("http://127.0.0.1:{port}"),
+            server_key_name: "defaultkey".to_string(),
+            server_key_value: "server_key".to_string(),
+            encryption_key: "encryption_key".to_string(),
+            legacy_until: None,
+            _state: std::marker::PhantomData::<Authenticated>,
+        }
+    }
+}
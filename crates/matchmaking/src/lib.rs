@@ -1,5 +1,64 @@
+pub mod admin;
+pub mod aging;
+pub mod allocation;
+pub mod analytics;
+pub mod audit;
+pub mod balance;
+pub mod chaos;
+pub mod claim;
+pub mod clans;
+pub mod client;
+pub mod clock;
+pub mod codec;
+pub mod config;
+pub mod datacenter;
+#[cfg(test)]
+mod e2e;
+pub mod environment;
+pub mod experiments;
+pub mod friends;
+pub mod geoip;
 pub mod internal_clients;
+pub mod latency;
+pub mod leader;
+pub mod leaderboard;
+pub mod lifecycle;
+pub mod lobby;
+pub mod match_settings;
+pub mod metrics;
 pub mod nakama;
+pub mod namespace;
+pub mod notifications;
+pub mod observability;
+pub mod party;
+pub mod penalty;
+pub mod playlists;
+pub mod preferences;
+pub mod presence;
+pub mod probes;
+pub mod profile;
 pub mod progression;
+pub mod raid;
+pub mod ranked;
+pub mod ratings;
+pub mod ready_check;
+pub mod records;
 pub mod regions;
+pub mod rematch;
+pub mod reports;
+pub mod rng;
+pub mod rolls;
 pub mod rpc;
+pub mod secrets;
+pub mod sessions;
+pub mod simulation;
+pub mod smurf;
+pub mod snapshot;
+pub mod starvation;
+pub mod store;
+#[cfg(test)]
+mod test_support;
+pub mod tournament;
+pub mod trust;
+pub mod validation;
+pub mod versus;
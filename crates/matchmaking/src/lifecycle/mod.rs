@@ -0,0 +1,154 @@
+//! Lifecycle of a match, stored with it so every replica agrees on where the match is:
+//! `Forming -> Ready -> Starting -> Active -> Completed`, any non-terminal state may be `Aborted`.
+
+use bitcode::{Decode, Encode};
+use serde::{Deserialize, Serialize};
+
+use crate::rpc::Match;
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, Encode, Decode, PartialEq, Eq, Default)]
+pub enum MatchState {
+    /// Open, players are still joining
+    #[default]
+    Forming,
+    /// Full, waiting in the closed matches queue
+    Ready,
+    /// Handed to Nakama, retried from the dead-letter queue until it starts
+    Starting,
+    /// Running, players can rejoin and slots can be backfilled
+    Active,
+    Completed,
+    Aborted,
+}
+
+impl MatchState {
+    pub const fn is_terminal(self) -> bool {
+        matches!(self, Self::Completed | Self::Aborted)
+    }
+
+    pub const fn can_become(self, to: Self) -> bool {
+        match (self, to) {
+            (Self::Forming, Self::Ready)
+            | (Self::Ready, Self::Starting)
+            | (Self::Starting, Self::Active)
+            | (Self::Active, Self::Completed) => true,
+            (from, Self::Aborted) => !from.is_terminal(),
+            _ => false,
+        }
+    }
+}
+
+#[derive(Debug, thiserror::Error, PartialEq, Eq)]
+pub enum Error {
+    #[error("match cannot go from `{from:?}` to `{to:?}`")]
+    InvalidTransition { from: MatchState, to: MatchState },
+}
+
+impl From<Error> for tonic::Status {
+    fn from(value: Error) -> Self {
+        Self::failed_precondition(value.to_string())
+    }
+}
+
+/// State entered at `at`, seconds since the game epoch
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, Encode, Decode, PartialEq, Eq)]
+pub struct Transition {
+    pub state: MatchState,
+    pub at: i64,
+}
+
+/// Every state a match went through, oldest first. A match without transitions is forming.
+#[derive(Debug, Clone, Serialize, Deserialize, Encode, Decode, PartialEq, Eq, Default)]
+pub struct Lifecycle {
+    pub transitions: Vec<Transition>,
+}
+
+impl Lifecycle {
+    /// Match forming since `at`
+    pub fn forming(at: i64) -> Self {
+        Self {
+            transitions: vec![Transition {
+                state: MatchState::Forming,
+                at,
+            }],
+        }
+    }
+
+    pub fn state(&self) -> MatchState {
+        self.transitions
+            .last()
+            .map_or(MatchState::Forming, |transition| transition.state)
+    }
+
+    /// When the match last entered `state`
+    pub fn entered_at(&self, state: MatchState) -> Option<i64> {
+        self.transitions
+            .iter()
+            .rev()
+            .find(|transition| transition.state == state)
+            .map(|transition| transition.at)
+    }
+
+    pub fn transition(&mut self, to: MatchState, at: i64) -> Result<(), Error> {
+        let from = self.state();
+        if !from.can_become(to) {
+            return Err(Error::InvalidTransition { from, to });
+        }
+        self.transitions.push(Transition { state: to, at });
+
+        Ok(())
+    }
+}
+
+impl Match {
+    pub fn state(&self) -> MatchState {
+        self.lifecycle.state()
+    }
+
+    /// Enters `to`, a match entering [`MatchState::Ready`] records the voice chat preference
+    /// of its players
+    pub fn transition(&mut self, to: MatchState, at: i64) -> Result<(), Error> {
+        self.lifecycle.transition(to, at)?;
+        if to == MatchState::Ready {
+            self.voice_chat = self.shared_voice_chat().into();
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn match_goes_through_its_states() {
+        let mut lifecycle = Lifecycle::forming(10);
+
+        lifecycle.transition(MatchState::Ready, 20).unwrap();
+        lifecycle.transition(MatchState::Starting, 21).unwrap();
+        lifecycle.transition(MatchState::Active, 22).unwrap();
+        lifecycle.transition(MatchState::Completed, 900).unwrap();
+
+        assert_eq!(lifecycle.state(), MatchState::Completed);
+        assert_eq!(lifecycle.entered_at(MatchState::Forming), Some(10));
+        assert_eq!(lifecycle.entered_at(MatchState::Active), Some(22));
+    }
+
+    #[test]
+    fn invalid_transitions() {
+        let mut lifecycle = Lifecycle::default();
+
+        assert_eq!(
+            lifecycle.transition(MatchState::Active, 1),
+            Err(Error::InvalidTransition {
+                from: MatchState::Forming,
+                to: MatchState::Active
+            })
+        );
+        lifecycle.transition(MatchState::Aborted, 2).unwrap();
+        assert!(lifecycle.transition(MatchState::Aborted, 3).is_err());
+        assert!(lifecycle.transition(MatchState::Ready, 3).is_err());
+        assert_eq!(lifecycle.state(), MatchState::Aborted);
+    }
+}
@@ -0,0 +1,213 @@
+//! Hosts kicking players from their lobby, a match still forming. The worker owns the open
+//! matches, so a kick is recorded under the match and applied on the next tick by
+//! [`crate::rpc::worker::MatchmakingWorker::apply_lobby_kicks`], which frees the slot and returns
+//! the kicked player to the queue. Players the host invited to its party are dropped from the
+//! queue instead, they would only be matched with the host again.
+
+use std::collections::HashMap;
+
+use redis::{AsyncCommands, RedisError, aio::MultiplexedConnection};
+use tonic::Code;
+use tonic_types::{ErrorDetails, FieldViolation, StatusExt};
+use uuid::Uuid;
+
+use crate::{
+    codec,
+    lifecycle::MatchState,
+    namespace,
+    rpc::{Match, server::TWELVE_MINUTES},
+    store,
+};
+
+pub const LOBBY_KICKS: &str = "lobby:kicks";
+
+#[derive(Debug, thiserror::Error)]
+pub enum Error {
+    #[error("match `{0}` is not forming")]
+    MatchNotFound(Uuid),
+    #[error("only the host kicks players from match `{0}`")]
+    NotHost(Uuid),
+    #[error("player `{0}` is not in the lobby")]
+    NotInLobby(Uuid),
+    #[error("hosts cannot kick themselves")]
+    KickHost,
+    #[error(transparent)]
+    Redis(#[from] RedisError),
+    #[error(transparent)]
+    BitcodeDeser(#[from] codec::Error),
+}
+
+impl From<Error> for tonic::Status {
+    fn from(value: Error) -> Self {
+        match value {
+            Error::MatchNotFound(_) => Self::failed_precondition(value.to_string()),
+            Error::NotHost(_) => Self::permission_denied(value.to_string()),
+            Error::NotInLobby(_) | Error::KickHost => Self::with_error_details(
+                Code::InvalidArgument,
+                value.to_string(),
+                ErrorDetails::with_bad_request(vec![FieldViolation::new(
+                    "kicked_id",
+                    value.to_string(),
+                )]),
+            ),
+            Error::Redis(_) | Error::BitcodeDeser(_) => Self::internal("Failed to kick player"),
+        }
+    }
+}
+
+/// Kick accepted by [`check`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Kick {
+    /// Uninvited players return to the queue, invited ones are dropped
+    pub requeue: bool,
+    /// Free slots of the lobby once the player left
+    pub open_slots: usize,
+}
+
+/// Kicks pending on a forming match, by player, with whether the player returns to the queue
+pub fn lobby_kicks_key(match_id: &Uuid) -> String {
+    namespace::key(format_args!("{LOBBY_KICKS}:{match_id}"))
+}
+
+/// Validates the kick of `player_id` by `host_id`
+pub fn check(lobby: &Match, host_id: Uuid, player_id: Uuid) -> Result<Kick, Error> {
+    if lobby.state() != MatchState::Forming {
+        return Err(Error::MatchNotFound(lobby.id));
+    }
+    if lobby.host_id != host_id {
+        return Err(Error::NotHost(lobby.id));
+    }
+    if player_id == host_id {
+        return Err(Error::KickHost);
+    }
+    if !lobby.players.iter().any(|p| p.player_id == player_id) {
+        return Err(Error::NotInLobby(player_id));
+    }
+    let invited = lobby
+        .host_player()
+        .is_some_and(|host| host.party_ids.contains(&player_id.to_string()));
+
+    Ok(Kick {
+        requeue: !invited,
+        open_slots: lobby
+            .params
+            .max_players
+            .saturating_sub(lobby.players.len() - 1),
+    })
+}
+
+/// Records the kick of `player_id` from the forming match `match_id`
+pub async fn kick(
+    conn: &mut MultiplexedConnection,
+    match_id: Uuid,
+    host_id: Uuid,
+    player_id: Uuid,
+) -> Result<Kick, Error> {
+    let data = store::match_data(conn, &match_id).await?;
+    let lobby: Match = codec::decode(&data.ok_or(Error::MatchNotFound(match_id))?)?;
+    let kick = check(&lobby, host_id, player_id)?;
+
+    let key = lobby_kicks_key(&match_id);
+    redis::pipe()
+        .atomic()
+        .hset(&key, player_id, kick.requeue)
+        .ignore()
+        .expire(&key, TWELVE_MINUTES as i64)
+        .ignore()
+        .query_async(conn)
+        .await
+        .map(|_: ()| ())?;
+
+    Ok(kick)
+}
+
+/// Kicks pending on `match_id`, removed with [`clear_kicks`] once applied
+pub async fn pending_kicks(
+    conn: &mut MultiplexedConnection,
+    match_id: &Uuid,
+) -> Result<HashMap<Uuid, bool>, RedisError> {
+    conn.hgetall(lobby_kicks_key(match_id)).await
+}
+
+/// Removes the applied kicks of `player_ids` in `pipe`, kicks added meanwhile are kept
+pub fn clear_kicks<'a>(
+    pipe: &mut redis::Pipeline,
+    match_id: &Uuid,
+    player_ids: impl IntoIterator<Item = &'a Uuid>,
+) {
+    let player_ids: Vec<_> = player_ids.into_iter().collect();
+    if !player_ids.is_empty() {
+        pipe.hdel(lobby_kicks_key(match_id), player_ids).ignore();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use skillratings::mhth::MhthRating;
+
+    use super::*;
+    use crate::rpc::{QueuedPlayer, matchmaking::Player};
+
+    fn queued(join_mode: i32, party_ids: Vec<String>) -> QueuedPlayer {
+        (
+            Uuid::new_v4(),
+            Player {
+                join_mode,
+                region: "CAN".to_string(),
+                party_member_id: party_ids,
+                ..Default::default()
+            },
+            MhthRating::default(),
+        )
+            .into()
+    }
+
+    #[test]
+    fn hosts_kick_players_of_their_lobby() {
+        let invited = queued(1, Vec::new());
+        let host = queued(0, vec![invited.player_id.to_string()]);
+        let stranger = queued(1, Vec::new());
+        let mut lobby = Match::host(&host, std::slice::from_ref(&invited)).unwrap();
+        lobby.players.push(stranger.clone());
+        let max = lobby.params.max_players;
+
+        assert_eq!(
+            check(&lobby, host.player_id, invited.player_id).unwrap(),
+            Kick {
+                requeue: false,
+                open_slots: max - 2
+            }
+        );
+        assert!(
+            check(&lobby, host.player_id, stranger.player_id)
+                .unwrap()
+                .requeue
+        );
+    }
+
+    #[test]
+    fn invalid_kicks_are_rejected() {
+        let host = queued(0, Vec::new());
+        let other = queued(1, Vec::new());
+        let mut lobby = Match::host(&host, &[]).unwrap();
+        lobby.players.push(other.clone());
+
+        assert!(matches!(
+            check(&lobby, other.player_id, host.player_id),
+            Err(Error::NotHost(_))
+        ));
+        assert!(matches!(
+            check(&lobby, host.player_id, host.player_id),
+            Err(Error::KickHost)
+        ));
+        assert!(matches!(
+            check(&lobby, host.player_id, Uuid::new_v4()),
+            Err(Error::NotInLobby(_))
+        ));
+        lobby.transition(MatchState::Ready, 1).unwrap();
+        assert!(matches!(
+            check(&lobby, host.player_id, other.player_id),
+            Err(Error::MatchNotFound(_))
+        ));
+    }
+}
@@ -0,0 +1,153 @@
+//! Modifiers of custom matches. Hosts set them when joining the queue, they are kept on the
+//! queued host, so the match formed around it carries them through [`crate::rpc::Match::settings`]
+//! to the Nakama match handler. Matches without settings play the default rules.
+
+use bitcode::{Decode, Encode};
+use serde::{Deserialize, Serialize};
+use tonic::Code;
+use tonic_types::{ErrorDetails, FieldViolation, StatusExt};
+
+use crate::rpc::matchmaking::{self, JoinMode};
+
+/// Most mutators of a match
+pub const MAX_MUTATORS: usize = 8;
+/// Longest mutator id
+pub const MAX_MUTATOR_LEN: usize = 32;
+
+#[derive(Debug, thiserror::Error)]
+pub enum Error {
+    #[error("only hosts set match settings")]
+    NotHost,
+    #[error("at most {MAX_MUTATORS} mutators, got {0}")]
+    TooManyMutators(usize),
+    #[error("invalid mutators: {0:?}")]
+    InvalidMutators(Vec<String>),
+}
+
+impl From<Error> for tonic::Status {
+    fn from(value: Error) -> Self {
+        let violations: Vec<FieldViolation> = match &value {
+            Error::NotHost => vec![FieldViolation::new("join_mode", value.to_string())],
+            Error::TooManyMutators(_) => {
+                vec![FieldViolation::new(
+                    "match_settings.mutators",
+                    value.to_string(),
+                )]
+            }
+            Error::InvalidMutators(mutators) => mutators
+                .iter()
+                .map(|mutator| FieldViolation::new("match_settings.mutators", mutator))
+                .collect(),
+        };
+
+        Self::with_error_details(
+            Code::InvalidArgument,
+            value.to_string(),
+            ErrorDetails::with_bad_request(violations),
+        )
+    }
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize, Encode, Decode, PartialEq, Eq)]
+pub struct MatchSettings {
+    pub friendly_fire: bool,
+    /// Mutator ids, lowercase alphanumerics and `_`
+    pub mutators: Vec<String>,
+    /// `None` lets the game server pick the seed
+    pub mission_seed: Option<u64>,
+}
+
+impl MatchSettings {
+    /// Validated settings of a player joining in `join_mode`
+    pub fn parse(
+        settings: Option<matchmaking::MatchSettings>,
+        join_mode: JoinMode,
+    ) -> Result<Option<Self>, Error> {
+        let Some(settings) = settings else {
+            return Ok(None);
+        };
+        if join_mode == JoinMode::JoinRoom {
+            return Err(Error::NotHost);
+        }
+        if settings.mutators.len() > MAX_MUTATORS {
+            return Err(Error::TooManyMutators(settings.mutators.len()));
+        }
+        let invalid: Vec<String> = settings
+            .mutators
+            .iter()
+            .enumerate()
+            .filter(|(i, mutator)| {
+                !is_mutator_id(mutator) || settings.mutators[..*i].contains(mutator)
+            })
+            .map(|(_, mutator)| mutator.clone())
+            .collect();
+        if !invalid.is_empty() {
+            return Err(Error::InvalidMutators(invalid));
+        }
+
+        Ok(Some(Self {
+            friendly_fire: settings.friendly_fire,
+            mutators: settings.mutators,
+            mission_seed: (settings.mission_seed != 0).then_some(settings.mission_seed),
+        }))
+    }
+}
+
+fn is_mutator_id(mutator: &str) -> bool {
+    !mutator.is_empty()
+        && mutator.len() <= MAX_MUTATOR_LEN
+        && mutator
+            .bytes()
+            .all(|byte| byte.is_ascii_lowercase() || byte.is_ascii_digit() || byte == b'_')
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn settings(mutators: &[&str]) -> matchmaking::MatchSettings {
+        matchmaking::MatchSettings {
+            friendly_fire: true,
+            mutators: mutators.iter().map(ToString::to_string).collect(),
+            mission_seed: 0,
+        }
+    }
+
+    #[test]
+    fn hosts_set_valid_settings() {
+        let parsed =
+            MatchSettings::parse(Some(settings(&["low_gravity", "x2"])), JoinMode::CreateRoom)
+                .unwrap()
+                .unwrap();
+
+        assert!(parsed.friendly_fire);
+        assert_eq!(parsed.mutators, vec!["low_gravity", "x2"]);
+        assert_eq!(parsed.mission_seed, None);
+        assert_eq!(
+            MatchSettings::parse(None, JoinMode::JoinRoom).unwrap(),
+            None
+        );
+    }
+
+    #[test]
+    fn invalid_settings_are_rejected() {
+        assert!(matches!(
+            MatchSettings::parse(Some(settings(&[])), JoinMode::JoinRoom),
+            Err(Error::NotHost)
+        ));
+        assert!(matches!(
+            MatchSettings::parse(
+                Some(settings(&["a"; MAX_MUTATORS + 1])),
+                JoinMode::CreateRoom
+            ),
+            Err(Error::TooManyMutators(9))
+        ));
+        let Err(Error::InvalidMutators(invalid)) = MatchSettings::parse(
+            Some(settings(&["fog", "Fog", "", "fog"])),
+            JoinMode::JoinOrCreateRoom,
+        ) else {
+            panic!("mutators should be invalid");
+        };
+        assert_eq!(invalid, vec!["Fog", "", "fog"]);
+    }
+}
@@ -0,0 +1,237 @@
+//! Fairness of the matches the worker closes: the skill spread of their players, and their
+//! expected success against the environment of the mission or, in PvP, of the favoured team.
+//! Every closed match adds up into the distributions admins read with `GetQueueMetrics`, and
+//! matches out of [`OUTLIER_SPREAD`] or [`SUCCESS_RANGE`] are logged as outliers. Together they
+//! tell whether the skill windows of `is_player_fit` are too wide or too narrow.
+
+use std::{collections::HashMap, ops::RangeInclusive};
+
+use redis::{AsyncCommands, RedisError, aio::MultiplexedConnection};
+use skillratings::mhth::{MhthConfig, MhthRating, expected_team_vs_environment};
+use tracing::{error, warn};
+use uuid::Uuid;
+
+use super::worker::{bucket, histogram};
+use crate::{
+    namespace,
+    rpc::{Match, matchmaking::FairnessMetrics},
+};
+
+pub const FAIRNESS_METRICS_KEY: &str = "metrics:fairness";
+/// Upper bounds of the skill spread buckets, in rating points
+pub const SPREAD_BUCKETS: [u64; 6] = [1, 2, 5, 10, 20, 40];
+/// Upper bounds of the success probability buckets, in percent
+pub const SUCCESS_BUCKETS: [u64; 9] = [10, 20, 30, 40, 50, 60, 70, 80, 90];
+/// Widest skill spread of a fair match
+pub const OUTLIER_SPREAD: f64 = 20.;
+/// Success probabilities of a fair match
+pub const SUCCESS_RANGE: RangeInclusive<f64> = 0.2..=0.8;
+
+pub fn fairness_metrics_key() -> String {
+    namespace::key(FAIRNESS_METRICS_KEY)
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Fairness {
+    /// Rating gap between the best and worst player
+    pub skill_spread: f64,
+    /// Expected success against the environment, or of the favoured team in PvP
+    pub success: f64,
+}
+
+impl Fairness {
+    /// Fairness of `closed`, rated against the challenge stored on it for PvE matches
+    pub fn of(closed: &Match) -> Self {
+        let ratings = closed
+            .players
+            .iter()
+            .map(|player| player.skillrating.rating);
+        let skill_spread =
+            ratings.clone().fold(f64::MIN, f64::max) - ratings.fold(f64::MAX, f64::min);
+        let success = match closed.squads.as_slice() {
+            [first, second] if closed.is_versus() => {
+                let squad = |ids: &[Uuid]| -> Vec<MhthRating> {
+                    closed
+                        .players
+                        .iter()
+                        .filter(|player| ids.contains(&player.player_id))
+                        .map(|player| player.skillrating)
+                        .collect()
+                };
+                let (first, _) =
+                    expected_team_vs_environment(&squad(first), &squad(second), &MhthConfig::new());
+                first.max(1. - first)
+            }
+            _ => closed
+                .challenge
+                .as_ref()
+                .map_or(0.5, |challenge| challenge.win_probability),
+        };
+
+        Self {
+            skill_spread: skill_spread.max(0.),
+            success,
+        }
+    }
+
+    pub fn is_outlier(&self) -> bool {
+        self.skill_spread > OUTLIER_SPREAD || !SUCCESS_RANGE.contains(&self.success)
+    }
+}
+
+/// Hash fields incremented by a closed match
+fn increments(fairness: &Fairness) -> Vec<(String, u64)> {
+    let spread = fairness.skill_spread.round() as u64;
+    let success_percent = (fairness.success * 100.).round() as u64;
+    vec![
+        ("matches".to_string(), 1),
+        ("outliers".to_string(), u64::from(fairness.is_outlier())),
+        (
+            format!("skill_spread:{}", bucket(&SPREAD_BUCKETS, spread)),
+            1,
+        ),
+        ("skill_spread:sum".to_string(), spread),
+        (
+            format!(
+                "success_percent:{}",
+                bucket(&SUCCESS_BUCKETS, success_percent)
+            ),
+            1,
+        ),
+        ("success_percent:sum".to_string(), success_percent),
+    ]
+}
+
+/// Adds a closed match to the totals
+pub async fn record(
+    conn: &mut MultiplexedConnection,
+    fairness: &Fairness,
+) -> Result<(), RedisError> {
+    let mut pipe = redis::pipe();
+    pipe.atomic();
+    for (field, value) in increments(fairness) {
+        pipe.hincr(fairness_metrics_key(), field, value).ignore();
+    }
+
+    pipe.query_async(conn).await
+}
+
+/// Records the fairness of `closed` and logs it when it is an outlier, failures are logged
+pub async fn observe(conn: &mut MultiplexedConnection, closed: &Match) {
+    let fairness = Fairness::of(closed);
+    if fairness.is_outlier() {
+        warn!(
+            "unfair match `{}`: skill spread {:.1}, success {:.2}",
+            closed.id, fairness.skill_spread, fairness.success
+        );
+    }
+    if let Err(err) = record(conn, &fairness).await {
+        error!(
+            "failed to record the fairness of match `{}`: {err}",
+            closed.id
+        );
+    }
+}
+
+fn totals(fields: &HashMap<String, u64>) -> FairnessMetrics {
+    let field = |name: &str| fields.get(name).copied().unwrap_or_default();
+
+    FairnessMetrics {
+        matches: field("matches"),
+        outliers: field("outliers"),
+        skill_spread: Some(histogram(fields, "skill_spread", &SPREAD_BUCKETS)),
+        success_percent: Some(histogram(fields, "success_percent", &SUCCESS_BUCKETS)),
+    }
+}
+
+/// Totals of every closed match
+pub async fn fairness_metrics(
+    conn: &mut MultiplexedConnection,
+) -> Result<FairnessMetrics, RedisError> {
+    let fields: HashMap<String, u64> = conn.hgetall(fairness_metrics_key()).await?;
+
+    Ok(totals(&fields))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        environment::Challenge,
+        rpc::{QueuedPlayer, matchmaking::Player},
+    };
+
+    fn player(rating: f64) -> QueuedPlayer {
+        (
+            Uuid::new_v4(),
+            Player {
+                region: "EU".to_string(),
+                ..Default::default()
+            },
+            MhthRating {
+                rating,
+                ..Default::default()
+            },
+        )
+            .into()
+    }
+
+    fn challenge(win_probability: f64) -> Challenge {
+        Challenge {
+            win_probability,
+            environment: MhthRating::default(),
+            entities: 0,
+        }
+    }
+
+    #[test]
+    fn spread_and_success_of_a_match() {
+        let host = player(30.);
+        let mut closed = Match::host(&host, &[player(22.), player(26.)]).unwrap();
+
+        closed.challenge = Some(challenge(0.6));
+        let fair = Fairness::of(&closed);
+        closed.challenge = Some(challenge(0.95));
+        let unfair = Fairness::of(&closed);
+
+        assert_eq!(
+            fair,
+            Fairness {
+                skill_spread: 8.,
+                success: 0.6
+            }
+        );
+        assert!(!fair.is_outlier());
+        assert!(unfair.is_outlier());
+    }
+
+    #[test]
+    fn closed_matches_add_up() {
+        let mut fields: HashMap<String, u64> = HashMap::new();
+        for fairness in [
+            Fairness {
+                skill_spread: 8.,
+                success: 0.6,
+            },
+            Fairness {
+                skill_spread: 50.,
+                success: 0.65,
+            },
+        ] {
+            for (field, value) in increments(&fairness) {
+                *fields.entry(field).or_default() += value;
+            }
+        }
+
+        let totals = totals(&fields);
+        let spread = totals.skill_spread.unwrap();
+        let success = totals.success_percent.unwrap();
+        assert_eq!(totals.matches, 2);
+        assert_eq!(totals.outliers, 1);
+        assert_eq!(spread.counts[3], 1);
+        assert_eq!(spread.counts[SPREAD_BUCKETS.len()], 1);
+        assert_eq!(spread.sum, 58);
+        assert_eq!(success.counts[5], 1);
+        assert_eq!(success.counts[6], 1);
+    }
+}
@@ -0,0 +1,27 @@
+use redis::{AsyncCommands, RedisError, aio::MultiplexedConnection};
+
+use crate::{namespace, rpc::matchmaking::QueueMetricsResponse};
+
+pub mod fairness;
+pub mod rpc;
+pub mod worker;
+
+pub const DUPLICATE_JOINS_KEY: &str = "metrics:duplicate_joins";
+
+pub fn duplicate_joins_key() -> String {
+    namespace::key(DUPLICATE_JOINS_KEY)
+}
+
+/// Counters exposed to admins
+pub async fn queue_metrics(
+    conn: &mut MultiplexedConnection,
+) -> Result<QueueMetricsResponse, RedisError> {
+    let duplicate_joins: Option<u64> = conn.get(duplicate_joins_key()).await?;
+
+    Ok(QueueMetricsResponse {
+        duplicate_joins: duplicate_joins.unwrap_or_default(),
+        worker: Some(worker::worker_metrics(conn).await?),
+        fairness: Some(fairness::fairness_metrics(conn).await?),
+        rpc: Some(rpc::rpc_metrics(conn).await?),
+    })
+}
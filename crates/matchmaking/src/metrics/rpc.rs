@@ -0,0 +1,104 @@
+//! Calls of the gRPC methods, recorded by the `metrics` middleware of the server, see
+//! [`crate::rpc::server::middleware`]. Totals are kept in Redis so they add up across replicas.
+
+use std::{collections::HashMap, time::Duration};
+
+use redis::{AsyncCommands, RedisError, aio::MultiplexedConnection};
+
+use crate::{
+    metrics::worker::{DURATION_BUCKETS_MS, bucket, histogram},
+    namespace,
+    rpc::matchmaking::RpcMetrics,
+};
+
+pub const RPC_METRICS_KEY: &str = "metrics:rpc";
+
+pub fn rpc_metrics_key() -> String {
+    namespace::key(RPC_METRICS_KEY)
+}
+
+/// Hash fields incremented by a call of `method` answered with the gRPC status `code`
+fn increments(method: &str, code: i32, duration: Duration) -> Vec<(String, u64)> {
+    let duration_ms = duration.as_millis() as u64;
+    let mut fields = vec![
+        (format!("calls:{method}"), 1),
+        (
+            format!("latency_ms:{}", bucket(&DURATION_BUCKETS_MS, duration_ms)),
+            1,
+        ),
+        ("latency_ms:sum".to_string(), duration_ms),
+    ];
+    if code != 0 {
+        fields.push((format!("errors:{method}"), 1));
+    }
+
+    fields.retain(|(_, value)| *value > 0);
+    fields
+}
+
+/// Adds the call to the totals
+pub async fn record(
+    conn: &mut MultiplexedConnection,
+    method: &str,
+    code: i32,
+    duration: Duration,
+) -> Result<(), RedisError> {
+    let mut pipe = redis::pipe();
+    pipe.atomic();
+    for (field, value) in increments(method, code, duration) {
+        pipe.hincr(rpc_metrics_key(), field, value).ignore();
+    }
+
+    pipe.query_async(conn).await
+}
+
+fn totals(fields: &HashMap<String, u64>) -> RpcMetrics {
+    let by_method = |prefix: &str| {
+        fields
+            .iter()
+            .filter_map(|(name, value)| Some((name.strip_prefix(prefix)?.to_string(), *value)))
+            .collect()
+    };
+
+    RpcMetrics {
+        calls: by_method("calls:"),
+        errors: by_method("errors:"),
+        latency_ms: Some(histogram(fields, "latency_ms", &DURATION_BUCKETS_MS)),
+    }
+}
+
+/// Totals of every recorded call
+pub async fn rpc_metrics(conn: &mut MultiplexedConnection) -> Result<RpcMetrics, RedisError> {
+    let fields: HashMap<String, u64> = conn.hgetall(rpc_metrics_key()).await?;
+
+    Ok(totals(&fields))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn calls_add_up_by_method() {
+        let mut fields: HashMap<String, u64> = HashMap::new();
+        for (method, code, duration_ms) in
+            [("JoinQueue", 0, 8), ("JoinQueue", 8, 3), ("Check", 0, 700)]
+        {
+            for (field, value) in increments(method, code, Duration::from_millis(duration_ms)) {
+                *fields.entry(field).or_default() += value;
+            }
+        }
+
+        let totals = totals(&fields);
+
+        assert_eq!(totals.calls["JoinQueue"], 2);
+        assert_eq!(totals.calls["Check"], 1);
+        assert_eq!(totals.errors["JoinQueue"], 1);
+        assert!(!totals.errors.contains_key("Check"));
+        let latency = totals.latency_ms.unwrap();
+        assert_eq!(latency.count, 3);
+        assert_eq!(latency.sum, 711);
+        assert_eq!(latency.counts[0], 2);
+        assert_eq!(latency.counts[5], 1);
+    }
+}
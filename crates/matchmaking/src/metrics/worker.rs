@@ -0,0 +1,298 @@
+//! Operational metrics of the matchmaking worker. A run counts into [`RunMetrics`], which is
+//! added to the totals in Redis once the run ends, so the totals survive restarts and add up
+//! across replicas. Admins read them next to the queue counters with `GetQueueMetrics`.
+
+use std::{
+    collections::{BTreeMap, HashMap},
+    time::Duration,
+};
+
+use bitcode::DecodeOwned;
+use redis::{AsyncCommands, RedisError, aio::MultiplexedConnection};
+
+use crate::{
+    codec, namespace,
+    rpc::matchmaking::{Histogram, WorkerMetrics},
+};
+
+pub const WORKER_METRICS_KEY: &str = "metrics:worker";
+/// Upper bounds of the run duration buckets, in milliseconds
+pub const DURATION_BUCKETS_MS: [u64; 8] = [10, 50, 100, 250, 500, 1000, 5000, 30000];
+/// Upper bounds of the matches per run buckets
+pub const MATCH_BUCKETS: [u64; 6] = [0, 1, 5, 10, 50, 100];
+
+pub fn worker_metrics_key() -> String {
+    namespace::key(WORKER_METRICS_KEY)
+}
+
+/// Why the worker skipped a queued player
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum SkipReason {
+    JoinMode,
+    Region,
+    Playlist,
+    Experiments,
+    Difficulty,
+    Trust,
+    Smurf,
+    Content,
+    Input,
+    Language,
+    Voice,
+    Skill,
+    Ping,
+    /// The party needs more slots than the match has open
+    PartySize,
+    /// The slots of the match are reserved to the friends of its host
+    FriendsOnly,
+    /// The player key expired while queued
+    Stale,
+    /// The heartbeats of the player stopped while queued
+    Abandoned,
+}
+
+impl SkipReason {
+    pub const fn as_str(self) -> &'static str {
+        match self {
+            Self::JoinMode => "join_mode",
+            Self::Region => "region",
+            Self::Playlist => "playlist",
+            Self::Experiments => "experiments",
+            Self::Difficulty => "difficulty",
+            Self::Trust => "trust",
+            Self::Smurf => "smurf",
+            Self::Content => "content",
+            Self::Input => "input",
+            Self::Language => "language",
+            Self::Voice => "voice",
+            Self::Skill => "skill",
+            Self::Ping => "ping",
+            Self::PartySize => "party_size",
+            Self::FriendsOnly => "friends_only",
+            Self::Stale => "stale",
+            Self::Abandoned => "abandoned",
+        }
+    }
+}
+
+/// Counters of a worker run
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct RunMetrics {
+    /// Hosted matches opened
+    pub matches_created: u64,
+    /// Matches moved to the closed queue, waiting to start
+    pub matches_closed: u64,
+    pub matches_started: u64,
+    /// Queue entries skipped because they failed to decode
+    pub decode_failures: u64,
+    /// Phases failed by Redis and failed health checks after them
+    pub redis_errors: u64,
+    /// Set when the run spent its budget, see [`crate::rpc::worker::budget`]
+    pub budget_exhausted: u64,
+    /// Queues starving past the SLA, counted on every run they starve
+    pub starved_queues: u64,
+    pub skipped: BTreeMap<SkipReason, u64>,
+}
+
+impl RunMetrics {
+    pub fn skip(&mut self, reason: SkipReason, players: u64) {
+        *self.skipped.entry(reason).or_default() += players;
+    }
+
+    /// Adds the counters of a concurrent task of the run
+    pub fn merge(&mut self, other: Self) {
+        self.matches_created += other.matches_created;
+        self.matches_closed += other.matches_closed;
+        self.matches_started += other.matches_started;
+        self.decode_failures += other.decode_failures;
+        self.redis_errors += other.redis_errors;
+        self.budget_exhausted += other.budget_exhausted;
+        self.starved_queues += other.starved_queues;
+        for (reason, players) in other.skipped {
+            self.skip(reason, players);
+        }
+    }
+
+    /// Decodes a queue entry, counting the entries that fail to decode
+    pub fn decode<T: codec::Versioned + DecodeOwned>(&mut self, bits: &[u8]) -> Option<T> {
+        let decoded = codec::decode(bits).ok();
+        if decoded.is_none() {
+            self.decode_failures += 1;
+        }
+
+        decoded
+    }
+}
+
+/// Bucket of `value`, values above the last bound land in the overflow bucket
+pub(super) fn bucket(bounds: &[u64], value: u64) -> usize {
+    bounds
+        .iter()
+        .position(|bound| value <= *bound)
+        .unwrap_or(bounds.len())
+}
+
+/// Hash fields incremented by a run
+fn increments(run: &RunMetrics, duration: Duration) -> Vec<(String, u64)> {
+    let duration_ms = duration.as_millis() as u64;
+    let mut fields = vec![
+        ("runs".to_string(), 1),
+        ("matches_created".to_string(), run.matches_created),
+        ("matches_closed".to_string(), run.matches_closed),
+        ("matches_started".to_string(), run.matches_started),
+        ("decode_failures".to_string(), run.decode_failures),
+        ("redis_errors".to_string(), run.redis_errors),
+        ("budget_exhausted".to_string(), run.budget_exhausted),
+        ("starved_queues".to_string(), run.starved_queues),
+    ];
+    fields.extend(
+        run.skipped
+            .iter()
+            .map(|(reason, players)| (format!("skipped:{}", reason.as_str()), *players)),
+    );
+    let mut observe = |name: &str, bounds: &[u64], value: u64| {
+        fields.push((format!("{name}:{}", bucket(bounds, value)), 1));
+        fields.push((format!("{name}:sum"), value));
+    };
+    observe("run_duration_ms", &DURATION_BUCKETS_MS, duration_ms);
+    observe("created_per_run", &MATCH_BUCKETS, run.matches_created);
+    observe("closed_per_run", &MATCH_BUCKETS, run.matches_closed);
+    observe("started_per_run", &MATCH_BUCKETS, run.matches_started);
+
+    fields.retain(|(_, value)| *value > 0);
+    fields
+}
+
+/// Adds the run to the totals
+pub async fn record(
+    conn: &mut MultiplexedConnection,
+    run: &RunMetrics,
+    duration: Duration,
+) -> Result<(), RedisError> {
+    let mut pipe = redis::pipe();
+    pipe.atomic();
+    for (field, value) in increments(run, duration) {
+        pipe.hincr(worker_metrics_key(), field, value).ignore();
+    }
+
+    pipe.query_async(conn).await
+}
+
+pub(super) fn histogram(fields: &HashMap<String, u64>, name: &str, bounds: &[u64]) -> Histogram {
+    let counts: Vec<u64> = (0..=bounds.len())
+        .map(|index| {
+            fields
+                .get(&format!("{name}:{index}"))
+                .copied()
+                .unwrap_or_default()
+        })
+        .collect();
+
+    Histogram {
+        bounds: bounds.to_vec(),
+        count: counts.iter().sum(),
+        counts,
+        sum: fields
+            .get(&format!("{name}:sum"))
+            .copied()
+            .unwrap_or_default(),
+    }
+}
+
+fn totals(fields: &HashMap<String, u64>) -> WorkerMetrics {
+    let field = |name: &str| fields.get(name).copied().unwrap_or_default();
+
+    WorkerMetrics {
+        runs: field("runs"),
+        matches_created: field("matches_created"),
+        matches_closed: field("matches_closed"),
+        matches_started: field("matches_started"),
+        decode_failures: field("decode_failures"),
+        redis_errors: field("redis_errors"),
+        skipped_players: fields
+            .iter()
+            .filter_map(|(name, value)| Some((name.strip_prefix("skipped:")?.to_string(), *value)))
+            .collect(),
+        run_duration_ms: Some(histogram(fields, "run_duration_ms", &DURATION_BUCKETS_MS)),
+        created_per_run: Some(histogram(fields, "created_per_run", &MATCH_BUCKETS)),
+        closed_per_run: Some(histogram(fields, "closed_per_run", &MATCH_BUCKETS)),
+        started_per_run: Some(histogram(fields, "started_per_run", &MATCH_BUCKETS)),
+        budget_exhausted_runs: field("budget_exhausted"),
+        starved_queues: field("starved_queues"),
+    }
+}
+
+/// Totals of every recorded run
+pub async fn worker_metrics(conn: &mut MultiplexedConnection) -> Result<WorkerMetrics, RedisError> {
+    let fields: HashMap<String, u64> = conn.hgetall(worker_metrics_key()).await?;
+
+    Ok(totals(&fields))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn fields(runs: &[(RunMetrics, Duration)]) -> HashMap<String, u64> {
+        let mut fields = HashMap::new();
+        for (run, duration) in runs {
+            for (field, value) in increments(run, *duration) {
+                *fields.entry(field).or_default() += value;
+            }
+        }
+
+        fields
+    }
+
+    #[test]
+    fn runs_add_up() {
+        let mut busy = RunMetrics {
+            matches_created: 3,
+            matches_closed: 2,
+            matches_started: 2,
+            budget_exhausted: 1,
+            ..Default::default()
+        };
+        busy.skip(SkipReason::Skill, 4);
+        busy.skip(SkipReason::Skill, 1);
+        busy.skip(SkipReason::Stale, 2);
+        let idle = RunMetrics {
+            redis_errors: 1,
+            ..Default::default()
+        };
+
+        let totals = totals(&fields(&[
+            (busy, Duration::from_millis(120)),
+            (idle, Duration::from_millis(5)),
+        ]));
+
+        assert_eq!(totals.runs, 2);
+        assert_eq!(totals.matches_created, 3);
+        assert_eq!(totals.redis_errors, 1);
+        assert_eq!(totals.budget_exhausted_runs, 1);
+        assert_eq!(totals.skipped_players["skill"], 5);
+        assert_eq!(totals.skipped_players["stale"], 2);
+        let durations = totals.run_duration_ms.unwrap();
+        assert_eq!(durations.count, 2);
+        assert_eq!(durations.sum, 125);
+        assert_eq!(durations.counts[0], 1);
+        assert_eq!(durations.counts[3], 1);
+        let started = totals.started_per_run.unwrap();
+        assert_eq!(started.counts[..3], [1, 0, 1]);
+    }
+
+    #[test]
+    fn values_above_the_bounds_overflow() {
+        assert_eq!(bucket(&MATCH_BUCKETS, 0), 0);
+        assert_eq!(bucket(&MATCH_BUCKETS, 5), 2);
+        assert_eq!(bucket(&MATCH_BUCKETS, 101), MATCH_BUCKETS.len());
+    }
+
+    #[test]
+    fn undecodable_entries_are_counted() {
+        let mut run = RunMetrics::default();
+
+        assert_eq!(run.decode::<Vec<String>>(&[0xff, 0x00]), None);
+        assert_eq!(run.decode_failures, 1);
+    }
+}
@@ -3,6 +3,8 @@ use serde::{
     de::{self, DeserializeOwned},
 };
 
+use crate::match_settings::MatchSettings;
+
 #[derive(Debug, Serialize, Deserialize, PartialEq, Eq, Hash)]
 pub struct RpcResponse<T>
 where
@@ -35,6 +37,121 @@ pub struct HealthcheckResponse {
     pub success: bool,
 }
 
+/// Runtime RPC creating an authoritative match, see [`CreateMatchRequest`]
+pub const CREATE_MATCH_PATH: (reqwest::Method, &str) = (
+    reqwest::Method::POST,
+    "/v2/console/api/endpoints/rpc/create_match",
+);
+
+/// Console call of a runtime RPC, `body` is the JSON encoded payload
+#[derive(Debug, Serialize, Deserialize, PartialEq, Eq, Hash)]
+pub struct RpcRequest {
+    pub body: String,
+}
+
+impl RpcRequest {
+    pub fn new<T: Serialize>(payload: &T) -> Result<Self, serde_json::Error> {
+        Ok(Self {
+            body: serde_json::to_string(payload)?,
+        })
+    }
+}
+
+/// Configuration of the match handler, derived from a formed match
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct CreateMatchRequest {
+    pub matchmaker_id: String,
+    pub host_id: String,
+    pub region: String,
+    pub playlist: String,
+    pub difficulty: i32,
+    pub max_players: usize,
+    pub player_ids: Vec<String>,
+    pub experiments: Vec<String>,
+    /// `host:port` of the dedicated server, empty when the match is not allocated one
+    pub game_server_address: String,
+    /// Player ids of each squad, empty when the match plays as one squad
+    pub squads: Vec<Vec<String>>,
+    /// Players per team of a PvP match, `0` for cooperative matches
+    pub team_size: usize,
+    /// Expected success of the players against the environment, `None` for PvP matches
+    pub win_probability: Option<f64>,
+    /// Modifiers of a custom match, `None` plays the default rules
+    pub settings: Option<MatchSettings>,
+    /// `VoiceChat` name shared by the players, `AnyVoice` when they did not agree on one
+    pub voice_chat: String,
+    /// Datacenter hosting the match, its region when the players share no datacenter
+    pub datacenter: String,
+}
+
+#[derive(Debug, Serialize, Deserialize, PartialEq, Eq, Hash)]
+pub struct CreateMatchResponse {
+    pub match_id: String,
+}
+
+/// Runtime RPC writing a leaderboard record, see [`WriteLeaderboardRecordRequest`]
+pub const WRITE_LEADERBOARD_RECORD_PATH: (reqwest::Method, &str) = (
+    reqwest::Method::POST,
+    "/v2/console/api/endpoints/rpc/write_leaderboard_record",
+);
+
+/// Leaderboard operator replacing the score of the owner, ratings may go down
+pub const LEADERBOARD_OPERATOR_SET: &str = "set";
+
+/// Record of `owner_id` written by the runtime on behalf of the server
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq, Hash)]
+pub struct WriteLeaderboardRecordRequest {
+    pub leaderboard_id: String,
+    pub owner_id: String,
+    pub score: i64,
+    pub operator: String,
+}
+
+/// Runtime RPC creating a tournament, see [`CreateTournamentRequest`]
+pub const CREATE_TOURNAMENT_PATH: (reqwest::Method, &str) = (
+    reqwest::Method::POST,
+    "/v2/console/api/endpoints/rpc/create_tournament",
+);
+
+/// Runtime RPC joining players to a tournament, see [`JoinTournamentRequest`]
+pub const JOIN_TOURNAMENT_PATH: (reqwest::Method, &str) = (
+    reqwest::Method::POST,
+    "/v2/console/api/endpoints/rpc/join_tournament",
+);
+
+/// Runtime RPC writing a tournament record, see [`WriteTournamentRecordRequest`]
+pub const WRITE_TOURNAMENT_RECORD_PATH: (reqwest::Method, &str) = (
+    reqwest::Method::POST,
+    "/v2/console/api/endpoints/rpc/write_tournament_record",
+);
+
+/// Leaderboard operator adding to the score of the owner
+pub const LEADERBOARD_OPERATOR_INCR: &str = "incr";
+
+/// Tournament mirroring a bracket, players must join it before records are written
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq, Hash)]
+pub struct CreateTournamentRequest {
+    pub tournament_id: String,
+    pub title: String,
+    pub operator: String,
+    pub join_required: bool,
+    pub duration_secs: u64,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq, Hash)]
+pub struct JoinTournamentRequest {
+    pub tournament_id: String,
+    pub user_ids: Vec<String>,
+}
+
+/// Record of `owner_id` written by the runtime, the runtime applies the tournament operator
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq, Hash)]
+pub struct WriteTournamentRecordRequest {
+    pub tournament_id: String,
+    pub owner_id: String,
+    pub score: i64,
+}
+
 pub const AUTH_PATH: (reqwest::Method, &str) = (reqwest::Method::POST, "/v2/console/authenticate");
 
 #[derive(Debug, Serialize, Deserialize, PartialEq, Eq, Hash)]
@@ -56,6 +173,137 @@ impl Default for AuthResponseBody {
     }
 }
 
+/// Followed by `/{user_id}`
+pub const ACCOUNT_PATH: (reqwest::Method, &str) = (reqwest::Method::GET, "/v2/console/account");
+
+#[derive(Debug, Serialize, Deserialize, PartialEq, Eq, Hash)]
+pub struct ConsoleAccount {
+    pub account: Account,
+}
+
+#[derive(Debug, Serialize, Deserialize, PartialEq, Eq, Hash)]
+pub struct Account {
+    pub user: User,
+}
+
+#[derive(Debug, Serialize, Deserialize, PartialEq, Eq, Hash)]
+pub struct User {
+    pub id: String,
+    pub create_time: Option<chrono::DateTime<chrono::Utc>>,
+}
+
+/// Followed by `/{user_id}/group`
+pub const USER_GROUPS_PATH: (reqwest::Method, &str) = (reqwest::Method::GET, "/v2/console/account");
+
+/// Followed by `/{group_id}/member`
+pub const GROUP_MEMBERS_PATH: (reqwest::Method, &str) = (reqwest::Method::GET, "/v2/console/group");
+
+/// Followed by `/{user_id}/friend`
+pub const FRIENDS_PATH: (reqwest::Method, &str) = (reqwest::Method::GET, "/v2/console/account");
+
+/// Friend state of mutual friends, the other states are pending invites and blocked users
+pub const FRIEND_MUTUAL: i32 = 0;
+
+#[derive(Debug, Default, Serialize, Deserialize, PartialEq, Eq, Hash)]
+pub struct FriendList {
+    #[serde(default)]
+    pub friends: Vec<Friend>,
+}
+
+#[derive(Debug, Serialize, Deserialize, PartialEq, Eq, Hash)]
+pub struct Friend {
+    pub user: User,
+    #[serde(default)]
+    pub state: i32,
+}
+
+/// Group state of users who only requested to join
+pub const GROUP_JOIN_REQUEST: i32 = 3;
+
+#[derive(Debug, Default, Serialize, Deserialize, PartialEq, Eq, Hash)]
+pub struct UserGroupList {
+    #[serde(default)]
+    pub user_groups: Vec<UserGroup>,
+}
+
+#[derive(Debug, Serialize, Deserialize, PartialEq, Eq, Hash)]
+pub struct UserGroup {
+    pub group: Group,
+    pub state: i32,
+}
+
+#[derive(Debug, Serialize, Deserialize, PartialEq, Eq, Hash)]
+pub struct Group {
+    pub id: String,
+    pub name: String,
+    /// JSON encoded metadata, may hold the clan `tag`
+    #[serde(default)]
+    pub metadata: String,
+}
+
+#[derive(Debug, Default, Serialize, Deserialize, PartialEq, Eq, Hash)]
+pub struct GroupUserList {
+    #[serde(default)]
+    pub group_users: Vec<GroupUser>,
+}
+
+#[derive(Debug, Serialize, Deserialize, PartialEq, Eq, Hash)]
+pub struct GroupUser {
+    pub user: User,
+    pub state: i32,
+}
+
+/// Game API authentication creating the account of an unknown custom id, e.g. a simulated player
+pub const AUTHENTICATE_CUSTOM_PATH: (reqwest::Method, &str) = (
+    reqwest::Method::POST,
+    "/v2/account/authenticate/custom?create=true",
+);
+
+#[derive(Debug, Serialize, Deserialize, PartialEq, Eq, Hash)]
+pub struct AuthenticateCustomBody {
+    pub id: String,
+}
+
+/// Game API account of the session user
+pub const SESSION_ACCOUNT_PATH: (reqwest::Method, &str) = (reqwest::Method::GET, "/v2/account");
+
+/// Followed by `/{collection}/{key}/{user_id}`
+pub const STORAGE_WRITE_PATH: (reqwest::Method, &str) =
+    (reqwest::Method::PUT, "/v2/console/storage");
+
+/// Followed by `/{collection}/{key}/{user_id}`
+pub const STORAGE_READ_PATH: (reqwest::Method, &str) =
+    (reqwest::Method::GET, "/v2/console/storage");
+
+/// Stored object, `value` is the JSON encoded object
+#[derive(Debug, Serialize, Deserialize, PartialEq, Eq, Hash)]
+pub struct StorageObject {
+    pub value: String,
+}
+
+/// Storage read permission: only the server can read the object
+pub const STORAGE_NO_READ: i32 = 0;
+/// Storage write permission: only the server can write the object
+pub const STORAGE_NO_WRITE: i32 = 0;
+
+#[derive(Debug, Serialize, Deserialize, PartialEq, Eq, Hash)]
+pub struct WriteStorageObjectBody {
+    pub value: String,
+    pub permission_read: i32,
+    pub permission_write: i32,
+}
+
+impl WriteStorageObjectBody {
+    /// Object only visible and writable by the server
+    pub fn private<T: Serialize>(value: &T) -> Result<Self, serde_json::Error> {
+        Ok(Self {
+            value: serde_json::to_string(value)?,
+            permission_read: STORAGE_NO_READ,
+            permission_write: STORAGE_NO_WRITE,
+        })
+    }
+}
+
 pub const NEW_USER: (reqwest::Method, &str) = (reqwest::Method::POST, "/v2/console/user");
 
 #[derive(Debug, Serialize, Deserialize, PartialEq, Eq, Hash)]
@@ -102,6 +350,29 @@ mod tests {
         assert!(resp.body.success);
     }
 
+    #[test]
+    fn deser_console_account() {
+        let account = r#"{"account": {"user": {"id": "user_id", "create_time": "2025-01-02T10:00:00Z"}}, "disable_time": null}"#;
+
+        let account: ConsoleAccount = serde_json::from_str(account).unwrap();
+
+        assert_eq!(account.account.user.id, "user_id");
+        assert_eq!(
+            account.account.user.create_time.unwrap().to_rfc3339(),
+            "2025-01-02T10:00:00+00:00"
+        );
+    }
+
+    #[test]
+    fn private_storage_object() {
+        let object =
+            WriteStorageObjectBody::private(&HealthcheckResponse { success: true }).unwrap();
+
+        assert_eq!(object.value, "{\"success\":true}");
+        assert_eq!(object.permission_read, STORAGE_NO_READ);
+        assert_eq!(object.permission_write, STORAGE_NO_WRITE);
+    }
+
     #[test]
     pub fn new_admin() {
         let admin =
@@ -1,16 +1,108 @@
+use std::fmt::Write;
+
+use chrono::{DateTime, Utc};
 use crc::{CRC_16_CDMA2000, Crc};
-use tracing::debug;
+use hmac::{Hmac, Mac};
+use sha2::Sha256;
+use tracing::{debug, warn};
+
+use crate::{
+    nakama::Error,
+    secrets::{self, NAKAMA_ENCRYPTION_KEY, NAKAMA_PASSWORD, NAKAMA_SERVER_KEY},
+};
 
-use crate::nakama::{Error, SALTING_KEY};
+/// Secret keying the HMAC-SHA256 derivation of the Nakama password
+pub const PASSWORD_SECRET_VAR: &str = "NAKAMA_PASSWORD_SECRET";
+/// Salt of the accounts registered with the legacy CRC16 derivation, tried after the HMAC one
+/// until [`LEGACY_UNTIL_VAR`]
+pub const LEGACY_SALT_VAR: &str = "NAKAMA_LEGACY_SALT";
+/// End of the deprecation window of the legacy derivation, RFC 3339, e.g. `2027-01-01T00:00:00Z`
+pub const LEGACY_UNTIL_VAR: &str = "NAKAMA_LEGACY_UNTIL";
 
-pub(super) fn get_password(env_password: &str) -> String {
-    let crc = Crc::<u16>::new(&CRC_16_CDMA2000);
-    let mut digest = crc.digest();
-    digest.update(env_password.as_bytes());
-    digest.update(SALTING_KEY.as_bytes());
-    let crc = digest.finalize();
+/// How the Nakama password is derived from `NAKAMA_PASSWORD`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(super) enum PasswordKdf {
+    /// HMAC-SHA256 keyed by the secret, hex encoded
+    Hmac(String),
+    /// Password, salt and their CRC16, kept for existing Nakama accounts
+    Legacy(String),
+}
 
-    format!("{}{}{:X}", env_password, SALTING_KEY, crc)
+pub(super) fn get_password(env_password: &str, kdf: &PasswordKdf) -> String {
+    match kdf {
+        PasswordKdf::Hmac(secret) => {
+            let mut mac = Hmac::<Sha256>::new_from_slice(secret.as_bytes())
+                .expect("HMAC accepts keys of any size");
+            mac.update(env_password.as_bytes());
+
+            mac.finalize()
+                .into_bytes()
+                .iter()
+                .fold(String::with_capacity(64), |mut hex, byte| {
+                    let _ = write!(hex, "{byte:02x}");
+                    hex
+                })
+        }
+        PasswordKdf::Legacy(salt) => {
+            let crc = Crc::<u16>::new(&CRC_16_CDMA2000);
+            let mut digest = crc.digest();
+            digest.update(env_password.as_bytes());
+            digest.update(salt.as_bytes());
+            let crc = digest.finalize();
+
+            format!("{env_password}{salt}{crc:X}")
+        }
+    }
+}
+
+/// Derivations to try in order: the HMAC one, then the legacy one while its deprecation window
+/// is open. A window without an end keeps the legacy derivation.
+pub(super) fn password_kdfs(
+    secret: Option<String>,
+    legacy_salt: Option<String>,
+    legacy_until: Option<DateTime<Utc>>,
+    now: DateTime<Utc>,
+) -> Vec<PasswordKdf> {
+    let legacy = legacy_salt.filter(|_| legacy_until.is_none_or(|until| now < until));
+
+    secret
+        .map(PasswordKdf::Hmac)
+        .into_iter()
+        .chain(legacy.map(PasswordKdf::Legacy))
+        .collect()
+}
+
+/// [`LEGACY_UNTIL_VAR`], `None` when the window has no end
+pub(super) fn get_env_legacy_until() -> Result<Option<DateTime<Utc>>, Error> {
+    std::env::var(LEGACY_UNTIL_VAR)
+        .ok()
+        .map(|until| {
+            DateTime::parse_from_rfc3339(&until)
+                .map(|until| until.with_timezone(&Utc))
+                .map_err(|_| Error::InvalidLegacyUntil(until))
+        })
+        .transpose()
+}
+
+#[allow(clippy::unnecessary_wraps, reason = "Non test feature is Result based")]
+pub(super) fn get_env_password_kdfs() -> Result<Vec<PasswordKdf>, Error> {
+    let kdfs = password_kdfs(
+        secrets::get(PASSWORD_SECRET_VAR),
+        std::env::var(LEGACY_SALT_VAR).ok(),
+        get_env_legacy_until()?,
+        Utc::now(),
+    );
+    match kdfs.first() {
+        Some(PasswordKdf::Legacy(_)) => {
+            warn!(".env `{PASSWORD_SECRET_VAR}` not set. Using the legacy password derivation.");
+            Ok(kdfs)
+        }
+        Some(PasswordKdf::Hmac(_)) => Ok(kdfs),
+        #[cfg(not(test))]
+        None => Err(Error::PasswordSecretNotSet),
+        #[cfg(test)]
+        None => Ok(vec![PasswordKdf::Hmac("secret".to_string())]),
+    }
 }
 
 pub(super) fn get_env_user() -> String {
@@ -25,12 +117,12 @@ pub(super) fn get_env_user() -> String {
 
 #[allow(clippy::unnecessary_wraps, reason = "Non test feature is Result based")]
 pub(super) fn get_env_password() -> Result<String, Error> {
-    match std::env::var("NAKAMA_PASSWORD") {
-        Ok(pswd) => Ok(pswd),
+    match secrets::get(NAKAMA_PASSWORD) {
+        Some(pswd) => Ok(pswd),
         #[cfg(not(test))]
-        Err(_) => Err(Error::PasswordEnvNotSet),
+        None => Err(Error::PasswordEnvNotSet),
         #[cfg(test)]
-        Err(_) => Ok("password".to_string()),
+        None => Ok("password".to_string()),
     }
 }
 
@@ -46,10 +138,12 @@ pub(super) fn get_env_endpoint() -> String {
 }
 
 pub(crate) fn get_env_encryption_key() -> String {
-    match std::env::var("NAKAMA_ENCRYPTION_KEY") {
-        Ok(key) => key,
-        Err(_) => "defaultencryptionkey".to_string(),
-    }
+    secrets::get(NAKAMA_ENCRYPTION_KEY).unwrap_or_else(|| "defaultencryptionkey".to_string())
+}
+
+/// Encryption key before its last rotation, while it is still accepted
+pub(crate) fn get_previous_encryption_key() -> Option<String> {
+    secrets::previous(NAKAMA_ENCRYPTION_KEY)
 }
 
 pub(super) fn get_env_server_key_name() -> String {
@@ -63,9 +157,9 @@ pub(super) fn get_env_server_key_name() -> String {
 }
 
 pub(super) fn get_env_server_key_value() -> String {
-    match std::env::var("NAKAMA_SERVER_KEY") {
-        Ok(url) => url,
-        Err(_) => {
+    match secrets::get(NAKAMA_SERVER_KEY) {
+        Some(key) => key,
+        None => {
             debug!(".env `NAKAMA_SERVER_KEY` not found. Using default.");
             "2b@Mis_MEEP_b7BurfxBkYcgfy@J_zp".to_string()
         }
@@ -74,13 +168,49 @@ pub(super) fn get_env_server_key_value() -> String {
 
 #[cfg(test)]
 mod tests {
-    use super::get_password;
+    use chrono::{Duration, Utc};
+
+    use super::{PasswordKdf, get_password, password_kdfs};
 
     #[test]
-    fn salt_password() {
-        let my_unsalted = "unsaltedPassword";
-        let salted = get_password(my_unsalted);
+    fn derive_password() {
+        let secret = PasswordKdf::Hmac("secret".to_string());
+        let derived = get_password("unsaltedPassword", &secret);
+
+        assert_eq!(derived.len(), 64);
+        assert!(!derived.contains("unsaltedPassword"));
+        assert_eq!(derived, get_password("unsaltedPassword", &secret));
+        assert_ne!(
+            derived,
+            get_password("unsaltedPassword", &PasswordKdf::Hmac("other".to_string()))
+        );
+    }
+
+    #[test]
+    fn legacy_salt_password() {
+        let legacy = PasswordKdf::Legacy("fL@.P47H$P!fmcdc".to_string());
+        let salted = get_password("unsaltedPassword", &legacy);
 
         assert_eq!(salted, "unsaltedPasswordfL@.P47H$P!fmcdcF460");
     }
+
+    #[test]
+    fn legacy_derivation_falls_back_until_the_window_ends() {
+        let now = Utc::now();
+        let secret = || Some("secret".to_string());
+        let salt = || Some("salt".to_string());
+        let hmac = PasswordKdf::Hmac("secret".to_string());
+        let legacy = PasswordKdf::Legacy("salt".to_string());
+
+        assert_eq!(
+            password_kdfs(secret(), salt(), Some(now + Duration::days(1)), now),
+            vec![hmac.clone(), legacy.clone()]
+        );
+        assert_eq!(
+            password_kdfs(secret(), salt(), Some(now - Duration::days(1)), now),
+            vec![hmac]
+        );
+        assert_eq!(password_kdfs(None, salt(), None, now), vec![legacy]);
+        assert!(password_kdfs(None, salt(), Some(now), now).is_empty());
+    }
 }
@@ -1,24 +1,34 @@
 use std::{marker::PhantomData, sync::Arc};
 
+use chrono::{DateTime, Utc};
 use skillratings::mhth::MhthRating;
 use tracing::{debug, error};
 
-use crate::nakama::{
-    endpoints::{
-        AUTH_PATH, AuthRequestBody, AuthResponseBody, CreateUserRequestBody, HEALTHCHECK_PATH,
-        NEW_USER,
-    },
-    helpers::{
-        get_env_encryption_key, get_env_endpoint, get_env_password, get_env_server_key_name,
-        get_env_server_key_value, get_env_user, get_password,
+use crate::{
+    chaos,
+    nakama::{
+        endpoints::{
+            ACCOUNT_PATH, AUTH_PATH, AUTHENTICATE_CUSTOM_PATH, AuthRequestBody, AuthResponseBody,
+            AuthenticateCustomBody, CREATE_MATCH_PATH, CREATE_TOURNAMENT_PATH, CreateMatchRequest,
+            CreateMatchResponse, CreateTournamentRequest, CreateUserRequestBody, FRIENDS_PATH,
+            Friend, FriendList, GROUP_MEMBERS_PATH, GroupUser, GroupUserList, HEALTHCHECK_PATH,
+            JOIN_TOURNAMENT_PATH, JoinTournamentRequest, NEW_USER, RpcRequest,
+            SESSION_ACCOUNT_PATH, STORAGE_READ_PATH, STORAGE_WRITE_PATH, StorageObject,
+            USER_GROUPS_PATH, UserGroup, UserGroupList, WRITE_LEADERBOARD_RECORD_PATH,
+            WRITE_TOURNAMENT_RECORD_PATH, WriteLeaderboardRecordRequest, WriteStorageObjectBody,
+            WriteTournamentRecordRequest,
+        },
+        helpers::{
+            PasswordKdf, get_env_encryption_key, get_env_endpoint, get_env_legacy_until,
+            get_env_password, get_env_password_kdfs, get_env_server_key_name,
+            get_env_server_key_value, get_env_user, get_password,
+        },
     },
 };
 
 pub mod endpoints;
 pub mod helpers;
 
-const SALTING_KEY: &str = "fL@.P47H$P!fmcdc";
-
 #[derive(Debug, Clone)]
 pub struct DefaultNakama;
 #[derive(Debug, Clone)]
@@ -32,10 +42,20 @@ pub struct Unauthenticated;
 pub enum Error {
     #[error(".env `NAKAMA_PASSWORD` not set")]
     PasswordEnvNotSet,
+    #[error(".env `NAKAMA_PASSWORD_SECRET` not set, or `NAKAMA_LEGACY_SALT` for existing accounts")]
+    PasswordSecretNotSet,
+    #[error(".env `NAKAMA_LEGACY_UNTIL` is not an RFC 3339 date: {0}")]
+    InvalidLegacyUntil(String),
+    #[error("the legacy password derivation was deprecated at {0}")]
+    LegacyWindowClosed(DateTime<Utc>),
     #[error("request failed: {0}")]
     RequestFailed(#[from] reqwest::Error),
     #[error(transparent)]
     Serde(#[from] serde_json::Error),
+    /// Failure injected by [`crate::chaos`]
+    #[cfg(feature = "chaos")]
+    #[error("injected failure")]
+    Injected,
 }
 
 #[derive(Debug, Clone, Default, PartialEq, Eq)]
@@ -52,6 +72,9 @@ pub struct NakamaClient<T = DefaultNakama> {
     pub(crate) server_key_value: String,
     /// Session Encryption Key
     pub(crate) encryption_key: String,
+    /// End of the deprecation window of the legacy derivation the password was derived with,
+    /// see [`helpers::LEGACY_UNTIL_VAR`]
+    pub(crate) legacy_until: Option<DateTime<Utc>>,
     pub(crate) _state: PhantomData<T>,
 }
 
@@ -62,7 +85,12 @@ impl NakamaClient<DefaultNakama> {
         let server_key_name = get_env_server_key_name();
         let server_key_value = get_env_server_key_value();
         let env_password = get_env_password()?;
-        let password = get_password(&env_password);
+        let kdf = &get_env_password_kdfs()?[0];
+        let password = get_password(&env_password, kdf);
+        let legacy_until = match kdf {
+            PasswordKdf::Legacy(_) => get_env_legacy_until()?,
+            PasswordKdf::Hmac(_) => None,
+        };
         let encryption_key = get_env_encryption_key();
 
         Ok(NakamaClient {
@@ -72,6 +100,7 @@ impl NakamaClient<DefaultNakama> {
             server_key_name,
             server_key_value,
             encryption_key,
+            legacy_until,
             _state: PhantomData::<Unauthenticated>,
             token: None,
         })
@@ -114,12 +143,28 @@ impl NakamaClient<NoUserRegistered> {
             server_key_name: self.server_key_name,
             server_key_value: self.server_key_value,
             encryption_key: self.encryption_key,
+            legacy_until: self.legacy_until,
             _state: PhantomData::<Unauthenticated>,
         })
     }
 }
 
 impl NakamaClient<Unauthenticated> {
+    /// Client of an account registered with the legacy password derivation, while its
+    /// deprecation window is open and the HMAC derivation is the preferred one
+    pub fn legacy_fallback(&self) -> Result<Option<Self>, Error> {
+        let env_password = get_env_password()?;
+        let kdfs = get_env_password_kdfs()?;
+
+        let legacy_until = get_env_legacy_until()?;
+
+        Ok(kdfs.get(1).map(|legacy| Self {
+            password: get_password(&env_password, legacy),
+            legacy_until,
+            ..self.clone()
+        }))
+    }
+
     pub async fn authenticate(
         self,
         http_client: &reqwest::Client,
@@ -150,21 +195,40 @@ impl NakamaClient<Unauthenticated> {
             server_key_name: self.server_key_name,
             server_key_value: self.server_key_value,
             encryption_key: self.encryption_key,
+            legacy_until: self.legacy_until,
             _state: PhantomData::<Authenticated>,
         })
     }
 }
 
 impl NakamaClient<Authenticated> {
+    /// Session token, rejected once the deprecation window of the legacy password derivation
+    /// it was authenticated with ended
+    fn token(&self) -> Result<&str, Error> {
+        if let Some(until) = self.legacy_until.filter(|until| Utc::now() >= *until) {
+            return Err(Error::LegacyWindowClosed(until));
+        }
+
+        Ok(self
+            .token
+            .as_deref()
+            .expect("Client is already authenticated"))
+    }
+
     pub async fn get_skill_rating(
         &self,
         http_client: Arc<reqwest::Client>,
         _player_id: &str,
     ) -> Result<MhthRating, Error> {
-        let token = self
-            .token
-            .as_ref()
-            .expect("Client is already authenticated");
+        chaos::nakama().await?;
+        self.healthcheck(&http_client).await?;
+
+        Ok(MhthRating::default())
+    }
+
+    /// Whether the Nakama healthcheck RPC succeeds
+    pub async fn healthcheck(&self, http_client: &reqwest::Client) -> Result<bool, Error> {
+        let token = self.token()?;
 
         let response: endpoints::RpcResponse<endpoints::HealthcheckResponse> = http_client
             .request(
@@ -180,7 +244,359 @@ impl NakamaClient<Authenticated> {
             .inspect_err(|err| error!("Response Error: {err:?}"))?;
         debug!("helthcheck: {}", response.body.success);
 
-        Ok(MhthRating::default())
+        Ok(response.body.success)
+    }
+
+    /// Creation time of the player account
+    pub async fn get_account_created_at(
+        &self,
+        http_client: Arc<reqwest::Client>,
+        player_id: &str,
+    ) -> Result<Option<chrono::DateTime<chrono::Utc>>, Error> {
+        chaos::nakama().await?;
+        let token = self.token()?;
+
+        let response: endpoints::ConsoleAccount = http_client
+            .request(
+                ACCOUNT_PATH.0,
+                format!("{}{}/{player_id}", self.url, ACCOUNT_PATH.1),
+            )
+            .bearer_auth(token)
+            .send()
+            .await
+            .inspect_err(|err| error!("Request Error: {err:?}"))?
+            .json()
+            .await
+            .inspect_err(|err| error!("Response Error: {err:?}"))?;
+
+        Ok(response.account.user.create_time)
+    }
+
+    /// Friends of `user_id`, including pending invites and blocked users
+    pub async fn get_friends(
+        &self,
+        http_client: Arc<reqwest::Client>,
+        user_id: &str,
+    ) -> Result<Vec<Friend>, Error> {
+        chaos::nakama().await?;
+        let token = self.token()?;
+
+        let response: FriendList = http_client
+            .request(
+                FRIENDS_PATH.0,
+                format!("{}{}/{user_id}/friend", self.url, FRIENDS_PATH.1),
+            )
+            .bearer_auth(token)
+            .send()
+            .await
+            .inspect_err(|err| error!("Request Error: {err:?}"))?
+            .error_for_status()
+            .inspect_err(|err| error!("Friends Error: {err:?}"))?
+            .json()
+            .await
+            .inspect_err(|err| error!("Response Error: {err:?}"))?;
+
+        Ok(response.friends)
+    }
+
+    /// Groups of `user_id`, including the ones it only requested to join
+    pub async fn get_user_groups(
+        &self,
+        http_client: Arc<reqwest::Client>,
+        user_id: &str,
+    ) -> Result<Vec<UserGroup>, Error> {
+        chaos::nakama().await?;
+        let token = self.token()?;
+
+        let response: UserGroupList = http_client
+            .request(
+                USER_GROUPS_PATH.0,
+                format!("{}{}/{user_id}/group", self.url, USER_GROUPS_PATH.1),
+            )
+            .bearer_auth(token)
+            .send()
+            .await
+            .inspect_err(|err| error!("Request Error: {err:?}"))?
+            .error_for_status()
+            .inspect_err(|err| error!("Groups Error: {err:?}"))?
+            .json()
+            .await
+            .inspect_err(|err| error!("Response Error: {err:?}"))?;
+
+        Ok(response.user_groups)
+    }
+
+    /// Users of the group `group_id`, including the ones who only requested to join
+    pub async fn get_group_members(
+        &self,
+        http_client: Arc<reqwest::Client>,
+        group_id: &str,
+    ) -> Result<Vec<GroupUser>, Error> {
+        chaos::nakama().await?;
+        let token = self.token()?;
+
+        let response: GroupUserList = http_client
+            .request(
+                GROUP_MEMBERS_PATH.0,
+                format!("{}{}/{group_id}/member", self.url, GROUP_MEMBERS_PATH.1),
+            )
+            .bearer_auth(token)
+            .send()
+            .await
+            .inspect_err(|err| error!("Request Error: {err:?}"))?
+            .error_for_status()
+            .inspect_err(|err| error!("Group Members Error: {err:?}"))?
+            .json()
+            .await
+            .inspect_err(|err| error!("Response Error: {err:?}"))?;
+
+        Ok(response.group_users)
+    }
+
+    /// Creates the authoritative match of `config`, returns its Nakama match id
+    pub async fn create_match(
+        &self,
+        http_client: Arc<reqwest::Client>,
+        config: &CreateMatchRequest,
+    ) -> Result<String, Error> {
+        chaos::nakama().await?;
+        let token = self.token()?;
+        let body = serde_json::to_string(&RpcRequest::new(config)?)?;
+
+        let response: endpoints::RpcResponse<CreateMatchResponse> = http_client
+            .request(
+                CREATE_MATCH_PATH.0,
+                format!("{}{}", self.url, CREATE_MATCH_PATH.1),
+            )
+            .bearer_auth(token)
+            .body(body)
+            .send()
+            .await
+            .inspect_err(|err| error!("Request Error: {err:?}"))?
+            .error_for_status()
+            .inspect_err(|err| error!("Create Match Error: {err:?}"))?
+            .json()
+            .await
+            .inspect_err(|err| error!("Response Error: {err:?}"))?;
+
+        Ok(response.body.match_id)
+    }
+
+    /// Reads the storage object of `user_id`, `None` when it was never written
+    pub async fn read_storage<T: serde::de::DeserializeOwned>(
+        &self,
+        http_client: Arc<reqwest::Client>,
+        collection: &str,
+        key: &str,
+        user_id: &str,
+    ) -> Result<Option<T>, Error> {
+        chaos::nakama().await?;
+        let token = self.token()?;
+
+        let response = http_client
+            .request(
+                STORAGE_READ_PATH.0,
+                format!(
+                    "{}{}/{collection}/{key}/{user_id}",
+                    self.url, STORAGE_READ_PATH.1
+                ),
+            )
+            .bearer_auth(token)
+            .send()
+            .await
+            .inspect_err(|err| error!("Request Error: {err:?}"))?;
+        if response.status() == reqwest::StatusCode::NOT_FOUND {
+            return Ok(None);
+        }
+        let object: StorageObject = response
+            .error_for_status()
+            .inspect_err(|err| error!("Storage Error: {err:?}"))?
+            .json()
+            .await
+            .inspect_err(|err| error!("Response Error: {err:?}"))?;
+
+        Ok(Some(serde_json::from_str(&object.value)?))
+    }
+
+    /// Creates the game account of `custom_id` through the game API at `api_url`, returns the
+    /// account user id. Authenticating an existing custom id returns its account.
+    pub async fn create_player(
+        &self,
+        http_client: Arc<reqwest::Client>,
+        api_url: &str,
+        custom_id: &str,
+    ) -> Result<String, Error> {
+        let body = serde_json::to_string(&AuthenticateCustomBody {
+            id: custom_id.to_string(),
+        })?;
+
+        let session: AuthResponseBody = http_client
+            .request(
+                AUTHENTICATE_CUSTOM_PATH.0,
+                format!("{api_url}{}", AUTHENTICATE_CUSTOM_PATH.1),
+            )
+            .basic_auth(&self.server_key_value, Some(""))
+            .body(body)
+            .send()
+            .await
+            .inspect_err(|err| error!("Request Error: {err:?}"))?
+            .error_for_status()
+            .inspect_err(|err| error!("Authenticate Error: {err:?}"))?
+            .json()
+            .await
+            .inspect_err(|err| error!("Response Error: {err:?}"))?;
+        let account: endpoints::Account = http_client
+            .request(
+                SESSION_ACCOUNT_PATH.0,
+                format!("{api_url}{}", SESSION_ACCOUNT_PATH.1),
+            )
+            .bearer_auth(session.token)
+            .send()
+            .await
+            .inspect_err(|err| error!("Request Error: {err:?}"))?
+            .error_for_status()
+            .inspect_err(|err| error!("Account Error: {err:?}"))?
+            .json()
+            .await
+            .inspect_err(|err| error!("Response Error: {err:?}"))?;
+
+        Ok(account.user.id)
+    }
+
+    /// Writes a server owned storage object of `user_id`
+    pub async fn write_storage<T: serde::Serialize>(
+        &self,
+        http_client: Arc<reqwest::Client>,
+        collection: &str,
+        key: &str,
+        user_id: &str,
+        value: &T,
+    ) -> Result<(), Error> {
+        chaos::nakama().await?;
+        let token = self.token()?;
+        let body = serde_json::to_string(&WriteStorageObjectBody::private(value)?)?;
+
+        http_client
+            .request(
+                STORAGE_WRITE_PATH.0,
+                format!(
+                    "{}{}/{collection}/{key}/{user_id}",
+                    self.url, STORAGE_WRITE_PATH.1
+                ),
+            )
+            .bearer_auth(token)
+            .body(body)
+            .send()
+            .await
+            .inspect_err(|err| error!("Request Error: {err:?}"))?
+            .error_for_status()
+            .inspect_err(|err| error!("Storage Error: {err:?}"))?;
+
+        Ok(())
+    }
+
+    /// Writes the leaderboard record of `record.owner_id` through the runtime RPC
+    pub async fn write_leaderboard_record(
+        &self,
+        http_client: Arc<reqwest::Client>,
+        record: &WriteLeaderboardRecordRequest,
+    ) -> Result<(), Error> {
+        chaos::nakama().await?;
+        let token = self.token()?;
+        let body = serde_json::to_string(&RpcRequest::new(record)?)?;
+
+        http_client
+            .request(
+                WRITE_LEADERBOARD_RECORD_PATH.0,
+                format!("{}{}", self.url, WRITE_LEADERBOARD_RECORD_PATH.1),
+            )
+            .bearer_auth(token)
+            .body(body)
+            .send()
+            .await
+            .inspect_err(|err| error!("Request Error: {err:?}"))?
+            .error_for_status()
+            .inspect_err(|err| error!("Leaderboard Error: {err:?}"))?;
+
+        Ok(())
+    }
+
+    /// Creates the tournament through the runtime RPC, creating an existing tournament is a no-op
+    pub async fn create_tournament(
+        &self,
+        http_client: Arc<reqwest::Client>,
+        tournament: &CreateTournamentRequest,
+    ) -> Result<(), Error> {
+        chaos::nakama().await?;
+        let token = self.token()?;
+        let body = serde_json::to_string(&RpcRequest::new(tournament)?)?;
+
+        http_client
+            .request(
+                CREATE_TOURNAMENT_PATH.0,
+                format!("{}{}", self.url, CREATE_TOURNAMENT_PATH.1),
+            )
+            .bearer_auth(token)
+            .body(body)
+            .send()
+            .await
+            .inspect_err(|err| error!("Request Error: {err:?}"))?
+            .error_for_status()
+            .inspect_err(|err| error!("Create Tournament Error: {err:?}"))?;
+
+        Ok(())
+    }
+
+    /// Joins `join.user_ids` to the tournament through the runtime RPC
+    pub async fn join_tournament(
+        &self,
+        http_client: Arc<reqwest::Client>,
+        join: &JoinTournamentRequest,
+    ) -> Result<(), Error> {
+        chaos::nakama().await?;
+        let token = self.token()?;
+        let body = serde_json::to_string(&RpcRequest::new(join)?)?;
+
+        http_client
+            .request(
+                JOIN_TOURNAMENT_PATH.0,
+                format!("{}{}", self.url, JOIN_TOURNAMENT_PATH.1),
+            )
+            .bearer_auth(token)
+            .body(body)
+            .send()
+            .await
+            .inspect_err(|err| error!("Request Error: {err:?}"))?
+            .error_for_status()
+            .inspect_err(|err| error!("Join Tournament Error: {err:?}"))?;
+
+        Ok(())
+    }
+
+    /// Writes the tournament record of `record.owner_id` through the runtime RPC
+    pub async fn write_tournament_record(
+        &self,
+        http_client: Arc<reqwest::Client>,
+        record: &WriteTournamentRecordRequest,
+    ) -> Result<(), Error> {
+        chaos::nakama().await?;
+        let token = self.token()?;
+        let body = serde_json::to_string(&RpcRequest::new(record)?)?;
+
+        http_client
+            .request(
+                WRITE_TOURNAMENT_RECORD_PATH.0,
+                format!("{}{}", self.url, WRITE_TOURNAMENT_RECORD_PATH.1),
+            )
+            .bearer_auth(token)
+            .body(body)
+            .send()
+            .await
+            .inspect_err(|err| error!("Request Error: {err:?}"))?
+            .error_for_status()
+            .inspect_err(|err| error!("Tournament Record Error: {err:?}"))?;
+
+        Ok(())
     }
 }
 
@@ -190,6 +606,7 @@ mod tests {
     use serde_json::json;
 
     use super::*;
+    use crate::match_settings::MatchSettings;
 
     #[tokio::test]
     async fn auth_nakama_client() {
@@ -229,6 +646,20 @@ mod tests {
         assert_eq!(client.token.unwrap(), "my-random-token");
     }
 
+    #[tokio::test]
+    async fn legacy_sessions_end_with_their_window() {
+        let until = Utc::now() - chrono::Duration::seconds(1);
+        let client = NakamaClient {
+            legacy_until: Some(until),
+            ..auth_client(7351)
+        };
+
+        assert!(matches!(
+            client.healthcheck(&reqwest::Client::new()).await,
+            Err(Error::LegacyWindowClosed(closed)) if closed == until
+        ));
+    }
+
     #[tokio::test]
     async fn get_skill_rating_with_auth() {
         let server = MockServer::start_async().await;
@@ -258,6 +689,363 @@ mod tests {
         assert_eq!(rating.rating, 25.);
     }
 
+    #[tokio::test]
+    async fn get_account_created_at() {
+        let server = MockServer::start_async().await;
+        let port = server.address().port();
+        let client = auth_client(port);
+
+        let mock = server
+            .mock_async(|when, then| {
+                when.method(GET).path("/v2/console/account/player_id");
+                then.status(200)
+                    .header("content-type", "application/json")
+                    .json_body(json!({
+                        "account": {"user": {"id": "player_id", "create_time": "2025-01-02T10:00:00Z"}}
+                    }));
+            })
+            .await;
+        let http_client = Arc::new(reqwest::Client::new());
+        let created_at = client
+            .get_account_created_at(http_client, "player_id")
+            .await
+            .unwrap();
+
+        mock.assert_async().await;
+        assert_eq!(
+            created_at.unwrap().to_rfc3339(),
+            "2025-01-02T10:00:00+00:00"
+        );
+    }
+
+    #[tokio::test]
+    async fn get_groups_and_members() {
+        let server = MockServer::start_async().await;
+        let port = server.address().port();
+        let client = auth_client(port);
+
+        let groups = server
+            .mock_async(|when, then| {
+                when.method(GET).path("/v2/console/account/player_id/group");
+                then.status(200)
+                    .header("content-type", "application/json")
+                    .json_body(json!({
+                        "user_groups": [{
+                            "group": {"id": "group_id", "name": "Hunters", "metadata": "{\"tag\": \"HNT\"}"},
+                            "state": 2
+                        }]
+                    }));
+            })
+            .await;
+        let members = server
+            .mock_async(|when, then| {
+                when.method(GET).path("/v2/console/group/group_id/member");
+                then.status(200)
+                    .header("content-type", "application/json")
+                    .json_body(json!({
+                        "group_users": [{"user": {"id": "player_id"}, "state": 0}]
+                    }));
+            })
+            .await;
+        let http_client = Arc::new(reqwest::Client::new());
+        let user_groups = client
+            .get_user_groups(http_client.clone(), "player_id")
+            .await
+            .unwrap();
+        let group_users = client
+            .get_group_members(http_client, "group_id")
+            .await
+            .unwrap();
+
+        groups.assert_async().await;
+        members.assert_async().await;
+        assert_eq!(user_groups[0].group.name, "Hunters");
+        assert_eq!(group_users[0].user.id, "player_id");
+    }
+
+    #[tokio::test]
+    async fn get_friends() {
+        let server = MockServer::start_async().await;
+        let port = server.address().port();
+        let client = auth_client(port);
+
+        let mock = server
+            .mock_async(|when, then| {
+                when.method(GET)
+                    .path("/v2/console/account/player_id/friend");
+                then.status(200)
+                    .header("content-type", "application/json")
+                    .json_body(json!({
+                        "friends": [{"user": {"id": "friend_id"}, "state": 0}]
+                    }));
+            })
+            .await;
+        let friends = client
+            .get_friends(Arc::new(reqwest::Client::new()), "player_id")
+            .await
+            .unwrap();
+
+        mock.assert_async().await;
+        assert_eq!(friends[0].user.id, "friend_id");
+        assert_eq!(friends[0].state, 0);
+    }
+
+    #[tokio::test]
+    async fn write_storage_object() {
+        let server = MockServer::start_async().await;
+        let port = server.address().port();
+        let client = auth_client(port);
+
+        let mock = server
+            .mock_async(|when, then| {
+                when.method(PUT)
+                    .path("/v2/console/storage/collection/key/user_id")
+                    .json_body(json!({
+                        "value": "{\"success\":true}",
+                        "permission_read": 0,
+                        "permission_write": 0
+                    }));
+                then.status(200)
+                    .header("content-type", "application/json")
+                    .json_body(json!({}));
+            })
+            .await;
+        let http_client = Arc::new(reqwest::Client::new());
+        client
+            .write_storage(
+                http_client,
+                "collection",
+                "key",
+                "user_id",
+                &endpoints::HealthcheckResponse { success: true },
+            )
+            .await
+            .unwrap();
+
+        mock.assert_async().await;
+    }
+
+    #[tokio::test]
+    async fn read_storage_object() {
+        let server = MockServer::start_async().await;
+        let port = server.address().port();
+        let client = auth_client(port);
+
+        let found = server
+            .mock_async(|when, then| {
+                when.method(GET)
+                    .path("/v2/console/storage/collection/key/user_id");
+                then.status(200)
+                    .header("content-type", "application/json")
+                    .json_body(json!({ "value": "{\"success\":true}" }));
+            })
+            .await;
+        let missing = server
+            .mock_async(|when, then| {
+                when.method(GET)
+                    .path("/v2/console/storage/collection/key/missing");
+                then.status(404);
+            })
+            .await;
+        let http_client = Arc::new(reqwest::Client::new());
+
+        let object: Option<endpoints::HealthcheckResponse> = client
+            .read_storage(http_client.clone(), "collection", "key", "user_id")
+            .await
+            .unwrap();
+        let absent: Option<endpoints::HealthcheckResponse> = client
+            .read_storage(http_client, "collection", "key", "missing")
+            .await
+            .unwrap();
+
+        found.assert_async().await;
+        missing.assert_async().await;
+        assert_eq!(
+            object,
+            Some(endpoints::HealthcheckResponse { success: true })
+        );
+        assert_eq!(absent, None);
+    }
+
+    #[tokio::test]
+    async fn create_match() {
+        let server = MockServer::start_async().await;
+        let port = server.address().port();
+        let client = auth_client(port);
+        let config = CreateMatchRequest {
+            matchmaker_id: "matchmaker_id".to_string(),
+            host_id: "host_id".to_string(),
+            region: "CAN".to_string(),
+            playlist: String::new(),
+            difficulty: 2,
+            max_players: 4,
+            player_ids: vec!["host_id".to_string()],
+            experiments: Vec::new(),
+            game_server_address: "10.0.0.7:7654".to_string(),
+            squads: Vec::new(),
+            team_size: 0,
+            win_probability: Some(0.5),
+            settings: Some(MatchSettings {
+                friendly_fire: true,
+                mutators: vec!["low_gravity".to_string()],
+                mission_seed: Some(42),
+            }),
+            voice_chat: "MicRequired".to_string(),
+            datacenter: "eu-west".to_string(),
+        };
+
+        let mock = server
+            .mock_async(|when, then| {
+                when.method(POST)
+                    .path("/v2/console/api/endpoints/rpc/create_match")
+                    .json_body(json!({ "body": serde_json::to_string(&config).unwrap() }));
+                then.status(200)
+                    .header("content-type", "application/json")
+                    .json_body(
+                        json!({"body": "{\"match_id\": \"nakama.match\"}", "error_message": ""}),
+                    );
+            })
+            .await;
+        let match_id = client
+            .create_match(Arc::new(reqwest::Client::new()), &config)
+            .await
+            .unwrap();
+
+        mock.assert_async().await;
+        assert_eq!(match_id, "nakama.match");
+    }
+
+    #[tokio::test]
+    async fn write_leaderboard_record() {
+        let server = MockServer::start_async().await;
+        let client = auth_client(server.address().port());
+        let record = WriteLeaderboardRecordRequest {
+            leaderboard_id: "ranked-s3".to_string(),
+            owner_id: "user_id".to_string(),
+            score: 1250,
+            operator: endpoints::LEADERBOARD_OPERATOR_SET.to_string(),
+        };
+
+        let mock = server
+            .mock_async(|when, then| {
+                when.method(POST)
+                    .path("/v2/console/api/endpoints/rpc/write_leaderboard_record")
+                    .header("authorization", "Bearer super_random_token")
+                    .json_body(json!({ "body": serde_json::to_string(&record).unwrap() }));
+                then.status(200)
+                    .header("content-type", "application/json")
+                    .json_body(json!({"body": "{}", "error_message": ""}));
+            })
+            .await;
+        client
+            .write_leaderboard_record(Arc::new(reqwest::Client::new()), &record)
+            .await
+            .unwrap();
+
+        mock.assert_async().await;
+    }
+
+    #[tokio::test]
+    async fn tournament_rpcs() {
+        let server = MockServer::start_async().await;
+        let client = auth_client(server.address().port());
+        let http_client = Arc::new(reqwest::Client::new());
+        let tournament = CreateTournamentRequest {
+            tournament_id: "weekend-cup".to_string(),
+            title: "Weekend cup".to_string(),
+            operator: endpoints::LEADERBOARD_OPERATOR_INCR.to_string(),
+            join_required: true,
+            duration_secs: 3600,
+        };
+        let join = JoinTournamentRequest {
+            tournament_id: "weekend-cup".to_string(),
+            user_ids: vec!["host".to_string(), "guest".to_string()],
+        };
+        let record = WriteTournamentRecordRequest {
+            tournament_id: "weekend-cup".to_string(),
+            owner_id: "host".to_string(),
+            score: 1,
+        };
+
+        let mut mocks = Vec::new();
+        for (rpc, body) in [
+            ("create_tournament", serde_json::to_string(&tournament)),
+            ("join_tournament", serde_json::to_string(&join)),
+            ("write_tournament_record", serde_json::to_string(&record)),
+        ] {
+            let body = body.unwrap();
+            mocks.push(
+                server
+                    .mock_async(|when, then| {
+                        when.method(POST)
+                            .path(format!("/v2/console/api/endpoints/rpc/{rpc}"))
+                            .json_body(json!({ "body": body }));
+                        then.status(200)
+                            .header("content-type", "application/json")
+                            .json_body(json!({"body": "{}", "error_message": ""}));
+                    })
+                    .await,
+            );
+        }
+        client
+            .create_tournament(http_client.clone(), &tournament)
+            .await
+            .unwrap();
+        client
+            .join_tournament(http_client.clone(), &join)
+            .await
+            .unwrap();
+        client
+            .write_tournament_record(http_client, &record)
+            .await
+            .unwrap();
+
+        for mock in mocks {
+            mock.assert_async().await;
+        }
+    }
+
+    #[tokio::test]
+    async fn create_player() {
+        let server = MockServer::start_async().await;
+        let port = server.address().port();
+        let client = auth_client(port);
+
+        let authenticate = server
+            .mock_async(|when, then| {
+                when.method(POST)
+                    .path("/v2/account/authenticate/custom")
+                    .query_param("create", "true")
+                    .json_body(json!({ "id": "sim-1" }));
+                then.status(200)
+                    .header("content-type", "application/json")
+                    .json_body(json!({ "token": "session", "refresh_token": "refresh" }));
+            })
+            .await;
+        let account = server
+            .mock_async(|when, then| {
+                when.method(GET)
+                    .path("/v2/account")
+                    .header("authorization", "Bearer session");
+                then.status(200)
+                    .header("content-type", "application/json")
+                    .json_body(json!({ "user": { "id": "user_id", "create_time": null } }));
+            })
+            .await;
+        let user_id = client
+            .create_player(
+                Arc::new(reqwest::Client::new()),
+                &format!("http://127.0.0.1:{port}"),
+                "sim-1",
+            )
+            .await
+            .unwrap();
+
+        authenticate.assert_async().await;
+        account.assert_async().await;
+        assert_eq!(user_id, "user_id");
+    }
+
     pub fn auth_client(port: u16) -> NakamaClient<Authenticated> {
         NakamaClient {
             username: "username".to_string(),
@@ -267,6 +1055,7 @@ mod tests {
             server_key_name: "defaultkey".to_string(),
             server_key_value: "server_key".to_string(),
             encryption_key: "encryption_key".to_string(),
+            legacy_until: None,
             _state: PhantomData,
         }
     }
@@ -0,0 +1,56 @@
+//! Prefix of every Redis key, so staging and production, or multiple game
+//! shards, can share a Redis cluster.
+
+use std::{fmt::Display, sync::OnceLock};
+
+/// Env var with the namespace, keys are not prefixed when unset
+pub const NAMESPACE_VAR: &str = "REDIS_NAMESPACE";
+
+static NAMESPACE: OnceLock<String> = OnceLock::new();
+
+/// Sets the namespace once, before any key is built.
+/// Returns `false` when a namespace was already set.
+pub fn set(namespace: impl Into<String>) -> bool {
+    NAMESPACE.set(namespace.into()).is_ok()
+}
+
+/// Reads the namespace from [`NAMESPACE_VAR`]
+pub fn set_from_env() -> bool {
+    std::env::var(NAMESPACE_VAR).is_ok_and(set)
+}
+
+pub fn get() -> &'static str {
+    NAMESPACE.get().map_or("", String::as_str)
+}
+
+/// Redis key inside the current namespace
+pub fn key(key: impl Display) -> String {
+    prefixed(get(), key)
+}
+
+fn prefixed(namespace: &str, key: impl Display) -> String {
+    if namespace.is_empty() {
+        key.to_string()
+    } else {
+        format!("{namespace}:{key}")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use uuid::Uuid;
+
+    use super::*;
+
+    #[test]
+    fn prefixed_keys() {
+        let player_id = Uuid::new_v4();
+
+        assert_eq!(prefixed("", "matches:closed"), "matches:closed");
+        assert_eq!(
+            prefixed("staging", "matches:closed"),
+            "staging:matches:closed"
+        );
+        assert_eq!(prefixed("eu-1", player_id), format!("eu-1:{player_id}"));
+    }
+}
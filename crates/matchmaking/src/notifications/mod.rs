@@ -0,0 +1,188 @@
+use std::num::NonZeroUsize;
+
+use bitcode::{Decode, Encode};
+use redis::{AsyncCommands, RedisError, aio::MultiplexedConnection};
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+use crate::{
+    codec,
+    environment::Challenge,
+    namespace,
+    rpc::{
+        matchmaking::{
+            KickedFromLobby, MatchFailed, MatchFound, PartyDisbanded, PartyHostChanged, QueueEvent,
+            QueueTimeout, ReadyCheck, queue_event::Event,
+        },
+        server::TEN_MINUTES,
+    },
+};
+
+pub const NOTIFICATIONS_KEY: &str = "notifications";
+const DRAIN_BATCH: NonZeroUsize = NonZeroUsize::new(32).unwrap();
+
+/// Events delivered to players through the `WatchQueue` stream
+#[derive(Debug, Clone, Serialize, Deserialize, Encode, Decode, PartialEq)]
+pub enum Notification {
+    PartyHostChanged {
+        party_id: Uuid,
+        host_id: Uuid,
+    },
+    PartyDisbanded {
+        party_id: Uuid,
+    },
+    MatchFound {
+        match_id: Uuid,
+        host_id: Uuid,
+        region: String,
+        nakama_match_id: String,
+        game_server_address: String,
+        backfill: bool,
+        challenge: Option<Challenge>,
+    },
+    MatchFailed {
+        match_id: Uuid,
+    },
+    QueueTimeout,
+    KickedFromLobby {
+        match_id: Uuid,
+        requeued: bool,
+    },
+    ReadyCheck {
+        match_id: Uuid,
+        deadline: i64,
+    },
+}
+
+impl codec::Versioned for Notification {}
+
+impl From<Notification> for QueueEvent {
+    fn from(value: Notification) -> Self {
+        let event = match value {
+            Notification::PartyHostChanged { party_id, host_id } => {
+                Event::PartyHostChanged(PartyHostChanged {
+                    party_id: party_id.to_string(),
+                    host_id: host_id.to_string(),
+                })
+            }
+            Notification::PartyDisbanded { party_id } => Event::PartyDisbanded(PartyDisbanded {
+                party_id: party_id.to_string(),
+            }),
+            Notification::MatchFound {
+                match_id,
+                host_id,
+                region,
+                nakama_match_id,
+                game_server_address,
+                backfill,
+                challenge,
+            } => Event::MatchFound(MatchFound {
+                match_id: match_id.to_string(),
+                host_id: host_id.to_string(),
+                region,
+                backfill,
+                nakama_match_id,
+                game_server_address,
+                win_probability: challenge.map(|challenge| challenge.win_probability),
+                environment: challenge.as_ref().map(Into::into),
+            }),
+            Notification::MatchFailed { match_id } => Event::MatchFailed(MatchFailed {
+                match_id: match_id.to_string(),
+            }),
+            Notification::QueueTimeout => Event::QueueTimeout(QueueTimeout {}),
+            Notification::KickedFromLobby { match_id, requeued } => {
+                Event::KickedFromLobby(KickedFromLobby {
+                    match_id: match_id.to_string(),
+                    requeued,
+                })
+            }
+            Notification::ReadyCheck { match_id, deadline } => Event::ReadyCheck(ReadyCheck {
+                match_id: match_id.to_string(),
+                deadline,
+            }),
+        };
+
+        Self { event: Some(event) }
+    }
+}
+
+pub fn notifications_key(player_id: &Uuid) -> String {
+    namespace::key(format_args!("{NOTIFICATIONS_KEY}:{player_id}"))
+}
+
+/// Queues a notification for each player. Pending notifications expire with the queue entry.
+pub async fn notify(
+    conn: &mut MultiplexedConnection,
+    players: &[Uuid],
+    notification: &Notification,
+) -> Result<(), RedisError> {
+    let encoded = codec::encode(notification);
+    let mut pipe = redis::pipe();
+    for player in players {
+        let key = notifications_key(player);
+        pipe.rpush(&key, &encoded)
+            .ignore()
+            .expire(&key, TEN_MINUTES as i64)
+            .ignore();
+    }
+    pipe.query_async(conn).await.map(|_: ()| ())
+}
+
+/// Pops the pending notifications of a player, oldest first
+pub async fn drain(
+    conn: &mut MultiplexedConnection,
+    player_id: &Uuid,
+) -> Result<Vec<Notification>, RedisError> {
+    let pending: Vec<Vec<u8>> = conn
+        .lpop(notifications_key(player_id), Some(DRAIN_BATCH))
+        .await?;
+
+    Ok(pending
+        .iter()
+        .filter_map(|bits| codec::decode(bits).ok())
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+    use crate::test_support::{create_redis, redis_client};
+
+    #[test]
+    fn notification_into_event() {
+        let party_id = Uuid::new_v4();
+        let event: QueueEvent = Notification::PartyDisbanded { party_id }.into();
+
+        assert_eq!(
+            event.event,
+            Some(Event::PartyDisbanded(PartyDisbanded {
+                party_id: party_id.to_string()
+            }))
+        );
+    }
+
+    #[tokio::test]
+    async fn notify_and_drain() {
+        let container = create_redis(6379).await;
+        let host = container.get_host().await.unwrap();
+        let port = container.get_host_port_ipv4(6379).await.unwrap();
+        let client = redis_client(host.to_string(), port);
+        let mut conn = client.get_multiplexed_async_connection().await.unwrap();
+        let player = Uuid::new_v4();
+        let party_id = Uuid::new_v4();
+        let changed = Notification::PartyHostChanged {
+            party_id,
+            host_id: player,
+        };
+        let disbanded = Notification::PartyDisbanded { party_id };
+
+        notify(&mut conn, &[player], &changed).await.unwrap();
+        notify(&mut conn, &[player], &disbanded).await.unwrap();
+        let pending = drain(&mut conn, &player).await.unwrap();
+        let empty = drain(&mut conn, &player).await.unwrap();
+
+        assert_eq!(pending, vec![changed, disbanded]);
+        assert!(empty.is_empty());
+    }
+}
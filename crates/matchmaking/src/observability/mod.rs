@@ -0,0 +1,283 @@
+//! Live snapshots of the matchmaker for dashboards: players queued and open matches of every
+//! region, and the worker run totals. The `WatchObservability` admin RPC streams one every
+//! interval, so dashboards subscribe instead of polling Redis themselves.
+//!
+//! A single [`SnapshotFeed`] per server loads a snapshot every [`MIN_INTERVAL`] while anyone
+//! watches and broadcasts it to every subscriber, so Redis is read at the same rate however many
+//! dashboards are open.
+
+use std::{
+    collections::BTreeMap,
+    sync::{Arc, Mutex, PoisonError},
+    time::Duration,
+};
+
+use redis::{RedisError, aio::MultiplexedConnection};
+use tokio::sync::broadcast;
+use tracing::{debug, error};
+
+use crate::{
+    analytics::{self, TickStats},
+    clock::Clock,
+    geoip,
+    metrics::worker::worker_metrics,
+    rpc::{
+        Match,
+        matchmaking::{ObservabilitySnapshot, RegionSnapshot},
+    },
+    store::{self, MatchStore, RedisStore},
+};
+
+/// Interval of the snapshots when the request sets none
+pub const DEFAULT_INTERVAL: Duration = Duration::from_secs(5);
+pub const MIN_INTERVAL: Duration = Duration::from_secs(1);
+pub const MAX_INTERVAL: Duration = Duration::from_secs(300);
+/// Snapshots a slow subscriber may fall behind before it skips to the latest
+const FEED_CAPACITY: usize = 4;
+
+#[derive(Debug, thiserror::Error)]
+pub enum Error {
+    #[error(transparent)]
+    Redis(#[from] RedisError),
+    #[error(transparent)]
+    Analytics(#[from] analytics::Error),
+    #[error(transparent)]
+    Regions(#[from] geoip::Error),
+    #[error(transparent)]
+    Store(#[from] store::Error),
+}
+
+impl From<Error> for tonic::Status {
+    fn from(_: Error) -> Self {
+        Self::internal("Failed to load observability snapshot")
+    }
+}
+
+/// Interval of a request asking for one every `interval_secs`
+pub fn interval(interval_secs: u32) -> Duration {
+    match interval_secs {
+        0 => DEFAULT_INTERVAL,
+        secs => Duration::from_secs(secs.into()).clamp(MIN_INTERVAL, MAX_INTERVAL),
+    }
+}
+
+/// Region of a queue, playlist queues are `<region>:<playlist>`
+fn base_region(queue_region: &str) -> &str {
+    queue_region.split(':').next().unwrap_or(queue_region)
+}
+
+/// Snapshots of the `served` regions and of the regions queued at the last `tick`
+pub fn regions(
+    served: &[String],
+    tick: Option<&TickStats>,
+    open: &BTreeMap<String, Vec<Match>>,
+) -> Vec<RegionSnapshot> {
+    let mut regions: BTreeMap<&str, RegionSnapshot> = served
+        .iter()
+        .map(|region| (region.as_str(), RegionSnapshot::default()))
+        .collect();
+    for queue in tick.iter().flat_map(|tick| &tick.queues) {
+        let snapshot = regions.entry(base_region(&queue.region)).or_default();
+        snapshot.queued_players += queue.players;
+        snapshot.longest_wait_secs = snapshot.longest_wait_secs.max(queue.longest_wait_secs);
+    }
+    for (region, matches) in open {
+        let snapshot = regions.entry(region).or_default();
+        snapshot.open_matches += matches.len() as u64;
+        for open in matches {
+            snapshot.open_match_players += open.players.len() as u64;
+            snapshot.open_slots += Match::MAX_PLAYERS.saturating_sub(open.players.len()) as u64;
+        }
+    }
+
+    regions
+        .into_iter()
+        .map(|(region, snapshot)| RegionSnapshot {
+            region: region.to_string(),
+            ..snapshot
+        })
+        .collect()
+}
+
+/// Snapshot of the queues, open matches and worker at `now`
+pub async fn snapshot(
+    conn: &mut MultiplexedConnection,
+    now: i64,
+) -> Result<ObservabilitySnapshot, Error> {
+    let served = geoip::served_regions(conn).await?;
+    let tick = analytics::latest(conn).await?;
+    let matches = RedisStore::new(conn.clone());
+    let mut open = BTreeMap::new();
+    for region in &served {
+        open.insert(region.clone(), matches.open_matches(region).await?);
+    }
+    let worker = worker_metrics(conn).await?;
+
+    Ok(ObservabilitySnapshot {
+        at: now,
+        last_tick_at: tick.as_ref().map_or(0, |tick| tick.at),
+        matches_formed: tick.as_ref().map_or(0, |tick| tick.matches_formed),
+        regions: regions(&served, tick.as_ref(), &open),
+        worker: Some(worker),
+    })
+}
+
+/// Snapshots broadcast to the `WatchObservability` subscribers, shared by the clones of the
+/// server. The poller starts with the first subscriber and stops once none is left.
+#[derive(Debug, Clone, Default)]
+pub struct SnapshotFeed(Arc<Mutex<Option<broadcast::Sender<ObservabilitySnapshot>>>>);
+
+impl SnapshotFeed {
+    /// Receives the snapshots loaded every [`MIN_INTERVAL`], starting the poller when it is
+    /// not running
+    pub fn subscribe(
+        &self,
+        conn: &MultiplexedConnection,
+        clock: &Arc<dyn Clock>,
+    ) -> broadcast::Receiver<ObservabilitySnapshot> {
+        let mut sender = self.0.lock().unwrap_or_else(PoisonError::into_inner);
+        if let Some(sender) = sender.as_ref().filter(|sender| sender.receiver_count() > 0) {
+            return sender.subscribe();
+        }
+
+        let (tx, rx) = broadcast::channel(FEED_CAPACITY);
+        *sender = Some(tx.clone());
+        tokio::spawn(poll(self.clone(), tx, conn.clone(), clock.clone()));
+
+        rx
+    }
+}
+
+/// Broadcasts a snapshot every [`MIN_INTERVAL`] until `tx` has no receiver left
+async fn poll(
+    feed: SnapshotFeed,
+    tx: broadcast::Sender<ObservabilitySnapshot>,
+    mut conn: MultiplexedConnection,
+    clock: Arc<dyn Clock>,
+) {
+    let mut interval = tokio::time::interval(MIN_INTERVAL);
+    loop {
+        interval.tick().await;
+        // checked under the lock, so a new subscriber either joins this poller or starts another
+        {
+            let mut sender = feed.0.lock().unwrap_or_else(PoisonError::into_inner);
+            if tx.receiver_count() == 0 {
+                if sender
+                    .as_ref()
+                    .is_some_and(|sender| sender.same_channel(&tx))
+                {
+                    *sender = None;
+                }
+                break;
+            }
+        }
+        // a failed snapshot is skipped, the dashboards keep the previous one
+        match snapshot(&mut conn, clock.time_since_epoch()).await {
+            Ok(snapshot) => {
+                let _ = tx.send(snapshot);
+            }
+            Err(err) => error!("Failed to load observability snapshot: {err}"),
+        }
+    }
+    debug!("observability poller stopped");
+}
+
+#[cfg(test)]
+mod tests {
+    use skillratings::mhth::MhthRating;
+    use uuid::Uuid;
+
+    use super::*;
+    use crate::{
+        analytics::QueueStats,
+        clock::SystemClock,
+        rpc::{QueuedPlayer, matchmaking::Player},
+        test_support::{create_redis, redis_client},
+    };
+
+    fn queue(region: &str, players: u64, longest_wait_secs: i64) -> QueueStats {
+        QueueStats {
+            region: region.to_string(),
+            difficulty: 1,
+            players,
+            average_wait_secs: 0.,
+            longest_wait_secs,
+            skill_spread: 0.,
+        }
+    }
+
+    #[test]
+    fn regions_add_up_queues_and_open_matches() {
+        let player = || -> QueuedPlayer {
+            (Uuid::new_v4(), Player::default(), MhthRating::default()).into()
+        };
+        let tick = TickStats {
+            at: 1000,
+            matches_formed: 2,
+            queues: vec![
+                queue("SA", 3, 40),
+                queue("SA:event", 2, 90),
+                queue("EU", 1, 5),
+            ],
+        };
+        let open = BTreeMap::from([(
+            "SA".to_string(),
+            vec![
+                Match::host(&player(), &[]).unwrap(),
+                Match::host(&player(), &[player(), player()]).unwrap(),
+            ],
+        )]);
+        let served = vec!["SA".to_string(), "US".to_string()];
+
+        let regions = regions(&served, Some(&tick), &open);
+
+        let names: Vec<&str> = regions
+            .iter()
+            .map(|region| region.region.as_str())
+            .collect();
+        assert_eq!(names, ["EU", "SA", "US"]);
+        let sa = &regions[1];
+        assert_eq!(sa.queued_players, 5);
+        assert_eq!(sa.longest_wait_secs, 90);
+        assert_eq!(sa.open_matches, 2);
+        assert_eq!(sa.open_match_players, 4);
+        assert_eq!(sa.open_slots, 4);
+        assert_eq!(
+            regions[2],
+            RegionSnapshot {
+                region: "US".to_string(),
+                ..Default::default()
+            }
+        );
+        assert_eq!(interval(0), DEFAULT_INTERVAL);
+        assert_eq!(interval(3600), MAX_INTERVAL);
+    }
+
+    #[tokio::test]
+    async fn subscribers_share_one_poller() {
+        let container = create_redis(6379).await;
+        let host = container.get_host().await.unwrap();
+        let port = container.get_host_port_ipv4(6379).await.unwrap();
+        let client = redis_client(host.to_string(), port);
+        let conn = client.get_multiplexed_async_connection().await.unwrap();
+        let clock: Arc<dyn Clock> = Arc::new(SystemClock::default());
+        let feed = SnapshotFeed::default();
+
+        let mut first = feed.subscribe(&conn, &clock);
+        let mut second = feed.subscribe(&conn, &clock);
+        let (first_snapshot, second_snapshot) = (first.recv().await, second.recv().await);
+        let receivers = feed
+            .0
+            .lock()
+            .unwrap()
+            .as_ref()
+            .map(|tx| tx.receiver_count());
+        drop((first, second));
+        tokio::time::sleep(MIN_INTERVAL * 2).await;
+        let stopped = feed.0.lock().unwrap().is_none();
+
+        assert_eq!(first_snapshot.unwrap(), second_snapshot.unwrap());
+        assert_eq!(receivers, Some(2));
+        assert!(stopped);
+    }
+}
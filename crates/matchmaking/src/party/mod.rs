@@ -0,0 +1,732 @@
+use std::{collections::HashMap, sync::LazyLock};
+
+use bitcode::{Decode, Encode};
+use redis::{AsyncCommands, RedisError, Script, aio::MultiplexedConnection};
+use serde::{Deserialize, Serialize};
+use tonic::Code;
+use tonic_types::{ErrorDetails, StatusExt};
+use uuid::Uuid;
+
+use crate::{
+    codec, namespace,
+    notifications::{self, Notification},
+    rpc::{
+        Match, QueuedPlayer,
+        matchmaking::{JoinMode, PartyResponse},
+        player_create_match_key, player_key, player_queue_key, player_versus_key,
+        server::{MATCH_LIFETIME, TEN_MINUTES},
+    },
+    store,
+    validation::ERROR_DOMAIN,
+};
+
+pub const PARTY_KEY: &str = "party";
+
+/// Checks the `ARGV[3]` field of the host hash (`KEYS[1]`) still holds `ARGV[4]`, empty when
+/// it was not queued, then refreshes the queue entries of the `ARGV[1]` next keys, the members,
+/// to `ARGV[2]` seconds and runs the writes of the host on the other keys, one key per write:
+/// its argument count, command and arguments follow in `ARGV`. Returns the indexes of the
+/// members that are not queued and writes nothing when there are some, `nil` when the host
+/// hash changed
+static HOLD_MEMBERS: LazyLock<Script> = LazyLock::new(|| {
+    Script::new(
+        r"
+        if (redis.call('HGET', KEYS[1], ARGV[3]) or '') ~= ARGV[4] then
+            return false
+        end
+        local members = tonumber(ARGV[1])
+        local missing = {}
+        for i = 2, members + 1 do
+            if redis.call('EXISTS', KEYS[i]) == 0 then
+                table.insert(missing, i - 2)
+            end
+        end
+        if #missing > 0 then
+            return missing
+        end
+        for i = 2, members + 1 do
+            redis.call('EXPIRE', KEYS[i], ARGV[2])
+        end
+        local arg = 5
+        for i = members + 2, #KEYS do
+            local argc = tonumber(ARGV[arg])
+            redis.call(ARGV[arg + 1], KEYS[i], unpack(ARGV, arg + 2, arg + 1 + argc))
+            arg = arg + 2 + argc
+        end
+        return missing
+        ",
+    )
+});
+
+#[derive(Debug, thiserror::Error)]
+pub enum Error {
+    #[error("party `{0}` not found")]
+    NotFound(Uuid),
+    #[error("player `{0}` is not the party host")]
+    NotHost(Uuid),
+    #[error("player `{0}` is already in a party")]
+    AlreadyInParty(Uuid),
+    #[error("player `{0}` was not invited to this party")]
+    NotInvited(Uuid),
+    #[error("player `{0}` is not a party member")]
+    NotMember(Uuid),
+    #[error("player cannot invite themselves")]
+    SelfInvite,
+    #[error("Party is full, MAX CAPACITY: {max}")]
+    Full { max: usize },
+    #[error("party members not confirmed: {0:?}")]
+    UnconfirmedMembers(Vec<String>),
+    #[error("party members not queued: {0:?}")]
+    MissingMembers(Vec<Uuid>),
+    #[error("player `{0}` joined the queue concurrently")]
+    ConcurrentJoin(Uuid),
+    #[error(transparent)]
+    Redis(#[from] RedisError),
+    #[error(transparent)]
+    BitcodeDeser(#[from] codec::Error),
+}
+
+impl From<Error> for tonic::Status {
+    fn from(value: Error) -> Self {
+        match value {
+            Error::NotFound(_) => Self::not_found(value.to_string()),
+            Error::NotHost(_) | Error::NotInvited(_) | Error::NotMember(_) => {
+                Self::permission_denied(value.to_string())
+            }
+            Error::AlreadyInParty(_) => Self::already_exists(value.to_string()),
+            Error::SelfInvite => Self::invalid_argument(value.to_string()),
+            Error::Full { .. } | Error::UnconfirmedMembers(_) => {
+                Self::failed_precondition(value.to_string())
+            }
+            Error::MissingMembers(ref missing) => {
+                let missing = missing
+                    .iter()
+                    .map(Uuid::to_string)
+                    .collect::<Vec<_>>()
+                    .join(",");
+                Self::with_error_details(
+                    Code::FailedPrecondition,
+                    value.to_string(),
+                    ErrorDetails::with_error_info(
+                        "PARTY_MEMBERS_NOT_QUEUED",
+                        ERROR_DOMAIN,
+                        HashMap::from([("missing".to_string(), missing)]),
+                    ),
+                )
+            }
+            Error::ConcurrentJoin(_) => Self::aborted(value.to_string()),
+            Error::Redis(_) | Error::BitcodeDeser(_) => Self::internal("Failed to load party"),
+        }
+    }
+}
+
+/// Outcome of the party host leaving
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum HostChange {
+    Promoted(Uuid),
+    Disbanded,
+}
+
+/// Party confirmed through invitations. `members` always contains the host.
+#[derive(Debug, Clone, Serialize, Deserialize, Encode, Decode, PartialEq)]
+pub struct Party {
+    pub id: Uuid,
+    pub host_id: Uuid,
+    pub members: Vec<Uuid>,
+    pub invited: Vec<Uuid>,
+}
+
+impl codec::Versioned for Party {}
+
+impl Party {
+    pub fn new(host_id: Uuid) -> Self {
+        Self {
+            id: Uuid::new_v4(),
+            host_id,
+            members: vec![host_id],
+            invited: Vec::new(),
+        }
+    }
+
+    pub fn invite(&mut self, host_id: Uuid, invitee_id: Uuid) -> Result<(), Error> {
+        if self.host_id != host_id {
+            return Err(Error::NotHost(host_id));
+        }
+        if host_id == invitee_id {
+            return Err(Error::SelfInvite);
+        }
+        if self.members.contains(&invitee_id) {
+            return Err(Error::AlreadyInParty(invitee_id));
+        }
+        if self.invited.contains(&invitee_id) {
+            return Ok(());
+        }
+        if self.members.len() + self.invited.len() >= Match::MAX_PLAYERS {
+            return Err(Error::Full {
+                max: Match::MAX_PLAYERS,
+            });
+        }
+        self.invited.push(invitee_id);
+
+        Ok(())
+    }
+
+    pub fn accept(&mut self, player_id: Uuid) -> Result<(), Error> {
+        let Some(index) = self.invited.iter().position(|id| *id == player_id) else {
+            return Err(Error::NotInvited(player_id));
+        };
+        self.invited.remove(index);
+        self.members.push(player_id);
+
+        Ok(())
+    }
+
+    pub fn decline(&mut self, player_id: Uuid) -> Result<(), Error> {
+        let Some(index) = self.invited.iter().position(|id| *id == player_id) else {
+            return Err(Error::NotInvited(player_id));
+        };
+        self.invited.remove(index);
+
+        Ok(())
+    }
+
+    /// Removes a guest from the party. Hosts leave through [`Party::migrate_host`].
+    pub fn leave(&mut self, player_id: Uuid) -> Result<(), Error> {
+        if player_id == self.host_id {
+            return Err(Error::NotMember(player_id));
+        }
+        let Some(index) = self.members.iter().position(|id| *id == player_id) else {
+            return Err(Error::NotMember(player_id));
+        };
+        self.members.remove(index);
+
+        Ok(())
+    }
+
+    /// Hands the host role to another confirmed member
+    pub fn transfer_host(&mut self, host_id: Uuid, new_host_id: Uuid) -> Result<(), Error> {
+        if self.host_id != host_id {
+            return Err(Error::NotHost(host_id));
+        }
+        if !self.members.contains(&new_host_id) {
+            return Err(Error::NotMember(new_host_id));
+        }
+        self.host_id = new_host_id;
+
+        Ok(())
+    }
+
+    /// Removes the host and promotes the first member still `queued`.
+    /// Without `require_queued` any remaining member can be promoted.
+    pub fn migrate_host(&mut self, queued: &[Uuid], require_queued: bool) -> HostChange {
+        let previous_host = self.host_id;
+        self.members.retain(|id| *id != previous_host);
+        let next_host = self
+            .members
+            .iter()
+            .find(|id| queued.contains(id))
+            .or_else(|| self.members.first().filter(|_| !require_queued))
+            .copied();
+
+        match next_host {
+            Some(host_id) => {
+                self.host_id = host_id;
+                HostChange::Promoted(host_id)
+            }
+            None => HostChange::Disbanded,
+        }
+    }
+
+    /// Party members excluding the host
+    pub fn guests(&self) -> impl Iterator<Item = &Uuid> {
+        self.members.iter().filter(|id| **id != self.host_id)
+    }
+
+    /// Validates the party members requested by the host against the confirmed party.
+    /// An empty request means the whole confirmed party.
+    pub fn confirm_members(&self, requested: &[String]) -> Result<Vec<String>, Error> {
+        if requested.is_empty() {
+            return Ok(self.guests().map(Uuid::to_string).collect());
+        }
+        let unconfirmed: Vec<String> = requested
+            .iter()
+            .filter(|id| {
+                Uuid::parse_str(id)
+                    .map_or(true, |id| id == self.host_id || !self.members.contains(&id))
+            })
+            .cloned()
+            .collect();
+        if !unconfirmed.is_empty() {
+            return Err(Error::UnconfirmedMembers(unconfirmed));
+        }
+
+        Ok(requested.to_vec())
+    }
+}
+
+impl From<&Party> for PartyResponse {
+    fn from(party: &Party) -> Self {
+        Self {
+            party_id: party.id.to_string(),
+            host_id: party.host_id.to_string(),
+            member_ids: party.members.iter().map(Uuid::to_string).collect(),
+            invited_ids: party.invited.iter().map(Uuid::to_string).collect(),
+        }
+    }
+}
+
+pub fn party_key(party_id: &Uuid) -> String {
+    namespace::key(format_args!("{PARTY_KEY}:{party_id}"))
+}
+
+pub fn player_party_key(player_id: &Uuid) -> String {
+    namespace::key(format_args!("{PARTY_KEY}:player:{player_id}"))
+}
+
+pub async fn get_party(
+    conn: &mut MultiplexedConnection,
+    party_id: &Uuid,
+) -> Result<Option<Party>, Error> {
+    let Some(data): Option<Vec<u8>> = conn.get(party_key(party_id)).await? else {
+        return Ok(None);
+    };
+
+    Ok(Some(codec::decode(&data)?))
+}
+
+/// Party the player is a confirmed member of
+pub async fn player_party(
+    conn: &mut MultiplexedConnection,
+    player_id: &Uuid,
+) -> Result<Option<Party>, Error> {
+    let Some(party_id): Option<Uuid> = conn.get(player_party_key(player_id)).await? else {
+        return Ok(None);
+    };
+
+    get_party(conn, &party_id).await
+}
+
+/// Stores the party and indexes every member to it
+pub async fn save_party(conn: &mut MultiplexedConnection, party: &Party) -> Result<(), Error> {
+    let encoded = codec::encode(party);
+    let mut pipe = redis::pipe();
+    pipe.set_ex(party_key(&party.id), encoded, *MATCH_LIFETIME);
+    for member in &party.members {
+        pipe.set_ex(player_party_key(member), party.id, *MATCH_LIFETIME);
+    }
+    pipe.query_async(conn).await.map(|_: ()| ())?;
+
+    Ok(())
+}
+
+/// Removes the party and the index of every listed player
+pub async fn delete_party(
+    conn: &mut MultiplexedConnection,
+    party: &Party,
+    players: &[Uuid],
+) -> Result<(), Error> {
+    let mut pipe = redis::pipe();
+    pipe.del(party_key(&party.id));
+    for player in players {
+        pipe.del(player_party_key(player));
+    }
+    pipe.query_async(conn).await.map(|_: ()| ())?;
+
+    Ok(())
+}
+
+/// Removes a player's party index
+pub async fn unlink_player(
+    conn: &mut MultiplexedConnection,
+    player_id: &Uuid,
+) -> Result<(), Error> {
+    conn.del(player_party_key(player_id))
+        .await
+        .map(|_: ()| ())?;
+
+    Ok(())
+}
+
+/// Promotes another member of a party whose host left and notifies every member.
+/// A promoted member that is queued switches to creating a room for the remaining party.
+pub async fn hand_over(
+    conn: &mut MultiplexedConnection,
+    party: &mut Party,
+    require_queued: bool,
+) -> Result<HostChange, Error> {
+    let previous_host = party.host_id;
+    let mut queued = Vec::new();
+    for member in party.guests() {
+        if let Some(data) = store::player_data(conn, member).await? {
+            queued.push((codec::decode::<QueuedPlayer>(&data)?, data));
+        }
+    }
+    let queued_ids: Vec<Uuid> = queued.iter().map(|(player, _)| player.player_id).collect();
+
+    let change = party.migrate_host(&queued_ids, require_queued);
+    match change {
+        HostChange::Promoted(host_id) => {
+            save_party(conn, party).await?;
+            unlink_player(conn, &previous_host).await?;
+            if let Some((player, data)) = queued.into_iter().find(|(p, _)| p.player_id == host_id) {
+                let mut promoted = player.clone();
+                promoted.join_mode = JoinMode::CreateRoom.into();
+                promoted.party_ids = party.guests().map(Uuid::to_string).collect();
+                replace_queue_entry(conn, &player, &data, &promoted).await?;
+            }
+            let notification = Notification::PartyHostChanged {
+                party_id: party.id,
+                host_id,
+            };
+            notifications::notify(conn, &party.members, &notification).await?;
+        }
+        HostChange::Disbanded => {
+            let mut players = party.members.clone();
+            players.push(previous_host);
+            delete_party(conn, party, &players).await?;
+            let notification = Notification::PartyDisbanded { party_id: party.id };
+            notifications::notify(conn, &party.members, &notification).await?;
+        }
+    }
+
+    Ok(change)
+}
+
+/// Disbands the party on the host's request and notifies every member
+pub async fn disband(
+    conn: &mut MultiplexedConnection,
+    party: &Party,
+    host_id: Uuid,
+) -> Result<(), Error> {
+    if party.host_id != host_id {
+        return Err(Error::NotHost(host_id));
+    }
+    delete_party(conn, party, &party.members).await?;
+    let notification = Notification::PartyDisbanded { party_id: party.id };
+    let guests: Vec<Uuid> = party.guests().copied().collect();
+    notifications::notify(conn, &guests, &notification).await?;
+
+    Ok(())
+}
+
+/// Applies `update` to a player's queue entry. Returns `false` when the player is not queued.
+pub async fn update_queued(
+    conn: &mut MultiplexedConnection,
+    player_id: &Uuid,
+    update: impl FnOnce(&mut QueuedPlayer),
+) -> Result<bool, Error> {
+    let Some(data) = store::player_data(conn, player_id).await? else {
+        return Ok(false);
+    };
+    let old: QueuedPlayer = codec::decode(&data)?;
+    let mut new = old.clone();
+    update(&mut new);
+    replace_queue_entry(conn, &old, &data, &new).await?;
+
+    Ok(true)
+}
+
+/// Checks every member is queued, keeps their entries for `ttl` seconds and runs the `writes` of
+/// the host, atomically, so a party host only queues once its whole party did. Every write
+/// takes its key first. Fails listing the members that are not queued, writing nothing, and
+/// with [`Error::ConcurrentJoin`] when the queued data of the host is no longer `previous`, the
+/// data it was read with, so two joins of a player cannot both queue it.
+pub async fn hold_members(
+    conn: &mut MultiplexedConnection,
+    host_id: &Uuid,
+    previous: Option<&[u8]>,
+    member_ids: &[Uuid],
+    ttl: u64,
+    writes: &redis::Pipeline,
+) -> Result<(), Error> {
+    let mut invocation = HOLD_MEMBERS.prepare_invoke();
+    invocation.key(player_key(host_id));
+    for member_id in member_ids {
+        invocation.key(player_key(member_id));
+    }
+    invocation
+        .arg(member_ids.len())
+        .arg(ttl)
+        .arg(store::DATA)
+        .arg(previous.unwrap_or_default());
+    for cmd in writes.cmd_iter() {
+        let mut args = cmd.args_iter().filter_map(|arg| match arg {
+            redis::Arg::Simple(arg) => Some(arg),
+            redis::Arg::Cursor => None,
+        });
+        let (Some(command), Some(key)) = (args.next(), args.next()) else {
+            continue;
+        };
+        let args: Vec<&[u8]> = args.collect();
+        invocation.key(key).arg(args.len()).arg(command);
+        for arg in args {
+            invocation.arg(arg);
+        }
+    }
+    let Some(missing): Option<Vec<usize>> = invocation.invoke_async(conn).await? else {
+        return Err(Error::ConcurrentJoin(*host_id));
+    };
+    if !missing.is_empty() {
+        return Err(Error::MissingMembers(
+            missing.into_iter().map(|i| member_ids[i]).collect(),
+        ));
+    }
+
+    Ok(())
+}
+
+/// Swaps a queued player's entry in the player and room-creation queues. The old entries are
+/// removed by `old_encoded`, the bytes stored in its hash, re-encoding `old` gives other bytes
+/// once the keys rotated or its version was bumped
+pub async fn replace_queue_entry(
+    conn: &mut MultiplexedConnection,
+    old: &QueuedPlayer,
+    old_encoded: &[u8],
+    new: &QueuedPlayer,
+) -> Result<(), Error> {
+    let new_encoded = codec::encode(new);
+    let mut pipe = redis::pipe();
+    pipe.zrem(player_queue_key(old), old_encoded)
+        .ignore()
+        .zrem(player_create_match_key(old), old_encoded)
+        .ignore()
+        .zrem(player_versus_key(old), old_encoded)
+        .ignore();
+    store::put_player(&mut pipe, new, &new_encoded, TEN_MINUTES);
+    pipe.zadd(player_queue_key(new), &new_encoded, new.join_time)
+        .ignore();
+    if new.creates_room() {
+        pipe.zadd(player_create_match_key(new), &new_encoded, new.join_time)
+            .ignore();
+    }
+    if new.is_versus() {
+        pipe.zadd(player_versus_key(new), &new_encoded, new.join_time)
+            .ignore();
+    }
+    pipe.query_async(conn).await.map(|_: ()| ())?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+    use crate::test_support::{create_redis, redis_client};
+
+    #[test]
+    fn invite_and_accept() {
+        let host = Uuid::new_v4();
+        let friend = Uuid::new_v4();
+        let mut party = Party::new(host);
+
+        party.invite(host, friend).unwrap();
+        assert_eq!(party.invited, vec![friend]);
+
+        party.accept(friend).unwrap();
+        assert!(party.invited.is_empty());
+        assert_eq!(party.members, vec![host, friend]);
+        assert_eq!(party.guests().collect::<Vec<_>>(), vec![&friend]);
+    }
+
+    #[test]
+    fn only_host_invites() {
+        let host = Uuid::new_v4();
+        let friend = Uuid::new_v4();
+        let mut party = Party::new(host);
+        party.invite(host, friend).unwrap();
+        party.accept(friend).unwrap();
+
+        let err = party.invite(friend, Uuid::new_v4()).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            format!("player `{friend}` is not the party host")
+        );
+
+        let err = party.invite(host, host).unwrap_err();
+        assert_eq!(err.to_string(), "player cannot invite themselves");
+    }
+
+    #[test]
+    fn full_party() {
+        let host = Uuid::new_v4();
+        let mut party = Party::new(host);
+        for _ in 1..Match::MAX_PLAYERS {
+            party.invite(host, Uuid::new_v4()).unwrap();
+        }
+
+        let err = party.invite(host, Uuid::new_v4()).unwrap_err();
+        assert_eq!(err.to_string(), "Party is full, MAX CAPACITY: 4");
+    }
+
+    #[test]
+    fn decline_and_not_invited() {
+        let host = Uuid::new_v4();
+        let friend = Uuid::new_v4();
+        let mut party = Party::new(host);
+        party.invite(host, friend).unwrap();
+
+        party.decline(friend).unwrap();
+        assert!(party.invited.is_empty());
+        assert!(matches!(party.accept(friend), Err(Error::NotInvited(_))));
+    }
+
+    #[test]
+    fn guest_leaves() {
+        let host = Uuid::new_v4();
+        let friend = Uuid::new_v4();
+        let mut party = Party::new(host);
+        party.invite(host, friend).unwrap();
+        party.accept(friend).unwrap();
+
+        assert!(matches!(party.leave(host), Err(Error::NotMember(_))));
+        party.leave(friend).unwrap();
+        assert_eq!(party.members, vec![host]);
+    }
+
+    #[test]
+    fn transfer_host_to_member() {
+        let host = Uuid::new_v4();
+        let friend = Uuid::new_v4();
+        let mut party = Party::new(host);
+        party.invite(host, friend).unwrap();
+
+        assert!(matches!(
+            party.transfer_host(host, friend),
+            Err(Error::NotMember(_))
+        ));
+        party.accept(friend).unwrap();
+        party.transfer_host(host, friend).unwrap();
+
+        assert_eq!(party.host_id, friend);
+        assert_eq!(party.guests().collect::<Vec<_>>(), vec![&host]);
+    }
+
+    #[test]
+    fn migrate_host_prefers_queued_members() {
+        let host = Uuid::new_v4();
+        let idle = Uuid::new_v4();
+        let queued = Uuid::new_v4();
+        let mut party = Party::new(host);
+        for id in [idle, queued] {
+            party.invite(host, id).unwrap();
+            party.accept(id).unwrap();
+        }
+
+        let change = party.clone().migrate_host(&[], true);
+        assert_eq!(change, HostChange::Disbanded);
+
+        let change = party.clone().migrate_host(&[], false);
+        assert_eq!(change, HostChange::Promoted(idle));
+
+        let change = party.migrate_host(&[queued], true);
+        assert_eq!(change, HostChange::Promoted(queued));
+        assert_eq!(party.host_id, queued);
+        assert_eq!(party.members, vec![idle, queued]);
+    }
+
+    #[test]
+    fn confirm_requested_members() {
+        let host = Uuid::new_v4();
+        let friend = Uuid::new_v4();
+        let stranger = Uuid::new_v4();
+        let mut party = Party::new(host);
+        party.invite(host, friend).unwrap();
+        party.accept(friend).unwrap();
+
+        assert_eq!(
+            party.confirm_members(&[]).unwrap(),
+            vec![friend.to_string()]
+        );
+        assert_eq!(
+            party.confirm_members(&[friend.to_string()]).unwrap(),
+            vec![friend.to_string()]
+        );
+        let err = party
+            .confirm_members(&[friend.to_string(), stranger.to_string()])
+            .unwrap_err();
+        assert!(matches!(err, Error::UnconfirmedMembers(ids) if ids == vec![stranger.to_string()]));
+    }
+
+    #[tokio::test]
+    async fn save_and_load_party() {
+        let container = create_redis(6379).await;
+        let host = container.get_host().await.unwrap();
+        let port = container.get_host_port_ipv4(6379).await.unwrap();
+        let client = redis_client(host.to_string(), port);
+        let mut conn = client.get_multiplexed_async_connection().await.unwrap();
+
+        let host_id = Uuid::new_v4();
+        let friend = Uuid::new_v4();
+        let mut party = Party::new(host_id);
+        party.invite(host_id, friend).unwrap();
+        party.accept(friend).unwrap();
+
+        save_party(&mut conn, &party).await.unwrap();
+        let hosted = player_party(&mut conn, &host_id).await.unwrap();
+        let joined = player_party(&mut conn, &friend).await.unwrap();
+
+        delete_party(&mut conn, &party, &party.members)
+            .await
+            .unwrap();
+        let deleted = player_party(&mut conn, &friend).await.unwrap();
+
+        assert_eq!(hosted, Some(party.clone()));
+        assert_eq!(joined, Some(party));
+        assert_eq!(deleted, None);
+    }
+
+    #[tokio::test]
+    async fn members_are_held_together() {
+        let container = create_redis(6379).await;
+        let host = container.get_host().await.unwrap();
+        let port = container.get_host_port_ipv4(6379).await.unwrap();
+        let client = redis_client(host.to_string(), port);
+        let mut conn = client.get_multiplexed_async_connection().await.unwrap();
+        let (queued, missing) = (Uuid::new_v4(), Uuid::new_v4());
+        let _: () = conn
+            .hset(player_key(&queued), store::DATA, b"queued".as_slice())
+            .await
+            .unwrap();
+        let _: () = conn.expire(player_key(&queued), 20).await.unwrap();
+
+        let host_id = Uuid::new_v4();
+        let host_key = player_key(&host_id);
+        let mut writes = redis::pipe();
+        writes
+            .hset_multiple(&host_key, &[(store::DATA, "host"), (store::REGION, "eu")])
+            .ignore()
+            .expire(&host_key, 60)
+            .ignore();
+
+        let err = hold_members(
+            &mut conn,
+            &host_id,
+            None,
+            &[queued, missing],
+            TEN_MINUTES,
+            &writes,
+        )
+        .await
+        .unwrap_err();
+        let ttl_after_failure: i64 = conn.ttl(player_key(&queued)).await.unwrap();
+        let host_after_failure: bool = conn.exists(&host_key).await.unwrap();
+        hold_members(&mut conn, &host_id, None, &[queued], TEN_MINUTES, &writes)
+            .await
+            .unwrap();
+        let concurrent = hold_members(&mut conn, &host_id, None, &[queued], TEN_MINUTES, &writes)
+            .await
+            .unwrap_err();
+        let ttl: i64 = conn.ttl(player_key(&queued)).await.unwrap();
+        let region: Option<String> = conn.hget(&host_key, store::REGION).await.unwrap();
+        let host_ttl: i64 = conn.ttl(&host_key).await.unwrap();
+
+        assert!(matches!(err, Error::MissingMembers(ids) if ids == vec![missing]));
+        assert!(matches!(concurrent, Error::ConcurrentJoin(id) if id == host_id));
+        assert!(ttl_after_failure <= 20);
+        assert!(!host_after_failure);
+        assert!(ttl > 20);
+        assert_eq!(region.as_deref(), Some("eu"));
+        assert!(host_ttl > 0 && host_ttl <= 60);
+    }
+}
@@ -0,0 +1,240 @@
+use std::{collections::HashSet, sync::LazyLock, time::Duration};
+
+use redis::{AsyncCommands, RedisError, Script, aio::MultiplexedConnection};
+use tonic::Code;
+use tonic_types::{ErrorDetails, StatusExt};
+use uuid::Uuid;
+
+use crate::{codec, namespace, rpc::server::MATCH_LIFETIME};
+
+pub const ABANDONS_KEY: &str = "penalty:abandons";
+pub const COOLDOWN_KEY: &str = "penalty:cooldown";
+pub const MATCH_ABANDONS_KEY: &str = "penalty:match";
+/// Abandons are forgotten after a day without new abandons
+pub const ABANDON_WINDOW: u64 = 86_400;
+/// Abandons tolerated inside the window before a cooldown applies
+pub const FREE_ABANDONS: u64 = 1;
+pub const BASE_COOLDOWN: u64 = 300;
+pub const MAX_COOLDOWN: u64 = 86_400;
+
+/// Adds the player to the abandons of the match (`KEYS[1]`), counts the abandon (`KEYS[2]`) and
+/// sets the cooldown (`KEYS[3]`) of [`cooldown_for`] the count, 0 when the abandon of that match
+/// was already counted
+static RECORD_ABANDON: LazyLock<Script> = LazyLock::new(|| {
+    Script::new(
+        r"
+        if redis.call('SADD', KEYS[1], ARGV[1]) == 0 then
+            return 0
+        end
+        redis.call('EXPIRE', KEYS[1], ARGV[2])
+        local abandons = redis.call('INCR', KEYS[2])
+        redis.call('EXPIRE', KEYS[2], ARGV[3])
+        local exceeding = abandons - tonumber(ARGV[4]) - 1
+        if exceeding >= 0 then
+            local cooldown = math.min(tonumber(ARGV[5]) * 2 ^ exceeding, tonumber(ARGV[6]))
+            redis.call('SET', KEYS[3], abandons, 'EX', math.floor(cooldown))
+        end
+        return abandons
+        ",
+    )
+});
+
+#[derive(Debug, thiserror::Error)]
+pub enum Error {
+    #[error("player is under queue cooldown for {remaining}s")]
+    Cooldown { remaining: u64 },
+    #[error("match `{0}` is not active")]
+    MatchNotFound(Uuid),
+    #[error("player `{0}` is not the match host")]
+    NotHost(Uuid),
+    #[error("player `{0}` is not in the match")]
+    NotInMatch(Uuid),
+    #[error("abandon of player `{0}` was already reported")]
+    AlreadyReported(Uuid),
+    #[error(transparent)]
+    Redis(#[from] RedisError),
+    #[error(transparent)]
+    BitcodeDeser(#[from] codec::Error),
+}
+
+impl From<Error> for tonic::Status {
+    fn from(value: Error) -> Self {
+        match value {
+            Error::Cooldown { remaining } => Self::with_error_details(
+                Code::FailedPrecondition,
+                value.to_string(),
+                ErrorDetails::with_retry_info(Some(Duration::from_secs(remaining))),
+            ),
+            Error::MatchNotFound(_) => Self::not_found(value.to_string()),
+            Error::NotHost(_) => Self::permission_denied(value.to_string()),
+            Error::NotInMatch(_) => Self::invalid_argument(value.to_string()),
+            Error::AlreadyReported(_) => Self::already_exists(value.to_string()),
+            Error::Redis(_) | Error::BitcodeDeser(_) => Self::internal("Failed to load penalties"),
+        }
+    }
+}
+
+/// Outcome of an abandon report
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Penalty {
+    pub abandons: u64,
+    pub cooldown: Option<u64>,
+}
+
+pub fn abandons_key(player_id: &Uuid) -> String {
+    namespace::key(format_args!("{ABANDONS_KEY}:{player_id}"))
+}
+
+pub fn cooldown_key(player_id: &Uuid) -> String {
+    namespace::key(format_args!("{COOLDOWN_KEY}:{player_id}"))
+}
+
+/// Players who abandoned a match
+pub fn match_abandons_key(match_id: &Uuid) -> String {
+    namespace::key(format_args!("{MATCH_ABANDONS_KEY}:{match_id}"))
+}
+
+/// Cooldown in seconds for the given abandon count, doubling with each abandon over the free ones
+pub fn cooldown_for(abandons: u64) -> Option<u64> {
+    let exceeding = abandons.checked_sub(FREE_ABANDONS + 1)?;
+    let factor = 2u64.checked_pow(exceeding as u32).unwrap_or(u64::MAX);
+
+    Some(BASE_COOLDOWN.saturating_mul(factor).min(MAX_COOLDOWN))
+}
+
+/// Counts the abandon of `match_id` once, remembering it until the match expires (see
+/// [`abandoned`]), and places the player under cooldown when it exceeds the free abandons.
+/// `None` when the abandon of that match was already counted
+pub async fn record_abandon(
+    conn: &mut MultiplexedConnection,
+    match_id: &Uuid,
+    player_id: &Uuid,
+) -> Result<Option<Penalty>, RedisError> {
+    let abandons: u64 = RECORD_ABANDON
+        .key(match_abandons_key(match_id))
+        .key(abandons_key(player_id))
+        .key(cooldown_key(player_id))
+        .arg(player_id)
+        .arg(*MATCH_LIFETIME)
+        .arg(ABANDON_WINDOW)
+        .arg(FREE_ABANDONS)
+        .arg(BASE_COOLDOWN)
+        .arg(MAX_COOLDOWN)
+        .invoke_async(conn)
+        .await?;
+    if abandons == 0 {
+        return Ok(None);
+    }
+
+    Ok(Some(Penalty {
+        abandons,
+        cooldown: cooldown_for(abandons),
+    }))
+}
+
+/// Players who abandoned `match_id`
+pub async fn abandoned(
+    conn: &mut MultiplexedConnection,
+    match_id: &Uuid,
+) -> Result<HashSet<Uuid>, RedisError> {
+    conn.smembers(match_abandons_key(match_id)).await
+}
+
+/// Abandons counted in the current window
+pub async fn abandons(
+    conn: &mut MultiplexedConnection,
+    player_id: &Uuid,
+) -> Result<u64, RedisError> {
+    let abandons: Option<u64> = conn.get(abandons_key(player_id)).await?;
+
+    Ok(abandons.unwrap_or_default())
+}
+
+/// Seconds left on the player cooldown, if any
+pub async fn cooldown_remaining(
+    conn: &mut MultiplexedConnection,
+    player_id: &Uuid,
+) -> Result<Option<u64>, RedisError> {
+    let ttl: i64 = conn.ttl(cooldown_key(player_id)).await?;
+
+    Ok(u64::try_from(ttl).ok().filter(|ttl| *ttl > 0))
+}
+
+/// Fails with [`Error::Cooldown`] while the player is under cooldown
+pub async fn check_cooldown(
+    conn: &mut MultiplexedConnection,
+    player_id: &Uuid,
+) -> Result<(), Error> {
+    match cooldown_remaining(conn, player_id).await? {
+        Some(remaining) => Err(Error::Cooldown { remaining }),
+        None => Ok(()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+    use crate::test_support::{create_redis, redis_client};
+
+    #[test]
+    fn cooldown_escalates() {
+        assert_eq!(cooldown_for(0), None);
+        assert_eq!(cooldown_for(1), None);
+        assert_eq!(cooldown_for(2), Some(BASE_COOLDOWN));
+        assert_eq!(cooldown_for(3), Some(BASE_COOLDOWN * 2));
+        assert_eq!(cooldown_for(4), Some(BASE_COOLDOWN * 4));
+        assert_eq!(cooldown_for(80), Some(MAX_COOLDOWN));
+    }
+
+    #[test]
+    fn cooldown_status_has_retry_info() {
+        let status: tonic::Status = Error::Cooldown { remaining: 42 }.into();
+
+        assert_eq!(status.code(), Code::FailedPrecondition);
+        assert_eq!(
+            status.get_details_retry_info().unwrap().retry_delay,
+            Some(Duration::from_secs(42))
+        );
+    }
+
+    #[tokio::test]
+    async fn repeated_abandons_place_cooldown() {
+        let container = create_redis(6379).await;
+        let host = container.get_host().await.unwrap();
+        let port = container.get_host_port_ipv4(6379).await.unwrap();
+        let client = redis_client(host.to_string(), port);
+        let mut conn = client.get_multiplexed_async_connection().await.unwrap();
+        let player_id = Uuid::new_v4();
+        let (first_match, second_match) = (Uuid::new_v4(), Uuid::new_v4());
+
+        let first = record_abandon(&mut conn, &first_match, &player_id)
+            .await
+            .unwrap();
+        let duplicate = record_abandon(&mut conn, &first_match, &player_id)
+            .await
+            .unwrap();
+        let free = check_cooldown(&mut conn, &player_id).await;
+        let second = record_abandon(&mut conn, &second_match, &player_id)
+            .await
+            .unwrap()
+            .unwrap();
+        let blocked = check_cooldown(&mut conn, &player_id).await;
+        let abandoned = abandoned(&mut conn, &first_match).await.unwrap();
+
+        assert_eq!(
+            first,
+            Some(Penalty {
+                abandons: 1,
+                cooldown: None
+            })
+        );
+        assert_eq!(duplicate, None);
+        assert!(free.is_ok());
+        assert_eq!(abandoned, HashSet::from([player_id]));
+        assert_eq!(second.cooldown, Some(BASE_COOLDOWN));
+        assert!(
+            matches!(blocked, Err(Error::Cooldown { remaining }) if remaining <= BASE_COOLDOWN)
+        );
+    }
+}
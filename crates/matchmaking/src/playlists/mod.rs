@@ -0,0 +1,315 @@
+use std::path::Path;
+
+use chrono::{DateTime, Datelike, Timelike, Utc, Weekday};
+use redis::{AsyncCommands, RedisError, aio::MultiplexedConnection};
+use serde::{Deserialize, Serialize};
+
+use crate::{namespace, raid::RaidFormat, ranked};
+
+pub const PLAYLISTS_KEY: &str = "match:playlists";
+
+#[derive(Debug, thiserror::Error)]
+pub enum Error {
+    #[error("playlist `{0}` does not exist")]
+    Unknown(String),
+    #[error("playlist `{0}` is not active")]
+    Inactive(String),
+    #[error("failed to read playlists config: {0}")]
+    Io(#[from] std::io::Error),
+    #[error(transparent)]
+    Json(#[from] serde_json::Error),
+    #[error(transparent)]
+    Redis(#[from] RedisError),
+    #[error(transparent)]
+    BitcodeDeser(#[from] bitcode::Error),
+}
+
+impl From<Error> for tonic::Status {
+    fn from(value: Error) -> Self {
+        match value {
+            Error::Unknown(_) => Self::invalid_argument(value.to_string()),
+            Error::Inactive(_) => Self::failed_precondition(value.to_string()),
+            Error::Io(_) | Error::Json(_) | Error::Redis(_) | Error::BitcodeDeser(_) => {
+                Self::internal("Failed to load playlists")
+            }
+        }
+    }
+}
+
+/// When a playlist accepts players, times are UTC
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum Schedule {
+    Always,
+    /// Limited-time mode, `ends_at` excluded
+    Between {
+        starts_at: DateTime<Utc>,
+        ends_at: DateTime<Utc>,
+    },
+    /// Recurring mode, active on `days` from `start_hour` until `end_hour` (excluded)
+    Weekly {
+        days: Vec<Weekday>,
+        start_hour: u32,
+        end_hour: u32,
+    },
+}
+
+impl Schedule {
+    pub fn is_active(&self, now: DateTime<Utc>) -> bool {
+        match self {
+            Self::Always => true,
+            Self::Between { starts_at, ends_at } => *starts_at <= now && now < *ends_at,
+            Self::Weekly {
+                days,
+                start_hour,
+                end_hour,
+            } => days.contains(&now.weekday()) && (*start_hour..*end_hour).contains(&now.hour()),
+        }
+    }
+}
+
+/// Game mode with its own queues, e.g. a weekend horde mode.
+/// Players joining without playlist use the default queues.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+pub struct Playlist {
+    pub id: String,
+    pub name: String,
+    /// Active when any schedule is active
+    pub schedules: Vec<Schedule>,
+    /// Players are assembled into raids instead of hosted matches, see [`crate::raid`]
+    #[serde(default)]
+    pub raid: Option<RaidFormat>,
+    /// Clan-vs-environment mode, played with a clan, see [`crate::clans`]
+    #[serde(default)]
+    pub clan: bool,
+}
+
+impl Playlist {
+    pub fn is_active(&self, now: DateTime<Utc>) -> bool {
+        self.schedules
+            .iter()
+            .any(|schedule| schedule.is_active(now))
+    }
+}
+
+/// Reads playlist definitions from a JSON config file
+pub fn load_file(path: impl AsRef<Path>) -> Result<Vec<Playlist>, Error> {
+    let config = std::fs::read_to_string(path)?;
+
+    Ok(serde_json::from_str(&config)?)
+}
+
+pub async fn set_playlists(
+    conn: &mut MultiplexedConnection,
+    playlists: &[Playlist],
+) -> Result<(), Error> {
+    conn.set(
+        namespace::key(PLAYLISTS_KEY),
+        bitcode::serialize(playlists)?,
+    )
+    .await
+    .map_err(Error::from)
+}
+
+pub async fn get_playlists(conn: &mut MultiplexedConnection) -> Result<Vec<Playlist>, Error> {
+    let encoded: Option<Vec<u8>> = conn.get(namespace::key(PLAYLISTS_KEY)).await?;
+
+    Ok(encoded
+        .map(|encoded| bitcode::deserialize(&encoded))
+        .transpose()?
+        .unwrap_or_default())
+}
+
+/// Checks the requested playlist accepts players and returns it,
+/// the empty playlist is always active and has no definition
+pub async fn check_active(
+    conn: &mut MultiplexedConnection,
+    playlist_id: &str,
+    now: DateTime<Utc>,
+) -> Result<Option<Playlist>, Error> {
+    if playlist_id.is_empty() {
+        return Ok(None);
+    }
+    let playlist = get_playlists(conn)
+        .await?
+        .into_iter()
+        .find(|playlist| playlist.id == playlist_id)
+        .ok_or_else(|| Error::Unknown(playlist_id.to_string()))?;
+
+    if playlist.is_active(now) {
+        Ok(Some(playlist))
+    } else {
+        Err(Error::Inactive(playlist_id.to_string()))
+    }
+}
+
+/// Region of the queues of a playlist, the default playlist uses the plain region
+pub fn queue_region(region: &str, playlist_id: &str) -> String {
+    if playlist_id.is_empty() {
+        region.to_string()
+    } else {
+        format!("{region}:{playlist_id}")
+    }
+}
+
+/// Ranked and quickplay queue regions of every region and configured playlist, including inactive
+/// playlists so players queued before a mode ended are still matched.
+pub async fn queue_regions(
+    conn: &mut MultiplexedConnection,
+    regions: &[String],
+) -> Result<Vec<String>, Error> {
+    let playlists = get_playlists(conn).await?;
+
+    Ok(regions
+        .iter()
+        .flat_map(|region| {
+            std::iter::once(region.clone()).chain(
+                playlists
+                    .iter()
+                    .map(|playlist| queue_region(region, &playlist.id)),
+            )
+        })
+        .flat_map(ranked::queue_regions)
+        .collect())
+}
+
+/// Ids of the clan playlists
+pub async fn clan_playlists(conn: &mut MultiplexedConnection) -> Result<Vec<String>, Error> {
+    Ok(get_playlists(conn)
+        .await?
+        .into_iter()
+        .filter(|playlist| playlist.clan)
+        .map(|playlist| playlist.id)
+        .collect())
+}
+
+/// Ids of the raid playlists
+pub async fn raid_playlists(conn: &mut MultiplexedConnection) -> Result<Vec<String>, Error> {
+    Ok(get_playlists(conn)
+        .await?
+        .into_iter()
+        .filter(|playlist| playlist.raid.is_some())
+        .map(|playlist| playlist.id)
+        .collect())
+}
+
+/// Ranked and quickplay queue regions of the raid playlists with their format
+pub async fn raid_queue_regions(
+    conn: &mut MultiplexedConnection,
+    regions: &[String],
+) -> Result<Vec<(String, RaidFormat)>, Error> {
+    let playlists = get_playlists(conn).await?;
+
+    Ok(regions
+        .iter()
+        .flat_map(|region| {
+            playlists
+                .iter()
+                .filter_map(|playlist| Some((queue_region(region, &playlist.id), playlist.raid?)))
+        })
+        .flat_map(|(queue_region, format)| {
+            ranked::queue_regions(queue_region).map(|queue_region| (queue_region, format))
+        })
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::TimeZone;
+
+    use super::*;
+    use crate::test_support::{create_redis, redis_client};
+
+    #[test]
+    fn weekend_schedule() {
+        let weekend = Schedule::Weekly {
+            days: vec![Weekday::Sat, Weekday::Sun],
+            start_hour: 0,
+            end_hour: 24,
+        };
+        // 2025-10-11 is a saturday
+        let saturday = Utc.with_ymd_and_hms(2025, 10, 11, 18, 0, 0).unwrap();
+        let monday = Utc.with_ymd_and_hms(2025, 10, 13, 18, 0, 0).unwrap();
+
+        assert!(weekend.is_active(saturday));
+        assert!(!weekend.is_active(monday));
+    }
+
+    #[test]
+    fn limited_time_schedule() {
+        let starts_at = Utc.with_ymd_and_hms(2025, 10, 1, 0, 0, 0).unwrap();
+        let ends_at = Utc.with_ymd_and_hms(2025, 10, 8, 0, 0, 0).unwrap();
+        let event = Schedule::Between { starts_at, ends_at };
+
+        assert!(event.is_active(starts_at));
+        assert!(!event.is_active(ends_at));
+    }
+
+    #[test]
+    fn playlist_from_config() {
+        let config = r#"[{
+            "id": "horde",
+            "name": "Weekend horde",
+            "schedules": [{"weekly": {"days": ["Sat", "Sun"], "start_hour": 0, "end_hour": 24}}]
+        }, {
+            "id": "classic",
+            "name": "Classic",
+            "schedules": ["always"]
+        }]"#;
+
+        let playlists: Vec<Playlist> = serde_json::from_str(config).unwrap();
+
+        assert_eq!(playlists[0].id, "horde");
+        assert_eq!(playlists[1].schedules, vec![Schedule::Always]);
+    }
+
+    #[test]
+    fn playlist_queue_region() {
+        assert_eq!(queue_region("CAN", ""), "CAN");
+        assert_eq!(queue_region("CAN", "horde"), "CAN:horde");
+    }
+
+    #[tokio::test]
+    async fn active_playlists() {
+        let container = create_redis(6379).await;
+        let host = container.get_host().await.unwrap();
+        let port = container.get_host_port_ipv4(6379).await.unwrap();
+        let client = redis_client(host.to_string(), port);
+        let mut conn = client.get_multiplexed_async_connection().await.unwrap();
+        let saturday = Utc.with_ymd_and_hms(2025, 10, 11, 18, 0, 0).unwrap();
+        let playlists = vec![Playlist {
+            id: "horde".to_string(),
+            name: "Weekend horde".to_string(),
+            schedules: vec![Schedule::Weekly {
+                days: vec![Weekday::Sat, Weekday::Sun],
+                start_hour: 0,
+                end_hour: 24,
+            }],
+            raid: None,
+            clan: false,
+        }];
+
+        set_playlists(&mut conn, &playlists).await.unwrap();
+        let active = check_active(&mut conn, "horde", saturday).await;
+        let inactive = check_active(&mut conn, "horde", saturday + chrono::Duration::days(2)).await;
+        let unknown = check_active(&mut conn, "raid", saturday).await;
+        let default = check_active(&mut conn, "", saturday).await;
+        let regions = queue_regions(&mut conn, &["CAN".to_string()])
+            .await
+            .unwrap();
+
+        assert!(active.is_ok());
+        assert!(matches!(inactive, Err(Error::Inactive(_))));
+        assert!(matches!(unknown, Err(Error::Unknown(_))));
+        assert!(default.is_ok());
+        assert_eq!(
+            regions,
+            vec![
+                "CAN".to_string(),
+                "CAN:quickplay".to_string(),
+                "CAN:horde".to_string(),
+                "CAN:horde:quickplay".to_string(),
+            ]
+        );
+    }
+}
@@ -0,0 +1,253 @@
+//! Matchmaking preferences a player keeps between sessions: preferred regions, default
+//! difficulty, input (crossplay), voice chat and languages. They are stored in Nakama storage
+//! and fill the fields a join leaves at their default value, so clients can send bare joins.
+
+use std::sync::Arc;
+
+use serde::{Deserialize, Serialize};
+use tonic::Code;
+use tonic_types::{ErrorDetails, StatusExt};
+use tracing::warn;
+use uuid::Uuid;
+
+use crate::{
+    nakama::{self, Authenticated, NakamaClient},
+    rpc::matchmaking::{InputDevice, MatchmakingPreferences, Player, VoiceChat},
+};
+
+/// Nakama storage of the preferences of a player, written by `SetPreferences`
+pub const PREFERENCES_COLLECTION: &str = "matchmaking";
+pub const PREFERENCES_KEY: &str = "preferences";
+pub const MAX_PREFERRED_REGIONS: usize = 8;
+pub const MAX_LANGUAGES: usize = 8;
+
+#[derive(Debug, thiserror::Error)]
+pub enum Error {
+    #[error("invalid preference `{field}`: {reason}")]
+    Invalid { field: &'static str, reason: String },
+    #[error(transparent)]
+    Nakama(#[from] nakama::Error),
+}
+
+impl From<Error> for tonic::Status {
+    fn from(value: Error) -> Self {
+        match value {
+            Error::Invalid { field, .. } => Self::with_error_details(
+                Code::InvalidArgument,
+                value.to_string(),
+                ErrorDetails::with_bad_request_violation(field, value.to_string()),
+            ),
+            Error::Nakama(_) => Self::internal("Failed to access matchmaking preferences"),
+        }
+    }
+}
+
+/// Stored form of [`MatchmakingPreferences`], enums keep their proto numbers
+#[derive(Debug, Clone, Default, Serialize, Deserialize, PartialEq, Eq)]
+pub struct Preferences {
+    #[serde(default)]
+    pub regions: Vec<String>,
+    #[serde(default)]
+    pub difficulty: i32,
+    #[serde(default)]
+    pub input_device: i32,
+    #[serde(default)]
+    pub voice_chat: i32,
+    #[serde(default)]
+    pub languages: Vec<String>,
+}
+
+impl From<MatchmakingPreferences> for Preferences {
+    fn from(value: MatchmakingPreferences) -> Self {
+        Self {
+            regions: value.regions,
+            difficulty: value.difficulty,
+            input_device: value.input_device,
+            voice_chat: value.voice_chat,
+            languages: value.languages,
+        }
+    }
+}
+
+impl From<Preferences> for MatchmakingPreferences {
+    fn from(value: Preferences) -> Self {
+        Self {
+            regions: value.regions,
+            difficulty: value.difficulty,
+            input_device: value.input_device,
+            voice_chat: value.voice_chat,
+            languages: value.languages,
+        }
+    }
+}
+
+impl Preferences {
+    /// Checks the preferences fit the joins they default: `served` regions, any region when none
+    /// are registered, and difficulties up to `max_difficulty`
+    pub fn validate(&self, served: &[String], max_difficulty: i32) -> Result<(), Error> {
+        let invalid = |field, reason: String| Err(Error::Invalid { field, reason });
+        if self.regions.len() > MAX_PREFERRED_REGIONS {
+            return invalid(
+                "regions",
+                format!("at most {MAX_PREFERRED_REGIONS} regions"),
+            );
+        }
+        if let Some(region) = self.regions.iter().find(|region| {
+            region.trim().is_empty() || (!served.is_empty() && !served.contains(region))
+        }) {
+            return invalid("regions", format!("region `{region}` is not served"));
+        }
+        if !(0..=max_difficulty).contains(&self.difficulty) {
+            return invalid("difficulty", format!("outside 0..={max_difficulty}"));
+        }
+        if InputDevice::try_from(self.input_device).is_err() {
+            return invalid(
+                "input_device",
+                format!("unknown value {}", self.input_device),
+            );
+        }
+        if VoiceChat::try_from(self.voice_chat).is_err() {
+            return invalid("voice_chat", format!("unknown value {}", self.voice_chat));
+        }
+        if self.languages.len() > MAX_LANGUAGES {
+            return invalid("languages", format!("at most {MAX_LANGUAGES} languages"));
+        }
+
+        Ok(())
+    }
+
+    /// Fills the fields `player` left at their default value, but the region, see
+    /// [`Preferences::region`]
+    pub fn apply(&self, player: &mut Player) {
+        if player.difficulty == 0 {
+            player.difficulty = self.difficulty;
+        }
+        if player.input_device == 0 {
+            player.input_device = self.input_device;
+        }
+        if player.voice_chat == 0 {
+            player.voice_chat = self.voice_chat;
+        }
+        if player.languages.is_empty() {
+            player.languages.clone_from(&self.languages);
+        }
+    }
+
+    /// Region of a join declaring `requested`, the first preferred region still `served` when
+    /// it declares none
+    pub fn region<'a>(&'a self, requested: &'a str, served: &[String]) -> &'a str {
+        if !requested.is_empty() {
+            return requested;
+        }
+
+        self.regions
+            .iter()
+            .find(|region| served.is_empty() || served.contains(region))
+            .map_or(requested, String::as_str)
+    }
+}
+
+/// Preferences stored in Nakama, the defaults when the player never set them
+pub async fn load(
+    nakama_client: &NakamaClient<Authenticated>,
+    http_client: Arc<reqwest::Client>,
+    player_id: &Uuid,
+) -> Result<Preferences, Error> {
+    let preferences = nakama_client
+        .read_storage(
+            http_client,
+            PREFERENCES_COLLECTION,
+            PREFERENCES_KEY,
+            &player_id.to_string(),
+        )
+        .await?;
+
+    Ok(preferences.unwrap_or_default())
+}
+
+/// Preferences defaulting a join, a Nakama outage queues the join as it was sent
+pub async fn load_for_join(
+    nakama_client: &NakamaClient<Authenticated>,
+    http_client: Arc<reqwest::Client>,
+    player_id: &Uuid,
+) -> Preferences {
+    load(nakama_client, http_client, player_id)
+        .await
+        .inspect_err(|err| warn!("Failed to load preferences of `{player_id}`: {err}"))
+        .unwrap_or_default()
+}
+
+pub async fn save(
+    nakama_client: &NakamaClient<Authenticated>,
+    http_client: Arc<reqwest::Client>,
+    player_id: &Uuid,
+    preferences: &Preferences,
+) -> Result<(), Error> {
+    nakama_client
+        .write_storage(
+            http_client,
+            PREFERENCES_COLLECTION,
+            PREFERENCES_KEY,
+            &player_id.to_string(),
+            preferences,
+        )
+        .await?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn preferences() -> Preferences {
+        Preferences {
+            regions: vec!["SA".to_string(), "US".to_string()],
+            difficulty: 3,
+            input_device: InputDevice::Controller.into(),
+            voice_chat: VoiceChat::MicRequired.into(),
+            languages: vec!["pt".to_string()],
+        }
+    }
+
+    #[test]
+    fn preferences_fill_the_omitted_fields() {
+        let served = vec!["US".to_string(), "CAN".to_string()];
+        let mut bare = Player::default();
+        let mut explicit = Player {
+            region: "CAN".to_string(),
+            difficulty: 1,
+            input_device: InputDevice::MouseKeyboard.into(),
+            languages: vec!["en".to_string()],
+            ..Default::default()
+        };
+
+        preferences().apply(&mut bare);
+        preferences().apply(&mut explicit);
+
+        assert_eq!(bare.difficulty, 3);
+        assert_eq!(bare.input_device(), InputDevice::Controller);
+        assert_eq!(bare.voice_chat(), VoiceChat::MicRequired);
+        assert_eq!(bare.languages, vec!["pt"]);
+        assert_eq!(preferences().region(&bare.region, &served), "US");
+        assert_eq!(explicit.difficulty, 1);
+        assert_eq!(explicit.input_device(), InputDevice::MouseKeyboard);
+        assert_eq!(explicit.languages, vec!["en"]);
+        assert_eq!(preferences().region(&explicit.region, &served), "CAN");
+        assert_eq!(Preferences::default().region("", &served), "");
+    }
+
+    #[test]
+    fn preferences_are_validated() {
+        let served = vec!["SA".to_string(), "US".to_string()];
+
+        assert!(preferences().validate(&served, 5).is_ok());
+        assert!(preferences().validate(&served[..1], 5).is_err());
+        assert!(preferences().validate(&served, 2).is_err());
+        let unknown_input = Preferences {
+            input_device: 9,
+            ..preferences()
+        };
+        assert!(unknown_input.validate(&served, 5).is_err());
+    }
+}
@@ -0,0 +1,92 @@
+//! Queue abandonment detected from the `Heartbeat` stream. Every acknowledged probe records when
+//! the player was last seen, and the worker drops queued players unseen for longer than
+//! [`abandon_after`] from the queues and open matches, instead of waiting for their queue entry
+//! to expire. Players that never sent a heartbeat are only dropped once their entry expires.
+
+use redis::{AsyncCommands, RedisError, aio::MultiplexedConnection};
+use uuid::Uuid;
+
+use crate::namespace;
+
+pub const PRESENCE_KEY: &str = "presence:heartbeats";
+/// Env var with the seconds without heartbeat after which a queued player abandoned the queue
+pub const ABANDON_AFTER_VAR: &str = "QUEUE_ABANDON_SECS";
+/// Fifteen missed heartbeats
+pub const DEFAULT_ABANDON_AFTER: i64 = 30;
+
+pub fn presence_key() -> String {
+    namespace::key(PRESENCE_KEY)
+}
+
+pub fn abandon_after() -> i64 {
+    std::env::var(ABANDON_AFTER_VAR)
+        .ok()
+        .and_then(|secs| secs.parse().ok())
+        .filter(|secs| *secs > 0)
+        .unwrap_or(DEFAULT_ABANDON_AFTER)
+}
+
+/// Records that `player_id` was seen at `now`
+pub async fn seen(
+    conn: &mut MultiplexedConnection,
+    player_id: &Uuid,
+    now: i64,
+) -> Result<(), RedisError> {
+    conn.zadd(presence_key(), player_id, now).await
+}
+
+/// Players last seen before `now - after`, at most `limit`
+pub async fn abandoned(
+    conn: &mut MultiplexedConnection,
+    now: i64,
+    after: i64,
+    limit: isize,
+) -> Result<Vec<Uuid>, RedisError> {
+    conn.zrangebyscore_limit(
+        presence_key(),
+        "-inf",
+        format!("({}", now - after),
+        0,
+        limit,
+    )
+    .await
+}
+
+/// Stops tracking the players, e.g. once they were dropped or joined again
+pub async fn forget(
+    conn: &mut MultiplexedConnection,
+    player_ids: &[Uuid],
+) -> Result<(), RedisError> {
+    if player_ids.is_empty() {
+        return Ok(());
+    }
+
+    conn.zrem(presence_key(), player_ids).await
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+    use crate::test_support::{create_redis, redis_client};
+
+    #[tokio::test]
+    async fn players_unseen_for_too_long_abandoned() {
+        let container = create_redis(6379).await;
+        let host = container.get_host().await.unwrap();
+        let port = container.get_host_port_ipv4(6379).await.unwrap();
+        let client = redis_client(host.to_string(), port);
+        let mut conn = client.get_multiplexed_async_connection().await.unwrap();
+        let (gone, limit, present) = (Uuid::new_v4(), Uuid::new_v4(), Uuid::new_v4());
+
+        seen(&mut conn, &gone, 100).await.unwrap();
+        seen(&mut conn, &limit, 170).await.unwrap();
+        seen(&mut conn, &present, 190).await.unwrap();
+        let abandoned_players = abandoned(&mut conn, 200, 30, 10).await.unwrap();
+        forget(&mut conn, &abandoned_players).await.unwrap();
+        let after_forget = abandoned(&mut conn, 200, 30, 10).await.unwrap();
+
+        assert_eq!(abandoned_players, vec![gone]);
+        assert!(after_forget.is_empty());
+    }
+}
@@ -0,0 +1,292 @@
+//! Kubernetes probes, served over plain HTTP on [`PROBES_PORT_VAR`], apart from the gRPC port.
+//!
+//! - [`LIVENESS_PATH`] answers while the tokio runtime still runs spawned tasks, so a stuck
+//!   instance is restarted.
+//! - [`READINESS_PATH`] answers while Redis and Nakama respond and the worker loop ran recently,
+//!   so traffic leaves an instance that cannot matchmake. The body lists the failed checks.
+
+use std::{
+    sync::{Arc, Mutex, PoisonError},
+    time::{Duration, Instant},
+};
+
+use redis::aio::MultiplexedConnection;
+use tokio::{
+    io::{AsyncBufReadExt, AsyncWriteExt, BufReader},
+    net::{TcpListener, TcpStream},
+};
+use tracing::{debug, warn};
+
+use crate::nakama::{Authenticated, NakamaClient};
+
+/// Env var with the port of the probes, defaults to [`DEFAULT_PORT`]
+pub const PROBES_PORT_VAR: &str = "PROBES_PORT";
+pub const DEFAULT_PORT: u16 = 8081;
+/// Env var with the seconds without a worker run after which the instance is not ready
+pub const WORKER_STALE_VAR: &str = "PROBES_WORKER_STALE_SECS";
+/// Three times the longest worker backoff
+pub const DEFAULT_WORKER_STALE: Duration = Duration::from_secs(180);
+pub const LIVENESS_PATH: &str = "/livez";
+pub const READINESS_PATH: &str = "/readyz";
+/// Longest wait on each check
+pub const CHECK_TIMEOUT: Duration = Duration::from_secs(2);
+
+pub fn port() -> u16 {
+    std::env::var(PROBES_PORT_VAR)
+        .ok()
+        .and_then(|port| port.parse().ok())
+        .unwrap_or(DEFAULT_PORT)
+}
+
+pub fn worker_stale_after() -> Duration {
+    std::env::var(WORKER_STALE_VAR)
+        .ok()
+        .and_then(|secs| secs.parse().ok())
+        .map_or(DEFAULT_WORKER_STALE, Duration::from_secs)
+}
+
+/// Last time the worker loop ran, shared between the loop and the probes
+#[derive(Debug, Clone)]
+pub struct Heartbeat(Arc<Mutex<Instant>>);
+
+impl Default for Heartbeat {
+    fn default() -> Self {
+        Self(Arc::new(Mutex::new(Instant::now())))
+    }
+}
+
+impl Heartbeat {
+    /// Records a run, failed runs count too as the loop is alive
+    pub fn beat(&self) {
+        *self.0.lock().unwrap_or_else(PoisonError::into_inner) = Instant::now();
+    }
+
+    /// Time since the last run, or since the heartbeat was created
+    pub fn elapsed(&self) -> Duration {
+        self.0
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .elapsed()
+    }
+}
+
+/// Dependencies checked by the readiness probe
+#[derive(Debug, Clone)]
+pub struct Probes {
+    pub redis: MultiplexedConnection,
+    pub http_client: Arc<reqwest::Client>,
+    pub nakama_client: Arc<NakamaClient<Authenticated>>,
+    pub worker: Heartbeat,
+    pub worker_stale_after: Duration,
+}
+
+impl Probes {
+    /// Whether a spawned task runs within [`CHECK_TIMEOUT`]
+    pub async fn live(&self) -> bool {
+        tokio::time::timeout(CHECK_TIMEOUT, tokio::spawn(async {}))
+            .await
+            .is_ok_and(|joined| joined.is_ok())
+    }
+
+    /// Names of the failed readiness checks, empty when ready
+    pub async fn not_ready(&self) -> Vec<&'static str> {
+        let mut redis = self.redis.clone();
+        let ping = redis::cmd("PING");
+        let (redis, nakama) = tokio::join!(
+            tokio::time::timeout(CHECK_TIMEOUT, ping.query_async::<String>(&mut redis)),
+            tokio::time::timeout(
+                CHECK_TIMEOUT,
+                self.nakama_client.healthcheck(&self.http_client)
+            ),
+        );
+
+        let mut failed = Vec::new();
+        if !redis.is_ok_and(|pong| pong.is_ok()) {
+            failed.push("redis");
+        }
+        if !nakama.is_ok_and(|healthy| healthy.is_ok_and(|healthy| healthy)) {
+            failed.push("nakama");
+        }
+        if self.worker.elapsed() > self.worker_stale_after {
+            failed.push("worker");
+        }
+
+        failed
+    }
+
+    /// Status and body of a request to `path`
+    pub async fn respond(&self, path: &str) -> (u16, String) {
+        match path {
+            LIVENESS_PATH if self.live().await => (200, "ok".to_string()),
+            LIVENESS_PATH => (503, "runtime".to_string()),
+            READINESS_PATH => match self.not_ready().await.as_slice() {
+                [] => (200, "ok".to_string()),
+                failed => (503, failed.join(",")),
+            },
+            _ => (404, "not found".to_string()),
+        }
+    }
+}
+
+/// Answers the probes on `listener` until the process stops
+pub async fn serve(listener: TcpListener, probes: Arc<Probes>) {
+    loop {
+        match listener.accept().await {
+            Ok((stream, _)) => {
+                let probes = probes.clone();
+                tokio::spawn(async move {
+                    if let Err(err) = handle(stream, &probes).await {
+                        debug!("probe connection: {err}");
+                    }
+                });
+            }
+            Err(err) => warn!("failed to accept a probe connection: {err}"),
+        }
+    }
+}
+
+async fn handle(stream: TcpStream, probes: &Probes) -> std::io::Result<()> {
+    let mut stream = BufReader::new(stream);
+    let mut request_line = String::new();
+    stream.read_line(&mut request_line).await?;
+    let (status, body) = match request_path(&request_line) {
+        Some(path) => probes.respond(path).await,
+        None => (405, "method not allowed".to_string()),
+    };
+
+    stream
+        .get_mut()
+        .write_all(response(status, &body).as_bytes())
+        .await?;
+    stream.get_mut().shutdown().await
+}
+
+/// Path of a `GET` request line, without its query
+fn request_path(request_line: &str) -> Option<&str> {
+    let mut parts = request_line.split_whitespace();
+    if parts.next() != Some("GET") {
+        return None;
+    }
+
+    parts
+        .next()
+        .map(|target| target.split('?').next().unwrap_or(target))
+}
+
+fn response(status: u16, body: &str) -> String {
+    let reason = match status {
+        200 => "OK",
+        404 => "Not Found",
+        405 => "Method Not Allowed",
+        _ => "Service Unavailable",
+    };
+
+    format!(
+        "HTTP/1.1 {status} {reason}\r\nContent-Type: text/plain\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{body}",
+        body.len()
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use httpmock::prelude::*;
+    use serde_json::json;
+    use tokio::io::AsyncReadExt;
+
+    use super::*;
+    use crate::test_support::{create_redis, redis_client};
+
+    #[test]
+    fn parse_request_line() {
+        assert_eq!(request_path("GET /readyz HTTP/1.1\r\n"), Some("/readyz"));
+        assert_eq!(
+            request_path("GET /livez?verbose HTTP/1.1\r\n"),
+            Some("/livez")
+        );
+        assert_eq!(request_path("POST /livez HTTP/1.1\r\n"), None);
+        assert!(response(503, "redis").starts_with("HTTP/1.1 503 Service Unavailable\r\n"));
+    }
+
+    #[test]
+    fn heartbeat_tracks_the_last_run() {
+        let heartbeat = Heartbeat(Arc::new(Mutex::new(
+            Instant::now() - Duration::from_secs(60),
+        )));
+        assert!(heartbeat.elapsed() >= Duration::from_secs(60));
+
+        let worker_loop = heartbeat.clone();
+        worker_loop.beat();
+        assert!(heartbeat.elapsed() < Duration::from_secs(60));
+    }
+
+    #[tokio::test]
+    async fn readiness_reflects_the_dependencies() {
+        let container = create_redis(6379).await;
+        let host = container.get_host().await.unwrap();
+        let port = container.get_host_port_ipv4(6379).await.unwrap();
+        let client = redis_client(host.to_string(), port);
+        let conn = client.get_multiplexed_async_connection().await.unwrap();
+        let nakama = MockServer::start_async().await;
+        nakama
+            .mock_async(|when, then| {
+                when.method(POST)
+                    .path("/v2/console/api/endpoints/rpc/healthcheck");
+                then.status(200)
+                    .header("content-type", "application/json")
+                    .json_body(json!({"body": "{\"success\": true}", "error_message": ""}));
+            })
+            .await;
+        let probes = Probes {
+            redis: conn,
+            http_client: Arc::new(reqwest::Client::new()),
+            nakama_client: Arc::new(auth_client(nakama.address().port())),
+            worker: Heartbeat::default(),
+            worker_stale_after: DEFAULT_WORKER_STALE,
+        };
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(serve(listener, Arc::new(probes.clone())));
+
+        let ready = get(addr, READINESS_PATH).await;
+        let live = get(addr, LIVENESS_PATH).await;
+        let stale = Probes {
+            worker: Heartbeat(Arc::new(Mutex::new(
+                Instant::now() - Duration::from_secs(600),
+            ))),
+            ..probes.clone()
+        };
+        let stale_worker = stale.respond(READINESS_PATH).await;
+        let redis_down = probes.respond(READINESS_PATH).await;
+
+        assert!(ready.starts_with("HTTP/1.1 200 OK"), "{ready}");
+        assert!(live.starts_with("HTTP/1.1 200 OK"), "{live}");
+        assert_eq!(stale_worker, (503, "worker".to_string()));
+        assert_eq!(redis_down, (503, "redis".to_string()));
+    }
+
+    async fn get(addr: std::net::SocketAddr, path: &str) -> String {
+        let mut stream = TcpStream::connect(addr).await.unwrap();
+        stream
+            .write_all(format!("GET {path} HTTP/1.1\r\nHost: localhost\r\n\r\n").as_bytes())
+            .await
+            .unwrap();
+        let mut response = String::new();
+        stream.read_to_string(&mut response).await.unwrap();
+
+        response
+    }
+
+    fn auth_client(port: u16) -> NakamaClient<Authenticated> {
+        NakamaClient {
+            username: "username".to_string(),
+            password: "password".to_string(),
+            token: Some("super_random_token".to_string()),
+            url: format!("http://127.0.0.1:{port}"),
+            server_key_name: "defaultkey".to_string(),
+            server_key_value: "server_key".to_string(),
+            encryption_key: "encryption_key".to_string(),
+            legacy_until: None,
+            _state: std::marker::PhantomData::<Authenticated>,
+        }
+    }
+}
@@ -0,0 +1,143 @@
+//! Named configuration profiles. [`APP_ENV_VAR`] selects `dev`, `staging` or `prod`, and the env
+//! file of the profile, e.g. `profiles/staging.env`, sets its Nakama endpoint, Redis URL, worker
+//! interval and log level. Vars already set in the process win over the profile file, and the
+//! profile file wins over the `.env` file shared by every profile, so [`APP_ENV_VAR`] itself is
+//! read from the process env only. Without [`APP_ENV_VAR`] no profile file is loaded, so a local
+//! `.env` is not shadowed by `dev.env`.
+
+use std::{
+    fmt::Display,
+    path::{Path, PathBuf},
+    str::FromStr,
+};
+
+/// Env var selecting the profile, defaults to `dev`
+pub const APP_ENV_VAR: &str = "APP_ENV";
+/// Env var with the directory of the profile files, defaults to [`DEFAULT_PROFILES_DIR`]
+pub const PROFILES_DIR_VAR: &str = "PROFILES_DIR";
+pub const DEFAULT_PROFILES_DIR: &str = "profiles";
+
+#[derive(Debug, thiserror::Error)]
+pub enum Error {
+    #[error("unknown {APP_ENV_VAR} `{0}`, expected dev, staging or prod")]
+    UnknownProfile(String),
+    #[error("failed to load profile file: {0}")]
+    Dotenv(#[from] dotenv::Error),
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum AppEnv {
+    #[default]
+    Dev,
+    Staging,
+    Prod,
+}
+
+impl FromStr for AppEnv {
+    type Err = Error;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value.trim().to_lowercase().as_str() {
+            "dev" | "development" => Ok(Self::Dev),
+            "staging" => Ok(Self::Staging),
+            "prod" | "production" => Ok(Self::Prod),
+            _ => Err(Error::UnknownProfile(value.to_string())),
+        }
+    }
+}
+
+impl Display for AppEnv {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            Self::Dev => "dev",
+            Self::Staging => "staging",
+            Self::Prod => "prod",
+        })
+    }
+}
+
+impl AppEnv {
+    pub fn from_env() -> Result<Self, Error> {
+        std::env::var(APP_ENV_VAR).map_or(Ok(Self::default()), |app_env| app_env.parse())
+    }
+
+    /// Log level when `LOG_LEVEL` is set neither in the process nor in the profile file
+    pub const fn log_level(self) -> tracing::Level {
+        match self {
+            Self::Dev => tracing::Level::DEBUG,
+            Self::Staging => tracing::Level::INFO,
+            Self::Prod => tracing::Level::WARN,
+        }
+    }
+
+    /// Env file of the profile in `dir`
+    pub fn file(self, dir: impl AsRef<Path>) -> PathBuf {
+        dir.as_ref().join(format!("{self}.env"))
+    }
+}
+
+/// Selects the profile of [`APP_ENV_VAR`] and loads its env file, when there is one. Only
+/// [`AppEnv::log_level`] of the default profile applies when [`APP_ENV_VAR`] is unset.
+/// Call it first, before any var is read.
+pub fn load() -> Result<AppEnv, Error> {
+    let Ok(app_env) = std::env::var(APP_ENV_VAR) else {
+        return Ok(AppEnv::default());
+    };
+    let app_env = app_env.parse::<AppEnv>()?;
+    let dir = std::env::var(PROFILES_DIR_VAR).unwrap_or_else(|_| DEFAULT_PROFILES_DIR.to_string());
+    load_file(app_env.file(dir))?;
+
+    Ok(app_env)
+}
+
+/// Sets the vars of the env file at `path` that are not set yet, nothing when it is missing
+pub fn load_file(path: impl AsRef<Path>) -> Result<(), Error> {
+    match dotenv::from_path(path.as_ref()) {
+        Err(dotenv::Error::Io(err)) if err.kind() == std::io::ErrorKind::NotFound => Ok(()),
+        result => Ok(result?),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_app_env() {
+        assert_eq!("prod".parse::<AppEnv>().unwrap(), AppEnv::Prod);
+        assert_eq!(" Staging ".parse::<AppEnv>().unwrap(), AppEnv::Staging);
+        assert_eq!("development".parse::<AppEnv>().unwrap(), AppEnv::Dev);
+        assert!(matches!(
+            "qa".parse::<AppEnv>(),
+            Err(Error::UnknownProfile(_))
+        ));
+        assert_eq!(
+            AppEnv::Staging.file("profiles"),
+            Path::new("profiles/staging.env")
+        );
+    }
+
+    #[test]
+    fn profile_file_keeps_process_vars() {
+        let dir = std::env::temp_dir().join(format!("profiles-{}", uuid::Uuid::new_v4()));
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(
+            AppEnv::Staging.file(&dir),
+            "PROFILE_TEST_HOST=nakama_staging\nPROFILE_TEST_LEVEL=INFO\n",
+        )
+        .unwrap();
+        unsafe {
+            std::env::set_var("PROFILE_TEST_LEVEL", "TRACE");
+        }
+
+        load_file(AppEnv::Staging.file(&dir)).unwrap();
+        load_file(AppEnv::Prod.file(&dir)).unwrap();
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        assert_eq!(
+            std::env::var("PROFILE_TEST_HOST").as_deref(),
+            Ok("nakama_staging")
+        );
+        assert_eq!(std::env::var("PROFILE_TEST_LEVEL").as_deref(), Ok("TRACE"));
+    }
+}
@@ -1,6 +1,52 @@
+use std::sync::Arc;
+
+use serde::{Deserialize, Serialize};
+use tonic::Code;
+use tonic_types::{ErrorDetails, FieldViolation, StatusExt};
 use uuid::Uuid;
 
+use crate::nakama::{self, Authenticated, NakamaClient};
+
+/// Nakama storage collection of the player progression, written by the game
+pub const PROGRESSION_COLLECTION: &str = "progression";
+pub const PROGRESSION_KEY: &str = "progression";
+
+#[derive(Debug, thiserror::Error)]
+pub enum Error {
+    #[error("loadout config is not valid: {0}")]
+    MalformedLoadout(#[from] serde_json::Error),
+    #[error("loadout uses entries the player has not unlocked: {0:?}")]
+    InvalidLoadout(Vec<String>),
+    #[error(transparent)]
+    Nakama(#[from] nakama::Error),
+}
+
+impl From<Error> for tonic::Status {
+    fn from(value: Error) -> Self {
+        match value {
+            Error::MalformedLoadout(_) => Self::with_error_details(
+                Code::InvalidArgument,
+                value.to_string(),
+                ErrorDetails::with_bad_request_violation("loadout_config", value.to_string()),
+            ),
+            Error::InvalidLoadout(ref entries) => {
+                let violations: Vec<FieldViolation> = entries
+                    .iter()
+                    .map(|entry| FieldViolation::new("loadout_config", entry))
+                    .collect();
+                Self::with_error_details(
+                    Code::InvalidArgument,
+                    value.to_string(),
+                    ErrorDetails::with_bad_request(violations),
+                )
+            }
+            Error::Nakama(_) => Self::internal("Failed to load player progression"),
+        }
+    }
+}
+
 /// Gets player progression
+#[derive(Debug, Clone, Default, Serialize, Deserialize, PartialEq)]
 pub struct Progression {
     pub level: u32,
     pub xp: u32,
@@ -10,8 +56,195 @@ pub struct Progression {
 }
 
 /// Inventory items
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
 pub struct InventoryItems {
     pub id: Uuid,
     pub rolls: Vec<Uuid>,
     pub rarity: u8,
 }
+
+/// Items and skills requested in `Player.loadout_config`, a JSON object
+#[derive(Debug, Clone, Default, Serialize, Deserialize, PartialEq, Eq)]
+pub struct Loadout {
+    #[serde(default)]
+    pub items: Vec<Uuid>,
+    #[serde(default)]
+    pub skills: Vec<Uuid>,
+}
+
+impl Loadout {
+    /// An empty config is the default loadout
+    pub fn parse(config: &str) -> Result<Self, Error> {
+        if config.trim().is_empty() {
+            return Ok(Self::default());
+        }
+
+        Ok(serde_json::from_str(config)?)
+    }
+
+    pub const fn is_empty(&self) -> bool {
+        self.items.is_empty() && self.skills.is_empty()
+    }
+}
+
+impl Progression {
+    /// Every item of `loadout` must be in the inventory and every skill unlocked
+    pub fn validate_loadout(&self, loadout: &Loadout) -> Result<(), Error> {
+        let items = loadout
+            .items
+            .iter()
+            .filter(|id| !self.inventory_items.iter().any(|item| item.id == **id))
+            .map(|id| format!("item `{id}`"));
+        let skills = loadout
+            .skills
+            .iter()
+            .filter(|id| !self.skills_unlocked.contains(id))
+            .map(|id| format!("skill `{id}`"));
+        let invalid: Vec<String> = items.chain(skills).collect();
+        if !invalid.is_empty() {
+            return Err(Error::InvalidLoadout(invalid));
+        }
+
+        Ok(())
+    }
+
+    /// Inventory entries of the loadout items
+    pub fn equipped(&self, loadout: &Loadout) -> Vec<InventoryItems> {
+        self.inventory_items
+            .iter()
+            .filter(|item| loadout.items.contains(&item.id))
+            .cloned()
+            .collect()
+    }
+}
+
+/// Rating modifier of the equipped items, `tier_modifier` per rarity tier up to `max_modifier`
+pub fn loadout_modifier(equipped: &[InventoryItems], tier_modifier: f64, max_modifier: f64) -> f64 {
+    equipped
+        .iter()
+        .map(|item| f64::from(item.rarity) * tier_modifier)
+        .sum::<f64>()
+        .min(max_modifier)
+}
+
+/// Progression stored in Nakama, players without one have not unlocked anything
+pub async fn progression(
+    nakama_client: &NakamaClient<Authenticated>,
+    http_client: Arc<reqwest::Client>,
+    player_id: &Uuid,
+) -> Result<Progression, Error> {
+    let progression = nakama_client
+        .read_storage(
+            http_client,
+            PROGRESSION_COLLECTION,
+            PROGRESSION_KEY,
+            &player_id.to_string(),
+        )
+        .await?;
+
+    Ok(progression.unwrap_or_default())
+}
+
+/// Validates `config` against the player progression, only loading it for custom loadouts.
+/// Returns the equipped items, empty for the default loadout.
+pub async fn check_loadout(
+    nakama_client: &NakamaClient<Authenticated>,
+    http_client: Arc<reqwest::Client>,
+    player_id: &Uuid,
+    config: &str,
+) -> Result<Vec<InventoryItems>, Error> {
+    let loadout = Loadout::parse(config)?;
+    if loadout.is_empty() {
+        return Ok(Vec::new());
+    }
+    let progression = progression(nakama_client, http_client, player_id).await?;
+    progression.validate_loadout(&loadout)?;
+
+    Ok(progression.equipped(&loadout))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn item(id: Uuid) -> InventoryItems {
+        InventoryItems {
+            id,
+            rolls: Vec::new(),
+            rarity: 0,
+        }
+    }
+
+    #[test]
+    fn item_tiers_add_up_to_the_cap() {
+        let (sword, shield) = (Uuid::new_v4(), Uuid::new_v4());
+        let progression = Progression {
+            inventory_items: vec![
+                InventoryItems {
+                    rarity: 1,
+                    ..item(sword)
+                },
+                InventoryItems {
+                    rarity: 3,
+                    ..item(shield)
+                },
+                item(Uuid::new_v4()),
+            ],
+            ..Default::default()
+        };
+        let equipped = progression.equipped(&Loadout {
+            items: vec![sword, shield],
+            skills: Vec::new(),
+        });
+
+        assert_eq!(equipped.len(), 2);
+        assert_eq!(loadout_modifier(&equipped, 0.5, 3.), 2.);
+        assert_eq!(loadout_modifier(&equipped, 1., 3.), 3.);
+        assert_eq!(loadout_modifier(&[], 0.5, 3.), 0.);
+    }
+
+    #[test]
+    fn empty_config_is_the_default_loadout() {
+        assert!(Loadout::parse("").unwrap().is_empty());
+        assert!(Loadout::parse("{}").unwrap().is_empty());
+        assert!(matches!(
+            Loadout::parse("sword"),
+            Err(Error::MalformedLoadout(_))
+        ));
+    }
+
+    #[test]
+    fn loadout_must_be_unlocked() {
+        let (sword, shield, dash, blink) = (
+            Uuid::new_v4(),
+            Uuid::new_v4(),
+            Uuid::new_v4(),
+            Uuid::new_v4(),
+        );
+        let progression = Progression {
+            skills_unlocked: vec![dash],
+            inventory_items: vec![item(sword)],
+            ..Default::default()
+        };
+
+        assert!(
+            progression
+                .validate_loadout(&Loadout {
+                    items: vec![sword],
+                    skills: vec![dash],
+                })
+                .is_ok()
+        );
+        let err = progression
+            .validate_loadout(&Loadout {
+                items: vec![sword, shield],
+                skills: vec![dash, blink],
+            })
+            .unwrap_err();
+        assert!(matches!(
+            err,
+            Error::InvalidLoadout(entries)
+                if entries == vec![format!("item `{shield}`"), format!("skill `{blink}`")]
+        ));
+    }
+}
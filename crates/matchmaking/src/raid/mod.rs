@@ -0,0 +1,242 @@
+//! Raids assembled from independent parties, e.g. 3 squads of 4. Players of a raid playlist
+//! wait in its raid queue, parties of similar skill are packed into squads and the squads are
+//! kept on the match so they can be rated as teams with [`mhth_multi_team`].
+
+use serde::{Deserialize, Serialize};
+use skillratings::{
+    MultiTeamOutcome,
+    mhth::{MhthConfig, MhthRating, mhth_multi_team},
+};
+use uuid::Uuid;
+
+use crate::{
+    balance,
+    lifecycle::Lifecycle,
+    rpc::{Match, MatchKind, QueuedPlayer},
+};
+
+/// Squads of a raid playlist, see [`crate::playlists::Playlist::raid`]
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
+pub struct RaidFormat {
+    pub squads: usize,
+    pub squad_size: usize,
+}
+
+impl RaidFormat {
+    pub const fn players(&self) -> usize {
+        self.squads * self.squad_size
+    }
+}
+
+/// Parties by average skill, each party is a list of player indexes
+fn parties_by_skill(players: &[QueuedPlayer], squad_size: usize) -> Vec<Vec<usize>> {
+    let mut parties: Vec<Vec<usize>> = balance::parties(players)
+        .into_iter()
+        .filter(|party| party.len() <= squad_size)
+        .collect();
+    let skill = |party: &Vec<usize>| {
+        party
+            .iter()
+            .map(|i| players[*i].skillrating.rating)
+            .sum::<f64>()
+            / party.len() as f64
+    };
+    parties.sort_by(|a, b| skill(a).total_cmp(&skill(b)));
+
+    parties
+}
+
+/// Packs the parties into full squads, each party in the first squad it fits.
+/// Returns the player indexes of each squad and the parties used.
+fn pack(parties: &[Vec<usize>], format: RaidFormat) -> Option<(Vec<Vec<usize>>, Vec<usize>)> {
+    let mut squads: Vec<Vec<usize>> = Vec::new();
+    let mut used = Vec::new();
+    for (index, party) in parties.iter().enumerate() {
+        let squad = match squads
+            .iter()
+            .position(|squad| squad.len() + party.len() <= format.squad_size)
+        {
+            Some(squad) => squad,
+            None if squads.len() < format.squads => {
+                squads.push(Vec::new());
+                squads.len() - 1
+            }
+            None => continue,
+        };
+        squads[squad].extend(party);
+        used.push(index);
+        if squads.len() == format.squads
+            && squads.iter().all(|squad| squad.len() == format.squad_size)
+        {
+            return Some((squads, used));
+        }
+    }
+
+    None
+}
+
+/// Raids of similar skill formed from `players`, as the player indexes of each squad
+pub fn assemble(players: &[QueuedPlayer], format: RaidFormat) -> Vec<Vec<Vec<usize>>> {
+    if format.squads == 0 || format.squad_size == 0 {
+        return Vec::new();
+    }
+    let mut parties = parties_by_skill(players, format.squad_size);
+    let mut raids = Vec::new();
+    while let Some((squads, used)) = pack(&parties, format) {
+        raids.push(squads);
+        for index in used.into_iter().rev() {
+            parties.remove(index);
+        }
+    }
+
+    raids
+}
+
+/// New ratings of the squad players from the squad ranks, `1` is the best squad
+pub fn rate(raid: &Match, ranks: &[usize]) -> Vec<(Uuid, MhthRating)> {
+    let squads = raid.squad_ratings();
+    let teams_and_ranks: Vec<(&[MhthRating], MultiTeamOutcome)> = squads
+        .iter()
+        .zip(ranks)
+        .map(|((_, ratings), rank)| (ratings.as_slice(), MultiTeamOutcome::new(*rank)))
+        .collect();
+    let rated = mhth_multi_team(&teams_and_ranks, &MhthConfig::new());
+
+    squads
+        .iter()
+        .zip(rated)
+        .flat_map(|((ids, _), ratings)| ids.iter().copied().zip(ratings).collect::<Vec<_>>())
+        .collect()
+}
+
+impl Match {
+    /// Raid of `squads`, hosted by the longest waiting player
+    pub fn raid(
+        players: Vec<QueuedPlayer>,
+        squads: Vec<Vec<Uuid>>,
+        format: RaidFormat,
+        now: i64,
+    ) -> Option<Self> {
+        let host = players.iter().min_by_key(|player| player.join_time)?;
+
+        Some(Self {
+            id: Uuid::new_v4(),
+            region: host.region.clone(),
+            host_id: host.player_id,
+            playlist: host.playlist.clone(),
+            difficulty: host.difficulty,
+            settings: host.match_settings.clone(),
+            voice_chat: 0,
+            experiments: host.experiments.clone(),
+            params: host.params,
+            lifecycle: Lifecycle::forming(now),
+            nakama_match_id: None,
+            game_server: None,
+            challenge: None,
+            datacenter: None,
+            kind: MatchKind::Raid {
+                squads: format.squads,
+                squad_size: format.squad_size,
+            },
+            squads,
+            players,
+        })
+    }
+
+    pub const fn is_raid(&self) -> bool {
+        matches!(self.kind, MatchKind::Raid { .. })
+    }
+
+    /// Player ids and ratings of each squad
+    pub fn squad_ratings(&self) -> Vec<(Vec<Uuid>, Vec<MhthRating>)> {
+        self.squads
+            .iter()
+            .map(|squad| {
+                let players: Vec<&QueuedPlayer> = squad
+                    .iter()
+                    .filter_map(|id| self.players.iter().find(|p| p.player_id == *id))
+                    .collect();
+                (
+                    players.iter().map(|p| p.player_id).collect(),
+                    players.iter().map(|p| p.skillrating).collect(),
+                )
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::rpc::matchmaking::Player;
+
+    const FORMAT: RaidFormat = RaidFormat {
+        squads: 3,
+        squad_size: 2,
+    };
+
+    fn player(rating: f64) -> QueuedPlayer {
+        (
+            Uuid::new_v4(),
+            Player::default(),
+            MhthRating {
+                rating,
+                ..Default::default()
+            },
+        )
+            .into()
+    }
+
+    fn party(players: &mut [QueuedPlayer]) {
+        let host = players[0].player_id.to_string();
+        for member in &mut players[1..] {
+            member.party_ids = vec![host.clone()];
+        }
+    }
+
+    #[test]
+    fn parties_are_packed_into_squads() {
+        let mut players: Vec<_> = [20., 21., 22., 23., 24., 25., 60.]
+            .into_iter()
+            .map(player)
+            .collect();
+        party(&mut players[0..2]);
+        party(&mut players[2..4]);
+
+        let raids = assemble(&players, FORMAT);
+
+        assert_eq!(raids.len(), 1);
+        assert_eq!(raids[0].len(), 3);
+        assert!(raids[0].iter().all(|squad| squad.len() == 2));
+        assert!(raids[0].contains(&vec![0, 1]));
+        assert!(raids[0].contains(&vec![2, 3]));
+        // the outlier waits for the next raid
+        assert!(raids[0].iter().all(|squad| !squad.contains(&6)));
+    }
+
+    #[test]
+    fn oversized_parties_wait() {
+        let mut players: Vec<_> = (0..6).map(|_| player(25.)).collect();
+        party(&mut players[0..3]);
+
+        assert!(assemble(&players, FORMAT).is_empty());
+    }
+
+    #[test]
+    fn winning_squad_gains_rating() {
+        let players: Vec<_> = (0..6).map(|_| player(25.)).collect();
+        let squads = vec![
+            vec![players[0].player_id, players[1].player_id],
+            vec![players[2].player_id, players[3].player_id],
+            vec![players[4].player_id, players[5].player_id],
+        ];
+        let raid = Match::raid(players.clone(), squads, FORMAT, 0).unwrap();
+
+        let rated = rate(&raid, &[1, 2, 3]);
+
+        assert_eq!(rated.len(), 6);
+        assert_eq!(rated[0].0, players[0].player_id);
+        assert!(rated[0].1.rating > 25.);
+        assert!(rated[5].1.rating < 25.);
+    }
+}
@@ -0,0 +1,116 @@
+//! Ranked and quickplay queues. Ranked players queue in their skill band with the configured
+//! skill window and their matches move their ratings. Quickplay players share a single band with
+//! a wide skill window, and their matches move the ratings by [`QUICKPLAY_RATING_WEIGHT`] of a
+//! ranked match, the reduced change is the one stored, see [`crate::ratings`]. Each queue type has
+//! its own queue regions, so they are never matched together.
+
+use skillratings::mhth::MhthRating;
+
+use crate::{experiments::MatchParams, records::RatingChange, rpc::matchmaking::QueueType};
+
+/// Suffix of the quickplay queue regions
+pub const QUICKPLAY_QUEUE: &str = "quickplay";
+/// Smallest skill window of quickplay players, relative to the match average
+pub const QUICKPLAY_SKILL_WINDOW: f64 = 1.;
+/// Share of a ranked rating change applied by quickplay matches
+pub const QUICKPLAY_RATING_WEIGHT: f64 = 0.25;
+
+/// Queue region of `queue_type`, ranked players keep the queue region of their playlist
+pub fn typed_queue_region(queue_region: String, queue_type: QueueType) -> String {
+    match queue_type {
+        QueueType::Ranked => queue_region,
+        QueueType::Quickplay => format!("{queue_region}:{QUICKPLAY_QUEUE}"),
+    }
+}
+
+/// Ranked and quickplay queue regions of a queue region
+pub fn queue_regions(queue_region: String) -> [String; 2] {
+    let quickplay = typed_queue_region(queue_region.clone(), QueueType::Quickplay);
+
+    [queue_region, quickplay]
+}
+
+/// Relaxes the skill window of quickplay players
+pub const fn apply(queue_type: QueueType, params: &mut MatchParams) {
+    if let QueueType::Quickplay = queue_type {
+        params.skill_window = params.skill_window.max(QUICKPLAY_SKILL_WINDOW);
+    }
+}
+
+/// Queue shard of the player, quickplay queues are not sharded by skill
+pub const fn skill_band(queue_type: QueueType, band: i64) -> i64 {
+    match queue_type {
+        QueueType::Ranked => band,
+        QueueType::Quickplay => 0,
+    }
+}
+
+pub const fn rating_weight(queue_type: QueueType) -> f64 {
+    match queue_type {
+        QueueType::Ranked => 1.,
+        QueueType::Quickplay => QUICKPLAY_RATING_WEIGHT,
+    }
+}
+
+/// Scales the change of a rating by the [`rating_weight`] of the match queue
+pub fn weigh(change: RatingChange, queue_type: QueueType) -> RatingChange {
+    let weight = rating_weight(queue_type);
+    let lerp = |before: f64, after: f64| before + (after - before) * weight;
+    let (before, after) = (change.before, change.after);
+
+    RatingChange {
+        after: MhthRating {
+            rating: lerp(before.rating, after.rating),
+            loadout_modifier: lerp(before.loadout_modifier, after.loadout_modifier),
+            uncertainty: lerp(before.uncertainty, after.uncertainty),
+        },
+        ..change
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use uuid::Uuid;
+
+    use super::*;
+
+    #[test]
+    fn queue_types_have_their_own_regions() {
+        assert_eq!(
+            queue_regions("CAN:horde".to_string()),
+            ["CAN:horde".to_string(), "CAN:horde:quickplay".to_string()]
+        );
+        assert_eq!(skill_band(QueueType::Ranked, 3), 3);
+        assert_eq!(skill_band(QueueType::Quickplay, 3), 0);
+
+        let mut params = MatchParams::DEFAULT;
+        apply(QueueType::Ranked, &mut params);
+        assert_eq!(params, MatchParams::DEFAULT);
+        apply(QueueType::Quickplay, &mut params);
+        assert_eq!(params.skill_window, QUICKPLAY_SKILL_WINDOW);
+    }
+
+    #[test]
+    fn quickplay_changes_are_reduced() {
+        let change = RatingChange {
+            match_id: Uuid::new_v4(),
+            player_id: Uuid::new_v4(),
+            before: MhthRating {
+                rating: 20.,
+                loadout_modifier: 1.,
+                uncertainty: 8.,
+            },
+            after: MhthRating {
+                rating: 24.,
+                loadout_modifier: 1.,
+                uncertainty: 6.,
+            },
+        };
+
+        assert_eq!(weigh(change, QueueType::Ranked), change);
+        let quickplay = weigh(change, QueueType::Quickplay);
+        assert_eq!(quickplay.after.rating, 21.);
+        assert_eq!(quickplay.after.uncertainty, 7.5);
+        assert_eq!(quickplay.before, change.before);
+    }
+}
@@ -0,0 +1,108 @@
+//! Ratings moved by match results. The ratings of a reported match are written back here, and
+//! players queue with their stored rating, so their matches move it from match to match. Players
+//! without a stored rating queue with the rating Nakama returns.
+
+use std::collections::HashMap;
+
+use redis::{AsyncCommands, RedisError, aio::MultiplexedConnection};
+use skillratings::mhth::MhthRating;
+use uuid::Uuid;
+
+use crate::{namespace, records::RatingChange};
+
+pub const RATINGS_KEY: &str = "ratings";
+
+const RATING_FIELD: &str = "rating";
+const UNCERTAINTY_FIELD: &str = "uncertainty";
+
+pub fn rating_key(player_id: &Uuid) -> String {
+    namespace::key(format_args!("{RATINGS_KEY}:{player_id}"))
+}
+
+/// Stored rating of the player, `None` before their first reported match. The loadout modifier
+/// is not stored, it is applied by the loadout of each queue entry.
+pub async fn get_rating(
+    conn: &mut MultiplexedConnection,
+    player_id: &Uuid,
+) -> Result<Option<MhthRating>, RedisError> {
+    let fields: HashMap<String, f64> = conn.hgetall(rating_key(player_id)).await?;
+    let (Some(rating), Some(uncertainty)) =
+        (fields.get(RATING_FIELD), fields.get(UNCERTAINTY_FIELD))
+    else {
+        return Ok(None);
+    };
+
+    Ok(Some(MhthRating {
+        rating: *rating,
+        uncertainty: *uncertainty,
+        ..Default::default()
+    }))
+}
+
+/// Stored rating of the player, `fallback` before their first reported match
+pub async fn rating_or(
+    conn: &mut MultiplexedConnection,
+    player_id: &Uuid,
+    fallback: MhthRating,
+) -> Result<MhthRating, RedisError> {
+    Ok(get_rating(conn, player_id).await?.unwrap_or(fallback))
+}
+
+/// Stores the ratings after `changes` in `pipe`, written with the result of their match
+pub fn apply(pipe: &mut redis::Pipeline, changes: &[RatingChange]) {
+    for change in changes {
+        pipe.hset_multiple(
+            rating_key(&change.player_id),
+            &[
+                (RATING_FIELD, change.after.rating),
+                (UNCERTAINTY_FIELD, change.after.uncertainty),
+            ],
+        )
+        .ignore();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+    use crate::test_support::{create_redis, redis_client};
+
+    #[tokio::test]
+    async fn applied_ratings_are_stored() {
+        let container = create_redis(6379).await;
+        let host = container.get_host().await.unwrap();
+        let port = container.get_host_port_ipv4(6379).await.unwrap();
+        let client = redis_client(host.to_string(), port);
+        let mut conn = client.get_multiplexed_async_connection().await.unwrap();
+        let player_id = Uuid::new_v4();
+        let change = RatingChange {
+            match_id: Uuid::new_v4(),
+            player_id,
+            before: MhthRating::default(),
+            after: MhthRating {
+                rating: 27.5,
+                loadout_modifier: 2.,
+                uncertainty: 7.,
+            },
+        };
+
+        let unrated = rating_or(&mut conn, &player_id, MhthRating::default())
+            .await
+            .unwrap();
+        let mut pipe = redis::pipe();
+        apply(&mut pipe, &[change]);
+        pipe.query_async::<()>(&mut conn).await.unwrap();
+        let stored = get_rating(&mut conn, &player_id).await.unwrap();
+
+        assert_eq!(unrated, MhthRating::default());
+        assert_eq!(
+            stored,
+            Some(MhthRating {
+                rating: 27.5,
+                loadout_modifier: 0.,
+                uncertainty: 7.,
+            })
+        );
+    }
+}
@@ -0,0 +1,232 @@
+//! Ready-check of the matches about to close. Once an open match is full, or large enough for its
+//! region, its players are asked to confirm with `ConfirmReady` within [`READY_CHECK_SECS`]. The
+//! match closes once every player confirmed. Players who let the check expire are dropped from the
+//! queue and replaced by queued players, then a new check starts. A silent host dissolves the
+//! match and its other players return to the queue.
+
+use std::collections::HashSet;
+
+use redis::{RedisError, aio::MultiplexedConnection};
+use tonic::Code;
+use tonic_types::{ErrorDetails, FieldViolation, StatusExt};
+use uuid::Uuid;
+
+use crate::{
+    codec, namespace,
+    notifications::{self, Notification},
+    rpc::{Match, match_id_key},
+    store,
+};
+
+pub const READY_CHECK: &str = "ready_check";
+/// Seconds players have to confirm
+pub const READY_CHECK_SECS: i64 = 20;
+
+#[derive(Debug, thiserror::Error)]
+pub enum Error {
+    #[error("match `{0}` has no ready-check running")]
+    NoReadyCheck(Uuid),
+    #[error("player `{0}` is not in the match")]
+    NotInMatch(Uuid),
+    #[error(transparent)]
+    Redis(#[from] RedisError),
+    #[error(transparent)]
+    BitcodeDeser(#[from] codec::Error),
+}
+
+impl From<Error> for tonic::Status {
+    fn from(value: Error) -> Self {
+        match value {
+            Error::NoReadyCheck(_) => Self::failed_precondition(value.to_string()),
+            Error::NotInMatch(_) => Self::with_error_details(
+                Code::InvalidArgument,
+                value.to_string(),
+                ErrorDetails::with_bad_request(vec![FieldViolation::new(
+                    "match_id",
+                    value.to_string(),
+                )]),
+            ),
+            Error::Redis(_) | Error::BitcodeDeser(_) => {
+                Self::internal("Failed to confirm ready-check")
+            }
+        }
+    }
+}
+
+/// Ready-check of an open match
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Status {
+    /// No check running, one starts
+    Idle,
+    /// Waiting for the players until `deadline`
+    Pending { deadline: i64 },
+    /// Every player confirmed, the match closes
+    Passed,
+    /// The check expired, `missing` players did not confirm
+    Expired { missing: Vec<Uuid> },
+}
+
+/// Deadline of the check running on a match
+pub fn ready_check_key(match_id: &Uuid) -> String {
+    namespace::key(format_args!("{READY_CHECK}:{match_id}"))
+}
+
+/// Players who confirmed the check running on a match
+pub fn confirmed_key(match_id: &Uuid) -> String {
+    namespace::key(format_args!("{READY_CHECK}:{match_id}:confirmed"))
+}
+
+/// Status of the check of `lobby` at `now`, from its `deadline` and the `confirmed` players
+pub fn status(lobby: &Match, deadline: Option<i64>, confirmed: &HashSet<Uuid>, now: i64) -> Status {
+    let Some(deadline) = deadline else {
+        return Status::Idle;
+    };
+    let missing: Vec<Uuid> = lobby
+        .players
+        .iter()
+        .map(|player| player.player_id)
+        .filter(|player_id| !confirmed.contains(player_id))
+        .collect();
+
+    if missing.is_empty() {
+        Status::Passed
+    } else if now >= deadline {
+        Status::Expired { missing }
+    } else {
+        Status::Pending { deadline }
+    }
+}
+
+/// Loads the check of `lobby` and its status at `now`
+pub async fn load(
+    conn: &mut MultiplexedConnection,
+    lobby: &Match,
+    now: i64,
+) -> Result<Status, RedisError> {
+    let (deadline, confirmed): (Option<i64>, HashSet<Uuid>) = redis::pipe()
+        .get(ready_check_key(&lobby.id))
+        .smembers(confirmed_key(&lobby.id))
+        .query_async(conn)
+        .await?;
+
+    Ok(status(lobby, deadline, &confirmed, now))
+}
+
+/// Starts the check of `lobby` and asks its players to confirm
+pub async fn start(
+    conn: &mut MultiplexedConnection,
+    lobby: &Match,
+    now: i64,
+) -> Result<i64, RedisError> {
+    let deadline = now + READY_CHECK_SECS;
+    redis::pipe()
+        .atomic()
+        .del(confirmed_key(&lobby.id))
+        .ignore()
+        .set_ex(
+            ready_check_key(&lobby.id),
+            deadline,
+            (READY_CHECK_SECS * 4) as u64,
+        )
+        .ignore()
+        .query_async(conn)
+        .await
+        .map(|_: ()| ())?;
+    let players: Vec<Uuid> = lobby.players.iter().map(|p| p.player_id).collect();
+    notifications::notify(
+        conn,
+        &players,
+        &Notification::ReadyCheck {
+            match_id: lobby.id,
+            deadline,
+        },
+    )
+    .await?;
+
+    Ok(deadline)
+}
+
+/// Ends the check of a match
+pub fn clear(pipe: &mut redis::Pipeline, match_id: &Uuid) {
+    pipe.del(ready_check_key(match_id))
+        .ignore()
+        .del(confirmed_key(match_id))
+        .ignore();
+}
+
+/// Confirms `player_id` is ready, returns the confirmed players and the players of the match
+pub async fn confirm(
+    conn: &mut MultiplexedConnection,
+    match_id: Uuid,
+    player_id: Uuid,
+) -> Result<(usize, usize), Error> {
+    let (running, players, joined): (bool, Option<usize>, Option<Uuid>) = redis::pipe()
+        .exists(ready_check_key(&match_id))
+        .hget(match_id_key(&match_id), store::PLAYERS)
+        .hget(store::player_match_index(), player_id)
+        .query_async(conn)
+        .await?;
+    let Some(players) = players.filter(|_| running) else {
+        return Err(Error::NoReadyCheck(match_id));
+    };
+    if joined != Some(match_id) {
+        return Err(Error::NotInMatch(player_id));
+    }
+
+    let key = confirmed_key(&match_id);
+    let (confirmed,): (usize,) = redis::pipe()
+        .atomic()
+        .sadd(&key, player_id)
+        .ignore()
+        .expire(&key, READY_CHECK_SECS * 4)
+        .ignore()
+        .scard(&key)
+        .query_async(conn)
+        .await?;
+
+    Ok((confirmed, players))
+}
+
+#[cfg(test)]
+mod tests {
+    use skillratings::mhth::MhthRating;
+
+    use super::*;
+    use crate::rpc::{QueuedPlayer, matchmaking::Player};
+
+    fn queued(join_mode: i32) -> QueuedPlayer {
+        (
+            Uuid::new_v4(),
+            Player {
+                join_mode,
+                region: "CAN".to_string(),
+                ..Default::default()
+            },
+            MhthRating::default(),
+        )
+            .into()
+    }
+
+    #[test]
+    fn checks_pass_once_every_player_confirmed() {
+        let host = queued(0);
+        let other = queued(1);
+        let mut lobby = Match::host(&host, &[]).unwrap();
+        lobby.players.push(other.clone());
+        let mut confirmed = HashSet::from([host.player_id]);
+
+        assert_eq!(status(&lobby, None, &confirmed, 10), Status::Idle);
+        assert_eq!(
+            status(&lobby, Some(20), &confirmed, 10),
+            Status::Pending { deadline: 20 }
+        );
+        assert_eq!(
+            status(&lobby, Some(20), &confirmed, 20),
+            Status::Expired {
+                missing: vec![other.player_id]
+            }
+        );
+        confirmed.insert(other.player_id);
+        assert_eq!(status(&lobby, Some(20), &confirmed, 30), Status::Passed);
+    }
+}
@@ -0,0 +1,419 @@
+//! Durable history of completed matches and the rating changes they imply. Redis evicts matches
+//! hours after they end, so deployments that keep a history set `DATABASE_URL` and build with
+//! the `postgres` feature, see [`postgres::PostgresRecords`]. Redis-only deployments use
+//! [`NoRecords`] and keep no history.
+//!
+//! Players read their history a page at a time, newest first, see [`HistoryQuery`]. Pages are
+//! chained by a [`Cursor`] on the last match of the previous page, so matches completed while a
+//! player pages do not shift the following pages.
+
+use std::{fmt::Debug, str::FromStr, sync::Arc};
+
+use chrono::{DateTime, Utc};
+use skillratings::{
+    Outcomes,
+    mhth::{MhthConfig, MhthRating, mhth_team_vs_environment},
+};
+use uuid::Uuid;
+
+use crate::{ranked, rpc::Match};
+
+#[cfg(feature = "postgres")]
+pub mod postgres;
+
+/// Env var with the Postgres connection string, no history is kept when unset
+pub const DATABASE_URL_VAR: &str = "DATABASE_URL";
+/// Matches of a history page when the request sets no page size
+pub const DEFAULT_PAGE_SIZE: usize = 20;
+pub const MAX_PAGE_SIZE: usize = 100;
+
+#[derive(Debug, thiserror::Error)]
+pub enum Error {
+    #[cfg(feature = "postgres")]
+    #[error(transparent)]
+    Postgres(#[from] sqlx::Error),
+    #[cfg(feature = "postgres")]
+    #[error(transparent)]
+    Migrate(#[from] sqlx::migrate::MigrateError),
+    #[error("{DATABASE_URL_VAR} is set but the server was built without the `postgres` feature")]
+    PostgresDisabled,
+    #[error("invalid history cursor `{0}`")]
+    InvalidCursor(String),
+}
+
+impl From<Error> for tonic::Status {
+    fn from(value: Error) -> Self {
+        match value {
+            Error::InvalidCursor(_) => Self::invalid_argument(value.to_string()),
+            _ => Self::internal("Failed to load match history"),
+        }
+    }
+}
+
+/// Completed match, written once its result is reported
+#[derive(Debug, Clone, PartialEq)]
+pub struct MatchRecord {
+    pub match_id: Uuid,
+    pub host_id: Uuid,
+    pub region: String,
+    pub playlist: String,
+    pub mission: String,
+    pub difficulty: i32,
+    pub won: bool,
+    pub player_ids: Vec<Uuid>,
+    pub completed_at: DateTime<Utc>,
+}
+
+impl MatchRecord {
+    pub fn new(completed: &Match, mission: &str, won: bool, completed_at: DateTime<Utc>) -> Self {
+        Self {
+            match_id: completed.id,
+            host_id: completed.host_id,
+            region: completed.region.clone(),
+            playlist: completed.playlist.clone(),
+            mission: mission.to_string(),
+            difficulty: completed.difficulty,
+            won,
+            player_ids: completed.players.iter().map(|p| p.player_id).collect(),
+            completed_at,
+        }
+    }
+}
+
+/// Position of a match in the history, newest first: completion time, then match id for the
+/// matches completed at the same time
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub struct Cursor {
+    pub completed_at: DateTime<Utc>,
+    pub match_id: Uuid,
+}
+
+impl Cursor {
+    pub const fn of(record: &MatchRecord) -> Self {
+        Self {
+            completed_at: record.completed_at,
+            match_id: record.match_id,
+        }
+    }
+}
+
+/// `<completed at, in microseconds>_<match id>`, opaque to clients
+impl std::fmt::Display for Cursor {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{}_{}",
+            self.completed_at.timestamp_micros(),
+            self.match_id.simple()
+        )
+    }
+}
+
+impl FromStr for Cursor {
+    type Err = Error;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        let invalid = || Error::InvalidCursor(value.to_string());
+        let (micros, match_id) = value.split_once('_').ok_or_else(invalid)?;
+        let completed_at = micros
+            .parse()
+            .ok()
+            .and_then(DateTime::from_timestamp_micros)
+            .ok_or_else(invalid)?;
+        let match_id = Uuid::parse_str(match_id).map_err(|_| invalid())?;
+
+        Ok(Self {
+            completed_at,
+            match_id,
+        })
+    }
+}
+
+/// Page of the matches of a player passing every filter
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct HistoryQuery {
+    pub player_id: Uuid,
+    /// Matches past the last one of the previous page, the newest matches when `None`
+    pub after: Option<Cursor>,
+    pub page_size: usize,
+    /// Completed at or after
+    pub from: Option<DateTime<Utc>>,
+    /// Completed before
+    pub to: Option<DateTime<Utc>>,
+    pub region: Option<String>,
+    pub won: Option<bool>,
+    /// Matches played with every one of these players
+    pub with_players: Vec<Uuid>,
+}
+
+impl HistoryQuery {
+    pub const fn new(player_id: Uuid) -> Self {
+        Self {
+            player_id,
+            after: None,
+            page_size: DEFAULT_PAGE_SIZE,
+            from: None,
+            to: None,
+            region: None,
+            won: None,
+            with_players: Vec::new(),
+        }
+    }
+
+    /// Page size of the request, [`DEFAULT_PAGE_SIZE`] when `0` and at most [`MAX_PAGE_SIZE`]
+    pub fn with_page_size(mut self, page_size: usize) -> Self {
+        self.page_size = match page_size {
+            0 => DEFAULT_PAGE_SIZE,
+            size => size.min(MAX_PAGE_SIZE),
+        };
+        self
+    }
+
+    /// Whether `record` passes the filters and comes after the cursor
+    pub fn accepts(&self, record: &MatchRecord) -> bool {
+        record.player_ids.contains(&self.player_id)
+            && self.after.is_none_or(|after| Cursor::of(record) < after)
+            && self.from.is_none_or(|from| record.completed_at >= from)
+            && self.to.is_none_or(|to| record.completed_at < to)
+            && self
+                .region
+                .as_ref()
+                .is_none_or(|region| &record.region == region)
+            && self.won.is_none_or(|won| record.won == won)
+            && self
+                .with_players
+                .iter()
+                .all(|player_id| record.player_ids.contains(player_id))
+    }
+
+    /// Page of the newest `records` accepted by the query, with the cursor of the next page when
+    /// more are left. Stores fetch one record past the page size to know whether more are left
+    pub fn page(&self, mut records: Vec<MatchRecord>) -> HistoryPage {
+        records.retain(|record| self.accepts(record));
+        records.sort_by_key(|record| std::cmp::Reverse(Cursor::of(record)));
+        let next = (records.len() > self.page_size).then(|| {
+            records.truncate(self.page_size);
+            records.last().map(Cursor::of)
+        });
+
+        HistoryPage {
+            matches: records,
+            next: next.flatten(),
+        }
+    }
+}
+
+/// Matches of a [`HistoryQuery`], newest first
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct HistoryPage {
+    pub matches: Vec<MatchRecord>,
+    /// Cursor of the next page, `None` on the last page
+    pub next: Option<Cursor>,
+}
+
+/// Ledger entry of a player rating moved by a match result
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct RatingChange {
+    pub match_id: Uuid,
+    pub player_id: Uuid,
+    pub before: MhthRating,
+    pub after: MhthRating,
+}
+
+/// Ratings of the players of `completed` after playing against `opponents`, weighed by its
+/// queue type, see [`ranked`]
+pub fn rating_changes(completed: &Match, opponents: &[MhthRating], won: bool) -> Vec<RatingChange> {
+    let team: Vec<MhthRating> = completed.players.iter().map(|p| p.skillrating).collect();
+    let outcome = if won {
+        Outcomes::SUCCESSFUL
+    } else {
+        Outcomes::FAILURE
+    };
+    let (updated, _) = mhth_team_vs_environment(&team, opponents, &outcome, &MhthConfig::new());
+
+    completed
+        .players
+        .iter()
+        .zip(updated)
+        .map(|(player, after)| {
+            ranked::weigh(
+                RatingChange {
+                    match_id: completed.id,
+                    player_id: player.player_id,
+                    before: player.skillrating,
+                    after,
+                },
+                completed.queue_type(),
+            )
+        })
+        .collect()
+}
+
+/// Storage of the match history, written by the result-processing path
+#[tonic::async_trait]
+pub trait MatchRecords: Debug + Send + Sync {
+    /// Writes the match and its rating changes together
+    async fn record(&self, record: &MatchRecord, changes: &[RatingChange]) -> Result<(), Error>;
+
+    /// Page of the match history of a player
+    async fn history(&self, query: &HistoryQuery) -> Result<HistoryPage, Error>;
+}
+
+/// History of Redis-only deployments, nothing outlives the Redis keys
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct NoRecords;
+
+#[tonic::async_trait]
+impl MatchRecords for NoRecords {
+    async fn record(&self, _: &MatchRecord, _: &[RatingChange]) -> Result<(), Error> {
+        Ok(())
+    }
+
+    async fn history(&self, _: &HistoryQuery) -> Result<HistoryPage, Error> {
+        Ok(HistoryPage::default())
+    }
+}
+
+/// Connects to the Postgres of [`DATABASE_URL_VAR`] and migrates it, [`NoRecords`] when unset
+pub async fn from_env() -> Result<Arc<dyn MatchRecords>, Error> {
+    let Ok(url) = std::env::var(DATABASE_URL_VAR) else {
+        return Ok(Arc::new(NoRecords));
+    };
+
+    #[cfg(feature = "postgres")]
+    {
+        Ok(Arc::new(postgres::PostgresRecords::connect(&url).await?))
+    }
+    #[cfg(not(feature = "postgres"))]
+    {
+        let _ = url;
+        Err(Error::PostgresDisabled)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::rpc::{
+        QueuedPlayer,
+        matchmaking::{Player, QueueType},
+    };
+
+    fn player(rating: f64) -> QueuedPlayer {
+        (
+            Uuid::new_v4(),
+            Player::default(),
+            MhthRating {
+                rating,
+                ..Default::default()
+            },
+        )
+            .into()
+    }
+
+    #[test]
+    fn wins_raise_every_rating() {
+        let completed = Match::host(&player(30.), &[player(20.)]).unwrap();
+        let changes = rating_changes(&completed, &[MhthRating::default()], true);
+
+        assert_eq!(changes.len(), 2);
+        assert!(
+            changes
+                .iter()
+                .all(|change| change.after.rating > change.before.rating)
+        );
+        assert_eq!(changes[1].player_id, completed.players[1].player_id);
+        assert_eq!(changes[0].before.rating, 20.);
+    }
+
+    #[test]
+    fn losses_lower_every_rating() {
+        let completed = Match::host(&player(25.), &[]).unwrap();
+        let changes = rating_changes(&completed, &[MhthRating::default()], false);
+
+        assert!(changes[0].after.rating < changes[0].before.rating);
+    }
+
+    #[test]
+    fn quickplay_results_move_ratings_less() {
+        let ranked = Match::host(&player(25.), &[]).unwrap();
+        let mut quickplay_host = ranked.players[0].clone();
+        quickplay_host.queue_type = QueueType::Quickplay.into();
+        let quickplay = Match::host(&quickplay_host, &[]).unwrap();
+
+        let ranked = rating_changes(&ranked, &[MhthRating::default()], true);
+        let quickplay = rating_changes(&quickplay, &[MhthRating::default()], true);
+        let moved = |change: &RatingChange| change.after.rating - change.before.rating;
+
+        assert!(
+            (moved(&quickplay[0]) - moved(&ranked[0]) * ranked::QUICKPLAY_RATING_WEIGHT).abs()
+                < 1e-9
+        );
+    }
+
+    #[tokio::test]
+    async fn no_records_accepts_every_match() {
+        let completed = Match::host(&player(25.), &[]).unwrap();
+        let record = MatchRecord::new(&completed, "hunt", true, Utc::now());
+
+        assert!(NoRecords.record(&record, &[]).await.is_ok());
+        assert_eq!(record.player_ids, vec![completed.host_id]);
+    }
+
+    #[test]
+    fn history_pages_chain_by_cursor() {
+        let host = player(25.);
+        let mate = player(20.);
+        let now = Utc::now();
+        let records: Vec<MatchRecord> = (0..5)
+            .map(|minutes| {
+                let completed = if minutes < 3 {
+                    Match::host(&host, std::slice::from_ref(&mate)).unwrap()
+                } else {
+                    Match::host(&host, &[]).unwrap()
+                };
+                let completed_at = now - chrono::Duration::minutes(minutes);
+                MatchRecord::new(&completed, "hunt", minutes % 2 == 0, completed_at)
+            })
+            .collect();
+        let query = HistoryQuery::new(host.player_id).with_page_size(2);
+
+        let first = query.page(records.clone());
+        let cursor: Cursor = first.next.unwrap().to_string().parse().unwrap();
+        let second = HistoryQuery {
+            after: Some(cursor),
+            ..query.clone()
+        }
+        .page(records.clone());
+        let last = HistoryQuery {
+            after: second.next,
+            ..query.clone()
+        }
+        .page(records.clone());
+        let with_mate = HistoryQuery {
+            with_players: vec![mate.player_id],
+            won: Some(false),
+            ..query
+        }
+        .page(records.clone());
+
+        assert_eq!(first.matches, records[..2]);
+        assert_eq!(second.matches, records[2..4]);
+        assert_eq!(last.matches, records[4..]);
+        assert_eq!(last.next, None);
+        assert_eq!(with_mate.matches, records[1..2]);
+        assert!(
+            HistoryQuery::new(mate.player_id)
+                .page(records[3..].to_vec())
+                .matches
+                .is_empty()
+        );
+        assert!("1700000000_not-a-uuid".parse::<Cursor>().is_err());
+        assert_eq!(
+            HistoryQuery::new(host.player_id)
+                .with_page_size(1000)
+                .page_size,
+            MAX_PAGE_SIZE
+        );
+    }
+}
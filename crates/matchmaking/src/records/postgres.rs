@@ -0,0 +1,232 @@
+use chrono::{DateTime, Utc};
+use sqlx::{PgPool, postgres::PgPoolOptions};
+use uuid::Uuid;
+
+use super::{Error, HistoryPage, HistoryQuery, MatchRecord, MatchRecords, RatingChange};
+
+/// Connections kept to Postgres, records are written once per completed match
+pub const MAX_CONNECTIONS: u32 = 5;
+
+#[derive(Debug, Clone)]
+pub struct PostgresRecords {
+    pool: PgPool,
+}
+
+impl PostgresRecords {
+    /// Connects to `url` and applies the pending migrations
+    pub async fn connect(url: &str) -> Result<Self, Error> {
+        let pool = PgPoolOptions::new()
+            .max_connections(MAX_CONNECTIONS)
+            .connect(url)
+            .await?;
+        sqlx::migrate!("./migrations").run(&pool).await?;
+
+        Ok(Self { pool })
+    }
+}
+
+#[tonic::async_trait]
+impl MatchRecords for PostgresRecords {
+    /// Retried results are ignored, the first report of a match is kept
+    async fn record(&self, record: &MatchRecord, changes: &[RatingChange]) -> Result<(), Error> {
+        let mut transaction = self.pool.begin().await?;
+        sqlx::query(
+            "INSERT INTO match_records \
+             (match_id, host_id, region, playlist, mission, difficulty, won, player_ids, completed_at) \
+             VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9) \
+             ON CONFLICT (match_id) DO NOTHING",
+        )
+        .bind(record.match_id)
+        .bind(record.host_id)
+        .bind(&record.region)
+        .bind(&record.playlist)
+        .bind(&record.mission)
+        .bind(record.difficulty)
+        .bind(record.won)
+        .bind(&record.player_ids)
+        .bind(record.completed_at)
+        .execute(&mut *transaction)
+        .await?;
+        for change in changes {
+            sqlx::query(
+                "INSERT INTO rating_changes \
+                 (match_id, player_id, rating_before, uncertainty_before, rating_after, uncertainty_after) \
+                 VALUES ($1, $2, $3, $4, $5, $6) \
+                 ON CONFLICT (match_id, player_id) DO NOTHING",
+            )
+            .bind(change.match_id)
+            .bind(change.player_id)
+            .bind(change.before.rating)
+            .bind(change.before.uncertainty)
+            .bind(change.after.rating)
+            .bind(change.after.uncertainty)
+            .execute(&mut *transaction)
+            .await?;
+        }
+
+        Ok(transaction.commit().await?)
+    }
+
+    /// Filtered and paged by Postgres, see the `match_history` indexes
+    async fn history(&self, query: &HistoryQuery) -> Result<HistoryPage, Error> {
+        type Row = (
+            Uuid,
+            Uuid,
+            String,
+            String,
+            String,
+            i32,
+            bool,
+            Vec<Uuid>,
+            DateTime<Utc>,
+        );
+        let rows: Vec<Row> = sqlx::query_as(
+            "SELECT match_id, host_id, region, playlist, mission, difficulty, won, player_ids, completed_at \
+             FROM match_records \
+             WHERE player_ids @> ARRAY[$1]::uuid[] AND player_ids @> $2 \
+             AND ($3::timestamptz IS NULL OR (completed_at, match_id) < ($3, $4::uuid)) \
+             AND ($5::timestamptz IS NULL OR completed_at >= $5) \
+             AND ($6::timestamptz IS NULL OR completed_at < $6) \
+             AND ($7::text IS NULL OR region = $7) \
+             AND ($8::boolean IS NULL OR won = $8) \
+             ORDER BY completed_at DESC, match_id DESC \
+             LIMIT $9",
+        )
+        .bind(query.player_id)
+        .bind(&query.with_players)
+        .bind(query.after.map(|after| after.completed_at))
+        .bind(query.after.map(|after| after.match_id))
+        .bind(query.from)
+        .bind(query.to)
+        .bind(&query.region)
+        .bind(query.won)
+        // one past the page tells whether a next page is left
+        .bind(query.page_size as i64 + 1)
+        .fetch_all(&self.pool)
+        .await?;
+        let records = rows
+            .into_iter()
+            .map(
+                |(
+                    match_id,
+                    host_id,
+                    region,
+                    playlist,
+                    mission,
+                    difficulty,
+                    won,
+                    player_ids,
+                    completed_at,
+                )| MatchRecord {
+                    match_id,
+                    host_id,
+                    region,
+                    playlist,
+                    mission,
+                    difficulty,
+                    won,
+                    player_ids,
+                    completed_at,
+                },
+            )
+            .collect();
+
+        Ok(query.page(records))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::Utc;
+    use skillratings::mhth::MhthRating;
+    use uuid::Uuid;
+
+    use super::*;
+    use crate::{
+        rpc::{Match, QueuedPlayer, matchmaking::Player},
+        test_support::create_postgres,
+    };
+
+    #[tokio::test]
+    async fn records_are_written_once() {
+        let container = create_postgres(5432).await;
+        let host = container.get_host().await.unwrap();
+        let port = container.get_host_port_ipv4(5432).await.unwrap();
+        let records = PostgresRecords::connect(&format!(
+            "postgres://postgres:password@{host}:{port}/postgres"
+        ))
+        .await
+        .unwrap();
+        let host: QueuedPlayer = (Uuid::new_v4(), Player::default(), MhthRating::default()).into();
+        let completed = Match::host(&host, &[]).unwrap();
+        let record = MatchRecord::new(&completed, "hunt", true, Utc::now());
+        let changes = super::super::rating_changes(&completed, &[MhthRating::default()], true);
+
+        records.record(&record, &changes).await.unwrap();
+        records.record(&record, &changes).await.unwrap();
+
+        let (matches,): (i64,) = sqlx::query_as("SELECT COUNT(*) FROM match_records")
+            .fetch_one(&records.pool)
+            .await
+            .unwrap();
+        let (player_id, rating_after): (Uuid, f64) =
+            sqlx::query_as("SELECT player_id, rating_after FROM rating_changes")
+                .fetch_one(&records.pool)
+                .await
+                .unwrap();
+
+        assert_eq!(matches, 1);
+        assert_eq!(player_id, completed.host_id);
+        assert_eq!(rating_after, changes[0].after.rating);
+    }
+
+    #[tokio::test]
+    async fn history_is_paged_newest_first() {
+        let container = create_postgres(5432).await;
+        let host = container.get_host().await.unwrap();
+        let port = container.get_host_port_ipv4(5432).await.unwrap();
+        let records = PostgresRecords::connect(&format!(
+            "postgres://postgres:password@{host}:{port}/postgres"
+        ))
+        .await
+        .unwrap();
+        let player: QueuedPlayer =
+            (Uuid::new_v4(), Player::default(), MhthRating::default()).into();
+        let now = Utc::now();
+        for minutes in 0..5 {
+            let completed = Match::host(&player, &[]).unwrap();
+            let record = MatchRecord::new(
+                &completed,
+                "hunt",
+                minutes % 2 == 0,
+                now - chrono::Duration::minutes(minutes),
+            );
+            records.record(&record, &[]).await.unwrap();
+        }
+        let query = HistoryQuery::new(player.player_id).with_page_size(2);
+
+        let first = records.history(&query).await.unwrap();
+        let second = records
+            .history(&HistoryQuery {
+                after: first.next,
+                ..query.clone()
+            })
+            .await
+            .unwrap();
+        let won = records
+            .history(&HistoryQuery {
+                won: Some(true),
+                page_size: 10,
+                ..query.clone()
+            })
+            .await
+            .unwrap();
+
+        assert_eq!(first.matches.len(), 2);
+        assert!(first.matches[0].completed_at > first.matches[1].completed_at);
+        assert!(second.matches[0].completed_at < first.matches[1].completed_at);
+        assert!(second.next.is_some());
+        assert_eq!(won.matches.len(), 3);
+        assert_eq!(won.next, None);
+    }
+}
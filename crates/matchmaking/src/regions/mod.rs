@@ -1,6 +1,47 @@
 use redis::{AsyncCommands, RedisError, aio::MultiplexedConnection};
+use tracing::{error, warn};
+
+use crate::{
+    audit::{self, Action, Actor, AuditEvent},
+    codec, namespace,
+};
+
+pub mod tuning;
 
 pub const REGIONS_KEY: &str = "match:regions";
+/// Env var with the comma separated regions seeded when [`REGIONS_KEY`] is missing at startup
+pub const DEFAULT_REGIONS_VAR: &str = "DEFAULT_REGIONS";
+
+/// Outcome of [`bootstrap`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Bootstrap {
+    /// Regions were already set, they are kept
+    Existing,
+    /// The default regions were seeded
+    Seeded,
+    /// No regions are set and no defaults are configured, the worker forms no match
+    Missing,
+}
+
+/// Regions of [`DEFAULT_REGIONS_VAR`], empty when unset
+pub fn default_regions() -> Vec<String> {
+    std::env::var(DEFAULT_REGIONS_VAR)
+        .map(|regions| parse_regions(&regions))
+        .unwrap_or_default()
+}
+
+pub fn parse_regions(regions: &str) -> Vec<String> {
+    regions
+        .split(',')
+        .map(str::trim)
+        .filter(|region| !region.is_empty())
+        .map(str::to_string)
+        .collect()
+}
+
+pub fn regions_key() -> String {
+    namespace::key(REGIONS_KEY)
+}
 
 pub async fn set_regions(
     conn: MultiplexedConnection,
@@ -8,28 +49,72 @@ pub async fn set_regions(
 ) -> Result<(), RedisError> {
     let mut conn = conn.clone();
 
-    let encode = bitcode::encode(regions);
-    conn.set(REGIONS_KEY, encode).await.map(|_: ()| ())?;
+    let encode = codec::encode(regions);
+    conn.set(regions_key(), encode).await.map(|_: ()| ())?;
 
     Ok(())
 }
 
+/// Seeds `defaults` when no regions are set, regions set in the meantime by another instance
+/// or an admin are kept
+pub async fn bootstrap(
+    conn: &mut MultiplexedConnection,
+    defaults: &[String],
+) -> Result<Bootstrap, RedisError> {
+    if conn.exists(regions_key()).await? {
+        return Ok(Bootstrap::Existing);
+    }
+    if defaults.is_empty() {
+        return Ok(Bootstrap::Missing);
+    }
+    let seeded: bool = conn.set_nx(regions_key(), codec::encode(defaults)).await?;
+
+    Ok(if seeded {
+        Bootstrap::Seeded
+    } else {
+        Bootstrap::Existing
+    })
+}
+
+/// [`bootstrap`] with [`default_regions`], seeding or missing regions alert the admins through
+/// the log and the audit trail
+pub async fn bootstrap_from_env(
+    conn: &mut MultiplexedConnection,
+    now: i64,
+) -> Result<Bootstrap, RedisError> {
+    let defaults = default_regions();
+    let bootstrap = bootstrap(conn, &defaults).await?;
+    let reason = match bootstrap {
+        Bootstrap::Existing => return Ok(bootstrap),
+        Bootstrap::Seeded => {
+            warn!("`{REGIONS_KEY}` was missing, seeded the default regions {defaults:?}");
+            "regions_bootstrapped"
+        }
+        Bootstrap::Missing => {
+            error!(
+                "`{REGIONS_KEY}` is missing and {DEFAULT_REGIONS_VAR} is unset, no match is formed until regions are set"
+            );
+            "regions_missing"
+        }
+    };
+    let alert = AuditEvent::new(Action::Admin, Actor::Worker, REGIONS_KEY, now).with_reason(reason);
+    audit::emit(conn, &alert).await;
+
+    Ok(bootstrap)
+}
+
 #[cfg(test)]
 mod tests {
-    use testcontainers::{
-        ContainerAsync, GenericImage, ImageExt,
-        core::{IntoContainerPort, WaitFor},
-        runners::AsyncRunner,
-    };
 
     use super::*;
+    use crate::test_support::{create_redis, redis_client};
 
     #[tokio::test]
     async fn set_multiple_regions() {
         let container = create_redis(6379).await;
         let host = container.get_host().await.unwrap();
         let port = container.get_host_port_ipv4(6379).await.unwrap();
-        let client = redis_client(host.to_string(), port).await;
+        let client = redis_client(host.to_string(), port);
         let conn = client.get_multiplexed_async_connection().await.unwrap();
         let regions = &[
             "CAN".to_string(),
@@ -39,26 +124,40 @@ mod tests {
 
         set_regions(conn.clone(), regions).await.unwrap();
 
-        let encoded: Option<Vec<u8>> = conn.clone().get(REGIONS_KEY).await.unwrap();
-        container.pause().await.unwrap();
+        let encoded: Option<Vec<u8>> = conn.clone().get(regions_key()).await.unwrap();
 
-        let decoded: Vec<String> = bitcode::decode(encoded.unwrap().as_slice()).unwrap();
+        let decoded: Vec<String> = codec::decode(encoded.unwrap().as_slice()).unwrap();
 
         assert_eq!(decoded, regions);
     }
 
-    async fn redis_client(host: String, port: u16) -> redis::Client {
-        redis::Client::open(format!("redis://{host}:{port}")).unwrap()
+    #[test]
+    fn default_regions_are_comma_separated() {
+        assert_eq!(
+            parse_regions(" CAN, US,,SOUTH_AMERICA "),
+            vec!["CAN", "US", "SOUTH_AMERICA"]
+        );
+        assert!(parse_regions("").is_empty());
     }
 
-    async fn create_redis(port: u16) -> ContainerAsync<GenericImage> {
-        GenericImage::new("redis", "8.2.1-bookworm")
-            .with_exposed_port(port.tcp())
-            .with_wait_for(WaitFor::message_on_stdout("Ready to accept connections"))
-            .with_env_var("REDIS_PASSWORD", "super-secret-password")
-            .with_env_var("REDIS_USER", "redis_mms_admin")
-            .start()
-            .await
-            .expect("Failed to start Redis")
+    #[tokio::test]
+    async fn bootstrap_seeds_missing_regions_once() {
+        let container = create_redis(6379).await;
+        let host = container.get_host().await.unwrap();
+        let port = container.get_host_port_ipv4(6379).await.unwrap();
+        let client = redis_client(host.to_string(), port);
+        let mut conn = client.get_multiplexed_async_connection().await.unwrap();
+        let defaults = vec!["CAN".to_string(), "US".to_string()];
+
+        let missing = bootstrap(&mut conn, &[]).await.unwrap();
+        let seeded = bootstrap(&mut conn, &defaults).await.unwrap();
+        let existing = bootstrap(&mut conn, &["EU".to_string()]).await.unwrap();
+        let encoded: Option<Vec<u8>> = conn.get(regions_key()).await.unwrap();
+
+        assert_eq!(missing, Bootstrap::Missing);
+        assert_eq!(seeded, Bootstrap::Seeded);
+        assert_eq!(existing, Bootstrap::Existing);
+        let decoded: Vec<String> = codec::decode(encoded.unwrap().as_slice()).unwrap();
+        assert_eq!(decoded, defaults);
     }
 }
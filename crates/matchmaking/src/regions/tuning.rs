@@ -0,0 +1,355 @@
+//! Worker overrides of a region, low-population regions need very different settings than the
+//! busy ones. Unset values keep the global [`crate::config`], regions without tuning behave as
+//! before. Playlist queue regions share the tuning of their region.
+
+use std::{collections::BTreeMap, path::Path};
+
+use bitcode::{Decode, Encode};
+use redis::{AsyncCommands, RedisError, aio::MultiplexedConnection};
+use serde::{Deserialize, Serialize};
+
+use crate::{aging::Aging, codec, experiments::MatchParams, namespace, rpc::Match};
+
+pub const REGION_TUNING_KEY: &str = "config:regions";
+
+#[derive(Debug, thiserror::Error)]
+pub enum Error {
+    #[error("invalid tuning of region `{0}`: {1}")]
+    Invalid(String, &'static str),
+    #[error("failed to read region tuning: {0}")]
+    Io(#[from] std::io::Error),
+    #[error(transparent)]
+    Json(#[from] serde_json::Error),
+    #[error(transparent)]
+    Redis(#[from] RedisError),
+    #[error(transparent)]
+    BitcodeDeser(#[from] codec::Error),
+}
+
+impl From<Error> for tonic::Status {
+    fn from(value: Error) -> Self {
+        match value {
+            Error::Invalid(..) | Error::Json(_) => Self::failed_precondition(value.to_string()),
+            Error::Io(_) | Error::Redis(_) | Error::BitcodeDeser(_) => {
+                Self::internal("Failed to load region tuning")
+            }
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, Encode, Decode, PartialEq, Eq)]
+#[serde(default)]
+pub struct RegionTuning {
+    /// The region is matched every `tick_every` worker runs, so slow regions gather players
+    pub tick_every: u64,
+    /// Open matches close with this many players once their host waited `close_after_secs`
+    pub min_players: Option<usize>,
+    pub close_after_secs: i64,
+    /// Players needed to close a match, replaces the configured `max_players`
+    pub max_players: Option<usize>,
+    /// Seconds added to the wait of backfill candidates, so they relax the skill window and
+    /// ping threshold sooner
+    pub backfill_head_start_secs: i64,
+    /// Region the players move to once they waited `fallback_after_secs`
+    pub fallback_region: Option<String>,
+    pub fallback_after_secs: i64,
+    /// Longest wait before every soft constraint of a player is relaxed, see [`crate::aging`]
+    pub max_wait_secs: Option<i64>,
+    /// Datacenters matches of the region can be placed in, lowercase names as players report
+    /// them, see [`crate::datacenter`]. Matches are placed by region when empty.
+    pub datacenters: Vec<String>,
+}
+
+impl Default for RegionTuning {
+    fn default() -> Self {
+        Self {
+            tick_every: 1,
+            min_players: None,
+            close_after_secs: 180,
+            max_players: None,
+            backfill_head_start_secs: 0,
+            fallback_region: None,
+            fallback_after_secs: 180,
+            max_wait_secs: None,
+            datacenters: Vec::new(),
+        }
+    }
+}
+
+impl RegionTuning {
+    /// Is the region matched in worker run `run`?
+    pub const fn is_due(&self, run: u64) -> bool {
+        run.is_multiple_of(self.tick_every)
+    }
+
+    pub const fn aging(&self) -> Aging {
+        Aging {
+            max_wait_secs: self.max_wait_secs,
+        }
+    }
+
+    pub const fn apply(&self, params: &mut MatchParams) {
+        if let Some(max_players) = self.max_players {
+            params.max_players = max_players;
+        }
+    }
+
+    /// Is the open match full, or large enough for the region once its host waited?
+    pub fn should_close(&self, open_match: &Match, now: i64) -> bool {
+        let players = open_match.players.len();
+        if players >= open_match.params.max_players {
+            return true;
+        }
+
+        self.min_players.is_some_and(|min_players| {
+            players >= min_players
+                && open_match
+                    .host_player()
+                    .is_some_and(|host| now - host.join_time >= self.close_after_secs)
+        })
+    }
+
+    fn validate(&self, region: &str) -> Result<(), Error> {
+        let invalid = |reason| Err(Error::Invalid(region.to_string(), reason));
+        let players = 1..=Match::MAX_PLAYERS;
+        if self.tick_every == 0 {
+            return invalid("tick_every must be at least 1");
+        }
+        if self.max_players.is_some_and(|max| !players.contains(&max))
+            || self.min_players.is_some_and(|min| !players.contains(&min))
+        {
+            return invalid("match size must be between 1 and MAX_PLAYERS");
+        }
+        if let (Some(min), Some(max)) = (self.min_players, self.max_players)
+            && min > max
+        {
+            return invalid("min_players must not exceed max_players");
+        }
+        if self.close_after_secs < 0
+            || self.backfill_head_start_secs < 0
+            || self.fallback_after_secs < 0
+        {
+            return invalid("durations must not be negative");
+        }
+        if self.max_wait_secs.is_some_and(|max_wait| max_wait < 1) {
+            return invalid("max_wait_secs must be at least 1");
+        }
+        if self.fallback_region.as_deref() == Some(region) {
+            return invalid("a region cannot fall back to itself");
+        }
+        if self.datacenters.iter().any(|datacenter| {
+            datacenter.is_empty() || *datacenter != datacenter.trim().to_lowercase()
+        }) {
+            return invalid("datacenters must be trimmed lowercase names");
+        }
+
+        Ok(())
+    }
+}
+
+/// Tuning of every region, by region name
+#[derive(Debug, Clone, Default, Serialize, Deserialize, Encode, Decode, PartialEq, Eq)]
+#[serde(transparent)]
+pub struct RegionTunings(pub BTreeMap<String, RegionTuning>);
+
+impl codec::Versioned for RegionTunings {}
+
+impl RegionTunings {
+    /// Tuning of a region or of its playlist queue region, defaults when none is configured
+    pub fn get(&self, queue_region: &str) -> RegionTuning {
+        let region = queue_region.split(':').next().unwrap_or(queue_region);
+
+        self.0.get(region).cloned().unwrap_or_default()
+    }
+
+    pub fn validate(&self) -> Result<(), Error> {
+        self.0
+            .iter()
+            .try_for_each(|(region, tuning)| tuning.validate(region))?;
+        self.0
+            .keys()
+            .try_for_each(|region| self.check_fallbacks(region))
+    }
+
+    /// Rejects fallback chains leading back to `region`, its players would move in a loop
+    fn check_fallbacks(&self, region: &str) -> Result<(), Error> {
+        let mut visited = vec![region];
+        let mut current = region;
+        while let Some(fallback) = self
+            .0
+            .get(current)
+            .and_then(|tuning| tuning.fallback_region.as_deref())
+        {
+            if visited.contains(&fallback) {
+                return Err(Error::Invalid(
+                    region.to_string(),
+                    "fallback regions must not form a cycle",
+                ));
+            }
+            visited.push(fallback);
+            current = fallback;
+        }
+
+        Ok(())
+    }
+}
+
+/// Reads the region tuning from a JSON config file, keyed by region
+pub fn load_file(path: impl AsRef<Path>) -> Result<RegionTunings, Error> {
+    let config = std::fs::read_to_string(path)?;
+    let tunings: RegionTunings = serde_json::from_str(&config)?;
+    tunings.validate()?;
+
+    Ok(tunings)
+}
+
+pub async fn set_tunings(
+    conn: &mut MultiplexedConnection,
+    tunings: &RegionTunings,
+) -> Result<(), Error> {
+    tunings.validate()?;
+    conn.set(namespace::key(REGION_TUNING_KEY), codec::encode(tunings))
+        .await
+        .map_err(Error::from)
+}
+
+/// Stored region tuning, empty when none was stored
+pub async fn get_tunings(conn: &mut MultiplexedConnection) -> Result<RegionTunings, Error> {
+    let encoded: Option<Vec<u8>> = conn.get(namespace::key(REGION_TUNING_KEY)).await?;
+
+    Ok(encoded
+        .map(|encoded| codec::decode(&encoded))
+        .transpose()?
+        .unwrap_or_default())
+}
+
+#[cfg(test)]
+mod tests {
+    use uuid::Uuid;
+
+    use super::*;
+    use crate::rpc::{
+        QueuedPlayer,
+        matchmaking::{JoinMode, Player},
+    };
+
+    fn host(join_time: i64) -> QueuedPlayer {
+        let player: QueuedPlayer = (
+            Uuid::new_v4(),
+            Player {
+                join_mode: JoinMode::CreateRoom.into(),
+                ..Default::default()
+            },
+            Default::default(),
+        )
+            .into();
+
+        player.joined_at(join_time)
+    }
+
+    #[test]
+    fn tuning_from_json() {
+        let tunings: RegionTunings = serde_json::from_str(
+            r#"{"SOUTH_AMERICA": {"tick_every": 3, "min_players": 2, "fallback_region": "US", "max_wait_secs": 90, "datacenters": ["sa-east"]}}"#,
+        )
+        .unwrap();
+        let tuning = tunings.get("SOUTH_AMERICA:ranked");
+
+        assert_eq!(tuning.tick_every, 3);
+        assert_eq!(tuning.min_players, Some(2));
+        assert_eq!(tuning.fallback_region.as_deref(), Some("US"));
+        assert_eq!(tuning.aging().max_wait_secs, Some(90));
+        assert_eq!(tuning.datacenters, vec!["sa-east".to_string()]);
+        assert_eq!(tunings.get("EU"), RegionTuning::default());
+        assert!(tunings.validate().is_ok());
+    }
+
+    #[test]
+    fn slow_regions_are_matched_every_few_runs() {
+        let tuning = RegionTuning {
+            tick_every: 3,
+            ..Default::default()
+        };
+
+        assert!(tuning.is_due(0));
+        assert!(!tuning.is_due(1));
+        assert!(tuning.is_due(3));
+        assert!(RegionTuning::default().is_due(1));
+    }
+
+    #[test]
+    fn small_matches_close_after_waiting() {
+        let tuning = RegionTuning {
+            min_players: Some(2),
+            close_after_secs: 60,
+            ..Default::default()
+        };
+        let solo = Match::host(&host(0), &[]).unwrap();
+        let duo = Match::host(&host(0), &[host(0)]).unwrap();
+
+        assert!(!tuning.should_close(&solo, 60));
+        assert!(!tuning.should_close(&duo, 59));
+        assert!(tuning.should_close(&duo, 60));
+        assert!(!RegionTuning::default().should_close(&duo, 600));
+    }
+
+    #[test]
+    fn invalid_tuning() {
+        let tuning = |tuning: RegionTuning| {
+            RegionTunings(BTreeMap::from([("EU".to_string(), tuning)])).validate()
+        };
+
+        assert!(
+            tuning(RegionTuning {
+                tick_every: 0,
+                ..Default::default()
+            })
+            .is_err()
+        );
+        assert!(
+            tuning(RegionTuning {
+                min_players: Some(3),
+                max_players: Some(2),
+                ..Default::default()
+            })
+            .is_err()
+        );
+        assert!(
+            tuning(RegionTuning {
+                fallback_region: Some("EU".to_string()),
+                ..Default::default()
+            })
+            .is_err()
+        );
+        assert!(
+            tuning(RegionTuning {
+                datacenters: vec!["EU-West".to_string()],
+                ..Default::default()
+            })
+            .is_err()
+        );
+    }
+
+    #[test]
+    fn fallback_cycles_are_invalid() {
+        let falls_back_to = |region: &str| RegionTuning {
+            fallback_region: Some(region.to_string()),
+            ..Default::default()
+        };
+        let chain = RegionTunings(BTreeMap::from([
+            ("SA".to_string(), falls_back_to("US")),
+            ("US".to_string(), falls_back_to("EU")),
+        ]));
+        let cycle = RegionTunings(BTreeMap::from([
+            ("SA".to_string(), falls_back_to("US")),
+            ("US".to_string(), falls_back_to("EU")),
+            ("EU".to_string(), falls_back_to("SA")),
+        ]));
+
+        assert!(chain.validate().is_ok());
+        assert!(matches!(
+            cycle.validate(),
+            Err(Error::Invalid(_, "fallback regions must not form a cycle"))
+        ));
+    }
+}
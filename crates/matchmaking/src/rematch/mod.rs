@@ -0,0 +1,378 @@
+//! Groups playing another mission together. Once a match completed, its players opt in with
+//! [`accept`] and its host re-queues the ones who accepted and stayed until the end as one party
+//! hosted by the host. The party queues with the wait its players accumulated before the match,
+//! so it is not sent to the back of the queue. Players who did not accept, abandoned the match,
+//! are under cooldown, queued again on their own or moved on to another match stay out.
+
+use std::collections::HashSet;
+
+use redis::{AsyncCommands, RedisError, aio::MultiplexedConnection};
+use uuid::Uuid;
+
+use crate::{
+    codec,
+    lifecycle::MatchState,
+    namespace,
+    party::{self, Party},
+    penalty,
+    rpc::{
+        Match, QueuedPlayer, active_match_key, matchmaking::JoinMode, player_create_match_key,
+        player_key, player_queue_key, player_versus_key, server::TEN_MINUTES,
+    },
+    store,
+};
+
+#[derive(Debug, thiserror::Error)]
+pub enum Error {
+    #[error("match `{0}` not found")]
+    MatchNotFound(Uuid),
+    #[error("player `{0}` is not the match host")]
+    NotHost(Uuid),
+    #[error("match `{0}` is not completed")]
+    NotCompleted(Uuid),
+    #[error("player `{0}` is already queued")]
+    AlreadyQueued(Uuid),
+    #[error("player `{0}` did not play this match")]
+    NotInMatch(Uuid),
+    #[error(transparent)]
+    Party(#[from] party::Error),
+    #[error(transparent)]
+    Penalty(#[from] penalty::Error),
+    #[error(transparent)]
+    Redis(#[from] RedisError),
+    #[error(transparent)]
+    BitcodeDeser(#[from] codec::Error),
+}
+
+impl From<Error> for tonic::Status {
+    fn from(value: Error) -> Self {
+        match value {
+            Error::MatchNotFound(_) => Self::not_found(value.to_string()),
+            Error::NotHost(_) | Error::NotInMatch(_) => Self::permission_denied(value.to_string()),
+            Error::NotCompleted(_) | Error::AlreadyQueued(_) => {
+                Self::failed_precondition(value.to_string())
+            }
+            Error::Party(err) => err.into(),
+            Error::Penalty(err) => err.into(),
+            Error::Redis(_) | Error::BitcodeDeser(_) => Self::internal("Failed to requeue party"),
+        }
+    }
+}
+
+pub const REMATCH_KEY: &str = "rematch";
+
+/// Players of a completed match who agreed to be queued again by its host
+pub fn accepted_key(match_id: &Uuid) -> String {
+    namespace::key(format_args!("{REMATCH_KEY}:{match_id}:accepted"))
+}
+
+/// Party queued again by [`requeue`]
+#[derive(Debug, Clone, PartialEq)]
+pub struct Requeued {
+    pub party: Party,
+    /// Join time shared by the party, see [`priority_join_time`]
+    pub join_time: i64,
+}
+
+/// Players of `completed` who did not abandon it, the host and the guests who `accepted`,
+/// host first
+pub fn survivors<'a>(
+    completed: &'a Match,
+    abandoned: &HashSet<Uuid>,
+    accepted: &HashSet<Uuid>,
+) -> Vec<&'a QueuedPlayer> {
+    let mut survivors: Vec<&QueuedPlayer> = completed
+        .players
+        .iter()
+        .filter(|player| !abandoned.contains(&player.player_id))
+        .filter(|player| {
+            player.player_id == completed.host_id || accepted.contains(&player.player_id)
+        })
+        .collect();
+    survivors.sort_by_key(|player| player.player_id != completed.host_id);
+
+    survivors
+}
+
+/// Join time keeping the longest wait of the players before `completed` formed
+pub fn priority_join_time(completed: &Match, now: i64) -> i64 {
+    let formed_at = completed
+        .lifecycle
+        .entered_at(MatchState::Ready)
+        .unwrap_or(now);
+    let waited = completed
+        .players
+        .iter()
+        .map(|player| formed_at - player.join_time)
+        .max()
+        .unwrap_or_default()
+        .max(0);
+
+    now - waited
+}
+
+/// Queue entries of the party, the host creates the room of its guests
+pub fn entries(members: &[&QueuedPlayer], host_id: Uuid, join_time: i64) -> Vec<QueuedPlayer> {
+    let guests: Vec<String> = members
+        .iter()
+        .filter(|player| player.player_id != host_id)
+        .map(|player| player.player_id.to_string())
+        .collect();
+
+    members
+        .iter()
+        .map(|player| {
+            let mut entry = (*player).clone();
+            entry.join_time = join_time;
+            if entry.player_id == host_id {
+                entry.join_mode = JoinMode::CreateRoom.into();
+                entry.party_ids = guests.clone();
+            } else {
+                entry.join_mode = JoinMode::JoinRoom.into();
+                entry.party_ids.clear();
+            }
+            entry
+        })
+        .collect()
+}
+
+async fn completed_match(conn: &mut MultiplexedConnection, match_id: Uuid) -> Result<Match, Error> {
+    let data: Option<Vec<u8>> = conn.get(active_match_key(&match_id)).await?;
+    let completed: Match = codec::decode(&data.ok_or(Error::MatchNotFound(match_id))?)?;
+    if completed.state() != MatchState::Completed {
+        return Err(Error::NotCompleted(match_id));
+    }
+
+    Ok(completed)
+}
+
+/// Records that `player_id` agrees to be queued again by the host of the completed match
+/// `match_id`, until the match expires
+pub async fn accept(
+    conn: &mut MultiplexedConnection,
+    match_id: Uuid,
+    player_id: Uuid,
+) -> Result<(), Error> {
+    let completed = completed_match(conn, match_id).await?;
+    if !completed
+        .players
+        .iter()
+        .any(|player| player.player_id == player_id)
+    {
+        return Err(Error::NotInMatch(player_id));
+    }
+    let key = accepted_key(&match_id);
+    redis::pipe()
+        .sadd(&key, player_id)
+        .ignore()
+        .expire(&key, TEN_MINUTES as i64)
+        .ignore()
+        .query_async(conn)
+        .await
+        .map(|_: ()| ())?;
+
+    Ok(())
+}
+
+/// Queues the players of the completed match `match_id` who accepted and are not queued yet as
+/// the party of its host
+pub async fn requeue(
+    conn: &mut MultiplexedConnection,
+    match_id: Uuid,
+    host_id: Uuid,
+    now: i64,
+) -> Result<Requeued, Error> {
+    let completed = completed_match(conn, match_id).await?;
+    if completed.host_id != host_id {
+        return Err(Error::NotHost(host_id));
+    }
+
+    penalty::check_cooldown(conn, &host_id).await?;
+
+    let abandoned = penalty::abandoned(conn, &match_id).await?;
+    let accepted: HashSet<Uuid> = conn.smembers(accepted_key(&match_id)).await?;
+    let mut members = Vec::new();
+    for player in survivors(&completed, &abandoned, &accepted) {
+        if conn.exists(player_key(&player.player_id)).await? {
+            if player.player_id == host_id {
+                return Err(Error::AlreadyQueued(host_id));
+            }
+            continue;
+        }
+        // players who moved on to another match
+        if store::player_match(conn, &player.player_id)
+            .await?
+            .is_some_and(|other| other != match_id)
+        {
+            continue;
+        }
+        // cooldowns come from abandoning other matches
+        if penalty::cooldown_remaining(conn, &player.player_id)
+            .await?
+            .is_some()
+        {
+            continue;
+        }
+        members.push(player);
+    }
+    let member_ids: Vec<Uuid> = members.iter().map(|player| player.player_id).collect();
+
+    let mut requeued = match party::player_party(conn, &host_id).await? {
+        Some(party) if party.host_id == host_id => party,
+        Some(mut other) => {
+            leave(conn, &mut other, host_id).await?;
+            Party::new(host_id)
+        }
+        None => Party::new(host_id),
+    };
+    let former: Vec<Uuid> = requeued
+        .guests()
+        .filter(|id| !member_ids.contains(id))
+        .copied()
+        .collect();
+    for player_id in &former {
+        party::unlink_player(conn, player_id).await?;
+    }
+    for player_id in member_ids
+        .iter()
+        .filter(|id| !requeued.members.contains(id))
+    {
+        if let Some(mut other) = party::player_party(conn, player_id).await? {
+            leave(conn, &mut other, *player_id).await?;
+        }
+    }
+    requeued.members.clone_from(&member_ids);
+    requeued.invited.retain(|id| !member_ids.contains(id));
+    party::save_party(conn, &requeued).await?;
+
+    let join_time = priority_join_time(&completed, now);
+    let create_room: i32 = JoinMode::CreateRoom.into();
+    let mut pipe = redis::pipe();
+    pipe.atomic();
+    for entry in entries(&members, host_id, join_time) {
+        let encoded = codec::encode(&entry);
+        store::put_player(&mut pipe, &entry, &encoded, TEN_MINUTES);
+        pipe.zadd(player_queue_key(&entry), &encoded, join_time)
+            .ignore();
+        if entry.join_mode == create_room {
+            pipe.zadd(player_create_match_key(&entry), &encoded, join_time)
+                .ignore();
+        }
+        if entry.is_versus() {
+            pipe.zadd(player_versus_key(&entry), &encoded, join_time)
+                .ignore();
+        }
+    }
+    pipe.query_async(conn).await.map(|_: ()| ())?;
+
+    Ok(Requeued {
+        party: requeued,
+        join_time,
+    })
+}
+
+/// Takes `player_id` out of a party it leaves for the requeued one
+async fn leave(
+    conn: &mut MultiplexedConnection,
+    party: &mut Party,
+    player_id: Uuid,
+) -> Result<(), party::Error> {
+    if party.host_id == player_id {
+        party::hand_over(conn, party, false).await?;
+    } else {
+        party.leave(player_id)?;
+        party::save_party(conn, party).await?;
+        party::unlink_player(conn, &player_id).await?;
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use skillratings::mhth::MhthRating;
+
+    use super::*;
+    use crate::rpc::matchmaking::Player;
+
+    fn queued(join_mode: i32, join_time: i64) -> QueuedPlayer {
+        let mut player: QueuedPlayer = (
+            Uuid::new_v4(),
+            Player {
+                join_mode,
+                region: "CAN".to_string(),
+                ..Default::default()
+            },
+            MhthRating::default(),
+        )
+            .into();
+        player.join_time = join_time;
+        player
+    }
+
+    fn completed() -> (Match, Vec<QueuedPlayer>) {
+        let host = queued(0, 100);
+        let guests = vec![queued(1, 40), queued(1, 90)];
+        let mut completed = Match::host(&host, &guests).unwrap();
+        for (state, at) in [
+            (MatchState::Ready, 160),
+            (MatchState::Starting, 160),
+            (MatchState::Active, 170),
+            (MatchState::Completed, 900),
+        ] {
+            completed.transition(state, at).unwrap();
+        }
+
+        (completed, guests)
+    }
+
+    #[test]
+    fn survivors_keep_their_wait() {
+        let (completed, guests) = completed();
+        let abandoned = HashSet::from([guests[0].player_id]);
+        let accepted = guests.iter().map(|guest| guest.player_id).collect();
+
+        let survivors = survivors(&completed, &abandoned, &accepted);
+        assert_eq!(survivors.len(), 2);
+        assert_eq!(survivors[0].player_id, completed.host_id);
+        assert_eq!(survivors[1].player_id, guests[1].player_id);
+        // the longest wait was 120s, from 40 to 160
+        assert_eq!(priority_join_time(&completed, 1000), 880);
+    }
+
+    #[test]
+    fn host_creates_the_room_of_its_guests() {
+        let (completed, guests) = completed();
+        let accepted = guests.iter().map(|guest| guest.player_id).collect();
+        let survivors = survivors(&completed, &HashSet::new(), &accepted);
+
+        let entries = entries(&survivors, completed.host_id, 880);
+        let create_room: i32 = JoinMode::CreateRoom.into();
+        assert_eq!(entries[0].join_mode, create_room);
+        assert_eq!(
+            entries[0].party_ids,
+            guests
+                .iter()
+                .map(|guest| guest.player_id.to_string())
+                .collect::<Vec<_>>()
+        );
+        assert!(entries[1..].iter().all(|entry| entry.party_ids.is_empty()
+            && entry.join_mode != create_room
+            && entry.join_time == 880));
+    }
+
+    #[test]
+    fn only_guests_who_accepted_are_requeued() {
+        let (completed, guests) = completed();
+        let accepted = HashSet::from([guests[1].player_id]);
+
+        let survivors = survivors(&completed, &HashSet::new(), &accepted);
+        assert_eq!(
+            survivors
+                .iter()
+                .map(|player| player.player_id)
+                .collect::<Vec<_>>(),
+            vec![completed.host_id, guests[1].player_id]
+        );
+    }
+}
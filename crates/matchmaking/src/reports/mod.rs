@@ -0,0 +1,315 @@
+use std::collections::HashMap;
+
+use redis::{AsyncCommands, RedisError, aio::MultiplexedConnection};
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+use crate::{
+    codec, nakama, namespace,
+    rpc::{
+        Match, active_match_key, matchmaking::ReportReason, player_match_key,
+        server::MATCH_LIFETIME,
+    },
+};
+
+pub const REPORTS_KEY: &str = "reports:count";
+/// Marks a player reported by a reporter for a match, so the report is counted once
+pub const REPORTED_KEY: &str = "reports:filed";
+/// Nakama storage collection holding the reports of the reported player
+pub const REPORTS_COLLECTION: &str = "player_reports";
+/// Counter field holding the report count of every reason
+pub const TOTAL_FIELD: &str = "total";
+pub const MAX_COMMENT_LEN: usize = 512;
+
+#[derive(Debug, thiserror::Error)]
+pub enum Error {
+    #[error("players cannot report themselves")]
+    SelfReport,
+    #[error("player `{0}` did not play the reported match")]
+    NotInSameMatch(Uuid),
+    #[error("report comment is too long, MAX LENGTH: {max}")]
+    CommentTooLong { max: usize },
+    #[error("player `{0}` was already reported for this match")]
+    AlreadyReported(Uuid),
+    #[error(transparent)]
+    Redis(#[from] RedisError),
+    #[error(transparent)]
+    BitcodeDeser(#[from] codec::Error),
+    #[error(transparent)]
+    Nakama(#[from] nakama::Error),
+}
+
+impl From<Error> for tonic::Status {
+    fn from(value: Error) -> Self {
+        match value {
+            Error::SelfReport | Error::CommentTooLong { .. } => {
+                Self::invalid_argument(value.to_string())
+            }
+            Error::NotInSameMatch(_) => Self::permission_denied(value.to_string()),
+            Error::AlreadyReported(_) => Self::already_exists(value.to_string()),
+            Error::Redis(_) | Error::BitcodeDeser(_) => Self::internal("Failed to load match"),
+            Error::Nakama(_) => Self::internal("Failed to store report"),
+        }
+    }
+}
+
+/// Report persisted to the Nakama storage of the reported player
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct Report {
+    pub id: Uuid,
+    pub reporter_id: Uuid,
+    pub reported_id: Uuid,
+    pub reason: String,
+    pub match_id: Uuid,
+    pub comment: String,
+    pub created_at: i64,
+}
+
+impl Report {
+    pub fn new(
+        reporter_id: Uuid,
+        reported_id: Uuid,
+        reason: ReportReason,
+        match_id: Uuid,
+        comment: String,
+    ) -> Result<Self, Error> {
+        if reporter_id == reported_id {
+            return Err(Error::SelfReport);
+        }
+        if comment.len() > MAX_COMMENT_LEN {
+            return Err(Error::CommentTooLong {
+                max: MAX_COMMENT_LEN,
+            });
+        }
+
+        Ok(Self {
+            id: Uuid::new_v4(),
+            reporter_id,
+            reported_id,
+            reason: reason.as_str_name().to_string(),
+            match_id,
+            comment,
+            created_at: chrono::Utc::now().timestamp(),
+        })
+    }
+}
+
+pub fn reports_key(player_id: &Uuid) -> String {
+    namespace::key(format_args!("{REPORTS_KEY}:{player_id}"))
+}
+
+pub fn reported_key(reporter_id: &Uuid, reported_id: &Uuid, match_id: &Uuid) -> String {
+    namespace::key(format_args!(
+        "{REPORTED_KEY}:{reporter_id}:{reported_id}:{match_id}"
+    ))
+}
+
+/// Claims the report of `reported_id` by `reporter_id` for `match_id`, once per match. Fails
+/// when it was already filed, [`release`] it when the report could not be stored.
+pub async fn claim(
+    conn: &mut MultiplexedConnection,
+    reporter_id: &Uuid,
+    reported_id: &Uuid,
+    match_id: &Uuid,
+) -> Result<(), Error> {
+    // matches are kept for their lifetime, reports can not be filed after
+    let claimed: bool = redis::cmd("SET")
+        .arg(reported_key(reporter_id, reported_id, match_id))
+        .arg(1)
+        .arg("NX")
+        .arg("EX")
+        .arg(*MATCH_LIFETIME)
+        .query_async(conn)
+        .await?;
+    if !claimed {
+        return Err(Error::AlreadyReported(*reported_id));
+    }
+
+    Ok(())
+}
+
+/// Releases a [`claim`], so the report can be filed again
+pub async fn release(
+    conn: &mut MultiplexedConnection,
+    reporter_id: &Uuid,
+    reported_id: &Uuid,
+    match_id: &Uuid,
+) -> Result<(), RedisError> {
+    conn.del(reported_key(reporter_id, reported_id, match_id))
+        .await
+}
+
+/// Finds the active match shared by both players, `match_id` defaults to the reporter's match
+pub async fn shared_match(
+    conn: &mut MultiplexedConnection,
+    reporter_id: &Uuid,
+    reported_id: &Uuid,
+    match_id: Option<Uuid>,
+) -> Result<Uuid, Error> {
+    let match_id = match match_id {
+        Some(match_id) => match_id,
+        None => conn
+            .get::<_, Option<Uuid>>(player_match_key(reporter_id))
+            .await?
+            .ok_or(Error::NotInSameMatch(*reporter_id))?,
+    };
+    let data: Option<Vec<u8>> = conn.get(active_match_key(&match_id)).await?;
+    let played: Match = codec::decode(&data.ok_or(Error::NotInSameMatch(*reporter_id))?)?;
+
+    for player_id in [reporter_id, reported_id] {
+        if !played.players.iter().any(|p| p.player_id == *player_id) {
+            return Err(Error::NotInSameMatch(*player_id));
+        }
+    }
+
+    Ok(match_id)
+}
+
+/// Increments the reason and total counters of the reported player, returning the total
+pub async fn increment(
+    conn: &mut MultiplexedConnection,
+    player_id: &Uuid,
+    reason: ReportReason,
+) -> Result<u64, RedisError> {
+    let key = reports_key(player_id);
+    let (total,): (u64,) = redis::pipe()
+        .hincr(&key, reason.as_str_name(), 1)
+        .ignore()
+        .hincr(&key, TOTAL_FIELD, 1)
+        .query_async(conn)
+        .await?;
+
+    Ok(total)
+}
+
+/// Reports received by a player for any reason
+pub async fn total(conn: &mut MultiplexedConnection, player_id: &Uuid) -> Result<u64, RedisError> {
+    let total: Option<u64> = conn.hget(reports_key(player_id), TOTAL_FIELD).await?;
+
+    Ok(total.unwrap_or_default())
+}
+
+/// Report counters of a player by reason name, including [`TOTAL_FIELD`]
+pub async fn counts(
+    conn: &mut MultiplexedConnection,
+    player_id: &Uuid,
+) -> Result<HashMap<String, u64>, RedisError> {
+    conn.hgetall(reports_key(player_id)).await
+}
+
+#[cfg(test)]
+mod tests {
+    use skillratings::mhth::MhthRating;
+
+    use super::*;
+    use crate::{
+        rpc::{QueuedPlayer, matchmaking::Player},
+        test_support::{create_redis, redis_client},
+    };
+
+    #[test]
+    fn self_report_is_rejected() {
+        let player_id = Uuid::new_v4();
+        let report = Report::new(
+            player_id,
+            player_id,
+            ReportReason::Griefing,
+            Uuid::new_v4(),
+            String::new(),
+        );
+
+        assert!(matches!(report, Err(Error::SelfReport)));
+    }
+
+    #[test]
+    fn long_comment_is_rejected() {
+        let report = Report::new(
+            Uuid::new_v4(),
+            Uuid::new_v4(),
+            ReportReason::Other,
+            Uuid::new_v4(),
+            "a".repeat(MAX_COMMENT_LEN + 1),
+        );
+
+        assert!(matches!(report, Err(Error::CommentTooLong { .. })));
+    }
+
+    #[tokio::test]
+    async fn report_same_match_player() {
+        let container = create_redis(6379).await;
+        let host = container.get_host().await.unwrap();
+        let port = container.get_host_port_ipv4(6379).await.unwrap();
+        let client = redis_client(host.to_string(), port);
+        let mut conn = client.get_multiplexed_async_connection().await.unwrap();
+        let [reporter, reported, stranger]: [QueuedPlayer; 3] = std::array::from_fn(|_| {
+            (Uuid::new_v4(), Player::default(), MhthRating::default()).into()
+        });
+        let played = Match::host(&reporter, std::slice::from_ref(&reported)).unwrap();
+        conn.set(active_match_key(&played.id), codec::encode(&played))
+            .await
+            .map(|_: ()| ())
+            .unwrap();
+        conn.set(player_match_key(&reporter.player_id), played.id)
+            .await
+            .map(|_: ()| ())
+            .unwrap();
+
+        let shared = shared_match(&mut conn, &reporter.player_id, &reported.player_id, None)
+            .await
+            .unwrap();
+        let not_shared = shared_match(
+            &mut conn,
+            &reporter.player_id,
+            &stranger.player_id,
+            Some(played.id),
+        )
+        .await;
+        increment(&mut conn, &reported.player_id, ReportReason::Cheating)
+            .await
+            .unwrap();
+        let total = increment(&mut conn, &reported.player_id, ReportReason::Afk)
+            .await
+            .unwrap();
+        claim(
+            &mut conn,
+            &reporter.player_id,
+            &reported.player_id,
+            &played.id,
+        )
+        .await
+        .unwrap();
+        let twice = claim(
+            &mut conn,
+            &reporter.player_id,
+            &reported.player_id,
+            &played.id,
+        )
+        .await;
+        release(
+            &mut conn,
+            &reporter.player_id,
+            &reported.player_id,
+            &played.id,
+        )
+        .await
+        .unwrap();
+        let released = claim(
+            &mut conn,
+            &reporter.player_id,
+            &reported.player_id,
+            &played.id,
+        )
+        .await;
+        let counts = counts(&mut conn, &reported.player_id).await.unwrap();
+        let reporter_total = super::total(&mut conn, &reporter.player_id).await.unwrap();
+
+        assert_eq!(shared, played.id);
+        assert!(matches!(not_shared, Err(Error::NotInSameMatch(id)) if id == stranger.player_id));
+        assert_eq!(total, 2);
+        assert_eq!(counts[ReportReason::Cheating.as_str_name()], 1);
+        assert_eq!(counts[TOTAL_FIELD], 2);
+        assert_eq!(reporter_total, 0);
+        assert!(matches!(twice, Err(Error::AlreadyReported(id)) if id == reported.player_id));
+        assert!(released.is_ok());
+    }
+}
@@ -0,0 +1,80 @@
+//! Seeded random numbers, so item rolls and simulations can be reproduced.
+
+/// SplitMix64, stable across releases unlike the std hasher
+#[derive(Debug, Clone)]
+pub struct Rng(u64);
+
+impl Rng {
+    pub const fn new(seed: u64) -> Self {
+        Self(seed)
+    }
+
+    pub const fn next_u64(&mut self) -> u64 {
+        self.0 = self.0.wrapping_add(0x9e37_79b9_7f4a_7c15);
+        let mut z = self.0;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        z ^ (z >> 31)
+    }
+
+    /// Value in `0..bound`, `bound` must not be `0`
+    pub const fn below(&mut self, bound: u64) -> u64 {
+        self.next_u64() % bound
+    }
+
+    /// Value in `0.0..1.0`
+    pub fn unit(&mut self) -> f64 {
+        (self.next_u64() >> 11) as f64 / (1u64 << 53) as f64
+    }
+
+    /// Normally distributed value, with the Box-Muller transform
+    pub fn normal(&mut self, mean: f64, deviation: f64) -> f64 {
+        let u1 = 1. - self.unit();
+        let u2 = self.unit();
+
+        mean + deviation * (-2. * u1.ln()).sqrt() * (std::f64::consts::TAU * u2).cos()
+    }
+
+    /// Index picked with the relative `weights`, `None` when every weight is `0`
+    pub fn weighted(&mut self, weights: &[u32]) -> Option<usize> {
+        let total: u64 = weights.iter().copied().map(u64::from).sum();
+        if total == 0 {
+            return None;
+        }
+        let mut pick = self.below(total);
+        weights.iter().position(|weight| {
+            let hit = pick < u64::from(*weight);
+            pick = pick.saturating_sub(u64::from(*weight));
+            hit
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn normal_values_center_on_the_mean() {
+        let mut rng = Rng::new(1);
+        let values: Vec<f64> = (0..4000).map(|_| rng.normal(25., 5.)).collect();
+        let mean = values.iter().sum::<f64>() / values.len() as f64;
+        let deviation =
+            (values.iter().map(|v| (v - mean).powi(2)).sum::<f64>() / values.len() as f64).sqrt();
+
+        assert!((mean - 25.).abs() < 0.5, "{mean}");
+        assert!((deviation - 5.).abs() < 0.5, "{deviation}");
+    }
+
+    #[test]
+    fn weights_decide_the_pick() {
+        let mut rng = Rng::new(2);
+        let picks: Vec<usize> = (0..1000).filter_map(|_| rng.weighted(&[1, 0, 3])).collect();
+
+        assert_eq!(picks.len(), 1000);
+        assert!(!picks.contains(&1));
+        let last = picks.iter().filter(|pick| **pick == 2).count();
+        assert!((650..850).contains(&last), "{last}");
+        assert_eq!(rng.weighted(&[0, 0]), None);
+    }
+}
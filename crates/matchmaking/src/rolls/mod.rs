@@ -0,0 +1,346 @@
+//! Rolls of the inventory items granted by the post-match reward flow. An item rarity is drawn
+//! from a weighted table, the rarity decides how many distinct rolls it gets from the roll pool.
+//! Generation is seeded so rewards can be reproduced, and a player never receives an exact copy
+//! of an item they own while the pool allows a different one.
+
+use std::{path::Path, sync::Arc};
+
+use redis::{AsyncCommands, RedisError, aio::MultiplexedConnection};
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+use crate::{
+    nakama::{Authenticated, NakamaClient},
+    namespace,
+    progression::{self, InventoryItems, PROGRESSION_COLLECTION, PROGRESSION_KEY},
+    rng::Rng,
+    rpc::server::MATCH_LIFETIME,
+};
+
+pub const ROLL_TABLE_KEY: &str = "rolls:table";
+/// Item granted to a player for a match, so a retried reward grants it once
+pub const REWARDED_KEY: &str = "rolls:rewarded";
+/// Attempts at an item the player does not own before granting a copy
+pub const MAX_REROLLS: usize = 8;
+
+#[derive(Debug, thiserror::Error)]
+pub enum Error {
+    #[error("failed to read roll table: {0}")]
+    Io(#[from] std::io::Error),
+    #[error(transparent)]
+    Json(#[from] serde_json::Error),
+    #[error(transparent)]
+    Redis(#[from] RedisError),
+    #[error(transparent)]
+    BitcodeDeser(#[from] bitcode::Error),
+    #[error(transparent)]
+    Progression(#[from] progression::Error),
+    #[error("roll table is not set")]
+    MissingTable,
+    #[error("roll table is not valid: {0}")]
+    InvalidTable(String),
+}
+
+impl From<Error> for tonic::Status {
+    fn from(value: Error) -> Self {
+        match value {
+            Error::Progression(err) => err.into(),
+            Error::MissingTable | Error::InvalidTable(_) => {
+                Self::failed_precondition(value.to_string())
+            }
+            _ => Self::internal("Failed to roll item"),
+        }
+    }
+}
+
+/// Chance of a rarity, relative to the other rarities of the table
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
+pub struct RarityWeight {
+    pub rarity: u8,
+    pub weight: u32,
+    /// Distinct rolls of an item of this rarity
+    pub rolls: usize,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize, PartialEq, Eq)]
+pub struct RollTable {
+    pub rarities: Vec<RarityWeight>,
+    /// Rolls an item can get, e.g. the affixes of the game
+    pub pool: Vec<Uuid>,
+}
+
+pub fn rewarded_key(match_id: &Uuid, player_id: &Uuid) -> String {
+    namespace::key(format_args!("{REWARDED_KEY}:{match_id}:{player_id}"))
+}
+
+/// Seed of the reward of `player_id` for `match_id`
+pub fn reward_seed(match_id: &Uuid, player_id: &Uuid) -> u64 {
+    match_id
+        .as_bytes()
+        .iter()
+        .chain(player_id.as_bytes())
+        .fold(0xcbf2_9ce4_8422_2325, |hash, byte| {
+            (hash ^ u64::from(*byte)).wrapping_mul(0x0100_0000_01b3)
+        })
+}
+
+impl RollTable {
+    pub fn validate(&self) -> Result<(), Error> {
+        if self.rarities.iter().all(|rarity| rarity.weight == 0) {
+            return Err(Error::InvalidTable("no rarity can be rolled".to_string()));
+        }
+        if let Some(rarity) = self
+            .rarities
+            .iter()
+            .find(|rarity| rarity.rolls > self.pool.len())
+        {
+            return Err(Error::InvalidTable(format!(
+                "rarity {} has more rolls than the pool",
+                rarity.rarity
+            )));
+        }
+
+        Ok(())
+    }
+
+    fn rarity(&self, rng: &mut Rng) -> RarityWeight {
+        let total: u64 = self.rarities.iter().map(|r| u64::from(r.weight)).sum();
+        let mut pick = rng.below(total);
+        for rarity in &self.rarities {
+            if pick < u64::from(rarity.weight) {
+                return *rarity;
+            }
+            pick -= u64::from(rarity.weight);
+        }
+
+        unreachable!("pick is below the total weight")
+    }
+
+    /// Distinct rolls drawn from the pool, sorted so equal items compare equal
+    fn rolls(&self, count: usize, rng: &mut Rng) -> Vec<Uuid> {
+        let mut pool = self.pool.clone();
+        let mut rolls = Vec::with_capacity(count);
+        for _ in 0..count {
+            let index = rng.below(pool.len() as u64) as usize;
+            rolls.push(pool.swap_remove(index));
+        }
+        rolls.sort();
+
+        rolls
+    }
+
+    /// Rolls `item_id`, drawing again up to [`MAX_REROLLS`] times while the item is in `owned`.
+    /// The table must be valid, see [`RollTable::validate`].
+    pub fn roll(&self, item_id: Uuid, seed: u64, owned: &[InventoryItems]) -> InventoryItems {
+        let mut rng = Rng::new(seed);
+        let mut item = self.roll_once(item_id, &mut rng);
+        for _ in 0..MAX_REROLLS {
+            if !owned.contains(&item) {
+                break;
+            }
+            item = self.roll_once(item_id, &mut rng);
+        }
+
+        item
+    }
+
+    fn roll_once(&self, item_id: Uuid, rng: &mut Rng) -> InventoryItems {
+        let rarity = self.rarity(rng);
+
+        InventoryItems {
+            id: item_id,
+            rolls: self.rolls(rarity.rolls, rng),
+            rarity: rarity.rarity,
+        }
+    }
+}
+
+/// Reads the roll table from a JSON config file
+pub fn load_file(path: impl AsRef<Path>) -> Result<RollTable, Error> {
+    let config = std::fs::read_to_string(path)?;
+    let table: RollTable = serde_json::from_str(&config)?;
+    table.validate()?;
+
+    Ok(table)
+}
+
+pub async fn set_table(conn: &mut MultiplexedConnection, table: &RollTable) -> Result<(), Error> {
+    table.validate()?;
+    conn.set(namespace::key(ROLL_TABLE_KEY), bitcode::serialize(table)?)
+        .await
+        .map_err(Error::from)
+}
+
+pub async fn get_table(conn: &mut MultiplexedConnection) -> Result<RollTable, Error> {
+    let encoded: Option<Vec<u8>> = conn.get(namespace::key(ROLL_TABLE_KEY)).await?;
+
+    Ok(bitcode::deserialize(&encoded.ok_or(Error::MissingTable)?)?)
+}
+
+/// Claims the reward of `player_id` for `match_id` with `item`, returns the item already
+/// granted when the reward was claimed before.
+pub async fn claim_reward(
+    conn: &mut MultiplexedConnection,
+    match_id: &Uuid,
+    player_id: &Uuid,
+    item: &InventoryItems,
+) -> Result<Option<InventoryItems>, Error> {
+    let granted: Option<Vec<u8>> = redis::cmd("SET")
+        .arg(rewarded_key(match_id, player_id))
+        .arg(bitcode::serialize(item)?)
+        .arg("NX")
+        .arg("GET")
+        .arg("EX")
+        .arg(*MATCH_LIFETIME)
+        .query_async(conn)
+        .await?;
+
+    Ok(granted
+        .map(|granted| bitcode::deserialize(&granted))
+        .transpose()?)
+}
+
+/// Rolls `item_id` for the player's reward of `match_id` and adds it to their progression,
+/// returns the granted item. A reward is granted once per match, retries return the same item.
+pub async fn reward(
+    conn: &mut MultiplexedConnection,
+    nakama_client: &NakamaClient<Authenticated>,
+    http_client: Arc<reqwest::Client>,
+    player_id: &Uuid,
+    match_id: &Uuid,
+    item_id: Uuid,
+) -> Result<InventoryItems, Error> {
+    let table = get_table(conn).await?;
+    let mut progression =
+        progression::progression(nakama_client, http_client.clone(), player_id).await?;
+    let item = table.roll(
+        item_id,
+        reward_seed(match_id, player_id),
+        &progression.inventory_items,
+    );
+    if let Some(granted) = claim_reward(conn, match_id, player_id, &item).await? {
+        return Ok(granted);
+    }
+    progression.inventory_items.push(item.clone());
+    if let Err(err) = nakama_client
+        .write_storage(
+            http_client,
+            PROGRESSION_COLLECTION,
+            PROGRESSION_KEY,
+            &player_id.to_string(),
+            &progression,
+        )
+        .await
+    {
+        // the reward was not granted, a retry can claim it again
+        conn.del::<_, ()>(rewarded_key(match_id, player_id)).await?;
+        return Err(progression::Error::from(err).into());
+    }
+
+    Ok(item)
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+    use crate::test_support::{create_redis, redis_client};
+
+    fn table() -> RollTable {
+        RollTable {
+            rarities: vec![
+                RarityWeight {
+                    rarity: 0,
+                    weight: 3,
+                    rolls: 1,
+                },
+                RarityWeight {
+                    rarity: 1,
+                    weight: 1,
+                    rolls: 3,
+                },
+            ],
+            pool: (0..4).map(|_| Uuid::new_v4()).collect(),
+        }
+    }
+
+    #[test]
+    fn same_seed_rolls_the_same_item() {
+        let table = table();
+        let item_id = Uuid::new_v4();
+
+        assert_eq!(table.roll(item_id, 7, &[]), table.roll(item_id, 7, &[]));
+    }
+
+    #[test]
+    fn rolls_are_distinct_and_match_the_rarity() {
+        let table = table();
+        for seed in 0..64 {
+            let item = table.roll(Uuid::new_v4(), seed, &[]);
+            let mut rolls = item.rolls.clone();
+            rolls.dedup();
+
+            let expected = if item.rarity == 0 { 1 } else { 3 };
+            assert_eq!(item.rolls.len(), expected);
+            assert_eq!(rolls, item.rolls);
+            assert!(item.rolls.iter().all(|roll| table.pool.contains(roll)));
+        }
+    }
+
+    #[test]
+    fn weights_decide_the_rarity() {
+        let table = table();
+        let common = (0..1000)
+            .filter(|seed| table.roll(Uuid::nil(), *seed, &[]).rarity == 0)
+            .count();
+
+        assert!((650..850).contains(&common), "{common}");
+    }
+
+    #[test]
+    fn owned_items_are_rerolled() {
+        let table = table();
+        let item_id = Uuid::new_v4();
+        let owned = table.roll(item_id, 3, &[]);
+
+        assert_ne!(table.roll(item_id, 3, std::slice::from_ref(&owned)), owned);
+    }
+
+    #[test]
+    fn invalid_tables() {
+        let mut empty = table();
+        empty
+            .rarities
+            .iter_mut()
+            .for_each(|rarity| rarity.weight = 0);
+        let mut small_pool = table();
+        small_pool.pool.truncate(2);
+
+        assert!(table().validate().is_ok());
+        assert!(matches!(empty.validate(), Err(Error::InvalidTable(_))));
+        assert!(matches!(small_pool.validate(), Err(Error::InvalidTable(_))));
+    }
+
+    #[tokio::test]
+    async fn rewards_are_claimed_once_per_match() {
+        let container = create_redis(6379).await;
+        let host = container.get_host().await.unwrap();
+        let port = container.get_host_port_ipv4(6379).await.unwrap();
+        let client = redis_client(host.to_string(), port);
+        let mut conn = client.get_multiplexed_async_connection().await.unwrap();
+        let table = table();
+        let (match_id, player_id) = (Uuid::new_v4(), Uuid::new_v4());
+        let item = table.roll(Uuid::new_v4(), reward_seed(&match_id, &player_id), &[]);
+        let other = table.roll(Uuid::new_v4(), 1, &[]);
+
+        let first = claim_reward(&mut conn, &match_id, &player_id, &item)
+            .await
+            .unwrap();
+        let retried = claim_reward(&mut conn, &match_id, &player_id, &other)
+            .await
+            .unwrap();
+
+        assert_eq!(first, None);
+        assert_eq!(retried, Some(item));
+    }
+}
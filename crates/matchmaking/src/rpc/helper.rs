@@ -1,10 +1,8 @@
 use std::fmt::Debug;
 
-use chrono::{DateTime, Local};
 use tonic::Status;
 use tracing::error;
-
-use crate::rpc::server::GAME_START;
+use uuid::Uuid;
 
 pub trait IntoTonicError<T> {
     fn to_tonic_error(
@@ -25,25 +23,9 @@ impl<T, E: Debug> IntoTonicError<T> for Result<T, E> {
     }
 }
 
-pub fn time_since(dt: &DateTime<Local>) -> Result<i64, tonic::Status> {
-    Ok(dt
-        .naive_utc()
-        .signed_duration_since(
-            GAME_START
-                .and_then(|dt| dt.and_hms_opt(0, 0, 0))
-                .ok_or_else(|| tonic::Status::internal("Failed define time of player join"))?,
-        )
-        .num_seconds())
-}
-
-#[cfg(test)]
-mod tests {
-    use super::*;
-
-    #[test]
-    fn now() {
-        let dt = Local::now();
-        let time = time_since(&dt).unwrap();
-        assert!(time > 22810515)
-    }
+pub fn parse_id(id: &str) -> Result<Uuid, Status> {
+    Uuid::parse_str(id).to_tonic_error(
+        format!("Invalid id: {id}"),
+        Box::new(Status::invalid_argument),
+    )
 }
@@ -3,11 +3,28 @@ use serde::{Deserialize, Serialize};
 use skillratings::mhth::MhthRating;
 use uuid::Uuid;
 
-use crate::rpc::matchmaking::Player;
+use crate::{
+    allocation::GameServer,
+    clans::Clan,
+    codec,
+    datacenter::{DatacenterPing, Placement},
+    environment::Challenge,
+    experiments::MatchParams,
+    lifecycle::Lifecycle,
+    match_settings::MatchSettings,
+    namespace,
+    rpc::matchmaking::Player,
+};
 
 pub mod matchmaking {
     #![allow(clippy::missing_const_for_fn)]
     tonic::include_proto!("matchmaking");
+
+    /// Second version of the player facing API, see [`crate::rpc::server::v2`]
+    pub mod v2 {
+        #![allow(clippy::missing_const_for_fn)]
+        tonic::include_proto!("matchmaking.v2");
+    }
 }
 
 pub mod helper;
@@ -16,15 +33,77 @@ pub mod server;
 pub mod worker;
 
 pub const CLOSED_MATCHES: &str = "matches:closed";
+pub const OPEN_MATCHES: &str = "matches:open";
+pub const DEAD_MATCHES: &str = "matches:dead_letter";
 pub const PLAYER_QUEUE: &str = "queue_player";
 pub const CREATE_MATCH_QUEUE: &str = "queue_create_match";
+pub const BACKFILL_QUEUE: &str = "queue_backfill";
+pub const VERSUS_QUEUE: &str = "queue_versus";
+pub const RAID_QUEUE: &str = "queue_raid";
+pub const ACTIVE_MATCH: &str = "match:active";
+pub const PLAYER_MATCH: &str = "match:player";
+pub const FORMING_MATCH: &str = "match:forming";
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, Encode, Decode, PartialEq, Eq, Default)]
+pub enum MatchKind {
+    /// Every player against the mission environment
+    #[default]
+    Cooperative,
+    /// Two opposing teams stored as the squads, see [`crate::versus`]
+    Versus { team_size: usize },
+    /// Independent parties stored as the squads, see [`crate::raid`]
+    Raid { squads: usize, squad_size: usize },
+}
+
+/// Whether the player chose its region, or it was detected from its address, see
+/// [`crate::geoip`]
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, Encode, Decode, PartialEq, Eq, Default)]
+pub enum RegionSource {
+    #[default]
+    Declared,
+    Detected,
+}
 
 #[derive(Debug, Clone, Serialize, Deserialize, Encode, Decode, PartialEq)]
 pub struct Match {
-    id: Uuid,
-    players: Vec<QueuedPlayer>,
-    region: String,
-    host_id: Uuid,
+    pub id: Uuid,
+    pub players: Vec<QueuedPlayer>,
+    pub region: String,
+    pub host_id: Uuid,
+    pub playlist: String,
+    /// Experiment buckets of the host, see [`crate::experiments`]
+    pub experiments: Vec<String>,
+    pub params: MatchParams,
+    /// State of the match and when it was entered, see [`crate::lifecycle`]
+    pub lifecycle: Lifecycle,
+    /// Id of the authoritative Nakama match, set once the match started
+    pub nakama_match_id: Option<String>,
+    /// Dedicated server of the match, see [`crate::allocation`]
+    pub game_server: Option<GameServer>,
+    /// Player ids of each squad, set when the match is ready, see [`crate::balance`]
+    pub squads: Vec<Vec<Uuid>>,
+    pub kind: MatchKind,
+    /// Set when the match starts, see [`crate::environment::challenge`]
+    pub challenge: Option<Challenge>,
+    /// Set when the match fills, see [`crate::datacenter`]
+    pub datacenter: Option<Placement>,
+    /// Mission difficulty chosen by the host, it does not change when players who opted in to
+    /// adjacent difficulties fill the match
+    pub difficulty: i32,
+    /// Modifiers of a custom match, chosen by the host it was created with
+    pub settings: Option<MatchSettings>,
+    /// [`VoiceChat`](matchmaking::VoiceChat) of the players, set when the match is ready, see
+    /// [`Match::shared_voice_chat`]
+    pub voice_chat: i32,
+}
+
+impl codec::Versioned for Match {
+    fn upgrade(version: u8, payload: &[u8]) -> Result<Self, codec::Error> {
+        match version {
+            0 => Ok(bitcode::decode::<codec::legacy::MatchV0>(payload)?.into()),
+            _ => Err(codec::Error::UnsupportedVersion(version)),
+        }
+    }
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize, Encode, Decode, PartialEq)]
@@ -38,16 +117,146 @@ pub struct QueuedPlayer {
     pub party_mode: i32,
     pub party_ids: Vec<String>,
     pub join_time: i64,
+    /// Trust factor between `0.0` and `1.0`, see [`crate::trust`]
+    pub trust: f64,
+    pub playlist: String,
+    /// Preferred mission types, empty accepts any
+    pub mission_types: Vec<String>,
+    /// Preferred maps, empty accepts any
+    pub maps: Vec<String>,
+    /// `{experiment}:{bucket}` ids, see [`crate::experiments`]
+    pub experiments: Vec<String>,
+    pub params: MatchParams,
+    /// Queue shard, see [`worker::can_match::skill_band`]
+    pub skill_band: i64,
+    /// Flagged by [`crate::smurf`], its rating was already raised
+    pub smurf: bool,
+    /// `x-request-id` of the join, see [`server::request_id`]
+    pub request_id: String,
+    /// [`matchmaking::InputDevice`] of the player
+    pub input_device: i32,
+    /// Whether the player declared `region` or it was detected
+    pub region_source: RegionSource,
+    /// [`matchmaking::QueueType`] of the player, see [`crate::ranked`]
+    pub queue_type: i32,
+    /// Modifiers of the match hosted by the player
+    pub match_settings: Option<MatchSettings>,
+    /// Nakama group the player queued with, see [`crate::clans`]
+    pub clan: Option<Clan>,
+    /// Preferred languages as lowercase ISO 639-1 codes, empty accepts any
+    pub languages: Vec<String>,
+    /// [`matchmaking::VoiceChat`] of the player
+    pub voice_chat: i32,
+    /// Closest reachable datacenters, see [`crate::datacenter`]
+    pub datacenter_pings: Vec<DatacenterPing>,
+    /// Fill a match one difficulty easier or harder when its own is starved, see
+    /// [`Match::is_difficulty_fit`]
+    pub adjacent_difficulty: bool,
+}
+
+impl codec::Versioned for QueuedPlayer {
+    fn upgrade(version: u8, payload: &[u8]) -> Result<Self, codec::Error> {
+        match version {
+            0 => Ok(bitcode::decode::<codec::legacy::QueuedPlayerV0>(payload)?.into()),
+            _ => Err(codec::Error::UnsupportedVersion(version)),
+        }
+    }
+}
+
+/// Queued player data
+pub fn player_key(player_id: &Uuid) -> String {
+    namespace::key(player_id)
+}
+
+pub fn closed_matches_key() -> String {
+    namespace::key(CLOSED_MATCHES)
+}
+
+pub fn dead_matches_key() -> String {
+    namespace::key(DEAD_MATCHES)
 }
 
 pub fn player_queue_key(data: &QueuedPlayer) -> String {
-    format!("{PLAYER_QUEUE}:{}:{}", data.party_mode, data.region)
+    region_queue_key(data.party_mode, &data.queue_region(), data.skill_band)
+}
+
+pub fn region_queue_key(party_mode: i32, region: &str, skill_band: i64) -> String {
+    namespace::key(format_args!(
+        "{PLAYER_QUEUE}:{party_mode}:{region}:{skill_band}"
+    ))
 }
 
 pub fn create_match_queue_key(region: &String) -> String {
-    format!("{CREATE_MATCH_QUEUE}:{}", region)
+    namespace::key(format_args!("{CREATE_MATCH_QUEUE}:{}", region))
+}
+
+pub fn player_create_match_key(data: &QueuedPlayer) -> String {
+    create_match_queue_key(&data.queue_region())
+}
+
+pub fn versus_queue_key(region: &String) -> String {
+    namespace::key(format_args!("{VERSUS_QUEUE}:{}", region))
+}
+
+pub fn player_versus_key(data: &QueuedPlayer) -> String {
+    versus_queue_key(&data.queue_region())
+}
+
+/// Raid queue of a raid playlist queue region, holds player ids scored by join time
+pub fn raid_queue_key(region: &String) -> String {
+    namespace::key(format_args!("{RAID_QUEUE}:{}", region))
+}
+
+pub fn player_raid_key(data: &QueuedPlayer) -> String {
+    raid_queue_key(&data.queue_region())
+}
+
+/// Queues `data` the way it joined: in its region queue, in the raid queue when it joined a
+/// `raid` playlist, else in the create-match queue when it hosts a room, and in the versus queue
+pub fn enqueue(pipe: &mut redis::Pipeline, data: &QueuedPlayer, encoded: &[u8], raid: bool) {
+    pipe.zadd(player_queue_key(data), encoded, data.join_time)
+        .ignore();
+    if raid {
+        pipe.zadd(player_raid_key(data), data.player_id, data.join_time)
+            .ignore();
+    } else if data.creates_room() {
+        pipe.zadd(player_create_match_key(data), encoded, data.join_time)
+            .ignore();
+    }
+    if data.is_versus() {
+        pipe.zadd(player_versus_key(data), encoded, data.join_time)
+            .ignore();
+    }
+}
+
+pub fn backfill_queue_key(region: &String) -> String {
+    namespace::key(format_args!("{BACKFILL_QUEUE}:{}", region))
+}
+
+pub fn backfill_slots_key(match_id: &Uuid) -> String {
+    namespace::key(format_args!("{BACKFILL_QUEUE}:slots:{match_id}"))
 }
 
 pub fn match_data_key(new_match: &Match) -> String {
-    format!("match:{}", new_match.id)
+    match_id_key(&new_match.id)
+}
+
+pub fn open_matches_key(region: &String) -> String {
+    namespace::key(format_args!("{OPEN_MATCHES}:{region}"))
+}
+
+pub fn match_id_key(match_id: &Uuid) -> String {
+    namespace::key(format_args!("match:{match_id}"))
+}
+
+pub fn active_match_key(match_id: &Uuid) -> String {
+    namespace::key(format_args!("{ACTIVE_MATCH}:{match_id}"))
+}
+
+pub fn player_match_key(player_id: &Uuid) -> String {
+    namespace::key(format_args!("{PLAYER_MATCH}:{player_id}"))
+}
+
+pub fn forming_match_key(player_id: &Uuid) -> String {
+    namespace::key(format_args!("{FORMING_MATCH}:{player_id}"))
 }
@@ -1,13 +1,91 @@
 use skillratings::mhth::MhthRating;
+use tracing::{Span, info_span};
 use uuid::Uuid;
 
-use crate::rpc::{Player, QueuedPlayer};
+use crate::{
+    clans::Clan,
+    config::MatchmakingConfig,
+    datacenter,
+    experiments::{Assignment, MatchParams},
+    match_settings::MatchSettings,
+    playlists::queue_region,
+    ranked,
+    rpc::{
+        Player, QueuedPlayer, RegionSource,
+        matchmaking::{QueueType, VoiceChat},
+        worker::can_match::skill_band,
+    },
+};
 
 impl QueuedPlayer {
     pub const fn joined_at(mut self, join_time: i64) -> Self {
         self.join_time = join_time;
         self
     }
+
+    pub const fn with_trust(mut self, trust: f64) -> Self {
+        self.trust = trust;
+        self
+    }
+
+    pub const fn with_smurf(mut self, smurf: bool) -> Self {
+        self.smurf = smurf;
+        self
+    }
+
+    pub fn with_skill_band(mut self, band_width: f64) -> Self {
+        self.skill_band =
+            ranked::skill_band(self.queue_type(), skill_band(&self.skillrating, band_width));
+        self
+    }
+
+    pub fn queue_type(&self) -> QueueType {
+        QueueType::try_from(self.queue_type).unwrap_or_default()
+    }
+
+    pub fn voice_chat(&self) -> VoiceChat {
+        VoiceChat::try_from(self.voice_chat).unwrap_or_default()
+    }
+
+    /// Queue region of the player playlist and queue type
+    pub fn queue_region(&self) -> String {
+        ranked::typed_queue_region(
+            queue_region(&self.region, &self.playlist),
+            self.queue_type(),
+        )
+    }
+
+    pub fn with_region(mut self, region: String, source: RegionSource) -> Self {
+        self.region = region;
+        self.region_source = source;
+        self
+    }
+
+    pub fn with_match_settings(mut self, match_settings: Option<MatchSettings>) -> Self {
+        self.match_settings = match_settings;
+        self
+    }
+
+    pub fn with_clan(mut self, clan: Option<Clan>) -> Self {
+        self.clan = clan;
+        self
+    }
+
+    pub fn with_request_id(mut self, request_id: String) -> Self {
+        self.request_id = request_id;
+        self
+    }
+
+    /// Span tracing the queue entry back to the `JoinQueue` request
+    pub fn span(&self) -> Span {
+        info_span!("queued_player", player_id = %self.player_id, request_id = %self.request_id)
+    }
+
+    pub fn with_experiments(mut self, assignment: Assignment) -> Self {
+        self.experiments = assignment.buckets;
+        self.params = assignment.params;
+        self
+    }
 }
 
 impl From<(Uuid, Player, MhthRating)> for QueuedPlayer {
@@ -16,7 +94,6 @@ impl From<(Uuid, Player, MhthRating)> for QueuedPlayer {
     ) -> Self {
         Self {
             player_id,
-            skillrating,
             ping: player.ping,
             difficulty: player.difficulty,
             join_mode: player.join_mode,
@@ -24,6 +101,29 @@ impl From<(Uuid, Player, MhthRating)> for QueuedPlayer {
             party_mode: player.party_mode,
             party_ids: player.party_member_id,
             join_time: 0,
+            trust: 1.,
+            playlist: player.playlist,
+            mission_types: player.mission_types,
+            maps: player.maps,
+            experiments: Vec::new(),
+            params: MatchParams::default(),
+            skillrating,
+            skill_band: skill_band(&skillrating, MatchmakingConfig::DEFAULT.skill_band_width),
+            smurf: false,
+            request_id: String::new(),
+            input_device: player.input_device,
+            region_source: RegionSource::Declared,
+            queue_type: player.queue_type,
+            match_settings: None,
+            clan: None,
+            languages: player
+                .languages
+                .iter()
+                .map(|language| language.trim().to_lowercase())
+                .collect(),
+            voice_chat: player.voice_chat,
+            datacenter_pings: datacenter::reported(player.datacenter_pings),
+            adjacent_difficulty: player.adjacent_difficulty,
         }
     }
 }
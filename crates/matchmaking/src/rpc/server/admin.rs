@@ -0,0 +1,123 @@
+use tonic::{Request, Response, Status};
+use tracing::info;
+
+use crate::{
+    admin,
+    rpc::{
+        helper::{IntoTonicError, parse_id},
+        matchmaking::{
+            ListMatchesRequest, ListMatchesResponse, MatchSummary, QueueSize, QueueSizesRequest,
+            QueueSizesResponse, QueuedPlayerRequest, QueuedPlayerResponse,
+            RemoveQueuedPlayerRequest, RemoveQueuedPlayerResponse, RequestTickRequest,
+            RequestTickResponse,
+        },
+        server::{
+            MatchmakingServer,
+            auth::{admin_actor, authorize_admin},
+        },
+    },
+    store,
+};
+
+impl MatchmakingServer {
+    pub(super) async fn queue_sizes(
+        &self,
+        request: Request<QueueSizesRequest>,
+    ) -> Result<Response<QueueSizesResponse>, Status> {
+        authorize_admin(&request)?;
+        let mut conn = self.redis.clone();
+
+        let queues = admin::queue_sizes(&mut conn)
+            .await?
+            .into_iter()
+            .map(|queue| QueueSize {
+                key: queue.key,
+                size: queue.size as u64,
+            })
+            .collect();
+
+        Ok(Response::new(QueueSizesResponse { queues }))
+    }
+
+    pub(super) async fn queued_player(
+        &self,
+        request: Request<QueuedPlayerRequest>,
+    ) -> Result<Response<QueuedPlayerResponse>, Status> {
+        authorize_admin(&request)?;
+        let player_id = parse_id(&request.get_ref().player_id)?;
+        let mut conn = self.redis.clone();
+
+        let queued = admin::queued_player(&mut conn, &player_id).await?;
+        let match_id = store::player_match(&mut conn, &player_id)
+            .await
+            .to_tonic_error(
+                "Failed to load the player match",
+                Box::new(Status::internal),
+            )?;
+
+        Ok(Response::new(QueuedPlayerResponse {
+            queued: queued.is_some(),
+            state: queued
+                .map(|player| format!("{player:#?}"))
+                .unwrap_or_default(),
+            match_id: match_id
+                .map(|match_id| match_id.to_string())
+                .unwrap_or_default(),
+        }))
+    }
+
+    pub(super) async fn remove_queued(
+        &self,
+        request: Request<RemoveQueuedPlayerRequest>,
+    ) -> Result<Response<RemoveQueuedPlayerResponse>, Status> {
+        authorize_admin(&request)?;
+        let actor = admin_actor(&request);
+        let player_id = parse_id(&request.get_ref().player_id)?;
+        let mut conn = self.redis.clone();
+
+        let removed =
+            admin::remove_player(&mut conn, &player_id, actor, self.clock.time_since_epoch())
+                .await?;
+        if removed {
+            info!("Removed `{player_id}` from the queues");
+        }
+
+        Ok(Response::new(RemoveQueuedPlayerResponse { removed }))
+    }
+
+    pub(super) async fn matches_in_state(
+        &self,
+        request: Request<ListMatchesRequest>,
+    ) -> Result<Response<ListMatchesResponse>, Status> {
+        authorize_admin(&request)?;
+        let name = &request.get_ref().state;
+        let state = store::parse_state(name)
+            .ok_or_else(|| Status::invalid_argument(format!("unknown match state `{name}`")))?;
+        let mut conn = self.redis.clone();
+
+        let matches = admin::matches_in_state(&mut conn, state)
+            .await?
+            .into_iter()
+            .map(|a_match| MatchSummary {
+                match_id: a_match.id.to_string(),
+                region: a_match.region,
+                host_id: a_match.host_id.to_string(),
+                players: a_match.players.len() as u32,
+            })
+            .collect();
+
+        Ok(Response::new(ListMatchesResponse { matches }))
+    }
+
+    pub(super) async fn tick(
+        &self,
+        request: Request<RequestTickRequest>,
+    ) -> Result<Response<RequestTickResponse>, Status> {
+        authorize_admin(&request)?;
+        let mut conn = self.redis.clone();
+
+        admin::request_tick(&mut conn).await?;
+
+        Ok(Response::new(RequestTickResponse {}))
+    }
+}
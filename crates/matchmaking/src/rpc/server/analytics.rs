@@ -0,0 +1,33 @@
+use tonic::{Request, Response, Status};
+
+use crate::{
+    analytics,
+    rpc::{
+        matchmaking::{QueueAnalyticsRequest, QueueAnalyticsResponse},
+        server::{MatchmakingServer, auth::authorize_admin},
+    },
+};
+
+impl MatchmakingServer {
+    pub(super) async fn analytics(
+        &self,
+        request: Request<QueueAnalyticsRequest>,
+    ) -> Result<Response<QueueAnalyticsResponse>, Status> {
+        authorize_admin(&request)?;
+        let window_secs = request.get_ref().window_secs;
+        let since = if window_secs == 0 {
+            i64::MIN
+        } else {
+            self.clock
+                .time_since_epoch()
+                .saturating_sub(i64::try_from(window_secs).unwrap_or(i64::MAX))
+        };
+        let mut conn = self.redis.clone();
+
+        let ticks = analytics::since(&mut conn, since).await?;
+
+        Ok(Response::new(QueueAnalyticsResponse {
+            ticks: ticks.into_iter().map(Into::into).collect(),
+        }))
+    }
+}
@@ -1,19 +1,24 @@
 use std::{
-    collections::BTreeMap,
-    sync::LazyLock,
+    collections::{BTreeMap, HashSet},
     time::{SystemTime, UNIX_EPOCH},
 };
 
 use hmac::{Hmac, Mac};
-use jwt::{Header, Token, VerifyWithKey};
+use jsonwebtoken::Algorithm;
+use jwt::{Header, SignWithKey, Token, VerifyWithKey};
 use serde::{Deserialize, Serialize};
 use sha2::Sha256;
 use tonic::{Request, Status};
 use tracing::error;
+use uuid::Uuid;
 
-use crate::nakama::helpers::get_env_encryption_key;
-
-static ENCRYPTION_KEY: LazyLock<String> = LazyLock::new(get_env_encryption_key);
+use super::{jwks, request_id::session_player};
+use crate::{
+    audit::Actor,
+    nakama::helpers::{get_env_encryption_key, get_previous_encryption_key},
+    rpc::helper::IntoTonicError,
+    sessions,
+};
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct SessionClaims {
@@ -30,30 +35,149 @@ pub struct UserId {
     pub(crate) player_id: String,
 }
 
-pub fn check_auth(mut req: Request<()>) -> Result<Request<()>, Status> {
+/// Env var with the comma separated user ids whose sessions may call the admin RPCs
+pub const ADMIN_USERS_VAR: &str = "ADMIN_USER_IDS";
+/// Env var with the comma separated user ids of the dedicated game servers and backend services
+pub const SERVER_USERS_VAR: &str = "SERVER_USER_IDS";
+
+/// User ids granted a role. Roles are granted by the server config. Nakama copies the session
+/// `vars` from the client authenticate request, so they never grant a role.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Roles {
+    pub admins: HashSet<String>,
+    pub servers: HashSet<String>,
+}
+
+impl Roles {
+    /// Reads [`ADMIN_USERS_VAR`] and [`SERVER_USERS_VAR`]
+    pub fn from_env() -> Self {
+        Self {
+            admins: user_ids(ADMIN_USERS_VAR),
+            servers: user_ids(SERVER_USERS_VAR),
+        }
+    }
+
+    /// Marks the request of `user_id` with its role
+    fn grant<T>(&self, user_id: &str, req: &mut Request<T>) {
+        if self.admins.contains(user_id) {
+            req.extensions_mut().insert(Admin);
+        } else if self.servers.contains(user_id) {
+            req.extensions_mut().insert(ServerSession);
+        }
+    }
+}
+
+fn user_ids(var: &str) -> HashSet<String> {
+    match std::env::var(var) {
+        Ok(ids) => ids
+            .split(',')
+            .map(str::trim)
+            .filter(|id| !id.is_empty())
+            .map(str::to_string)
+            .collect(),
+        Err(_) => HashSet::new(),
+    }
+}
+
+/// Marks requests from an admin session
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Admin;
+
+/// Marks requests from a server session
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ServerSession;
+
+pub(crate) fn authorize_admin<T>(request: &Request<T>) -> Result<(), Status> {
+    match request.extensions().get::<Admin>() {
+        Some(Admin) => Ok(()),
+        None => Err(Status::permission_denied("admin session required")),
+    }
+}
+
+/// Audit actor of a request authorized by [`authorize_admin`]
+pub(crate) fn admin_actor<T>(request: &Request<T>) -> Actor {
+    Actor::Admin(session_player(request).to_string())
+}
+
+/// Server sessions, admins act as servers too
+pub(crate) fn authorize_server<T>(request: &Request<T>) -> Result<(), Status> {
+    match request.extensions().get::<ServerSession>() {
+        Some(ServerSession) => Ok(()),
+        None => authorize_admin(request)
+            .map_err(|_| Status::permission_denied("server session required")),
+    }
+}
+
+/// Host reporting for a match: none for server sessions, which report for the dedicated server
+/// of any match, else the session player, who must host the match
+pub(crate) fn reporting_host<T>(
+    request: &Request<T>,
+    player_id: &str,
+) -> Result<Option<Uuid>, Status> {
+    if authorize_server(request).is_ok() {
+        return Ok(None);
+    }
+
+    authorize_player(request, player_id).map(Some)
+}
+
+/// Parses the requested `player_id` and checks it belongs to the session user.
+pub(crate) fn authorize_player<T>(request: &Request<T>, player_id: &str) -> Result<Uuid, Status> {
+    let user_id = request.extensions().get::<UserId>();
+
+    let player_id = Uuid::parse_str(player_id).to_tonic_error(
+        format!("Invalid player id: {player_id}"),
+        Box::new(Status::invalid_argument),
+    )?;
+    if user_id.is_none_or(|id| id.player_id != player_id.to_string()) {
+        return Err(Status::unauthenticated("invalid player token"));
+    }
+
+    Ok(player_id)
+}
+
+/// HS256 session of `user_id` signed with the Nakama encryption key, the way Nakama signs its
+/// sessions. Used by tooling acting as players, e.g. the `simulator` binary.
+pub fn sign_session(user_id: &str, issued_at: i64, expires_at: i64) -> String {
+    let claims = SessionClaims {
+        token_id: Uuid::new_v4().to_string(),
+        user_id: user_id.to_string(),
+        username: user_id.to_string(),
+        vars: BTreeMap::new(),
+        expires_at,
+        issued_at,
+    };
+    let key: Hmac<Sha256> = Hmac::new_from_slice(get_env_encryption_key().as_bytes())
+        .expect("HMAC accepts keys of any size");
+
+    Token::new(Header::default(), claims)
+        .sign_with_key(&key)
+        .expect("session claims serialize")
+        .as_str()
+        .to_string()
+}
+
+/// Verifies the session of `req`, marking it with its user and the role `roles` grant the user
+pub fn check_auth(mut req: Request<()>, roles: &Roles) -> Result<Request<()>, Status> {
     match req.metadata().get("authorization") {
         Some(t) => {
-            let key: Hmac<Sha256> = Hmac::new_from_slice(ENCRYPTION_KEY.as_bytes())
-                .inspect_err(|err| error!("Encryption key: {err}"))
-                .map_err(|_| Status::internal("Failed to verify token"))?;
             let token = t
                 .to_str()
                 .inspect_err(|err| error!("Failed to parse token as str: {err}"))
                 .map_err(|_| Status::internal("Failed to verify token"))?;
-
-            let token: Token<Header, SessionClaims, _> =
-                VerifyWithKey::verify_with_key(token, &key)
-                    .inspect_err(|err| error!("Failed to verify token: {err:?}"))
-                    .map_err(|_| Status::internal("Failed to verify token"))?;
+            let claims = verify_token(token)?;
+            if sessions::is_revoked(&claims.token_id, &claims.user_id, claims.issued_at) {
+                return Err(Status::unauthenticated("session revoked"));
+            }
 
             let start = SystemTime::now()
                 .duration_since(UNIX_EPOCH)
                 .expect("Time went backwards");
 
-            let (_, claims) = token.into();
             req.extensions_mut().insert(UserId {
                 player_id: claims.user_id.clone(),
             });
+            roles.grant(&claims.user_id, &mut req);
 
             if start.as_secs() > claims.expires_at as u64 {
                 Err(Status::unauthenticated("please refresh session token"))
@@ -65,12 +189,59 @@ pub fn check_auth(mut req: Request<()>) -> Result<Request<()>, Status> {
     }
 }
 
+/// HS256 tokens are verified with the Nakama encryption key,
+/// RS256 and EdDSA tokens with the JWKS key matching their `kid`.
+fn verify_token(token: &str) -> Result<SessionClaims, Status> {
+    let header = jsonwebtoken::decode_header(token)
+        .inspect_err(|err| error!("Failed to parse token header: {err}"))
+        .map_err(|_| Status::internal("Failed to verify token"))?;
+
+    match header.alg {
+        Algorithm::HS256 => {
+            // sessions signed before a key rotation are accepted during its grace period
+            let verified = verify_hs256(token, &get_env_encryption_key()).or_else(|err| {
+                get_previous_encryption_key()
+                    .and_then(|previous| verify_hs256(token, &previous).ok())
+                    .ok_or(err)
+            });
+
+            verified
+                .inspect_err(|err| error!("Failed to verify token: {err:?}"))
+                .map_err(|_| Status::internal("Failed to verify token"))
+        }
+        Algorithm::RS256 | Algorithm::EdDSA => jwks::verify(token, &header)
+            .inspect_err(|err| error!("Failed to verify token: {err}"))
+            .map_err(|_| Status::internal("Failed to verify token")),
+        alg => {
+            error!("Unsupported token algorithm: {alg:?}");
+            Err(Status::internal("Failed to verify token"))
+        }
+    }
+}
+
+fn verify_hs256(token: &str, encryption_key: &str) -> Result<SessionClaims, jwt::Error> {
+    let key: Hmac<Sha256> = Hmac::new_from_slice(encryption_key.as_bytes())
+        .map_err(|_| jwt::Error::InvalidSignature)?;
+    let token: Token<Header, SessionClaims, _> = VerifyWithKey::verify_with_key(token, &key)?;
+    let (_, claims) = token.into();
+
+    Ok(claims)
+}
+
 #[cfg(test)]
 mod tests {
-    use jwt::{Header, SignWithKey, Token};
-
     use super::*;
 
+    const ADMIN_ID: &str = "admin_id";
+    const SERVER_ID: &str = "server_id";
+
+    fn roles() -> Roles {
+        Roles {
+            admins: HashSet::from([ADMIN_ID.to_string()]),
+            servers: HashSet::from([SERVER_ID.to_string()]),
+        }
+    }
+
     #[test]
     fn happy_path_request() {
         let mut req = Request::new(());
@@ -88,13 +259,13 @@ mod tests {
             expires_at: exp as i64,
             issued_at: 0,
         };
-        let key: Hmac<Sha256> = Hmac::new_from_slice(ENCRYPTION_KEY.as_bytes()).unwrap();
+        let key: Hmac<Sha256> = Hmac::new_from_slice(get_env_encryption_key().as_bytes()).unwrap();
         let header = Header::default();
         let token = Token::new(header, claims).sign_with_key(&key).unwrap();
         let meta = req.metadata_mut();
         meta.insert("authorization", token.as_str().parse().unwrap());
 
-        let req = check_auth(req).unwrap();
+        let req = check_auth(req, &roles()).unwrap();
 
         assert_eq!(
             req.extensions().get::<UserId>().unwrap().player_id,
@@ -102,6 +273,112 @@ mod tests {
         );
     }
 
+    #[test]
+    fn signed_session_is_accepted() {
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .expect("Time went backwards")
+            .as_secs() as i64;
+        let mut req = Request::new(());
+        req.metadata_mut().insert(
+            "authorization",
+            sign_session("player_id", now, now + 100).parse().unwrap(),
+        );
+
+        let req = check_auth(req, &roles()).unwrap();
+
+        assert_eq!(
+            req.extensions().get::<UserId>().unwrap().player_id,
+            "player_id"
+        );
+        assert!(authorize_admin(&req).is_err());
+    }
+
+    fn session_of(user_id: &str, vars: BTreeMap<String, String>) -> Request<()> {
+        let mut req = Request::new(());
+        let exp = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .expect("Time went backwards")
+            .as_secs()
+            + 100;
+        let claims = SessionClaims {
+            token_id: "token_id".to_string(),
+            user_id: user_id.to_string(),
+            username: "username".to_string(),
+            vars,
+            expires_at: exp as i64,
+            issued_at: 0,
+        };
+        let key: Hmac<Sha256> = Hmac::new_from_slice(get_env_encryption_key().as_bytes()).unwrap();
+        let token = Token::new(Header::default(), claims)
+            .sign_with_key(&key)
+            .unwrap();
+        req.metadata_mut()
+            .insert("authorization", token.as_str().parse().unwrap());
+
+        req
+    }
+
+    #[test]
+    fn admin_role_request() {
+        let req = check_auth(session_of(ADMIN_ID, BTreeMap::new()), &roles()).unwrap();
+
+        assert!(authorize_admin(&req).is_ok());
+        assert!(authorize_server(&req).is_ok());
+        assert!(authorize_admin(&Request::new(())).is_err());
+
+        let req = check_auth(session_of(SERVER_ID, BTreeMap::new()), &roles()).unwrap();
+
+        assert!(authorize_server(&req).is_ok());
+        assert!(authorize_admin(&req).is_err());
+    }
+
+    #[test]
+    fn client_role_vars_are_ignored() {
+        for role in ["admin", "server"] {
+            let vars = BTreeMap::from([("role".to_string(), role.to_string())]);
+            let req = check_auth(session_of("player_id", vars), &roles()).unwrap();
+
+            assert!(authorize_admin(&req).is_err());
+            assert!(authorize_server(&req).is_err());
+        }
+    }
+
+    #[test]
+    fn servers_report_without_hosting() {
+        let host_id = Uuid::new_v4();
+        let server = check_auth(session_of(SERVER_ID, BTreeMap::new()), &roles()).unwrap();
+        let player =
+            check_auth(session_of(&host_id.to_string(), BTreeMap::new()), &roles()).unwrap();
+
+        assert_eq!(reporting_host(&server, "").unwrap(), None);
+        assert_eq!(
+            reporting_host(&player, &host_id.to_string()).unwrap(),
+            Some(host_id)
+        );
+        assert!(reporting_host(&player, &Uuid::new_v4().to_string()).is_err());
+    }
+
+    #[test]
+    fn roles_are_granted_to_configured_users() {
+        let roles = Roles {
+            admins: HashSet::from(["admin".to_string()]),
+            servers: HashSet::from(["server".to_string()]),
+        };
+        let granted = |user_id: &str| {
+            let mut req = Request::new(());
+            roles.grant(user_id, &mut req);
+            (
+                req.extensions().get::<Admin>().is_some(),
+                req.extensions().get::<ServerSession>().is_some(),
+            )
+        };
+
+        assert_eq!(granted("admin"), (true, false));
+        assert_eq!(granted("server"), (false, true));
+        assert_eq!(granted("player"), (false, false));
+    }
+
     #[test]
     fn wrong_key() {
         let mut req = Request::new(());
@@ -125,7 +402,7 @@ mod tests {
         let meta = req.metadata_mut();
         meta.insert("authorization", token.as_str().parse().unwrap());
 
-        let err = check_auth(req).unwrap_err();
+        let err = check_auth(req, &roles()).unwrap_err();
 
         assert_eq!(err.message(), "Failed to verify token");
     }
@@ -153,7 +430,7 @@ mod tests {
         let meta = req.metadata_mut();
         meta.insert("other-meta-key", token.as_str().parse().unwrap());
 
-        let err = check_auth(req).unwrap_err();
+        let err = check_auth(req, &roles()).unwrap_err();
 
         assert_eq!(err.message(), "No valid auth token");
     }
@@ -175,13 +452,13 @@ mod tests {
             expires_at: exp as i64,
             issued_at: 0,
         };
-        let key: Hmac<Sha256> = Hmac::new_from_slice(ENCRYPTION_KEY.as_bytes()).unwrap();
+        let key: Hmac<Sha256> = Hmac::new_from_slice(get_env_encryption_key().as_bytes()).unwrap();
         let header = Header::default();
         let token = Token::new(header, claims).sign_with_key(&key).unwrap();
         let meta = req.metadata_mut();
         meta.insert("authorization", token.as_str().parse().unwrap());
 
-        let req = check_auth(req).unwrap_err();
+        let req = check_auth(req, &roles()).unwrap_err();
 
         assert_eq!(req.message(), "please refresh session token");
     }
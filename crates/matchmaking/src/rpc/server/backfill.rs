@@ -0,0 +1,78 @@
+use redis::AsyncCommands;
+use tonic::{Request, Response, Status};
+use tracing::debug;
+
+use crate::{
+    codec,
+    rpc::{
+        Match, active_match_key, backfill_queue_key, backfill_slots_key,
+        helper::{IntoTonicError, parse_id},
+        matchmaking::{OpenSlotsRequest, OpenSlotsResponse},
+        server::{MATCH_LIFETIME, MatchmakingServer, auth::reporting_host},
+    },
+};
+
+impl MatchmakingServer {
+    pub(super) async fn open_slots(
+        &self,
+        request: Request<OpenSlotsRequest>,
+    ) -> Result<Response<OpenSlotsResponse>, Status> {
+        let host_id = reporting_host(&request, &request.get_ref().player_id)?;
+        let match_id = parse_id(&request.get_ref().match_id)?;
+        let open_slots = request
+            .get_ref()
+            .open_slots
+            .min(Match::MAX_PLAYERS as u32 - 1);
+        let mut conn = self.redis.clone();
+
+        let active: Option<Vec<u8>> = conn
+            .get(active_match_key(&match_id))
+            .await
+            .to_tonic_error("Failed to load active match", Box::new(Status::internal))?;
+        let active: Match = match active {
+            Some(data) => codec::decode(&data)
+                .to_tonic_error("Failed to load active match", Box::new(Status::internal))?,
+            None => return Err(Status::not_found("match is no longer active")),
+        };
+        if host_id.is_some_and(|host_id| active.host_id != host_id) {
+            return Err(Status::permission_denied(
+                "only the match host can backfill",
+            ));
+        }
+        if active.is_versus() {
+            return Err(Status::failed_precondition(
+                "versus matches are not backfilled",
+            ));
+        }
+
+        let queue_key = backfill_queue_key(&active.region);
+        if open_slots == 0 {
+            redis::pipe()
+                .zrem(&queue_key, match_id)
+                .ignore()
+                .del(backfill_slots_key(&match_id))
+                .ignore()
+                .query_async(&mut conn)
+                .await
+                .map(|_: ()| ())
+                .to_tonic_error("Failed to cancel backfill", Box::new(Status::internal))?;
+        } else {
+            let requested_at = self.clock.time_since_epoch();
+            redis::pipe()
+                .set_ex(backfill_slots_key(&match_id), open_slots, *MATCH_LIFETIME)
+                .ignore()
+                .zadd(&queue_key, match_id, requested_at)
+                .ignore()
+                .query_async(&mut conn)
+                .await
+                .map(|_: ()| ())
+                .to_tonic_error("Failed to queue backfill", Box::new(Status::internal))?;
+        }
+        debug!("Match `{match_id}` open slots: {open_slots}");
+
+        Ok(Response::new(OpenSlotsResponse {
+            match_id: match_id.to_string(),
+            open_slots,
+        }))
+    }
+}
@@ -0,0 +1,117 @@
+use tonic::{Request, Response, Status};
+
+use crate::{
+    rpc::{
+        Match,
+        helper::IntoTonicError,
+        matchmaking::{ListOpenMatchesRequest, ListOpenMatchesResponse, OpenMatch, PingTier},
+        server::{MatchmakingServer, auth::authorize_player},
+        worker::can_match::PingDeviation,
+    },
+    store::{self, MatchStore, RedisStore},
+};
+
+impl From<&Match> for OpenMatch {
+    fn from(value: &Match) -> Self {
+        let host_ping_tier: PingTier = value
+            .host_player()
+            .map_or(PingDeviation::Worst, |host| {
+                PingDeviation::from_ping(host.ping)
+            })
+            .into();
+
+        Self {
+            match_id: value.id.to_string(),
+            host_id: value.host_id.to_string(),
+            region: value.region.clone(),
+            players: value.players.len() as u32,
+            max_players: Match::MAX_PLAYERS as u32,
+            difficulty: value.difficulty,
+            host_ping_tier: host_ping_tier.into(),
+        }
+    }
+}
+
+impl ListOpenMatchesRequest {
+    fn accepts(&self, open: &OpenMatch) -> bool {
+        self.difficulty.is_none_or(|d| d == open.difficulty)
+            && self
+                .max_host_ping_tier
+                .is_none_or(|tier| open.host_ping_tier <= tier)
+            && open.max_players.saturating_sub(open.players) >= self.min_open_slots.max(1)
+    }
+}
+
+impl MatchmakingServer {
+    pub(super) async fn list_open(
+        &self,
+        request: Request<ListOpenMatchesRequest>,
+    ) -> Result<Response<ListOpenMatchesResponse>, Status> {
+        authorize_player(&request, &request.get_ref().player_id)?;
+        let matches = list_open(&RedisStore::new(self.redis.clone()), request.get_ref())
+            .await
+            .to_tonic_error("Failed to load open matches", Box::new(Status::internal))?;
+
+        Ok(Response::new(ListOpenMatchesResponse { matches }))
+    }
+}
+
+/// Open matches of the requested region accepted by the request filters
+pub(crate) async fn list_open(
+    matches: &dyn MatchStore,
+    request: &ListOpenMatchesRequest,
+) -> Result<Vec<OpenMatch>, store::Error> {
+    Ok(matches
+        .open_matches(&request.region)
+        .await?
+        .iter()
+        .map(OpenMatch::from)
+        .filter(|open| request.accepts(open))
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use skillratings::mhth::MhthRating;
+    use uuid::Uuid;
+
+    use super::*;
+    use crate::{
+        rpc::{QueuedPlayer, matchmaking::Player},
+        store::MemoryStore,
+    };
+
+    #[tokio::test]
+    async fn full_matches_are_not_listed() {
+        let store = MemoryStore::default();
+        let host = |_| -> QueuedPlayer {
+            (
+                Uuid::new_v4(),
+                Player {
+                    region: "CAN".to_string(),
+                    ..Default::default()
+                },
+                MhthRating::default(),
+            )
+                .into()
+        };
+        let open = Match::host(&host(0), &[]).unwrap();
+        let guests: Vec<QueuedPlayer> = (1..Match::MAX_PLAYERS).map(host).collect();
+        let full = Match::host(&host(0), &guests).unwrap();
+        store.open_match(&open, 60).await.unwrap();
+        store.open_match(&full, 60).await.unwrap();
+
+        let listed = list_open(
+            &store,
+            &ListOpenMatchesRequest {
+                region: "CAN".to_string(),
+                ..Default::default()
+            },
+        )
+        .await
+        .unwrap();
+
+        let ids: Vec<String> = listed.into_iter().map(|open| open.match_id).collect();
+        assert_eq!(ids, vec![open.id.to_string()]);
+    }
+}
@@ -0,0 +1,38 @@
+use tonic::{Request, Response, Status};
+use tracing::info;
+
+use crate::{
+    audit::{self, Action, AuditEvent},
+    config,
+    rpc::{
+        matchmaking::{ReloadConfigRequest, ReloadConfigResponse},
+        server::{
+            MatchmakingServer,
+            auth::{admin_actor, authorize_admin},
+        },
+    },
+};
+
+impl MatchmakingServer {
+    pub(super) async fn reload(
+        &self,
+        request: Request<ReloadConfigRequest>,
+    ) -> Result<Response<ReloadConfigResponse>, Status> {
+        authorize_admin(&request)?;
+        let actor = admin_actor(&request);
+        let mut conn = self.redis.clone();
+
+        let config = config::reload(&mut conn).await?;
+        info!("Matchmaking config reloaded: {config:?}");
+        let reloaded = AuditEvent::new(
+            Action::Admin,
+            actor,
+            "config",
+            self.clock.time_since_epoch(),
+        )
+        .with_reason("reload_config");
+        audit::emit(&mut conn, &reloaded).await;
+
+        Ok(Response::new(config.into()))
+    }
+}
@@ -0,0 +1,116 @@
+use std::{sync::LazyLock, time::Duration};
+
+use tokio::time::Instant;
+use tonic::{Request, Status, codegen::http};
+use tracing::warn;
+
+/// Env var with the longest time in seconds an RPC may wait on its backends
+pub const MAX_TIMEOUT_VAR: &str = "RPC_MAX_TIMEOUT_SECS";
+pub const DEFAULT_MAX_TIMEOUT: Duration = Duration::from_secs(10);
+const GRPC_TIMEOUT_HEADER: &str = "grpc-timeout";
+
+static MAX_TIMEOUT: LazyLock<Duration> = LazyLock::new(|| {
+    std::env::var(MAX_TIMEOUT_VAR)
+        .ok()
+        .and_then(|secs| secs.parse().ok())
+        .map_or(DEFAULT_MAX_TIMEOUT, Duration::from_secs)
+});
+
+/// Point in time after which an RPC stops waiting on Nakama and Redis
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Deadline(Instant);
+
+impl Deadline {
+    /// The client `grpc-timeout`, capped by the server maximum
+    pub fn from_request<T>(request: &Request<T>) -> Self {
+        Self::from_headers(request.metadata().as_ref())
+    }
+
+    /// [`Self::from_request`] of the headers of a call, before tonic decoded it
+    pub fn from_headers(headers: &http::HeaderMap) -> Self {
+        let timeout = headers
+            .get(GRPC_TIMEOUT_HEADER)
+            .and_then(|timeout| timeout.to_str().ok())
+            .and_then(parse_grpc_timeout)
+            .map_or(*MAX_TIMEOUT, |timeout| timeout.min(*MAX_TIMEOUT));
+
+        Self::after(timeout)
+    }
+
+    pub fn after(timeout: Duration) -> Self {
+        Self(Instant::now() + timeout)
+    }
+
+    /// Awaits `future`, failing with `DEADLINE_EXCEEDED` once the deadline passed
+    pub async fn run<F: Future>(self, future: F) -> Result<F::Output, Status> {
+        tokio::time::timeout_at(self.0, future)
+            .await
+            .inspect_err(|_| warn!("RPC deadline exceeded waiting on a backend"))
+            .map_err(|_| Status::deadline_exceeded("backend did not answer in time"))
+    }
+}
+
+/// Parses `grpc-timeout` values: up to 8 digits followed by a `H`, `M`, `S`, `m`, `u` or `n` unit
+fn parse_grpc_timeout(value: &str) -> Option<Duration> {
+    let (amount, unit) = value.split_at_checked(value.len().checked_sub(1)?)?;
+    if amount.is_empty() || amount.len() > 8 {
+        return None;
+    }
+    let amount: u64 = amount.parse().ok()?;
+
+    match unit {
+        "H" => Some(Duration::from_secs(amount * 3600)),
+        "M" => Some(Duration::from_secs(amount * 60)),
+        "S" => Some(Duration::from_secs(amount)),
+        "m" => Some(Duration::from_millis(amount)),
+        "u" => Some(Duration::from_micros(amount)),
+        "n" => Some(Duration::from_nanos(amount)),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn grpc_timeout_values() {
+        assert_eq!(parse_grpc_timeout("2S"), Some(Duration::from_secs(2)));
+        assert_eq!(parse_grpc_timeout("1H"), Some(Duration::from_secs(3600)));
+        assert_eq!(parse_grpc_timeout("250m"), Some(Duration::from_millis(250)));
+        assert_eq!(parse_grpc_timeout("10n"), Some(Duration::from_nanos(10)));
+        assert_eq!(parse_grpc_timeout("S"), None);
+        assert_eq!(parse_grpc_timeout("123456789S"), None);
+        assert_eq!(parse_grpc_timeout("5x"), None);
+        assert_eq!(parse_grpc_timeout(""), None);
+    }
+
+    #[test]
+    fn server_maximum_caps_client_timeout() {
+        let mut request = Request::new(());
+        request
+            .metadata_mut()
+            .insert(GRPC_TIMEOUT_HEADER, "1H".parse().unwrap());
+        let capped = Deadline::from_request(&request);
+        request
+            .metadata_mut()
+            .insert(GRPC_TIMEOUT_HEADER, "100m".parse().unwrap());
+        let client = Deadline::from_request(&request);
+
+        assert!(capped.0 <= Instant::now() + DEFAULT_MAX_TIMEOUT);
+        assert!(client.0 <= Instant::now() + Duration::from_millis(100));
+    }
+
+    #[tokio::test]
+    async fn stalled_backend() {
+        let ok = Deadline::after(Duration::from_secs(1))
+            .run(async { 1 })
+            .await;
+        let stalled = Deadline::after(Duration::from_millis(10))
+            .run(std::future::pending::<()>())
+            .await;
+
+        assert_eq!(ok.unwrap(), 1);
+        assert_eq!(stalled.unwrap_err().code(), tonic::Code::DeadlineExceeded);
+    }
+}
@@ -0,0 +1,55 @@
+use tonic::{Request, Response, Status};
+
+use crate::{
+    config, environment, party, ratings,
+    rpc::{
+        helper::IntoTonicError,
+        matchmaking::{RecommendDifficultyRequest, RecommendDifficultyResponse},
+        server::{MatchmakingServer, auth::authorize_player},
+    },
+    smurf,
+};
+
+impl MatchmakingServer {
+    pub(super) async fn recommend(
+        &self,
+        request: Request<RecommendDifficultyRequest>,
+    ) -> Result<Response<RecommendDifficultyResponse>, Status> {
+        let player_id = authorize_player(&request, &request.get_ref().player_id)?;
+        let mut conn = self.redis.clone();
+        let config = config::get_config(&mut conn).await?;
+
+        let members = match party::player_party(&mut conn, &player_id).await? {
+            Some(party) => party.members,
+            None => vec![player_id],
+        };
+        let mut team = Vec::with_capacity(members.len());
+        for member_id in &members {
+            let rating = self
+                .nakama_client
+                .get_skill_rating(self.http_client.clone(), &member_id.to_string())
+                .await
+                .to_tonic_error("Nakama API failed", Box::new(Status::internal))?;
+            let rating = ratings::rating_or(&mut conn, member_id, rating)
+                .await
+                .to_tonic_error("Failed to load rating", Box::new(Status::internal))?;
+            let stats = smurf::stats(&mut conn, member_id)
+                .await
+                .map_err(smurf::Error::from)?;
+            team.push(stats.converge(rating));
+        }
+
+        let environments =
+            environment::difficulties(&mut conn, &request.get_ref().mission, config.max_difficulty)
+                .await?;
+        let (difficulty, success_probability) =
+            environment::recommend(&environments, &team, config.target_success)
+                .ok_or_else(|| Status::failed_precondition("no difficulty to recommend"))?;
+
+        Ok(Response::new(RecommendDifficultyResponse {
+            difficulty,
+            success_probability,
+            target_success: config.target_success,
+        }))
+    }
+}
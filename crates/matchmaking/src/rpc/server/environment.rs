@@ -0,0 +1,91 @@
+use tonic::{Request, Response, Status};
+use tracing::info;
+
+use crate::{
+    audit::{self, Action, AuditEvent},
+    environment::{self, Environment},
+    rpc::{
+        matchmaking::{EnvironmentRequest, EnvironmentResponse, SetEnvironmentRequest},
+        server::{
+            MatchmakingServer,
+            auth::{admin_actor, authorize_admin},
+        },
+    },
+};
+
+impl MatchmakingServer {
+    pub(super) async fn set_environment_ratings(
+        &self,
+        request: Request<SetEnvironmentRequest>,
+    ) -> Result<Response<EnvironmentResponse>, Status> {
+        authorize_admin(&request)?;
+        let actor = admin_actor(&request);
+        let request = request.into_inner();
+        let environment = Environment {
+            mission: request.mission,
+            difficulty: request.difficulty,
+            entities: request.entities.into_iter().map(Into::into).collect(),
+            matches: 0,
+        }
+        .validate()?;
+        let mut conn = self.redis.clone();
+
+        environment::set_environment(&mut conn, &environment).await?;
+        info!(
+            "Environment of `{}` at difficulty {} rated with {} entities",
+            environment.mission,
+            environment.difficulty,
+            environment.entities.len()
+        );
+        let target = format!(
+            "environment:{}:{}",
+            environment.mission, environment.difficulty
+        );
+        let set = AuditEvent::new(Action::Admin, actor, target, self.clock.time_since_epoch())
+            .with_reason("set_environment");
+        audit::emit(&mut conn, &set).await;
+
+        Ok(Response::new((&environment).into()))
+    }
+
+    pub(super) async fn get_environment_ratings(
+        &self,
+        request: Request<EnvironmentRequest>,
+    ) -> Result<Response<EnvironmentResponse>, Status> {
+        authorize_admin(&request)?;
+        let request = request.into_inner();
+        let mut conn = self.redis.clone();
+
+        let environment =
+            environment::get_environment(&mut conn, &request.mission, request.difficulty).await?;
+
+        Ok(Response::new((&environment).into()))
+    }
+
+    pub(super) async fn delete_environment_ratings(
+        &self,
+        request: Request<EnvironmentRequest>,
+    ) -> Result<Response<EnvironmentResponse>, Status> {
+        authorize_admin(&request)?;
+        let actor = admin_actor(&request);
+        let request = request.into_inner();
+        let mut conn = self.redis.clone();
+
+        environment::delete_environment(&mut conn, &request.mission, request.difficulty).await?;
+        info!(
+            "Environment of `{}` at difficulty {} deleted",
+            request.mission, request.difficulty
+        );
+        let target = format!("environment:{}:{}", request.mission, request.difficulty);
+        let deleted = AuditEvent::new(Action::Admin, actor, target, self.clock.time_since_epoch())
+            .with_reason("delete_environment");
+        audit::emit(&mut conn, &deleted).await;
+
+        Ok(Response::new(EnvironmentResponse {
+            mission: request.mission,
+            difficulty: request.difficulty,
+            entities: Vec::new(),
+            matches: 0,
+        }))
+    }
+}
@@ -0,0 +1,155 @@
+use std::{pin::Pin, time::Duration};
+
+use tokio::sync::mpsc;
+use tokio_stream::{Stream, wrappers::ReceiverStream};
+use tonic::{Request, Response, Status};
+use tracing::{debug, error};
+use uuid::Uuid;
+
+use crate::{
+    notifications,
+    rpc::{
+        matchmaking::{QueueEvent, QueuePosition, WatchQueueRequest, queue_event::Event},
+        server::{MatchmakingServer, auth::authorize_player},
+    },
+    store::{self, MatchStore, QueueStore, RedisStore},
+};
+
+pub(crate) type QueueEventStream = Pin<Box<dyn Stream<Item = Result<QueueEvent, Status>> + Send>>;
+
+const POLL_INTERVAL: Duration = Duration::from_millis(500);
+/// Queue position is recomputed every `POSITION_TICKS` polls
+const POSITION_TICKS: usize = 4;
+
+impl MatchmakingServer {
+    pub(super) async fn watch_events(
+        &self,
+        request: Request<WatchQueueRequest>,
+    ) -> Result<Response<QueueEventStream>, Status> {
+        let player_id = authorize_player(&request, &request.get_ref().player_id)?;
+        debug!("MatchmakingServer::watch_queue `{player_id}`");
+        let mut conn = self.redis.clone();
+        let store = RedisStore::new(conn.clone());
+
+        let (tx, rx) = mpsc::channel(128);
+        tokio::spawn(async move {
+            let mut interval = tokio::time::interval(POLL_INTERVAL);
+            let mut last_position = None;
+            let mut tick = 0;
+            while !tx.is_closed() {
+                interval.tick().await;
+                let mut events: Vec<QueueEvent> =
+                    match notifications::drain(&mut conn, &player_id).await {
+                        Ok(events) => events.into_iter().map(Into::into).collect(),
+                        Err(err) => {
+                            error!("Failed to load notifications of `{player_id}`: {err}");
+                            continue;
+                        }
+                    };
+                if tick % POSITION_TICKS == 0 {
+                    match queue_position(&store, &store, &player_id).await {
+                        Ok(position) if position != last_position => {
+                            if let Some(position) = position {
+                                events.push(QueueEvent {
+                                    event: Some(Event::QueuePosition(position)),
+                                });
+                            }
+                            last_position = position;
+                        }
+                        Ok(_) => {}
+                        Err(err) => error!("Failed to load queue position of `{player_id}`: {err}"),
+                    }
+                }
+                tick += 1;
+                for event in events {
+                    if tx.send(Ok(event)).await.is_err() {
+                        break;
+                    }
+                }
+            }
+            debug!("\tclient `{player_id}` disconnected");
+        });
+
+        Ok(Response::new(
+            Box::pin(ReceiverStream::new(rx)) as QueueEventStream
+        ))
+    }
+}
+
+/// Computes the player position from the rank of its queue entry.
+/// Returns `None` when the player is not queued.
+pub(crate) async fn queue_position(
+    queues: &dyn QueueStore,
+    matches: &dyn MatchStore,
+    player_id: &Uuid,
+) -> Result<Option<QueuePosition>, store::Error> {
+    let Some(rank) = queues.queue_rank(player_id).await? else {
+        return Ok(None);
+    };
+
+    let matched_players = match matches.forming_match(player_id).await? {
+        Some(match_id) => matches
+            .match_players(&match_id)
+            .await?
+            .map_or(0, |players| players as u32),
+        None => 0,
+    };
+
+    Ok(Some(QueuePosition {
+        position: rank.rank.map_or(0, |rank| rank + 1),
+        queue_size: rank.size,
+        matched_players,
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use skillratings::mhth::MhthRating;
+
+    use super::*;
+    use crate::{
+        rpc::{Match, QueuedPlayer, matchmaking::Player},
+        store::MemoryStore,
+    };
+
+    #[tokio::test]
+    async fn position_counts_the_forming_match() {
+        let store = MemoryStore::default();
+        let players: Vec<QueuedPlayer> = (0..3)
+            .map(|join_time| {
+                let player: QueuedPlayer = (
+                    Uuid::new_v4(),
+                    Player {
+                        region: "CAN".to_string(),
+                        ..Default::default()
+                    },
+                    MhthRating::default(),
+                )
+                    .into();
+                player.joined_at(join_time)
+            })
+            .collect();
+        for player in &players {
+            store.enqueue(player);
+        }
+        let forming = Match::host(&players[1], &players[2..]).unwrap();
+        store.open_match(&forming, 7200).await.unwrap();
+
+        let position = queue_position(&store, &store, &players[1].player_id)
+            .await
+            .unwrap();
+        let unknown = queue_position(&store, &store, &Uuid::new_v4())
+            .await
+            .unwrap();
+
+        assert_eq!(
+            position,
+            Some(QueuePosition {
+                position: 2,
+                queue_size: 3,
+                matched_players: 2,
+            })
+        );
+        assert_eq!(unknown, None);
+    }
+}
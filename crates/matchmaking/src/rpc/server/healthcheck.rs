@@ -1,21 +1,40 @@
-use std::pin::Pin;
+use std::{
+    pin::Pin,
+    sync::{Arc, Mutex, PoisonError},
+    time::{Duration, Instant},
+};
 
 use tokio_stream::Stream;
 use tonic::Request;
 
-use crate::rpc::matchmaking::{
-    HealthCheckRequest, HealthCheckResponse, matchmaking_service_server::SERVICE_NAME,
+use crate::{
+    probes::CHECK_TIMEOUT,
+    rpc::{
+        matchmaking::{
+            HealthCheckRequest, HealthCheckResponse, matchmaking_service_server::SERVICE_NAME,
+        },
+        server::MatchmakingServer,
+    },
 };
 
 pub(crate) type ResponseStream =
     Pin<Box<dyn Stream<Item = Result<HealthCheckResponse, tonic::Status>> + Send>>;
 
+/// How long a probe result is reused, so health checks do not load Redis and Nakama
+pub const HEALTH_CACHE_TTL: Duration = Duration::from_secs(5);
+/// Dependencies answering slower than this serve degraded
+pub const DEGRADED_LATENCY: Duration = Duration::from_millis(500);
+/// Interval between the status checks of a `watch` stream
+pub const WATCH_INTERVAL: Duration = Duration::from_millis(200);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum ServingStatus {
     NotFound,
     Serving,
     NotServing,
     ServiceUnknown,
     DEPRECATED,
+    Degraded,
 }
 
 impl From<ServingStatus> for i32 {
@@ -26,6 +45,7 @@ impl From<ServingStatus> for i32 {
             ServingStatus::NotServing => 2,
             ServingStatus::ServiceUnknown => 3,
             ServingStatus::DEPRECATED => 4,
+            ServingStatus::Degraded => 5,
         }
     }
 }
@@ -38,42 +58,157 @@ impl From<ServingStatus> for HealthCheckResponse {
     }
 }
 
-pub fn healthy(request: Request<HealthCheckRequest>) -> HealthCheckResponse {
-    if request.get_ref().service != SERVICE_NAME && request.get_ref().service != "matchmaking" {
-        ServingStatus::NotFound.into()
-    } else {
-        use std::process::Command;
+/// Result of probing a dependency
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DependencyStatus {
+    Up,
+    /// Answered slower than [`DEGRADED_LATENCY`]
+    Slow,
+    /// Failed or timed out
+    Down,
+}
+
+impl DependencyStatus {
+    pub fn from_probe(answered: bool, latency: Duration) -> Self {
+        match (answered, latency > DEGRADED_LATENCY) {
+            (false, _) => Self::Down,
+            (true, true) => Self::Slow,
+            (true, false) => Self::Up,
+        }
+    }
+}
+
+/// Serving status of the dependencies: queues fail when Redis or Nakama is down, and wait on
+/// them when either is slow
+pub const fn serving_status(redis: DependencyStatus, nakama: DependencyStatus) -> ServingStatus {
+    match (redis, nakama) {
+        (DependencyStatus::Down, _) | (_, DependencyStatus::Down) => ServingStatus::NotServing,
+        (DependencyStatus::Slow, _) | (_, DependencyStatus::Slow) => ServingStatus::Degraded,
+        _ => ServingStatus::Serving,
+    }
+}
+
+pub fn known_service(request: &Request<HealthCheckRequest>) -> bool {
+    request.get_ref().service == SERVICE_NAME || request.get_ref().service == "matchmaking"
+}
+
+/// Last serving status and when it was probed, shared by the clones of the server
+#[derive(Debug, Clone, Default)]
+pub struct HealthCache(Arc<Mutex<Option<(Instant, ServingStatus)>>>);
+
+impl HealthCache {
+    /// Status probed less than [`HEALTH_CACHE_TTL`] before `now`
+    pub fn get(&self, now: Instant) -> Option<ServingStatus> {
+        self.0
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .filter(|(probed_at, _)| now.saturating_duration_since(*probed_at) < HEALTH_CACHE_TTL)
+            .map(|(_, status)| status)
+    }
+
+    pub fn set(&self, probed_at: Instant, status: ServingStatus) {
+        *self.0.lock().unwrap_or_else(PoisonError::into_inner) = Some((probed_at, status));
+    }
+}
+
+impl MatchmakingServer {
+    /// Serving status of Redis and Nakama, probed at most once every [`HEALTH_CACHE_TTL`]
+    pub(super) async fn serving_status(&self) -> ServingStatus {
+        if let Some(status) = self.health.get(Instant::now()) {
+            return status;
+        }
 
-        let status = Command::new("echo").arg("healthy").status();
-        match status {
-            Ok(_) => ServingStatus::Serving.into(),
-            Err(_) => ServingStatus::NotServing.into(),
+        let mut redis = self.redis.clone();
+        let ping = redis::cmd("PING");
+        let (redis, nakama) = tokio::join!(
+            timed(ping.query_async::<String>(&mut redis)),
+            timed(self.nakama_client.healthcheck(&self.http_client)),
+        );
+        let status = serving_status(
+            DependencyStatus::from_probe(redis.0.is_some_and(|pong| pong.is_ok()), redis.1),
+            DependencyStatus::from_probe(
+                nakama
+                    .0
+                    .is_some_and(|healthy| healthy.is_ok_and(|healthy| healthy)),
+                nakama.1,
+            ),
+        );
+        self.health.set(Instant::now(), status);
+
+        status
+    }
+
+    pub(super) async fn health_status(
+        &self,
+        request: &Request<HealthCheckRequest>,
+    ) -> HealthCheckResponse {
+        if known_service(request) {
+            self.serving_status().await.into()
+        } else {
+            ServingStatus::NotFound.into()
         }
     }
 }
 
+/// Output of `probe`, `None` after [`CHECK_TIMEOUT`], and how long it took
+async fn timed<T>(probe: impl Future<Output = T>) -> (Option<T>, Duration) {
+    let start = Instant::now();
+    let output = tokio::time::timeout(CHECK_TIMEOUT, probe).await.ok();
+
+    (output, start.elapsed())
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
 
     #[test]
-    fn matchmaking_is_healthy() {
-        let health = healthy(Request::new(HealthCheckRequest {
+    fn matchmaking_is_known() {
+        assert!(known_service(&Request::new(HealthCheckRequest {
             service: "matchmaking".to_string(),
-        }));
-        assert_eq!(health.status, 1);
-
-        let health = healthy(Request::new(HealthCheckRequest {
+        })));
+        assert!(known_service(&Request::new(HealthCheckRequest {
             service: SERVICE_NAME.to_string(),
-        }));
-        assert_eq!(health.status, 1);
+        })));
+        assert!(!known_service(&Request::new(HealthCheckRequest {
+            service: "random".to_string(),
+        })));
     }
 
     #[test]
-    fn other_service_is_notfound() {
-        let health = healthy(Request::new(HealthCheckRequest {
-            service: "random".to_string(),
-        }));
-        assert_eq!(health.status, 0);
+    fn any_dependency_down_stops_serving() {
+        let fast = Duration::from_millis(10);
+        let slow = DEGRADED_LATENCY + fast;
+        let up = DependencyStatus::from_probe(true, fast);
+
+        assert_eq!(serving_status(up, up), ServingStatus::Serving);
+        assert_eq!(
+            serving_status(up, DependencyStatus::from_probe(true, slow)),
+            ServingStatus::Degraded
+        );
+        assert_eq!(
+            serving_status(DependencyStatus::from_probe(false, fast), up),
+            ServingStatus::NotServing
+        );
+        assert_eq!(
+            serving_status(DependencyStatus::Slow, DependencyStatus::Down),
+            ServingStatus::NotServing
+        );
+        assert_eq!(i32::from(ServingStatus::Degraded), 5);
+    }
+
+    #[test]
+    fn cached_status_expires() {
+        let cache = HealthCache::default();
+        let shared = cache.clone();
+        let probed_at = Instant::now();
+
+        assert_eq!(cache.get(probed_at), None);
+        cache.set(probed_at, ServingStatus::Degraded);
+        assert_eq!(
+            shared.get(probed_at + Duration::from_secs(1)),
+            Some(ServingStatus::Degraded)
+        );
+        assert_eq!(cache.get(probed_at + HEALTH_CACHE_TTL), None);
     }
 }
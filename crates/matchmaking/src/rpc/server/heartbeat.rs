@@ -0,0 +1,77 @@
+use std::{collections::BTreeMap, pin::Pin, time::Duration};
+
+use tokio::{sync::mpsc, time::Instant};
+use tokio_stream::{Stream, wrappers::ReceiverStream};
+use tonic::{Request, Response, Status, Streaming};
+use tracing::{debug, error};
+
+use crate::{
+    latency, presence,
+    rpc::{
+        matchmaking::{HeartbeatAck, HeartbeatProbe},
+        server::{MatchmakingServer, auth::authorize_player, request_id::session_player},
+    },
+};
+
+pub(crate) type HeartbeatStream =
+    Pin<Box<dyn Stream<Item = Result<HeartbeatProbe, Status>> + Send>>;
+
+pub const HEARTBEAT_INTERVAL: Duration = Duration::from_secs(2);
+/// Probes left unanswered this long are dropped, they would only add stale samples
+const MAX_RTT: Duration = Duration::from_secs(5);
+
+impl MatchmakingServer {
+    pub(super) async fn heartbeat_stream(
+        &self,
+        request: Request<Streaming<HeartbeatAck>>,
+    ) -> Result<Response<HeartbeatStream>, Status> {
+        let player_id = authorize_player(&request, session_player(&request))?;
+        debug!("MatchmakingServer::heartbeat `{player_id}`");
+        let mut acks = request.into_inner();
+        let mut conn = self.redis.clone();
+        let clock = self.clock.clone();
+
+        let (tx, rx) = mpsc::channel(16);
+        tokio::spawn(async move {
+            let mut interval = tokio::time::interval(HEARTBEAT_INTERVAL);
+            // send time of the unanswered probes, timed by the server so clients cannot skew it
+            let mut pending: BTreeMap<u64, Instant> = BTreeMap::new();
+            let mut sequence = 0;
+            let mut rtt_ms = 0;
+            loop {
+                tokio::select! {
+                    _ = interval.tick() => {
+                        pending.retain(|_, sent_at| sent_at.elapsed() < MAX_RTT);
+                        sequence += 1;
+                        pending.insert(sequence, Instant::now());
+                        let probe = HeartbeatProbe { sequence, rtt_ms };
+                        if tx.send(Ok(probe)).await.is_err() {
+                            break;
+                        }
+                    }
+                    ack = acks.message() => {
+                        let Ok(Some(ack)) = ack else {
+                            break;
+                        };
+                        if let Err(err) = presence::seen(&mut conn, &player_id, clock.time_since_epoch()).await {
+                            error!("Failed to record the heartbeat of `{player_id}`: {err}");
+                        }
+                        let Some(sent_at) = pending.remove(&ack.sequence) else {
+                            continue;
+                        };
+                        let sample_ms = sent_at.elapsed().as_secs_f64() * 1000.;
+                        match latency::record(&mut conn, &player_id, sample_ms).await {
+                            Ok(rtt) => rtt_ms = rtt.ping().max(0) as u32,
+                            Err(err) => error!("Failed to record the ping of `{player_id}`: {err}"),
+                        }
+                    }
+                }
+            }
+            debug!("\theartbeat of `{player_id}` ended");
+        });
+
+        Ok(Response::new(
+            Box::pin(ReceiverStream::new(rx)) as HeartbeatStream
+        ))
+    }
+}
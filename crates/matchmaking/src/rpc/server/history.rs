@@ -0,0 +1,129 @@
+use chrono::{DateTime, Utc};
+use tonic::{Request, Response, Status};
+use uuid::Uuid;
+
+use crate::{
+    records::{HistoryQuery, MatchRecord},
+    rpc::{
+        helper::IntoTonicError,
+        matchmaking::{MatchHistoryEntry, MatchHistoryRequest, MatchHistoryResponse, MatchOutcome},
+        server::{MatchmakingServer, auth::authorize_player},
+    },
+};
+
+impl From<MatchRecord> for MatchHistoryEntry {
+    fn from(value: MatchRecord) -> Self {
+        Self {
+            match_id: value.match_id.to_string(),
+            host_id: value.host_id.to_string(),
+            region: value.region,
+            playlist: value.playlist,
+            mission: value.mission,
+            difficulty: value.difficulty,
+            won: value.won,
+            player_ids: value.player_ids.iter().map(Uuid::to_string).collect(),
+            completed_at: value.completed_at.timestamp(),
+        }
+    }
+}
+
+impl MatchmakingServer {
+    pub(super) async fn match_history(
+        &self,
+        request: Request<MatchHistoryRequest>,
+    ) -> Result<Response<MatchHistoryResponse>, Status> {
+        let player_id = authorize_player(&request, &request.get_ref().player_id)?;
+        let query = history_query(player_id, request.get_ref())?;
+
+        let page = self.records.history(&query).await?;
+
+        Ok(Response::new(MatchHistoryResponse {
+            matches: page.matches.into_iter().map(Into::into).collect(),
+            next_cursor: page
+                .next
+                .map(|cursor| cursor.to_string())
+                .unwrap_or_default(),
+        }))
+    }
+}
+
+fn history_query(player_id: Uuid, request: &MatchHistoryRequest) -> Result<HistoryQuery, Status> {
+    let timestamp = |secs: Option<i64>| {
+        secs.map(|secs| {
+            DateTime::<Utc>::from_timestamp(secs, 0)
+                .ok_or_else(|| Status::invalid_argument(format!("invalid timestamp {secs}")))
+        })
+        .transpose()
+    };
+    let after = match request.cursor.as_str() {
+        "" => None,
+        cursor => Some(cursor.parse()?),
+    };
+    let with_players = request
+        .party_member_id
+        .iter()
+        .map(|member_id| Uuid::parse_str(member_id))
+        .collect::<Result<_, _>>()
+        .to_tonic_error(
+            "Invalid party member id",
+            Box::new(Status::invalid_argument),
+        )?;
+    let won = match request.outcome() {
+        MatchOutcome::AnyOutcome => None,
+        MatchOutcome::Won => Some(true),
+        MatchOutcome::Lost => Some(false),
+    };
+
+    Ok(HistoryQuery {
+        after,
+        from: timestamp(request.from)?,
+        to: timestamp(request.to)?,
+        region: Some(request.region.clone()).filter(|region| !region.is_empty()),
+        won,
+        with_players,
+        ..HistoryQuery::new(player_id).with_page_size(request.page_size as usize)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::records::Cursor;
+
+    #[test]
+    fn requests_map_to_history_queries() {
+        let player_id = Uuid::new_v4();
+        let mate = Uuid::new_v4();
+        let cursor = Cursor {
+            completed_at: DateTime::from_timestamp(1_700_000_000, 0).unwrap(),
+            match_id: Uuid::new_v4(),
+        };
+        let request = MatchHistoryRequest {
+            player_id: player_id.to_string(),
+            cursor: cursor.to_string(),
+            from: Some(1_600_000_000),
+            region: "CAN".to_string(),
+            outcome: MatchOutcome::Lost.into(),
+            party_member_id: vec![mate.to_string()],
+            ..Default::default()
+        };
+
+        let query = history_query(player_id, &request).unwrap();
+
+        assert_eq!(query.after, Some(cursor));
+        assert_eq!(query.page_size, crate::records::DEFAULT_PAGE_SIZE);
+        assert_eq!(query.from.unwrap().timestamp(), 1_600_000_000);
+        assert_eq!(query.to, None);
+        assert_eq!(query.region.as_deref(), Some("CAN"));
+        assert_eq!(query.won, Some(false));
+        assert_eq!(query.with_players, vec![mate]);
+        let invalid = MatchHistoryRequest {
+            cursor: "page-2".to_string(),
+            ..request
+        };
+        assert_eq!(
+            history_query(player_id, &invalid).unwrap_err().code(),
+            tonic::Code::InvalidArgument
+        );
+    }
+}
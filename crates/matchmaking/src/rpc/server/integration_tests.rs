@@ -1,23 +1,30 @@
 use std::{marker::PhantomData, str::FromStr};
 
-use httpmock::{Method::POST, MockServer};
-use redis::aio::MultiplexedConnection;
-use serde_json::json;
-use testcontainers::{
-    ContainerAsync, GenericImage, ImageExt,
-    core::{IntoContainerPort, WaitFor},
-    runners::AsyncRunner,
+use httpmock::{
+    Method::{GET, POST},
+    MockServer,
 };
+use redis::{AsyncCommands, aio::MultiplexedConnection};
+use serde_json::json;
+use uuid::Uuid;
 
 use super::*;
-use crate::nakama::NakamaClient;
+use crate::{
+    clock::SystemClock,
+    codec,
+    lifecycle::MatchState,
+    nakama::NakamaClient,
+    records::NoRecords,
+    test_support::{create_redis, redis_client},
+    validation::NoValidation,
+};
 
 #[tokio::test]
 async fn test_join_queue() {
     let container = create_redis(6379).await;
     let host = container.get_host().await.unwrap();
     let port = container.get_host_port_ipv4(6379).await.unwrap();
-    let client = redis_client(host.to_string(), port).await;
+    let client = redis_client(host.to_string(), port);
     let mut conn = client.get_multiplexed_async_connection().await.unwrap();
     init_regions(conn.clone()).await;
 
@@ -34,6 +41,18 @@ async fn test_join_queue() {
                 .json_body(json!({"body": "{\"success\": true}", "error_message": "error"}));
         })
         .await;
+    let account_mock = server
+        .mock_async(|when, then| {
+            when.method(GET)
+                .path("/v2/console/account/01997433-3000-7b4b-8712-9253d26a68c8");
+            then.status(200)
+                .header("content-type", "application/json")
+                .json_body(json!({"account": {"user": {
+                    "id": "01997433-3000-7b4b-8712-9253d26a68c8",
+                    "create_time": "2025-01-02T10:00:00Z"
+                }}}));
+        })
+        .await;
 
     let http = reqwest::Client::new();
     let nakama_client = auth_client(server_port);
@@ -43,6 +62,12 @@ async fn test_join_queue() {
         redis: conn.clone(),
         http_client,
         nakama_client,
+        clock: Arc::new(SystemClock::default()),
+        records: Arc::new(NoRecords),
+        leaderboard: None,
+        health: Default::default(),
+        observability: Default::default(),
+        validator: Arc::new(NoValidation),
     };
 
     let player_data = Player {
@@ -54,18 +79,32 @@ async fn test_join_queue() {
         join_mode: 2,
         party_mode: 0,
         party_member_id: Vec::new(),
+        playlist: String::new(),
+        mission_types: Vec::new(),
+        maps: Vec::new(),
+        input_device: 0,
+        queue_type: 0,
+        match_settings: None,
+        clan_id: String::new(),
+        languages: Vec::new(),
+        voice_chat: 0,
+        datacenter_pings: Default::default(),
+        adjacent_difficulty: false,
     };
     let mut req = Request::new(player_data.clone());
     add_auth(&mut req);
     let response = matchmaking_server.join_queue(req).await.unwrap();
 
     mock.assert_async().await;
+    account_mock.assert_async().await;
 
-    let saved_player_encoded: Option<Vec<u8>> = conn
-        .get(Uuid::from_str("01997433-3000-7b4b-8712-9253d26a68c8").unwrap())
-        .await
-        .unwrap();
-    let decoded_player: QueuedPlayer = bitcode::decode(&saved_player_encoded.unwrap()).unwrap();
+    let saved_player_encoded = store::player_data(
+        &mut conn,
+        &Uuid::from_str("01997433-3000-7b4b-8712-9253d26a68c8").unwrap(),
+    )
+    .await
+    .unwrap();
+    let decoded_player: QueuedPlayer = codec::decode(&saved_player_encoded.unwrap()).unwrap();
 
     let zqueued = conn
         .zrange::<String, Vec<Option<Vec<u8>>>>(player_queue_key(&decoded_player), 0, -1)
@@ -74,13 +113,17 @@ async fn test_join_queue() {
         .clone()
         .unwrap();
     let zmatch = conn
-        .zrange::<String, Vec<Option<Vec<u8>>>>(create_match_queue_key(&player_data.region), 0, 1)
+        .zrange::<String, Vec<Option<Vec<u8>>>>(
+            crate::rpc::create_match_queue_key(&player_data.region),
+            0,
+            1,
+        )
         .await
         .unwrap();
 
-    container.pause().await.unwrap();
-    let decode_queued: QueuedPlayer = bitcode::decode(&zqueued).unwrap();
+    let decode_queued: QueuedPlayer = codec::decode(&zqueued).unwrap();
     assert_eq!(decode_queued, decoded_player);
+    assert_eq!(decoded_player.trust, 1.);
     // Only player is not Host
     assert!(zmatch.is_empty());
 
@@ -91,22 +134,280 @@ async fn test_join_queue() {
 
     let response = response.into_inner();
     assert_eq!(response.player_id, player_data.player_id);
-    assert_eq!(response.status, "waiting in queue");
+    assert_eq!(response.status(), JoinQueueStatus::Queued);
+}
+
+#[tokio::test]
+async fn test_duplicate_join_queue() {
+    let container = create_redis(6379).await;
+    let host = container.get_host().await.unwrap();
+    let port = container.get_host_port_ipv4(6379).await.unwrap();
+    let client = redis_client(host.to_string(), port);
+    let mut conn = client.get_multiplexed_async_connection().await.unwrap();
+    init_regions(conn.clone()).await;
+
+    let server = MockServer::start_async().await;
+    server
+        .mock_async(|when, then| {
+            when.method(POST)
+                .path("/v2/console/api/endpoints/rpc/healthcheck");
+            then.status(200)
+                .header("content-type", "application/json")
+                .json_body(json!({"body": "{\"success\": true}", "error_message": "error"}));
+        })
+        .await;
+    server
+        .mock_async(|when, then| {
+            when.method(GET)
+                .path("/v2/console/account/01997433-3000-7b4b-8712-9253d26a68c8");
+            then.status(200)
+                .header("content-type", "application/json")
+                .json_body(json!({"account": {"user": {
+                    "id": "01997433-3000-7b4b-8712-9253d26a68c8",
+                    "create_time": "2025-01-02T10:00:00Z"
+                }}}));
+        })
+        .await;
+    let matchmaking_server = MatchmakingServer {
+        redis: conn.clone(),
+        http_client: Arc::new(reqwest::Client::new()),
+        nakama_client: Arc::new(auth_client(server.address().port())),
+        clock: Arc::new(SystemClock::default()),
+        records: Arc::new(NoRecords),
+        leaderboard: None,
+        health: Default::default(),
+        observability: Default::default(),
+        validator: Arc::new(NoValidation),
+    };
+
+    let mut player_data = Player {
+        player_id: "01997433-3000-7b4b-8712-9253d26a68c8".to_string(),
+        region: "CAN".to_string(),
+        ping: 20,
+        join_mode: 2,
+        ..Default::default()
+    };
+    let mut req = Request::new(player_data.clone());
+    add_auth(&mut req);
+    let first = matchmaking_server.join_queue(req).await.unwrap();
+    player_data.ping = 80;
+    let mut req = Request::new(player_data.clone());
+    add_auth(&mut req);
+    let second = matchmaking_server.join_queue(req).await.unwrap();
+
+    let saved = store::player_data(&mut conn, &Uuid::from_str(&player_data.player_id).unwrap())
+        .await
+        .unwrap()
+        .unwrap();
+    let saved: QueuedPlayer = codec::decode(&saved).unwrap();
+    let queued: Vec<Vec<u8>> = conn.zrange(player_queue_key(&saved), 0, -1).await.unwrap();
+    let metrics = crate::metrics::queue_metrics(&mut conn).await.unwrap();
+
+    assert_eq!(saved.ping, 80);
+    assert_eq!(queued, vec![codec::encode(&saved)]);
+    assert_eq!(metrics.duplicate_joins, 1);
+    assert_eq!(first.get_ref().status(), JoinQueueStatus::Queued);
+    assert_eq!(second.get_ref().status(), JoinQueueStatus::AlreadyQueued);
+}
+
+#[tokio::test]
+async fn test_rejoin_match() {
+    let container = create_redis(6379).await;
+    let host = container.get_host().await.unwrap();
+    let port = container.get_host_port_ipv4(6379).await.unwrap();
+    let client = redis_client(host.to_string(), port);
+    let mut conn = client.get_multiplexed_async_connection().await.unwrap();
+
+    let player_id = Uuid::from_str("01997433-3000-7b4b-8712-9253d26a68c8").unwrap();
+    let player: QueuedPlayer = (
+        player_id,
+        Player {
+            region: "CAN".to_string(),
+            ..Default::default()
+        },
+        skillratings::mhth::MhthRating::default(),
+    )
+        .into();
+    let started = crate::rpc::Match::host(&player, &[]).unwrap();
+    conn.set(
+        crate::rpc::active_match_key(&started.id),
+        codec::encode(&started),
+    )
+    .await
+    .map(|_: ()| ())
+    .unwrap();
+    conn.set(crate::rpc::player_match_key(&player_id), started.id)
+        .await
+        .map(|_: ()| ())
+        .unwrap();
+
+    let matchmaking_server = MatchmakingServer {
+        redis: conn.clone(),
+        http_client: Arc::new(reqwest::Client::new()),
+        nakama_client: Arc::new(auth_client(666)),
+        clock: Arc::new(SystemClock::default()),
+        records: Arc::new(NoRecords),
+        leaderboard: None,
+        health: Default::default(),
+        observability: Default::default(),
+        validator: Arc::new(NoValidation),
+    };
+    let mut req = Request::new(crate::rpc::matchmaking::RejoinMatchRequest {
+        player_id: player_id.to_string(),
+    });
+    add_auth(&mut req);
+    let response = matchmaking_server.rejoin_match(req).await.unwrap();
+    let mut req = Request::new(Player {
+        player_id: player_id.to_string(),
+        region: "CAN".to_string(),
+        ..Default::default()
+    });
+    add_auth(&mut req);
+    let joined = matchmaking_server.join_queue(req).await.unwrap();
+
+    let joined = joined.into_inner();
+    assert_eq!(joined.status(), JoinQueueStatus::InMatch);
+    assert!(joined.detail.contains(&started.id.to_string()));
+    let response = response.into_inner();
+    assert_eq!(response.match_id, started.id.to_string());
+    assert_eq!(response.host_id, player_id.to_string());
+    assert_eq!(response.player_ids, vec![player_id.to_string()]);
 }
 
-async fn redis_client(host: String, port: u16) -> redis::Client {
-    redis::Client::open(format!("redis://{host}:{port}")).unwrap()
+#[tokio::test]
+async fn test_queue_position() {
+    let container = create_redis(6379).await;
+    let host = container.get_host().await.unwrap();
+    let port = container.get_host_port_ipv4(6379).await.unwrap();
+    let client = redis_client(host.to_string(), port);
+    let mut conn = client.get_multiplexed_async_connection().await.unwrap();
+
+    let players: Vec<QueuedPlayer> = (0..3)
+        .map(|_| {
+            (
+                Uuid::new_v4(),
+                Player {
+                    region: "CAN".to_string(),
+                    ..Default::default()
+                },
+                skillratings::mhth::MhthRating::default(),
+            )
+                .into()
+        })
+        .collect();
+    for (score, player) in players.iter().enumerate() {
+        let encoded = codec::encode(player);
+        let mut pipe = redis::pipe();
+        store::put_player(&mut pipe, player, &encoded, TEN_MINUTES);
+        pipe.query_async(&mut conn).await.map(|_: ()| ()).unwrap();
+        conn.zadd(player_queue_key(player), &encoded, score)
+            .await
+            .map(|_: ()| ())
+            .unwrap();
+    }
+    let forming = crate::rpc::Match::host(&players[1], &players[2..]).unwrap();
+    let mut pipe = redis::pipe();
+    store::put_match(&mut pipe, &forming, TWELVE_MINUTES);
+    pipe.query_async(&mut conn).await.map(|_: ()| ()).unwrap();
+    conn.set(
+        crate::rpc::forming_match_key(&players[1].player_id),
+        forming.id,
+    )
+    .await
+    .map(|_: ()| ())
+    .unwrap();
+    let store = crate::store::RedisStore::new(conn.clone());
+
+    let position = events::queue_position(&store, &store, &players[1].player_id)
+        .await
+        .unwrap();
+    let unknown = events::queue_position(&store, &store, &Uuid::new_v4())
+        .await
+        .unwrap();
+
+    assert_eq!(
+        position,
+        Some(crate::rpc::matchmaking::QueuePosition {
+            position: 2,
+            queue_size: 3,
+            matched_players: 2,
+        })
+    );
+    assert_eq!(unknown, None);
 }
 
-async fn create_redis(port: u16) -> ContainerAsync<GenericImage> {
-    GenericImage::new("redis", "8.2.1-bookworm")
-        .with_exposed_port(port.tcp())
-        .with_wait_for(WaitFor::message_on_stdout("Ready to accept connections"))
-        .with_env_var("REDIS_PASSWORD", "super-secret-password")
-        .with_env_var("REDIS_USER", "redis_mms_admin")
-        .start()
+#[tokio::test]
+async fn test_list_open_matches() {
+    let container = create_redis(6379).await;
+    let host = container.get_host().await.unwrap();
+    let port = container.get_host_port_ipv4(6379).await.unwrap();
+    let client = redis_client(host.to_string(), port);
+    let mut conn = client.get_multiplexed_async_connection().await.unwrap();
+
+    let [easy_host, hard_host]: [QueuedPlayer; 2] = std::array::from_fn(|difficulty| {
+        (
+            Uuid::new_v4(),
+            Player {
+                region: "CAN".to_string(),
+                difficulty: difficulty as i32,
+                ping: 120,
+                ..Default::default()
+            },
+            skillratings::mhth::MhthRating::default(),
+        )
+            .into()
+    });
+    let expired = Uuid::new_v4();
+    for open in [
+        crate::rpc::Match::host(&easy_host, &[]).unwrap(),
+        crate::rpc::Match::host(&hard_host, &[]).unwrap(),
+    ] {
+        let mut pipe = redis::pipe();
+        store::put_match(&mut pipe, &open, TWELVE_MINUTES);
+        pipe.query_async(&mut conn).await.map(|_: ()| ()).unwrap();
+        conn.zadd(crate::rpc::open_matches_key(&open.region), open.id, 1)
+            .await
+            .map(|_: ()| ())
+            .unwrap();
+    }
+    conn.zadd(crate::rpc::open_matches_key(&"CAN".to_string()), expired, 1)
+        .await
+        .map(|_: ()| ())
+        .unwrap();
+
+    let matchmaking_server = MatchmakingServer {
+        redis: conn.clone(),
+        http_client: Arc::new(reqwest::Client::new()),
+        nakama_client: Arc::new(auth_client(666)),
+        clock: Arc::new(SystemClock::default()),
+        records: Arc::new(NoRecords),
+        leaderboard: None,
+        health: Default::default(),
+        observability: Default::default(),
+        validator: Arc::new(NoValidation),
+    };
+    let mut req = Request::new(crate::rpc::matchmaking::ListOpenMatchesRequest {
+        player_id: "01997433-3000-7b4b-8712-9253d26a68c8".to_string(),
+        region: "CAN".to_string(),
+        difficulty: Some(1),
+        ..Default::default()
+    });
+    add_auth(&mut req);
+    let response = matchmaking_server.list_open_matches(req).await.unwrap();
+    let remaining: Vec<Uuid> = conn
+        .zrange(crate::rpc::open_matches_key(&"CAN".to_string()), 0, -1)
         .await
-        .expect("Failed to start Redis")
+        .unwrap();
+
+    let matches = response.into_inner().matches;
+    assert_eq!(matches.len(), 1);
+    assert_eq!(matches[0].host_id, hard_host.player_id.to_string());
+    assert_eq!(matches[0].players, 1);
+    assert_eq!(
+        matches[0].host_ping_tier(),
+        crate::rpc::matchmaking::PingTier::Disadvantage
+    );
+    assert!(!remaining.contains(&expired));
 }
 
 pub fn auth_client(port: u16) -> NakamaClient<Authenticated> {
@@ -118,6 +419,7 @@ pub fn auth_client(port: u16) -> NakamaClient<Authenticated> {
         server_key_name: "defaultkey".to_string(),
         server_key_value: "server_key".to_string(),
         encryption_key: "encryption_key".to_string(),
+        legacy_until: None,
         _state: PhantomData::<Authenticated>,
     }
 }
@@ -132,7 +434,91 @@ async fn init_regions(conn: MultiplexedConnection) {
     crate::regions::set_regions(conn, regions).await.unwrap();
 }
 
-fn add_auth(req: &mut Request<Player>) {
+#[tokio::test]
+async fn test_match_stats_retry_after_failed_ratings_write() {
+    let container = create_redis(6379).await;
+    let host = container.get_host().await.unwrap();
+    let port = container.get_host_port_ipv4(6379).await.unwrap();
+    let client = redis_client(host.to_string(), port);
+    let mut conn = client.get_multiplexed_async_connection().await.unwrap();
+
+    let host_id = Uuid::from_str("01997433-3000-7b4b-8712-9253d26a68c8").unwrap();
+    let [host, guest]: [QueuedPlayer; 2] = [host_id, Uuid::new_v4()].map(|player_id| {
+        (
+            player_id,
+            Player {
+                region: "CAN".to_string(),
+                ..Default::default()
+            },
+            skillratings::mhth::MhthRating::default(),
+        )
+            .into()
+    });
+    let mut active = crate::rpc::Match::host(&host, std::slice::from_ref(&guest)).unwrap();
+    for state in [MatchState::Ready, MatchState::Starting, MatchState::Active] {
+        active.transition(state, 10).unwrap();
+    }
+    conn.set(
+        crate::rpc::active_match_key(&active.id),
+        codec::encode(&active),
+    )
+    .await
+    .map(|_: ()| ())
+    .unwrap();
+    // the rating of the guest is not a hash, writing it fails
+    let rating_key = crate::ratings::rating_key(&guest.player_id);
+    conn.set(&rating_key, "corrupted")
+        .await
+        .map(|_: ()| ())
+        .unwrap();
+
+    let matchmaking_server = MatchmakingServer {
+        redis: conn.clone(),
+        http_client: Arc::new(reqwest::Client::new()),
+        nakama_client: Arc::new(auth_client(666)),
+        clock: Arc::new(SystemClock::default()),
+        records: Arc::new(NoRecords),
+        leaderboard: None,
+        health: Default::default(),
+        observability: Default::default(),
+        validator: Arc::new(NoValidation),
+    };
+    let stats = || {
+        let mut req = Request::new(crate::rpc::matchmaking::MatchStatsRequest {
+            player_id: host_id.to_string(),
+            match_id: active.id.to_string(),
+            won: true,
+            performances: vec![crate::rpc::matchmaking::PlayerPerformance {
+                player_id: guest.player_id.to_string(),
+                performance: 0.6,
+            }],
+            mission: String::new(),
+        });
+        add_auth(&mut req);
+        req
+    };
+
+    assert!(matchmaking_server.match_stats(stats()).await.is_err());
+    let stored: Vec<u8> = conn
+        .get(crate::rpc::active_match_key(&active.id))
+        .await
+        .unwrap();
+    let stored: crate::rpc::Match = codec::decode(&stored).unwrap();
+    assert_eq!(stored.state(), MatchState::Active);
+
+    conn.del(&rating_key).await.map(|_: ()| ()).unwrap();
+    assert!(matchmaking_server.match_stats(stats()).await.is_ok());
+    let rated: bool = conn.exists(&rating_key).await.unwrap();
+    assert!(rated);
+    let stored: Vec<u8> = conn
+        .get(crate::rpc::active_match_key(&active.id))
+        .await
+        .unwrap();
+    let stored: crate::rpc::Match = codec::decode(&stored).unwrap();
+    assert_eq!(stored.state(), MatchState::Completed);
+}
+
+fn add_auth<T>(req: &mut Request<T>) {
     req.extensions_mut().insert(auth::UserId {
         player_id: "01997433-3000-7b4b-8712-9253d26a68c8".to_string(),
     });
@@ -0,0 +1,236 @@
+//! Listeners of the gRPC services. They always listen on TCP and, when [`UDS_PATH_VAR`] is set,
+//! on a Unix domain socket too, for sidecars on the same host, e.g. the game-server proxy, that
+//! skip the TCP stack and TLS. Calls over the socket carry no remote address: public calls are
+//! rate limited and located by the `x-forwarded-for` header of the proxy, see
+//! [`super::middleware::rate_key`].
+//!
+//! The socket is created with [`UDS_MODE`], so only the user and group of the server connect,
+//! and removed once the listeners shut down.
+
+use std::{
+    future::Future,
+    net::{Ipv4Addr, SocketAddr},
+    path::{Path, PathBuf},
+};
+
+use tokio::{
+    io::{AsyncRead, AsyncWrite},
+    sync::watch,
+};
+use tokio_stream::Stream;
+use tonic::transport::{
+    Server,
+    server::{Connected, TcpIncoming},
+};
+use tower::ServiceBuilder;
+use tracing::{error, info};
+
+use crate::rpc::server::{
+    MatchmakingServer, MatchmakingServiceServer, MatchmakingServiceV2Server,
+    middleware::{MiddlewareStack, Middlewares},
+};
+
+/// Env var with the path of the Unix domain socket, only TCP is served when unset
+pub const UDS_PATH_VAR: &str = "GRPC_UDS_PATH";
+pub const TCP_ADDR: SocketAddr =
+    SocketAddr::new(std::net::IpAddr::V4(Ipv4Addr::UNSPECIFIED), 50051);
+/// Permissions of the socket, read and write for the user and group of the server
+pub const UDS_MODE: u32 = 0o660;
+
+#[derive(Debug, thiserror::Error)]
+pub enum Error {
+    #[error("`{0}` exists and is not a socket")]
+    NotASocket(PathBuf),
+    #[error("`{0}` is served by another process")]
+    SocketInUse(PathBuf),
+    #[error(transparent)]
+    Io(#[from] std::io::Error),
+    #[error(transparent)]
+    Transport(#[from] tonic::transport::Error),
+}
+
+/// Addresses the services listen on
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Listeners {
+    pub tcp: SocketAddr,
+    pub uds: Option<PathBuf>,
+}
+
+impl Default for Listeners {
+    fn default() -> Self {
+        Self {
+            tcp: TCP_ADDR,
+            uds: None,
+        }
+    }
+}
+
+impl Listeners {
+    /// [`TCP_ADDR`], and the socket of [`UDS_PATH_VAR`] when set
+    pub fn from_env() -> Self {
+        Self {
+            uds: std::env::var(UDS_PATH_VAR)
+                .ok()
+                .filter(|path| !path.trim().is_empty())
+                .map(PathBuf::from),
+            ..Self::default()
+        }
+    }
+
+    /// Serves `server` on every listener until one of them fails or `shutdown` resolves, then
+    /// drains the calls in flight and removes the socket. The listeners share the layers of
+    /// `middlewares`, so a session is rate limited once whichever listener it calls
+    pub async fn serve(
+        &self,
+        server: MatchmakingServer,
+        middlewares: &Middlewares,
+        shutdown: impl Future<Output = ()>,
+    ) -> Result<(), Error> {
+        let layers = middlewares.layers(server.redis.clone());
+        let (stop, stopped) = watch::channel(());
+        let stop = async move {
+            shutdown.await;
+            info!("gRPC shutting down");
+            stop.send_replace(());
+            Ok(())
+        };
+        let tcp = serve(
+            server.clone(),
+            layers.clone(),
+            TcpIncoming::bind(self.tcp).map_err(std::io::Error::other)?,
+            stopped_signal(stopped.clone()),
+        );
+        info!("gRPC listening on {}", self.tcp);
+        let Some(path) = &self.uds else {
+            tokio::try_join!(tcp, stop)?;
+            return Ok(());
+        };
+        let uds = serve(server, layers, bind_uds(path)?, stopped_signal(stopped));
+        info!("gRPC listening on {}", path.display());
+
+        let served = tokio::try_join!(tcp, uds, stop);
+        if let Err(err) = std::fs::remove_file(path) {
+            error!("failed to remove `{}`: {err}", path.display());
+        }
+        served?;
+        Ok(())
+    }
+}
+
+async fn stopped_signal(mut stopped: watch::Receiver<()>) {
+    // a dropped sender stops the listeners too
+    let _ = stopped.changed().await;
+}
+
+/// Resolves on ctrl-c, or `SIGTERM` on Unix
+pub async fn shutdown_signal() {
+    #[cfg(unix)]
+    {
+        use tokio::signal::unix::{SignalKind, signal};
+
+        match signal(SignalKind::terminate()) {
+            Ok(mut terminate) => {
+                tokio::select! {
+                    _ = tokio::signal::ctrl_c() => {}
+                    _ = terminate.recv() => {}
+                }
+                return;
+            }
+            Err(err) => error!("failed to listen for SIGTERM: {err}"),
+        }
+    }
+    if let Err(err) = tokio::signal::ctrl_c().await {
+        error!("failed to listen for ctrl-c: {err}");
+        std::future::pending::<()>().await;
+    }
+}
+
+/// Serves the services on the connections of `incoming` behind `layers` until `shutdown`
+/// resolves
+pub async fn serve<I, IO, IE>(
+    server: MatchmakingServer,
+    layers: ServiceBuilder<MiddlewareStack>,
+    incoming: I,
+    shutdown: impl Future<Output = ()>,
+) -> Result<(), tonic::transport::Error>
+where
+    I: Stream<Item = Result<IO, IE>>,
+    IO: AsyncRead + AsyncWrite + Connected + Unpin + Send + 'static,
+    IO::ConnectInfo: Clone + Send + Sync + 'static,
+    IE: Into<Box<dyn std::error::Error + Send + Sync>>,
+{
+    Server::builder()
+        .layer(layers)
+        .add_service(MatchmakingServiceV2Server::new(server.clone()))
+        .add_service(MatchmakingServiceServer::new(server))
+        .serve_with_incoming_shutdown(incoming, shutdown)
+        .await
+}
+
+/// Listens on the socket at `path` with [`UDS_MODE`]. A socket left behind by a previous run,
+/// refusing connections, is replaced, a socket still served by another process is not
+#[cfg(unix)]
+pub fn bind_uds(path: &Path) -> Result<tokio_stream::wrappers::UnixListenerStream, Error> {
+    use std::os::unix::fs::{FileTypeExt, PermissionsExt};
+
+    match std::fs::symlink_metadata(path) {
+        Ok(metadata) if metadata.file_type().is_socket() => {
+            match std::os::unix::net::UnixStream::connect(path) {
+                Ok(_) => return Err(Error::SocketInUse(path.to_path_buf())),
+                Err(err) if err.kind() == std::io::ErrorKind::ConnectionRefused => {
+                    std::fs::remove_file(path)?;
+                }
+                Err(err) => return Err(err.into()),
+            }
+        }
+        Ok(_) => return Err(Error::NotASocket(path.to_path_buf())),
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => {}
+        Err(err) => return Err(err.into()),
+    }
+    let listener = tokio::net::UnixListener::bind(path)?;
+    std::fs::set_permissions(path, std::fs::Permissions::from_mode(UDS_MODE))?;
+
+    Ok(tokio_stream::wrappers::UnixListenerStream::new(listener))
+}
+
+#[cfg(not(unix))]
+pub fn bind_uds(
+    path: &Path,
+) -> Result<tokio_stream::Empty<Result<tokio::net::TcpStream, std::io::Error>>, Error> {
+    Err(std::io::Error::new(
+        std::io::ErrorKind::Unsupported,
+        format!(
+            "Unix domain sockets are not supported, `{}`",
+            path.display()
+        ),
+    )
+    .into())
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn stale_sockets_are_replaced() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let path = std::env::temp_dir().join(format!("matchmaking-{}.sock", uuid::Uuid::new_v4()));
+        let file = path.with_extension("txt");
+        std::fs::write(&file, "not a socket").unwrap();
+
+        let first = bind_uds(&path).unwrap();
+        let in_use = bind_uds(&path);
+        drop(first);
+        let second = bind_uds(&path);
+        let mode = std::fs::metadata(&path).unwrap().permissions().mode();
+        let not_a_socket = bind_uds(&file);
+        std::fs::remove_file(&path).unwrap();
+        std::fs::remove_file(&file).unwrap();
+
+        assert!(matches!(in_use, Err(Error::SocketInUse(_))));
+        assert!(second.is_ok());
+        assert_eq!(mode & 0o777, UDS_MODE);
+        assert!(matches!(not_a_socket, Err(Error::NotASocket(_))));
+    }
+}
@@ -0,0 +1,47 @@
+use tonic::{Request, Response, Status};
+
+use crate::{
+    lobby, ready_check,
+    rpc::{
+        helper::parse_id,
+        matchmaking::{
+            ConfirmReadyRequest, ConfirmReadyResponse, KickFromLobbyRequest, KickFromLobbyResponse,
+        },
+        server::{MatchmakingServer, auth::authorize_player},
+    },
+};
+
+impl MatchmakingServer {
+    pub(super) async fn kick(
+        &self,
+        request: Request<KickFromLobbyRequest>,
+    ) -> Result<Response<KickFromLobbyResponse>, Status> {
+        let host_id = authorize_player(&request, &request.get_ref().player_id)?;
+        let match_id = parse_id(&request.get_ref().match_id)?;
+        let kicked_id = parse_id(&request.get_ref().kicked_id)?;
+        let mut conn = self.redis.clone();
+
+        let kick = lobby::kick(&mut conn, match_id, host_id, kicked_id).await?;
+
+        Ok(Response::new(KickFromLobbyResponse {
+            open_slots: kick.open_slots as u32,
+            requeued: kick.requeue,
+        }))
+    }
+
+    pub(super) async fn confirm(
+        &self,
+        request: Request<ConfirmReadyRequest>,
+    ) -> Result<Response<ConfirmReadyResponse>, Status> {
+        let player_id = authorize_player(&request, &request.get_ref().player_id)?;
+        let match_id = parse_id(&request.get_ref().match_id)?;
+        let mut conn = self.redis.clone();
+
+        let (confirmed, players) = ready_check::confirm(&mut conn, match_id, player_id).await?;
+
+        Ok(Response::new(ConfirmReadyResponse {
+            confirmed: confirmed as u32,
+            players: players as u32,
+        }))
+    }
+}
@@ -0,0 +1,26 @@
+use tonic::{Request, Response, Status};
+
+use crate::{
+    metrics,
+    rpc::{
+        helper::IntoTonicError,
+        matchmaking::{QueueMetricsRequest, QueueMetricsResponse},
+        server::{MatchmakingServer, auth::authorize_admin},
+    },
+};
+
+impl MatchmakingServer {
+    pub(super) async fn metrics(
+        &self,
+        request: Request<QueueMetricsRequest>,
+    ) -> Result<Response<QueueMetricsResponse>, Status> {
+        authorize_admin(&request)?;
+        let mut conn = self.redis.clone();
+
+        let metrics = metrics::queue_metrics(&mut conn)
+            .await
+            .to_tonic_error("Failed to load queue metrics", Box::new(Status::internal))?;
+
+        Ok(Response::new(metrics))
+    }
+}
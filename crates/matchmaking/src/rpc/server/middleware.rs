@@ -0,0 +1,633 @@
+//! Middleware of the gRPC server, tower layers wrapping every service of the router. Each one but
+//! `auth` can be toggled with [`MIDDLEWARE_VAR`], e.g. to enable `rate_limit` and `metrics`.
+//! `auth` always runs: it resolves the session the player RPCs are authorized against. Enabled
+//! middleware runs in the order of [`Middleware`], whatever the order of the var:
+//!
+//! - `request_id` keeps the client `x-request-id` or generates one, see [`RequestId`].
+//! - `metrics` counts the calls of every method, see [`crate::metrics::rpc`].
+//! - `auth` checks the [`AuthPolicies`] of the method.
+//! - `rate_limit` limits the calls of each session, or of each client for public methods, see
+//!   [`rate_key`].
+//! - `deadline` fails calls still running after their `grpc-timeout`, capped by the server.
+
+use std::{
+    collections::{BTreeSet, HashMap},
+    str::FromStr,
+    sync::{Arc, Mutex, PoisonError},
+    task::{Context, Poll},
+};
+
+use redis::aio::MultiplexedConnection;
+use tokio::time::Instant;
+use tonic::{
+    Status,
+    body::Body,
+    codegen::{BoxFuture, Service, http},
+    transport::server::TcpConnectInfo,
+};
+use tower::{
+    Layer, ServiceBuilder,
+    layer::util::{Identity, Stack},
+    util::Either,
+};
+use tracing::{info, warn};
+
+use super::{
+    auth::UserId,
+    deadline::Deadline,
+    policy::{self, AuthPolicies, Authorized},
+    request_id::RequestId,
+};
+use crate::{geoip, metrics};
+
+/// Comma separated middleware of the server, defaults to [`DEFAULT_MIDDLEWARE`]
+pub const MIDDLEWARE_VAR: &str = "RPC_MIDDLEWARE";
+pub const DEFAULT_MIDDLEWARE: &str = "request_id,auth";
+/// Calls a session may make every second, refilling its burst
+pub const RATE_LIMIT_VAR: &str = "RPC_RATE_LIMIT_PER_SEC";
+pub const DEFAULT_RATE_LIMIT: f64 = 20.;
+/// Calls a session may make at once
+pub const RATE_BURST_VAR: &str = "RPC_RATE_LIMIT_BURST";
+pub const DEFAULT_RATE_BURST: f64 = 40.;
+/// Sessions tracked, the full buckets then the least recently used are dropped past it
+const MAX_BUCKETS: usize = 10_000;
+
+#[derive(Debug, thiserror::Error)]
+pub enum Error {
+    #[error("unknown middleware `{0}`, expected request_id, metrics, auth, rate_limit or deadline")]
+    UnknownMiddleware(String),
+    #[error("`{0}` must be a positive number")]
+    InvalidRateLimit(&'static str),
+    #[error(transparent)]
+    Policy(#[from] policy::Error),
+}
+
+/// Middleware of the server, in the order it runs
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Middleware {
+    RequestId,
+    Metrics,
+    Auth,
+    RateLimit,
+    Deadline,
+}
+
+impl Middleware {
+    pub const fn as_str(self) -> &'static str {
+        match self {
+            Self::RequestId => "request_id",
+            Self::Metrics => "metrics",
+            Self::Auth => "auth",
+            Self::RateLimit => "rate_limit",
+            Self::Deadline => "deadline",
+        }
+    }
+}
+
+impl FromStr for Middleware {
+    type Err = Error;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value.trim() {
+            "request_id" => Ok(Self::RequestId),
+            "metrics" => Ok(Self::Metrics),
+            "auth" => Ok(Self::Auth),
+            "rate_limit" => Ok(Self::RateLimit),
+            "deadline" => Ok(Self::Deadline),
+            _ => Err(Error::UnknownMiddleware(value.to_string())),
+        }
+    }
+}
+
+/// Layers of [`Middlewares::layers`], disabled middleware is an [`Identity`]
+pub type MiddlewareStack = Stack<
+    Either<DeadlineLayer, Identity>,
+    Stack<
+        Either<RateLimitLayer, Identity>,
+        Stack<
+            AuthLayer,
+            Stack<
+                Either<MetricsLayer, Identity>,
+                Stack<Either<RequestIdLayer, Identity>, Identity>,
+            >,
+        >,
+    >,
+>;
+
+/// Enabled middleware and its settings, `auth` is always enabled
+#[derive(Debug, Clone)]
+pub struct Middlewares {
+    enabled: BTreeSet<Middleware>,
+    pub policies: Arc<AuthPolicies>,
+    pub rate_limit: RateLimit,
+}
+
+impl Default for Middlewares {
+    fn default() -> Self {
+        Self {
+            enabled: parse_middleware(DEFAULT_MIDDLEWARE).expect("default middleware is valid"),
+            policies: Arc::new(AuthPolicies::default()),
+            rate_limit: RateLimit::default(),
+        }
+    }
+}
+
+impl Middlewares {
+    /// Middleware of [`MIDDLEWARE_VAR`], with the auth policies and rate limit of their vars
+    pub fn from_env() -> Result<Self, Error> {
+        let enabled = match std::env::var(MIDDLEWARE_VAR) {
+            Ok(value) => parse_middleware(&value)?,
+            Err(_) => parse_middleware(DEFAULT_MIDDLEWARE)?,
+        };
+
+        Ok(Self {
+            policies: Arc::new(AuthPolicies::from_env()?),
+            rate_limit: RateLimit::from_env()?,
+            ..Self::default()
+        }
+        .with_enabled(enabled))
+    }
+
+    #[must_use]
+    pub fn with_enabled(mut self, enabled: impl IntoIterator<Item = Middleware>) -> Self {
+        self.enabled = enabled.into_iter().collect();
+        self.enabled.insert(Middleware::Auth);
+        self
+    }
+
+    pub fn is_enabled(&self, middleware: Middleware) -> bool {
+        self.enabled.contains(&middleware)
+    }
+
+    /// Names of the enabled middleware, in the order it runs
+    pub fn names(&self) -> Vec<&'static str> {
+        self.enabled
+            .iter()
+            .map(|middleware| middleware.as_str())
+            .collect()
+    }
+
+    /// Layers of the enabled middleware, `redis` keeps the RPC metrics
+    pub fn layers(&self, redis: MultiplexedConnection) -> ServiceBuilder<MiddlewareStack> {
+        info!("RPC middleware: {}", self.names().join(", "));
+        let enabled = |middleware| self.is_enabled(middleware);
+
+        ServiceBuilder::new()
+            .option_layer(enabled(Middleware::RequestId).then_some(RequestIdLayer))
+            .option_layer(enabled(Middleware::Metrics).then(|| MetricsLayer { redis }))
+            .layer(AuthLayer {
+                policies: self.policies.clone(),
+            })
+            .option_layer(enabled(Middleware::RateLimit).then(|| RateLimitLayer {
+                rate_limit: self.rate_limit.clone(),
+            }))
+            .option_layer(enabled(Middleware::Deadline).then_some(DeadlineLayer))
+    }
+}
+
+pub fn parse_middleware(value: &str) -> Result<BTreeSet<Middleware>, Error> {
+    value
+        .split(',')
+        .filter(|middleware| !middleware.trim().is_empty())
+        .map(Middleware::from_str)
+        .collect()
+}
+
+/// Method of a call path, e.g. `JoinQueue`
+fn method(path: &str) -> &str {
+    path.rsplit('/').next().unwrap_or_default()
+}
+
+#[derive(Debug, Clone, Copy, Default)]
+pub struct RequestIdLayer;
+
+impl<S> Layer<S> for RequestIdLayer {
+    type Service = WithRequestId<S>;
+
+    fn layer(&self, inner: S) -> Self::Service {
+        WithRequestId { inner }
+    }
+}
+
+/// Service assigning the [`RequestId`] of every call
+#[derive(Debug, Clone)]
+pub struct WithRequestId<S> {
+    inner: S,
+}
+
+impl<S, B> Service<http::Request<B>> for WithRequestId<S>
+where
+    S: Service<http::Request<B>>,
+{
+    type Response = S::Response;
+    type Error = S::Error;
+    type Future = S::Future;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, mut req: http::Request<B>) -> Self::Future {
+        let request_id = RequestId::from_headers(req.headers());
+        req.extensions_mut().insert(request_id);
+
+        self.inner.call(req)
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct MetricsLayer {
+    redis: MultiplexedConnection,
+}
+
+impl<S> Layer<S> for MetricsLayer {
+    type Service = Metered<S>;
+
+    fn layer(&self, inner: S) -> Self::Service {
+        Metered {
+            inner,
+            redis: self.redis.clone(),
+        }
+    }
+}
+
+/// Service recording the calls of every method, without delaying their response
+#[derive(Debug, Clone)]
+pub struct Metered<S> {
+    inner: S,
+    redis: MultiplexedConnection,
+}
+
+impl<S, B> Service<http::Request<B>> for Metered<S>
+where
+    S: Service<http::Request<B>, Response = http::Response<Body>>,
+    S::Future: Send + 'static,
+{
+    type Response = http::Response<Body>;
+    type Error = S::Error;
+    type Future = BoxFuture<Self::Response, Self::Error>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, req: http::Request<B>) -> Self::Future {
+        let method = method(req.uri().path()).to_string();
+        let started = Instant::now();
+        let mut conn = self.redis.clone();
+        let response = self.inner.call(req);
+
+        Box::pin(async move {
+            let response = response.await?;
+            // failed calls answer with their status in the headers, successful ones in trailers
+            let code = response
+                .headers()
+                .get("grpc-status")
+                .and_then(|code| code.to_str().ok())
+                .and_then(|code| code.parse().ok())
+                .unwrap_or(0);
+            let duration = started.elapsed();
+            tokio::spawn(async move {
+                if let Err(err) = metrics::rpc::record(&mut conn, &method, code, duration).await {
+                    warn!("failed to record the metrics of `{method}`: {err}");
+                }
+            });
+
+            Ok(response)
+        })
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct AuthLayer {
+    policies: Arc<AuthPolicies>,
+}
+
+impl<S> Layer<S> for AuthLayer {
+    type Service = Authorized<S>;
+
+    fn layer(&self, inner: S) -> Self::Service {
+        Authorized::new(inner, self.policies.clone())
+    }
+}
+
+/// Token buckets of the sessions, shared by the clones of the service
+#[derive(Debug, Clone)]
+pub struct RateLimit {
+    /// Calls refilled every second
+    pub per_sec: f64,
+    pub burst: f64,
+    buckets: Arc<Mutex<HashMap<String, Bucket>>>,
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Bucket {
+    tokens: f64,
+    refilled_at: Instant,
+}
+
+impl Default for RateLimit {
+    fn default() -> Self {
+        Self::new(DEFAULT_RATE_LIMIT, DEFAULT_RATE_BURST)
+    }
+}
+
+impl RateLimit {
+    pub fn new(per_sec: f64, burst: f64) -> Self {
+        Self {
+            per_sec,
+            burst,
+            buckets: Arc::default(),
+        }
+    }
+
+    /// Limit of [`RATE_LIMIT_VAR`] and [`RATE_BURST_VAR`]
+    pub fn from_env() -> Result<Self, Error> {
+        Ok(Self::new(
+            positive_var(RATE_LIMIT_VAR, DEFAULT_RATE_LIMIT)?,
+            positive_var(RATE_BURST_VAR, DEFAULT_RATE_BURST)?,
+        ))
+    }
+
+    /// Takes a call from the bucket of `key`, `false` once it is empty
+    pub fn allow(&self, key: &str, now: Instant) -> bool {
+        let mut buckets = self.buckets.lock().unwrap_or_else(PoisonError::into_inner);
+        if buckets.len() >= MAX_BUCKETS && !buckets.contains_key(key) {
+            buckets.retain(|_, bucket| self.refill(*bucket, now).tokens < self.burst);
+            if buckets.len() >= MAX_BUCKETS
+                && let Some(oldest) = buckets
+                    .iter()
+                    .min_by_key(|(_, bucket)| bucket.refilled_at)
+                    .map(|(key, _)| key.clone())
+            {
+                buckets.remove(&oldest);
+            }
+        }
+        let bucket = buckets.entry(key.to_string()).or_insert(Bucket {
+            tokens: self.burst,
+            refilled_at: now,
+        });
+        *bucket = self.refill(*bucket, now);
+        if bucket.tokens < 1. {
+            return false;
+        }
+        bucket.tokens -= 1.;
+
+        true
+    }
+
+    fn refill(&self, bucket: Bucket, now: Instant) -> Bucket {
+        let elapsed = now.saturating_duration_since(bucket.refilled_at);
+
+        Bucket {
+            tokens: (bucket.tokens + elapsed.as_secs_f64() * self.per_sec).min(self.burst),
+            refilled_at: now,
+        }
+    }
+}
+
+fn positive_var(name: &'static str, default: f64) -> Result<f64, Error> {
+    match std::env::var(name) {
+        Ok(value) => value
+            .parse()
+            .ok()
+            .filter(|value: &f64| *value > 0.)
+            .ok_or(Error::InvalidRateLimit(name)),
+        Err(_) => Ok(default),
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct RateLimitLayer {
+    rate_limit: RateLimit,
+}
+
+impl<S> Layer<S> for RateLimitLayer {
+    type Service = RateLimited<S>;
+
+    fn layer(&self, inner: S) -> Self::Service {
+        RateLimited {
+            inner,
+            rate_limit: self.rate_limit.clone(),
+        }
+    }
+}
+
+/// Service failing the calls over the [`RateLimit`] of their session with `RESOURCE_EXHAUSTED`
+#[derive(Debug, Clone)]
+pub struct RateLimited<S> {
+    inner: S,
+    rate_limit: RateLimit,
+}
+
+impl<S, B> Service<http::Request<B>> for RateLimited<S>
+where
+    S: Service<http::Request<B>, Response = http::Response<Body>>,
+    S::Future: Send + 'static,
+{
+    type Response = http::Response<Body>;
+    type Error = S::Error;
+    type Future = BoxFuture<Self::Response, Self::Error>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, req: http::Request<B>) -> Self::Future {
+        let key = rate_key(&req);
+        if !self.rate_limit.allow(&key, Instant::now()) {
+            warn!("rate limit exceeded by `{key}`");
+            let status = Status::resource_exhausted("rate limit exceeded");
+            return Box::pin(async move { Ok(status.into_http()) });
+        }
+
+        Box::pin(self.inner.call(req))
+    }
+}
+
+/// Bucket of a call: its session, known once `auth` ran, else the address of its TCP peer, or
+/// the client address forwarded by a [`geoip::TRUSTED_PROXIES_VAR`] peer. Calls over the Unix
+/// socket have no peer address, they are keyed by the user of the peer process, as its
+/// forwarded headers can not be trusted.
+pub fn rate_key<B>(req: &http::Request<B>) -> String {
+    let extensions = req.extensions();
+    if let Some(user) = extensions.get::<UserId>() {
+        return user.player_id.clone();
+    }
+    if let Some(addr) = extensions
+        .get::<TcpConnectInfo>()
+        .and_then(TcpConnectInfo::remote_addr)
+    {
+        let forwarded = req
+            .headers()
+            .get(geoip::FORWARDED_FOR_HEADER)
+            .and_then(|forwarded| forwarded.to_str().ok());
+        return geoip::trusted_proxies()
+            .client(addr.ip(), forwarded)
+            .to_string();
+    }
+    #[cfg(unix)]
+    if let Some(cred) = extensions
+        .get::<tonic::transport::server::UdsConnectInfo>()
+        .and_then(|info| info.peer_cred)
+    {
+        return format!("uid:{}", cred.uid());
+    }
+
+    String::new()
+}
+
+#[derive(Debug, Clone, Copy, Default)]
+pub struct DeadlineLayer;
+
+impl<S> Layer<S> for DeadlineLayer {
+    type Service = WithDeadline<S>;
+
+    fn layer(&self, inner: S) -> Self::Service {
+        WithDeadline { inner }
+    }
+}
+
+/// Service failing the calls whose response is not ready by their [`Deadline`] with
+/// `DEADLINE_EXCEEDED`, streams are not limited once they started
+#[derive(Debug, Clone)]
+pub struct WithDeadline<S> {
+    inner: S,
+}
+
+impl<S, B> Service<http::Request<B>> for WithDeadline<S>
+where
+    S: Service<http::Request<B>, Response = http::Response<Body>>,
+    S::Future: Send + 'static,
+{
+    type Response = http::Response<Body>;
+    type Error = S::Error;
+    type Future = BoxFuture<Self::Response, Self::Error>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, req: http::Request<B>) -> Self::Future {
+        let deadline = Deadline::from_headers(req.headers());
+        let response = self.inner.call(req);
+
+        Box::pin(async move {
+            match deadline.run(response).await {
+                Ok(response) => response,
+                Err(status) => Ok(status.into_http()),
+            }
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::*;
+    use crate::rpc::server::request_id::REQUEST_ID_HEADER;
+
+    #[test]
+    fn middleware_runs_in_a_fixed_order() {
+        let middlewares =
+            Middlewares::default().with_enabled(parse_middleware("deadline, metrics").unwrap());
+
+        assert_eq!(middlewares.names(), ["metrics", "auth", "deadline"]);
+        assert_eq!(Middlewares::default().names(), ["request_id", "auth"]);
+        assert!(matches!(
+            parse_middleware("auth,cors"),
+            Err(Error::UnknownMiddleware(_))
+        ));
+    }
+
+    #[test]
+    fn buckets_refill_over_time() {
+        let rate_limit = RateLimit::new(2., 3.);
+        let now = Instant::now();
+
+        assert!((0..3).all(|_| rate_limit.allow("player", now)));
+        assert!(!rate_limit.allow("player", now));
+        assert!(rate_limit.allow("other", now));
+        assert!(rate_limit.allow("player", now + Duration::from_millis(500)));
+        assert!(!rate_limit.allow("player", now + Duration::from_millis(500)));
+    }
+
+    #[test]
+    fn buckets_are_bounded() {
+        let rate_limit = RateLimit::new(1., 2.);
+        let now = Instant::now();
+        for session in 0..MAX_BUCKETS {
+            assert!(rate_limit.allow(&session.to_string(), now));
+        }
+
+        assert!(rate_limit.allow("late", now + Duration::from_millis(1)));
+        let buckets = rate_limit.buckets.lock().unwrap();
+        assert_eq!(buckets.len(), MAX_BUCKETS);
+        assert!(buckets.contains_key("late"));
+    }
+
+    #[test]
+    fn calls_are_keyed_by_session_then_peer() {
+        let peer = TcpConnectInfo {
+            local_addr: None,
+            remote_addr: "198.51.100.1:4000".parse().ok(),
+        };
+        let mut forged = http::Request::builder()
+            .header(geoip::FORWARDED_FOR_HEADER, "203.0.113.7")
+            .body(())
+            .unwrap();
+        forged.extensions_mut().insert(peer.clone());
+        let unknown = http::Request::builder()
+            .header(geoip::FORWARDED_FOR_HEADER, "203.0.113.7")
+            .body(())
+            .unwrap();
+        let mut session = http::Request::builder().body(()).unwrap();
+        session.extensions_mut().insert(peer);
+        session.extensions_mut().insert(UserId {
+            player_id: "player".to_string(),
+        });
+
+        assert_eq!(rate_key(&forged), "198.51.100.1");
+        assert_eq!(
+            rate_key(&unknown),
+            "",
+            "forwarded headers need a trusted peer"
+        );
+        assert_eq!(rate_key(&session), "player");
+    }
+
+    #[tokio::test]
+    async fn disabled_middleware_is_skipped() {
+        let calls = Arc::new(Mutex::new(Vec::new()));
+        let recorded = calls.clone();
+        let inner = tower::service_fn(move |req: http::Request<Body>| {
+            recorded
+                .lock()
+                .unwrap()
+                .push(req.extensions().get::<RequestId>().map(|id| id.0.clone()));
+            async { Ok::<_, std::convert::Infallible>(http::Response::new(Body::empty())) }
+        });
+        let request = || {
+            http::Request::builder()
+                .uri("/matchmaking.MatchmakingService/Check")
+                .header(REQUEST_ID_HEADER, "client-id")
+                .body(Body::empty())
+                .unwrap()
+        };
+
+        let mut with_id = ServiceBuilder::new()
+            .option_layer(Some(RequestIdLayer))
+            .service(inner.clone());
+        let mut without_id = ServiceBuilder::new()
+            .option_layer(None::<RequestIdLayer>)
+            .service(inner);
+        with_id.call(request()).await.unwrap();
+        without_id.call(request()).await.unwrap();
+
+        assert_eq!(
+            *calls.lock().unwrap(),
+            [Some("client-id".to_string()), None]
+        );
+    }
+}
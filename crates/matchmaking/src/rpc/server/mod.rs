@@ -1,122 +1,755 @@
-use std::{sync::Arc, time::Duration};
+use std::sync::{Arc, LazyLock};
 
-use chrono::{Local, NaiveDate};
-use redis::AsyncCommands;
 use tokio::sync::mpsc;
-use tokio_stream::{StreamExt, wrappers::ReceiverStream};
+use tokio_stream::wrappers::ReceiverStream;
 use tonic::{Request, Status};
-use tracing::{debug, error};
+use tracing::{debug, error, instrument, warn};
 use uuid::Uuid;
 
+use self::{
+    deadline::Deadline,
+    request_id::{request_id, session_player},
+};
 use super::matchmaking::matchmaking_service_server::MatchmakingService;
-pub use super::matchmaking::matchmaking_service_server::MatchmakingServiceServer;
+pub use super::matchmaking::{
+    matchmaking_service_server::MatchmakingServiceServer,
+    v2::matchmaking_service_server::MatchmakingServiceServer as MatchmakingServiceV2Server,
+};
 use crate::{
+    audit::{self, Action, Actor, AuditEvent},
+    clock::Clock,
+    codec,
+    leaderboard::SeasonLeaderboard,
+    metrics::duplicate_joins_key,
     nakama::{self, Authenticated},
+    presence,
+    records::MatchRecords,
     rpc::{
-        QueuedPlayer, create_match_queue_key,
-        helper::{IntoTonicError, time_since},
+        QueuedPlayer, enqueue,
+        helper::IntoTonicError,
         matchmaking::{
-            HealthCheckRequest, HealthCheckResponse, JoinMode, JoinQueueResponse, Player,
+            AbandonReport, AbandonResponse, AcceptRequeueRequest, AcceptRequeueResponse,
+            ClanPartyRequest, ConfirmReadyRequest, ConfirmReadyResponse, CreateTournamentRequest,
+            EnvironmentRequest, EnvironmentResponse, HealthCheckRequest, HealthCheckResponse,
+            HeartbeatAck, JoinQueueResponse, JoinQueueStatus, KickFromLobbyRequest,
+            KickFromLobbyResponse, ListMatchesRequest, ListMatchesResponse, ListOpenMatchesRequest,
+            ListOpenMatchesResponse, MatchHistoryRequest, MatchHistoryResponse, MatchResultRequest,
+            MatchStatsRequest, MatchStatsResponse, ObservabilityRequest, OpenSlotsRequest,
+            OpenSlotsResponse, PartyInviteRequest, PartyRequest, PartyResponse,
+            PartyTransferRequest, Player, PreferencesRequest, PreferencesResponse,
+            QueueAnalyticsRequest, QueueAnalyticsResponse, QueueMetricsRequest,
+            QueueMetricsResponse, QueueSizesRequest, QueueSizesResponse, QueuedPlayerRequest,
+            QueuedPlayerResponse, RecommendDifficultyRequest, RecommendDifficultyResponse,
+            RegisterTournamentRequest, RejoinMatchRequest, RejoinMatchResponse,
+            ReloadConfigRequest, ReloadConfigResponse, RemoveQueuedPlayerRequest,
+            RemoveQueuedPlayerResponse, ReportPlayerRequest, ReportPlayerResponse,
+            RequestTickRequest, RequestTickResponse, RequeuePartyRequest, RequeuePartyResponse,
+            RevokeSessionRequest, RevokeSessionResponse, SetEnvironmentRequest,
+            SetPreferencesRequest, TournamentRequest, TournamentResponse, WatchQueueRequest,
         },
-        player_queue_key,
+        player_create_match_key, player_queue_key, player_versus_key,
     },
+    store,
+    validation::{JoinAttempt, JoinValidator},
 };
 
+mod admin;
+mod analytics;
 pub mod auth;
+mod backfill;
+mod browse;
+mod config;
+mod deadline;
+mod difficulty;
+mod environment;
+mod events;
 pub mod healthcheck;
+mod heartbeat;
+mod history;
+pub mod jwks;
+pub mod listener;
+mod lobby;
+mod metrics;
+pub mod middleware;
+mod observability;
+mod party;
+mod penalty;
+pub mod policy;
+mod preferences;
+mod rejoin;
+mod report;
+pub mod request_id;
+mod sessions;
+mod smurf;
+mod tournament;
+pub mod v2;
 
 pub(crate) static TEN_MINUTES: u64 = 600;
-pub(crate) static TWO_HOURS: u64 = 720;
-pub(crate) static GAME_START: Option<NaiveDate> = NaiveDate::from_yo_opt(2025, 1);
+/// Seconds forming matches, lobbies and their keys live without progress
+pub(crate) static TWELVE_MINUTES: u64 = 720;
+/// Env var with the longest mission in seconds
+pub const MAX_MISSION_VAR: &str = "MAX_MISSION_SECS";
+pub const DEFAULT_MAX_MISSION: u64 = 7200;
+
+/// Seconds a started match lives: its longest mission, plus ten minutes for the reports sent
+/// when it ends. The active match, the index of its players and its once per match keys expire
+/// with it
+pub(crate) static MATCH_LIFETIME: LazyLock<u64> = LazyLock::new(|| {
+    std::env::var(MAX_MISSION_VAR)
+        .ok()
+        .and_then(|secs| secs.parse().ok())
+        .unwrap_or(DEFAULT_MAX_MISSION)
+        + TEN_MINUTES
+});
 
 #[derive(Debug, Clone)]
 pub struct MatchmakingServer {
     pub redis: redis::aio::MultiplexedConnection,
     pub http_client: Arc<reqwest::Client>,
     pub nakama_client: Arc<nakama::NakamaClient<Authenticated>>,
+    pub clock: Arc<dyn Clock>,
+    /// History of completed matches, see [`crate::records`]
+    pub records: Arc<dyn MatchRecords>,
+    /// Season leaderboard receiving the ratings moved by match results, see [`crate::leaderboard`]
+    pub leaderboard: Option<SeasonLeaderboard>,
+    /// Last probe of Redis and Nakama answering `check` and `watch`
+    pub health: healthcheck::HealthCache,
+    /// Anti-cheat and client version checks of `join_queue`, see [`crate::validation`]
+    pub validator: Arc<dyn JoinValidator>,
+    /// Snapshots streamed by `watch_observability`, see [`crate::observability`]
+    pub observability: crate::observability::SnapshotFeed,
 }
 
 #[tonic::async_trait]
 impl MatchmakingService for MatchmakingServer {
     type WatchStream = healthcheck::ResponseStream;
+    type WatchQueueStream = events::QueueEventStream;
+    type HeartbeatStream = heartbeat::HeartbeatStream;
+    type WatchObservabilityStream = observability::ObservabilityStream;
 
+    #[instrument(skip_all, fields(request_id = request_id(&request), player_id = session_player(&request)))]
     async fn join_queue(
         &self,
-        request: Request<Player>,
+        mut request: Request<Player>,
     ) -> Result<tonic::Response<JoinQueueResponse>, tonic::Status> {
-        let user_id = request.extensions().get::<auth::UserId>();
-
-        let player_id = Uuid::parse_str(&request.get_ref().player_id).to_tonic_error(
-            format!("Invalid player id: {}", request.get_ref().player_id),
-            Box::new(tonic::Status::invalid_argument),
-        )?;
-        if user_id.is_none_or(|id| id.player_id != player_id.to_string()) {
-            return Err(tonic::Status::unauthenticated("invalid player token"));
+        let player_id = auth::authorize_player(&request, &request.get_ref().player_id)?;
+        let deadline = Deadline::from_request(&request);
+        let mut conn = self.redis.clone();
+        let config = deadline.run(crate::config::get_config(&mut conn)).await??;
+        let served = deadline
+            .run(crate::geoip::served_regions(&mut conn))
+            .await??;
+        // malformed joins are rejected before the penalty, anti-cheat and Nakama calls. The
+        // preferences only fill the fields left unset and the region of joins declaring none,
+        // so both are checked again once they are applied
+        crate::validation::input::check_player(request.get_ref(), config.max_difficulty)?;
+        if !request.get_ref().region.is_empty() {
+            let (region, _) = crate::geoip::table().resolve(
+                &request.get_ref().region,
+                &served,
+                crate::geoip::client_ip(&request),
+            );
+            crate::validation::input::check_region(&region, &served)?;
+        }
+        if let Some(active) = deadline
+            .run(rejoin::active_match(&mut conn, &player_id))
+            .await??
+        {
+            let detail = format!("match `{}` is active", active.id);
+            return Ok(not_queued(&player_id, JoinQueueStatus::InMatch, detail));
+        }
+        match deadline
+            .run(crate::penalty::check_cooldown(&mut conn, &player_id))
+            .await?
+        {
+            Err(err @ crate::penalty::Error::Cooldown { .. }) => {
+                return Ok(not_queued(
+                    &player_id,
+                    JoinQueueStatus::RejectedPenalty,
+                    err,
+                ));
+            }
+            checked => checked?,
+        }
+        match deadline
+            .run(
+                self.validator
+                    .validate(&JoinAttempt::new(&request, player_id)),
+            )
+            .await?
+        {
+            Err(err @ crate::validation::Error::Flagged(_)) => {
+                return Ok(not_queued(
+                    &player_id,
+                    JoinQueueStatus::RejectedAntiCheat,
+                    err,
+                ));
+            }
+            Err(err @ crate::validation::Error::OutdatedClient { .. }) => {
+                let status = JoinQueueStatus::RejectedOutdatedClient;
+                return Ok(not_queued(&player_id, status, err));
+            }
+            validated => validated?,
         }
+        let preferences = deadline
+            .run(crate::preferences::load_for_join(
+                &self.nakama_client,
+                self.http_client.clone(),
+                &player_id,
+            ))
+            .await?;
+        preferences.apply(request.get_mut());
+        crate::validation::input::check_player(request.get_ref(), config.max_difficulty)?;
+        let match_settings = crate::match_settings::MatchSettings::parse(
+            request.get_ref().match_settings.clone(),
+            request.get_ref().join_mode(),
+        )?;
+        let playlist = match deadline
+            .run(crate::playlists::check_active(
+                &mut conn,
+                &request.get_ref().playlist,
+                self.clock.now(),
+            ))
+            .await?
+        {
+            Err(err @ crate::playlists::Error::Inactive(_)) => {
+                let status = JoinQueueStatus::RejectedPlaylistInactive;
+                return Ok(not_queued(&player_id, status, err));
+            }
+            playlist => playlist?,
+        };
+        let clan = deadline
+            .run(crate::clans::resolve(
+                &self.nakama_client,
+                self.http_client.clone(),
+                &player_id,
+                &request.get_ref().clan_id,
+            ))
+            .await??;
+        crate::clans::check_playlist(playlist.as_ref(), clan.as_ref())?;
+
+        let party_ids = match deadline
+            .run(crate::party::player_party(&mut conn, &player_id))
+            .await??
+        {
+            Some(party) if party.host_id == player_id => {
+                party.confirm_members(&request.get_ref().party_member_id)?
+            }
+            _ if !request.get_ref().party_member_id.is_empty() => {
+                return Err(crate::party::Error::UnconfirmedMembers(
+                    request.get_ref().party_member_id.clone(),
+                )
+                .into());
+            }
+            _ => Vec::new(),
+        };
+
+        let equipped = deadline
+            .run(crate::progression::check_loadout(
+                &self.nakama_client,
+                self.http_client.clone(),
+                &player_id,
+                &request.get_ref().loadout_config,
+            ))
+            .await??;
 
         let skill_result = {
             let nakama_client = self.nakama_client.clone();
             let http_client = self.http_client.clone();
-            nakama_client
-                .get_skill_rating(http_client, &request.get_ref().player_id)
-                .await
+            deadline
+                .run(nakama_client.get_skill_rating(http_client, &request.get_ref().player_id))
+                .await?
         };
         let skillrating = skill_result
             .inspect_err(|err| error!("Nakama API failed: {err}\n{err:?}"))
             .to_tonic_error("Nakama API failed", Box::new(tonic::Status::internal))?;
-        let dt = Local::now();
-        let time_since = time_since(&dt)?;
-        let data: QueuedPlayer = (player_id, request.into_inner(), skillrating).into();
-        let data = data.joined_at(time_since);
+        let skillrating = deadline
+            .run(crate::ratings::rating_or(
+                &mut conn,
+                &player_id,
+                skillrating,
+            ))
+            .await?
+            .to_tonic_error("Failed to load rating", Box::new(tonic::Status::internal))?;
+        let smurf_stats = deadline
+            .run(crate::smurf::stats(&mut conn, &player_id))
+            .await?
+            .map_err(crate::smurf::Error::from)?;
+        let skillrating = smurf_stats.converge(skillrating);
+        let behavior = deadline
+            .run(crate::trust::behavior(
+                &mut conn,
+                &self.nakama_client,
+                self.http_client.clone(),
+                &player_id,
+            ))
+            .await??;
+        let skillrating = skillrating.loadout_modifier(crate::progression::loadout_modifier(
+            &equipped,
+            config.loadout_tier_modifier,
+            config.max_loadout_modifier,
+        ));
+        let tunings = deadline
+            .run(crate::regions::tuning::get_tunings(&mut conn))
+            .await??;
+        let (region, region_source) = crate::geoip::table().resolve(
+            preferences.region(&request.get_ref().region, &served),
+            &served,
+            crate::geoip::client_ip(&request),
+        );
+        crate::validation::input::check_region(&region, &served)?;
+        let mut params = config.params();
+        tunings.get(&region).apply(&mut params);
+        crate::ranked::apply(request.get_ref().queue_type(), &mut params);
+        let assignment = deadline
+            .run(crate::experiments::assignment(
+                &mut conn, params, &player_id,
+            ))
+            .await??;
+        let measured = deadline
+            .run(crate::latency::measured(&mut conn, &player_id))
+            .await??;
+        let time_since = self.clock.time_since_epoch();
+        let request_id = request_id(&request).to_string();
+        let mut player = request.into_inner();
+        player.party_member_id = party_ids;
+        // the requested ping is only trusted until the heartbeat measured one, which is
+        // snapshotted in the queue entry and refreshed when the player joins again
+        if let Some(rtt) = measured {
+            player.ping = rtt.ping();
+        }
+        let data: QueuedPlayer = (player_id, player, skillrating).into();
+        let data = data
+            .with_region(region, region_source)
+            .with_match_settings(match_settings)
+            .with_clan(clan)
+            .joined_at(time_since)
+            .with_trust(behavior.trust())
+            .with_smurf(smurf_stats.is_smurf())
+            .with_experiments(assignment)
+            .with_skill_band(config.skill_band_width)
+            .with_request_id(request_id);
 
         // Redis block
-        let encoded_player = bitcode::encode(&data);
-        let mut conn = self.redis.clone();
-        conn.set_ex(player_id, &encoded_player, TEN_MINUTES)
-            .await
-            .map(|_: ()| ())
-            .inspect_err(|err| error!("Redis failed to save player: {err}"))
-            .to_tonic_error(
-                format!("Failed to save player `{player_id}` to redis"),
-                Box::new(tonic::Status::internal),
-            )?;
-
-        let player_key = player_queue_key(&data);
-
-        let order: usize = conn
-            .zadd(player_key, &encoded_player, time_since)
-            .await
-            .inspect_err(|err| error!("Redis failed to queue player: {err}\n{err:?}"))
+        let previous_bits: Option<Vec<u8>> = deadline
+            .run(store::player_data(&mut conn, &player_id))
+            .await?
             .to_tonic_error(
-                "Failed to add player to queue",
+                "Failed to load queued player",
                 Box::new(tonic::Status::internal),
             )?;
-        debug!("Player: `{player_id}` Index: `{order}` TimeSince: `{time_since}`");
-
-        let create_room: i32 = JoinMode::CreateRoom.into();
-        if data.join_mode == create_room {
-            let create_match_key = create_match_queue_key(&data.region);
+        // the entries are removed by the bytes they were queued with
+        let previous = previous_bits.as_ref().and_then(|bits| {
+            codec::decode::<QueuedPlayer>(bits)
+                .ok()
+                .map(|player| (player, bits))
+        });
+        // A retry or a second device replaces the queued entry, keeping its queue position
+        let data = match &previous {
+            Some((previous, _)) => data.joined_at(previous.join_time),
+            None => data,
+        };
+        let encoded_player = codec::encode(&data);
+        // the whole party is queued with the host, or the host does not queue
+        let member_ids: Vec<Uuid> = data
+            .party_ids
+            .iter()
+            .filter_map(|member_id| Uuid::parse_str(member_id).ok())
+            .collect();
 
-            let _ = conn
-                .zadd(create_match_key, &encoded_player, time_since)
-                .await
-                .map(|_: ()| ())
-                .inspect_err(|err| error!("Redis failed to queue room creation: {err}\n{err:?}"));
+        let mut pipe = redis::pipe();
+        if let Some((previous, encoded_previous)) = &previous {
+            pipe.zrem(player_queue_key(previous), encoded_previous)
+                .ignore()
+                .zrem(player_create_match_key(previous), encoded_previous)
+                .ignore()
+                .zrem(player_versus_key(previous), encoded_previous)
+                .ignore()
+                .incr(duplicate_joins_key(), 1)
+                .ignore();
+            warn!("Player `{player_id}` joined the queue twice, replacing its entry");
         }
+        store::put_player(&mut pipe, &data, &encoded_player, TEN_MINUTES);
+        let is_raid = playlist.is_some_and(|playlist| playlist.raid.is_some());
+        enqueue(&mut pipe, &data, &encoded_player, is_raid);
+        // a heartbeat that stopped before this join does not count against it
+        pipe.zrem(presence::presence_key(), player_id).ignore();
+        deadline
+            .run(crate::party::hold_members(
+                &mut conn,
+                &player_id,
+                previous_bits.as_deref(),
+                &member_ids,
+                TEN_MINUTES,
+                &pipe,
+            ))
+            .await?
+            .map_err(|err| match err {
+                crate::party::Error::Redis(err) => {
+                    error!("Redis failed to queue player: {err}\n{err:?}");
+                    tonic::Status::internal("Failed to add player to queue")
+                }
+                err => err.into(),
+            })?;
+        debug!("Player: `{player_id}` TimeSince: `{time_since}`");
+        let queued = AuditEvent::new(
+            Action::PlayerQueued,
+            Actor::Player(player_id),
+            player_id,
+            time_since,
+        )
+        .with_reason(if previous.is_some() { "replaced" } else { "" });
+        audit::emit(&mut conn, &queued).await;
 
+        let status = match previous {
+            Some(_) => JoinQueueStatus::AlreadyQueued,
+            None => JoinQueueStatus::Queued,
+        };
         Ok(tonic::Response::new(JoinQueueResponse {
             player_id: player_id.to_string(),
-            status: "waiting in queue".to_string(),
+            region: data.region,
+            status: status.into(),
+            detail: String::new(),
         }))
     }
 
+    #[instrument(skip_all, fields(request_id = request_id(&request), player_id = session_player(&request)))]
+    async fn invite_to_party(
+        &self,
+        request: Request<PartyInviteRequest>,
+    ) -> Result<tonic::Response<PartyResponse>, tonic::Status> {
+        self.invite(request).await
+    }
+
+    #[instrument(skip_all, fields(request_id = request_id(&request), player_id = session_player(&request)))]
+    async fn accept_invite(
+        &self,
+        request: Request<PartyRequest>,
+    ) -> Result<tonic::Response<PartyResponse>, tonic::Status> {
+        self.accept(request).await
+    }
+
+    #[instrument(skip_all, fields(request_id = request_id(&request), player_id = session_player(&request)))]
+    async fn decline_invite(
+        &self,
+        request: Request<PartyRequest>,
+    ) -> Result<tonic::Response<PartyResponse>, tonic::Status> {
+        self.decline(request).await
+    }
+
+    #[instrument(skip_all, fields(request_id = request_id(&request), player_id = session_player(&request)))]
+    async fn leave_party(
+        &self,
+        request: Request<PartyRequest>,
+    ) -> Result<tonic::Response<PartyResponse>, tonic::Status> {
+        self.leave(request).await
+    }
+
+    #[instrument(skip_all, fields(request_id = request_id(&request), player_id = session_player(&request)))]
+    async fn transfer_party_host(
+        &self,
+        request: Request<PartyTransferRequest>,
+    ) -> Result<tonic::Response<PartyResponse>, tonic::Status> {
+        self.transfer_host(request).await
+    }
+
+    #[instrument(skip_all, fields(request_id = request_id(&request), player_id = session_player(&request)))]
+    async fn disband_party(
+        &self,
+        request: Request<PartyRequest>,
+    ) -> Result<tonic::Response<PartyResponse>, tonic::Status> {
+        self.disband(request).await
+    }
+
+    #[instrument(skip_all, fields(request_id = request_id(&request), player_id = session_player(&request)))]
+    async fn create_clan_party(
+        &self,
+        request: Request<ClanPartyRequest>,
+    ) -> Result<tonic::Response<PartyResponse>, tonic::Status> {
+        self.clan_party(request).await
+    }
+
+    #[instrument(skip_all, fields(request_id = request_id(&request), player_id = session_player(&request)))]
+    async fn requeue_party(
+        &self,
+        request: Request<RequeuePartyRequest>,
+    ) -> Result<tonic::Response<RequeuePartyResponse>, tonic::Status> {
+        self.requeue_completed(request).await
+    }
+
+    #[instrument(skip_all, fields(request_id = request_id(&request), player_id = session_player(&request)))]
+    async fn accept_requeue(
+        &self,
+        request: Request<AcceptRequeueRequest>,
+    ) -> Result<tonic::Response<AcceptRequeueResponse>, tonic::Status> {
+        self.accept_completed_requeue(request).await
+    }
+
+    #[instrument(skip_all, fields(request_id = request_id(&request), player_id = session_player(&request)))]
+    async fn rejoin_match(
+        &self,
+        request: Request<RejoinMatchRequest>,
+    ) -> Result<tonic::Response<RejoinMatchResponse>, tonic::Status> {
+        self.rejoin(request).await
+    }
+
+    #[instrument(skip_all, fields(request_id = request_id(&request), player_id = session_player(&request)))]
+    async fn report_open_slots(
+        &self,
+        request: Request<OpenSlotsRequest>,
+    ) -> Result<tonic::Response<OpenSlotsResponse>, tonic::Status> {
+        self.open_slots(request).await
+    }
+
+    #[instrument(skip_all, fields(request_id = request_id(&request), player_id = session_player(&request)))]
+    async fn watch_queue(
+        &self,
+        request: Request<WatchQueueRequest>,
+    ) -> Result<tonic::Response<Self::WatchQueueStream>, tonic::Status> {
+        self.watch_events(request).await
+    }
+
+    #[instrument(skip_all, fields(request_id = request_id(&request), player_id = session_player(&request)))]
+    async fn heartbeat(
+        &self,
+        request: Request<tonic::Streaming<HeartbeatAck>>,
+    ) -> Result<tonic::Response<Self::HeartbeatStream>, tonic::Status> {
+        self.heartbeat_stream(request).await
+    }
+
+    #[instrument(skip_all, fields(request_id = request_id(&request), player_id = session_player(&request)))]
+    async fn report_abandon(
+        &self,
+        request: Request<AbandonReport>,
+    ) -> Result<tonic::Response<AbandonResponse>, tonic::Status> {
+        self.abandon(request).await
+    }
+
+    #[instrument(skip_all, fields(request_id = request_id(&request), player_id = session_player(&request)))]
+    async fn report_match_stats(
+        &self,
+        request: Request<MatchStatsRequest>,
+    ) -> Result<tonic::Response<MatchStatsResponse>, tonic::Status> {
+        self.match_stats(request).await
+    }
+
+    #[instrument(skip_all, fields(request_id = request_id(&request), player_id = session_player(&request)))]
+    async fn report_player(
+        &self,
+        request: Request<ReportPlayerRequest>,
+    ) -> Result<tonic::Response<ReportPlayerResponse>, tonic::Status> {
+        self.report(request).await
+    }
+
+    #[instrument(skip_all, fields(request_id = request_id(&request), player_id = session_player(&request)))]
+    async fn list_open_matches(
+        &self,
+        request: Request<ListOpenMatchesRequest>,
+    ) -> Result<tonic::Response<ListOpenMatchesResponse>, tonic::Status> {
+        self.list_open(request).await
+    }
+
+    #[instrument(skip_all, fields(request_id = request_id(&request), player_id = session_player(&request)))]
+    async fn get_preferences(
+        &self,
+        request: Request<PreferencesRequest>,
+    ) -> Result<tonic::Response<PreferencesResponse>, tonic::Status> {
+        self.preferences(request).await
+    }
+
+    #[instrument(skip_all, fields(request_id = request_id(&request), player_id = session_player(&request)))]
+    async fn set_preferences(
+        &self,
+        request: Request<SetPreferencesRequest>,
+    ) -> Result<tonic::Response<PreferencesResponse>, tonic::Status> {
+        self.save_preferences(request).await
+    }
+
+    #[instrument(skip_all, fields(request_id = request_id(&request), player_id = session_player(&request)))]
+    async fn get_match_history(
+        &self,
+        request: Request<MatchHistoryRequest>,
+    ) -> Result<tonic::Response<MatchHistoryResponse>, tonic::Status> {
+        self.match_history(request).await
+    }
+
+    #[instrument(skip_all, fields(request_id = request_id(&request), player_id = session_player(&request)))]
+    async fn kick_from_lobby(
+        &self,
+        request: Request<KickFromLobbyRequest>,
+    ) -> Result<tonic::Response<KickFromLobbyResponse>, tonic::Status> {
+        self.kick(request).await
+    }
+
+    #[instrument(skip_all, fields(request_id = request_id(&request), player_id = session_player(&request)))]
+    async fn confirm_ready(
+        &self,
+        request: Request<ConfirmReadyRequest>,
+    ) -> Result<tonic::Response<ConfirmReadyResponse>, tonic::Status> {
+        self.confirm(request).await
+    }
+
+    #[instrument(skip_all, fields(request_id = request_id(&request), player_id = session_player(&request)))]
+    async fn create_tournament(
+        &self,
+        request: Request<CreateTournamentRequest>,
+    ) -> Result<tonic::Response<TournamentResponse>, tonic::Status> {
+        self.create_bracket(request).await
+    }
+
+    #[instrument(skip_all, fields(request_id = request_id(&request), player_id = session_player(&request)))]
+    async fn register_tournament_party(
+        &self,
+        request: Request<RegisterTournamentRequest>,
+    ) -> Result<tonic::Response<TournamentResponse>, tonic::Status> {
+        self.register_bracket_party(request).await
+    }
+
+    #[instrument(skip_all, fields(request_id = request_id(&request), player_id = session_player(&request)))]
+    async fn start_tournament(
+        &self,
+        request: Request<TournamentRequest>,
+    ) -> Result<tonic::Response<TournamentResponse>, tonic::Status> {
+        self.start_bracket(request).await
+    }
+
+    #[instrument(skip_all, fields(request_id = request_id(&request), player_id = session_player(&request)))]
+    async fn get_tournament(
+        &self,
+        request: Request<TournamentRequest>,
+    ) -> Result<tonic::Response<TournamentResponse>, tonic::Status> {
+        self.bracket(request).await
+    }
+
+    #[instrument(skip_all, fields(request_id = request_id(&request), player_id = session_player(&request)))]
+    async fn report_match_result(
+        &self,
+        request: Request<MatchResultRequest>,
+    ) -> Result<tonic::Response<TournamentResponse>, tonic::Status> {
+        self.match_result(request).await
+    }
+
+    #[instrument(skip_all, fields(request_id = request_id(&request), player_id = session_player(&request)))]
+    async fn recommend_difficulty(
+        &self,
+        request: Request<RecommendDifficultyRequest>,
+    ) -> Result<tonic::Response<RecommendDifficultyResponse>, tonic::Status> {
+        self.recommend(request).await
+    }
+
+    #[instrument(skip_all, fields(request_id = request_id(&request), player_id = session_player(&request)))]
+    async fn reload_config(
+        &self,
+        request: Request<ReloadConfigRequest>,
+    ) -> Result<tonic::Response<ReloadConfigResponse>, tonic::Status> {
+        self.reload(request).await
+    }
+
+    #[instrument(skip_all, fields(request_id = request_id(&request), player_id = session_player(&request)))]
+    async fn get_queue_metrics(
+        &self,
+        request: Request<QueueMetricsRequest>,
+    ) -> Result<tonic::Response<QueueMetricsResponse>, tonic::Status> {
+        self.metrics(request).await
+    }
+
+    #[instrument(skip_all, fields(request_id = request_id(&request), player_id = session_player(&request)))]
+    async fn get_queue_analytics(
+        &self,
+        request: Request<QueueAnalyticsRequest>,
+    ) -> Result<tonic::Response<QueueAnalyticsResponse>, tonic::Status> {
+        self.analytics(request).await
+    }
+
+    #[instrument(skip_all, fields(request_id = request_id(&request), player_id = session_player(&request)))]
+    async fn watch_observability(
+        &self,
+        request: Request<ObservabilityRequest>,
+    ) -> Result<tonic::Response<Self::WatchObservabilityStream>, tonic::Status> {
+        self.observe(request).await
+    }
+
+    #[instrument(skip_all, fields(request_id = request_id(&request), player_id = session_player(&request)))]
+    async fn revoke_session(
+        &self,
+        request: Request<RevokeSessionRequest>,
+    ) -> Result<tonic::Response<RevokeSessionResponse>, tonic::Status> {
+        self.revoke(request).await
+    }
+
+    #[instrument(skip_all, fields(request_id = request_id(&request), player_id = session_player(&request)))]
+    async fn set_environment(
+        &self,
+        request: Request<SetEnvironmentRequest>,
+    ) -> Result<tonic::Response<EnvironmentResponse>, tonic::Status> {
+        self.set_environment_ratings(request).await
+    }
+
+    #[instrument(skip_all, fields(request_id = request_id(&request), player_id = session_player(&request)))]
+    async fn get_environment(
+        &self,
+        request: Request<EnvironmentRequest>,
+    ) -> Result<tonic::Response<EnvironmentResponse>, tonic::Status> {
+        self.get_environment_ratings(request).await
+    }
+
+    #[instrument(skip_all, fields(request_id = request_id(&request), player_id = session_player(&request)))]
+    async fn delete_environment(
+        &self,
+        request: Request<EnvironmentRequest>,
+    ) -> Result<tonic::Response<EnvironmentResponse>, tonic::Status> {
+        self.delete_environment_ratings(request).await
+    }
+
+    #[instrument(skip_all, fields(request_id = request_id(&request), player_id = session_player(&request)))]
+    async fn get_queue_sizes(
+        &self,
+        request: Request<QueueSizesRequest>,
+    ) -> Result<tonic::Response<QueueSizesResponse>, tonic::Status> {
+        self.queue_sizes(request).await
+    }
+
+    #[instrument(skip_all, fields(request_id = request_id(&request), player_id = session_player(&request)))]
+    async fn get_queued_player(
+        &self,
+        request: Request<QueuedPlayerRequest>,
+    ) -> Result<tonic::Response<QueuedPlayerResponse>, tonic::Status> {
+        self.queued_player(request).await
+    }
+
+    #[instrument(skip_all, fields(request_id = request_id(&request), player_id = session_player(&request)))]
+    async fn remove_queued_player(
+        &self,
+        request: Request<RemoveQueuedPlayerRequest>,
+    ) -> Result<tonic::Response<RemoveQueuedPlayerResponse>, tonic::Status> {
+        self.remove_queued(request).await
+    }
+
+    #[instrument(skip_all, fields(request_id = request_id(&request), player_id = session_player(&request)))]
+    async fn list_matches(
+        &self,
+        request: Request<ListMatchesRequest>,
+    ) -> Result<tonic::Response<ListMatchesResponse>, tonic::Status> {
+        self.matches_in_state(request).await
+    }
+
+    #[instrument(skip_all, fields(request_id = request_id(&request), player_id = session_player(&request)))]
+    async fn request_tick(
+        &self,
+        request: Request<RequestTickRequest>,
+    ) -> Result<tonic::Response<RequestTickResponse>, tonic::Status> {
+        self.tick(request).await
+    }
+
+    #[instrument(skip_all, fields(request_id = request_id(&request), player_id = session_player(&request)))]
     async fn check(
         &self,
         request: Request<HealthCheckRequest>,
     ) -> Result<tonic::Response<HealthCheckResponse>, tonic::Status> {
-        Ok(tonic::Response::new(healthcheck::healthy(request)))
+        Ok(tonic::Response::new(self.health_status(&request).await))
     }
 
+    #[instrument(skip_all, fields(request_id = request_id(&request), player_id = session_player(&request)))]
     async fn watch(
         &self,
         request: Request<HealthCheckRequest>,
@@ -124,23 +757,31 @@ impl MatchmakingService for MatchmakingServer {
         debug!("MatchmakingServer::watch::healthcheck");
         debug!("\tclient connected from: {:?}", request.remote_addr());
 
-        // creating infinite stream with requested message
-        let repeat = std::iter::repeat(healthcheck::healthy(request));
-        let mut stream = Box::pin(tokio_stream::iter(repeat).throttle(Duration::from_millis(200)));
-
-        // spawn and channel are required if you want handle "disconnect" functionality
-        // the `out_stream` will not be polled after client disconnect
+        // the current status is sent right away, then every change of it
+        let known = healthcheck::known_service(&request);
+        let server = self.clone();
         let (tx, rx) = mpsc::channel(128);
         tokio::spawn(async move {
-            while let Some(item) = stream.next().await {
-                match tx.send(Result::<_, Status>::Ok(item)).await {
-                    Ok(_) => {
-                        // item (server response) was queued to be send to client
-                    }
-                    Err(_item) => {
-                        // output_stream was build from rx and both are dropped
-                        break;
-                    }
+            let mut interval = tokio::time::interval(healthcheck::WATCH_INTERVAL);
+            let mut last = None;
+            while !tx.is_closed() {
+                interval.tick().await;
+                let status = if known {
+                    server.serving_status().await
+                } else {
+                    healthcheck::ServingStatus::ServiceUnknown
+                };
+                if last == Some(status) {
+                    continue;
+                }
+                last = Some(status);
+                if tx
+                    .send(Result::<_, Status>::Ok(status.into()))
+                    .await
+                    .is_err()
+                {
+                    // output_stream was build from rx and both are dropped
+                    break;
                 }
             }
             debug!("\tclient disconnected");
@@ -154,5 +795,19 @@ impl MatchmakingService for MatchmakingServer {
     }
 }
 
+/// Answer of a join that did not queue the player, `detail` says why
+fn not_queued(
+    player_id: &Uuid,
+    status: JoinQueueStatus,
+    detail: impl ToString,
+) -> tonic::Response<JoinQueueResponse> {
+    tonic::Response::new(JoinQueueResponse {
+        player_id: player_id.to_string(),
+        status: status.into(),
+        detail: detail.to_string(),
+        ..Default::default()
+    })
+}
+
 #[cfg(test)]
 mod integration_tests;
@@ -0,0 +1,60 @@
+use std::pin::Pin;
+
+use tokio::{
+    sync::{broadcast::error::RecvError, mpsc},
+    time::Instant,
+};
+use tokio_stream::{Stream, wrappers::ReceiverStream};
+use tonic::{Request, Response, Status};
+use tracing::debug;
+
+use crate::{
+    observability,
+    rpc::{
+        matchmaking::{ObservabilityRequest, ObservabilitySnapshot},
+        server::{MatchmakingServer, auth::authorize_admin},
+    },
+};
+
+pub(crate) type ObservabilityStream =
+    Pin<Box<dyn Stream<Item = Result<ObservabilitySnapshot, Status>> + Send>>;
+
+impl MatchmakingServer {
+    pub(super) async fn observe(
+        &self,
+        request: Request<ObservabilityRequest>,
+    ) -> Result<Response<ObservabilityStream>, Status> {
+        authorize_admin(&request)?;
+        let interval = observability::interval(request.get_ref().interval_secs);
+        let mut snapshots = self.observability.subscribe(&self.redis, &self.clock);
+
+        let (tx, rx) = mpsc::channel(16);
+        tokio::spawn(async move {
+            // the shared feed sends every second, forwarded once every `interval`
+            let mut sent_at: Option<Instant> = None;
+            loop {
+                let snapshot = tokio::select! {
+                    () = tx.closed() => break,
+                    snapshot = snapshots.recv() => snapshot,
+                };
+                let snapshot = match snapshot {
+                    Ok(snapshot) => snapshot,
+                    Err(RecvError::Lagged(_)) => continue,
+                    Err(RecvError::Closed) => break,
+                };
+                if sent_at.is_some_and(|sent_at| sent_at.elapsed() < interval) {
+                    continue;
+                }
+                sent_at = Some(Instant::now());
+                if tx.send(Ok(snapshot)).await.is_err() {
+                    break;
+                }
+            }
+            debug!("observability watcher disconnected");
+        });
+
+        Ok(Response::new(
+            Box::pin(ReceiverStream::new(rx)) as ObservabilityStream
+        ))
+    }
+}
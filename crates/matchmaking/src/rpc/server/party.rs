@@ -0,0 +1,234 @@
+use tonic::{Request, Response, Status};
+use tracing::debug;
+use uuid::Uuid;
+
+use crate::{
+    clans,
+    notifications::{self, Notification},
+    party::{self, Party},
+    rematch,
+    rpc::{
+        helper::parse_id,
+        matchmaking::{
+            AcceptRequeueRequest, AcceptRequeueResponse, ClanPartyRequest, JoinMode,
+            PartyInviteRequest, PartyRequest, PartyResponse, PartyTransferRequest,
+            RequeuePartyRequest, RequeuePartyResponse,
+        },
+        server::{MatchmakingServer, auth::authorize_player},
+    },
+};
+
+impl MatchmakingServer {
+    pub(super) async fn invite(
+        &self,
+        request: Request<PartyInviteRequest>,
+    ) -> Result<Response<PartyResponse>, Status> {
+        let host_id = authorize_player(&request, &request.get_ref().player_id)?;
+        let invitee_id = parse_id(&request.get_ref().invitee_id)?;
+        let mut conn = self.redis.clone();
+
+        if party::player_party(&mut conn, &invitee_id).await?.is_some() {
+            return Err(party::Error::AlreadyInParty(invitee_id).into());
+        }
+        let mut party = party::player_party(&mut conn, &host_id)
+            .await?
+            .unwrap_or_else(|| Party::new(host_id));
+        party.invite(host_id, invitee_id)?;
+        party::save_party(&mut conn, &party).await?;
+        debug!("Player `{invitee_id}` invited to party `{}`", party.id);
+
+        Ok(Response::new((&party).into()))
+    }
+
+    pub(super) async fn clan_party(
+        &self,
+        request: Request<ClanPartyRequest>,
+    ) -> Result<Response<PartyResponse>, Status> {
+        let host_id = authorize_player(&request, &request.get_ref().player_id)?;
+        let clan_id = &request.get_ref().clan_id;
+        let mut conn = self.redis.clone();
+
+        let clan = clans::resolve(
+            &self.nakama_client,
+            self.http_client.clone(),
+            &host_id,
+            clan_id,
+        )
+        .await?
+        .ok_or_else(|| clans::Error::NotMember(clan_id.clone()))?;
+        let roster = clans::roster(&self.nakama_client, self.http_client.clone(), &clan.id).await?;
+        let mut party = match party::player_party(&mut conn, &host_id).await? {
+            Some(party) if party.host_id == host_id => party,
+            Some(_) => return Err(party::Error::AlreadyInParty(host_id).into()),
+            None => Party::new(host_id),
+        };
+        for member_id in &roster {
+            if *member_id == host_id
+                || party.members.contains(member_id)
+                || party::player_party(&mut conn, member_id).await?.is_some()
+            {
+                continue;
+            }
+            match party.invite(host_id, *member_id) {
+                Ok(()) => {}
+                Err(party::Error::Full { .. }) => break,
+                Err(err) => return Err(err.into()),
+            }
+        }
+        party::save_party(&mut conn, &party).await?;
+        debug!("Clan `{}` invited to party `{}`", clan.tag, party.id);
+
+        Ok(Response::new((&party).into()))
+    }
+
+    pub(super) async fn accept(
+        &self,
+        request: Request<PartyRequest>,
+    ) -> Result<Response<PartyResponse>, Status> {
+        let player_id = authorize_player(&request, &request.get_ref().player_id)?;
+        let party_id = parse_id(&request.get_ref().party_id)?;
+        let mut conn = self.redis.clone();
+
+        if party::player_party(&mut conn, &player_id).await?.is_some() {
+            return Err(party::Error::AlreadyInParty(player_id).into());
+        }
+        let mut party = party::get_party(&mut conn, &party_id)
+            .await?
+            .ok_or(party::Error::NotFound(party_id))?;
+        party.accept(player_id)?;
+        party::save_party(&mut conn, &party).await?;
+
+        Ok(Response::new((&party).into()))
+    }
+
+    pub(super) async fn decline(
+        &self,
+        request: Request<PartyRequest>,
+    ) -> Result<Response<PartyResponse>, Status> {
+        let player_id = authorize_player(&request, &request.get_ref().player_id)?;
+        let party_id = parse_id(&request.get_ref().party_id)?;
+        let mut conn = self.redis.clone();
+
+        let mut party = party::get_party(&mut conn, &party_id)
+            .await?
+            .ok_or(party::Error::NotFound(party_id))?;
+        party.decline(player_id)?;
+        party::save_party(&mut conn, &party).await?;
+
+        Ok(Response::new((&party).into()))
+    }
+
+    pub(super) async fn leave(
+        &self,
+        request: Request<PartyRequest>,
+    ) -> Result<Response<PartyResponse>, Status> {
+        let player_id = authorize_player(&request, &request.get_ref().player_id)?;
+        let party_id = parse_id(&request.get_ref().party_id)?;
+        let mut conn = self.redis.clone();
+
+        let mut party = party::get_party(&mut conn, &party_id)
+            .await?
+            .ok_or(party::Error::NotFound(party_id))?;
+        if party.host_id == player_id {
+            let change = party::hand_over(&mut conn, &mut party, false).await?;
+            party::update_queued(&mut conn, &player_id, |player| player.party_ids.clear()).await?;
+            debug!("Party `{party_id}` host left: {change:?}");
+        } else {
+            party.leave(player_id)?;
+            party::save_party(&mut conn, &party).await?;
+            party::unlink_player(&mut conn, &player_id).await?;
+        }
+
+        Ok(Response::new((&party).into()))
+    }
+
+    pub(super) async fn transfer_host(
+        &self,
+        request: Request<PartyTransferRequest>,
+    ) -> Result<Response<PartyResponse>, Status> {
+        let host_id = authorize_player(&request, &request.get_ref().player_id)?;
+        let party_id = parse_id(&request.get_ref().party_id)?;
+        let new_host_id = parse_id(&request.get_ref().new_host_id)?;
+        let mut conn = self.redis.clone();
+
+        let mut party = party::get_party(&mut conn, &party_id)
+            .await?
+            .ok_or(party::Error::NotFound(party_id))?;
+        party.transfer_host(host_id, new_host_id)?;
+        party::save_party(&mut conn, &party).await?;
+
+        let guests: Vec<String> = party.guests().map(Uuid::to_string).collect();
+        party::update_queued(&mut conn, &host_id, |player| {
+            player.join_mode = JoinMode::JoinRoom.into();
+            player.party_ids.clear();
+        })
+        .await?;
+        party::update_queued(&mut conn, &new_host_id, |player| {
+            player.join_mode = JoinMode::CreateRoom.into();
+            player.party_ids = guests;
+        })
+        .await?;
+        let notification = Notification::PartyHostChanged {
+            party_id,
+            host_id: new_host_id,
+        };
+        notifications::notify(&mut conn, &party.members, &notification)
+            .await
+            .map_err(party::Error::from)?;
+
+        Ok(Response::new((&party).into()))
+    }
+
+    pub(super) async fn disband(
+        &self,
+        request: Request<PartyRequest>,
+    ) -> Result<Response<PartyResponse>, Status> {
+        let host_id = authorize_player(&request, &request.get_ref().player_id)?;
+        let party_id = parse_id(&request.get_ref().party_id)?;
+        let mut conn = self.redis.clone();
+
+        let party = party::get_party(&mut conn, &party_id)
+            .await?
+            .ok_or(party::Error::NotFound(party_id))?;
+        party::disband(&mut conn, &party, host_id).await?;
+        party::update_queued(&mut conn, &host_id, |player| player.party_ids.clear()).await?;
+        debug!("Party `{party_id}` disbanded");
+
+        Ok(Response::new((&party).into()))
+    }
+
+    pub(super) async fn requeue_completed(
+        &self,
+        request: Request<RequeuePartyRequest>,
+    ) -> Result<Response<RequeuePartyResponse>, Status> {
+        let host_id = authorize_player(&request, &request.get_ref().player_id)?;
+        let match_id = parse_id(&request.get_ref().match_id)?;
+        let mut conn = self.redis.clone();
+
+        let requeued =
+            rematch::requeue(&mut conn, match_id, host_id, self.clock.time_since_epoch()).await?;
+        debug!(
+            "Party `{}` of match `{match_id}` queued again",
+            requeued.party.id
+        );
+
+        Ok(Response::new(RequeuePartyResponse {
+            party: Some((&requeued.party).into()),
+            join_time: requeued.join_time,
+        }))
+    }
+
+    pub(super) async fn accept_completed_requeue(
+        &self,
+        request: Request<AcceptRequeueRequest>,
+    ) -> Result<Response<AcceptRequeueResponse>, Status> {
+        let player_id = authorize_player(&request, &request.get_ref().player_id)?;
+        let match_id = parse_id(&request.get_ref().match_id)?;
+        let mut conn = self.redis.clone();
+
+        rematch::accept(&mut conn, match_id, player_id).await?;
+        debug!("Player `{player_id}` accepted to requeue after match `{match_id}`");
+
+        Ok(Response::new(AcceptRequeueResponse {}))
+    }
+}
@@ -0,0 +1,53 @@
+use redis::AsyncCommands;
+use tonic::{Request, Response, Status};
+use tracing::info;
+
+use crate::{
+    codec,
+    penalty::{self, Error},
+    rpc::{
+        Match, active_match_key,
+        helper::parse_id,
+        matchmaking::{AbandonReport, AbandonResponse},
+        server::{MatchmakingServer, auth::reporting_host},
+    },
+};
+
+impl MatchmakingServer {
+    pub(super) async fn abandon(
+        &self,
+        request: Request<AbandonReport>,
+    ) -> Result<Response<AbandonResponse>, Status> {
+        let host_id = reporting_host(&request, &request.get_ref().player_id)?;
+        let match_id = parse_id(&request.get_ref().match_id)?;
+        let abandoner_id = parse_id(&request.get_ref().abandoner_id)?;
+        let mut conn = self.redis.clone();
+
+        let active: Option<Vec<u8>> = conn
+            .get(active_match_key(&match_id))
+            .await
+            .map_err(Error::from)?;
+        let active: Match =
+            codec::decode(&active.ok_or(Error::MatchNotFound(match_id))?).map_err(Error::from)?;
+        if let Some(host_id) = host_id.filter(|host_id| active.host_id != *host_id) {
+            return Err(Error::NotHost(host_id).into());
+        }
+        for player_id in host_id.into_iter().chain([abandoner_id]) {
+            if !active.players.iter().any(|p| p.player_id == player_id) {
+                return Err(Error::NotInMatch(player_id).into());
+            }
+        }
+
+        let penalty = penalty::record_abandon(&mut conn, &match_id, &abandoner_id)
+            .await
+            .map_err(Error::from)?
+            .ok_or(Error::AlreadyReported(abandoner_id))?;
+        info!("Player `{abandoner_id}` abandoned match `{match_id}`: {penalty:?}");
+
+        Ok(Response::new(AbandonResponse {
+            abandoner_id: abandoner_id.to_string(),
+            abandons: penalty.abandons,
+            cooldown_seconds: penalty.cooldown.unwrap_or_default(),
+        }))
+    }
+}
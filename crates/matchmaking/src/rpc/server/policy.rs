@@ -0,0 +1,357 @@
+//! Per-method auth policies. [`Authorized`] wraps a generated service and checks every call
+//! against the [`AuthPolicy`] of its method before it reaches the handler, so health probes stay
+//! public, game server reports require a server session and admin RPCs an admin session.
+
+use std::{
+    collections::HashMap,
+    str::FromStr,
+    sync::Arc,
+    task::{Context, Poll},
+};
+
+use tonic::{
+    Request, Status,
+    body::Body,
+    codegen::{BoxFuture, Service, http},
+    metadata::MetadataMap,
+    server::NamedService,
+};
+
+use super::auth::{Roles, authorize_admin, authorize_server, check_auth};
+
+/// Overrides of the default policies, e.g. `ReportOpenSlots=player,GetTournament=public`
+pub const AUTH_POLICIES_VAR: &str = "AUTH_POLICIES";
+
+#[derive(Debug, thiserror::Error)]
+pub enum Error {
+    #[error("unknown auth policy `{0}`, expected public, player, server or admin")]
+    UnknownPolicy(String),
+    #[error("invalid auth policy entry `{0}`, expected `Method=policy`")]
+    InvalidEntry(String),
+}
+
+/// Who may call a method
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AuthPolicy {
+    /// Anyone, e.g. health probes
+    Public,
+    /// Any valid session
+    Player,
+    /// Sessions of the server users, or admins
+    Server,
+    /// Sessions of the admin users
+    Admin,
+}
+
+impl FromStr for AuthPolicy {
+    type Err = Error;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value.trim().to_lowercase().as_str() {
+            "public" => Ok(Self::Public),
+            "player" => Ok(Self::Player),
+            "server" => Ok(Self::Server),
+            "admin" => Ok(Self::Admin),
+            _ => Err(Error::UnknownPolicy(value.to_string())),
+        }
+    }
+}
+
+impl AuthPolicy {
+    /// Authenticates `req` with the `roles` of the server and checks it is allowed by the policy
+    pub fn authorize(self, req: Request<()>, roles: &Roles) -> Result<Request<()>, Status> {
+        match self {
+            Self::Public => Ok(req),
+            Self::Player => check_auth(req, roles),
+            Self::Server => {
+                let req = check_auth(req, roles)?;
+                authorize_server(&req)?;
+                Ok(req)
+            }
+            Self::Admin => {
+                let req = check_auth(req, roles)?;
+                authorize_admin(&req)?;
+                Ok(req)
+            }
+        }
+    }
+}
+
+/// Policy of every method by name, shared by the API versions
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AuthPolicies {
+    methods: HashMap<String, AuthPolicy>,
+    /// Policy of the methods without their own
+    fallback: AuthPolicy,
+    roles: Roles,
+}
+
+impl Default for AuthPolicies {
+    /// Health checks are public, the reports of game servers require a server session, the admin
+    /// RPCs an admin session and the other methods a session
+    fn default() -> Self {
+        let public = ["Check", "Watch"].map(|method| (method, AuthPolicy::Public));
+        let server = [
+            "ReportOpenSlots",
+            "ReportAbandon",
+            "ReportMatchStats",
+            "ReportMatchResult",
+        ]
+        .map(|method| (method, AuthPolicy::Server));
+        let admin = [
+            "CreateTournament",
+            "StartTournament",
+            "ReloadConfig",
+            "GetQueueMetrics",
+            "GetQueueAnalytics",
+            "WatchObservability",
+            "RevokeSession",
+            "SetEnvironment",
+            "GetEnvironment",
+            "DeleteEnvironment",
+            "GetQueueSizes",
+            "GetQueuedPlayer",
+            "RemoveQueuedPlayer",
+            "ListMatches",
+            "RequestTick",
+        ]
+        .map(|method| (method, AuthPolicy::Admin));
+
+        Self {
+            methods: public
+                .into_iter()
+                .chain(server)
+                .chain(admin)
+                .map(|(method, policy)| (method.to_string(), policy))
+                .collect(),
+            fallback: AuthPolicy::Player,
+            roles: Roles::default(),
+        }
+    }
+}
+
+impl AuthPolicies {
+    /// Sets the policy of `method`, e.g. `ReportOpenSlots`
+    pub fn with(mut self, method: &str, policy: AuthPolicy) -> Self {
+        self.methods.insert(method.to_string(), policy);
+        self
+    }
+
+    /// Users granted a role, none by default
+    pub fn with_roles(mut self, roles: Roles) -> Self {
+        self.roles = roles;
+        self
+    }
+
+    /// Default policies with the overrides of [`AUTH_POLICIES_VAR`] and the roles of
+    /// [`Roles::from_env`]
+    pub fn from_env() -> Result<Self, Error> {
+        let policies = Self::default().with_roles(Roles::from_env());
+        match std::env::var(AUTH_POLICIES_VAR) {
+            Ok(overrides) => policies.with_overrides(&overrides),
+            Err(_) => Ok(policies),
+        }
+    }
+
+    /// Applies comma separated `Method=policy` entries
+    pub fn with_overrides(self, overrides: &str) -> Result<Self, Error> {
+        overrides
+            .split(',')
+            .filter(|entry| !entry.trim().is_empty())
+            .try_fold(self, |policies, entry| {
+                let (method, policy) = entry
+                    .split_once('=')
+                    .ok_or_else(|| Error::InvalidEntry(entry.to_string()))?;
+                Ok(policies.with(method.trim(), policy.parse()?))
+            })
+    }
+
+    /// Policy of the gRPC `path`, e.g. `/matchmaking.MatchmakingService/Check`
+    pub fn policy(&self, path: &str) -> AuthPolicy {
+        let method = path.rsplit('/').next().unwrap_or_default();
+
+        self.methods.get(method).copied().unwrap_or(self.fallback)
+    }
+
+    /// Checks `req` against the policy of the gRPC `path`
+    pub fn authorize(&self, path: &str, req: Request<()>) -> Result<Request<()>, Status> {
+        self.policy(path).authorize(req, &self.roles)
+    }
+}
+
+/// Generated service checking the [`AuthPolicies`] of its methods
+#[derive(Debug, Clone)]
+pub struct Authorized<S> {
+    inner: S,
+    policies: Arc<AuthPolicies>,
+}
+
+impl<S> Authorized<S> {
+    pub const fn new(inner: S, policies: Arc<AuthPolicies>) -> Self {
+        Self { inner, policies }
+    }
+}
+
+impl<S: NamedService> NamedService for Authorized<S> {
+    const NAME: &'static str = S::NAME;
+}
+
+impl<S, B> Service<http::Request<B>> for Authorized<S>
+where
+    S: Service<http::Request<B>, Response = http::Response<Body>>,
+    S::Future: Send + 'static,
+{
+    type Response = http::Response<Body>;
+    type Error = S::Error;
+    type Future = BoxFuture<Self::Response, Self::Error>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, req: http::Request<B>) -> Self::Future {
+        let (mut parts, body) = req.into_parts();
+        let request = Request::from_parts(
+            MetadataMap::from_headers(std::mem::take(&mut parts.headers)),
+            std::mem::take(&mut parts.extensions),
+            (),
+        );
+
+        match self.policies.authorize(parts.uri.path(), request) {
+            Ok(request) => {
+                let (metadata, extensions, ()) = request.into_parts();
+                parts.headers = metadata.into_headers();
+                parts.extensions = extensions;
+                Box::pin(self.inner.call(http::Request::from_parts(parts, body)))
+            }
+            Err(status) => Box::pin(async move { Ok(status.into_http()) }),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::{BTreeMap, HashSet};
+
+    use hmac::{Hmac, Mac};
+    use jwt::{Header, SignWithKey, Token};
+    use sha2::Sha256;
+
+    use super::*;
+    use crate::{
+        nakama::helpers::get_env_encryption_key,
+        rpc::server::auth::{SessionClaims, UserId},
+    };
+
+    const ADMIN_ID: &str = "admin_id";
+    const SERVER_ID: &str = "server_id";
+
+    fn roles() -> Roles {
+        Roles {
+            admins: HashSet::from([ADMIN_ID.to_string()]),
+            servers: HashSet::from([SERVER_ID.to_string()]),
+        }
+    }
+
+    fn session(user_id: Option<&str>) -> Request<()> {
+        let claims = SessionClaims {
+            token_id: "token_id".to_string(),
+            user_id: user_id.unwrap_or("player_id").to_string(),
+            username: "username".to_string(),
+            vars: BTreeMap::new(),
+            expires_at: i64::MAX,
+            issued_at: 0,
+        };
+        let key: Hmac<Sha256> = Hmac::new_from_slice(get_env_encryption_key().as_bytes()).unwrap();
+        let token = Token::new(Header::default(), claims)
+            .sign_with_key(&key)
+            .unwrap();
+        let mut req = Request::new(());
+        req.metadata_mut()
+            .insert("authorization", token.as_str().parse().unwrap());
+
+        req
+    }
+
+    #[test]
+    fn health_checks_are_public() {
+        let policies = AuthPolicies::default();
+
+        let policy = policies.policy("/matchmaking.MatchmakingService/Check");
+        assert_eq!(policy, AuthPolicy::Public);
+        assert!(policy.authorize(Request::new(()), &roles()).is_ok());
+        assert_eq!(
+            policies.policy("/matchmaking.v2.MatchmakingService/JoinQueue"),
+            AuthPolicy::Player
+        );
+        assert_eq!(
+            policies.policy("/matchmaking.MatchmakingService/ReloadConfig"),
+            AuthPolicy::Admin
+        );
+        assert_eq!(
+            policies.policy("/matchmaking.MatchmakingService/ReportAbandon"),
+            AuthPolicy::Server
+        );
+    }
+
+    #[test]
+    fn policies_check_the_session_role() {
+        let policies = AuthPolicies::default().with_roles(roles());
+        let authorize = |method: &str, user_id: Option<&str>| {
+            policies.authorize(
+                &format!("/matchmaking.MatchmakingService/{method}"),
+                session(user_id),
+            )
+        };
+
+        let player = authorize("JoinQueue", None).unwrap();
+        assert!(player.extensions().get::<UserId>().is_some());
+        assert!(
+            policies
+                .authorize(
+                    "/matchmaking.MatchmakingService/JoinQueue",
+                    Request::new(())
+                )
+                .is_err()
+        );
+
+        let denied = authorize("ReloadConfig", None).unwrap_err();
+        assert_eq!(denied.code(), tonic::Code::PermissionDenied);
+        assert!(authorize("ReloadConfig", Some(ADMIN_ID)).is_ok());
+
+        assert!(authorize("ReportAbandon", None).is_err());
+        assert!(authorize("ReportAbandon", Some(SERVER_ID)).is_ok());
+        assert!(authorize("ReportAbandon", Some(ADMIN_ID)).is_ok());
+        assert!(authorize("ReloadConfig", Some(SERVER_ID)).is_err());
+        // roles are not granted without the server config
+        assert!(
+            AuthPolicy::Admin
+                .authorize(session(Some(ADMIN_ID)), &Roles::default())
+                .is_err()
+        );
+    }
+
+    #[test]
+    fn overrides_replace_default_policies() {
+        let policies = AuthPolicies::default()
+            .with_overrides("ReportOpenSlots=player, GetTournament = Public,")
+            .unwrap();
+
+        assert_eq!(
+            policies.policy("/matchmaking.MatchmakingService/ReportOpenSlots"),
+            AuthPolicy::Player
+        );
+        assert_eq!(
+            policies.policy("/matchmaking.MatchmakingService/GetTournament"),
+            AuthPolicy::Public
+        );
+        assert!(matches!(
+            AuthPolicies::default().with_overrides("Check=nobody"),
+            Err(Error::UnknownPolicy(_))
+        ));
+        assert!(matches!(
+            AuthPolicies::default().with_overrides("Check"),
+            Err(Error::InvalidEntry(_))
+        ));
+    }
+}
@@ -0,0 +1,49 @@
+use tonic::{Request, Response, Status};
+
+use crate::{
+    preferences::{self, Preferences},
+    rpc::{
+        matchmaking::{PreferencesRequest, PreferencesResponse, SetPreferencesRequest},
+        server::{MatchmakingServer, auth::authorize_player},
+    },
+};
+
+impl MatchmakingServer {
+    pub(super) async fn preferences(
+        &self,
+        request: Request<PreferencesRequest>,
+    ) -> Result<Response<PreferencesResponse>, Status> {
+        let player_id = authorize_player(&request, &request.get_ref().player_id)?;
+
+        let preferences =
+            preferences::load(&self.nakama_client, self.http_client.clone(), &player_id).await?;
+
+        Ok(Response::new(PreferencesResponse {
+            preferences: Some(preferences.into()),
+        }))
+    }
+
+    pub(super) async fn save_preferences(
+        &self,
+        request: Request<SetPreferencesRequest>,
+    ) -> Result<Response<PreferencesResponse>, Status> {
+        let player_id = authorize_player(&request, &request.get_ref().player_id)?;
+        let preferences: Preferences = request.into_inner().preferences.unwrap_or_default().into();
+        let mut conn = self.redis.clone();
+
+        let config = crate::config::get_config(&mut conn).await?;
+        let served = crate::geoip::served_regions(&mut conn).await?;
+        preferences.validate(&served, config.max_difficulty)?;
+        preferences::save(
+            &self.nakama_client,
+            self.http_client.clone(),
+            &player_id,
+            &preferences,
+        )
+        .await?;
+
+        Ok(Response::new(PreferencesResponse {
+            preferences: Some(preferences.into()),
+        }))
+    }
+}
@@ -0,0 +1,78 @@
+use redis::{AsyncCommands, aio::MultiplexedConnection};
+use tonic::{Request, Response, Status};
+use uuid::Uuid;
+
+use crate::{
+    allocation::GameServer,
+    codec,
+    rpc::{
+        Match, active_match_key,
+        helper::IntoTonicError,
+        matchmaking::{RejoinMatchRequest, RejoinMatchResponse},
+        player_match_key,
+        server::{MatchmakingServer, auth::authorize_player},
+    },
+};
+
+impl From<&Match> for RejoinMatchResponse {
+    fn from(value: &Match) -> Self {
+        Self {
+            match_id: value.id.to_string(),
+            host_id: value.host_id.to_string(),
+            region: value.region.clone(),
+            player_ids: value
+                .players
+                .iter()
+                .map(|player| player.player_id.to_string())
+                .collect(),
+            nakama_match_id: value.nakama_match_id.clone().unwrap_or_default(),
+            game_server_address: value
+                .game_server
+                .as_ref()
+                .map(GameServer::address)
+                .unwrap_or_default(),
+        }
+    }
+}
+
+impl MatchmakingServer {
+    pub(super) async fn rejoin(
+        &self,
+        request: Request<RejoinMatchRequest>,
+    ) -> Result<Response<RejoinMatchResponse>, Status> {
+        let player_id = authorize_player(&request, &request.get_ref().player_id)?;
+        let mut conn = self.redis.clone();
+
+        match active_match(&mut conn, &player_id).await? {
+            Some(active) => Ok(Response::new((&active).into())),
+            None => Err(Status::not_found("player has no active match")),
+        }
+    }
+}
+
+/// Match of `player_id` that is not over yet
+pub(super) async fn active_match(
+    conn: &mut MultiplexedConnection,
+    player_id: &Uuid,
+) -> Result<Option<Match>, Status> {
+    let match_id: Option<Uuid> = conn
+        .get(player_match_key(player_id))
+        .await
+        .to_tonic_error("Failed to load active match", Box::new(Status::internal))?;
+    let Some(match_id) = match_id else {
+        return Ok(None);
+    };
+    let active: Option<Vec<u8>> = conn
+        .get(active_match_key(&match_id))
+        .await
+        .to_tonic_error("Failed to load active match", Box::new(Status::internal))?;
+    let Some(active) = active else {
+        return Ok(None);
+    };
+    let active: Match = codec::decode(&active)
+        .to_tonic_error("Failed to load active match", Box::new(Status::internal))?;
+
+    Ok(Some(active).filter(|active| {
+        !active.state().is_terminal() && active.players.iter().any(|p| p.player_id == *player_id)
+    }))
+}
@@ -0,0 +1,67 @@
+use tonic::{Request, Response, Status};
+use tracing::{error, info};
+
+use crate::{
+    reports::{self, REPORTS_COLLECTION, Report},
+    rpc::{
+        helper::parse_id,
+        matchmaking::{ReportPlayerRequest, ReportPlayerResponse},
+        server::{MatchmakingServer, auth::authorize_player},
+    },
+};
+
+impl MatchmakingServer {
+    pub(super) async fn report(
+        &self,
+        request: Request<ReportPlayerRequest>,
+    ) -> Result<Response<ReportPlayerResponse>, Status> {
+        let reporter_id = authorize_player(&request, &request.get_ref().player_id)?;
+        let reported_id = parse_id(&request.get_ref().reported_id)?;
+        let match_id = request
+            .get_ref()
+            .match_id
+            .as_deref()
+            .map(parse_id)
+            .transpose()?;
+        let reason = request.get_ref().reason();
+        let mut conn = self.redis.clone();
+
+        let match_id =
+            reports::shared_match(&mut conn, &reporter_id, &reported_id, match_id).await?;
+        let report = Report::new(
+            reporter_id,
+            reported_id,
+            reason,
+            match_id,
+            request.into_inner().comment,
+        )?;
+        reports::claim(&mut conn, &reporter_id, &reported_id, &match_id).await?;
+        if let Err(err) = self
+            .nakama_client
+            .write_storage(
+                self.http_client.clone(),
+                REPORTS_COLLECTION,
+                &report.id.to_string(),
+                &reported_id.to_string(),
+                &report,
+            )
+            .await
+        {
+            if let Err(err) =
+                reports::release(&mut conn, &reporter_id, &reported_id, &match_id).await
+            {
+                error!("Failed to release the report of `{reported_id}`: {err}");
+            }
+            return Err(reports::Error::from(err).into());
+        }
+        let reports = reports::increment(&mut conn, &reported_id, reason)
+            .await
+            .map_err(reports::Error::from)?;
+        info!("Player `{reported_id}` reported for `{}`", report.reason);
+
+        Ok(Response::new(ReportPlayerResponse {
+            report_id: report.id.to_string(),
+            reports,
+        }))
+    }
+}
@@ -0,0 +1,79 @@
+use tonic::{Request, Status, codegen::http};
+use uuid::Uuid;
+
+use super::auth::UserId;
+
+pub const REQUEST_ID_HEADER: &str = "x-request-id";
+/// Longer client ids are replaced, they would bloat every log line
+const MAX_LEN: usize = 128;
+
+/// Id of the request, traced through the server spans and the worker logs of the queue entry
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RequestId(pub String);
+
+impl RequestId {
+    /// The client `x-request-id`, or a generated one
+    pub fn from_headers(headers: &http::HeaderMap) -> Self {
+        let request_id = headers
+            .get(REQUEST_ID_HEADER)
+            .and_then(|id| id.to_str().ok())
+            .filter(|id| !id.is_empty() && id.len() <= MAX_LEN)
+            .map_or_else(|| Uuid::new_v4().to_string(), str::to_string);
+
+        Self(request_id)
+    }
+}
+
+/// Interceptor keeping the client `x-request-id`, or generating one
+pub fn assign(mut req: Request<()>) -> Result<Request<()>, Status> {
+    let request_id = RequestId::from_headers(req.metadata().as_ref());
+    req.extensions_mut().insert(request_id);
+
+    Ok(req)
+}
+
+pub(crate) fn request_id<T>(request: &Request<T>) -> &str {
+    request
+        .extensions()
+        .get::<RequestId>()
+        .map_or("", |id| id.0.as_str())
+}
+
+/// Player of the session, empty for unauthenticated requests
+pub(crate) fn session_player<T>(request: &Request<T>) -> &str {
+    request
+        .extensions()
+        .get::<UserId>()
+        .map_or("", |id| id.player_id.as_str())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn propagates_client_id() {
+        let mut req = Request::new(());
+        req.metadata_mut()
+            .insert(REQUEST_ID_HEADER, "client-id".parse().unwrap());
+
+        let req = assign(req).unwrap();
+
+        assert_eq!(request_id(&req), "client-id");
+    }
+
+    #[test]
+    fn generates_missing_id() {
+        let mut oversized = Request::new(());
+        oversized
+            .metadata_mut()
+            .insert(REQUEST_ID_HEADER, "x".repeat(MAX_LEN + 1).parse().unwrap());
+
+        let generated = assign(Request::new(())).unwrap();
+        let oversized = assign(oversized).unwrap();
+
+        assert!(Uuid::parse_str(request_id(&generated)).is_ok());
+        assert!(Uuid::parse_str(request_id(&oversized)).is_ok());
+        assert_eq!(request_id(&Request::new(())), "");
+    }
+}
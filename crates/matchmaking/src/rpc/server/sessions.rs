@@ -0,0 +1,56 @@
+use tonic::{Request, Response, Status};
+use tracing::info;
+
+use crate::{
+    audit::{self, Action, AuditEvent},
+    rpc::{
+        helper::{IntoTonicError, parse_id},
+        matchmaking::{RevokeSessionRequest, RevokeSessionResponse},
+        server::{
+            MatchmakingServer,
+            auth::{admin_actor, authorize_admin},
+        },
+    },
+    sessions,
+};
+
+impl MatchmakingServer {
+    pub(super) async fn revoke(
+        &self,
+        request: Request<RevokeSessionRequest>,
+    ) -> Result<Response<RevokeSessionResponse>, Status> {
+        authorize_admin(&request)?;
+        let actor = admin_actor(&request);
+        let request = request.into_inner();
+        let user_id = (!request.user_id.is_empty())
+            .then(|| parse_id(&request.user_id))
+            .transpose()?;
+        if request.token_ids.is_empty() && user_id.is_none() {
+            return Err(Status::invalid_argument("no session to revoke"));
+        }
+        let mut conn = self.redis.clone();
+
+        if !request.token_ids.is_empty() {
+            sessions::revoke_tokens(&mut conn, &request.token_ids)
+                .await
+                .to_tonic_error("Failed to revoke sessions", Box::new(Status::internal))?;
+        }
+        if let Some(user_id) = user_id {
+            sessions::revoke_user(&mut conn, &user_id)
+                .await
+                .to_tonic_error("Failed to revoke sessions", Box::new(Status::internal))?;
+            info!("Revoked sessions of user {user_id}");
+        }
+        // enforced right away on this instance, other instances pick it up on their next sync
+        sessions::sync(&mut conn)
+            .await
+            .to_tonic_error("Failed to sync revocations", Box::new(Status::internal))?;
+        let target = user_id.map_or_else(|| "sessions".to_string(), |user_id| user_id.to_string());
+        let revoked = AuditEvent::new(Action::Admin, actor, target, self.clock.time_since_epoch())
+            .with_players(user_id)
+            .with_reason("revoke_session");
+        audit::emit(&mut conn, &revoked).await;
+
+        Ok(Response::new(RevokeSessionResponse {}))
+    }
+}
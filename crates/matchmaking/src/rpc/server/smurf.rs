@@ -0,0 +1,160 @@
+use redis::{AsyncCommands, aio::MultiplexedConnection};
+use tonic::{Request, Response, Status};
+use tracing::{debug, error, warn};
+
+use crate::{
+    codec, environment,
+    lifecycle::MatchState,
+    ratings,
+    records::{self, MatchRecord, RatingChange},
+    rpc::{
+        Match, QueuedPlayer, active_match_key,
+        helper::parse_id,
+        matchmaking::{MatchStatsRequest, MatchStatsResponse},
+        server::{MATCH_LIFETIME, MatchmakingServer, auth::reporting_host},
+    },
+    smurf::{self, Error},
+    store,
+};
+
+impl MatchmakingServer {
+    pub(super) async fn match_stats(
+        &self,
+        request: Request<MatchStatsRequest>,
+    ) -> Result<Response<MatchStatsResponse>, Status> {
+        let host_id = reporting_host(&request, &request.get_ref().player_id)?;
+        let match_id = parse_id(&request.get_ref().match_id)?;
+        let mut conn = self.redis.clone();
+
+        let active: Option<Vec<u8>> = conn
+            .get(active_match_key(&match_id))
+            .await
+            .map_err(Error::from)?;
+        let mut active: Match =
+            codec::decode(&active.ok_or(Error::MatchNotFound(match_id))?).map_err(Error::from)?;
+        if let Some(host_id) = host_id.filter(|host_id| active.host_id != *host_id) {
+            return Err(Error::NotHost(host_id).into());
+        }
+        active.check_mission(&request.get_ref().mission)?;
+        let mut results = Vec::new();
+        for performance in &request.get_ref().performances {
+            let player_id = parse_id(&performance.player_id)?;
+            let player = active
+                .players
+                .iter()
+                .find(|p| p.player_id == player_id)
+                .ok_or(Error::NotInMatch(player_id))?;
+            if !(0. ..=1.).contains(&performance.performance) {
+                return Err(Error::InvalidPerformance(performance.performance).into());
+            }
+            // a host player reports the stats, it can not rate itself
+            if host_id == Some(player_id) {
+                continue;
+            }
+            results.push((player.clone(), performance.performance));
+        }
+        // stats are reported once, when the match completes
+        active.transition(MatchState::Completed, self.clock.time_since_epoch())?;
+        smurf::claim(&mut conn, &match_id).await?;
+        let completed = self
+            .complete_match(&mut conn, request.get_ref(), &active, results)
+            .await;
+        if completed.is_err() {
+            // the match did not complete, so the host can report it again
+            if let Err(err) = smurf::release(&mut conn, &match_id).await {
+                error!("Failed to release the stats report of match `{match_id}`: {err}");
+            }
+        }
+        let (changes, flagged_ids) = completed?;
+
+        // the result is already applied, a history outage must not make the host retry it
+        let record = MatchRecord::new(
+            &active,
+            &request.get_ref().mission,
+            request.get_ref().won,
+            self.clock.now(),
+        );
+        if let Err(err) = self.records.record(&record, &changes).await {
+            error!("Failed to record match `{match_id}`: {err}");
+        }
+        if let Some(leaderboard) = &self.leaderboard {
+            leaderboard.spawn_publish(
+                conn,
+                self.nakama_client.clone(),
+                self.http_client.clone(),
+                changes.iter().map(|change| change.player_id).collect(),
+            );
+        }
+
+        Ok(Response::new(MatchStatsResponse { flagged_ids }))
+    }
+
+    /// Records the stats and applies the ratings of a completed match, returns the rating
+    /// changes and the players flagged as smurfs
+    async fn complete_match(
+        &self,
+        conn: &mut MultiplexedConnection,
+        request: &MatchStatsRequest,
+        active: &Match,
+        results: Vec<(QueuedPlayer, f64)>,
+    ) -> Result<(Vec<RatingChange>, Vec<String>), Status> {
+        let match_id = active.id;
+        let team: Vec<_> = active.players.iter().map(|p| p.skillrating).collect();
+        let difficulty = active.difficulty;
+        let catalog = environment::get_environment(conn, &request.mission, difficulty).await?;
+        let environment = catalog.rating();
+        let mut flagged_ids = Vec::new();
+        for (player, share) in results {
+            let stats = smurf::record(
+                conn,
+                &player.player_id,
+                request.won,
+                smurf::expected_win(&player.skillrating, &environment),
+                smurf::performance_ratio(&player.skillrating, &team, share),
+            )
+            .await
+            .map_err(Error::from)?;
+            if stats.is_smurf() {
+                warn!("Player `{}` flagged as smurf: {stats:?}", player.player_id);
+                flagged_ids.push(player.player_id.to_string());
+            }
+        }
+
+        if let Some(tuned) =
+            environment::record_result(conn, &request.mission, difficulty, &team, request.won)
+                .await?
+        {
+            debug!(
+                "Environment of `{}` at difficulty {difficulty} rated from {} matches",
+                tuned.mission, tuned.matches
+            );
+        }
+
+        // ratings are absolute values, a report retried after the match failed to complete
+        // applies them again
+        let changes = records::rating_changes(active, &catalog.opponents(&team), request.won);
+        let mut pipe = redis::pipe();
+        pipe.atomic();
+        ratings::apply(&mut pipe, &changes);
+        pipe.query_async(conn)
+            .await
+            .map(|_: ()| ())
+            .map_err(Error::from)?;
+
+        let mut pipe = redis::pipe();
+        pipe.atomic();
+        pipe.set_ex(
+            active_match_key(&match_id),
+            codec::encode(active),
+            *MATCH_LIFETIME,
+        )
+        .ignore();
+        store::index_match(&mut pipe, active);
+        pipe.query_async(conn)
+            .await
+            .map(|_: ()| ())
+            .map_err(Error::from)?;
+
+        Ok((changes, flagged_ids))
+    }
+}
@@ -0,0 +1,178 @@
+use redis::AsyncCommands;
+use tonic::{Request, Response, Status};
+use tracing::{error, info, warn};
+
+use crate::{
+    audit::{self, Action, AuditEvent},
+    codec, party,
+    rpc::{
+        Match, active_match_key,
+        helper::parse_id,
+        matchmaking::{
+            CreateTournamentRequest, MatchResultRequest, RegisterTournamentRequest,
+            TournamentRequest, TournamentResponse,
+        },
+        server::{
+            MatchmakingServer,
+            auth::{admin_actor, authorize_admin, authorize_player, reporting_host},
+        },
+    },
+    tournament::{self, Error, Tournament},
+};
+
+impl MatchmakingServer {
+    pub(super) async fn create_bracket(
+        &self,
+        request: Request<CreateTournamentRequest>,
+    ) -> Result<Response<TournamentResponse>, Status> {
+        authorize_admin(&request)?;
+        let actor = admin_actor(&request);
+        let format = request.get_ref().format();
+        let request = request.into_inner();
+        let mut conn = self.redis.clone();
+
+        let tournament = Tournament::new(request.name, request.region, request.difficulty, format)
+            .with_nakama_id(request.nakama_tournament_id)
+            .with_registration_window(self.clock.time_since_epoch(), request.registration_secs)?;
+        if let Some(nakama_tournament) = tournament.nakama_tournament() {
+            self.nakama_client
+                .create_tournament(self.http_client.clone(), &nakama_tournament)
+                .await
+                .map_err(Error::from)?;
+        }
+        tournament::save_tournament(&mut conn, &tournament).await?;
+        info!("Tournament `{}` created", tournament.id);
+        let created = AuditEvent::new(
+            Action::Admin,
+            actor,
+            tournament.id,
+            self.clock.time_since_epoch(),
+        )
+        .with_reason("create_tournament");
+        audit::emit(&mut conn, &created).await;
+
+        Ok(Response::new((&tournament).into()))
+    }
+
+    pub(super) async fn register_bracket_party(
+        &self,
+        request: Request<RegisterTournamentRequest>,
+    ) -> Result<Response<TournamentResponse>, Status> {
+        let player_id = authorize_player(&request, &request.get_ref().player_id)?;
+        let tournament_id = parse_id(&request.get_ref().tournament_id)?;
+        let party_id = parse_id(&request.get_ref().party_id)?;
+        let mut conn = self.redis.clone();
+
+        let party = party::get_party(&mut conn, &party_id)
+            .await?
+            .ok_or(party::Error::NotFound(party_id))?;
+        if party.host_id != player_id {
+            return Err(party::Error::NotHost(player_id).into());
+        }
+        let tournament = tournament::get_tournament(&mut conn, &tournament_id).await?;
+        // checked before joining the Nakama tournament, and again when saved
+        tournament.clone().register(party.id, party.members.len())?;
+        if let Some(join) = tournament.nakama_join(&party.members) {
+            self.nakama_client
+                .join_tournament(self.http_client.clone(), &join)
+                .await
+                .map_err(Error::from)?;
+        }
+        let (tournament, ()) =
+            tournament::update_tournament(&mut conn, &tournament_id, |tournament| {
+                tournament.register(party.id, party.members.len())
+            })
+            .await?;
+
+        Ok(Response::new((&tournament).into()))
+    }
+
+    pub(super) async fn start_bracket(
+        &self,
+        request: Request<TournamentRequest>,
+    ) -> Result<Response<TournamentResponse>, Status> {
+        authorize_admin(&request)?;
+        let actor = admin_actor(&request);
+        let tournament_id = parse_id(&request.get_ref().tournament_id)?;
+        let mut conn = self.redis.clone();
+
+        let (tournament, ()) =
+            tournament::update_tournament(&mut conn, &tournament_id, Tournament::start).await?;
+        info!("Tournament `{tournament_id}` started");
+        let started = AuditEvent::new(
+            Action::Admin,
+            actor,
+            tournament_id,
+            self.clock.time_since_epoch(),
+        )
+        .with_reason("start_tournament");
+        audit::emit(&mut conn, &started).await;
+
+        Ok(Response::new((&tournament).into()))
+    }
+
+    pub(super) async fn bracket(
+        &self,
+        request: Request<TournamentRequest>,
+    ) -> Result<Response<TournamentResponse>, Status> {
+        let tournament_id = parse_id(&request.get_ref().tournament_id)?;
+        let mut conn = self.redis.clone();
+
+        let tournament = tournament::get_tournament(&mut conn, &tournament_id).await?;
+
+        Ok(Response::new((&tournament).into()))
+    }
+
+    pub(super) async fn match_result(
+        &self,
+        request: Request<MatchResultRequest>,
+    ) -> Result<Response<TournamentResponse>, Status> {
+        let host_id = reporting_host(&request, &request.get_ref().player_id)?;
+        let match_id = parse_id(&request.get_ref().match_id)?;
+        let winner_id = parse_id(&request.get_ref().winner_party_id)?;
+        let mut conn = self.redis.clone();
+
+        let (tournament_id, index) = tournament::match_bracket(&mut conn, &match_id).await?;
+        if let Some(host_id) = host_id {
+            let active: Option<Vec<u8>> = conn
+                .get(active_match_key(&match_id))
+                .await
+                .map_err(Error::from)?;
+            let active: Match = codec::decode(&active.ok_or(Error::NotTournamentMatch(match_id))?)
+                .map_err(Error::from)?;
+            if active.host_id != host_id {
+                return Err(Error::NotHost(host_id).into());
+            }
+        }
+        let (tournament, ()) =
+            tournament::update_tournament(&mut conn, &tournament_id, |tournament| {
+                tournament.report(index, winner_id)
+            })
+            .await?;
+        info!("Tournament `{tournament_id}` match {index} won by party `{winner_id}`");
+
+        // the bracket already advanced, a Nakama outage only loses the score
+        if tournament.nakama_id.is_some() {
+            match party::get_party(&mut conn, &winner_id).await {
+                Ok(Some(winner)) => {
+                    for record in tournament.nakama_win_records(&winner.members) {
+                        if let Err(err) = self
+                            .nakama_client
+                            .write_tournament_record(self.http_client.clone(), &record)
+                            .await
+                        {
+                            error!(
+                                "Failed to score `{}` in tournament `{}`: {err}",
+                                record.owner_id, record.tournament_id
+                            );
+                        }
+                    }
+                }
+                Ok(None) => warn!("party `{winner_id}` left before its win was scored"),
+                Err(err) => error!("Failed to load party `{winner_id}`: {err}"),
+            }
+        }
+
+        Ok(Response::new((&tournament).into()))
+    }
+}
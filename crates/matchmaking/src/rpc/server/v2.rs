@@ -0,0 +1,232 @@
+//! Second version of the player facing API. Requests are converted to the first version and
+//! served by the same handlers, so both versions queue players together while clients migrate.
+
+use std::pin::Pin;
+
+use tokio_stream::{Stream, StreamExt};
+use tonic::{Code, Request, Response, Status};
+use tonic_types::{ErrorDetails, StatusExt};
+
+use crate::rpc::{
+    matchmaking::{
+        self, InputDevice, WatchQueueRequest, matchmaking_service_server,
+        queue_event::Event,
+        v2::{self, Platform, matchmaking_service_server::MatchmakingService},
+    },
+    server::MatchmakingServer,
+};
+
+pub(crate) type QueueEventStream =
+    Pin<Box<dyn Stream<Item = Result<v2::QueueEvent, Status>> + Send>>;
+
+fn invalid_argument(field: &str, description: &str) -> Status {
+    Status::with_error_details(
+        Code::InvalidArgument,
+        description,
+        ErrorDetails::with_bad_request_violation(field, description),
+    )
+}
+
+/// JSON loadout config of the first version, see [`crate::progression::Loadout`]
+fn loadout_config(loadout: Option<v2::Loadout>) -> String {
+    match loadout {
+        Some(loadout) if !loadout.item_ids.is_empty() || !loadout.skill_ids.is_empty() => {
+            serde_json::json!({ "items": loadout.item_ids, "skills": loadout.skill_ids })
+                .to_string()
+        }
+        _ => String::new(),
+    }
+}
+
+impl TryFrom<v2::Player> for matchmaking::Player {
+    type Error = Status;
+
+    fn try_from(value: v2::Player) -> Result<Self, Self::Error> {
+        let console = matches!(
+            value.platform(),
+            Platform::Playstation | Platform::Xbox | Platform::Switch
+        );
+        let input_device = match value.input_device() {
+            InputDevice::AnyInput if console => InputDevice::Controller,
+            input_device => input_device,
+        };
+        let region = if value.region.is_empty() {
+            value
+                .region_pings
+                .iter()
+                .min_by_key(|(region, ping)| (**ping, (*region).clone()))
+                .map(|(region, _)| region.clone())
+                .ok_or_else(|| invalid_argument("region", "region or region pings are required"))?
+        } else {
+            value.region
+        };
+        let ping = value.region_pings.get(&region).copied().unwrap_or_default();
+
+        Ok(Self {
+            player_id: value.player_id,
+            loadout_config: loadout_config(value.loadout),
+            region,
+            ping,
+            difficulty: value.difficulty,
+            join_mode: value.join_mode,
+            party_mode: value.party_mode,
+            party_member_id: value.party_member_ids,
+            playlist: value.playlist,
+            mission_types: value.mission_types,
+            maps: value.maps,
+            input_device: input_device.into(),
+            queue_type: value.queue_type,
+            match_settings: value.match_settings,
+            clan_id: value.clan_id,
+            languages: value.languages,
+            voice_chat: value.voice_chat,
+            datacenter_pings: value.datacenter_pings,
+            adjacent_difficulty: value.adjacent_difficulty,
+        })
+    }
+}
+
+impl From<matchmaking::MatchFound> for v2::MatchFound {
+    fn from(value: matchmaking::MatchFound) -> Self {
+        let host_address = value
+            .game_server_address
+            .rsplit_once(':')
+            .and_then(|(host, port)| {
+                Some(v2::HostAddress {
+                    host: host.to_string(),
+                    port: port.parse().ok()?,
+                })
+            });
+
+        Self {
+            match_id: value.match_id,
+            host_id: value.host_id,
+            region: value.region,
+            backfill: value.backfill,
+            nakama_match_id: value.nakama_match_id,
+            host_address,
+            win_probability: value.win_probability,
+            environment: value.environment,
+        }
+    }
+}
+
+impl From<matchmaking::QueueEvent> for v2::QueueEvent {
+    fn from(value: matchmaking::QueueEvent) -> Self {
+        use v2::queue_event::Event as V2Event;
+
+        Self {
+            event: value.event.map(|event| match event {
+                Event::PartyHostChanged(changed) => V2Event::PartyHostChanged(changed),
+                Event::PartyDisbanded(disbanded) => V2Event::PartyDisbanded(disbanded),
+                Event::MatchFound(found) => V2Event::MatchFound(found.into()),
+                Event::QueuePosition(position) => V2Event::QueuePosition(position),
+                Event::MatchFailed(failed) => V2Event::MatchFailed(failed),
+                Event::QueueTimeout(timeout) => V2Event::QueueTimeout(timeout),
+                Event::KickedFromLobby(kicked) => V2Event::KickedFromLobby(kicked),
+                Event::ReadyCheck(check) => V2Event::ReadyCheck(check),
+            }),
+        }
+    }
+}
+
+#[tonic::async_trait]
+impl MatchmakingService for MatchmakingServer {
+    type WatchQueueStream = QueueEventStream;
+
+    async fn join_queue(
+        &self,
+        request: Request<v2::Player>,
+    ) -> Result<Response<v2::JoinQueueResponse>, Status> {
+        let (metadata, extensions, player) = request.into_parts();
+        let player = matchmaking::Player::try_from(player)?;
+
+        let joined = matchmaking_service_server::MatchmakingService::join_queue(
+            self,
+            Request::from_parts(metadata, extensions, player),
+        )
+        .await?
+        .into_inner();
+
+        Ok(Response::new(v2::JoinQueueResponse {
+            player_id: joined.player_id,
+            region: joined.region,
+            status: joined.status,
+            detail: joined.detail,
+        }))
+    }
+
+    async fn watch_queue(
+        &self,
+        request: Request<WatchQueueRequest>,
+    ) -> Result<Response<Self::WatchQueueStream>, Status> {
+        let events = self.watch_events(request).await?.into_inner();
+
+        Ok(Response::new(
+            Box::pin(events.map(|event| event.map(v2::QueueEvent::from))) as QueueEventStream,
+        ))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use super::*;
+
+    #[test]
+    fn lowest_ping_region_is_picked() {
+        let player = matchmaking::Player::try_from(v2::Player {
+            player_id: "player".to_string(),
+            region_pings: HashMap::from([("EU".to_string(), 80), ("US".to_string(), 35)]),
+            platform: Platform::Xbox.into(),
+            loadout: Some(v2::Loadout {
+                item_ids: vec!["item".to_string()],
+                skill_ids: Vec::new(),
+            }),
+            ..Default::default()
+        })
+        .unwrap();
+
+        assert_eq!(player.region, "US");
+        assert_eq!(player.ping, 35);
+        assert_eq!(player.input_device(), InputDevice::Controller);
+        assert_eq!(player.loadout_config, r#"{"items":["item"],"skills":[]}"#);
+    }
+
+    #[test]
+    fn preferred_region_keeps_its_ping() {
+        let player = matchmaking::Player::try_from(v2::Player {
+            region: "EU".to_string(),
+            region_pings: HashMap::from([("EU".to_string(), 80), ("US".to_string(), 35)]),
+            platform: Platform::Pc.into(),
+            ..Default::default()
+        })
+        .unwrap();
+
+        assert_eq!(player.region, "EU");
+        assert_eq!(player.ping, 80);
+        assert_eq!(player.input_device(), InputDevice::AnyInput);
+        assert!(player.loadout_config.is_empty());
+        assert!(matchmaking::Player::try_from(v2::Player::default()).is_err());
+    }
+
+    #[test]
+    fn game_server_address_is_split() {
+        let found: v2::MatchFound = matchmaking::MatchFound {
+            game_server_address: "10.0.0.7:7654".to_string(),
+            ..Default::default()
+        }
+        .into();
+        let hosted: v2::MatchFound = matchmaking::MatchFound::default().into();
+
+        assert_eq!(
+            found.host_address,
+            Some(v2::HostAddress {
+                host: "10.0.0.7".to_string(),
+                port: 7654,
+            })
+        );
+        assert_eq!(hosted.host_address, None);
+    }
+}
@@ -0,0 +1,49 @@
+use redis::{AsyncCommands, RedisError};
+use tracing::debug;
+
+use crate::{
+    analytics::{self, TickStats},
+    codec,
+    rpc::{
+        QueuedPlayer,
+        worker::{MatchmakingWorker, cleanup::player_queue_keys},
+    },
+};
+
+#[derive(Debug, thiserror::Error)]
+pub enum Error {
+    #[error(transparent)]
+    Redis(#[from] RedisError),
+    #[error(transparent)]
+    Analytics(#[from] analytics::Error),
+}
+
+impl MatchmakingWorker {
+    /// Records the statistics of the queues, reading at most `scan_budget` entries per queue.
+    /// `matches_formed` are the matches started this tick.
+    pub async fn aggregate_analytics(&mut self, matches_formed: usize) -> Result<TickStats, Error> {
+        let mut conn = self.redis.clone();
+        let now = self.clock.time_since_epoch();
+
+        let mut players = Vec::new();
+        for key in player_queue_keys(&mut conn).await? {
+            let entries: Vec<Vec<u8>> = conn
+                .zrange(&key, 0, self.config.scan_budget as isize - 1)
+                .await?;
+            players.extend(
+                entries
+                    .iter()
+                    .filter_map(|entry| codec::decode::<QueuedPlayer>(entry).ok()),
+            );
+        }
+        let stats = analytics::aggregate(&players, matches_formed as u64, now);
+        analytics::record(&mut conn, &stats).await?;
+        debug!(
+            "{} players queued in {} queues, {matches_formed} matches formed",
+            players.len(),
+            stats.queues.len()
+        );
+
+        Ok(stats)
+    }
+}
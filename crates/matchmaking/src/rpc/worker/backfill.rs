@@ -0,0 +1,515 @@
+use std::{collections::HashSet, sync::LazyLock};
+
+use redis::{AsyncCommands, RedisError, Script, aio::MultiplexedConnection};
+use tracing::{error, info};
+use uuid::Uuid;
+
+use crate::{
+    aging::Aging,
+    allocation::GameServer,
+    claim, clans, codec, friends,
+    metrics::worker::{RunMetrics, SkipReason},
+    notifications::{self, Notification},
+    playlists,
+    regions::{regions_key, tuning::RegionTuning},
+    rpc::{
+        Match, QueuedPlayer, active_match_key, backfill_queue_key, backfill_slots_key,
+        matchmaking::PartyMode,
+        player_key, player_match_key, player_queue_key, region_queue_key,
+        server::MATCH_LIFETIME,
+        worker::{MatchmakingWorker, can_match::wait_priority, scan::scan},
+    },
+    starvation::Starvation,
+    store::{self, DATA},
+};
+
+/// Adds a group to an active match (`KEYS[1]`) while it still exists and stays within
+/// `MAX_PLAYERS`. Removing the entry of the candidate from its queue (`KEYS[2]`) claims it, then
+/// the match is written and every member, by the triples of its hash, queue and match key, is
+/// dequeued and pointed to the match. 0 when the group was not added.
+static BACKFILL: LazyLock<Script> = LazyLock::new(|| {
+    Script::new(
+        r"
+        if redis.call('EXISTS', KEYS[1]) == 0 or tonumber(ARGV[6]) > tonumber(ARGV[7]) then
+            return 0
+        end
+        if redis.call('ZREM', KEYS[2], ARGV[3]) == 0 then
+            return 0
+        end
+        redis.call('SET', KEYS[1], ARGV[1], 'EX', ARGV[2])
+        for i = 3, #KEYS, 3 do
+            local data = redis.call('HGET', KEYS[i], ARGV[5])
+            if data then
+                redis.call('ZREM', KEYS[i + 1], data)
+            end
+            redis.call('SET', KEYS[i + 2], ARGV[4], 'EX', ARGV[2])
+        end
+        return 1
+        ",
+    )
+});
+
+#[derive(Debug, thiserror::Error)]
+pub enum Error {
+    #[error(transparent)]
+    Redis(#[from] RedisError),
+    #[error(transparent)]
+    BitcodeDeser(#[from] codec::Error),
+    #[error(transparent)]
+    Playlists(#[from] playlists::Error),
+}
+
+impl MatchmakingWorker {
+    /// Fills open slots reported by running matches with compatible solo players.
+    /// Returns how many players were backfilled.
+    pub async fn backfill_matches(&mut self) -> Result<usize, Error> {
+        let mut conn = self.redis.clone();
+        let Some(regions): Option<Vec<u8>> = conn.get(regions_key()).await? else {
+            error!("No regions registred");
+            return Ok(0);
+        };
+        let regions: Vec<String> = codec::decode(regions.as_slice())?;
+        let clan_playlists = playlists::clan_playlists(&mut conn).await?;
+
+        let mut filled = 0;
+        for region in &regions {
+            let tuning = self.region_tunings.get(region);
+            if !tuning.is_due(self.runs) {
+                continue;
+            }
+            let queue_key = backfill_queue_key(region);
+            let match_ids: Vec<Uuid> = conn.zrange(&queue_key, 0, -1).await?;
+            for match_id in match_ids {
+                // the matches left are backfilled next tick
+                if self.budget.is_spent() {
+                    break;
+                }
+                if !conn.exists(active_match_key(&match_id)).await? {
+                    close_backfill(&mut conn, &queue_key, &match_id).await?;
+                    continue;
+                }
+                let Some((claim, active)) = claim::acquire_active(&mut conn, &match_id).await?
+                else {
+                    info!("match `{match_id}` is claimed, backfilling later");
+                    continue;
+                };
+                if active.state().is_terminal() {
+                    claim::release(&mut conn, claim).await?;
+                    close_backfill(&mut conn, &queue_key, &match_id).await?;
+                    continue;
+                }
+                let backfilled = self
+                    .backfill_match(&mut conn, active, &queue_key, &tuning, &clan_playlists)
+                    .await;
+                claim::release(&mut conn, claim).await?;
+                filled += backfilled?;
+            }
+        }
+
+        Ok(filled)
+    }
+
+    /// Fills the open slots of `active`, claimed by the caller, returns how many players were
+    /// backfilled
+    async fn backfill_match(
+        &mut self,
+        conn: &mut MultiplexedConnection,
+        mut active: Match,
+        queue_key: &str,
+        tuning: &RegionTuning,
+        clan_playlists: &[String],
+    ) -> Result<usize, Error> {
+        let match_id = active.id;
+        let mut filled = 0;
+        let slots: Option<u32> = conn.get(backfill_slots_key(&match_id)).await?;
+        let mut slots = slots.unwrap_or_default();
+
+        let queue_region = active.queue_region();
+        let mut candidates = Vec::new();
+        for band in active.skill_bands() {
+            for party_mode in [PartyMode::Solo, PartyMode::Party] {
+                let key = region_queue_key(party_mode.into(), &queue_region, band);
+                // the longest waiting entries, they have the highest priority
+                let scanned = scan(
+                    conn,
+                    &key,
+                    None,
+                    self.config.scan_batch_size,
+                    self.config.scan_budget,
+                )
+                .await?;
+                self.budget.spend_commands(scanned.commands);
+                // Party members are pulled along with their host
+                candidates.extend(scanned.entries.into_iter().filter_map(|player_bits| {
+                    let player = self.metrics.decode::<QueuedPlayer>(&player_bits)?;
+                    (!player.is_versus()
+                        && (party_mode == PartyMode::Solo || !player.party_ids.is_empty()))
+                    .then_some((player, player_bits))
+                }));
+            }
+        }
+        let now = self.clock.time_since_epoch();
+        let aging = tuning.aging();
+        candidates.sort_by(|(a, _), (b, _)| {
+            wait_priority(b, aging.aged_now(b, now))
+                .total_cmp(&wait_priority(a, aging.aged_now(a, now)))
+        });
+        let clan = clans::preferred_clan(&active, clan_playlists);
+        clans::members_first(&mut candidates, clan, |(player, _)| player);
+        let friends = friends::reserved_for(
+            conn,
+            &self.nakama_client,
+            self.http_client.clone(),
+            &active,
+            &self.config,
+            now,
+        )
+        .await
+        .unwrap_or_else(|err| {
+            error!("failed to load the friends of `{}`: {err}", active.host_id);
+            Some(HashSet::new())
+        });
+
+        for (player, encoded) in candidates {
+            if slots == 0 {
+                break;
+            }
+            let waited = now + tuning.backfill_head_start_secs;
+            let Some(group) = backfill_group(
+                conn,
+                &active,
+                player,
+                waited,
+                aging,
+                &self.starved,
+                &mut self.metrics,
+            )
+            .await?
+            else {
+                continue;
+            };
+            if let Some(friends) = &friends
+                && !group
+                    .iter()
+                    .all(|member| friends.contains(&member.player_id))
+            {
+                self.metrics
+                    .skip(SkipReason::FriendsOnly, group.len() as u64);
+                continue;
+            }
+            if group.len() > slots as usize {
+                self.metrics.skip(SkipReason::PartySize, group.len() as u64);
+                continue;
+            }
+            let player_ids: Vec<Uuid> = group.iter().map(|p| p.player_id).collect();
+            let mut backfilled = active.clone();
+            backfilled.players.extend(group.iter().cloned());
+            if !add_group(conn, &backfilled, &group, &encoded).await? {
+                continue;
+            }
+            let mut pipe = redis::pipe();
+            store::index_match(&mut pipe, &backfilled);
+            pipe.query_async(conn).await.map(|_: ()| ())?;
+            self.budget.spend_commands(pipe.len() + 1);
+            let notification = Notification::MatchFound {
+                match_id,
+                host_id: active.host_id,
+                region: active.region.clone(),
+                nakama_match_id: active.nakama_match_id.clone().unwrap_or_default(),
+                game_server_address: active
+                    .game_server
+                    .as_ref()
+                    .map(GameServer::address)
+                    .unwrap_or_default(),
+                backfill: true,
+                challenge: active.challenge,
+            };
+            notifications::notify(conn, &player_ids, &notification).await?;
+            group[0].span().in_scope(|| {
+                info!("players {player_ids:?} backfilled into match `{match_id}`");
+            });
+            slots -= group.len() as u32;
+            filled += group.len();
+            active = backfilled;
+        }
+
+        if slots == 0 {
+            close_backfill(conn, queue_key, &match_id).await?;
+        } else {
+            conn.set_ex(backfill_slots_key(&match_id), slots, *MATCH_LIFETIME)
+                .await
+                .map(|_: ()| ())?;
+        }
+
+        Ok(filled)
+    }
+}
+
+/// Writes `backfilled` with the players of `group` dequeued, see [`BACKFILL`]. `false` when the
+/// match expired, would exceed `MAX_PLAYERS` or the candidate was already matched
+async fn add_group(
+    conn: &mut MultiplexedConnection,
+    backfilled: &Match,
+    group: &[QueuedPlayer],
+    encoded: &[u8],
+) -> Result<bool, RedisError> {
+    let mut invocation = BACKFILL.prepare_invoke();
+    invocation
+        .key(active_match_key(&backfilled.id))
+        .key(player_queue_key(&group[0]));
+    for member in group {
+        invocation
+            .key(player_key(&member.player_id))
+            .key(player_queue_key(member))
+            .key(player_match_key(&member.player_id));
+    }
+    invocation
+        .arg(codec::encode(backfilled))
+        .arg(*MATCH_LIFETIME)
+        .arg(encoded)
+        .arg(backfilled.id)
+        .arg(DATA)
+        .arg(backfilled.players.len())
+        .arg(Match::MAX_PLAYERS);
+
+    invocation.invoke_async(conn).await
+}
+
+/// Candidate and its queued party members, `None` when any of them does not fit
+async fn backfill_group(
+    conn: &mut redis::aio::MultiplexedConnection,
+    active: &Match,
+    player: QueuedPlayer,
+    now: i64,
+    aging: Aging,
+    starved: &Starvation,
+    metrics: &mut RunMetrics,
+) -> Result<Option<Vec<QueuedPlayer>>, Error> {
+    let party_ids = player.party_ids.clone();
+    let mut group = vec![player];
+    for member_id in &party_ids {
+        let Ok(member_id) = Uuid::parse_str(member_id) else {
+            return Ok(None);
+        };
+        let Some(data) = store::player_data(conn, &member_id).await? else {
+            return Ok(None);
+        };
+        group.push(codec::decode(&data)?);
+    }
+    for member in &group {
+        if let Some(reason) = active.backfill_misfit(member, aging.aged_now(member, now), starved) {
+            metrics.skip(reason, group.len() as u64);
+            return Ok(None);
+        }
+        if active
+            .players
+            .iter()
+            .any(|p| p.player_id == member.player_id)
+            || !conn.exists(player_key(&member.player_id)).await?
+        {
+            return Ok(None);
+        }
+    }
+
+    Ok(Some(group))
+}
+
+async fn close_backfill(
+    conn: &mut redis::aio::MultiplexedConnection,
+    queue_key: &str,
+    match_id: &Uuid,
+) -> Result<(), RedisError> {
+    redis::pipe()
+        .zrem(queue_key, match_id)
+        .ignore()
+        .del(backfill_slots_key(match_id))
+        .ignore()
+        .query_async(conn)
+        .await
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use redis::aio::MultiplexedConnection;
+    use skillratings::mhth::MhthRating;
+
+    use super::*;
+    use crate::{
+        clock::SystemClock,
+        nakama::{Authenticated, NakamaClient},
+        rpc::matchmaking::Player,
+        test_support::{create_redis, redis_client},
+    };
+
+    #[tokio::test]
+    async fn backfill_open_slot() {
+        let host: QueuedPlayer = (
+            Uuid::new_v4(),
+            Player {
+                join_mode: 0,
+                region: "CAN".to_string(),
+                ..Default::default()
+            },
+            MhthRating::default(),
+        )
+            .into();
+        let queued_ids = [Uuid::new_v4(), Uuid::new_v4()];
+        let container = create_redis(6379).await;
+        let redis_host = container.get_host().await.unwrap();
+        let port = container.get_host_port_ipv4(6379).await.unwrap();
+        let client = redis_client(redis_host.to_string(), port);
+        let mut conn = client.get_multiplexed_async_connection().await.unwrap();
+        init_regions(conn.clone()).await;
+
+        let running = Match::host(&host, &[]).unwrap();
+        conn.set(active_match_key(&running.id), codec::encode(&running))
+            .await
+            .map(|_: ()| ())
+            .unwrap();
+        conn.set(backfill_slots_key(&running.id), 1)
+            .await
+            .map(|_: ()| ())
+            .unwrap();
+        conn.zadd(backfill_queue_key(&host.region), running.id, 1)
+            .await
+            .map(|_: ()| ())
+            .unwrap();
+        for (score, id) in queued_ids.iter().enumerate() {
+            let player: QueuedPlayer = (
+                *id,
+                Player {
+                    join_mode: 1,
+                    region: "CAN".to_string(),
+                    ..Default::default()
+                },
+                MhthRating::default(),
+            )
+                .into();
+            let encoded = codec::encode(&player);
+            let mut pipe = redis::pipe();
+            store::put_player(&mut pipe, &player, &encoded, 200);
+            pipe.query_async(&mut conn).await.map(|_: ()| ()).unwrap();
+            conn.zadd(player_queue_key(&player), &encoded, score)
+                .await
+                .map(|_: ()| ())
+                .unwrap();
+        }
+
+        let mut worker = MatchmakingWorker::new(
+            conn.clone(),
+            Arc::new(reqwest::Client::new()),
+            auth_client(666).into(),
+            Arc::new(SystemClock::default()),
+        );
+        let filled = worker.backfill_matches().await.unwrap();
+
+        let active: Vec<u8> = conn.get(active_match_key(&running.id)).await.unwrap();
+        let backfills: Vec<Uuid> = conn
+            .zrange(backfill_queue_key(&host.region), 0, -1)
+            .await
+            .unwrap();
+        let events = notifications::drain(&mut conn, &queued_ids[0])
+            .await
+            .unwrap();
+
+        assert_eq!(filled, 1);
+        assert!(backfills.is_empty());
+        let active: Match = codec::decode(&active).unwrap();
+        assert_eq!(active.players.len(), 2);
+        assert_eq!(active.players[1].player_id, queued_ids[0]);
+        assert_eq!(
+            events,
+            vec![Notification::MatchFound {
+                match_id: running.id,
+                host_id: host.player_id,
+                region: "CAN".to_string(),
+                nakama_match_id: String::new(),
+                game_server_address: String::new(),
+                backfill: true,
+                challenge: None,
+            }]
+        );
+    }
+
+    #[tokio::test]
+    async fn full_matches_are_not_backfilled() {
+        let queued = |join_mode| -> QueuedPlayer {
+            (
+                Uuid::new_v4(),
+                Player {
+                    join_mode,
+                    region: "CAN".to_string(),
+                    ..Default::default()
+                },
+                MhthRating::default(),
+            )
+                .into()
+        };
+        let container = create_redis(6379).await;
+        let redis_host = container.get_host().await.unwrap();
+        let port = container.get_host_port_ipv4(6379).await.unwrap();
+        let client = redis_client(redis_host.to_string(), port);
+        let mut conn = client.get_multiplexed_async_connection().await.unwrap();
+
+        let mut running = Match::host(&queued(0), &[]).unwrap();
+        while running.players.len() < Match::MAX_PLAYERS {
+            running.players.push(queued(1));
+        }
+        conn.set(active_match_key(&running.id), codec::encode(&running))
+            .await
+            .map(|_: ()| ())
+            .unwrap();
+        let candidate = queued(1);
+        let encoded = codec::encode(&candidate);
+        conn.zadd(player_queue_key(&candidate), &encoded, 1)
+            .await
+            .map(|_: ()| ())
+            .unwrap();
+        let mut backfilled = running.clone();
+        backfilled.players.push(candidate.clone());
+
+        let added = add_group(
+            &mut conn,
+            &backfilled,
+            std::slice::from_ref(&candidate),
+            &encoded,
+        )
+        .await
+        .unwrap();
+        let queue: Vec<Vec<u8>> = conn
+            .zrange(player_queue_key(&candidate), 0, -1)
+            .await
+            .unwrap();
+        let active: Vec<u8> = conn.get(active_match_key(&running.id)).await.unwrap();
+
+        assert!(!added);
+        assert_eq!(queue, vec![encoded]);
+        assert_eq!(codec::decode::<Match>(&active).unwrap(), running);
+    }
+
+    async fn init_regions(conn: MultiplexedConnection) {
+        let regions = &[
+            "CAN".to_string(),
+            "US".to_string(),
+            "SOUTH_AMERICA".to_string(),
+        ];
+
+        crate::regions::set_regions(conn, regions).await.unwrap();
+    }
+
+    pub fn auth_client(port: u16) -> NakamaClient<Authenticated> {
+        NakamaClient {
+            username: "username".to_string(),
+            password: "password".to_string(),
+            token: Some("super_random_token".to_string()),
+            url: format!("http://127.0.0.1:{port}"),
+            server_key_name: "defaultkey".to_string(),
+            server_key_value: "server_key".to_string(),
+            encryption_key: "encryption_key".to_string(),
+            legacy_until: None,
+            _state: std::marker::PhantomData::<Authenticated>,
+        }
+    }
+}
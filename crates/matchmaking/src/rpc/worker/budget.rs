@@ -0,0 +1,91 @@
+//! Budget of a worker tick. A tick forms at most [`MatchmakingConfig::tick_match_budget`]
+//! matches and issues about [`MatchmakingConfig::tick_command_budget`] Redis commands reading
+//! queues and writing matches, so a queue spike cannot stretch a tick over the next interval.
+//! Once the budget is spent the phases forming matches stop where they are and the rest carries
+//! over: the queues keep their entries and the scans resume at the entries left unread.
+
+use std::sync::{
+    Arc,
+    atomic::{AtomicUsize, Ordering},
+};
+
+use crate::config::MatchmakingConfig;
+
+/// Remaining budget of a tick, shared by the concurrent tasks of the tick
+#[derive(Debug, Clone)]
+pub struct TickBudget {
+    matches: Arc<AtomicUsize>,
+    commands: Arc<AtomicUsize>,
+}
+
+impl TickBudget {
+    pub fn new(matches: usize, commands: usize) -> Self {
+        Self {
+            matches: Arc::new(AtomicUsize::new(matches)),
+            commands: Arc::new(AtomicUsize::new(commands)),
+        }
+    }
+
+    pub fn from_config(config: &MatchmakingConfig) -> Self {
+        Self::new(config.tick_match_budget, config.tick_command_budget)
+    }
+
+    /// Counts a formed match
+    pub fn spend_match(&self) {
+        spend(&self.matches, 1);
+    }
+
+    /// Counts `commands` Redis commands, a pipeline counts its commands
+    pub fn spend_commands(&self, commands: usize) {
+        spend(&self.commands, commands);
+    }
+
+    /// Matches left to the tick, `0` once the command budget is spent
+    pub fn matches_left(&self) -> usize {
+        if self.commands.load(Ordering::Relaxed) == 0 {
+            return 0;
+        }
+
+        self.matches.load(Ordering::Relaxed)
+    }
+
+    /// Is the match or the command budget spent?
+    pub fn is_spent(&self) -> bool {
+        self.matches.load(Ordering::Relaxed) == 0 || self.commands.load(Ordering::Relaxed) == 0
+    }
+}
+
+impl Default for TickBudget {
+    fn default() -> Self {
+        Self::from_config(&MatchmakingConfig::DEFAULT)
+    }
+}
+
+fn spend(remaining: &AtomicUsize, amount: usize) {
+    let _ = remaining.fetch_update(Ordering::Relaxed, Ordering::Relaxed, |left| {
+        Some(left.saturating_sub(amount))
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn either_budget_ends_the_tick() {
+        let budget = TickBudget::new(2, 100);
+        let shared = budget.clone();
+
+        budget.spend_match();
+        shared.spend_commands(40);
+        assert!(!budget.is_spent());
+        assert_eq!(budget.matches_left(), 1);
+        shared.spend_match();
+        assert!(budget.is_spent());
+
+        let budget = TickBudget::new(2, 100);
+        budget.spend_commands(150);
+        assert!(budget.is_spent());
+        assert_eq!(budget.matches_left(), 0);
+    }
+}
@@ -1,9 +1,21 @@
+use std::ops::RangeInclusive;
+
 use bitcode::{Decode, Encode};
-use chrono::Local;
 use serde::{Deserialize, Serialize};
+use skillratings::mhth::MhthRating;
 use uuid::Uuid;
 
-use crate::rpc::{Match, QueuedPlayer, helper::time_since, matchmaking::JoinMode};
+use crate::{
+    lifecycle::Lifecycle,
+    metrics::worker::SkipReason,
+    playlists::queue_region,
+    ranked,
+    rpc::{
+        Match, QueuedPlayer,
+        matchmaking::{JoinMode, PingTier, QueueType, VoiceChat},
+    },
+    starvation::Starvation,
+};
 
 #[derive(Debug, Serialize, Deserialize, Encode, Decode, PartialEq, Eq)]
 pub enum PingDeviation {
@@ -19,6 +31,30 @@ pub enum PingDeviation {
     Worst,
 }
 
+impl PingDeviation {
+    pub const fn from_ping(ping: i32) -> Self {
+        match ping {
+            ..50 => Self::Excellent,
+            50..100 => Self::Good,
+            100..150 => Self::Disadvantage,
+            150..300 => Self::Poor,
+            _ => Self::Worst,
+        }
+    }
+}
+
+impl From<PingDeviation> for PingTier {
+    fn from(value: PingDeviation) -> Self {
+        match value {
+            PingDeviation::Excellent => Self::Excellent,
+            PingDeviation::Good => Self::Good,
+            PingDeviation::Disadvantage => Self::Disadvantage,
+            PingDeviation::Poor => Self::Poor,
+            PingDeviation::Worst => Self::Worst,
+        }
+    }
+}
+
 #[derive(Debug, thiserror::Error)]
 pub enum Error {
     #[error("Player cannot host a match")]
@@ -28,17 +64,21 @@ pub enum Error {
 }
 
 impl Match {
-    const MAX_PLAYERS: usize = 4;
+    pub const MAX_PLAYERS: usize = 4;
+    /// Max relative distance between a backfilled player's skill and the match average
+    pub const BACKFILL_SKILL_WINDOW: f64 = 0.25;
+    /// Max distance between a player's trust and the match average before waiting 3 minutes
+    pub const TRUST_WINDOW: f64 = 0.3;
 
     pub fn host(player: &QueuedPlayer, party: &[QueuedPlayer]) -> Result<Self, Error> {
         let join_only_mode: i32 = JoinMode::JoinRoom.into();
         if player.join_mode == join_only_mode {
             return Err(Error::JoinOnlyMode);
         }
-        if party.len() + 1 > Self::MAX_PLAYERS {
+        if party.len() + 1 > player.params.max_players {
             return Err(Error::OversidedParty {
                 count: party.len() + 1,
-                max: Self::MAX_PLAYERS,
+                max: player.params.max_players,
             });
         }
         let mut party = party.to_vec();
@@ -47,17 +87,228 @@ impl Match {
             host_id: player.player_id,
             id: Uuid::new_v4(),
             region: player.region.clone(),
+            playlist: player.playlist.clone(),
+            experiments: player.experiments.clone(),
+            params: player.params,
             players: party,
+            lifecycle: Lifecycle::default(),
+            nakama_match_id: None,
+            game_server: None,
+            squads: Vec::new(),
+            kind: Default::default(),
+            challenge: None,
+            datacenter: None,
+            difficulty: player.difficulty,
+            settings: player.match_settings.clone(),
+            voice_chat: 0,
         })
     }
 
+    pub fn host_player(&self) -> Option<&QueuedPlayer> {
+        self.players.iter().find(|p| p.player_id == self.host_id)
+    }
+
+    /// Queue shards of the players and their adjacent shards
+    pub fn skill_bands(&self) -> RangeInclusive<i64> {
+        let bands = self.players.iter().map(|p| p.skill_band);
+        let (Some(min), Some(max)) = (bands.clone().min(), bands.max()) else {
+            return 0..=0;
+        };
+
+        min - 1..=max + 1
+    }
+
+    /// Queue type of the host, see [`crate::ranked`]
+    pub fn queue_type(&self) -> QueueType {
+        self.host_player()
+            .map(QueuedPlayer::queue_type)
+            .unwrap_or_default()
+    }
+
+    /// Queue region the players of the match were queued in
+    pub fn queue_region(&self) -> String {
+        ranked::typed_queue_region(
+            queue_region(&self.region, &self.playlist),
+            self.queue_type(),
+        )
+    }
+
+    /// Voice chat preference recorded when the match was ready
+    pub fn voice_chat(&self) -> VoiceChat {
+        VoiceChat::try_from(self.voice_chat).unwrap_or_default()
+    }
+
+    /// Voice chat preference shared by the players who set one, [`VoiceChat::AnyVoice`] when
+    /// none did or they waited long enough to be mixed
+    pub fn shared_voice_chat(&self) -> VoiceChat {
+        let mut preferences = self
+            .players
+            .iter()
+            .map(QueuedPlayer::voice_chat)
+            .filter(|voice_chat| *voice_chat != VoiceChat::AnyVoice);
+        let Some(first) = preferences.next() else {
+            return VoiceChat::AnyVoice;
+        };
+
+        if preferences.all(|voice_chat| voice_chat == first) {
+            first
+        } else {
+            VoiceChat::AnyVoice
+        }
+    }
+
+    /// Players fill matches of their difficulty. Players who opted in to adjacent difficulties
+    /// fill a match one difficulty easier or harder while the queue of their own region and
+    /// difficulty is in `starved`, see [`crate::starvation`].
+    pub fn is_difficulty_fit(&self, player: &QueuedPlayer, starved: &Starvation) -> bool {
+        self.difficulty == player.difficulty
+            || (player.adjacent_difficulty
+                && (self.difficulty - player.difficulty).abs() == 1
+                && starved.starves(&player.region, player.difficulty))
+    }
+
+    /// Can player fill an open slot of a running match?
+    /// Capacity is reported by the game server, so only compatibility is checked.
+    pub fn is_backfill_fit(&self, player: &QueuedPlayer, now: i64, starved: &Starvation) -> bool {
+        self.backfill_misfit(player, now, starved).is_none()
+    }
+
+    /// Why the player cannot fill an open slot of the match, `None` when it fits
+    pub fn backfill_misfit(
+        &self,
+        player: &QueuedPlayer,
+        now: i64,
+        starved: &Starvation,
+    ) -> Option<SkipReason> {
+        let checks = [
+            (!player.creates_room(), SkipReason::JoinMode),
+            (self.region == player.region, SkipReason::Region),
+            (self.playlist == player.playlist, SkipReason::Playlist),
+            (
+                self.experiments == player.experiments,
+                SkipReason::Experiments,
+            ),
+            (
+                self.is_difficulty_fit(player, starved),
+                SkipReason::Difficulty,
+            ),
+            (self.is_trust_fit(player, now), SkipReason::Trust),
+            (self.is_smurf_fit(player, now), SkipReason::Smurf),
+            (self.is_content_fit(player, now), SkipReason::Content),
+            (self.is_input_fit(player, now), SkipReason::Input),
+            (self.is_language_fit(player, now), SkipReason::Language),
+            (self.is_voice_fit(player, now), SkipReason::Voice),
+        ];
+        if let Some((_, reason)) = checks.into_iter().find(|(fits, _)| !fits) {
+            return Some(reason);
+        }
+        let average_skill = (self
+            .players
+            .iter()
+            .map(|p| p.skillrating.rating + p.skillrating.loadout_modifier)
+            .sum::<f64>())
+            / (self.players.len() as f64);
+        let player_skill = player.skillrating.rating + player.skillrating.loadout_modifier;
+        let within_window =
+            (player_skill - average_skill).abs() <= average_skill * self.params.skill_window;
+
+        let long_wait = more_than_minutes(3, player.join_time, now);
+        if !within_window && !long_wait {
+            return Some(SkipReason::Skill);
+        }
+        if player.ping >= self.params.ping_threshold
+            && (player.ping >= self.params.max_ping || !long_wait)
+        {
+            return Some(SkipReason::Ping);
+        }
+
+        None
+    }
+
+    /// Players are grouped with similar trust levels, until they waited too long
+    pub fn is_trust_fit(&self, player: &QueuedPlayer, now: i64) -> bool {
+        if self.players.is_empty() {
+            return true;
+        }
+        let average_trust =
+            self.players.iter().map(|p| p.trust).sum::<f64>() / (self.players.len() as f64);
+
+        (player.trust - average_trust).abs() <= Self::TRUST_WINDOW
+            || more_than_minutes(3, player.join_time, now)
+    }
+
+    /// Smurfs are matched together, until they waited more than 3 minutes
+    pub fn is_smurf_fit(&self, player: &QueuedPlayer, now: i64) -> bool {
+        self.players.iter().all(|p| p.smurf == player.smurf)
+            || more_than_minutes(3, player.join_time, now)
+    }
+
+    /// Players need a mission type and a map in common with the match,
+    /// until they waited more than 2 minutes
+    pub fn is_content_fit(&self, player: &QueuedPlayer, now: i64) -> bool {
+        let overlaps = |preferences: fn(&QueuedPlayer) -> &Vec<String>| {
+            let player_preferences = preferences(player);
+            player_preferences.is_empty()
+                || self
+                    .players
+                    .iter()
+                    .map(preferences)
+                    .filter(|preferred| !preferred.is_empty())
+                    .all(|preferred| player_preferences.iter().any(|p| preferred.contains(p)))
+        };
+
+        (overlaps(|p| &p.mission_types) && overlaps(|p| &p.maps))
+            || more_than_minutes(2, player.join_time, now)
+    }
+
+    /// Players are pooled by input device, until they waited more than the input pool
+    /// threshold. Players without an input device fit any match.
+    pub fn is_input_fit(&self, player: &QueuedPlayer, now: i64) -> bool {
+        player.input_device == 0
+            || self
+                .players
+                .iter()
+                .all(|p| p.input_device == 0 || p.input_device == player.input_device)
+            || now - player.join_time >= self.params.input_pool_secs
+    }
+
+    /// Players share a language with every player of the match who set languages, until they
+    /// waited more than 2 minutes. Players without languages fit any match.
+    pub fn is_language_fit(&self, player: &QueuedPlayer, now: i64) -> bool {
+        player.languages.is_empty()
+            || self
+                .players
+                .iter()
+                .filter(|p| !p.languages.is_empty())
+                .all(|p| player.languages.iter().any(|l| p.languages.contains(l)))
+            || more_than_minutes(2, player.join_time, now)
+    }
+
+    /// Players requiring a mic are not grouped with players without one, until they waited more
+    /// than 2 minutes. Players without a preference fit any match.
+    pub fn is_voice_fit(&self, player: &QueuedPlayer, now: i64) -> bool {
+        player.voice_chat == 0
+            || self
+                .players
+                .iter()
+                .all(|p| p.voice_chat == 0 || p.voice_chat == player.voice_chat)
+            || more_than_minutes(2, player.join_time, now)
+    }
+
     /// Can player be matched?
-    pub fn is_player_fit(&self, player: QueuedPlayer) -> (bool, PingDeviation) {
+    pub fn is_player_fit(&self, player: QueuedPlayer, now: i64) -> (bool, PingDeviation) {
         let current_players_count = self.players.len();
-        let create_room: i32 = JoinMode::CreateRoom.into();
-        if player.join_mode == create_room
-            || current_players_count >= Self::MAX_PLAYERS
+        if player.creates_room()
+            || current_players_count >= self.params.max_players
             || self.region != player.region
+            || self.playlist != player.playlist
+            || self.experiments != player.experiments
+            || !self.is_trust_fit(&player, now)
+            || !self.is_smurf_fit(&player, now)
+            || !self.is_content_fit(&player, now)
+            || !self.is_input_fit(&player, now)
+            || !self.is_language_fit(&player, now)
+            || !self.is_voice_fit(&player, now)
         {
             return (false, PingDeviation::Worst);
         }
@@ -72,20 +323,23 @@ impl Match {
         let player_skill = player.skillrating.rating + player.skillrating.loadout_modifier;
 
         let percent_skill = ((player_skill / average_skill) - 1f64) * 50f64;
+        let (threshold, max_ping) = (self.params.ping_threshold, self.params.max_ping);
 
         if player.ping < 50 {
             (true, PingDeviation::Excellent)
         } else if player.ping < 100 {
             (true, PingDeviation::Good)
-        } else if player.ping < 150 && (average_ping + 25f64) > (player.ping as f64) {
+        } else if player.ping < threshold && (average_ping + 25f64) > (player.ping as f64) {
             (true, PingDeviation::Disadvantage)
-        } else if (player.ping < 150 && more_than_minutes(1, player.join_time))
-            || ((player.ping as f64 + percent_skill) > 150f64)
+        } else if (player.ping < threshold && more_than_minutes(1, player.join_time, now))
+            || ((player.ping as f64 + percent_skill) > threshold as f64)
         {
             (true, PingDeviation::Poor)
-        } else if player.ping < 150 {
+        } else if player.ping < threshold {
             (false, PingDeviation::Disadvantage)
-        } else if player.ping >= 150 && player.ping < 300 && more_than_minutes(3, player.join_time)
+        } else if player.ping >= threshold
+            && player.ping < max_ping
+            && more_than_minutes(3, player.join_time, now)
         {
             (true, PingDeviation::Poor)
         } else {
@@ -94,20 +348,41 @@ impl Match {
     }
 }
 
-pub fn more_than_minutes(minutes: i64, joined_at: i64) -> bool {
-    let dt = Local::now();
-    let Ok(time_since) = time_since(&dt) else {
-        return false;
-    };
-    ((time_since - joined_at) / 60) > minutes
+/// Extra wait-priority of each party member besides the first
+pub const PARTY_PRIORITY: f64 = 0.5;
+
+/// Priority of a candidate for an open match, its wait weighted by party size
+/// so (near-)full parties are not starved by a stream of solo players.
+pub fn wait_priority(player: &QueuedPlayer, now: i64) -> f64 {
+    let waited = (now - player.join_time).max(0) as f64;
+
+    waited * (1. + PARTY_PRIORITY * player.party_ids.len() as f64)
+}
+
+/// Queue shard of a rating, conservative ratings are divided into `band_width` bands
+pub fn skill_band(rating: &MhthRating, band_width: f64) -> i64 {
+    let conservative = rating.rating + rating.loadout_modifier - 3. * rating.uncertainty;
+
+    (conservative / band_width).floor() as i64
+}
+
+/// Did a player joined at `joined_at` wait more than `minutes` by `now`, both seconds since the
+/// game epoch?
+pub const fn more_than_minutes(minutes: i64, joined_at: i64, now: i64) -> bool {
+    ((now - joined_at) / 60) > minutes
 }
 
 #[cfg(test)]
 mod tests {
-    use chrono::Duration;
-    use skillratings::mhth::MhthRating;
-
     use super::*;
+    use crate::{
+        experiments::MatchParams,
+        rpc::{RegionSource, matchmaking::InputDevice},
+        starvation::StarvedQueue,
+    };
+
+    /// Seconds since the game epoch the tests run at
+    const NOW: i64 = 30_000_000;
 
     #[test]
     fn single_player_match() {
@@ -188,7 +463,7 @@ mod tests {
         )
         .unwrap();
 
-        let val = a_match.is_player_fit(demo_player(Uuid::new_v4(), JoinMode::JoinRoom));
+        let val = a_match.is_player_fit(demo_player(Uuid::new_v4(), JoinMode::JoinRoom), NOW);
 
         assert!(!val.0);
         assert_eq!(val.1, PingDeviation::Worst);
@@ -208,12 +483,12 @@ mod tests {
         )
         .unwrap();
 
-        let val = a_match.is_player_fit(demo_player(Uuid::new_v4(), JoinMode::JoinRoom));
+        let val = a_match.is_player_fit(demo_player(Uuid::new_v4(), JoinMode::JoinRoom), NOW);
 
         assert!(val.0);
         assert_eq!(val.1, PingDeviation::Excellent);
 
-        let val = a_match.is_player_fit(demo_player(Uuid::new_v4(), JoinMode::CreateRoom));
+        let val = a_match.is_player_fit(demo_player(Uuid::new_v4(), JoinMode::CreateRoom), NOW);
 
         assert!(!val.0);
         assert_eq!(val.1, PingDeviation::Worst);
@@ -221,7 +496,15 @@ mod tests {
         // differente region
         let mut other = demo_player(Uuid::new_v4(), JoinMode::JoinRoom);
         other.region = "OTHER".to_string();
-        let val = a_match.is_player_fit(other);
+        let val = a_match.is_player_fit(other, NOW);
+
+        assert!(!val.0);
+        assert_eq!(val.1, PingDeviation::Worst);
+
+        // differente playlist
+        let mut other = demo_player(Uuid::new_v4(), JoinMode::JoinRoom);
+        other.playlist = "horde".to_string();
+        let val = a_match.is_player_fit(other, NOW);
 
         assert!(!val.0);
         assert_eq!(val.1, PingDeviation::Worst);
@@ -243,7 +526,7 @@ mod tests {
 
         let mut other = demo_player(Uuid::new_v4(), JoinMode::JoinRoom);
         other.ping = 51;
-        let val = a_match.is_player_fit(other);
+        let val = a_match.is_player_fit(other, NOW);
 
         assert!(val.0);
         assert_eq!(val.1, PingDeviation::Good);
@@ -251,7 +534,7 @@ mod tests {
         let mut other = demo_player(Uuid::new_v4(), JoinMode::JoinRoom);
         other.ping = 101;
         // Joined at time zero
-        let val = a_match.is_player_fit(other);
+        let val = a_match.is_player_fit(other, NOW);
 
         assert!(val.0);
         assert_eq!(val.1, PingDeviation::Poor);
@@ -259,11 +542,9 @@ mod tests {
         let mut other = demo_player(Uuid::new_v4(), JoinMode::JoinRoom);
         other.ping = 101;
         // just joined
-        let dt = Local::now() - Duration::seconds(10);
-        let join = time_since(&dt).unwrap();
-        other.join_time = join;
+        other.join_time = NOW - 10;
 
-        let val = a_match.is_player_fit(other);
+        let val = a_match.is_player_fit(other, NOW);
 
         assert!(!val.0);
         assert_eq!(val.1, PingDeviation::Disadvantage);
@@ -273,11 +554,9 @@ mod tests {
         other.ping = 101;
         other.skillrating.rating = 5000f64;
         // just joined
-        let dt = Local::now() - Duration::seconds(10);
-        let join = time_since(&dt).unwrap();
-        other.join_time = join;
+        other.join_time = NOW - 10;
 
-        let val = a_match.is_player_fit(other);
+        let val = a_match.is_player_fit(other, NOW);
 
         assert!(val.0);
         assert_eq!(val.1, PingDeviation::Poor);
@@ -285,11 +564,319 @@ mod tests {
         let mut other = demo_player(Uuid::new_v4(), JoinMode::JoinRoom);
         other.ping = 201;
         // Joined at time zero
-        let val = a_match.is_player_fit(other);
+        let val = a_match.is_player_fit(other, NOW);
         assert!(val.0);
         assert_eq!(val.1, PingDeviation::Poor);
     }
 
+    #[test]
+    fn backfill_fit() {
+        let host_id = Uuid::new_v4();
+        let player = demo_player(host_id, JoinMode::CreateRoom);
+        let a_match = Match::host(
+            &player,
+            &[
+                demo_player(Uuid::new_v4(), JoinMode::JoinRoom),
+                demo_player(Uuid::new_v4(), JoinMode::JoinRoom),
+                demo_player(Uuid::new_v4(), JoinMode::JoinRoom),
+            ],
+        )
+        .unwrap();
+        let just_joined = NOW - 10;
+
+        // full rosters can still be backfilled
+        let mut other = demo_player(Uuid::new_v4(), JoinMode::JoinRoom);
+        other.join_time = just_joined;
+        assert!(a_match.is_backfill_fit(&other, NOW, &Starvation::default()));
+
+        other.difficulty = 2;
+        assert!(!a_match.is_backfill_fit(&other, NOW, &Starvation::default()));
+
+        let mut other = demo_player(Uuid::new_v4(), JoinMode::JoinRoom);
+        other.join_time = just_joined;
+        other.skillrating.rating = 50.;
+        assert!(!a_match.is_backfill_fit(&other, NOW, &Starvation::default()));
+
+        // long wait relaxes skill window
+        other.join_time = 0;
+        assert!(a_match.is_backfill_fit(&other, NOW, &Starvation::default()));
+
+        let other = demo_player(Uuid::new_v4(), JoinMode::CreateRoom);
+        assert!(!a_match.is_backfill_fit(&other, NOW, &Starvation::default()));
+    }
+
+    #[test]
+    fn adjacent_difficulties_fit_starved_players() {
+        let mut host = demo_player(Uuid::new_v4(), JoinMode::CreateRoom);
+        host.difficulty = 2;
+        let a_match = Match::host(&host, &[]).unwrap();
+        let mut other = demo_player(Uuid::new_v4(), JoinMode::JoinRoom);
+        other.difficulty = 3;
+        let none = Starvation::default();
+        let starved = |difficulty| Starvation {
+            queues: vec![StarvedQueue {
+                region: other.region.clone(),
+                difficulty,
+                players: 1,
+                longest_wait_secs: 600,
+            }],
+            busiest_region: None,
+        };
+
+        assert!(!a_match.is_difficulty_fit(&other, &starved(3)));
+        other.adjacent_difficulty = true;
+        assert!(!a_match.is_difficulty_fit(&other, &none));
+        // only the queue of its own difficulty counts
+        assert!(!a_match.is_difficulty_fit(&other, &starved(2)));
+
+        assert!(a_match.is_difficulty_fit(&other, &starved(3)));
+        other.difficulty = 4;
+        assert!(!a_match.is_difficulty_fit(&other, &starved(4)));
+        assert_eq!(a_match.difficulty, 2);
+    }
+
+    #[test]
+    fn ping_tiers() {
+        assert_eq!(PingDeviation::from_ping(0), PingDeviation::Excellent);
+        assert_eq!(PingDeviation::from_ping(50), PingDeviation::Good);
+        assert_eq!(PingDeviation::from_ping(149), PingDeviation::Disadvantage);
+        assert_eq!(PingDeviation::from_ping(150), PingDeviation::Poor);
+        assert_eq!(PingDeviation::from_ping(300), PingDeviation::Worst);
+    }
+
+    #[test]
+    fn similar_trust_fit() {
+        let host_id = Uuid::new_v4();
+        let player = demo_player(host_id, JoinMode::CreateRoom);
+        let a_match =
+            Match::host(&player, &[demo_player(Uuid::new_v4(), JoinMode::JoinRoom)]).unwrap();
+        let just_joined = NOW - 10;
+
+        let mut other = demo_player(Uuid::new_v4(), JoinMode::JoinRoom);
+        other.join_time = just_joined;
+        other.trust = 0.8;
+        assert!(a_match.is_player_fit(other.clone(), NOW).0);
+
+        other.trust = 0.2;
+        let val = a_match.is_player_fit(other.clone(), NOW);
+        assert!(!val.0);
+        assert_eq!(val.1, PingDeviation::Worst);
+        assert!(!a_match.is_backfill_fit(&other, NOW, &Starvation::default()));
+
+        // long wait accepts any trust
+        other.join_time = 0;
+        assert!(a_match.is_player_fit(other, NOW).0);
+    }
+
+    #[test]
+    fn content_preferences_fit() {
+        let host_id = Uuid::new_v4();
+        let mut player = demo_player(host_id, JoinMode::CreateRoom);
+        player.mission_types = vec!["hunt".to_string(), "capture".to_string()];
+        player.maps = vec!["swamp".to_string()];
+        let a_match = Match::host(&player, &[]).unwrap();
+        let just_joined = NOW - 10;
+
+        // no preference accepts any content
+        let mut other = demo_player(Uuid::new_v4(), JoinMode::JoinRoom);
+        other.join_time = just_joined;
+        assert!(a_match.is_content_fit(&other, NOW));
+
+        other.mission_types = vec!["capture".to_string(), "escort".to_string()];
+        assert!(a_match.is_content_fit(&other, NOW));
+
+        other.maps = vec!["desert".to_string()];
+        assert!(!a_match.is_content_fit(&other, NOW));
+        assert!(!a_match.is_player_fit(other.clone(), NOW).0);
+
+        // long wait accepts any content
+        other.join_time = 0;
+        assert!(a_match.is_content_fit(&other, NOW));
+    }
+
+    #[test]
+    fn input_devices_fit() {
+        let mut host = demo_player(Uuid::new_v4(), JoinMode::CreateRoom);
+        host.input_device = InputDevice::Controller.into();
+        let a_match = Match::host(&host, &[]).unwrap();
+        let mut other = demo_player(Uuid::new_v4(), JoinMode::JoinRoom);
+        other.join_time = NOW - 10;
+
+        // no input device fits any match
+        assert!(a_match.is_input_fit(&other, NOW));
+
+        other.input_device = InputDevice::Controller.into();
+        assert!(a_match.is_input_fit(&other, NOW));
+
+        other.input_device = InputDevice::MouseKeyboard.into();
+        assert!(!a_match.is_input_fit(&other, NOW));
+        assert!(!a_match.is_player_fit(other.clone(), NOW).0);
+        assert!(!a_match.is_backfill_fit(&other, NOW, &Starvation::default()));
+
+        // waiting past the threshold mixes inputs
+        other.join_time = NOW - a_match.params.input_pool_secs;
+        assert!(a_match.is_input_fit(&other, NOW));
+    }
+
+    #[test]
+    fn languages_fit() {
+        let mut host = demo_player(Uuid::new_v4(), JoinMode::CreateRoom);
+        host.languages = vec!["pt".to_string(), "es".to_string()];
+        let a_match = Match::host(&host, &[]).unwrap();
+        let mut other = demo_player(Uuid::new_v4(), JoinMode::JoinRoom);
+        other.join_time = NOW - 10;
+
+        // no languages fit any match
+        assert!(a_match.is_language_fit(&other, NOW));
+
+        other.languages = vec!["en".to_string(), "es".to_string()];
+        assert!(a_match.is_language_fit(&other, NOW));
+
+        other.languages = vec!["de".to_string()];
+        assert!(!a_match.is_language_fit(&other, NOW));
+        assert!(!a_match.is_player_fit(other.clone(), NOW).0);
+        assert_eq!(
+            a_match.backfill_misfit(&other, NOW, &Starvation::default()),
+            Some(SkipReason::Language)
+        );
+
+        // waiting more than 2 minutes mixes languages
+        other.join_time = NOW - 3 * 60;
+        assert!(a_match.is_language_fit(&other, NOW));
+    }
+
+    #[test]
+    fn voice_chat_fits() {
+        let mut host = demo_player(Uuid::new_v4(), JoinMode::CreateRoom);
+        host.voice_chat = VoiceChat::MicRequired.into();
+        let mut a_match = Match::host(&host, &[]).unwrap();
+        let mut other = demo_player(Uuid::new_v4(), JoinMode::JoinRoom);
+        other.join_time = NOW - 10;
+
+        // no preference fits any match
+        assert!(a_match.is_voice_fit(&other, NOW));
+
+        other.voice_chat = VoiceChat::NoMic.into();
+        assert!(!a_match.is_voice_fit(&other, NOW));
+        assert!(!a_match.is_player_fit(other.clone(), NOW).0);
+        assert_eq!(
+            a_match.backfill_misfit(&other, NOW, &Starvation::default()),
+            Some(SkipReason::Voice)
+        );
+
+        other.voice_chat = VoiceChat::MicRequired.into();
+        assert!(a_match.is_voice_fit(&other, NOW));
+        a_match.players.push(other.clone());
+        assert_eq!(a_match.shared_voice_chat(), VoiceChat::MicRequired);
+
+        // waiting more than 2 minutes mixes preferences
+        other.voice_chat = VoiceChat::NoMic.into();
+        other.join_time = NOW - 3 * 60;
+        assert!(a_match.is_voice_fit(&other, NOW));
+        a_match.players.push(other);
+        assert_eq!(a_match.shared_voice_chat(), VoiceChat::AnyVoice);
+        // recorded once ready
+        assert_eq!(a_match.voice_chat(), VoiceChat::AnyVoice);
+        a_match.players.pop();
+        a_match
+            .transition(crate::lifecycle::MatchState::Ready, NOW)
+            .unwrap();
+        assert_eq!(a_match.voice_chat(), VoiceChat::MicRequired);
+    }
+
+    #[test]
+    fn settings_stay_with_the_match() {
+        let mut host = demo_player(Uuid::new_v4(), JoinMode::CreateRoom);
+        host.match_settings = Some(crate::match_settings::MatchSettings {
+            friendly_fire: true,
+            mutators: Vec::new(),
+            mission_seed: Some(7),
+        });
+        let mut a_match = Match::host(&host, &[]).unwrap();
+
+        a_match.host_id = Uuid::new_v4();
+
+        assert_eq!(a_match.settings, host.match_settings);
+    }
+
+    #[test]
+    fn experiment_buckets_fit() {
+        let mut host = demo_player(Uuid::new_v4(), JoinMode::CreateRoom);
+        host.experiments = vec!["small-matches:duos".to_string()];
+        host.params.max_players = 2;
+        let mut other = demo_player(Uuid::new_v4(), JoinMode::JoinRoom);
+
+        let mut a_match = Match::host(&host, &[]).unwrap();
+        assert!(!a_match.is_player_fit(other.clone(), NOW).0);
+
+        other.experiments = host.experiments.clone();
+        assert!(a_match.is_player_fit(other.clone(), NOW).0);
+        a_match.players.push(other.clone());
+        assert!(!a_match.is_player_fit(other.clone(), NOW).0);
+        assert!(matches!(
+            Match::host(&host, &[other.clone(), other]),
+            Err(Error::OversidedParty { count: 3, max: 2 })
+        ));
+    }
+
+    #[test]
+    fn skill_bands() {
+        let rating = MhthRating {
+            rating: 30.,
+            loadout_modifier: 1.,
+            uncertainty: 2.,
+        };
+        assert_eq!(skill_band(&rating, 5.), 5);
+        assert_eq!(skill_band(&MhthRating::new(), 5.), 0);
+        assert_eq!(skill_band(&MhthRating::new().loadout_modifier(-4.), 5.), -1);
+
+        let mut host = demo_player(Uuid::new_v4(), JoinMode::CreateRoom);
+        host.skill_band = 2;
+        let mut other = demo_player(Uuid::new_v4(), JoinMode::JoinRoom);
+        other.skill_band = 4;
+        let a_match = Match::host(&host, &[other]).unwrap();
+
+        assert_eq!(a_match.skill_bands(), 1..=5);
+    }
+
+    #[test]
+    fn parties_have_wait_priority() {
+        let mut solo = demo_player(Uuid::new_v4(), JoinMode::JoinRoom);
+        solo.party_ids.clear();
+        solo.join_time = 0;
+        let mut party = demo_player(Uuid::new_v4(), JoinMode::JoinRoom);
+        party.party_ids = vec![Uuid::new_v4().to_string(); 3];
+        party.join_time = 40;
+
+        assert_eq!(wait_priority(&solo, 100), 100.);
+        assert_eq!(wait_priority(&party, 100), 150.);
+        assert_eq!(wait_priority(&party, 20), 0.);
+    }
+
+    #[test]
+    fn smurfs_fit_together() {
+        let host = demo_player(Uuid::new_v4(), JoinMode::CreateRoom);
+        let a_match = Match::host(&host, &[]).unwrap();
+        let mut smurf = demo_player(Uuid::new_v4(), JoinMode::JoinRoom);
+        smurf.smurf = true;
+        smurf.join_time = NOW - 10;
+
+        assert!(!a_match.is_smurf_fit(&smurf, NOW));
+        assert!(a_match.is_smurf_fit(&demo_player(Uuid::new_v4(), JoinMode::JoinRoom), NOW));
+
+        assert!(!a_match.is_player_fit(smurf.clone(), NOW).0);
+        let mut smurf_host = host;
+        smurf_host.smurf = true;
+        assert!(
+            Match::host(&smurf_host, &[])
+                .unwrap()
+                .is_smurf_fit(&smurf, NOW)
+        );
+
+        smurf.join_time = 0;
+        assert!(a_match.is_smurf_fit(&smurf, NOW));
+    }
+
     fn demo_player(id: Uuid, join_mode: JoinMode) -> QueuedPlayer {
         QueuedPlayer {
             player_id: id,
@@ -301,56 +888,64 @@ mod tests {
             party_mode: 1,
             party_ids: vec![String::new(), String::new()],
             join_time: 0,
+            trust: 1.,
+            playlist: String::new(),
+            mission_types: Vec::new(),
+            maps: Vec::new(),
+            experiments: Vec::new(),
+            params: MatchParams::default(),
+            skill_band: 0,
+            smurf: false,
+            request_id: String::new(),
+            input_device: 0,
+            region_source: RegionSource::Declared,
+            queue_type: 0,
+            match_settings: None,
+            clan: None,
+            languages: Vec::new(),
+            voice_chat: 0,
+            datacenter_pings: Vec::new(),
+            adjacent_difficulty: false,
         }
     }
 }
 
 #[cfg(test)]
 mod time_tests {
-    use chrono::Local;
-
     use super::*;
 
+    const NOW: i64 = 30_000_000;
+
     #[test]
     fn test_exactly_equal_minutes() {
-        let now = Local::now();
-        let current_since = time_since(&now).unwrap();
-        let joined_at = current_since - (5 * 60); // joined exactly 5 minutes ago
-        assert!(!more_than_minutes(5, joined_at));
+        let joined_at = NOW - (5 * 60); // joined exactly 5 minutes ago
+        assert!(!more_than_minutes(5, joined_at, NOW));
         // because (5*60)/60 == 5, not > 5
     }
 
     #[test]
     fn test_more_than_minutes_true() {
-        let now = Local::now();
-        let current_since = time_since(&now).unwrap();
-        let joined_at = current_since - (10 * 60); // joined 10 minutes ago
-        assert!(more_than_minutes(5, joined_at));
+        let joined_at = NOW - (10 * 60); // joined 10 minutes ago
+        assert!(more_than_minutes(5, joined_at, NOW));
         // (10*60)/60 == 10, so > 5
     }
 
     #[test]
     fn test_less_than_minutes_false() {
-        let now = Local::now();
-        let current_since = time_since(&now).unwrap();
-        let joined_at = current_since - (2 * 60); // joined 2 minutes ago
-        assert!(!more_than_minutes(5, joined_at));
+        let joined_at = NOW - (2 * 60); // joined 2 minutes ago
+        assert!(!more_than_minutes(5, joined_at, NOW));
         // (2*60)/60 == 2, so not > 5
     }
 
     #[test]
     fn test_negative_joined_at() {
-        let now = Local::now();
-        let current_since = time_since(&now).unwrap();
-        let joined_at = current_since + (60); // future join time (invalid, but test anyway)
-        assert!(!more_than_minutes(1, joined_at));
+        let joined_at = NOW + (60); // future join time (invalid, but test anyway)
+        assert!(!more_than_minutes(1, joined_at, NOW));
     }
 
     #[test]
     fn test_zero_minutes_threshold() {
-        let now = Local::now();
-        let current_since = time_since(&now).unwrap();
-        let joined_at = current_since - 60; // joined 1 minute ago
-        assert!(more_than_minutes(0, joined_at));
+        let joined_at = NOW - 60; // joined 1 minute ago
+        assert!(more_than_minutes(0, joined_at, NOW));
     }
 }
@@ -0,0 +1,356 @@
+use std::collections::HashSet;
+
+use redis::{AsyncCommands, RedisError, aio::MultiplexedConnection};
+use tracing::{error, info};
+use uuid::Uuid;
+
+use crate::{
+    audit::{self, Action, Actor, AuditEvent},
+    claim, namespace,
+    notifications::{self, Notification},
+    presence,
+    rpc::{
+        Match, PLAYER_QUEUE, QueuedPlayer, forming_match_key, open_matches_key, player_key,
+        server::TWELVE_MINUTES,
+        worker::{MatchmakingWorker, scan::scan},
+    },
+    store,
+};
+
+const CLEANUP_SCAN: &str = "cleanup";
+/// Abandoned players removed per run
+const ABANDONED_BATCH: isize = 500;
+
+#[derive(Debug, thiserror::Error)]
+pub enum Error {
+    #[error(transparent)]
+    Redis(#[from] RedisError),
+}
+
+/// Every player queue shard, they are keyed by party mode, queue region and skill band
+pub(super) async fn player_queue_keys(
+    conn: &mut MultiplexedConnection,
+) -> Result<Vec<String>, RedisError> {
+    let pattern = namespace::key(format_args!("{PLAYER_QUEUE}:*"));
+    let mut keys = Vec::new();
+    let mut iter = conn.scan_match::<_, String>(pattern).await?;
+    while let Some(key) = iter.next_item().await {
+        keys.push(key);
+    }
+
+    Ok(keys)
+}
+
+impl MatchmakingWorker {
+    /// Removes queue entries whose player key expired, from the queues and from the open
+    /// matches they were pencilled into, and tells the players to queue again.
+    /// Returns how many players were removed.
+    pub async fn remove_stale_entries(&mut self) -> Result<usize, Error> {
+        let mut conn = self.redis.clone();
+        let mut stale = HashSet::new();
+
+        for key in player_queue_keys(&mut conn).await? {
+            let scan_key = (CLEANUP_SCAN, key.clone());
+            let scanned = scan(
+                &mut conn,
+                &key,
+                self.scan_cursors.get(&scan_key).copied(),
+                self.config.scan_batch_size,
+                self.config.scan_budget,
+            )
+            .await?;
+            match scanned.cursor {
+                Some(cursor) => self.scan_cursors.insert(scan_key, cursor),
+                None => self.scan_cursors.remove(&scan_key),
+            };
+            for (player, encoded) in scanned.entries.iter().filter_map(|player_bits| {
+                Some((
+                    self.metrics.decode::<QueuedPlayer>(player_bits)?,
+                    player_bits,
+                ))
+            }) {
+                if conn.exists(player_key(&player.player_id)).await? {
+                    continue;
+                }
+                let _: () = conn.zrem(&key, encoded).await?;
+                stale.insert(player.player_id);
+            }
+        }
+
+        self.remove_stale_match_players(&mut conn, &mut stale)
+            .await?;
+        if stale.is_empty() {
+            return Ok(0);
+        }
+
+        let players: Vec<Uuid> = stale.into_iter().collect();
+        info!("removed {} stale queue entries", players.len());
+        let now = self.clock.time_since_epoch();
+        for player_id in &players {
+            let left = AuditEvent::new(Action::PlayerLeft, Actor::Worker, player_id, now)
+                .with_reason("timeout");
+            audit::emit(&mut conn, &left).await;
+        }
+        notifications::notify(&mut conn, &players, &Notification::QueueTimeout).await?;
+
+        Ok(players.len())
+    }
+
+    /// Removes the queued players whose heartbeats stopped for [`presence::abandon_after`]
+    /// seconds, from the queues and from the open matches they were pencilled into, and tells
+    /// them to queue again. Returns how many players were removed.
+    pub async fn remove_abandoned_players(&mut self) -> Result<usize, Error> {
+        let mut conn = self.redis.clone();
+        let now = self.clock.time_since_epoch();
+        let abandoned =
+            presence::abandoned(&mut conn, now, presence::abandon_after(), ABANDONED_BATCH).await?;
+        if abandoned.is_empty() {
+            return Ok(0);
+        }
+
+        let mut removed = HashSet::new();
+        for player_id in &abandoned {
+            if store::remove_player(&mut conn, player_id).await? {
+                removed.insert(*player_id);
+            }
+        }
+        presence::forget(&mut conn, &abandoned).await?;
+        self.remove_stale_match_players(&mut conn, &mut removed)
+            .await?;
+        if removed.is_empty() {
+            return Ok(0);
+        }
+
+        let players: Vec<Uuid> = removed.into_iter().collect();
+        info!("removed {} abandoned players", players.len());
+        for player_id in &players {
+            let left = AuditEvent::new(Action::PlayerLeft, Actor::Worker, player_id, now)
+                .with_reason("abandoned");
+            audit::emit(&mut conn, &left).await;
+        }
+        notifications::notify(&mut conn, &players, &Notification::QueueTimeout).await?;
+
+        Ok(players.len())
+    }
+
+    /// Drops the expired players of open matches, hosts are handed over by
+    /// [`MatchmakingWorker::migrate_expired_hosts`]
+    async fn remove_stale_match_players(
+        &mut self,
+        conn: &mut MultiplexedConnection,
+        stale: &mut HashSet<Uuid>,
+    ) -> Result<(), RedisError> {
+        for open_match in &mut self.open_matches {
+            if expired_players(conn, open_match).await?.is_empty() {
+                continue;
+            }
+            let Some((claim, reloaded)) = claim::acquire_forming(conn, &open_match.id).await?
+            else {
+                info!(
+                    "match `{}` is claimed, removing its players later",
+                    open_match.id
+                );
+                continue;
+            };
+            *open_match = reloaded;
+            let expired = match expired_players(conn, open_match).await {
+                Ok(expired) => expired,
+                Err(err) => {
+                    claim::release(conn, claim).await?;
+                    return Err(err);
+                }
+            };
+
+            open_match
+                .players
+                .retain(|player| !expired.contains(&player.player_id));
+            let mut pipe = redis::pipe();
+            store::put_match(&mut pipe, open_match, TWELVE_MINUTES);
+            pipe.zadd(
+                open_matches_key(&open_match.region),
+                open_match.id,
+                open_match.players.len(),
+            )
+            .ignore();
+            store::unindex_players(&mut pipe, &expired);
+            for player_id in &expired {
+                pipe.del(forming_match_key(player_id)).ignore();
+            }
+            let written = pipe.query_async(conn).await.map(|_: ()| ());
+            claim::release(conn, claim).await?;
+            if let Err(err) = written {
+                error!("failed to update match `{}`: {err}", open_match.id);
+                continue;
+            }
+            stale.extend(expired);
+        }
+
+        Ok(())
+    }
+}
+
+/// Players of `open_match` but its host whose player key expired
+async fn expired_players(
+    conn: &mut MultiplexedConnection,
+    open_match: &Match,
+) -> Result<Vec<Uuid>, RedisError> {
+    let mut expired = Vec::new();
+    for player in &open_match.players {
+        if player.player_id != open_match.host_id
+            && !conn.exists(player_key(&player.player_id)).await?
+        {
+            expired.push(player.player_id);
+        }
+    }
+
+    Ok(expired)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use skillratings::mhth::MhthRating;
+
+    use super::*;
+    use crate::{
+        clock::{Clock, SystemClock},
+        codec,
+        nakama::{Authenticated, NakamaClient},
+        rpc::{matchmaking::Player, player_queue_key},
+        test_support::{create_redis, redis_client},
+    };
+
+    #[tokio::test]
+    async fn stale_entries_are_removed() {
+        let container = create_redis(6379).await;
+        let host = container.get_host().await.unwrap();
+        let port = container.get_host_port_ipv4(6379).await.unwrap();
+        let client = redis_client(host.to_string(), port);
+        let mut conn = client.get_multiplexed_async_connection().await.unwrap();
+        let queued = |join_mode| -> QueuedPlayer {
+            (
+                Uuid::new_v4(),
+                Player {
+                    join_mode,
+                    region: "CAN".to_string(),
+                    ..Default::default()
+                },
+                MhthRating::default(),
+            )
+                .into()
+        };
+        let (active, expired, match_host, pencilled) = (queued(1), queued(1), queued(0), queued(1));
+        for player in [&active, &expired] {
+            let _: () = conn
+                .zadd(player_queue_key(player), codec::encode(player), 1)
+                .await
+                .unwrap();
+        }
+        let mut pipe = redis::pipe();
+        for player in [&active, &match_host] {
+            store::put_player(&mut pipe, player, &codec::encode(player), 200);
+        }
+        let _: () = pipe.query_async(&mut conn).await.unwrap();
+        let mut open_match = Match::host(&match_host, &[]).unwrap();
+        open_match.players.push(pencilled.clone());
+        let mut pipe = redis::pipe();
+        store::put_match(&mut pipe, &open_match, 200);
+        let _: () = pipe.query_async(&mut conn).await.unwrap();
+        let mut worker = MatchmakingWorker::new(
+            conn.clone(),
+            Arc::new(reqwest::Client::new()),
+            auth_client(666).into(),
+            Arc::new(SystemClock::default()),
+        );
+        worker.open_matches.push(open_match);
+
+        let removed = worker.remove_stale_entries().await.unwrap();
+        let queue: Vec<Vec<u8>> = conn.zrange(player_queue_key(&active), 0, -1).await.unwrap();
+        let expired_events = notifications::drain(&mut conn, &expired.player_id)
+            .await
+            .unwrap();
+        let pencilled_events = notifications::drain(&mut conn, &pencilled.player_id)
+            .await
+            .unwrap();
+
+        assert_eq!(removed, 2);
+        assert_eq!(queue, vec![codec::encode(&active)]);
+        assert_eq!(worker.open_matches[0].players.len(), 1);
+        assert_eq!(expired_events, vec![Notification::QueueTimeout]);
+        assert_eq!(pencilled_events, vec![Notification::QueueTimeout]);
+    }
+
+    #[tokio::test]
+    async fn abandoned_players_are_removed() {
+        let container = create_redis(6379).await;
+        let host = container.get_host().await.unwrap();
+        let port = container.get_host_port_ipv4(6379).await.unwrap();
+        let client = redis_client(host.to_string(), port);
+        let mut conn = client.get_multiplexed_async_connection().await.unwrap();
+        let queued = || -> QueuedPlayer {
+            (
+                Uuid::new_v4(),
+                Player {
+                    join_mode: 1,
+                    region: "CAN".to_string(),
+                    ..Default::default()
+                },
+                MhthRating::default(),
+            )
+                .into()
+        };
+        let (present, gone, silent) = (queued(), queued(), queued());
+        let mut pipe = redis::pipe();
+        for player in [&present, &gone, &silent] {
+            let encoded = codec::encode(player);
+            store::put_player(&mut pipe, player, &encoded, 600);
+            pipe.zadd(player_queue_key(player), encoded, 1).ignore();
+        }
+        let _: () = pipe.query_async(&mut conn).await.unwrap();
+        let clock = SystemClock::default();
+        let now = clock.time_since_epoch();
+        presence::seen(&mut conn, &present.player_id, now)
+            .await
+            .unwrap();
+        presence::seen(&mut conn, &gone.player_id, now - 120)
+            .await
+            .unwrap();
+        let mut worker = MatchmakingWorker::new(
+            conn.clone(),
+            Arc::new(reqwest::Client::new()),
+            auth_client(666).into(),
+            Arc::new(clock),
+        );
+
+        let removed = worker.remove_abandoned_players().await.unwrap();
+        let queue: Vec<Vec<u8>> = conn
+            .zrange(player_queue_key(&present), 0, -1)
+            .await
+            .unwrap();
+        let gone_queued: bool = conn.exists(player_key(&gone.player_id)).await.unwrap();
+        let gone_events = notifications::drain(&mut conn, &gone.player_id)
+            .await
+            .unwrap();
+
+        assert_eq!(removed, 1);
+        assert_eq!(queue.len(), 2);
+        assert!(!queue.contains(&codec::encode(&gone)));
+        assert!(!gone_queued);
+        assert_eq!(gone_events, vec![Notification::QueueTimeout]);
+    }
+
+    fn auth_client(port: u16) -> NakamaClient<Authenticated> {
+        NakamaClient {
+            username: "username".to_string(),
+            password: "password".to_string(),
+            token: Some("super_random_token".to_string()),
+            url: format!("http://127.0.0.1:{port}"),
+            server_key_name: "defaultkey".to_string(),
+            server_key_value: "server_key".to_string(),
+            encryption_key: "encryption_key".to_string(),
+            legacy_until: None,
+            _state: std::marker::PhantomData::<Authenticated>,
+        }
+    }
+}
@@ -0,0 +1,339 @@
+use bitcode::{Decode, Encode};
+use redis::{AsyncCommands, RedisError, aio::MultiplexedConnection};
+use tracing::{error, info, warn};
+use uuid::Uuid;
+
+use crate::{
+    audit::{self, Action, AuditEvent},
+    codec,
+    lifecycle::MatchState,
+    notifications::{self, Notification},
+    rpc::{
+        Match, QueuedPlayer, dead_matches_key, enqueue, forming_match_key, player_queue_key,
+        server::{MATCH_LIFETIME, TEN_MINUTES},
+        worker::MatchmakingWorker,
+    },
+    store,
+};
+
+/// Start attempts of a match before its players are queued again
+pub const MAX_START_ATTEMPTS: u32 = 5;
+/// Seconds before the first retry, doubled after every failed attempt
+pub const RETRY_DELAY_SECS: i64 = 5;
+
+/// Match that failed to start, scored in the dead-letter queue by its next retry
+#[derive(Debug, Clone, Encode, Decode, PartialEq)]
+pub struct DeadMatch {
+    pub dead: Match,
+    /// Start attempts that already failed
+    pub attempts: u32,
+}
+
+impl codec::Versioned for DeadMatch {}
+
+#[derive(Debug, thiserror::Error)]
+pub enum Error {
+    #[error(transparent)]
+    Redis(#[from] RedisError),
+}
+
+/// Seconds since the game epoch when a match failing its `attempts` start is retried
+pub const fn retry_at(now: i64, attempts: u32) -> i64 {
+    now + (RETRY_DELAY_SECS << attempts.saturating_sub(1))
+}
+
+/// Queues `failed` for another start attempt at `retry_at`
+pub async fn push(
+    conn: &mut MultiplexedConnection,
+    failed: &Match,
+    attempts: u32,
+    retry_at: i64,
+) -> Result<(), RedisError> {
+    let dead = DeadMatch {
+        dead: failed.clone(),
+        attempts,
+    };
+
+    let mut pipe = redis::pipe();
+    pipe.zadd(dead_matches_key(), codec::encode(&dead), retry_at)
+        .ignore();
+    store::index_match(&mut pipe, failed);
+
+    pipe.query_async(conn).await
+}
+
+/// Queues the players of `failed` again with their original join time, ahead of the players who
+/// joined after them, in the queues they joined, see [`enqueue`]. Players pencilled into another
+/// match or queued again on their own since are left there. Returns the players queued again.
+pub async fn requeue_players(
+    conn: &mut MultiplexedConnection,
+    failed: &Match,
+) -> Result<Vec<Uuid>, RedisError> {
+    let mut pipe = redis::pipe();
+    pipe.atomic();
+    let mut requeued = Vec::new();
+    for player in &failed.players {
+        let forming: Option<Uuid> = conn.get(forming_match_key(&player.player_id)).await?;
+        if forming.is_some_and(|match_id| match_id != failed.id)
+            || is_queued(conn, &player.player_id).await?
+        {
+            continue;
+        }
+        let encoded = codec::encode(player);
+        pipe.del(forming_match_key(&player.player_id)).ignore();
+        store::put_player(&mut pipe, player, &encoded, TEN_MINUTES);
+        enqueue(&mut pipe, player, &encoded, failed.is_raid());
+        requeued.push(player.player_id);
+    }
+    pipe.query_async(conn).await.map(|_: ()| ())?;
+
+    Ok(requeued)
+}
+
+/// Whether the stored entry of the player is in its region queue, a match forming takes it out
+async fn is_queued(conn: &mut MultiplexedConnection, player_id: &Uuid) -> Result<bool, RedisError> {
+    let Some(bits) = store::player_data(conn, player_id).await? else {
+        return Ok(false);
+    };
+    let Ok(queued) = codec::decode::<QueuedPlayer>(&bits) else {
+        return Ok(false);
+    };
+    // the hash holds the very bytes of the queue member
+    let score: Option<i64> = conn.zscore(player_queue_key(&queued), &bits).await?;
+
+    Ok(score.is_some())
+}
+
+impl MatchmakingWorker {
+    /// Retries the dead-lettered matches due by now, their players are queued again and told the
+    /// match failed after [`MAX_START_ATTEMPTS`]. Returns how many matches were started.
+    pub async fn retry_dead_matches(&mut self) -> Result<usize, Error> {
+        let mut conn = self.redis.clone();
+        let now = self.clock.time_since_epoch();
+        let due: Vec<Vec<u8>> = conn
+            .zrangebyscore_limit(
+                dead_matches_key(),
+                "-inf",
+                now,
+                0,
+                self.config.scan_budget as isize,
+            )
+            .await?;
+
+        let mut started = 0;
+        for encoded in due {
+            // Removing the entry claims the retry
+            let removed: usize = conn.zrem(dead_matches_key(), &encoded).await?;
+            if removed == 0 {
+                continue;
+            }
+            let Ok(DeadMatch { mut dead, attempts }) = codec::decode(&encoded) else {
+                self.metrics.decode_failures += 1;
+                error!("dropped undecodable dead-lettered match");
+                continue;
+            };
+            let attempts = attempts + 1;
+
+            match self.start_match(&mut dead).await {
+                Ok(()) => {
+                    info!("match `{}` started after {attempts} attempts", dead.id);
+                    started += 1;
+                }
+                Err(err) if attempts >= MAX_START_ATTEMPTS => {
+                    error!(
+                        "match `{}` failed to start {attempts} times: {err}",
+                        dead.id
+                    );
+                    if let Err(err) = dead.transition(MatchState::Aborted, now) {
+                        error!("failed to abort match `{}`: {err}", dead.id);
+                    }
+                    // kept for inspection, like the matches that started
+                    let mut pipe = redis::pipe();
+                    store::put_match(&mut pipe, &dead, *MATCH_LIFETIME);
+                    pipe.query_async(&mut conn).await.map(|_: ()| ())?;
+                    let requeued = requeue_players(&mut conn, &dead).await?;
+                    info!(
+                        "queued {} players of match `{}` again",
+                        requeued.len(),
+                        dead.id
+                    );
+                    let notification = Notification::MatchFailed { match_id: dead.id };
+                    notifications::notify(&mut conn, &requeued, &notification).await?;
+                    let cancelled = AuditEvent::for_match(Action::MatchCancelled, &dead, now)
+                        .with_reason("start_failed");
+                    audit::emit(&mut conn, &cancelled).await;
+                }
+                Err(err) => {
+                    warn!("match `{}` failed to start again: {err}", dead.id);
+                    push(&mut conn, &dead, attempts, retry_at(now, attempts)).await?;
+                }
+            }
+        }
+
+        Ok(started)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use skillratings::mhth::MhthRating;
+    use uuid::Uuid;
+
+    use super::*;
+    use crate::{
+        clock::{Clock, SystemClock},
+        nakama::{Authenticated, NakamaClient},
+        rpc::{active_match_key, matchmaking::Player, player_create_match_key},
+        test_support::{create_redis, redis_client},
+    };
+
+    #[test]
+    fn retries_back_off() {
+        assert_eq!(retry_at(100, 1), 105);
+        assert_eq!(retry_at(100, 2), 110);
+        assert_eq!(retry_at(100, 4), 140);
+    }
+
+    #[tokio::test]
+    async fn retry_due_matches() {
+        let container = create_redis(6379).await;
+        let host = container.get_host().await.unwrap();
+        let port = container.get_host_port_ipv4(6379).await.unwrap();
+        let client = redis_client(host.to_string(), port);
+        let mut conn = client.get_multiplexed_async_connection().await.unwrap();
+        let clock = SystemClock::default();
+        let now = clock.time_since_epoch();
+        let host: QueuedPlayer = (
+            Uuid::new_v4(),
+            Player {
+                region: "CAN".to_string(),
+                ..Default::default()
+            },
+            MhthRating::default(),
+        )
+            .into();
+        let starting = || {
+            let mut starting = Match::host(&host, &[]).unwrap();
+            starting.transition(MatchState::Ready, now).unwrap();
+            starting.transition(MatchState::Starting, now).unwrap();
+            // created in Nakama before activating it failed
+            starting.nakama_match_id = Some("nakama.match".to_string());
+            starting
+        };
+        let (due, later) = (starting(), starting());
+        push(&mut conn, &due, 1, now - 1).await.unwrap();
+        push(&mut conn, &later, 1, now + 60).await.unwrap();
+        let mut worker = MatchmakingWorker::new(
+            conn.clone(),
+            Arc::new(reqwest::Client::new()),
+            auth_client(666).into(),
+            Arc::new(clock),
+        );
+
+        let started = worker.retry_dead_matches().await.unwrap();
+        let active: Option<Vec<u8>> = conn.get(active_match_key(&due.id)).await.unwrap();
+        let waiting: usize = conn.zcard(dead_matches_key()).await.unwrap();
+
+        assert_eq!(started, 1);
+        assert!(active.is_some());
+        assert_eq!(waiting, 1);
+    }
+
+    #[tokio::test]
+    async fn players_of_failed_matches_are_queued_again() {
+        let container = create_redis(6379).await;
+        let host = container.get_host().await.unwrap();
+        let port = container.get_host_port_ipv4(6379).await.unwrap();
+        let client = redis_client(host.to_string(), port);
+        let mut conn = client.get_multiplexed_async_connection().await.unwrap();
+        let clock = SystemClock::default();
+        let now = clock.time_since_epoch();
+        let queued = |join_time| -> QueuedPlayer {
+            let mut player: QueuedPlayer = (
+                Uuid::new_v4(),
+                Player {
+                    region: "CAN".to_string(),
+                    ..Default::default()
+                },
+                MhthRating::default(),
+            )
+                .into();
+            player.join_time = join_time;
+            player
+        };
+        let (match_host, member, elsewhere) = (queued(now - 90), queued(now - 30), queued(now));
+        let rejoined = queued(now - 60);
+        let mut failed = Match::host(
+            &match_host,
+            &[member.clone(), elsewhere.clone(), rejoined.clone()],
+        )
+        .unwrap();
+        failed.transition(MatchState::Ready, now).unwrap();
+        failed.transition(MatchState::Starting, now).unwrap();
+        push(&mut conn, &failed, MAX_START_ATTEMPTS - 1, now - 1)
+            .await
+            .unwrap();
+        let _: () = conn
+            .set(forming_match_key(&elsewhere.player_id), Uuid::new_v4())
+            .await
+            .unwrap();
+        // left the failed match and joined again on its own
+        let mut rejoined_entry = rejoined.clone();
+        rejoined_entry.join_time = now;
+        let mut pipe = redis::pipe();
+        let encoded = codec::encode(&rejoined_entry);
+        store::put_player(&mut pipe, &rejoined_entry, &encoded, 200);
+        enqueue(&mut pipe, &rejoined_entry, &encoded, false);
+        pipe.query_async(&mut conn).await.map(|_: ()| ()).unwrap();
+        let mut worker = MatchmakingWorker::new(
+            conn.clone(),
+            Arc::new(reqwest::Client::new()),
+            auth_client(666).into(),
+            Arc::new(clock),
+        );
+
+        let started = worker.retry_dead_matches().await.unwrap();
+        let queue = player_queue_key(&match_host);
+        let host_score: Option<i64> = conn
+            .zscore(&queue, codec::encode(&match_host))
+            .await
+            .unwrap();
+        let member_score: Option<i64> = conn.zscore(&queue, codec::encode(&member)).await.unwrap();
+        let rejoined_score: Option<i64> = conn.zscore(&queue, &encoded).await.unwrap();
+        let queue_len: usize = conn.zcard(&queue).await.unwrap();
+        let hosting: Option<i64> = conn
+            .zscore(
+                player_create_match_key(&match_host),
+                codec::encode(&match_host),
+            )
+            .await
+            .unwrap();
+        let member_data = store::player_data(&mut conn, &member.player_id)
+            .await
+            .unwrap();
+
+        assert_eq!(started, 0);
+        assert_eq!(host_score, Some(now - 90));
+        assert_eq!(member_score, Some(now - 30));
+        assert_eq!(rejoined_score, Some(now));
+        assert_eq!(queue_len, 3);
+        assert_eq!(hosting, Some(now - 90));
+        assert!(member_data.is_some());
+    }
+
+    fn auth_client(port: u16) -> NakamaClient<Authenticated> {
+        NakamaClient {
+            username: "username".to_string(),
+            password: "password".to_string(),
+            token: Some("super_random_token".to_string()),
+            url: format!("http://127.0.0.1:{port}"),
+            server_key_name: "defaultkey".to_string(),
+            server_key_value: "server_key".to_string(),
+            encryption_key: "encryption_key".to_string(),
+            legacy_until: None,
+            _state: std::marker::PhantomData::<Authenticated>,
+        }
+    }
+}
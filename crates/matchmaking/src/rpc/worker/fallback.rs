@@ -0,0 +1,248 @@
+use std::sync::LazyLock;
+
+use redis::{AsyncCommands, RedisError, Script, aio::MultiplexedConnection};
+use tracing::info;
+
+use crate::{
+    codec, namespace,
+    rpc::{
+        PLAYER_QUEUE, QueuedPlayer, forming_match_key, matchmaking::PartyMode,
+        player_create_match_key, player_key, player_queue_key, player_raid_key, player_versus_key,
+        worker::MatchmakingWorker,
+    },
+    store::{DATA, JOIN_TIME, REGION},
+};
+
+/// Moves a queued player to the queues of its fallback region. Skips players hosting a forming
+/// match (`KEYS[1]`) or queued for a raid (`KEYS[2]`), then removing the entry from its queue
+/// (`KEYS[3]`) claims the player. The entry is added to `KEYS[4]`, the pairs after the player
+/// hash (`KEYS[5]`) move it between the other queues, and the hash is rewritten only while the
+/// player is queued, keeping its expiry.
+static FALL_BACK: LazyLock<Script> = LazyLock::new(|| {
+    Script::new(
+        r"
+        if redis.call('EXISTS', KEYS[1]) == 1 or redis.call('ZSCORE', KEYS[2], ARGV[1]) then
+            return 0
+        end
+        if redis.call('ZREM', KEYS[3], ARGV[2]) == 0 then
+            return 0
+        end
+        redis.call('ZADD', KEYS[4], ARGV[4], ARGV[3])
+        for i = 6, #KEYS, 2 do
+            redis.call('ZREM', KEYS[i], ARGV[2])
+            redis.call('ZADD', KEYS[i + 1], ARGV[4], ARGV[3])
+        end
+        if redis.call('EXISTS', KEYS[5]) == 1 then
+            redis.call('HSET', KEYS[5], ARGV[5], ARGV[3], ARGV[6], ARGV[7], ARGV[8], ARGV[4])
+        end
+        return 1
+        ",
+    )
+});
+
+#[derive(Debug, thiserror::Error)]
+pub enum Error {
+    #[error(transparent)]
+    Redis(#[from] RedisError),
+}
+
+/// Solo queue shards of a region and of its playlist queue regions
+async fn solo_queue_keys(
+    conn: &mut MultiplexedConnection,
+    region: &str,
+) -> Result<Vec<String>, RedisError> {
+    let solo: i32 = PartyMode::Solo.into();
+    let pattern = namespace::key(format_args!("{PLAYER_QUEUE}:{solo}:{region}:*"));
+    let mut keys = Vec::new();
+    let mut iter = conn.scan_match::<_, String>(pattern).await?;
+    while let Some(key) = iter.next_item().await {
+        keys.push(key);
+    }
+
+    Ok(keys)
+}
+
+impl MatchmakingWorker {
+    /// Moves solo players waiting past the fallback of their region into the queues of the
+    /// fallback region, keeping their queue position. Returns how many players moved.
+    pub async fn fall_back_regions(&mut self) -> Result<usize, Error> {
+        let mut conn = self.redis.clone();
+        let now = self.clock.time_since_epoch();
+
+        let mut moved = 0;
+        for (region, tuning) in &self.region_tunings.0 {
+            let Some(fallback) = &tuning.fallback_region else {
+                continue;
+            };
+            for key in solo_queue_keys(&mut conn, region).await? {
+                let entries: Vec<Vec<u8>> = conn
+                    .zrangebyscore_limit(
+                        &key,
+                        "-inf",
+                        now - tuning.fallback_after_secs,
+                        0,
+                        self.config.scan_budget as isize,
+                    )
+                    .await?;
+                for (player, encoded) in entries.iter().filter_map(|player_bits| {
+                    Some((
+                        codec::decode::<QueuedPlayer>(player_bits).ok()?,
+                        player_bits,
+                    ))
+                }) {
+                    if player.party_ids.is_empty()
+                        && fall_back(&mut conn, &player, encoded, fallback).await?
+                    {
+                        player.span().in_scope(|| {
+                            info!("player moved from region `{region}` to `{fallback}`");
+                        });
+                        moved += 1;
+                    }
+                }
+            }
+        }
+
+        Ok(moved)
+    }
+}
+
+/// Requeues `player` in `region` with [`FALL_BACK`], `false` when the player was already
+/// matched, is hosting a forming match or queued for a raid
+async fn fall_back(
+    conn: &mut MultiplexedConnection,
+    player: &QueuedPlayer,
+    encoded: &[u8],
+    region: &str,
+) -> Result<bool, RedisError> {
+    let mut moved = player.clone();
+    moved.region = region.to_string();
+    let encoded_moved = codec::encode(&moved);
+
+    let mut invocation = FALL_BACK.prepare_invoke();
+    invocation
+        .key(forming_match_key(&player.player_id))
+        .key(player_raid_key(player))
+        .key(player_queue_key(player))
+        .key(player_queue_key(&moved))
+        .key(player_key(&player.player_id));
+    if player.creates_room() {
+        invocation
+            .key(player_create_match_key(player))
+            .key(player_create_match_key(&moved));
+    }
+    if player.is_versus() {
+        invocation
+            .key(player_versus_key(player))
+            .key(player_versus_key(&moved));
+    }
+    invocation
+        .arg(player.player_id)
+        .arg(encoded)
+        .arg(&encoded_moved)
+        .arg(moved.join_time)
+        .arg(DATA)
+        .arg(REGION)
+        .arg(&moved.region)
+        .arg(JOIN_TIME);
+
+    invocation.invoke_async(conn).await
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{collections::BTreeMap, sync::Arc};
+
+    use uuid::Uuid;
+
+    use super::*;
+    use crate::{
+        clock::SystemClock,
+        nakama::{Authenticated, NakamaClient},
+        regions::tuning::{RegionTuning, RegionTunings},
+        rpc::{
+            matchmaking::{JoinMode, Player},
+            server::TEN_MINUTES,
+        },
+        store,
+        test_support::{create_redis, redis_client},
+    };
+
+    #[tokio::test]
+    async fn waiting_players_move_to_the_fallback_region() {
+        let container = create_redis(6379).await;
+        let host = container.get_host().await.unwrap();
+        let port = container.get_host_port_ipv4(6379).await.unwrap();
+        let client = redis_client(host.to_string(), port);
+        let mut conn = client.get_multiplexed_async_connection().await.unwrap();
+        let mut worker = MatchmakingWorker::new(
+            conn.clone(),
+            Arc::new(reqwest::Client::new()),
+            auth_client(666).into(),
+            Arc::new(SystemClock::default()),
+        );
+        worker.region_tunings = RegionTunings(BTreeMap::from([(
+            "SA".to_string(),
+            RegionTuning {
+                fallback_region: Some("US".to_string()),
+                fallback_after_secs: 60,
+                ..Default::default()
+            },
+        )]));
+        let now = worker.clock.time_since_epoch();
+        let queued = |join_time: i64| -> QueuedPlayer {
+            let player: QueuedPlayer = (
+                Uuid::new_v4(),
+                Player {
+                    region: "SA".to_string(),
+                    join_mode: JoinMode::JoinRoom.into(),
+                    ..Default::default()
+                },
+                Default::default(),
+            )
+                .into();
+            player.joined_at(join_time)
+        };
+        let (waiting, recent) = (queued(now - 120), queued(now));
+        for player in [&waiting, &recent] {
+            let encoded = codec::encode(player);
+            let mut pipe = redis::pipe();
+            store::put_player(&mut pipe, player, &encoded, TEN_MINUTES);
+            let _: () = pipe.query_async(&mut conn).await.unwrap();
+            let _: () = conn
+                .zadd(player_queue_key(player), &encoded, player.join_time)
+                .await
+                .unwrap();
+        }
+
+        let moved = worker.fall_back_regions().await.unwrap();
+        let mut fallen = waiting.clone();
+        fallen.region = "US".to_string();
+        let us_queue: Vec<Vec<u8>> = conn.zrange(player_queue_key(&fallen), 0, -1).await.unwrap();
+        let sa_queue: Vec<Vec<u8>> = conn.zrange(player_queue_key(&recent), 0, -1).await.unwrap();
+        let stored = store::player_data(&mut conn, &waiting.player_id)
+            .await
+            .unwrap()
+            .unwrap();
+        let ttl: i64 = conn.ttl(player_key(&waiting.player_id)).await.unwrap();
+
+        assert_eq!(moved, 1);
+        assert_eq!(us_queue, vec![codec::encode(&fallen)]);
+        assert_eq!(sa_queue, vec![codec::encode(&recent)]);
+        assert_eq!(codec::decode::<QueuedPlayer>(&stored).unwrap(), fallen);
+        assert!(ttl > 0);
+    }
+
+    fn auth_client(port: u16) -> NakamaClient<Authenticated> {
+        NakamaClient {
+            username: "username".to_string(),
+            password: "password".to_string(),
+            token: Some("super_random_token".to_string()),
+            url: format!("http://127.0.0.1:{port}"),
+            server_key_name: "defaultkey".to_string(),
+            server_key_value: "server_key".to_string(),
+            encryption_key: "encryption_key".to_string(),
+            legacy_until: None,
+            _state: std::marker::PhantomData::<Authenticated>,
+        }
+    }
+}
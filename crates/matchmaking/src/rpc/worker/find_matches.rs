@@ -1,12 +1,27 @@
-use redis::{AsyncCommands, RedisError};
-use tracing::{error, info, warn};
+use redis::{AsyncCommands, RedisError, aio::MultiplexedConnection};
+use tokio::task::{JoinError, JoinSet};
+use tracing::{Instrument, error, info, warn};
 
 use crate::{
-    regions::REGIONS_KEY,
+    audit::{self, Action, AuditEvent},
+    codec,
+    config::MatchmakingConfig,
+    environment,
+    lifecycle::MatchState,
+    metrics::{fairness, worker::RunMetrics},
+    playlists,
+    regions::regions_key,
     rpc::{
-        CLOSED_MATCHES, QueuedPlayer, create_match_queue_key, match_data_key,
-        worker::MatchmakingWorker,
+        Match, QueuedPlayer, closed_matches_key, create_match_queue_key, match_data_key,
+        open_matches_key,
+        worker::{
+            MatchmakingWorker,
+            budget::TickBudget,
+            form_match,
+            scan::{Cursor, scan},
+        },
     },
+    store,
 };
 
 #[derive(Debug, thiserror::Error)]
@@ -16,51 +31,140 @@ pub enum Error {
     #[error(transparent)]
     Redis(#[from] RedisError),
     #[error(transparent)]
-    BitcodeDeser(#[from] bitcode::Error),
+    BitcodeDeser(#[from] codec::Error),
+    #[error(transparent)]
+    Playlists(#[from] playlists::Error),
+}
+
+/// Regions whose hosted matches are created concurrently
+pub const MAX_CONCURRENT_REGIONS: usize = 8;
+const HOSTED_SCAN: &str = "hosted";
+
+/// Matches created from a region create-match queue in one tick
+#[derive(Debug)]
+struct HostedMatches {
+    region_key: String,
+    cursor: Option<Cursor>,
+    created: Vec<Match>,
+    metrics: RunMetrics,
 }
 
 impl MatchmakingWorker {
+    fn collect_hosted(&mut self, created: Option<Result<HostedMatches, JoinError>>) {
+        match created {
+            Some(Ok(hosted)) => {
+                let scan_key = (HOSTED_SCAN, hosted.region_key);
+                match hosted.cursor {
+                    Some(cursor) => self.scan_cursors.insert(scan_key, cursor),
+                    None => self.scan_cursors.remove(&scan_key),
+                };
+                self.metrics.matches_created += hosted.created.len() as u64;
+                self.metrics.merge(hosted.metrics);
+                self.open_matches.extend(hosted.created);
+            }
+            Some(Err(err)) => error!("hosted matches task failed: {err}"),
+            None => {}
+        }
+    }
+
     pub async fn hosted_matches(&mut self) -> Result<(), Error> {
         let mut conn: redis::aio::MultiplexedConnection = self.redis.clone();
-        let Some(regions): Option<Vec<u8>> = conn.get(REGIONS_KEY).await? else {
+        let Some(regions): Option<Vec<u8>> = conn.get(regions_key()).await? else {
             error!("No regions registred");
             return Ok(());
         };
-        let regions: Vec<String> = bitcode::decode(regions.as_slice())?;
+        let regions: Vec<String> = codec::decode(regions.as_slice())?;
+        // raid playlists are assembled by `raid_matches`
+        let raids: Vec<String> = playlists::raid_queue_regions(&mut conn, &regions)
+            .await?
+            .into_iter()
+            .map(|(region, _)| region)
+            .collect();
+        let regions: Vec<String> = playlists::queue_regions(&mut conn, &regions)
+            .await?
+            .into_iter()
+            .filter(|region| {
+                !raids.contains(region) && self.region_tunings.get(region).is_due(self.runs)
+            })
+            .collect();
 
+        // regions share the multiplexed connection, at most `MAX_CONCURRENT_REGIONS` at a time
+        let now = self.clock.time_since_epoch();
+        let mut tasks = JoinSet::new();
         for region_key in regions.iter().map(create_match_queue_key) {
-            if let Ok(host_players) = conn.zrange::<_, Vec<Vec<u8>>>(&region_key, 0, -1).await {
-                for player in host_players.into_iter().filter_map(|player_bits| {
-                    bitcode::decode::<QueuedPlayer>(player_bits.as_slice()).ok()
-                }) {
-                    match self.create_match(&player).await {
-                        Ok(true) => info!("match created for player {}", player.player_id),
-                        Ok(false) => error!("match not created for player {}", player.player_id),
-                        Err(err) => error!(
-                            "failed to create match for player {}: {err}",
-                            player.player_id
-                        ),
-                    }
-                }
-            } else {
-                warn!("Failed to find open matches for region {region_key}");
+            if tasks.len() >= MAX_CONCURRENT_REGIONS {
+                self.collect_hosted(tasks.join_next().await);
             }
+            // the regions left keep their cursor for the next tick
+            if self.budget.is_spent() {
+                break;
+            }
+            let cursor = self
+                .scan_cursors
+                .get(&(HOSTED_SCAN, region_key.clone()))
+                .copied();
+            tasks.spawn(region_hosted_matches(
+                conn.clone(),
+                region_key,
+                cursor,
+                self.config,
+                self.budget.clone(),
+                now,
+            ));
+        }
+        while let Some(created) = tasks.join_next().await {
+            self.collect_hosted(Some(created));
         }
 
         if let Err(err) = self.remove_matched_players().await {
             error!("{err}");
         };
+        self.close_ready_matches(now).await;
+
+        Ok(())
+    }
 
+    /// Closes the open matches due to close whose ready-check passed, see [`crate::ready_check`]
+    pub async fn close_ready_matches(&mut self, now: i64) {
+        let mut conn = self.redis.clone();
         let mut open_matches = Vec::new();
 
         for (index, a_match) in self.open_matches.iter().enumerate() {
-            // TODO: Customize to player max expected okayers
-            if a_match.players.len() >= 4 {
-                if (conn.del(match_data_key(a_match)).await.map(|_: ()| ())).is_ok() {
-                    let encode = bitcode::encode(a_match);
-                    conn.zadd(CLOSED_MATCHES, encode, index)
+            let ready = self.ready_matches.remove(&a_match.id);
+            if ready
+                && self
+                    .region_tunings
+                    .get(&a_match.region)
+                    .should_close(a_match, now)
+            {
+                let mut ready = a_match.clone();
+                ready.balance();
+                ready.place(&self.region_tunings.get(&ready.region).datacenters);
+                if let Err(err) = ready.transition(MatchState::Ready, now) {
+                    error!("failed to close match `{}`: {err}", a_match.id);
+                    continue;
+                }
+                ready.challenge = environment::closing_challenge(&mut conn, &ready).await;
+                let mut pipe = redis::pipe();
+                pipe.del(match_data_key(a_match))
+                    .zrem(open_matches_key(&a_match.region), a_match.id);
+                store::index_match(&mut pipe, &ready);
+                let closed = pipe.query_async(&mut conn).await.map(|_: ()| ());
+                if closed.is_ok() {
+                    let encode = codec::encode(&ready);
+                    match conn
+                        .zadd(closed_matches_key(), encode, index)
                         .await
-                        .map(|_: ()| ())?;
+                        .map(|_: ()| ())
+                    {
+                        Ok(()) => {
+                            self.metrics.matches_closed += 1;
+                            let matched = AuditEvent::for_match(Action::Matched, &ready, now);
+                            audit::emit(&mut conn, &matched).await;
+                            fairness::observe(&mut conn, &ready).await;
+                        }
+                        Err(err) => error!("failed to close match `{}`: {err}", a_match.id),
+                    }
                 } else {
                     error!(
                         "failed to add match `{}` to closed matches queue",
@@ -73,8 +177,81 @@ impl MatchmakingWorker {
         }
 
         self.open_matches = open_matches;
+    }
+}
 
-        Ok(())
+/// Creates the matches hosted in the next scanned entries of a region queue
+async fn region_hosted_matches(
+    mut conn: MultiplexedConnection,
+    region_key: String,
+    cursor: Option<Cursor>,
+    config: MatchmakingConfig,
+    budget: TickBudget,
+    now: i64,
+) -> HostedMatches {
+    let mut metrics = RunMetrics::default();
+    // every entry hosts at most one match, the entries past the budget are read next tick
+    let Ok(scanned) = scan(
+        &mut conn,
+        &region_key,
+        cursor,
+        config.scan_batch_size,
+        config.scan_budget.min(budget.matches_left()),
+    )
+    .await
+    else {
+        warn!("Failed to find open matches for region {region_key}");
+        metrics.redis_errors += 1;
+        return HostedMatches {
+            region_key,
+            cursor,
+            created: Vec::new(),
+            metrics,
+        };
+    };
+
+    budget.spend_commands(scanned.commands);
+    let mut created = Vec::new();
+    for player in scanned
+        .entries
+        .into_iter()
+        .filter_map(|player_bits| metrics.decode::<QueuedPlayer>(&player_bits))
+    {
+        let span = player.span();
+        async {
+            match MatchmakingWorker::hosted_match(conn.clone(), &player, now).await {
+                Ok(Some(hosted)) => {
+                    info!("match created for player {}", player.player_id);
+                    budget.spend_match();
+                    created.push(hosted);
+                }
+                Ok(None) => error!("match not created for player {}", player.player_id),
+                Err(form_match::Error::MissingPartyMembers(missing)) => {
+                    warn!(
+                        "party members {missing:?} of player {} left the queue, dropping the host",
+                        player.player_id
+                    );
+                    if let Err(err) =
+                        MatchmakingWorker::drop_incomplete_party(&mut conn, &player, now).await
+                    {
+                        error!("failed to drop player {}: {err}", player.player_id);
+                    }
+                }
+                Err(err) => error!(
+                    "failed to create match for player {}: {err}",
+                    player.player_id
+                ),
+            }
+        }
+        .instrument(span)
+        .await;
+    }
+
+    HostedMatches {
+        region_key,
+        cursor: scanned.cursor,
+        created,
+        metrics,
     }
 }
 
@@ -84,17 +261,14 @@ mod tests {
 
     use redis::aio::MultiplexedConnection;
     use skillratings::mhth::MhthRating;
-    use testcontainers::{
-        ContainerAsync, GenericImage, ImageExt,
-        core::{IntoContainerPort, WaitFor},
-        runners::AsyncRunner,
-    };
     use uuid::Uuid;
 
     use super::*;
     use crate::{
+        clock::SystemClock,
         nakama::{Authenticated, NakamaClient},
         rpc::{Match, matchmaking::Player, player_queue_key},
+        test_support::{create_redis, redis_client},
     };
 
     #[tokio::test]
@@ -177,10 +351,11 @@ mod tests {
         .iter()
         .enumerate()
         {
-            let encode = bitcode::encode(p);
+            let encode = codec::encode(p);
             let key = player_queue_key(p);
-            conn.clone()
-                .set_ex(p.player_id, &encode, 200)
+            let mut pipe = redis::pipe();
+            store::put_player(&mut pipe, p, &encode, 200);
+            pipe.query_async(&mut conn.clone())
                 .await
                 .map(|_: ()| ())
                 .unwrap();
@@ -192,7 +367,7 @@ mod tests {
         }
         // set hosted match
         let create_match_key = create_match_queue_key(&player.region);
-        let encoded_player = bitcode::encode(&player);
+        let encoded_player = codec::encode(&player);
         conn.clone()
             .zadd(create_match_key, &encoded_player, 1)
             .await
@@ -202,18 +377,29 @@ mod tests {
             conn.clone(),
             Arc::new(reqwest::Client::new()),
             nakama.into(),
+            Arc::new(SystemClock::default()),
         );
         worker.hosted_matches().await.unwrap();
+        worker.ready_checks().await.unwrap();
+        let match_id = worker.open_matches[0].id;
+        for player_id in [host_id, friend_1_id, friend_2_id, friend_3_id] {
+            crate::ready_check::confirm(&mut conn.clone(), match_id, player_id)
+                .await
+                .unwrap();
+        }
+        assert_eq!(worker.ready_checks().await.unwrap(), 1);
+        worker
+            .close_ready_matches(worker.clock.time_since_epoch())
+            .await;
         let closed_matches = conn
             .clone()
-            .zrange::<&str, Vec<Vec<u8>>>(CLOSED_MATCHES, 0, -1)
+            .zrange::<_, Vec<Vec<u8>>>(closed_matches_key(), 0, -1)
             .await
             .unwrap();
-        container.pause().await.unwrap();
 
         assert_eq!(worker.open_matches, vec![]);
         assert_eq!(closed_matches.len(), 1);
-        let closed_match: Match = bitcode::decode(closed_matches[0].as_slice()).unwrap();
+        let closed_match: Match = codec::decode(closed_matches[0].as_slice()).unwrap();
 
         assert_eq!(closed_match.host_id, host_id);
     }
@@ -228,21 +414,6 @@ mod tests {
         crate::regions::set_regions(conn, regions).await.unwrap();
     }
 
-    fn redis_client(host: String, port: u16) -> redis::Client {
-        redis::Client::open(format!("redis://{host}:{port}")).unwrap()
-    }
-
-    async fn create_redis(port: u16) -> ContainerAsync<GenericImage> {
-        GenericImage::new("redis", "8.2.1-bookworm")
-            .with_exposed_port(port.tcp())
-            .with_wait_for(WaitFor::message_on_stdout("Ready to accept connections"))
-            .with_env_var("REDIS_PASSWORD", "super-secret-password")
-            .with_env_var("REDIS_USER", "redis_mms_admin")
-            .start()
-            .await
-            .expect("Failed to start Redis")
-    }
-
     pub fn auth_client(port: u16) -> NakamaClient<Authenticated> {
         NakamaClient {
             username: "username".to_string(),
@@ -252,6 +423,7 @@ mod tests {
             server_key_name: "defaultkey".to_string(),
             server_key_value: "server_key".to_string(),
             encryption_key: "encryption_key".to_string(),
+            legacy_until: None,
             _state: std::marker::PhantomData::<Authenticated>,
         }
     }
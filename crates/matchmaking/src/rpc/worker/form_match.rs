@@ -1,12 +1,19 @@
 use std::str::FromStr;
 
-use redis::{AsyncCommands, RedisError};
+use redis::{RedisError, aio::MultiplexedConnection};
 use tracing::error;
 use uuid::Uuid;
 
-use crate::rpc::{
-    self, Match, QueuedPlayer, match_data_key, matchmaking::JoinMode, player_queue_key,
-    server::TWO_HOURS, worker::MatchmakingWorker,
+use crate::{
+    audit::{self, Action, Actor, AuditEvent},
+    codec,
+    lifecycle::Lifecycle,
+    notifications::{self, Notification},
+    rpc::{
+        self, Match, QueuedPlayer, player_create_match_key, player_key, player_queue_key,
+        player_versus_key, server::TWELVE_MINUTES, worker::MatchmakingWorker,
+    },
+    store::{self, MatchStore, RedisStore},
 };
 
 #[derive(Debug, thiserror::Error)]
@@ -17,19 +24,26 @@ pub enum Error {
     Redis(#[from] RedisError),
     #[error("failed to deserialize queued player")]
     BitcodeDeser,
+    #[error("party members left the queue: {0:?}")]
+    MissingPartyMembers(Vec<Uuid>),
     #[error(transparent)]
     CanMatch(#[from] rpc::worker::can_match::Error),
 }
 
 impl MatchmakingWorker {
-    pub(crate) async fn create_match(&mut self, player: &QueuedPlayer) -> Result<bool, Error> {
-        let create_room: i32 = JoinMode::CreateRoom.into();
-        if player.join_mode != create_room {
-            return Ok(false);
+    /// Forms the match hosted by `player` at `now`, `None` when the player does not create a room.
+    /// Fails when a party member left the queue, a party never launches short-handed.
+    pub(crate) async fn hosted_match(
+        mut conn: MultiplexedConnection,
+        player: &QueuedPlayer,
+        now: i64,
+    ) -> Result<Option<Match>, Error> {
+        if !player.creates_room() {
+            return Ok(None);
         }
 
-        let mut conn = self.redis.clone();
         let mut party = Vec::new();
+        let mut missing = Vec::new();
         for friend in &player.party_ids {
             let friend_id = Uuid::from_str(friend)
                 .inspect_err(|err| {
@@ -40,50 +54,67 @@ impl MatchmakingWorker {
                 })
                 .map_err(|_| Error::InvalidFriendId(friend.to_owned()))?;
 
-            let Some(data): Option<Vec<u8>> = conn.get(friend_id).await? else {
+            let Some(data) = store::player_data(&mut conn, &friend_id).await? else {
+                missing.push(friend_id);
                 continue;
             };
-            let friend_data: QueuedPlayer = bitcode::decode(&data)
+            let friend_data: QueuedPlayer = codec::decode(&data)
                 .inspect_err(|err| error!("{err}"))
                 .map_err(|_| Error::BitcodeDeser)?;
 
             party.push(friend_data);
         }
+        if !missing.is_empty() {
+            return Err(Error::MissingPartyMembers(missing));
+        }
 
-        let hosted_match = Match::host(player, &party)?;
-
-        self.open_matches.push(hosted_match.clone());
+        let mut hosted_match = Match::host(player, &party)?;
+        hosted_match.lifecycle = Lifecycle::forming(now);
 
-        if let Err(err) = self.form_match(hosted_match).await {
+        if let Err(err) = RedisStore::new(conn)
+            .open_match(&hosted_match, TWELVE_MINUTES)
+            .await
+        {
             error!("failed to create match {err}");
-            Ok(false)
+            Ok(None)
         } else {
-            Ok(true)
+            Ok(Some(hosted_match))
         }
     }
 
-    async fn form_match(&self, new_match: Match) -> Result<(), Error> {
-        let encode_match = bitcode::encode(&new_match);
-        let redis_match_data_key = match_data_key(&new_match);
-
-        let mut conn = self.redis.clone();
-
-        conn.set_ex(&redis_match_data_key, &encode_match, TWO_HOURS)
-            .await
-            .map(|_: ()| ())?;
+    /// Drops a host whose party members left the queue, so the party is not retried every tick,
+    /// and tells them to queue again
+    pub(crate) async fn drop_incomplete_party(
+        conn: &mut MultiplexedConnection,
+        host: &QueuedPlayer,
+        now: i64,
+    ) -> Result<(), RedisError> {
+        let mut pipe = redis::pipe();
+        pipe.atomic();
+        store::dequeue(
+            &mut pipe,
+            &host.player_id,
+            &[
+                player_queue_key(host),
+                player_create_match_key(host),
+                player_versus_key(host),
+            ],
+        );
+        pipe.del(player_key(&host.player_id)).ignore();
+        pipe.query_async(&mut *conn).await.map(|_: ()| ())?;
+        let left = AuditEvent::new(Action::PlayerLeft, Actor::Worker, host.player_id, now)
+            .with_reason("party members left");
+        audit::emit(conn, &left).await;
 
-        Ok(())
+        notifications::notify(conn, &[host.player_id], &Notification::QueueTimeout).await
     }
 
     pub(crate) async fn remove_matched_players(&self) -> Result<(), Error> {
         let mut conn = self.redis.clone();
-        for (key, player) in self
-            .open_matches
-            .iter()
-            .flat_map(|mtc| mtc.players.iter())
-            .map(|player| (player_queue_key(player), bitcode::encode(player)))
-        {
-            if let Err(err) = conn.zrem(key, player).await.map(|_: ()| ()) {
+        for player in self.open_matches.iter().flat_map(|mtc| mtc.players.iter()) {
+            let mut pipe = redis::pipe();
+            store::dequeue(&mut pipe, &player.player_id, &[player_queue_key(player)]);
+            if let Err(err) = pipe.query_async(&mut conn).await.map(|_: ()| ()) {
                 error!("failed to remove matched player: {err}");
             };
         }
@@ -96,18 +127,15 @@ impl MatchmakingWorker {
 mod tests {
     use std::sync::Arc;
 
-    use redis::aio::MultiplexedConnection;
+    use redis::{AsyncCommands, aio::MultiplexedConnection};
     use skillratings::mhth::MhthRating;
-    use testcontainers::{
-        ContainerAsync, GenericImage, ImageExt,
-        core::{IntoContainerPort, WaitFor},
-        runners::AsyncRunner,
-    };
 
     use super::*;
     use crate::{
+        clock::SystemClock,
         nakama::{Authenticated, NakamaClient},
         rpc::matchmaking::Player,
+        test_support::{create_redis, redis_client},
     };
 
     #[tokio::test]
@@ -124,19 +152,14 @@ mod tests {
         let container = create_redis(6379).await;
         let host = container.get_host().await.unwrap();
         let port = container.get_host_port_ipv4(6379).await.unwrap();
-        let client = redis_client(host.to_string(), port).await;
+        let client = redis_client(host.to_string(), port);
         let conn = client.get_multiplexed_async_connection().await.unwrap();
 
-        let mut worker = MatchmakingWorker::new(
-            conn,
-            Arc::new(reqwest::Client::new()),
-            auth_client(666).into(),
-        );
-
-        let not_created = worker.create_match(&player).await.unwrap();
+        let not_created = MatchmakingWorker::hosted_match(conn, &player, 0)
+            .await
+            .unwrap();
 
-        container.pause().await.unwrap();
-        assert!(!not_created)
+        assert!(not_created.is_none())
     }
 
     #[tokio::test]
@@ -175,28 +198,27 @@ mod tests {
         let container = create_redis(6379).await;
         let host = container.get_host().await.unwrap();
         let port = container.get_host_port_ipv4(6379).await.unwrap();
-        let client = redis_client(host.to_string(), port).await;
+        let client = redis_client(host.to_string(), port);
         let conn = client.get_multiplexed_async_connection().await.unwrap();
 
         // Sets friends to create match
-        for (id, friend) in [(friend_1_id, friend_1), (friend_2_id, friend_2)] {
-            let encode = bitcode::encode(&friend);
-            conn.clone().set(id, encode).await.map(|_: ()| ()).unwrap();
+        for friend in [friend_1, friend_2] {
+            let mut pipe = redis::pipe();
+            store::put_player(&mut pipe, &friend, &codec::encode(&friend), 200);
+            pipe.query_async(&mut conn.clone())
+                .await
+                .map(|_: ()| ())
+                .unwrap();
         }
 
-        let mut worker = MatchmakingWorker::new(
-            conn,
-            Arc::new(reqwest::Client::new()),
-            auth_client(666).into(),
-        );
-
-        let created = worker.create_match(&player).await.unwrap();
-        container.pause().await.unwrap();
+        let created = MatchmakingWorker::hosted_match(conn, &player, 0)
+            .await
+            .unwrap()
+            .unwrap();
 
-        assert!(created);
-        assert_eq!(worker.open_matches[0].host_id, host_id);
+        assert_eq!(created.host_id, host_id);
         assert_eq!(
-            worker.open_matches[0]
+            created
                 .players
                 .iter()
                 .map(|p| p.player_id)
@@ -205,6 +227,53 @@ mod tests {
         );
     }
 
+    #[tokio::test]
+    async fn hosts_of_incomplete_parties_are_dropped() {
+        let missing_id = Uuid::new_v4();
+        let player: QueuedPlayer = (
+            Uuid::new_v4(),
+            Player {
+                join_mode: 0,
+                party_member_id: vec![missing_id.to_string()],
+                ..Default::default()
+            },
+            MhthRating::default(),
+        )
+            .into();
+        let container = create_redis(6379).await;
+        let host = container.get_host().await.unwrap();
+        let port = container.get_host_port_ipv4(6379).await.unwrap();
+        let client = redis_client(host.to_string(), port);
+        let mut conn = client.get_multiplexed_async_connection().await.unwrap();
+        let encoded = codec::encode(&player);
+        let mut pipe = redis::pipe();
+        store::put_player(&mut pipe, &player, &encoded, 200);
+        rpc::enqueue(&mut pipe, &player, &encoded, false);
+        pipe.query_async(&mut conn).await.map(|_: ()| ()).unwrap();
+
+        let err = MatchmakingWorker::hosted_match(conn.clone(), &player, 0)
+            .await
+            .unwrap_err();
+        MatchmakingWorker::drop_incomplete_party(&mut conn, &player, 0)
+            .await
+            .unwrap();
+        let queue: Vec<Vec<u8>> = conn.zrange(player_queue_key(&player), 0, -1).await.unwrap();
+        let hosts: Vec<Vec<u8>> = conn
+            .zrange(player_create_match_key(&player), 0, -1)
+            .await
+            .unwrap();
+        let queued: bool = conn.exists(player_key(&player.player_id)).await.unwrap();
+        let events = notifications::drain(&mut conn, &player.player_id)
+            .await
+            .unwrap();
+
+        assert!(matches!(err, Error::MissingPartyMembers(ids) if ids == vec![missing_id]));
+        assert!(queue.is_empty());
+        assert!(hosts.is_empty());
+        assert!(!queued);
+        assert_eq!(events, vec![Notification::QueueTimeout]);
+    }
+
     #[tokio::test]
     async fn form_match_sets_redis_data() {
         let match_id = Uuid::new_v4();
@@ -215,32 +284,39 @@ mod tests {
             host_id: host_player.player_id,
             players: vec![host_player.clone()],
             region: "CAN".to_string(),
+            playlist: String::new(),
+            experiments: Vec::new(),
+            params: Default::default(),
+            lifecycle: Default::default(),
+            nakama_match_id: None,
+            game_server: None,
+            squads: Vec::new(),
+            kind: Default::default(),
+            challenge: None,
+            datacenter: None,
+            difficulty: 0,
+            settings: None,
+            voice_chat: 0,
         };
         let container = create_redis(6379).await;
         let host = container.get_host().await.unwrap();
         let port = container.get_host_port_ipv4(6379).await.unwrap();
-        let client = redis_client(host.to_string(), port).await;
+        let client = redis_client(host.to_string(), port);
         let mut conn = client.get_multiplexed_async_connection().await.unwrap();
         init_regions(conn.clone()).await;
 
-        let worker = MatchmakingWorker::new(
-            conn.clone(),
-            Arc::new(reqwest::Client::new()),
-            auth_client(666).into(),
-        );
-        let redis_match_data_key = match_data_key(&new_match);
-
-        worker.form_match(new_match).await.unwrap();
+        crate::store::RedisStore::new(conn.clone())
+            .open_match(&new_match, TWELVE_MINUTES)
+            .await
+            .unwrap();
 
-        let stored: Vec<u8> = conn
-            .get(redis_match_data_key)
+        let stored = store::match_data(&mut conn, &new_match.id)
             .await
-            .map(|u: Vec<u8>| u)
+            .unwrap()
             .unwrap();
         let empty_key: Result<Option<Vec<u8>>, RedisError> = conn.get("random-key").await;
 
-        container.pause().await.unwrap();
-        let decoded: Match = bitcode::decode(&stored).unwrap();
+        let decoded: Match = codec::decode(&stored).unwrap();
 
         assert_eq!(decoded.host_id, host_player.player_id);
         assert_eq!(decoded.id, match_id);
@@ -284,7 +360,7 @@ mod tests {
         let container = create_redis(6379).await;
         let host = container.get_host().await.unwrap();
         let port = container.get_host_port_ipv4(6379).await.unwrap();
-        let client = redis_client(host.to_string(), port).await;
+        let client = redis_client(host.to_string(), port);
         let mut conn = client.get_multiplexed_async_connection().await.unwrap();
 
         // Sets friends to create match
@@ -292,7 +368,7 @@ mod tests {
             .iter()
             .enumerate()
         {
-            let encode = bitcode::encode(p);
+            let encode = codec::encode(p);
             let key = player_queue_key(p);
             conn.clone()
                 .zadd(key, encode, score)
@@ -312,6 +388,7 @@ mod tests {
             conn.clone(),
             Arc::new(reqwest::Client::new()),
             auth_client(666).into(),
+            Arc::new(SystemClock::default()),
         );
         worker.open_matches.push(mtc);
 
@@ -321,25 +398,9 @@ mod tests {
             .zcount(player_queue_key(&player), 0, 100)
             .await
             .unwrap();
-        container.pause().await.unwrap();
         assert_eq!(count, 1);
     }
 
-    async fn redis_client(host: String, port: u16) -> redis::Client {
-        redis::Client::open(format!("redis://{host}:{port}")).unwrap()
-    }
-
-    async fn create_redis(port: u16) -> ContainerAsync<GenericImage> {
-        GenericImage::new("redis", "8.2.1-bookworm")
-            .with_exposed_port(port.tcp())
-            .with_wait_for(WaitFor::message_on_stdout("Ready to accept connections"))
-            .with_env_var("REDIS_PASSWORD", "super-secret-password")
-            .with_env_var("REDIS_USER", "redis_mms_admin")
-            .start()
-            .await
-            .expect("Failed to start Redis")
-    }
-
     pub fn auth_client(port: u16) -> NakamaClient<Authenticated> {
         NakamaClient {
             username: "username".to_string(),
@@ -349,6 +410,7 @@ mod tests {
             server_key_name: "defaultkey".to_string(),
             server_key_value: "server_key".to_string(),
             encryption_key: "encryption_key".to_string(),
+            legacy_until: None,
             _state: std::marker::PhantomData::<Authenticated>,
         }
     }
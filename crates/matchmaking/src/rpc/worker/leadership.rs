@@ -0,0 +1,69 @@
+use redis::{AsyncCommands, RedisError};
+use tracing::{info, warn};
+use uuid::Uuid;
+
+use crate::{
+    codec,
+    leader::{self, LEASE_INTERVALS},
+    regions::regions_key,
+    rpc::{Match, open_matches_key, worker::MatchmakingWorker},
+    store,
+};
+
+#[derive(Debug, thiserror::Error)]
+pub enum Error {
+    #[error(transparent)]
+    Redis(#[from] RedisError),
+    #[error(transparent)]
+    BitcodeDeser(#[from] codec::Error),
+}
+
+impl MatchmakingWorker {
+    /// Renews or takes the leader lease, returns `false` when another replica leads
+    pub async fn ensure_leader(&mut self) -> Result<bool, Error> {
+        let lease = self.config.worker_interval() * LEASE_INTERVALS;
+        let elected = leader::acquire(&mut self.redis, &self.instance_id, lease).await?;
+
+        match (self.is_leader, elected) {
+            (false, true) => {
+                info!("worker `{}` elected leader", self.instance_id);
+                self.restore_open_matches().await?;
+            }
+            (true, false) => {
+                warn!("worker `{}` lost the leader lease", self.instance_id);
+                self.open_matches.clear();
+                self.ready_matches.clear();
+            }
+            _ => {}
+        }
+        self.is_leader = elected;
+
+        Ok(elected)
+    }
+
+    /// Picks up the matches formed by the previous leader
+    async fn restore_open_matches(&mut self) -> Result<(), Error> {
+        let mut conn = self.redis.clone();
+        let Some(regions): Option<Vec<u8>> = conn.get(regions_key()).await? else {
+            return Ok(());
+        };
+        let regions: Vec<String> = codec::decode(&regions)?;
+
+        let mut open_matches = Vec::new();
+        for region in &regions {
+            let match_ids: Vec<Uuid> = conn.zrange(open_matches_key(region), 0, -1).await?;
+            if match_ids.is_empty() {
+                continue;
+            }
+            let data = store::matches_data(&mut conn, &match_ids).await?;
+            open_matches.extend(
+                data.into_iter()
+                    .flatten()
+                    .filter_map(|bits| codec::decode::<Match>(&bits).ok()),
+            );
+        }
+        self.open_matches = open_matches;
+
+        Ok(())
+    }
+}
@@ -0,0 +1,219 @@
+use redis::{AsyncCommands, RedisError, aio::MultiplexedConnection};
+use tracing::{error, info};
+use uuid::Uuid;
+
+use crate::{
+    claim, codec, lobby,
+    notifications::{self, Notification},
+    rpc::{
+        Match, QueuedPlayer, enqueue, forming_match_key, open_matches_key, player_key,
+        server::TWELVE_MINUTES, worker::MatchmakingWorker,
+    },
+    store,
+};
+
+#[derive(Debug, thiserror::Error)]
+pub enum Error {
+    #[error(transparent)]
+    Redis(#[from] RedisError),
+}
+
+impl MatchmakingWorker {
+    /// Removes the players kicked by their host from the open matches, returns how many left
+    pub async fn apply_lobby_kicks(&mut self) -> Result<usize, Error> {
+        let mut conn = self.redis.clone();
+        let mut kicked_count = 0;
+        for open_match in &mut self.open_matches {
+            if !conn.exists(lobby::lobby_kicks_key(&open_match.id)).await? {
+                continue;
+            }
+            let Some((claim, reloaded)) = claim::acquire_forming(&mut conn, &open_match.id).await?
+            else {
+                info!("match `{}` is claimed, kicking later", open_match.id);
+                continue;
+            };
+            *open_match = reloaded;
+            let applied = apply_kicks(&mut conn, open_match).await;
+            claim::release(&mut conn, claim).await?;
+            let (kicked, requeued) = match applied {
+                Ok(applied) => applied,
+                Err(err) => {
+                    error!("failed to update match `{}`: {err}", open_match.id);
+                    continue;
+                }
+            };
+            kicked_count += kicked.len();
+
+            for player in &kicked {
+                let notification = Notification::KickedFromLobby {
+                    match_id: open_match.id,
+                    requeued: requeued.contains(&player.player_id),
+                };
+                if let Err(err) =
+                    notifications::notify(&mut conn, &[player.player_id], &notification).await
+                {
+                    error!(
+                        "failed to notify kicked player `{}`: {err}",
+                        player.player_id
+                    );
+                }
+            }
+        }
+
+        Ok(kicked_count)
+    }
+}
+
+/// Removes the players kicked from `open_match`, claimed by the caller, and queues again those
+/// whose host asked for it. Returns the players kicked and the ids of those queued again.
+async fn apply_kicks(
+    conn: &mut MultiplexedConnection,
+    open_match: &mut Match,
+) -> Result<(Vec<QueuedPlayer>, Vec<Uuid>), RedisError> {
+    let kicks = lobby::pending_kicks(conn, &open_match.id).await?;
+    let (kicked, players): (Vec<QueuedPlayer>, Vec<QueuedPlayer>) =
+        open_match.players.drain(..).partition(|player| {
+            player.player_id != open_match.host_id && kicks.contains_key(&player.player_id)
+        });
+    open_match.players = players;
+
+    let mut pipe = redis::pipe();
+    pipe.atomic();
+    store::put_match(&mut pipe, open_match, TWELVE_MINUTES);
+    // kicks stay pending until the match is written, a failed pass retries them
+    lobby::clear_kicks(&mut pipe, &open_match.id, kicks.keys());
+    pipe.zadd(
+        open_matches_key(&open_match.region),
+        open_match.id,
+        open_match.players.len(),
+    )
+    .ignore();
+    let kicked_ids: Vec<_> = kicked.iter().map(|player| player.player_id).collect();
+    store::unindex_players(&mut pipe, &kicked_ids);
+    let mut requeued = Vec::new();
+    for player in &kicked {
+        pipe.del(forming_match_key(&player.player_id)).ignore();
+        let queued: bool = conn.exists(player_key(&player.player_id)).await?;
+        if queued && kicks[&player.player_id] {
+            requeue(&mut pipe, player, open_match.is_raid());
+            requeued.push(player.player_id);
+        } else {
+            pipe.del(player_key(&player.player_id)).ignore();
+        }
+    }
+    pipe.query_async(conn).await.map(|_: ()| ())?;
+
+    Ok((kicked, requeued))
+}
+
+/// Returns a player leaving a forming match to its queues, with its original join time, see
+/// [`enqueue`]
+pub(crate) fn requeue(pipe: &mut redis::Pipeline, player: &QueuedPlayer, raid: bool) {
+    enqueue(pipe, player, &codec::encode(player), raid);
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use skillratings::mhth::MhthRating;
+    use uuid::Uuid;
+
+    use super::*;
+    use crate::{
+        clock::SystemClock,
+        nakama::{Authenticated, NakamaClient},
+        rpc::{Match, matchmaking::Player, player_queue_key},
+        test_support::{create_redis, redis_client},
+    };
+
+    #[tokio::test]
+    async fn kicked_players_leave_the_lobby() {
+        let container = create_redis(6379).await;
+        let host = container.get_host().await.unwrap();
+        let port = container.get_host_port_ipv4(6379).await.unwrap();
+        let client = redis_client(host.to_string(), port);
+        let mut conn = client.get_multiplexed_async_connection().await.unwrap();
+        let queued = |join_mode, party_ids| -> QueuedPlayer {
+            (
+                Uuid::new_v4(),
+                Player {
+                    join_mode,
+                    region: "CAN".to_string(),
+                    party_member_id: party_ids,
+                    ..Default::default()
+                },
+                MhthRating::default(),
+            )
+                .into()
+        };
+        let invited = queued(1, Vec::new());
+        let stranger = queued(1, Vec::new());
+        let match_host = queued(0, vec![invited.player_id.to_string()]);
+        let mut pipe = redis::pipe();
+        for player in [&invited, &stranger, &match_host] {
+            store::put_player(&mut pipe, player, &codec::encode(player), 200);
+        }
+        let mut open_match = Match::host(&match_host, std::slice::from_ref(&invited)).unwrap();
+        open_match.players.push(stranger.clone());
+        store::put_match(&mut pipe, &open_match, 200);
+        let _: () = pipe.query_async(&mut conn).await.unwrap();
+        let mut worker = MatchmakingWorker::new(
+            conn.clone(),
+            Arc::new(reqwest::Client::new()),
+            auth_client(666).into(),
+            Arc::new(SystemClock::default()),
+        );
+        worker.open_matches.push(open_match.clone());
+
+        for kicked in [&invited, &stranger] {
+            lobby::kick(
+                &mut conn,
+                open_match.id,
+                match_host.player_id,
+                kicked.player_id,
+            )
+            .await
+            .unwrap();
+        }
+        let kicked = worker.apply_lobby_kicks().await.unwrap();
+        let queue: Vec<Vec<u8>> = conn
+            .zrange(player_queue_key(&stranger), 0, -1)
+            .await
+            .unwrap();
+        let invited_queued: bool = conn.exists(player_key(&invited.player_id)).await.unwrap();
+        let pending = lobby::pending_kicks(&mut conn, &open_match.id)
+            .await
+            .unwrap();
+        let events = notifications::drain(&mut conn, &stranger.player_id)
+            .await
+            .unwrap();
+
+        assert_eq!(kicked, 2);
+        assert_eq!(worker.open_matches[0].players.len(), 1);
+        assert_eq!(queue, vec![codec::encode(&stranger)]);
+        assert!(!invited_queued);
+        assert!(pending.is_empty());
+        assert_eq!(
+            events,
+            vec![Notification::KickedFromLobby {
+                match_id: open_match.id,
+                requeued: true
+            }]
+        );
+    }
+
+    fn auth_client(port: u16) -> NakamaClient<Authenticated> {
+        NakamaClient {
+            username: "username".to_string(),
+            password: "password".to_string(),
+            token: Some("super_random_token".to_string()),
+            url: format!("http://127.0.0.1:{port}"),
+            server_key_name: "defaultkey".to_string(),
+            server_key_value: "server_key".to_string(),
+            encryption_key: "encryption_key".to_string(),
+            legacy_until: None,
+            _state: std::marker::PhantomData::<Authenticated>,
+        }
+    }
+}
@@ -0,0 +1,222 @@
+use redis::{AsyncCommands, RedisError};
+use tracing::{error, info};
+
+use crate::{
+    codec,
+    party::{self, HostChange},
+    playlists,
+    regions::regions_key,
+    rpc::{
+        QueuedPlayer, create_match_queue_key, player_key, player_queue_key,
+        worker::{MatchmakingWorker, scan::scan},
+    },
+};
+
+const MIGRATE_SCAN: &str = "migrate";
+
+#[derive(Debug, thiserror::Error)]
+pub enum Error {
+    #[error(transparent)]
+    Redis(#[from] RedisError),
+    #[error(transparent)]
+    BitcodeDeser(#[from] codec::Error),
+    #[error(transparent)]
+    Party(#[from] party::Error),
+    #[error(transparent)]
+    Playlists(#[from] playlists::Error),
+}
+
+impl MatchmakingWorker {
+    /// Removes hosts whose queue entry expired and hands their party over to another
+    /// queued member, or disbands it when nobody is left to host.
+    pub async fn migrate_expired_hosts(&mut self) -> Result<usize, Error> {
+        let mut conn = self.redis.clone();
+        let Some(regions): Option<Vec<u8>> = conn.get(regions_key()).await? else {
+            error!("No regions registred");
+            return Ok(0);
+        };
+        let regions: Vec<String> = codec::decode(regions.as_slice())?;
+        let regions = playlists::queue_regions(&mut conn, &regions).await?;
+
+        let mut count = 0;
+        for region_key in regions.iter().map(create_match_queue_key) {
+            let scan_key = (MIGRATE_SCAN, region_key.clone());
+            let hosts = scan(
+                &mut conn,
+                &region_key,
+                self.scan_cursors.get(&scan_key).copied(),
+                self.config.scan_batch_size,
+                self.config.scan_budget,
+            )
+            .await?;
+            match hosts.cursor {
+                Some(cursor) => self.scan_cursors.insert(scan_key, cursor),
+                None => self.scan_cursors.remove(&scan_key),
+            };
+            for (host, encoded) in hosts.entries.iter().filter_map(|player_bits| {
+                Some((
+                    codec::decode::<QueuedPlayer>(player_bits.as_slice()).ok()?,
+                    player_bits,
+                ))
+            }) {
+                if conn.exists(player_key(&host.player_id)).await? {
+                    continue;
+                }
+                redis::pipe()
+                    .zrem(&region_key, encoded)
+                    .ignore()
+                    .zrem(player_queue_key(&host), encoded)
+                    .ignore()
+                    .query_async(&mut conn)
+                    .await
+                    .map(|_: ()| ())?;
+                count += 1;
+
+                let Some(mut party) = party::player_party(&mut conn, &host.player_id).await? else {
+                    continue;
+                };
+                if party.host_id != host.player_id {
+                    continue;
+                }
+                match party::hand_over(&mut conn, &mut party, true).await? {
+                    HostChange::Promoted(new_host) => info!(
+                        "party `{}` host `{}` expired, promoted `{new_host}`",
+                        party.id, host.player_id
+                    ),
+                    HostChange::Disbanded => info!(
+                        "party `{}` host `{}` expired, party disbanded",
+                        party.id, host.player_id
+                    ),
+                }
+            }
+        }
+
+        Ok(count)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use redis::aio::MultiplexedConnection;
+    use skillratings::mhth::MhthRating;
+    use uuid::Uuid;
+
+    use super::*;
+    use crate::{
+        clock::SystemClock,
+        nakama::{Authenticated, NakamaClient},
+        notifications::{self, Notification},
+        party::Party,
+        rpc::matchmaking::{JoinMode, Player},
+        store,
+        test_support::{create_redis, redis_client},
+    };
+
+    #[tokio::test]
+    async fn expired_host_promotes_queued_member() {
+        let host_id = Uuid::new_v4();
+        let friend_id = Uuid::new_v4();
+        let host: QueuedPlayer = (
+            host_id,
+            Player {
+                join_mode: 0,
+                region: "CAN".to_string(),
+                party_member_id: vec![friend_id.to_string()],
+                ..Default::default()
+            },
+            MhthRating::default(),
+        )
+            .into();
+        let friend: QueuedPlayer = (
+            friend_id,
+            Player {
+                join_mode: 1,
+                region: "CAN".to_string(),
+                ..Default::default()
+            },
+            MhthRating::default(),
+        )
+            .into();
+        let container = create_redis(6379).await;
+        let redis_host = container.get_host().await.unwrap();
+        let port = container.get_host_port_ipv4(6379).await.unwrap();
+        let client = redis_client(redis_host.to_string(), port);
+        let mut conn = client.get_multiplexed_async_connection().await.unwrap();
+        init_regions(conn.clone()).await;
+
+        let mut party = Party::new(host_id);
+        party.invite(host_id, friend_id).unwrap();
+        party.accept(friend_id).unwrap();
+        party::save_party(&mut conn, &party).await.unwrap();
+        let party_id = party.id;
+        // Host entry only lives in the queues, its player key expired
+        let encoded_host = codec::encode(&host);
+        conn.zadd(create_match_queue_key(&host.region), &encoded_host, 1)
+            .await
+            .map(|_: ()| ())
+            .unwrap();
+        let encoded_friend = codec::encode(&friend);
+        let mut pipe = redis::pipe();
+        store::put_player(&mut pipe, &friend, &encoded_friend, 200);
+        pipe.query_async(&mut conn).await.map(|_: ()| ()).unwrap();
+        conn.zadd(player_queue_key(&friend), &encoded_friend, 2)
+            .await
+            .map(|_: ()| ())
+            .unwrap();
+
+        let mut worker = MatchmakingWorker::new(
+            conn.clone(),
+            Arc::new(reqwest::Client::new()),
+            auth_client(666).into(),
+            Arc::new(SystemClock::default()),
+        );
+        let migrated = worker.migrate_expired_hosts().await.unwrap();
+
+        let hosts: Vec<Vec<u8>> = conn
+            .zrange(create_match_queue_key(&host.region), 0, -1)
+            .await
+            .unwrap();
+        let migrated_party = party::player_party(&mut conn, &friend_id).await.unwrap();
+        let events = notifications::drain(&mut conn, &friend_id).await.unwrap();
+
+        assert_eq!(migrated, 1);
+        assert_eq!(hosts.len(), 1);
+        let new_host: QueuedPlayer = codec::decode(&hosts[0]).unwrap();
+        assert_eq!(new_host.player_id, friend_id);
+        assert_eq!(new_host.join_mode, i32::from(JoinMode::CreateRoom));
+        assert_eq!(migrated_party.unwrap().host_id, friend_id);
+        assert_eq!(
+            events,
+            vec![Notification::PartyHostChanged {
+                party_id,
+                host_id: friend_id
+            }]
+        );
+    }
+
+    async fn init_regions(conn: MultiplexedConnection) {
+        let regions = &[
+            "CAN".to_string(),
+            "US".to_string(),
+            "SOUTH_AMERICA".to_string(),
+        ];
+
+        crate::regions::set_regions(conn, regions).await.unwrap();
+    }
+
+    pub fn auth_client(port: u16) -> NakamaClient<Authenticated> {
+        NakamaClient {
+            username: "username".to_string(),
+            password: "password".to_string(),
+            token: Some("super_random_token".to_string()),
+            url: format!("http://127.0.0.1:{port}"),
+            server_key_name: "defaultkey".to_string(),
+            server_key_value: "server_key".to_string(),
+            encryption_key: "encryption_key".to_string(),
+            legacy_until: None,
+            _state: std::marker::PhantomData::<Authenticated>,
+        }
+    }
+}
@@ -1,41 +1,441 @@
-use std::sync::Arc;
+use std::{
+    collections::{HashMap, HashSet},
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+use redis::RedisError;
+use tracing::{Instrument, error, info, info_span, warn};
+use uuid::Uuid;
 
+use self::budget::TickBudget;
 use crate::{
+    admin,
+    allocation::Allocator,
+    clock::Clock,
+    config::{self, MatchmakingConfig},
+    metrics::{
+        self,
+        worker::{RunMetrics, SkipReason},
+    },
     nakama::{self, Authenticated},
+    regions::tuning::{self, RegionTunings},
     rpc::Match,
+    starvation::{Starvation, StarvationPolicy},
 };
 
+pub mod analytics;
+pub mod backfill;
+pub mod budget;
 pub mod can_match;
+pub mod cleanup;
+pub mod dead_letter;
+pub mod fallback;
 pub mod find_matches;
 pub mod form_match;
+pub mod leadership;
+pub mod lobby_kicks;
+pub mod migrate_hosts;
+pub mod raids;
+pub mod ready_check;
+pub mod reconcile;
+pub mod scan;
 pub mod start_matches;
+pub mod starvation;
+pub mod tournaments;
+pub mod versus;
+
+/// Longest pause between runs while Redis is unreachable
+pub const MAX_BACKOFF: Duration = Duration::from_secs(60);
+
+#[derive(Debug, thiserror::Error)]
+pub enum Error {
+    #[error("failed to refresh matchmaking config: {0}")]
+    Config(#[from] config::Error),
+    #[error("failed to refresh region tuning: {0}")]
+    RegionTuning(#[from] tuning::Error),
+    #[error("failed to renew the leader lease: {0}")]
+    Leadership(#[from] leadership::Error),
+    #[error("failed to remove stale queue entries: {0}")]
+    Cleanup(#[from] cleanup::Error),
+    #[error("failed to move waiting players to their fallback region: {0}")]
+    Fallback(#[from] fallback::Error),
+    #[error("failed to reconcile orphaned matches: {0}")]
+    Reconcile(#[from] reconcile::Error),
+    #[error("failed to migrate expired party hosts: {0}")]
+    MigrateHosts(#[from] migrate_hosts::Error),
+    #[error("failed to backfill running matches: {0}")]
+    Backfill(#[from] backfill::Error),
+    #[error("failed to apply lobby kicks: {0}")]
+    LobbyKicks(#[from] lobby_kicks::Error),
+    #[error("failed to run ready-checks: {0}")]
+    ReadyCheck(#[from] ready_check::Error),
+    #[error("failed to create hosted matches: {0}")]
+    HostedMatches(#[from] find_matches::Error),
+    #[error("failed to form versus matches: {0}")]
+    Versus(#[from] versus::Error),
+    #[error("failed to assemble raids: {0}")]
+    Raids(#[from] raids::Error),
+    #[error("failed to schedule tournament matches: {0}")]
+    Tournaments(#[from] tournaments::Error),
+    #[error("failed to start closed matches: {0}")]
+    StartMatches(#[from] start_matches::Error),
+    #[error("failed to retry dead-lettered matches: {0}")]
+    DeadMatches(#[from] dead_letter::Error),
+    #[error("failed to aggregate queue analytics: {0}")]
+    Analytics(#[from] analytics::Error),
+    #[error("redis unavailable: {0}")]
+    Unavailable(RedisError),
+    #[error("lost the leader lease during the run")]
+    LostLease,
+}
+
+impl Error {
+    /// Did Redis fail the phase?
+    pub const fn is_redis(&self) -> bool {
+        matches!(
+            self,
+            Self::Config(config::Error::Redis(_))
+                | Self::RegionTuning(tuning::Error::Redis(_))
+                | Self::Leadership(leadership::Error::Redis(_))
+                | Self::Cleanup(cleanup::Error::Redis(_))
+                | Self::Fallback(fallback::Error::Redis(_))
+                | Self::Reconcile(reconcile::Error::Redis(_))
+                | Self::MigrateHosts(migrate_hosts::Error::Redis(_))
+                | Self::Backfill(backfill::Error::Redis(_))
+                | Self::LobbyKicks(lobby_kicks::Error::Redis(_))
+                | Self::ReadyCheck(ready_check::Error::Redis(_))
+                | Self::HostedMatches(find_matches::Error::Redis(_))
+                | Self::Versus(versus::Error::Redis(_))
+                | Self::Raids(raids::Error::Redis(_))
+                | Self::Tournaments(tournaments::Error::Redis(_))
+                | Self::StartMatches(start_matches::Error::Redis(_))
+                | Self::DeadMatches(dead_letter::Error::Redis(_))
+                | Self::Analytics(analytics::Error::Redis(_))
+                | Self::Unavailable(_)
+        )
+    }
+}
 
 #[derive(Debug, Clone)]
 pub struct MatchmakingWorker {
     pub redis: redis::aio::MultiplexedConnection,
     pub http_client: Arc<reqwest::Client>,
     pub nakama_client: Arc<nakama::NakamaClient<Authenticated>>,
+    /// Game server backend of ready matches, none when matches are hosted by players
+    pub allocator: Option<Allocator>,
     pub open_matches: Vec<Match>,
+    /// Open matches whose ready-check passed, closed by [`MatchmakingWorker::hosted_matches`]
+    pub ready_matches: HashSet<Uuid>,
+    /// Refreshed on every run, see [`crate::config`]
+    pub config: MatchmakingConfig,
+    /// Refreshed on every run, see [`crate::regions::tuning`]
+    pub region_tunings: RegionTunings,
+    /// Runs since the worker started, slow regions are matched every few runs
+    pub runs: u64,
+    /// Source of the wait times, see [`crate::clock`]
+    pub clock: Arc<dyn Clock>,
+    /// Identifies the replica in the leader election, see [`crate::leader`]
+    pub instance_id: Uuid,
+    pub is_leader: bool,
+    /// Where the scan of each phase and queue key resumes on the next tick, see [`scan::scan`]
+    pub scan_cursors: HashMap<(&'static str, String), scan::Cursor>,
+    /// Runs failed in a row, backs off the next one, see [`MatchmakingWorker::next_delay`]
+    pub failures: u32,
+    /// Counters of the current run, see [`crate::metrics::worker`]
+    pub metrics: RunMetrics,
+    /// Matches and Redis commands left to the current run, see [`budget`]
+    pub budget: TickBudget,
+    /// When queues starve and how their regions are relaxed, see [`crate::starvation`]
+    pub starvation: StarvationPolicy,
+    /// Queues starving in the last run, their regions are relaxed on the next ones
+    pub starved: Starvation,
 }
 
 impl MatchmakingWorker {
-    pub const fn new(
+    pub fn new(
         redis: redis::aio::MultiplexedConnection,
         http_client: Arc<reqwest::Client>,
         nakama_client: Arc<nakama::NakamaClient<Authenticated>>,
+        clock: Arc<dyn Clock>,
     ) -> Self {
         Self {
             redis,
             http_client,
             nakama_client,
+            allocator: None,
             open_matches: Vec::new(),
+            ready_matches: HashSet::new(),
+            config: MatchmakingConfig::from_env(),
+            region_tunings: RegionTunings::default(),
+            runs: 0,
+            clock,
+            instance_id: Uuid::new_v4(),
+            is_leader: false,
+            scan_cursors: HashMap::new(),
+            failures: 0,
+            metrics: RunMetrics::default(),
+            budget: TickBudget::default(),
+            starvation: StarvationPolicy::default(),
+            starved: Starvation::default(),
+        }
+    }
+
+    /// Waits the worker interval, doubled for every failed run up to [`MAX_BACKOFF`]
+    pub fn next_delay(&self) -> Duration {
+        backoff(self.config.worker_interval(), self.failures)
+    }
+
+    /// Waits [`Self::next_delay`], the leader stops waiting once an admin requested a tick, see
+    /// [`admin::request_tick`]
+    pub async fn wait_next_run(&mut self) {
+        let until = Instant::now() + self.next_delay();
+        loop {
+            let left = until.saturating_duration_since(Instant::now());
+            if left.is_zero() {
+                return;
+            }
+            tokio::time::sleep(left.min(admin::TICK_POLL)).await;
+            if !self.is_leader {
+                continue;
+            }
+            match admin::take_tick_request(&mut self.redis).await {
+                Ok(true) => {
+                    info!("running the tick requested by an admin");
+                    return;
+                }
+                Ok(false) => {}
+                Err(err) => warn!("failed to check for a requested tick: {err}"),
+            }
+        }
+    }
+
+    /// Every log of a run carries its `run_id`
+    pub async fn run(&mut self) -> Result<(), Error> {
+        let span = info_span!("worker_run", run_id = %Uuid::new_v4());
+        let started = Instant::now();
+
+        let result = self.run_phases().instrument(span).await;
+        let run = std::mem::take(&mut self.metrics);
+        if let Err(err) = metrics::worker::record(&mut self.redis, &run, started.elapsed()).await {
+            warn!("failed to record worker metrics: {err}");
         }
+        self.runs = self.runs.wrapping_add(1);
+        self.failures = match result {
+            Ok(()) => 0,
+            Err(_) => self.failures.saturating_add(1),
+        };
+
+        result
     }
 
-    pub async fn run(&mut self) -> Result<(), ()> {
-        self.hosted_matches().await.unwrap();
-        self.start_matches().await.unwrap();
+    /// A failed phase is logged and the next ones still run, unless Redis is down
+    async fn run_phases(&mut self) -> Result<(), Error> {
+        match config::get_config(&mut self.redis).await {
+            Ok(config) => self.config = config,
+            Err(err) => self.phase_failed(err.into()).await?,
+        }
+        match tuning::get_tunings(&mut self.redis).await {
+            Ok(tunings) => self.region_tunings = tunings,
+            Err(err) => self.phase_failed(err.into()).await?,
+        }
+        self.starvation
+            .relax(&mut self.region_tunings, &self.starved);
+        self.budget = TickBudget::from_config(&self.config);
+        match self.lead_phases().await {
+            // another replica runs this tick, or the next phases
+            Err(Error::LostLease) => Ok(()),
+            result => result,
+        }
+    }
+
+    /// Phases of the leader, the lease is renewed between them so a run outliving it stops before
+    /// the next leader forms the same matches
+    async fn lead_phases(&mut self) -> Result<(), Error> {
+        self.keep_lease().await?;
+        if let Err(err) = self.reconcile_matches().await {
+            self.phase_failed(err.into()).await?;
+        }
+        self.keep_lease().await?;
+        match self.remove_abandoned_players().await {
+            Ok(removed) => self.metrics.skip(SkipReason::Abandoned, removed as u64),
+            Err(err) => self.phase_failed(err.into()).await?,
+        }
+        self.keep_lease().await?;
+        if let Err(err) = self.migrate_expired_hosts().await {
+            self.phase_failed(err.into()).await?;
+        }
+        self.keep_lease().await?;
+        match self.remove_stale_entries().await {
+            Ok(removed) => self.metrics.skip(SkipReason::Stale, removed as u64),
+            Err(err) => self.phase_failed(err.into()).await?,
+        }
+        self.keep_lease().await?;
+        if let Err(err) = self.fall_back_regions().await {
+            self.phase_failed(err.into()).await?;
+        }
+        self.keep_lease().await?;
+        if let Err(err) = self.backfill_matches().await {
+            self.phase_failed(err.into()).await?;
+        }
+        self.keep_lease().await?;
+        if let Err(err) = self.apply_lobby_kicks().await {
+            self.phase_failed(err.into()).await?;
+        }
+        self.keep_lease().await?;
+        if let Err(err) = self.ready_checks().await {
+            self.phase_failed(err.into()).await?;
+        }
+        self.keep_lease().await?;
+        if let Err(err) = self.hosted_matches().await {
+            self.phase_failed(err.into()).await?;
+        }
+        self.keep_lease().await?;
+        match self.versus_matches().await {
+            Ok(formed) => self.metrics.matches_closed += formed as u64,
+            Err(err) => self.phase_failed(err.into()).await?,
+        }
+        self.keep_lease().await?;
+        match self.raid_matches().await {
+            Ok(formed) => self.metrics.matches_closed += formed as u64,
+            Err(err) => self.phase_failed(err.into()).await?,
+        }
+        self.keep_lease().await?;
+        if let Err(err) = self.close_registrations().await {
+            self.phase_failed(err.into()).await?;
+        }
+        self.keep_lease().await?;
+        if let Err(err) = self.schedule_tournaments().await {
+            self.phase_failed(err.into()).await?;
+        }
+        self.keep_lease().await?;
+        let started = match self.start_matches().await {
+            Ok(started) => started,
+            Err(err) => {
+                self.phase_failed(err.into()).await?;
+                0
+            }
+        };
+        self.metrics.matches_started += started as u64;
+        self.keep_lease().await?;
+        match self.retry_dead_matches().await {
+            Ok(retried) => self.metrics.matches_started += retried as u64,
+            Err(err) => self.phase_failed(err.into()).await?,
+        }
+        self.keep_lease().await?;
+        match self.aggregate_analytics(started).await {
+            Ok(stats) => {
+                self.detect_starvation(&stats).await;
+            }
+            Err(err) => self.phase_failed(err.into()).await?,
+        }
+        if self.budget.is_spent() {
+            warn!("tick budget spent, the next run forms the remaining matches");
+            self.metrics.budget_exhausted += 1;
+        }
 
         Ok(())
     }
+
+    /// Renews the leader lease, fails with [`Error::LostLease`] when another replica took it or
+    /// the lease could not be checked
+    async fn keep_lease(&mut self) -> Result<(), Error> {
+        match self.ensure_leader().await {
+            Ok(true) => Ok(()),
+            Ok(false) => Err(Error::LostLease),
+            Err(err) => {
+                self.phase_failed(err.into()).await?;
+                Err(Error::LostLease)
+            }
+        }
+    }
+
+    /// Logs and counts `err`, aborts the run when Redis stopped answering
+    async fn phase_failed(&mut self, err: Error) -> Result<(), Error> {
+        error!("{err}");
+        if err.is_redis() {
+            self.metrics.redis_errors += 1;
+        }
+
+        redis::cmd("PING")
+            .query_async::<()>(&mut self.redis)
+            .await
+            .map_err(Error::Unavailable)
+    }
+}
+
+fn backoff(interval: Duration, failures: u32) -> Duration {
+    let factor = 2u32.saturating_pow(failures);
+
+    interval
+        .saturating_mul(factor)
+        .min(MAX_BACKOFF.max(interval))
+}
+
+#[cfg(test)]
+mod tests {
+    use redis::AsyncCommands;
+
+    use super::*;
+    use crate::{
+        clock::SystemClock,
+        leader,
+        nakama::NakamaClient,
+        test_support::{create_redis, redis_client},
+    };
+
+    #[test]
+    fn backoff_doubles_up_to_max() {
+        let interval = Duration::from_secs(1);
+
+        assert_eq!(backoff(interval, 0), interval);
+        assert_eq!(backoff(interval, 3), Duration::from_secs(8));
+        assert_eq!(backoff(interval, 40), MAX_BACKOFF);
+        assert_eq!(
+            backoff(Duration::from_secs(120), 2),
+            Duration::from_secs(120)
+        );
+    }
+
+    #[tokio::test]
+    async fn run_stops_once_the_lease_is_lost() {
+        let container = create_redis(6379).await;
+        let host = container.get_host().await.unwrap();
+        let port = container.get_host_port_ipv4(6379).await.unwrap();
+        let client = redis_client(host.to_string(), port);
+        let mut conn = client.get_multiplexed_async_connection().await.unwrap();
+        let mut worker = MatchmakingWorker::new(
+            conn.clone(),
+            Arc::new(reqwest::Client::new()),
+            auth_client(666).into(),
+            Arc::new(SystemClock::default()),
+        );
+
+        worker.keep_lease().await.unwrap();
+        assert!(worker.is_leader);
+        // the lease expired during a slow phase and another replica took it
+        conn.set(leader::leader_key(), Uuid::new_v4().to_string())
+            .await
+            .map(|_: ()| ())
+            .unwrap();
+
+        let run = worker.lead_phases().await;
+
+        assert!(matches!(run, Err(Error::LostLease)));
+        assert!(!worker.is_leader);
+    }
+
+    fn auth_client(port: u16) -> NakamaClient<Authenticated> {
+        NakamaClient {
+            username: "username".to_string(),
+            password: "password".to_string(),
+            token: Some("super_random_token".to_string()),
+            url: format!("http://127.0.0.1:{port}"),
+            server_key_name: "defaultkey".to_string(),
+            server_key_value: "server_key".to_string(),
+            encryption_key: "encryption_key".to_string(),
+            legacy_until: None,
+            _state: std::marker::PhantomData::<Authenticated>,
+        }
+    }
 }
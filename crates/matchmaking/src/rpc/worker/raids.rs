@@ -0,0 +1,113 @@
+use redis::{AsyncCommands, RedisError};
+use tracing::{error, info};
+use uuid::Uuid;
+
+use crate::{
+    codec, environment,
+    lifecycle::{self, MatchState},
+    metrics::fairness,
+    playlists, raid,
+    regions::regions_key,
+    rpc::{
+        Match, QueuedPlayer, closed_matches_key, player_queue_key, raid_queue_key,
+        worker::MatchmakingWorker,
+    },
+    store,
+};
+
+#[derive(Debug, thiserror::Error)]
+pub enum Error {
+    #[error(transparent)]
+    Redis(#[from] RedisError),
+    #[error(transparent)]
+    BitcodeDeser(#[from] codec::Error),
+    #[error(transparent)]
+    Playlists(#[from] playlists::Error),
+    #[error(transparent)]
+    Lifecycle(#[from] lifecycle::Error),
+}
+
+impl MatchmakingWorker {
+    /// Assembles raids from the raid playlist queues, the longest waiting players first.
+    /// Returns how many raids were formed.
+    pub async fn raid_matches(&mut self) -> Result<usize, Error> {
+        let mut conn = self.redis.clone();
+        let Some(regions): Option<Vec<u8>> = conn.get(regions_key()).await? else {
+            error!("No regions registred");
+            return Ok(0);
+        };
+        let regions: Vec<String> = codec::decode(regions.as_slice())?;
+        let now = self.clock.time_since_epoch();
+
+        let mut formed = 0;
+        for (region, format) in playlists::raid_queue_regions(&mut conn, &regions).await? {
+            // the regions left are assembled next tick
+            if self.budget.is_spent() {
+                break;
+            }
+            let queue_key = raid_queue_key(&region);
+            let player_ids: Vec<Uuid> = conn
+                .zrange(&queue_key, 0, self.config.scan_budget as isize - 1)
+                .await?;
+            if player_ids.len() < format.players() {
+                continue;
+            }
+            let data = store::players_data(&mut conn, &player_ids).await?;
+            // the range and a read per player
+            self.budget.spend_commands(1 + player_ids.len());
+
+            let mut queued = Vec::new();
+            for (player_id, data) in player_ids.iter().zip(data) {
+                match data.and_then(|data| self.metrics.decode::<QueuedPlayer>(&data)) {
+                    Some(player) => queued.push(player),
+                    None => {
+                        let _: () = conn.zrem(&queue_key, player_id).await?;
+                    }
+                }
+            }
+
+            for squads in raid::assemble(&queued, format) {
+                if self.budget.is_spent() {
+                    break;
+                }
+                let players: Vec<QueuedPlayer> = squads
+                    .iter()
+                    .flatten()
+                    .map(|i| queued[*i].clone())
+                    .collect();
+                let squads = squads
+                    .into_iter()
+                    .map(|squad| squad.into_iter().map(|i| queued[i].player_id).collect())
+                    .collect();
+                let Some(mut ready) = Match::raid(players, squads, format, now) else {
+                    continue;
+                };
+                ready.place(&self.region_tunings.get(&ready.region).datacenters);
+                ready.transition(MatchState::Ready, now)?;
+                ready.challenge = environment::closing_challenge(&mut conn, &ready).await;
+
+                let mut pipe = redis::pipe();
+                pipe.atomic();
+                for player in &ready.players {
+                    pipe.zrem(&queue_key, player.player_id).ignore();
+                    store::dequeue(&mut pipe, &player.player_id, &[player_queue_key(player)]);
+                }
+                pipe.zadd(closed_matches_key(), codec::encode(&ready), now)
+                    .ignore();
+                store::index_match(&mut pipe, &ready);
+                pipe.query_async(&mut conn).await.map(|_: ()| ())?;
+                self.budget.spend_match();
+                self.budget.spend_commands(pipe.len());
+                info!(
+                    "raid `{}` formed with {} squads",
+                    ready.id,
+                    ready.squads.len()
+                );
+                fairness::observe(&mut conn, &ready).await;
+                formed += 1;
+            }
+        }
+
+        Ok(formed)
+    }
+}
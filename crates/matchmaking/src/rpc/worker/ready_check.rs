@@ -0,0 +1,372 @@
+use std::collections::HashSet;
+
+use redis::{AsyncCommands, RedisError, aio::MultiplexedConnection};
+use tracing::{error, info};
+use uuid::Uuid;
+
+use crate::{
+    aging::Aging,
+    audit::{self, Action, Actor, AuditEvent},
+    claim, clans, codec, friends,
+    notifications::{self, Notification},
+    playlists,
+    ready_check::{self, Status},
+    rpc::{
+        Match, QueuedPlayer, forming_match_key, open_matches_key, player_key, region_queue_key,
+        server::TWELVE_MINUTES,
+        worker::{MatchmakingWorker, lobby_kicks::requeue},
+    },
+    store,
+};
+
+#[derive(Debug, thiserror::Error)]
+pub enum Error {
+    #[error(transparent)]
+    Redis(#[from] RedisError),
+    #[error(transparent)]
+    Playlists(#[from] playlists::Error),
+}
+
+impl MatchmakingWorker {
+    /// Runs the ready-checks of the open matches due to close, returns how many passed
+    pub async fn ready_checks(&mut self) -> Result<usize, Error> {
+        let mut conn = self.redis.clone();
+        let now = self.clock.time_since_epoch();
+        let mut passed = 0;
+        let mut dissolved = HashSet::new();
+        let clan_playlists = playlists::clan_playlists(&mut conn).await?;
+        for open_match in &mut self.open_matches {
+            if self.ready_matches.contains(&open_match.id)
+                || !self
+                    .region_tunings
+                    .get(&open_match.region)
+                    .should_close(open_match, now)
+            {
+                continue;
+            }
+            match ready_check::load(&mut conn, open_match, now).await? {
+                Status::Idle => {
+                    ready_check::start(&mut conn, open_match, now).await?;
+                }
+                Status::Pending { .. } => {}
+                Status::Passed => {
+                    let mut pipe = redis::pipe();
+                    ready_check::clear(&mut pipe, &open_match.id);
+                    pipe.query_async(&mut conn).await.map(|_: ()| ())?;
+                    self.ready_matches.insert(open_match.id);
+                    passed += 1;
+                }
+                Status::Expired { missing } if missing.contains(&open_match.host_id) => {
+                    info!("host of match `{}` missed its ready-check", open_match.id);
+                    dissolve(&mut conn, open_match, &missing).await?;
+                    let cancelled = AuditEvent::for_match(Action::MatchCancelled, open_match, now)
+                        .with_reason("host_missed_ready_check");
+                    audit::emit(&mut conn, &cancelled).await;
+                    dissolved.insert(open_match.id);
+                }
+                Status::Expired { missing } => {
+                    let Some((claim, reloaded)) =
+                        claim::acquire_forming(&mut conn, &open_match.id).await?
+                    else {
+                        info!("match `{}` is claimed, replacing later", open_match.id);
+                        continue;
+                    };
+                    *open_match = reloaded;
+                    let batch = self.config.scan_batch_size;
+                    let clan =
+                        clans::preferred_clan(open_match, &clan_playlists).map(str::to_string);
+                    let friends = friends::reserved_for(
+                        &mut conn,
+                        &self.nakama_client,
+                        self.http_client.clone(),
+                        open_match,
+                        &self.config,
+                        now,
+                    )
+                    .await
+                    .unwrap_or_else(|err| {
+                        error!(
+                            "failed to load the friends of `{}`: {err}",
+                            open_match.host_id
+                        );
+                        Some(HashSet::new())
+                    });
+                    let fill = Fill {
+                        clan: clan.as_deref(),
+                        friends: friends.as_ref(),
+                        aging: self.region_tunings.get(&open_match.region).aging(),
+                    };
+                    let replaced =
+                        replace_players(&mut conn, open_match, &missing, fill, batch, now).await;
+                    claim::release(&mut conn, claim).await?;
+                    replaced?;
+                    for player_id in &missing {
+                        let left =
+                            AuditEvent::new(Action::PlayerLeft, Actor::Worker, player_id, now)
+                                .with_reason("missed_ready_check");
+                        audit::emit(&mut conn, &left).await;
+                    }
+                }
+            }
+        }
+        self.open_matches
+            .retain(|open_match| !dissolved.contains(&open_match.id));
+
+        Ok(passed)
+    }
+}
+
+/// Who replaces the missing players of a match
+#[derive(Debug, Clone, Copy, Default)]
+struct Fill<'a> {
+    /// Members of the clan are pulled first
+    clan: Option<&'a str>,
+    /// Only these players are pulled, while the match is friends-only
+    friends: Option<&'a HashSet<Uuid>>,
+    /// Wait-time aging of the region of the match
+    aging: Aging,
+}
+
+/// Drops the `missing` players of `open_match` and fills their slots from its queue, see [`Fill`]
+async fn replace_players(
+    conn: &mut MultiplexedConnection,
+    open_match: &mut Match,
+    missing: &[Uuid],
+    fill: Fill<'_>,
+    batch: usize,
+    now: i64,
+) -> Result<(), RedisError> {
+    let slots = open_match.players.len();
+    open_match
+        .players
+        .retain(|player| !missing.contains(&player.player_id));
+
+    let party_mode = open_match
+        .host_player()
+        .map(|host| host.party_mode)
+        .unwrap_or_default();
+    let mut candidates = Vec::new();
+    for band in open_match.skill_bands() {
+        let key = region_queue_key(party_mode, &open_match.queue_region(), band);
+        let queued: Vec<Vec<u8>> = conn.zrange(&key, 0, batch as isize - 1).await?;
+        candidates.extend(
+            queued
+                .iter()
+                .filter_map(|bits| codec::decode::<QueuedPlayer>(bits).ok()),
+        );
+    }
+    if let Some(friends) = fill.friends {
+        candidates.retain(|candidate| friends.contains(&candidate.player_id));
+    }
+    clans::members_first(&mut candidates, fill.clan, |player| player);
+
+    let mut added = Vec::new();
+    for candidate in candidates {
+        if open_match.players.len() >= slots {
+            break;
+        }
+        if missing.contains(&candidate.player_id)
+            || open_match
+                .players
+                .iter()
+                .any(|p| p.player_id == candidate.player_id)
+            || !open_match
+                .is_player_fit(candidate.clone(), fill.aging.aged_now(&candidate, now))
+                .0
+        {
+            continue;
+        }
+        let (forming, queued): (bool, bool) = redis::pipe()
+            .exists(forming_match_key(&candidate.player_id))
+            .exists(player_key(&candidate.player_id))
+            .query_async(conn)
+            .await?;
+        if forming || !queued {
+            continue;
+        }
+        added.push(candidate.player_id);
+        open_match.players.push(candidate);
+    }
+
+    let mut pipe = redis::pipe();
+    pipe.atomic();
+    store::put_match(&mut pipe, open_match, TWELVE_MINUTES);
+    pipe.zadd(
+        open_matches_key(&open_match.region),
+        open_match.id,
+        open_match.players.len(),
+    )
+    .ignore();
+    store::unindex_players(&mut pipe, missing);
+    ready_check::clear(&mut pipe, &open_match.id);
+    for player_id in missing {
+        pipe.del(forming_match_key(player_id))
+            .ignore()
+            .del(player_key(player_id))
+            .ignore();
+    }
+    for player_id in &added {
+        pipe.set_ex(forming_match_key(player_id), open_match.id, TWELVE_MINUTES)
+            .ignore();
+    }
+    pipe.query_async(conn).await.map(|_: ()| ())?;
+    info!(
+        "replaced {} of {} players missing the ready-check of match `{}`",
+        added.len(),
+        missing.len(),
+        open_match.id
+    );
+    if let Err(err) = notifications::notify(conn, missing, &Notification::QueueTimeout).await {
+        error!("failed to notify players missing a ready-check: {err}");
+    }
+
+    Ok(())
+}
+
+/// Removes `open_match`, its players who confirmed return to the queue.
+/// Returns how many players returned to the queue.
+pub(super) async fn dissolve(
+    conn: &mut MultiplexedConnection,
+    open_match: &Match,
+    missing: &[Uuid],
+) -> Result<usize, RedisError> {
+    let mut pipe = redis::pipe();
+    pipe.atomic()
+        .zrem(open_matches_key(&open_match.region), open_match.id)
+        .ignore();
+    store::remove_match(&mut pipe, open_match);
+    ready_check::clear(&mut pipe, &open_match.id);
+    let mut requeued = Vec::new();
+    for player in &open_match.players {
+        pipe.del(forming_match_key(&player.player_id)).ignore();
+        let queued: bool = conn.exists(player_key(&player.player_id)).await?;
+        if queued && !missing.contains(&player.player_id) {
+            requeue(&mut pipe, player, open_match.is_raid());
+            requeued.push(player.player_id);
+        } else {
+            pipe.del(player_key(&player.player_id)).ignore();
+        }
+    }
+    pipe.query_async(conn).await.map(|_: ()| ())?;
+
+    let failed = Notification::MatchFailed {
+        match_id: open_match.id,
+    };
+    if let Err(err) = notifications::notify(conn, &requeued, &failed).await {
+        error!(
+            "failed to notify players of match `{}`: {err}",
+            open_match.id
+        );
+    }
+    if let Err(err) = notifications::notify(conn, missing, &Notification::QueueTimeout).await {
+        error!("failed to notify players missing a ready-check: {err}");
+    }
+
+    Ok(requeued.len())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use skillratings::mhth::MhthRating;
+
+    use super::*;
+    use crate::{
+        clock::{Clock, SystemClock},
+        nakama::{Authenticated, NakamaClient},
+        rpc::{matchmaking::Player, player_queue_key},
+        test_support::{create_redis, redis_client},
+    };
+
+    #[tokio::test]
+    async fn missing_players_are_replaced_from_the_queue() {
+        let container = create_redis(6379).await;
+        let host = container.get_host().await.unwrap();
+        let port = container.get_host_port_ipv4(6379).await.unwrap();
+        let client = redis_client(host.to_string(), port);
+        let mut conn = client.get_multiplexed_async_connection().await.unwrap();
+        let queued = |join_mode| -> QueuedPlayer {
+            (
+                Uuid::new_v4(),
+                Player {
+                    join_mode,
+                    region: "CAN".to_string(),
+                    ping: 20,
+                    ..Default::default()
+                },
+                MhthRating::default(),
+            )
+                .into()
+        };
+        let (match_host, silent, waiting) = (queued(0), queued(1), queued(1));
+        let mut pipe = redis::pipe();
+        for player in [&match_host, &silent, &waiting] {
+            store::put_player(&mut pipe, player, &codec::encode(player), 200);
+        }
+        let _: () = pipe.query_async(&mut conn).await.unwrap();
+        let _: () = conn
+            .zadd(player_queue_key(&waiting), codec::encode(&waiting), 1)
+            .await
+            .unwrap();
+        let mut open_match = Match::host(&match_host, &[]).unwrap();
+        open_match.players.push(silent.clone());
+        open_match.params.max_players = 2;
+        let mut pipe = redis::pipe();
+        store::put_match(&mut pipe, &open_match, TWELVE_MINUTES);
+        let _: () = pipe.query_async(&mut conn).await.unwrap();
+        let clock = Arc::new(SystemClock::default());
+        let mut worker = MatchmakingWorker::new(
+            conn.clone(),
+            Arc::new(reqwest::Client::new()),
+            auth_client(666).into(),
+            clock.clone(),
+        );
+        worker.open_matches.push(open_match.clone());
+
+        worker.ready_checks().await.unwrap();
+        ready_check::confirm(&mut conn, open_match.id, match_host.player_id)
+            .await
+            .unwrap();
+        let _: () = conn
+            .set(
+                ready_check::ready_check_key(&open_match.id),
+                clock.time_since_epoch(),
+            )
+            .await
+            .unwrap();
+        worker.ready_checks().await.unwrap();
+        let silent_queued: bool = conn.exists(player_key(&silent.player_id)).await.unwrap();
+        let events = notifications::drain(&mut conn, &silent.player_id)
+            .await
+            .unwrap();
+
+        let players: Vec<Uuid> = worker.open_matches[0]
+            .players
+            .iter()
+            .map(|p| p.player_id)
+            .collect();
+        assert_eq!(players, vec![match_host.player_id, waiting.player_id]);
+        assert!(!silent_queued);
+        assert_eq!(
+            events.last(),
+            Some(&Notification::QueueTimeout),
+            "after the ready-check request"
+        );
+        assert!(worker.ready_matches.is_empty());
+    }
+
+    fn auth_client(port: u16) -> NakamaClient<Authenticated> {
+        NakamaClient {
+            username: "username".to_string(),
+            password: "password".to_string(),
+            token: Some("super_random_token".to_string()),
+            url: format!("http://127.0.0.1:{port}"),
+            server_key_name: "defaultkey".to_string(),
+            server_key_value: "server_key".to_string(),
+            encryption_key: "encryption_key".to_string(),
+            legacy_until: None,
+            _state: std::marker::PhantomData::<Authenticated>,
+        }
+    }
+}
@@ -0,0 +1,289 @@
+use std::collections::HashSet;
+
+use redis::{AsyncCommands, RedisError, aio::MultiplexedConnection};
+use tracing::{error, info};
+use uuid::Uuid;
+
+use crate::{
+    audit::{self, Action, AuditEvent},
+    codec,
+    lifecycle::MatchState,
+    namespace,
+    notifications::{self, Notification},
+    playlists,
+    rpc::{
+        FORMING_MATCH, Match, OPEN_MATCHES, QueuedPlayer, active_match_key, closed_matches_key,
+        match_id_key, open_matches_key, player_key, player_match_key,
+        worker::{MatchmakingWorker, lobby_kicks::requeue, ready_check::dissolve},
+    },
+    store,
+};
+
+/// Runs between two reconciliations, the first run of a worker reconciles
+pub const RECONCILE_RUNS: u64 = 60;
+
+#[derive(Debug, thiserror::Error)]
+pub enum Error {
+    #[error(transparent)]
+    Redis(#[from] RedisError),
+    #[error(transparent)]
+    Playlists(#[from] playlists::Error),
+}
+
+/// Keys of the namespace matching `pattern`
+async fn scan_keys(
+    conn: &mut MultiplexedConnection,
+    pattern: &str,
+) -> Result<Vec<String>, RedisError> {
+    let mut keys = Vec::new();
+    let mut iter = conn
+        .scan_match::<_, String>(namespace::key(pattern))
+        .await?;
+    while let Some(key) = iter.next_item().await {
+        keys.push(key);
+    }
+
+    Ok(keys)
+}
+
+impl MatchmakingWorker {
+    /// Open matches only live in the memory of the leader, so a restarted worker orphans the
+    /// matches it was forming. Every [`RECONCILE_RUNS`] runs, the forming matches missing from
+    /// memory are restored while their host is queued and dissolved otherwise, and the players of
+    /// expired matches are queued again. Returns how many players were queued again.
+    pub async fn reconcile_matches(&mut self) -> Result<usize, Error> {
+        if !self.runs.is_multiple_of(RECONCILE_RUNS) {
+            return Ok(0);
+        }
+        let mut conn = self.redis.clone();
+        let prefix = namespace::key("match:");
+
+        let mut requeued = 0;
+        for key in scan_keys(&mut conn, "match:*").await? {
+            let Some(match_id) = key
+                .strip_prefix(&prefix)
+                .and_then(|id| Uuid::parse_str(id).ok())
+            else {
+                continue;
+            };
+            if self.open_matches.iter().any(|open| open.id == match_id) {
+                continue;
+            }
+            // aborted matches are kept for inspection, only the forming ones are decoded
+            let state: Option<String> = conn.hget(&key, store::STATE).await?;
+            if state.as_deref() != Some(store::state_name(MatchState::Forming)) {
+                continue;
+            }
+            let Some(orphan) = store::match_data(&mut conn, &match_id)
+                .await?
+                .and_then(|data| self.metrics.decode::<Match>(&data))
+            else {
+                continue;
+            };
+
+            if conn.exists(player_key(&orphan.host_id)).await? {
+                conn.zadd(
+                    open_matches_key(&orphan.region),
+                    orphan.id,
+                    orphan.players.len(),
+                )
+                .await
+                .map(|_: ()| ())?;
+                info!("restored orphaned match `{match_id}`");
+                self.open_matches.push(orphan);
+            } else {
+                info!("dissolving orphaned match `{match_id}`, its host left the queue");
+                requeued += dissolve(&mut conn, &orphan, &[]).await?;
+                let cancelled = AuditEvent::for_match(
+                    Action::MatchCancelled,
+                    &orphan,
+                    self.clock.time_since_epoch(),
+                )
+                .with_reason("host_left_queue");
+                audit::emit(&mut conn, &cancelled).await;
+            }
+        }
+        remove_expired_open_matches(&mut conn).await?;
+        requeued += requeue_orphaned_players(&mut conn).await?;
+
+        Ok(requeued)
+    }
+}
+
+/// Drops the ids of expired matches from the open matches of every region
+async fn remove_expired_open_matches(conn: &mut MultiplexedConnection) -> Result<(), RedisError> {
+    for key in scan_keys(conn, &format!("{OPEN_MATCHES}:*")).await? {
+        let match_ids: Vec<Uuid> = conn.zrange(&key, 0, -1).await?;
+        for match_id in match_ids {
+            if !conn.exists(match_id_key(&match_id)).await? {
+                conn.zrem(&key, match_id).await.map(|_: ()| ())?;
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// Queues again the players pencilled into a forming match that expired before it closed,
+/// returns how many were queued again
+async fn requeue_orphaned_players(conn: &mut MultiplexedConnection) -> Result<usize, Error> {
+    let raid_playlists = playlists::raid_playlists(conn).await?;
+    let closed: Vec<Vec<u8>> = conn.zrange(closed_matches_key(), 0, -1).await?;
+    let closed: HashSet<Uuid> = closed
+        .iter()
+        .filter_map(|bits| codec::decode::<Match>(bits).ok())
+        .map(|closed| closed.id)
+        .collect();
+
+    let mut requeued = 0;
+    for key in scan_keys(conn, &format!("{FORMING_MATCH}:*")).await? {
+        let Some(player_id) = key
+            .rsplit(':')
+            .next()
+            .and_then(|id| Uuid::parse_str(id).ok())
+        else {
+            continue;
+        };
+        let Some(match_id): Option<Uuid> = conn.get(&key).await? else {
+            continue;
+        };
+        let (forming, active, started): (bool, bool, bool) = redis::pipe()
+            .exists(match_id_key(&match_id))
+            .exists(active_match_key(&match_id))
+            .exists(player_match_key(&player_id))
+            .query_async(conn)
+            .await?;
+        if forming || active || started || closed.contains(&match_id) {
+            continue;
+        }
+
+        let data = store::player_data(conn, &player_id).await?;
+        let mut pipe = redis::pipe();
+        pipe.del(&key).ignore();
+        let queued = data.and_then(|bits| codec::decode::<QueuedPlayer>(&bits).ok());
+        if let Some(player) = &queued {
+            requeue(&mut pipe, player, raid_playlists.contains(&player.playlist));
+        }
+        pipe.query_async(conn).await.map(|_: ()| ())?;
+        if queued.is_none() {
+            continue;
+        }
+
+        requeued += 1;
+        info!("queued player `{player_id}` of expired match `{match_id}` again");
+        if let Err(err) =
+            notifications::notify(conn, &[player_id], &Notification::MatchFailed { match_id }).await
+        {
+            error!("failed to notify player `{player_id}`: {err}");
+        }
+    }
+
+    Ok(requeued)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use skillratings::mhth::MhthRating;
+
+    use super::*;
+    use crate::{
+        clock::SystemClock,
+        nakama::{Authenticated, NakamaClient},
+        rpc::{forming_match_key, matchmaking::Player, player_queue_key, server::TWELVE_MINUTES},
+        test_support::{create_redis, redis_client},
+    };
+
+    #[tokio::test]
+    async fn orphaned_matches_are_restored_or_dissolved() {
+        let container = create_redis(6379).await;
+        let host = container.get_host().await.unwrap();
+        let port = container.get_host_port_ipv4(6379).await.unwrap();
+        let client = redis_client(host.to_string(), port);
+        let mut conn = client.get_multiplexed_async_connection().await.unwrap();
+        let queued = |join_mode| -> QueuedPlayer {
+            (
+                Uuid::new_v4(),
+                Player {
+                    join_mode,
+                    region: "CAN".to_string(),
+                    ..Default::default()
+                },
+                MhthRating::default(),
+            )
+                .into()
+        };
+        let (live_host, gone_host, guest, stranded) = (queued(0), queued(0), queued(1), queued(1));
+        let mut pipe = redis::pipe();
+        for player in [&live_host, &guest, &stranded] {
+            store::put_player(&mut pipe, player, &codec::encode(player), 200);
+        }
+        let live = Match::host(&live_host, &[]).unwrap();
+        let mut dead = Match::host(&gone_host, &[]).unwrap();
+        dead.players.push(guest.clone());
+        for orphan in [&live, &dead] {
+            store::put_match(&mut pipe, orphan, TWELVE_MINUTES);
+        }
+        let _: () = pipe.query_async(&mut conn).await.unwrap();
+        let expired_id = Uuid::new_v4();
+        let _: () = redis::pipe()
+            .set_ex(
+                forming_match_key(&stranded.player_id),
+                expired_id,
+                TWELVE_MINUTES,
+            )
+            .zadd(open_matches_key(&"CAN".to_string()), expired_id, 2)
+            .query_async(&mut conn)
+            .await
+            .unwrap();
+        let mut worker = MatchmakingWorker::new(
+            conn.clone(),
+            Arc::new(reqwest::Client::new()),
+            auth_client(666).into(),
+            Arc::new(SystemClock::default()),
+        );
+
+        let requeued = worker.reconcile_matches().await.unwrap();
+        let open: Vec<Uuid> = conn
+            .zrange(open_matches_key(&"CAN".to_string()), 0, -1)
+            .await
+            .unwrap();
+        let dead_data = store::match_data(&mut conn, &dead.id).await.unwrap();
+        let guest_queue: Vec<Vec<u8>> = conn.zrange(player_queue_key(&guest), 0, -1).await.unwrap();
+        let stranded_forming: Option<Uuid> = conn
+            .get(forming_match_key(&stranded.player_id))
+            .await
+            .unwrap();
+        let stranded_events = notifications::drain(&mut conn, &stranded.player_id)
+            .await
+            .unwrap();
+
+        assert_eq!(requeued, 2);
+        assert_eq!(worker.open_matches, vec![live.clone()]);
+        assert_eq!(open, vec![live.id]);
+        assert!(dead_data.is_none());
+        assert!(guest_queue.contains(&codec::encode(&guest)));
+        assert!(stranded_forming.is_none());
+        assert_eq!(
+            stranded_events,
+            vec![Notification::MatchFailed {
+                match_id: expired_id
+            }]
+        );
+    }
+
+    fn auth_client(port: u16) -> NakamaClient<Authenticated> {
+        NakamaClient {
+            username: "username".to_string(),
+            password: "password".to_string(),
+            token: Some("super_random_token".to_string()),
+            url: format!("http://127.0.0.1:{port}"),
+            server_key_name: "defaultkey".to_string(),
+            server_key_value: "server_key".to_string(),
+            encryption_key: "encryption_key".to_string(),
+            legacy_until: None,
+            _state: std::marker::PhantomData::<Authenticated>,
+        }
+    }
+}
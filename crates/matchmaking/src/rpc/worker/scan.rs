@@ -0,0 +1,150 @@
+use redis::{AsyncCommands, RedisError, aio::MultiplexedConnection};
+
+use crate::chaos;
+
+/// Position in a queue ZSET, entries are ordered by join time
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Cursor {
+    pub join_time: f64,
+    /// Entries already read with exactly `join_time`, players often join in the same second
+    pub offset: usize,
+}
+
+/// Entries read from a queue in one tick
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Scan {
+    pub entries: Vec<Vec<u8>>,
+    /// Where the next tick resumes, `None` once the end of the queue was reached
+    pub cursor: Option<Cursor>,
+    /// Redis commands issued, see [`super::budget`]
+    pub commands: usize,
+}
+
+/// Reads up to `budget` entries after `cursor` in batches of `batch` entries,
+/// so a tick never loads a whole queue at once.
+pub async fn scan(
+    conn: &mut MultiplexedConnection,
+    key: &str,
+    cursor: Option<Cursor>,
+    batch: usize,
+    budget: usize,
+) -> Result<Scan, RedisError> {
+    chaos::redis().await?;
+    let mut scan = Scan {
+        entries: Vec::new(),
+        cursor,
+        commands: 0,
+    };
+    let batch = batch.max(1);
+
+    while scan.entries.len() < budget {
+        let limit = batch.min(budget - scan.entries.len());
+        let (min, offset) = scan.cursor.map_or_else(
+            || ("-inf".to_string(), 0),
+            |cursor| (cursor.join_time.to_string(), cursor.offset),
+        );
+        let page: Vec<(Vec<u8>, f64)> = conn
+            .zrangebyscore_limit_withscores(key, min, "+inf", offset as isize, limit as isize)
+            .await?;
+        scan.commands += 1;
+        let end = page.len() < limit;
+        scan.cursor = advance(scan.cursor, &page);
+        scan.entries
+            .extend(page.into_iter().map(|(entry, _)| entry));
+        if end {
+            scan.cursor = None;
+            break;
+        }
+    }
+
+    Ok(scan)
+}
+
+fn advance(cursor: Option<Cursor>, page: &[(Vec<u8>, f64)]) -> Option<Cursor> {
+    let Some((_, last)) = page.last() else {
+        return cursor;
+    };
+    let tied = page
+        .iter()
+        .rev()
+        .take_while(|(_, score)| score == last)
+        .count();
+
+    Some(match cursor {
+        Some(cursor) if cursor.join_time == *last => Cursor {
+            join_time: *last,
+            offset: cursor.offset + tied,
+        },
+        _ => Cursor {
+            join_time: *last,
+            offset: tied,
+        },
+    })
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+    use crate::test_support::{create_redis, redis_client};
+
+    #[test]
+    fn cursor_skips_tied_entries() {
+        let page = |scores: &[f64]| -> Vec<(Vec<u8>, f64)> {
+            scores.iter().map(|score| (Vec::new(), *score)).collect()
+        };
+
+        let first = advance(None, &page(&[1., 2., 2.])).unwrap();
+        let second = advance(Some(first), &page(&[2., 2.])).unwrap();
+        let third = advance(Some(second), &page(&[2., 3.])).unwrap();
+
+        assert_eq!(
+            first,
+            Cursor {
+                join_time: 2.,
+                offset: 2
+            }
+        );
+        assert_eq!(
+            second,
+            Cursor {
+                join_time: 2.,
+                offset: 4
+            }
+        );
+        assert_eq!(
+            third,
+            Cursor {
+                join_time: 3.,
+                offset: 1
+            }
+        );
+        assert_eq!(advance(Some(third), &[]), Some(third));
+    }
+
+    #[tokio::test]
+    async fn scan_resumes_across_ticks() {
+        let container = create_redis(6379).await;
+        let host = container.get_host().await.unwrap();
+        let port = container.get_host_port_ipv4(6379).await.unwrap();
+        let client = redis_client(host.to_string(), port);
+        let mut conn = client.get_multiplexed_async_connection().await.unwrap();
+        let entries: Vec<(i64, String)> = (0..10).map(|i| (i / 3, format!("player-{i}"))).collect();
+        let _: () = conn.zadd_multiple("queue", &entries).await.unwrap();
+
+        let first = scan(&mut conn, "queue", None, 2, 4).await.unwrap();
+        let second = scan(&mut conn, "queue", first.cursor, 2, 4).await.unwrap();
+        let last = scan(&mut conn, "queue", second.cursor, 2, 4).await.unwrap();
+
+        let read: Vec<Vec<u8>> = [first.entries, second.entries, last.entries].concat();
+        assert_eq!(
+            read,
+            entries
+                .iter()
+                .map(|(_, entry)| entry.as_bytes().to_vec())
+                .collect::<Vec<_>>()
+        );
+        assert!(first.cursor.is_some());
+        assert_eq!(last.cursor, None);
+    }
+}
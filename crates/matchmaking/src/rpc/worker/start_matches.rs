@@ -1,53 +1,236 @@
-use redis::AsyncCommands;
-use tracing::info;
+use redis::{AsyncCommands, RedisError};
+use tracing::{error, info};
+use uuid::Uuid;
 
-use crate::rpc::{CLOSED_MATCHES, Match, worker::MatchmakingWorker};
+use crate::{
+    allocation::{self, GameServer},
+    audit::{self, Action, AuditEvent},
+    codec, environment,
+    lifecycle::{self, MatchState},
+    nakama::{self, endpoints::CreateMatchRequest},
+    notifications::{self, Notification},
+    rpc::{
+        Match, MatchKind, active_match_key, closed_matches_key, player_match_key,
+        server::MATCH_LIFETIME,
+        worker::{
+            MatchmakingWorker, dead_letter,
+            scan::{Scan, scan},
+        },
+    },
+    store,
+};
+
+#[derive(Debug, thiserror::Error)]
+pub enum Error {
+    #[error(transparent)]
+    Redis(#[from] RedisError),
+    #[error(transparent)]
+    Lifecycle(#[from] lifecycle::Error),
+    #[error("nakama failed to create the match: {0}")]
+    Nakama(#[from] nakama::Error),
+    #[error(transparent)]
+    Environment(#[from] environment::Error),
+    #[error("failed to allocate a game server: {0}")]
+    Allocation(#[from] allocation::Error),
+}
+
+impl From<&Match> for CreateMatchRequest {
+    fn from(value: &Match) -> Self {
+        Self {
+            matchmaker_id: value.id.to_string(),
+            host_id: value.host_id.to_string(),
+            region: value.region.clone(),
+            playlist: value.playlist.clone(),
+            difficulty: value.difficulty,
+            max_players: value.params.max_players,
+            player_ids: value
+                .players
+                .iter()
+                .map(|p| p.player_id.to_string())
+                .collect(),
+            experiments: value.experiments.clone(),
+            game_server_address: value
+                .game_server
+                .as_ref()
+                .map(GameServer::address)
+                .unwrap_or_default(),
+            squads: value
+                .squads
+                .iter()
+                .map(|squad| squad.iter().map(Uuid::to_string).collect())
+                .collect(),
+            team_size: match value.kind {
+                MatchKind::Versus { team_size } => team_size,
+                MatchKind::Cooperative | MatchKind::Raid { .. } => 0,
+            },
+            win_probability: value.challenge.map(|challenge| challenge.win_probability),
+            settings: value.settings.clone(),
+            voice_chat: value.voice_chat().as_str_name().to_string(),
+            datacenter: value.location().to_string(),
+        }
+    }
+}
 
 impl MatchmakingWorker {
-    pub async fn start_matches(&mut self) -> Result<usize, ()> {
+    /// Starts the closed matches, a match failing to start is moved to the dead-letter queue
+    pub async fn start_matches(&mut self) -> Result<usize, Error> {
         let mut count = 0;
-        if let Ok(encoded_matchs) = &self
-            .redis
-            .zrange::<&str, Vec<Vec<u8>>>(CLOSED_MATCHES, 0, -1)
-            .await
-        {
-            for (decoded_match, encoded) in encoded_matchs.iter().filter_map(|matches_bits| {
-                Some((
-                    bitcode::decode::<Match>(matches_bits.as_slice()).ok()?,
-                    matches_bits,
-                ))
-            }) {
-                self.redis
-                    .zrem(CLOSED_MATCHES, encoded)
-                    .await
-                    .map(|_: ()| ())
-                    .unwrap();
-                info!("Call Nakama start match RPC: {decoded_match:?}");
+        let mut conn = self.redis.clone();
+        let Scan {
+            entries: encoded_matchs,
+            ..
+        } = scan(
+            &mut conn,
+            &closed_matches_key(),
+            None,
+            self.config.scan_batch_size,
+            self.config.scan_budget,
+        )
+        .await?;
+
+        let now = self.clock.time_since_epoch();
+        // decoded up front, starting a match needs the worker
+        let decoded: Vec<(Match, &Vec<u8>)> = encoded_matchs
+            .iter()
+            .filter_map(|matches_bits| {
+                Some((self.metrics.decode::<Match>(matches_bits)?, matches_bits))
+            })
+            .collect();
+        for (mut decoded_match, encoded) in decoded {
+            let started = match decoded_match.transition(MatchState::Starting, now) {
+                Err(err) => {
+                    error!("dropped closed match `{}`: {err}", decoded_match.id);
+                    false
+                }
+                Ok(()) => match self.start_match(&mut decoded_match).await {
+                    Ok(()) => true,
+                    Err(err) => {
+                        error!("failed to start match `{}`: {err}", decoded_match.id);
+                        let retry_at = dead_letter::retry_at(now, 1);
+                        if let Err(err) =
+                            dead_letter::push(&mut conn, &decoded_match, 1, retry_at).await
+                        {
+                            error!(
+                                "failed to dead-letter match `{}`, retrying next tick: {err}",
+                                decoded_match.id
+                            );
+                            continue;
+                        }
+                        false
+                    }
+                },
+            };
+            // the match leaves the closed queue once it started or was dead-lettered
+            if let Err(err) = conn
+                .zrem(closed_matches_key(), encoded)
+                .await
+                .map(|_: ()| ())
+            {
+                error!(
+                    "failed to dequeue closed match `{}`: {err}",
+                    decoded_match.id
+                );
+            }
+            if started {
                 count += 1;
             }
         }
 
         Ok(count)
     }
+
+    /// Allocates a game server and creates the Nakama match of `starting`, once each,
+    /// then activates it and tells its players. Only fails when the match could not be started.
+    pub(crate) async fn start_match(&mut self, starting: &mut Match) -> Result<(), Error> {
+        if let Some(allocator) = &self.allocator
+            && starting.game_server.is_none()
+        {
+            let game_server = allocator.allocate(&self.http_client, starting).await?;
+            info!(
+                "match `{}` allocated `{}`",
+                starting.id,
+                game_server.address()
+            );
+            starting.game_server = Some(game_server);
+        }
+        if starting.challenge.is_none() {
+            starting.challenge = environment::challenge(&mut self.redis, starting).await?;
+        }
+        if starting.nakama_match_id.is_none() {
+            let nakama_match_id = self
+                .nakama_client
+                .create_match(self.http_client.clone(), &(&*starting).into())
+                .await?;
+            info!("match `{}` created as `{nakama_match_id}`", starting.id);
+            starting.nakama_match_id = Some(nakama_match_id);
+        }
+        let now = self.clock.time_since_epoch();
+        let mut started = starting.clone();
+        started.transition(MatchState::Active, now)?;
+        self.activate_match(&started).await?;
+
+        let players: Vec<_> = started.players.iter().map(|p| p.player_id).collect();
+        let notification = Notification::MatchFound {
+            match_id: started.id,
+            host_id: started.host_id,
+            region: started.region.clone(),
+            nakama_match_id: started.nakama_match_id.clone().unwrap_or_default(),
+            game_server_address: started
+                .game_server
+                .as_ref()
+                .map(GameServer::address)
+                .unwrap_or_default(),
+            backfill: false,
+            challenge: started.challenge,
+        };
+        if let Err(err) = notifications::notify(&mut self.redis, &players, &notification).await {
+            error!("failed to notify match `{}` players: {err}", started.id);
+        }
+        let audited = AuditEvent::for_match(Action::MatchStarted, &started, now);
+        audit::emit(&mut self.redis, &audited).await;
+
+        Ok(())
+    }
+
+    /// Keeps the started match and an index from each player to it, so players can rejoin
+    pub(crate) async fn activate_match(&self, started: &Match) -> Result<(), RedisError> {
+        let mut conn = self.redis.clone();
+        let mut pipe = redis::pipe();
+        pipe.set_ex(
+            active_match_key(&started.id),
+            codec::encode(started),
+            *MATCH_LIFETIME,
+        )
+        .ignore();
+        for player in &started.players {
+            pipe.set_ex(
+                player_match_key(&player.player_id),
+                started.id,
+                *MATCH_LIFETIME,
+            )
+            .ignore();
+        }
+        store::index_match(&mut pipe, started);
+
+        pipe.query_async(&mut conn).await
+    }
 }
 
 #[cfg(test)]
 mod tests {
     use std::sync::Arc;
 
+    use httpmock::prelude::*;
     use redis::aio::MultiplexedConnection;
+    use serde_json::json;
     use skillratings::mhth::MhthRating;
-    use testcontainers::{
-        ContainerAsync, GenericImage, ImageExt,
-        core::{IntoContainerPort, WaitFor},
-        runners::AsyncRunner,
-    };
-    use uuid::Uuid;
 
     use super::*;
     use crate::{
+        clock::SystemClock,
         nakama::{Authenticated, NakamaClient},
         rpc::{QueuedPlayer, create_match_queue_key, matchmaking::Player, player_queue_key},
+        test_support::{create_redis, redis_client},
     };
 
     #[tokio::test]
@@ -118,7 +301,19 @@ mod tests {
         let client = redis_client(host.to_string(), port);
         let conn = client.get_multiplexed_async_connection().await.unwrap();
         init_regions(conn.clone()).await;
-        let nakama = auth_client(666);
+        let server = MockServer::start_async().await;
+        let create_match = server
+            .mock_async(|when, then| {
+                when.method(POST)
+                    .path("/v2/console/api/endpoints/rpc/create_match");
+                then.status(200)
+                    .header("content-type", "application/json")
+                    .json_body(
+                        json!({"body": "{\"match_id\": \"nakama.match\"}", "error_message": ""}),
+                    );
+            })
+            .await;
+        let nakama = auth_client(server.address().port());
         // add players to queue
         for (score, p) in [
             player.clone(),
@@ -130,10 +325,11 @@ mod tests {
         .iter()
         .enumerate()
         {
-            let encode = bitcode::encode(p);
+            let encode = codec::encode(p);
             let key = player_queue_key(p);
-            conn.clone()
-                .set_ex(p.player_id, &encode, 200)
+            let mut pipe = redis::pipe();
+            store::put_player(&mut pipe, p, &encode, 200);
+            pipe.query_async(&mut conn.clone())
                 .await
                 .map(|_: ()| ())
                 .unwrap();
@@ -145,7 +341,7 @@ mod tests {
         }
         // set hosted match
         let create_match_key = create_match_queue_key(&player.region);
-        let encoded_player = bitcode::encode(&player);
+        let encoded_player = codec::encode(&player);
         conn.clone()
             .zadd(create_match_key, &encoded_player, 1)
             .await
@@ -155,13 +351,36 @@ mod tests {
             conn.clone(),
             Arc::new(reqwest::Client::new()),
             nakama.into(),
+            Arc::new(SystemClock::default()),
         );
         worker.hosted_matches().await.unwrap();
+        // every player passed the ready-check
+        let ready = worker.open_matches.iter().map(|open| open.id);
+        worker.ready_matches.extend(ready);
+        worker
+            .close_ready_matches(worker.clock.time_since_epoch())
+            .await;
         let matches = worker.start_matches().await.unwrap();
+        let match_id: Option<Uuid> = conn
+            .clone()
+            .get(player_match_key(&friend_1_id))
+            .await
+            .unwrap();
+        let active: Option<Vec<u8>> = conn
+            .clone()
+            .get(active_match_key(&match_id.unwrap()))
+            .await
+            .unwrap();
 
-        container.pause().await.unwrap();
+        let closed: usize = conn.clone().zcard(closed_matches_key()).await.unwrap();
 
-        assert_eq!(matches, 1)
+        create_match.assert_async().await;
+        assert_eq!(matches, 1);
+        assert_eq!(closed, 0);
+        let active: Match = codec::decode(&active.unwrap()).unwrap();
+        assert_eq!(active.host_id, host_id);
+        assert_eq!(active.state(), MatchState::Active);
+        assert_eq!(active.nakama_match_id.as_deref(), Some("nakama.match"));
     }
 
     async fn init_regions(conn: MultiplexedConnection) {
@@ -174,21 +393,6 @@ mod tests {
         crate::regions::set_regions(conn, regions).await.unwrap();
     }
 
-    fn redis_client(host: String, port: u16) -> redis::Client {
-        redis::Client::open(format!("redis://{host}:{port}")).unwrap()
-    }
-
-    async fn create_redis(port: u16) -> ContainerAsync<GenericImage> {
-        GenericImage::new("redis", "8.2.1-bookworm")
-            .with_exposed_port(port.tcp())
-            .with_wait_for(WaitFor::message_on_stdout("Ready to accept connections"))
-            .with_env_var("REDIS_PASSWORD", "super-secret-password")
-            .with_env_var("REDIS_USER", "redis_mms_admin")
-            .start()
-            .await
-            .expect("Failed to start Redis")
-    }
-
     pub fn auth_client(port: u16) -> NakamaClient<Authenticated> {
         NakamaClient {
             username: "username".to_string(),
@@ -198,6 +402,7 @@ mod tests {
             server_key_name: "defaultkey".to_string(),
             server_key_value: "server_key".to_string(),
             encryption_key: "encryption_key".to_string(),
+            legacy_until: None,
             _state: std::marker::PhantomData::<Authenticated>,
         }
     }
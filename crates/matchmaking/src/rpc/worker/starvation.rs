@@ -0,0 +1,106 @@
+use tracing::{info, warn};
+
+use crate::{
+    analytics::TickStats,
+    audit::{self, Action, Actor, AuditEvent},
+    rpc::worker::MatchmakingWorker,
+    starvation::{self, Starvation},
+};
+
+impl MatchmakingWorker {
+    /// Detects the queues of `stats` starving past the SLA, alerting once per starving queue.
+    /// Their regions are relaxed from the next run on, see [`crate::starvation`].
+    pub async fn detect_starvation(&mut self, stats: &TickStats) -> Starvation {
+        let mut conn = self.redis.clone();
+        let starved = starvation::detect(stats, self.starvation.sla_secs);
+        self.metrics.starved_queues += starved.queues.len() as u64;
+
+        for queue in &starved.queues {
+            if self.starved.contains(&queue.key()) {
+                continue;
+            }
+            warn!(
+                "queue `{}` is starving: {} players, the longest waiting {}s",
+                queue.key(),
+                queue.players,
+                queue.longest_wait_secs
+            );
+            let alert = AuditEvent::new(Action::QueueStarved, Actor::Worker, queue.key(), stats.at)
+                .with_reason(self.starvation.reason());
+            audit::emit(&mut conn, &alert).await;
+        }
+        for queue in &self.starved.queues {
+            if !starved.contains(&queue.key()) {
+                info!("queue `{}` recovered from starvation", queue.key());
+            }
+        }
+        self.starved = starved.clone();
+
+        starved
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use redis::AsyncCommands;
+
+    use super::*;
+    use crate::{
+        analytics::QueueStats,
+        clock::SystemClock,
+        nakama::{Authenticated, NakamaClient},
+        test_support::{create_redis, redis_client},
+    };
+
+    #[tokio::test]
+    async fn starving_queues_alert_once() {
+        let container = create_redis(6379).await;
+        let host = container.get_host().await.unwrap();
+        let port = container.get_host_port_ipv4(6379).await.unwrap();
+        let client = redis_client(host.to_string(), port);
+        let conn = client.get_multiplexed_async_connection().await.unwrap();
+        let mut worker = MatchmakingWorker::new(
+            conn.clone(),
+            Arc::new(reqwest::Client::new()),
+            auth_client(666).into(),
+            Arc::new(SystemClock::default()),
+        );
+        let stats = TickStats {
+            at: 1000,
+            matches_formed: 0,
+            queues: vec![QueueStats {
+                region: "CAN".to_string(),
+                difficulty: 2,
+                players: 3,
+                average_wait_secs: 400.,
+                longest_wait_secs: 600,
+                skill_spread: 0.,
+            }],
+        };
+
+        let first = worker.detect_starvation(&stats).await;
+        let second = worker.detect_starvation(&stats).await;
+        let alerts: usize = conn.clone().xlen(audit::audit_key()).await.unwrap();
+
+        assert_eq!(first, second);
+        assert!(first.contains("CAN:2"));
+        assert_eq!(worker.metrics.starved_queues, 2);
+        assert_eq!(alerts, 1);
+    }
+
+    fn auth_client(port: u16) -> NakamaClient<Authenticated> {
+        NakamaClient {
+            username: "username".to_string(),
+            password: "password".to_string(),
+            token: Some("super_random_token".to_string()),
+            url: format!("http://127.0.0.1:{port}"),
+            server_key_name: "defaultkey".to_string(),
+            server_key_value: "server_key".to_string(),
+            encryption_key: "encryption_key".to_string(),
+            legacy_until: None,
+            _state: std::marker::PhantomData::<Authenticated>,
+        }
+    }
+}
@@ -0,0 +1,364 @@
+use redis::RedisError;
+use skillratings::mhth::MhthRating;
+use tracing::{info, warn};
+use uuid::Uuid;
+
+use crate::{
+    codec,
+    lifecycle::{self, Lifecycle, MatchState},
+    party::{self, Party},
+    rpc::{
+        Match, QueuedPlayer, closed_matches_key,
+        matchmaking::{JoinMode, Player},
+        worker::{MatchmakingWorker, can_match},
+    },
+    store,
+    tournament::{self, Tournament},
+};
+
+#[derive(Debug, thiserror::Error)]
+pub enum Error {
+    #[error(transparent)]
+    Tournament(#[from] tournament::Error),
+    #[error(transparent)]
+    Party(#[from] party::Error),
+    #[error(transparent)]
+    CanMatch(#[from] can_match::Error),
+    #[error(transparent)]
+    Redis(#[from] RedisError),
+    #[error(transparent)]
+    Lifecycle(#[from] lifecycle::Error),
+}
+
+impl MatchmakingWorker {
+    /// Closes the registration of tournaments whose window ended, their bracket is scheduled
+    /// by [`Self::schedule_tournaments`]. Returns how many registrations closed.
+    pub async fn close_registrations(&mut self) -> Result<usize, Error> {
+        let mut conn = self.redis.clone();
+        let now = self.clock.time_since_epoch();
+        let mut closed = 0;
+        for tournament_id in tournament::registration_ended(&mut conn, now).await? {
+            // also drops tournaments an admin started early from the index
+            let closing = tournament::update_tournament(&mut conn, &tournament_id, |tournament| {
+                tournament.close_registration(now)
+            })
+            .await;
+            match closing {
+                Ok((tournament, true)) => {
+                    info!(
+                        "tournament `{tournament_id}` registration closed with {} parties",
+                        tournament.parties.len()
+                    );
+                    closed += 1;
+                }
+                Ok((_, false)) => {}
+                Err(tournament::Error::NotFound(_)) => {
+                    warn!("tournament `{tournament_id}` expired before its registration closed");
+                    tournament::forget_registration(&mut conn, &tournament_id).await?;
+                }
+                Err(tournament::Error::Changed(_)) => {
+                    info!("tournament `{tournament_id}` changed, closing its registration later");
+                }
+                Err(err) => return Err(err.into()),
+            }
+        }
+
+        Ok(closed)
+    }
+
+    /// Sends ready bracket matches of running tournaments to the closed matches queue,
+    /// so they are started like any other match. Missing parties forfeit.
+    pub async fn schedule_tournaments(&mut self) -> Result<usize, Error> {
+        let mut conn = self.redis.clone();
+        let mut scheduled = 0;
+        for tournament_id in tournament::active_tournaments(&mut conn).await? {
+            let mut tournament = tournament::get_tournament(&mut conn, &tournament_id).await?;
+            let mut steps = Vec::new();
+            while let Some(index) = tournament.bracket.next_ready() {
+                let Some([first, second]) = tournament.bracket.matches[index].parties() else {
+                    break;
+                };
+                let first_party = party::get_party(&mut conn, &first).await?;
+                let second_party = party::get_party(&mut conn, &second).await?;
+                let (first_party, second_party) = match (first_party, second_party) {
+                    (Some(first_party), Some(second_party)) => (first_party, second_party),
+                    (None, Some(_)) => {
+                        warn!("party `{first}` forfeits tournament `{tournament_id}`");
+                        tournament.report(index, second)?;
+                        steps.push(Step::Forfeit(index, second));
+                        continue;
+                    }
+                    (_, None) => {
+                        warn!("party `{second}` forfeits tournament `{tournament_id}`");
+                        tournament.report(index, first)?;
+                        steps.push(Step::Forfeit(index, first));
+                        continue;
+                    }
+                };
+
+                let mut bracket_match = bracket_match(&tournament, &first_party, &second_party)?;
+                // bracket matches are full as soon as they are formed
+                let now = self.clock.time_since_epoch();
+                bracket_match.lifecycle = Lifecycle::forming(now);
+                bracket_match.place(&self.region_tunings.get(&bracket_match.region).datacenters);
+                bracket_match.transition(MatchState::Ready, now)?;
+                let mut pipe = redis::pipe();
+                pipe.zadd(closed_matches_key(), codec::encode(&bracket_match), 0)
+                    .ignore();
+                store::index_match(&mut pipe, &bracket_match);
+                pipe.query_async(&mut conn).await.map(|_: ()| ())?;
+                tournament::link_match(&mut conn, &bracket_match.id, &tournament_id, index).await?;
+                tournament.bracket.matches[index].match_id = Some(bracket_match.id);
+                steps.push(Step::Scheduled(index, bracket_match.id));
+                info!(
+                    "tournament `{tournament_id}` match {index} scheduled as `{}`",
+                    bracket_match.id
+                );
+                scheduled += 1;
+            }
+            if steps.is_empty() {
+                continue;
+            }
+            // results reported meanwhile are kept, the steps are replayed on them
+            tournament::update_tournament(&mut conn, &tournament_id, |tournament| {
+                steps.iter().try_for_each(|step| step.replay(tournament))
+            })
+            .await?;
+        }
+
+        Ok(scheduled)
+    }
+}
+
+/// Bracket change of [`MatchmakingWorker::schedule_tournaments`]
+enum Step {
+    /// The party won the bracket match, its opponent left
+    Forfeit(usize, Uuid),
+    /// The bracket match was sent to the closed matches queue as the match
+    Scheduled(usize, Uuid),
+}
+
+impl Step {
+    fn replay(&self, tournament: &mut Tournament) -> Result<(), tournament::Error> {
+        match *self {
+            Self::Forfeit(index, winner) => match tournament.report(index, winner) {
+                Err(tournament::Error::AlreadyDecided(_)) => Ok(()),
+                reported => reported,
+            },
+            Self::Scheduled(index, match_id) => {
+                if let Some(bracket_match) = tournament.bracket.matches.get_mut(index) {
+                    bracket_match.match_id.get_or_insert(match_id);
+                }
+                Ok(())
+            }
+        }
+    }
+}
+
+/// Both parties in a single match hosted by the first party host.
+/// Tournament matches are not skill based, so players keep the default rating.
+/// Match of two parties, each party plays as one squad
+fn bracket_match(tournament: &Tournament, first: &Party, second: &Party) -> Result<Match, Error> {
+    let queued = |player_id: Uuid, party: &Party, join_mode: JoinMode| -> QueuedPlayer {
+        (
+            player_id,
+            Player {
+                region: tournament.region.clone(),
+                difficulty: tournament.difficulty,
+                join_mode: join_mode.into(),
+                party_member_id: party
+                    .members
+                    .iter()
+                    .filter(|member_id| **member_id != player_id)
+                    .map(Uuid::to_string)
+                    .collect(),
+                ..Default::default()
+            },
+            MhthRating::default(),
+        )
+            .into()
+    };
+    let host = queued(first.host_id, first, JoinMode::CreateRoom);
+    let others: Vec<QueuedPlayer> = first
+        .guests()
+        .map(|player_id| queued(*player_id, first, JoinMode::JoinRoom))
+        .chain(
+            second
+                .members
+                .iter()
+                .map(|player_id| queued(*player_id, second, JoinMode::JoinRoom)),
+        )
+        .collect();
+
+    let mut bracket_match = Match::host(&host, &others)?;
+    bracket_match.squads = vec![first.members.clone(), second.members.clone()];
+
+    Ok(bracket_match)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use redis::AsyncCommands;
+
+    use super::*;
+    use crate::{
+        clock::{Clock, SystemClock},
+        nakama::{Authenticated, NakamaClient},
+        rpc::matchmaking::{BracketFormat, TournamentStatus},
+        test_support::{create_redis, redis_client},
+    };
+
+    #[test]
+    fn parties_share_bracket_match() {
+        let tournament = Tournament::new(
+            "Weekend cup".to_string(),
+            "CAN".to_string(),
+            2,
+            BracketFormat::SingleElimination,
+        );
+        let first = Party::new(Uuid::new_v4());
+        let mut second = Party::new(Uuid::new_v4());
+        second.members.push(Uuid::new_v4());
+
+        let bracket_match = bracket_match(&tournament, &first, &second).unwrap();
+
+        assert_eq!(bracket_match.host_id, first.host_id);
+        assert_eq!(bracket_match.players.len(), 3);
+        assert_eq!(bracket_match.region, "CAN");
+        assert_eq!(bracket_match.difficulty, 2);
+        let guest = bracket_match
+            .players
+            .iter()
+            .find(|p| p.player_id == second.members[1])
+            .unwrap();
+        assert_eq!(guest.party_ids, vec![second.host_id.to_string()]);
+        assert_eq!(bracket_match.squads, vec![first.members, second.members]);
+    }
+
+    #[tokio::test]
+    async fn schedule_ready_bracket_matches() {
+        let container = create_redis(6379).await;
+        let host = container.get_host().await.unwrap();
+        let port = container.get_host_port_ipv4(6379).await.unwrap();
+        let client = redis_client(host.to_string(), port);
+        let mut conn = client.get_multiplexed_async_connection().await.unwrap();
+        let parties: Vec<Party> = (0..3).map(|_| Party::new(Uuid::new_v4())).collect();
+        let mut tournament = Tournament::new(
+            "Weekend cup".to_string(),
+            "CAN".to_string(),
+            1,
+            BracketFormat::SingleElimination,
+        );
+        // The last registered party disbanded before the tournament started
+        for party in &parties {
+            tournament.register(party.id, 1).unwrap();
+        }
+        tournament.register(Uuid::new_v4(), 1).unwrap();
+        tournament.start().unwrap();
+        for party in &parties {
+            party::save_party(&mut conn, party).await.unwrap();
+        }
+        tournament::save_tournament(&mut conn, &tournament)
+            .await
+            .unwrap();
+
+        let mut worker = MatchmakingWorker::new(
+            conn.clone(),
+            Arc::new(reqwest::Client::new()),
+            auth_client(666).into(),
+            Arc::new(SystemClock::default()),
+        );
+        let scheduled = worker.schedule_tournaments().await.unwrap();
+        let closed: Vec<Vec<u8>> = conn.zrange(closed_matches_key(), 0, -1).await.unwrap();
+        let saved = tournament::get_tournament(&mut conn, &tournament.id)
+            .await
+            .unwrap();
+
+        // first seed wins by forfeit, second seed plays the third
+        assert_eq!(scheduled, 1);
+        assert_eq!(closed.len(), 1);
+        let closed: Match = codec::decode(&closed[0]).unwrap();
+        assert_eq!(closed.host_id, parties[1].host_id);
+        assert_eq!(saved.bracket.matches[1].match_id, Some(closed.id));
+        assert_eq!(
+            saved.bracket.matches[0].winner.and_then(|w| w.party()),
+            Some(parties[0].id)
+        );
+    }
+
+    #[tokio::test]
+    async fn close_ended_registrations() {
+        let container = create_redis(6379).await;
+        let host = container.get_host().await.unwrap();
+        let port = container.get_host_port_ipv4(6379).await.unwrap();
+        let client = redis_client(host.to_string(), port);
+        let mut conn = client.get_multiplexed_async_connection().await.unwrap();
+        let clock = Arc::new(SystemClock::default());
+        let now = clock.time_since_epoch();
+        let new = |registration_secs: u64| {
+            let mut tournament = Tournament::new(
+                "Weekend cup".to_string(),
+                "CAN".to_string(),
+                1,
+                BracketFormat::SingleElimination,
+            )
+            .with_registration_window(now - 120, registration_secs)
+            .unwrap();
+            tournament.register(Uuid::new_v4(), 1).unwrap();
+            tournament.register(Uuid::new_v4(), 1).unwrap();
+            tournament
+        };
+        let ended = new(60);
+        let open = new(600);
+        let expired = new(60);
+        for tournament in [&ended, &open, &expired] {
+            tournament::save_tournament(&mut conn, tournament)
+                .await
+                .unwrap();
+        }
+        let _: () = conn
+            .del(tournament::tournament_key(&expired.id))
+            .await
+            .unwrap();
+
+        let mut worker = MatchmakingWorker::new(
+            conn.clone(),
+            Arc::new(reqwest::Client::new()),
+            auth_client(666).into(),
+            clock,
+        );
+        let closed = worker.close_registrations().await.unwrap();
+        let started = tournament::get_tournament(&mut conn, &ended.id)
+            .await
+            .unwrap();
+        let waiting = tournament::get_tournament(&mut conn, &open.id)
+            .await
+            .unwrap();
+        let active = tournament::active_tournaments(&mut conn).await.unwrap();
+        let pending = tournament::registration_ended(&mut conn, i64::MAX)
+            .await
+            .unwrap();
+
+        assert_eq!(closed, 1);
+        assert_eq!(pending, vec![open.id]);
+        assert_eq!(started.status(), TournamentStatus::Running);
+        assert_eq!(waiting.status(), TournamentStatus::Registration);
+        assert_eq!(active, vec![ended.id]);
+    }
+
+    pub fn auth_client(port: u16) -> NakamaClient<Authenticated> {
+        NakamaClient {
+            username: "username".to_string(),
+            password: "password".to_string(),
+            token: Some("super_random_token".to_string()),
+            url: format!("http://127.0.0.1:{port}"),
+            server_key_name: "defaultkey".to_string(),
+            server_key_value: "server_key".to_string(),
+            encryption_key: "encryption_key".to_string(),
+            legacy_until: None,
+            _state: std::marker::PhantomData::<Authenticated>,
+        }
+    }
+}
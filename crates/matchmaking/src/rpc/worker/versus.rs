@@ -0,0 +1,221 @@
+use redis::{AsyncCommands, RedisError};
+use tracing::{error, info};
+
+use crate::{
+    balance, codec,
+    lifecycle::MatchState,
+    metrics::fairness,
+    playlists,
+    regions::regions_key,
+    rpc::{
+        Match, QueuedPlayer, closed_matches_key, player_key, player_queue_key, player_versus_key,
+        versus_queue_key,
+        worker::{MatchmakingWorker, scan::scan},
+    },
+    store, versus,
+};
+
+#[derive(Debug, thiserror::Error)]
+pub enum Error {
+    #[error(transparent)]
+    Redis(#[from] RedisError),
+    #[error(transparent)]
+    BitcodeDeser(#[from] codec::Error),
+    #[error(transparent)]
+    Playlists(#[from] playlists::Error),
+    #[error(transparent)]
+    Lifecycle(#[from] crate::lifecycle::Error),
+}
+
+/// Queued parties by average skill, each party is a list of its players
+fn parties_by_skill(players: &[QueuedPlayer], team_size: usize) -> Vec<Vec<QueuedPlayer>> {
+    let mut parties: Vec<Vec<QueuedPlayer>> = balance::parties(players)
+        .into_iter()
+        .filter(|party| party.len() <= team_size)
+        .map(|party| party.into_iter().map(|i| players[i].clone()).collect())
+        .collect();
+    let skill = |party: &Vec<QueuedPlayer>| {
+        party.iter().map(|p| p.skillrating.rating).sum::<f64>() / party.len() as f64
+    };
+    parties.sort_by(|a, b| skill(a).total_cmp(&skill(b)));
+
+    parties
+}
+
+/// Consecutive parties of similar skill filling exactly `players` slots, starting at `start`
+fn window(parties: &[Vec<QueuedPlayer>], start: usize, players: usize) -> Option<Vec<usize>> {
+    let mut picked = Vec::new();
+    let mut count = 0;
+    for (index, party) in parties.iter().enumerate().skip(start) {
+        if count + party.len() > players {
+            continue;
+        }
+        count += party.len();
+        picked.push(index);
+        if count == players {
+            return Some(picked);
+        }
+    }
+
+    None
+}
+
+impl MatchmakingWorker {
+    /// Forms PvP matches from the versus queues, the fairest teams of similar skill are
+    /// closed right away. Returns how many matches were formed.
+    pub async fn versus_matches(&mut self) -> Result<usize, Error> {
+        let mut conn = self.redis.clone();
+        let Some(regions): Option<Vec<u8>> = conn.get(regions_key()).await? else {
+            error!("No regions registred");
+            return Ok(0);
+        };
+        let regions: Vec<String> = codec::decode(regions.as_slice())?;
+        let regions = playlists::queue_regions(&mut conn, &regions).await?;
+        let team_size = self.config.versus_team_size;
+        let now = self.clock.time_since_epoch();
+
+        let mut formed = 0;
+        for region in &regions {
+            if !self.region_tunings.get(region).is_due(self.runs) {
+                continue;
+            }
+            // the regions left are matched next tick
+            if self.budget.is_spent() {
+                break;
+            }
+            let queue_key = versus_queue_key(region);
+            let scanned = scan(
+                &mut conn,
+                &queue_key,
+                None,
+                self.config.scan_batch_size,
+                self.config.scan_budget,
+            )
+            .await?;
+            self.budget.spend_commands(scanned.commands);
+            let mut queued = Vec::new();
+            for (player, encoded) in scanned.entries.iter().filter_map(|player_bits| {
+                Some((
+                    self.metrics.decode::<QueuedPlayer>(player_bits)?,
+                    player_bits,
+                ))
+            }) {
+                if conn.exists(player_key(&player.player_id)).await? {
+                    queued.push(player);
+                } else {
+                    let _: () = conn.zrem(&queue_key, encoded).await?;
+                }
+            }
+
+            let mut parties = parties_by_skill(&queued, team_size);
+            let mut start = 0;
+            while let Some(picked) = window(&parties, start, 2 * team_size) {
+                if self.budget.is_spent() {
+                    break;
+                }
+                let players: Vec<QueuedPlayer> =
+                    picked.iter().flat_map(|i| parties[*i].clone()).collect();
+                if !versus::same_input(&players, now) {
+                    start += 1;
+                    continue;
+                }
+                let fair = versus::teams(&players, team_size)
+                    .filter(|(_, quality)| *quality >= self.config.versus_min_quality);
+                let Some((teams, quality)) = fair else {
+                    start += 1;
+                    continue;
+                };
+                let Some(mut ready) = Match::versus(players, teams, now) else {
+                    break;
+                };
+                ready.place(&self.region_tunings.get(&ready.region).datacenters);
+                ready.transition(MatchState::Ready, now)?;
+
+                let mut pipe = redis::pipe();
+                pipe.atomic();
+                for player in &ready.players {
+                    store::dequeue(
+                        &mut pipe,
+                        &player.player_id,
+                        &[player_versus_key(player), player_queue_key(player)],
+                    );
+                }
+                pipe.zadd(closed_matches_key(), codec::encode(&ready), now)
+                    .ignore();
+                store::index_match(&mut pipe, &ready);
+                pipe.query_async(&mut conn).await.map(|_: ()| ())?;
+                self.budget.spend_match();
+                self.budget.spend_commands(pipe.len());
+                info!(
+                    "versus match `{}` formed with quality {quality:.2}",
+                    ready.id
+                );
+                fairness::observe(&mut conn, &ready).await;
+                formed += 1;
+
+                for index in picked.into_iter().rev() {
+                    parties.remove(index);
+                }
+            }
+        }
+
+        Ok(formed)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use skillratings::mhth::MhthRating;
+    use uuid::Uuid;
+
+    use super::*;
+    use crate::rpc::matchmaking::{JoinMode, Player};
+
+    fn player(rating: f64) -> QueuedPlayer {
+        (
+            Uuid::new_v4(),
+            Player {
+                join_mode: JoinMode::Versus.into(),
+                ..Default::default()
+            },
+            MhthRating {
+                rating,
+                ..Default::default()
+            },
+        )
+            .into()
+    }
+
+    #[test]
+    fn windows_group_similar_skill() {
+        let players = vec![
+            player(50.),
+            player(10.),
+            player(12.),
+            player(48.),
+            player(11.),
+        ];
+        let parties = parties_by_skill(&players, 1);
+
+        let picked = window(&parties, 0, 2).unwrap();
+
+        let ratings: Vec<f64> = picked
+            .iter()
+            .map(|i| parties[*i][0].skillrating.rating)
+            .collect();
+        assert_eq!(ratings, vec![10., 11.]);
+        assert_eq!(window(&parties, 4, 2), None);
+    }
+
+    #[test]
+    fn windows_skip_parties_that_do_not_fit() {
+        let mut players = vec![player(10.), player(11.), player(12.), player(13.)];
+        players[1].party_ids = vec![players[0].player_id.to_string()];
+        let parties = parties_by_skill(&players, 2);
+
+        let picked = window(&parties, 0, 3).unwrap();
+
+        assert_eq!(picked.len(), 2);
+        assert_eq!(picked.iter().map(|i| parties[*i].len()).sum::<usize>(), 3);
+    }
+}
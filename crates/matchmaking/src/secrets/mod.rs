@@ -0,0 +1,466 @@
+//! Credentials of the matchmaker: the Nakama password, server key and encryption key, and the
+//! Redis password. They are read from the process env by default, or from the [`Provider`]
+//! selected by [`PROVIDER_VAR`]: a directory of secret files, Vault or a SigV4 signing proxy in
+//! front of AWS Secrets Manager.
+//!
+//! Provided secrets are cached in memory and reloaded every [`refresh_interval`], so rotating a
+//! secret in the provider reaches the process without a restart. The encryption key is read on
+//! every session check and follows a rotation right away, sessions signed with the previous key
+//! are still accepted for the [`grace_period`]. The other credentials are read when the Nakama
+//! and Redis clients are created. Secrets missing from the provider fall back to the env.
+//!
+//! The Vault token is renewed on every refresh, so a renewable token outlives its TTL. The
+//! `aws-proxy` provider holds no AWS credentials, the proxy signs its requests.
+
+use std::{
+    collections::HashMap,
+    path::{Path, PathBuf},
+    sync::{LazyLock, PoisonError, RwLock},
+    time::{Duration, Instant},
+};
+
+use tracing::{debug, error};
+
+/// Env var selecting the provider: `env`, `file`, `vault` or `aws-proxy`
+pub const PROVIDER_VAR: &str = "SECRETS_PROVIDER";
+/// Env var with the directory of the `file` provider, one file per secret named after it
+pub const DIR_VAR: &str = "SECRETS_DIR";
+pub const DEFAULT_DIR: &str = "/run/secrets";
+/// Env var with the seconds between secret refreshes
+pub const REFRESH_VAR: &str = "SECRETS_REFRESH_SECS";
+pub const DEFAULT_REFRESH: Duration = Duration::from_secs(300);
+/// Env var with the seconds a rotated secret is still accepted, see [`previous`]
+pub const GRACE_VAR: &str = "SECRETS_GRACE_SECS";
+pub const DEFAULT_GRACE: Duration = Duration::from_secs(3600);
+pub const VAULT_ADDR_VAR: &str = "VAULT_ADDR";
+pub const VAULT_TOKEN_VAR: &str = "VAULT_TOKEN";
+/// Env var with the KV v2 path holding the secrets, e.g. `secret/data/matchmaking`
+pub const VAULT_PATH_VAR: &str = "VAULT_SECRET_PATH";
+/// SigV4 signing proxy in front of AWS Secrets Manager, e.g. `aws-sigv4-proxy`
+pub const AWS_PROXY_URL_VAR: &str = "AWS_SECRETS_PROXY_URL";
+/// Env var with the name or ARN of the AWS secret
+pub const AWS_SECRET_ID_VAR: &str = "AWS_SECRET_ID";
+
+pub const NAKAMA_PASSWORD: &str = "NAKAMA_PASSWORD";
+pub const NAKAMA_SERVER_KEY: &str = "NAKAMA_SERVER_KEY";
+pub const NAKAMA_ENCRYPTION_KEY: &str = "NAKAMA_ENCRYPTION_KEY";
+pub const REDIS_PASSWORD: &str = "REDIS_PASSWORD";
+
+static SECRETS: LazyLock<RwLock<Cache>> = LazyLock::new(|| RwLock::new(Cache::default()));
+
+/// Provided secrets, with the value each rotated secret had before its rotation
+#[derive(Debug, Default)]
+struct Cache {
+    current: HashMap<String, String>,
+    previous: HashMap<String, (String, Instant)>,
+}
+
+impl Cache {
+    /// Replaces the secrets, the changed ones keep their value until `now` plus the grace period
+    fn replace(&mut self, secrets: HashMap<String, String>, now: Instant) {
+        for (name, secret) in &secrets {
+            if let Some(old) = self.current.get(name).filter(|old| *old != secret) {
+                self.previous.insert(name.clone(), (old.clone(), now));
+            }
+        }
+        self.current = secrets;
+    }
+
+    fn previous(&self, name: &str, now: Instant, grace: Duration) -> Option<&str> {
+        self.previous
+            .get(name)
+            .filter(|(_, rotated_at)| now.duration_since(*rotated_at) < grace)
+            .map(|(secret, _)| secret.as_str())
+    }
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum Error {
+    #[error("unknown secrets provider `{0}`, expected env, file, vault or aws-proxy")]
+    UnknownProvider(String),
+    #[error("`{0}` must be set for the secrets provider")]
+    MissingVar(&'static str),
+    #[error("secret `{0}` is not a JSON object of strings")]
+    InvalidSecret(String),
+    #[error("failed to read secrets: {0}")]
+    Io(#[from] std::io::Error),
+    #[error(transparent)]
+    Json(#[from] serde_json::Error),
+    #[error(transparent)]
+    Http(#[from] reqwest::Error),
+}
+
+/// Where the secrets are loaded from
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Provider {
+    /// The process env, nothing to load
+    Env,
+    /// Directory with one file per secret, e.g. Docker or Kubernetes secrets
+    File(PathBuf),
+    /// Vault KV v2 secret, each key is a secret
+    Vault {
+        addr: String,
+        token: String,
+        path: String,
+    },
+    /// AWS Secrets Manager secret holding a JSON object, each key is a secret, read through a
+    /// SigV4 signing proxy
+    AwsProxy {
+        proxy_url: String,
+        secret_id: String,
+    },
+}
+
+impl Provider {
+    pub fn from_env() -> Result<Self, Error> {
+        let Ok(provider) = std::env::var(PROVIDER_VAR) else {
+            return Ok(Self::Env);
+        };
+        match provider.trim().to_lowercase().as_str() {
+            "env" => Ok(Self::Env),
+            "file" => Ok(Self::File(
+                std::env::var(DIR_VAR)
+                    .unwrap_or_else(|_| DEFAULT_DIR.to_string())
+                    .into(),
+            )),
+            "vault" => Ok(Self::Vault {
+                addr: required(VAULT_ADDR_VAR)?,
+                token: required(VAULT_TOKEN_VAR)?,
+                path: required(VAULT_PATH_VAR)?,
+            }),
+            "aws-proxy" => Ok(Self::AwsProxy {
+                proxy_url: required(AWS_PROXY_URL_VAR)?,
+                secret_id: required(AWS_SECRET_ID_VAR)?,
+            }),
+            _ => Err(Error::UnknownProvider(provider)),
+        }
+    }
+
+    pub async fn load(&self, client: &reqwest::Client) -> Result<HashMap<String, String>, Error> {
+        match self {
+            Self::Env => Ok(HashMap::new()),
+            Self::File(dir) => load_dir(dir),
+            Self::Vault { addr, token, path } => fetch_vault(client, addr, token, path).await,
+            Self::AwsProxy {
+                proxy_url,
+                secret_id,
+            } => fetch_aws_proxy(client, proxy_url, secret_id).await,
+        }
+    }
+}
+
+fn required(var: &'static str) -> Result<String, Error> {
+    std::env::var(var).map_err(|_| Error::MissingVar(var))
+}
+
+pub fn refresh_interval() -> Duration {
+    std::env::var(REFRESH_VAR)
+        .ok()
+        .and_then(|secs| secs.parse().ok())
+        .map_or(DEFAULT_REFRESH, Duration::from_secs)
+}
+
+pub fn grace_period() -> Duration {
+    std::env::var(GRACE_VAR)
+        .ok()
+        .and_then(|secs| secs.parse().ok())
+        .map_or(DEFAULT_GRACE, Duration::from_secs)
+}
+
+/// Secrets in `dir`, named after their file, without the trailing newline
+pub fn load_dir(dir: impl AsRef<Path>) -> Result<HashMap<String, String>, Error> {
+    let mut secrets = HashMap::new();
+    for entry in std::fs::read_dir(dir)? {
+        let path = entry?.path();
+        let Some(name) = path.file_name().and_then(|name| name.to_str()) else {
+            continue;
+        };
+        if path.is_file() && !name.starts_with('.') {
+            let secret = std::fs::read_to_string(&path)?;
+            secrets.insert(name.to_string(), secret.trim_end().to_string());
+        }
+    }
+
+    Ok(secrets)
+}
+
+#[derive(Debug, serde::Deserialize)]
+struct VaultResponse {
+    data: VaultData,
+}
+
+#[derive(Debug, serde::Deserialize)]
+struct VaultData {
+    data: HashMap<String, String>,
+}
+
+pub async fn fetch_vault(
+    client: &reqwest::Client,
+    addr: &str,
+    token: &str,
+    path: &str,
+) -> Result<HashMap<String, String>, Error> {
+    let response: VaultResponse = client
+        .get(format!("{}/v1/{}", addr.trim_end_matches('/'), path))
+        .header("X-Vault-Token", token)
+        .send()
+        .await?
+        .error_for_status()?
+        .json()
+        .await?;
+
+    Ok(response.data.data)
+}
+
+/// Extends the TTL of the Vault `token`, tokens that are not renewable fail
+pub async fn renew_vault_token(
+    client: &reqwest::Client,
+    addr: &str,
+    token: &str,
+) -> Result<(), Error> {
+    client
+        .post(format!(
+            "{}/v1/auth/token/renew-self",
+            addr.trim_end_matches('/')
+        ))
+        .header("X-Vault-Token", token)
+        .send()
+        .await?
+        .error_for_status()?;
+
+    Ok(())
+}
+
+#[derive(Debug, serde::Deserialize)]
+struct GetSecretValueResponse {
+    #[serde(rename = "SecretString")]
+    secret_string: String,
+}
+
+/// Keys of the JSON object stored in the AWS secret `secret_id`, the request is unsigned and
+/// `proxy_url` signs it
+pub async fn fetch_aws_proxy(
+    client: &reqwest::Client,
+    proxy_url: &str,
+    secret_id: &str,
+) -> Result<HashMap<String, String>, Error> {
+    let response: GetSecretValueResponse = client
+        .post(proxy_url)
+        .header("X-Amz-Target", "secretsmanager.GetSecretValue")
+        .header("Content-Type", "application/x-amz-json-1.1")
+        .json(&serde_json::json!({ "SecretId": secret_id }))
+        .send()
+        .await?
+        .error_for_status()?
+        .json()
+        .await?;
+
+    serde_json::from_str(&response.secret_string)
+        .map_err(|_| Error::InvalidSecret(secret_id.to_string()))
+}
+
+/// Replaces the cached secrets, secrets missing from `secrets` fall back to the env
+pub fn set_secrets(secrets: HashMap<String, String>) {
+    SECRETS
+        .write()
+        .unwrap_or_else(PoisonError::into_inner)
+        .replace(secrets, Instant::now());
+}
+
+/// Secret `name` from the cache, or the env var of the same name
+pub fn get(name: &str) -> Option<String> {
+    SECRETS
+        .read()
+        .unwrap_or_else(PoisonError::into_inner)
+        .current
+        .get(name)
+        .cloned()
+        .or_else(|| std::env::var(name).ok())
+}
+
+/// Value of secret `name` before its last rotation, for the [`grace_period`] after it
+pub fn previous(name: &str) -> Option<String> {
+    SECRETS
+        .read()
+        .unwrap_or_else(PoisonError::into_inner)
+        .previous(name, Instant::now(), grace_period())
+        .map(str::to_string)
+}
+
+/// Reloads the cached secrets from `provider`, returns the number of secrets
+pub async fn refresh(client: &reqwest::Client, provider: &Provider) -> Result<usize, Error> {
+    let secrets = provider.load(client).await?;
+    let count = secrets.len();
+    set_secrets(secrets);
+    debug!("Loaded {count} secrets");
+
+    Ok(count)
+}
+
+/// Refreshes the secrets every [`refresh_interval`], failed refreshes keep the previous secrets.
+/// The Vault token is renewed before each refresh.
+pub async fn refresh_periodically(client: &reqwest::Client, provider: Provider) {
+    loop {
+        tokio::time::sleep(refresh_interval()).await;
+        if let Provider::Vault { addr, token, .. } = &provider
+            && let Err(err) = renew_vault_token(client, addr, token).await
+        {
+            error!("Vault token renewal: {err}");
+        }
+        if let Err(err) = refresh(client, &provider).await {
+            error!("Secrets refresh: {err}");
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use httpmock::prelude::*;
+    use serde_json::json;
+
+    use super::*;
+
+    #[test]
+    fn load_secret_files() {
+        let dir = std::env::temp_dir().join(format!("secrets-{}", uuid::Uuid::new_v4()));
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(dir.join(REDIS_PASSWORD), "redis-password\n").unwrap();
+        std::fs::write(dir.join(".hidden"), "ignored").unwrap();
+
+        let secrets = load_dir(&dir).unwrap();
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        assert_eq!(
+            secrets,
+            HashMap::from([(REDIS_PASSWORD.to_string(), "redis-password".to_string())])
+        );
+    }
+
+    #[test]
+    fn cached_secrets_shadow_the_env() {
+        set_secrets(HashMap::from([(
+            "SECRETS_TEST_ROTATED".to_string(),
+            "old".to_string(),
+        )]));
+        assert_eq!(get("SECRETS_TEST_ROTATED").as_deref(), Some("old"));
+
+        set_secrets(HashMap::from([(
+            "SECRETS_TEST_ROTATED".to_string(),
+            "new".to_string(),
+        )]));
+        assert_eq!(get("SECRETS_TEST_ROTATED").as_deref(), Some("new"));
+        assert_eq!(get("SECRETS_TEST_MISSING"), None);
+    }
+
+    #[test]
+    fn rotated_secrets_are_kept_for_the_grace_period() {
+        let mut cache = Cache::default();
+        let start = Instant::now();
+        let grace = Duration::from_secs(60);
+        let secrets = |key: &str| {
+            HashMap::from([
+                (NAKAMA_ENCRYPTION_KEY.to_string(), key.to_string()),
+                (REDIS_PASSWORD.to_string(), "redis-password".to_string()),
+            ])
+        };
+
+        cache.replace(secrets("old-key"), start);
+        cache.replace(secrets("new-key"), start);
+
+        assert_eq!(
+            cache.previous(NAKAMA_ENCRYPTION_KEY, start, grace),
+            Some("old-key")
+        );
+        assert_eq!(
+            cache.previous(NAKAMA_ENCRYPTION_KEY, start + grace, grace),
+            None
+        );
+        assert_eq!(cache.previous(REDIS_PASSWORD, start, grace), None);
+        assert_eq!(cache.current[NAKAMA_ENCRYPTION_KEY], "new-key");
+    }
+
+    #[tokio::test]
+    async fn renew_vault_token_self() {
+        let server = MockServer::start_async().await;
+        let mock = server
+            .mock_async(|when, then| {
+                when.method(POST)
+                    .path("/v1/auth/token/renew-self")
+                    .header("X-Vault-Token", "vault-token");
+                then.status(200)
+                    .header("content-type", "application/json")
+                    .json_body(json!({ "auth": { "client_token": "vault-token" } }));
+            })
+            .await;
+
+        renew_vault_token(&reqwest::Client::new(), &server.base_url(), "vault-token")
+            .await
+            .unwrap();
+
+        mock.assert_async().await;
+    }
+
+    #[tokio::test]
+    async fn fetch_vault_secrets() {
+        let server = MockServer::start_async().await;
+        let mock = server
+            .mock_async(|when, then| {
+                when.method(GET)
+                    .path("/v1/secret/data/matchmaking")
+                    .header("X-Vault-Token", "vault-token");
+                then.status(200)
+                    .header("content-type", "application/json")
+                    .json_body(json!({
+                        "data": {
+                            "data": { NAKAMA_SERVER_KEY: "server-key" },
+                            "metadata": { "version": 2 }
+                        }
+                    }));
+            })
+            .await;
+        let provider = Provider::Vault {
+            addr: server.base_url(),
+            token: "vault-token".to_string(),
+            path: "secret/data/matchmaking".to_string(),
+        };
+
+        let secrets = provider.load(&reqwest::Client::new()).await.unwrap();
+
+        mock.assert_async().await;
+        assert_eq!(
+            secrets.get(NAKAMA_SERVER_KEY).map(String::as_str),
+            Some("server-key")
+        );
+    }
+
+    #[tokio::test]
+    async fn fetch_aws_secrets_through_the_proxy() {
+        let server = MockServer::start_async().await;
+        let mock = server
+            .mock_async(|when, then| {
+                when.method(POST)
+                    .path("/")
+                    .header("X-Amz-Target", "secretsmanager.GetSecretValue")
+                    .json_body(json!({ "SecretId": "matchmaking" }));
+                then.status(200)
+                    .header("content-type", "application/x-amz-json-1.1")
+                    .json_body(json!({
+                        "Name": "matchmaking",
+                        "SecretString": "{\"REDIS_PASSWORD\":\"redis-password\"}"
+                    }));
+            })
+            .await;
+        let provider = Provider::AwsProxy {
+            proxy_url: server.url("/"),
+            secret_id: "matchmaking".to_string(),
+        };
+
+        let secrets = provider.load(&reqwest::Client::new()).await.unwrap();
+
+        mock.assert_async().await;
+        assert_eq!(
+            secrets.get(REDIS_PASSWORD).map(String::as_str),
+            Some("redis-password")
+        );
+    }
+}
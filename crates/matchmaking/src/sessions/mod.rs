@@ -0,0 +1,173 @@
+//! Revoked session tokens. Nakama logout and ban hooks call the `RevokeSession`
+//! RPC, `check_auth` rejects revoked tokens using a copy synced from Redis.
+
+use std::{
+    collections::{HashMap, HashSet},
+    sync::{LazyLock, PoisonError, RwLock},
+    time::Duration,
+};
+
+use chrono::Utc;
+use redis::{AsyncCommands, RedisError, aio::MultiplexedConnection};
+use tracing::error;
+use uuid::Uuid;
+
+use crate::namespace;
+
+pub const REVOKED_TOKENS_KEY: &str = "sessions:revoked_tokens";
+pub const REVOKED_USERS_KEY: &str = "sessions:revoked_users";
+/// Env var with the lifetime of the Nakama session tokens in seconds, its
+/// `session.token_expiry_sec`. Revocations are forgotten once every session they cover expired
+pub const SESSION_LIFETIME_VAR: &str = "NAKAMA_SESSION_LIFETIME_SECS";
+pub const DEFAULT_SESSION_LIFETIME: i64 = 86_400;
+/// Delay before a revocation made by another instance is enforced
+pub const SYNC_INTERVAL: Duration = Duration::from_secs(5);
+
+static REVOKED: LazyLock<RwLock<Revocations>> = LazyLock::new(Default::default);
+/// Seconds a session lives, see [`SESSION_LIFETIME_VAR`]
+pub(crate) static SESSION_LIFETIME: LazyLock<i64> = LazyLock::new(|| {
+    std::env::var(SESSION_LIFETIME_VAR)
+        .ok()
+        .and_then(|secs| secs.parse().ok())
+        .filter(|secs| *secs > 0)
+        .unwrap_or(DEFAULT_SESSION_LIFETIME)
+});
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Revocations {
+    pub tokens: HashSet<String>,
+    /// Sessions of the user issued until this timestamp are revoked
+    pub users: HashMap<String, i64>,
+}
+
+impl Revocations {
+    pub fn is_revoked(&self, token_id: &str, user_id: &str, issued_at: i64) -> bool {
+        self.tokens.contains(token_id)
+            || self
+                .users
+                .get(user_id)
+                .is_some_and(|revoked_at| issued_at <= *revoked_at)
+    }
+}
+
+pub fn revoked_tokens_key() -> String {
+    namespace::key(REVOKED_TOKENS_KEY)
+}
+
+pub fn revoked_users_key() -> String {
+    namespace::key(REVOKED_USERS_KEY)
+}
+
+/// Checks a session against the last synced revocations
+pub fn is_revoked(token_id: &str, user_id: &str, issued_at: i64) -> bool {
+    REVOKED
+        .read()
+        .unwrap_or_else(PoisonError::into_inner)
+        .is_revoked(token_id, user_id, issued_at)
+}
+
+/// Revokes single sessions, eg on logout
+pub async fn revoke_tokens(
+    conn: &mut MultiplexedConnection,
+    token_ids: &[String],
+) -> Result<(), RedisError> {
+    let now = Utc::now().timestamp();
+    let members: Vec<(i64, &String)> = token_ids.iter().map(|id| (now, id)).collect();
+
+    conn.zadd_multiple(revoked_tokens_key(), &members).await
+}
+
+/// Revokes every session issued to the user until now, eg on ban
+pub async fn revoke_user(
+    conn: &mut MultiplexedConnection,
+    user_id: &Uuid,
+) -> Result<(), RedisError> {
+    conn.zadd(
+        revoked_users_key(),
+        user_id.to_string(),
+        Utc::now().timestamp(),
+    )
+    .await
+}
+
+/// Revocations of sessions that may not have expired yet, older ones are dropped
+pub async fn revocations(conn: &mut MultiplexedConnection) -> Result<Revocations, RedisError> {
+    let expired = Utc::now().timestamp() - *SESSION_LIFETIME;
+    let (tokens, users): (Vec<String>, Vec<(String, i64)>) = redis::pipe()
+        .zrembyscore(revoked_tokens_key(), "-inf", expired)
+        .ignore()
+        .zrembyscore(revoked_users_key(), "-inf", expired)
+        .ignore()
+        .zrange(revoked_tokens_key(), 0, -1)
+        .zrange_withscores(revoked_users_key(), 0, -1)
+        .query_async(conn)
+        .await?;
+
+    Ok(Revocations {
+        tokens: tokens.into_iter().collect(),
+        users: users.into_iter().collect(),
+    })
+}
+
+/// Replaces the revocations checked by [`is_revoked`] with the stored ones
+pub async fn sync(conn: &mut MultiplexedConnection) -> Result<(), RedisError> {
+    let revocations = revocations(conn).await?;
+    *REVOKED.write().unwrap_or_else(PoisonError::into_inner) = revocations;
+
+    Ok(())
+}
+
+/// Syncs the revocations every [`SYNC_INTERVAL`], failed syncs keep the previous revocations
+pub async fn sync_periodically(mut conn: MultiplexedConnection) {
+    loop {
+        if let Err(err) = sync(&mut conn).await {
+            error!("Session revocations sync: {err}");
+        }
+        tokio::time::sleep(SYNC_INTERVAL).await;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+    use crate::test_support::{create_redis, redis_client};
+
+    #[test]
+    fn revoked_sessions() {
+        let revocations = Revocations {
+            tokens: HashSet::from(["stolen".to_string()]),
+            users: HashMap::from([("banned".to_string(), 100)]),
+        };
+
+        assert!(revocations.is_revoked("stolen", "player", 0));
+        assert!(revocations.is_revoked("token", "banned", 100));
+        assert!(!revocations.is_revoked("token", "banned", 101));
+        assert!(!revocations.is_revoked("token", "player", 0));
+    }
+
+    #[tokio::test]
+    async fn stored_revocations() {
+        let container = create_redis(6379).await;
+        let host = container.get_host().await.unwrap();
+        let port = container.get_host_port_ipv4(6379).await.unwrap();
+        let client = redis_client(host.to_string(), port);
+        let mut conn = client.get_multiplexed_async_connection().await.unwrap();
+        let user_id = Uuid::new_v4();
+        let now = Utc::now().timestamp();
+        let _: () = conn
+            .zadd(revoked_tokens_key(), "expired", now - *SESSION_LIFETIME - 1)
+            .await
+            .unwrap();
+
+        revoke_tokens(&mut conn, &["stolen".to_string()])
+            .await
+            .unwrap();
+        revoke_user(&mut conn, &user_id).await.unwrap();
+        let revocations = revocations(&mut conn).await.unwrap();
+
+        assert_eq!(revocations.tokens, HashSet::from(["stolen".to_string()]));
+        assert!(revocations.is_revoked("token", &user_id.to_string(), now));
+        assert!(!revocations.is_revoked("token", &user_id.to_string(), now + 10));
+    }
+}
@@ -0,0 +1,407 @@
+//! Synthetic player populations driven through `join_queue` by the `simulator` binary, and the
+//! wait-time and fairness report of a run. Populations are seeded so a load test can be replayed
+//! against another build or config.
+//!
+//! The server rates players through Nakama, so the generated ratings do not change how players
+//! are matched. The report measures how far apart they end up, i.e. the fairness the matchmaker
+//! would reach if Nakama served those ratings.
+
+use std::{collections::BTreeMap, fmt, path::Path};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    rng::Rng,
+    rpc::matchmaking::{JoinMode, PartyMode, Player},
+};
+
+#[derive(Debug, thiserror::Error)]
+pub enum Error {
+    #[error("failed to read simulation config: {0}")]
+    Io(#[from] std::io::Error),
+    #[error(transparent)]
+    Json(#[from] serde_json::Error),
+    #[error("simulation config is not valid: {0}")]
+    InvalidConfig(String),
+}
+
+/// Chance of a region, relative to the other regions of the config
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+pub struct RegionWeight {
+    pub region: String,
+    pub weight: u32,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+#[serde(default)]
+pub struct SimulationConfig {
+    pub seed: u64,
+    pub players: usize,
+    /// Parties joining the queue per second
+    pub join_rate: f64,
+    pub rating_mean: f64,
+    pub rating_deviation: f64,
+    pub regions: Vec<RegionWeight>,
+    pub min_ping: i32,
+    pub max_ping: i32,
+    /// Relative weight of each party size, the first entry weighs solo players
+    pub party_sizes: Vec<u32>,
+    /// Difficulties picked uniformly
+    pub difficulties: Vec<i32>,
+    /// Wait after which a player counts as not matched
+    pub timeout_secs: u64,
+}
+
+impl Default for SimulationConfig {
+    fn default() -> Self {
+        Self {
+            seed: 0,
+            players: 100,
+            join_rate: 10.,
+            rating_mean: 25.,
+            rating_deviation: 25. / 3.,
+            regions: vec![RegionWeight {
+                region: "CAN".to_string(),
+                weight: 1,
+            }],
+            min_ping: 20,
+            max_ping: 120,
+            party_sizes: vec![6, 2, 1, 1],
+            difficulties: vec![1, 2, 3],
+            timeout_secs: 120,
+        }
+    }
+}
+
+impl SimulationConfig {
+    pub fn validate(&self) -> Result<(), Error> {
+        let invalid = |reason: &str| Err(Error::InvalidConfig(reason.to_string()));
+        if self.players == 0 {
+            return invalid("no players to simulate");
+        }
+        if self.join_rate.is_nan() || self.join_rate <= 0. {
+            return invalid("join rate must be positive");
+        }
+        if self.rating_deviation < 0. {
+            return invalid("rating deviation must not be negative");
+        }
+        if self.regions.iter().all(|region| region.weight == 0) {
+            return invalid("no region can be picked");
+        }
+        if self.min_ping > self.max_ping {
+            return invalid("min ping is above max ping");
+        }
+        if self.party_sizes.iter().all(|weight| *weight == 0) {
+            return invalid("no party size can be picked");
+        }
+        if self.difficulties.is_empty() {
+            return invalid("no difficulty can be picked");
+        }
+
+        Ok(())
+    }
+}
+
+/// Reads the simulation config from a JSON file
+pub fn load_file(path: impl AsRef<Path>) -> Result<SimulationConfig, Error> {
+    let config = std::fs::read_to_string(path)?;
+    let config: SimulationConfig = serde_json::from_str(&config)?;
+    config.validate()?;
+
+    Ok(config)
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct SimulatedPlayer {
+    /// Nakama custom id of the account
+    pub custom_id: String,
+    pub rating: f64,
+    pub ping: i32,
+}
+
+/// Players queueing together, the first member hosts the party
+#[derive(Debug, Clone, PartialEq)]
+pub struct SimulatedParty {
+    pub region: String,
+    pub difficulty: i32,
+    pub members: Vec<SimulatedPlayer>,
+}
+
+impl SimulatedParty {
+    /// `join_queue` request of the host, the server queues the confirmed members with it
+    pub fn join_request(&self, host_id: &str) -> Player {
+        let party_mode = if self.members.len() > 1 {
+            PartyMode::Party
+        } else {
+            PartyMode::Solo
+        };
+
+        Player {
+            player_id: host_id.to_string(),
+            region: self.region.clone(),
+            ping: self.members.first().map_or(0, |host| host.ping),
+            difficulty: self.difficulty,
+            join_mode: JoinMode::JoinOrCreateRoom.into(),
+            party_mode: party_mode.into(),
+            ..Default::default()
+        }
+    }
+}
+
+/// Parties of `config.players` players in joining order, the config must be valid
+pub fn population(config: &SimulationConfig) -> Vec<SimulatedParty> {
+    let mut rng = Rng::new(config.seed);
+    let region_weights: Vec<u32> = config.regions.iter().map(|r| r.weight).collect();
+    let ping_range = (config.max_ping - config.min_ping) as u64 + 1;
+    let mut parties = Vec::new();
+    let mut index = 0;
+    while index < config.players {
+        let size = rng
+            .weighted(&config.party_sizes)
+            .map_or(1, |size| size + 1)
+            .min(config.players - index);
+        let region = rng.weighted(&region_weights).unwrap_or_default();
+        let difficulty = rng.below(config.difficulties.len() as u64) as usize;
+        let members = (index..index + size)
+            .map(|index| SimulatedPlayer {
+                custom_id: format!("simulated-{}-{index}", config.seed),
+                rating: rng
+                    .normal(config.rating_mean, config.rating_deviation)
+                    .max(0.),
+                ping: config.min_ping + rng.below(ping_range) as i32,
+            })
+            .collect();
+        parties.push(SimulatedParty {
+            region: config.regions[region].region.clone(),
+            difficulty: config.difficulties[difficulty],
+            members,
+        });
+        index += size;
+    }
+
+    parties
+}
+
+/// What happened to a simulated player
+#[derive(Debug, Clone, PartialEq)]
+pub enum Outcome {
+    Matched {
+        rating: f64,
+        wait_secs: f64,
+        match_id: String,
+        win_probability: Option<f64>,
+    },
+    /// No match within the configured timeout, or the server timed the player out
+    TimedOut,
+    /// The server rejected the party or the match failed to start
+    Failed,
+}
+
+/// Value below which `percentile` of the sorted values fall, nearest-rank
+pub fn percentile(sorted: &[f64], percentile: f64) -> Option<f64> {
+    let rank = (percentile * sorted.len() as f64).ceil() as usize;
+
+    sorted.get(rank.saturating_sub(1)).copied()
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Distribution {
+    pub p50: f64,
+    pub p90: f64,
+    pub p99: f64,
+    pub max: f64,
+}
+
+impl Distribution {
+    pub fn of(mut values: Vec<f64>) -> Option<Self> {
+        values.sort_by(f64::total_cmp);
+
+        Some(Self {
+            p50: percentile(&values, 0.5)?,
+            p90: percentile(&values, 0.9)?,
+            p99: percentile(&values, 0.99)?,
+            max: *values.last()?,
+        })
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Report {
+    pub players: usize,
+    pub matched: usize,
+    pub timed_out: usize,
+    pub failed: usize,
+    pub matches: usize,
+    pub wait_secs: Option<Distribution>,
+    /// Standard deviation of the ratings of each match
+    pub rating_spread: Option<Distribution>,
+    pub win_probability: Option<Distribution>,
+}
+
+impl Report {
+    pub fn new(outcomes: &[Outcome]) -> Self {
+        let mut matches: BTreeMap<&str, (Vec<f64>, Option<f64>)> = BTreeMap::new();
+        let mut waits = Vec::new();
+        let (mut timed_out, mut failed) = (0, 0);
+        for outcome in outcomes {
+            match outcome {
+                Outcome::Matched {
+                    rating,
+                    wait_secs,
+                    match_id,
+                    win_probability,
+                } => {
+                    waits.push(*wait_secs);
+                    let entry = matches.entry(match_id).or_default();
+                    entry.0.push(*rating);
+                    entry.1 = *win_probability;
+                }
+                Outcome::TimedOut => timed_out += 1,
+                Outcome::Failed => failed += 1,
+            }
+        }
+        let spreads = matches
+            .values()
+            .map(|(ratings, _)| {
+                let count = ratings.len() as f64;
+                let mean = ratings.iter().sum::<f64>() / count;
+                (ratings.iter().map(|r| (r - mean).powi(2)).sum::<f64>() / count).sqrt()
+            })
+            .collect();
+
+        Self {
+            players: outcomes.len(),
+            matched: waits.len(),
+            timed_out,
+            failed,
+            matches: matches.len(),
+            wait_secs: Distribution::of(waits),
+            rating_spread: Distribution::of(spreads),
+            win_probability: Distribution::of(
+                matches.values().filter_map(|(_, win)| *win).collect(),
+            ),
+        }
+    }
+}
+
+impl fmt::Display for Report {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(
+            f,
+            "players: {}, matched: {}, timed out: {}, failed: {}, matches: {}",
+            self.players, self.matched, self.timed_out, self.failed, self.matches
+        )?;
+        let distributions = [
+            ("wait (s)", self.wait_secs),
+            ("rating spread", self.rating_spread),
+            ("win probability", self.win_probability),
+        ];
+        for (name, distribution) in distributions {
+            match distribution {
+                Some(d) => writeln!(
+                    f,
+                    "{name}: p50 {:.2}, p90 {:.2}, p99 {:.2}, max {:.2}",
+                    d.p50, d.p90, d.p99, d.max
+                )?,
+                None => writeln!(f, "{name}: no samples")?,
+            }
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn population_is_seeded() {
+        let config = SimulationConfig {
+            players: 50,
+            ..Default::default()
+        };
+        let parties = population(&config);
+
+        assert_eq!(parties, population(&config));
+        assert_ne!(parties, population(&SimulationConfig { seed: 1, ..config }));
+        assert_eq!(parties.iter().map(|p| p.members.len()).sum::<usize>(), 50);
+        assert!(parties.iter().all(|party| party.members.len() <= 4));
+        assert!(
+            parties
+                .iter()
+                .flat_map(|party| &party.members)
+                .all(|player| (20..=120).contains(&player.ping))
+        );
+    }
+
+    #[test]
+    fn solo_parties_join_solo() {
+        let config = SimulationConfig {
+            party_sizes: vec![1],
+            ..Default::default()
+        };
+        let party = &population(&config)[0];
+        let request = party.join_request("host");
+
+        assert_eq!(party.members.len(), 1);
+        assert_eq!(request.party_mode, i32::from(PartyMode::Solo));
+        assert_eq!(request.ping, party.members[0].ping);
+        assert!(config.difficulties.contains(&request.difficulty));
+    }
+
+    #[test]
+    fn invalid_configs() {
+        let no_regions = SimulationConfig {
+            regions: Vec::new(),
+            ..Default::default()
+        };
+        let no_rate = SimulationConfig {
+            join_rate: 0.,
+            ..Default::default()
+        };
+
+        assert!(SimulationConfig::default().validate().is_ok());
+        assert!(matches!(
+            no_regions.validate(),
+            Err(Error::InvalidConfig(_))
+        ));
+        assert!(matches!(no_rate.validate(), Err(Error::InvalidConfig(_))));
+    }
+
+    #[test]
+    fn percentiles_use_the_nearest_rank() {
+        let values: Vec<f64> = (1..=10).map(f64::from).collect();
+
+        assert_eq!(percentile(&values, 0.5), Some(5.));
+        assert_eq!(percentile(&values, 0.99), Some(10.));
+        assert_eq!(percentile(&values, 0.), Some(1.));
+        assert_eq!(percentile(&[], 0.5), None);
+    }
+
+    #[test]
+    fn report_groups_players_by_match() {
+        let matched = |rating, wait_secs, match_id: &str| Outcome::Matched {
+            rating,
+            wait_secs,
+            match_id: match_id.to_string(),
+            win_probability: Some(0.5),
+        };
+        let report = Report::new(&[
+            matched(20., 4., "a"),
+            matched(30., 2., "a"),
+            matched(25., 10., "b"),
+            Outcome::TimedOut,
+            Outcome::Failed,
+        ]);
+
+        assert_eq!(report.players, 5);
+        assert_eq!((report.matched, report.timed_out, report.failed), (3, 1, 1));
+        assert_eq!(report.matches, 2);
+        assert_eq!(report.wait_secs.unwrap().p50, 4.);
+        assert_eq!(report.wait_secs.unwrap().max, 10.);
+        assert_eq!(report.rating_spread.unwrap().max, 5.);
+        assert_eq!(report.win_probability.unwrap().p50, 0.5);
+        assert!(report.to_string().contains("matched: 3"));
+    }
+}
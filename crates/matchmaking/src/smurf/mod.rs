@@ -0,0 +1,309 @@
+use std::collections::HashMap;
+
+use redis::{AsyncCommands, RedisError, aio::MultiplexedConnection};
+use skillratings::mhth::{MhthConfig, MhthRating, expected_score};
+use uuid::Uuid;
+
+use crate::{codec, namespace, rpc::server::MATCH_LIFETIME};
+
+pub const STATS_KEY: &str = "smurf:stats";
+/// Marks the matches whose stats were reported
+pub const REPORTED_KEY: &str = "smurf:reported";
+/// Stats are forgotten after 30 days without matches
+pub const STATS_TTL: u64 = 2_592_000;
+/// Matches played before an account can be flagged
+pub const MIN_MATCHES: u64 = 10;
+/// Win rate over the expected win rate that flags an account
+pub const WIN_RATE_MARGIN: f64 = 0.25;
+/// Average performance over the expected performance that flags an account
+pub const PERFORMANCE_RATIO: f64 = 1.5;
+/// Rating added per point of excess win rate to flagged accounts
+pub const RATING_BOOST: f64 = 20.;
+
+const MATCHES_FIELD: &str = "matches";
+const WINS_FIELD: &str = "wins";
+const EXPECTED_WINS_FIELD: &str = "expected_wins";
+const PERFORMANCE_FIELD: &str = "performance";
+
+#[derive(Debug, thiserror::Error)]
+pub enum Error {
+    #[error("match `{0}` is not active")]
+    MatchNotFound(Uuid),
+    #[error("player `{0}` is not the match host")]
+    NotHost(Uuid),
+    #[error("player `{0}` is not in the match")]
+    NotInMatch(Uuid),
+    #[error("performance `{0}` must be between 0 and 1")]
+    InvalidPerformance(f64),
+    #[error("stats of match `{0}` were already reported")]
+    AlreadyReported(Uuid),
+    #[error(transparent)]
+    Redis(#[from] RedisError),
+    #[error(transparent)]
+    BitcodeDeser(#[from] codec::Error),
+}
+
+impl From<Error> for tonic::Status {
+    fn from(value: Error) -> Self {
+        match value {
+            Error::MatchNotFound(_) => Self::not_found(value.to_string()),
+            Error::NotHost(_) => Self::permission_denied(value.to_string()),
+            Error::AlreadyReported(_) => Self::already_exists(value.to_string()),
+            Error::NotInMatch(_) | Error::InvalidPerformance(_) => {
+                Self::invalid_argument(value.to_string())
+            }
+            Error::Redis(_) | Error::BitcodeDeser(_) => {
+                Self::internal("Failed to load match stats")
+            }
+        }
+    }
+}
+
+/// Match results of a player compared to what their rating predicts
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct Stats {
+    pub matches: u64,
+    pub wins: u64,
+    /// Sum of the expected scores of every match
+    pub expected_wins: f64,
+    /// Sum of the performance ratios of every match, see [`performance_ratio`]
+    pub performance: f64,
+}
+
+impl Stats {
+    /// Win rate over the win rate expected from the player rating
+    pub fn excess_win_rate(&self) -> f64 {
+        if self.matches == 0 {
+            return 0.;
+        }
+
+        (self.wins as f64 - self.expected_wins) / self.matches as f64
+    }
+
+    pub fn performance_ratio(&self) -> f64 {
+        if self.matches == 0 {
+            return 1.;
+        }
+
+        self.performance / self.matches as f64
+    }
+
+    pub fn is_smurf(&self) -> bool {
+        self.matches >= MIN_MATCHES
+            && self.excess_win_rate() >= WIN_RATE_MARGIN
+            && self.performance_ratio() >= PERFORMANCE_RATIO
+    }
+
+    /// Raises the rating of flagged accounts and resets their uncertainty,
+    /// so the next results move their rating faster.
+    pub fn converge(&self, rating: MhthRating) -> MhthRating {
+        if !self.is_smurf() {
+            return rating;
+        }
+
+        MhthRating {
+            rating: self.excess_win_rate().mul_add(RATING_BOOST, rating.rating),
+            uncertainty: rating.uncertainty.max(MhthRating::new().uncertainty),
+            ..rating
+        }
+    }
+}
+
+/// Rating of the mission environment at a difficulty
+pub fn environment_rating(difficulty: i32) -> MhthRating {
+    MhthRating {
+        rating: 5f64.mul_add(f64::from(difficulty), 25.),
+        loadout_modifier: 0.,
+        uncertainty: MhthRating::new().uncertainty,
+    }
+}
+
+/// Probability of the player beating the environment
+pub fn expected_win(player: &MhthRating, environment: &MhthRating) -> f64 {
+    expected_score(player, environment, &MhthConfig::new()).0
+}
+
+/// Share of the team performance over the share expected from the player skill,
+/// `1.0` is a performance matching the rating.
+pub fn performance_ratio(player: &MhthRating, team: &[MhthRating], share: f64) -> f64 {
+    let skill = |rating: &MhthRating| (rating.rating + rating.loadout_modifier).max(f64::EPSILON);
+    let expected_share = skill(player) / team.iter().map(skill).sum::<f64>();
+
+    share / expected_share
+}
+
+pub fn stats_key(player_id: &Uuid) -> String {
+    namespace::key(format_args!("{STATS_KEY}:{player_id}"))
+}
+
+pub fn reported_key(match_id: &Uuid) -> String {
+    namespace::key(format_args!("{REPORTED_KEY}:{match_id}"))
+}
+
+/// Claims the stats report of a match, stats of a match are only recorded once
+pub async fn claim(conn: &mut MultiplexedConnection, match_id: &Uuid) -> Result<(), Error> {
+    // completed matches are kept for their lifetime
+    let claimed: bool = redis::cmd("SET")
+        .arg(reported_key(match_id))
+        .arg(1)
+        .arg("NX")
+        .arg("EX")
+        .arg(*MATCH_LIFETIME)
+        .query_async(conn)
+        .await?;
+    if !claimed {
+        return Err(Error::AlreadyReported(*match_id));
+    }
+
+    Ok(())
+}
+
+/// Releases the claim of a report that failed, so the host can report the match again
+pub async fn release(conn: &mut MultiplexedConnection, match_id: &Uuid) -> Result<(), RedisError> {
+    conn.del(reported_key(match_id)).await
+}
+
+/// Records a match result, returns the updated stats
+pub async fn record(
+    conn: &mut MultiplexedConnection,
+    player_id: &Uuid,
+    won: bool,
+    expected_win: f64,
+    performance_ratio: f64,
+) -> Result<Stats, RedisError> {
+    let key = stats_key(player_id);
+    let (fields,): (HashMap<String, f64>,) = redis::pipe()
+        .atomic()
+        .hincr(&key, MATCHES_FIELD, 1)
+        .ignore()
+        .hincr(&key, WINS_FIELD, u64::from(won))
+        .ignore()
+        .hincr(&key, EXPECTED_WINS_FIELD, expected_win)
+        .ignore()
+        .hincr(&key, PERFORMANCE_FIELD, performance_ratio)
+        .ignore()
+        .expire(&key, STATS_TTL as i64)
+        .ignore()
+        .hgetall(&key)
+        .query_async(conn)
+        .await?;
+
+    Ok(Stats::from_fields(&fields))
+}
+
+pub async fn stats(
+    conn: &mut MultiplexedConnection,
+    player_id: &Uuid,
+) -> Result<Stats, RedisError> {
+    let fields: HashMap<String, f64> = conn.hgetall(stats_key(player_id)).await?;
+
+    Ok(Stats::from_fields(&fields))
+}
+
+impl Stats {
+    fn from_fields(fields: &HashMap<String, f64>) -> Self {
+        let field = |name: &str| fields.get(name).copied().unwrap_or_default();
+
+        Self {
+            matches: field(MATCHES_FIELD) as u64,
+            wins: field(WINS_FIELD) as u64,
+            expected_wins: field(EXPECTED_WINS_FIELD),
+            performance: field(PERFORMANCE_FIELD),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+    use crate::test_support::{create_redis, redis_client};
+
+    #[test]
+    fn flags_dominant_accounts() {
+        let smurf = Stats {
+            matches: 10,
+            wins: 9,
+            expected_wins: 5.,
+            performance: 20.,
+        };
+        let lucky = Stats {
+            performance: 10.,
+            ..smurf
+        };
+        let new_account = Stats {
+            matches: 3,
+            wins: 3,
+            expected_wins: 1.5,
+            performance: 6.,
+        };
+
+        assert!(smurf.is_smurf());
+        assert!(!lucky.is_smurf());
+        assert!(!new_account.is_smurf());
+        assert!(!Stats::default().is_smurf());
+    }
+
+    #[test]
+    fn converges_flagged_ratings() {
+        let smurf = Stats {
+            matches: 10,
+            wins: 9,
+            expected_wins: 5.,
+            performance: 20.,
+        };
+        let settled = MhthRating {
+            rating: 25.,
+            loadout_modifier: 1.,
+            uncertainty: 1.,
+        };
+
+        let converged = smurf.converge(settled);
+
+        assert_eq!(converged.rating, 33.);
+        assert_eq!(converged.uncertainty, MhthRating::new().uncertainty);
+        assert_eq!(Stats::default().converge(settled), settled);
+    }
+
+    #[test]
+    fn expected_performance() {
+        let team = [MhthRating::new(), MhthRating::new()];
+
+        assert_eq!(performance_ratio(&team[0], &team, 0.5), 1.);
+        assert_eq!(performance_ratio(&team[0], &team, 0.75), 1.5);
+        assert!(expected_win(&MhthRating::new(), &environment_rating(0)) > 0.5);
+        assert!(expected_win(&MhthRating::new(), &environment_rating(3)) < 0.5);
+    }
+
+    #[tokio::test]
+    async fn record_match_stats() {
+        let container = create_redis(6379).await;
+        let host = container.get_host().await.unwrap();
+        let port = container.get_host_port_ipv4(6379).await.unwrap();
+        let client = redis_client(host.to_string(), port);
+        let mut conn = client.get_multiplexed_async_connection().await.unwrap();
+        let player_id = Uuid::new_v4();
+
+        let empty = stats(&mut conn, &player_id).await.unwrap();
+        record(&mut conn, &player_id, true, 0.5, 1.5).await.unwrap();
+        let recorded = record(&mut conn, &player_id, false, 0.25, 1.)
+            .await
+            .unwrap();
+        let match_id = Uuid::new_v4();
+        let first = claim(&mut conn, &match_id).await;
+        let duplicate = claim(&mut conn, &match_id).await;
+
+        assert_eq!(empty, Stats::default());
+        assert_eq!(
+            recorded,
+            Stats {
+                matches: 2,
+                wins: 1,
+                expected_wins: 0.75,
+                performance: 2.5,
+            }
+        );
+        assert!(first.is_ok());
+        assert!(matches!(duplicate, Err(Error::AlreadyReported(id)) if id == match_id));
+    }
+}
@@ -0,0 +1,231 @@
+//! Snapshot of the matchmaking state in Redis, i.e. the queues, matches, parties and queued
+//! players, saved to a file by the `snapshot` binary and restored into another Redis. Seeds
+//! staging with realistic state and keeps everyone queued through an emergency Redis migration.
+//!
+//! Keys are saved with Redis `DUMP` and their remaining TTL, relative to the namespace, so a
+//! snapshot can be restored into another namespace. `DUMP` payloads only restore into a Redis of
+//! the same or a newer version.
+
+use std::path::Path;
+
+use bitcode::{Decode, Encode};
+use redis::{RedisError, aio::MultiplexedConnection};
+
+use crate::{
+    codec, namespace,
+    party::PARTY_KEY,
+    rpc::{
+        ACTIVE_MATCH, BACKFILL_QUEUE, CLOSED_MATCHES, CREATE_MATCH_QUEUE, DEAD_MATCHES,
+        FORMING_MATCH, OPEN_MATCHES, PLAYER_MATCH, PLAYER_QUEUE, RAID_QUEUE, VERSUS_QUEUE,
+    },
+    store::INDEX,
+};
+
+/// Keys read per `SCAN` and restored per pipeline
+pub const SNAPSHOT_BATCH: usize = 500;
+/// Matches a UUID, queued players are stored under their bare id
+const ID_PATTERN: &str = "????????-????-????-????-????????????";
+
+#[derive(Debug, thiserror::Error)]
+pub enum Error {
+    #[error("failed to access snapshot file: {0}")]
+    Io(#[from] std::io::Error),
+    #[error(transparent)]
+    Redis(#[from] RedisError),
+    #[error(transparent)]
+    BitcodeDeser(#[from] codec::Error),
+}
+
+/// Key saved with its `DUMP` payload
+#[derive(Debug, Clone, Encode, Decode, PartialEq, Eq)]
+pub struct SnapshotEntry {
+    /// Key without the namespace
+    pub key: String,
+    pub dump: Vec<u8>,
+    /// Remaining milliseconds to live, `0` for keys without expiry
+    pub ttl_ms: i64,
+}
+
+#[derive(Debug, Clone, Default, Encode, Decode, PartialEq, Eq)]
+pub struct Snapshot {
+    pub taken_at: i64,
+    pub entries: Vec<SnapshotEntry>,
+}
+
+impl codec::Versioned for Snapshot {}
+
+/// Patterns of the state keys, inside the namespace
+pub fn state_patterns() -> Vec<String> {
+    [
+        format!("{PLAYER_QUEUE}:*"),
+        format!("{CREATE_MATCH_QUEUE}:*"),
+        format!("{BACKFILL_QUEUE}:*"),
+        format!("{VERSUS_QUEUE}:*"),
+        format!("{RAID_QUEUE}:*"),
+        format!("{OPEN_MATCHES}:*"),
+        CLOSED_MATCHES.to_string(),
+        DEAD_MATCHES.to_string(),
+        format!("{ACTIVE_MATCH}:*"),
+        format!("{PLAYER_MATCH}:*"),
+        format!("{FORMING_MATCH}:*"),
+        format!("match:{ID_PATTERN}"),
+        format!("{PARTY_KEY}:*"),
+        format!("{INDEX}:*"),
+        ID_PATTERN.to_string(),
+    ]
+    .into_iter()
+    .map(namespace::key)
+    .collect()
+}
+
+pub(crate) async fn scan_keys(
+    conn: &mut MultiplexedConnection,
+    pattern: &str,
+) -> Result<Vec<String>, RedisError> {
+    let mut keys = Vec::new();
+    let mut cursor: u64 = 0;
+    loop {
+        let (next, page): (u64, Vec<String>) = redis::cmd("SCAN")
+            .arg(cursor)
+            .arg("MATCH")
+            .arg(pattern)
+            .arg("COUNT")
+            .arg(SNAPSHOT_BATCH)
+            .query_async(conn)
+            .await?;
+        keys.extend(page);
+        if next == 0 {
+            break;
+        }
+        cursor = next;
+    }
+    keys.sort();
+    keys.dedup();
+
+    Ok(keys)
+}
+
+/// Dumps every state key, keys expiring while the snapshot is taken are skipped
+pub async fn take(conn: &mut MultiplexedConnection, now: i64) -> Result<Snapshot, Error> {
+    let prefix = namespace::key("");
+    let mut snapshot = Snapshot {
+        taken_at: now,
+        entries: Vec::new(),
+    };
+    for pattern in state_patterns() {
+        let keys = scan_keys(conn, &pattern).await?;
+        for batch in keys.chunks(SNAPSHOT_BATCH) {
+            let mut pipe = redis::pipe();
+            for key in batch {
+                pipe.cmd("DUMP").arg(key).cmd("PTTL").arg(key);
+            }
+            let dumps: Vec<(Option<Vec<u8>>, i64)> = pipe.query_async(conn).await?;
+            snapshot
+                .entries
+                .extend(batch.iter().zip(dumps).filter_map(|(key, (dump, ttl_ms))| {
+                    Some(SnapshotEntry {
+                        key: key.strip_prefix(&prefix).unwrap_or(key).to_string(),
+                        dump: dump?,
+                        ttl_ms: ttl_ms.max(0),
+                    })
+                }));
+        }
+    }
+
+    Ok(snapshot)
+}
+
+/// Restores the snapshot into the current namespace, replacing keys that already exist.
+/// TTLs keep counting from when the snapshot was taken. Returns the keys restored.
+pub async fn restore(
+    conn: &mut MultiplexedConnection,
+    snapshot: &Snapshot,
+) -> Result<usize, Error> {
+    for batch in snapshot.entries.chunks(SNAPSHOT_BATCH) {
+        let mut pipe = redis::pipe();
+        pipe.atomic();
+        for entry in batch {
+            pipe.cmd("RESTORE")
+                .arg(namespace::key(&entry.key))
+                .arg(entry.ttl_ms)
+                .arg(&entry.dump)
+                .arg("REPLACE")
+                .ignore();
+        }
+        pipe.query_async::<()>(conn).await?;
+    }
+
+    Ok(snapshot.entries.len())
+}
+
+pub fn save_file(path: impl AsRef<Path>, snapshot: &Snapshot) -> Result<(), Error> {
+    Ok(std::fs::write(path, codec::encode(snapshot))?)
+}
+
+pub fn load_file(path: impl AsRef<Path>) -> Result<Snapshot, Error> {
+    Ok(codec::decode(&std::fs::read(path)?)?)
+}
+
+#[cfg(test)]
+mod tests {
+    use redis::AsyncCommands;
+    use uuid::Uuid;
+
+    use super::*;
+    use crate::{
+        rpc::{match_id_key, player_key},
+        test_support::{create_redis, redis_client},
+    };
+
+    #[tokio::test]
+    async fn snapshot_restores_the_state_keys() {
+        let container = create_redis(6379).await;
+        let host = container.get_host().await.unwrap();
+        let port = container.get_host_port_ipv4(6379).await.unwrap();
+        let client = redis_client(host.to_string(), port);
+        let mut conn = client.get_multiplexed_async_connection().await.unwrap();
+        let (player_id, match_id) = (Uuid::new_v4(), Uuid::new_v4());
+        let queue = namespace::key(format_args!("{PLAYER_QUEUE}:0:CAN:0"));
+
+        let _: () = conn.zadd(&queue, b"player".as_slice(), 10).await.unwrap();
+        let _: () = conn
+            .set_ex(player_key(&player_id), b"queued".as_slice(), 600)
+            .await
+            .unwrap();
+        let _: () = conn
+            .set(match_id_key(&match_id), b"match".as_slice())
+            .await
+            .unwrap();
+        let _: () = conn.set("unrelated", b"kept out".as_slice()).await.unwrap();
+        let snapshot = take(&mut conn, 42).await.unwrap();
+        let path = std::env::temp_dir().join(format!("snapshot-{}", Uuid::new_v4()));
+        save_file(&path, &snapshot).unwrap();
+        let _: () = redis::cmd("FLUSHALL").query_async(&mut conn).await.unwrap();
+        let restored = restore(&mut conn, &load_file(&path).unwrap())
+            .await
+            .unwrap();
+        std::fs::remove_file(path).unwrap();
+
+        let queued: Vec<(Vec<u8>, f64)> = conn.zrange_withscores(&queue, 0, -1).await.unwrap();
+        let player: Vec<u8> = conn.get(player_key(&player_id)).await.unwrap();
+        let ttl: i64 = conn.ttl(player_key(&player_id)).await.unwrap();
+        let unrelated: Option<Vec<u8>> = conn.get("unrelated").await.unwrap();
+
+        assert_eq!(snapshot.taken_at, 42);
+        assert_eq!(restored, 3);
+        assert_eq!(queued, vec![(b"player".to_vec(), 10.)]);
+        assert_eq!(player, b"queued");
+        assert!((1..=600).contains(&ttl));
+        assert_eq!(unrelated, None);
+    }
+
+    #[test]
+    fn patterns_cover_every_queue() {
+        let patterns = state_patterns();
+
+        assert!(patterns.contains(&format!("{PLAYER_QUEUE}:*")));
+        assert!(patterns.contains(&format!("{RAID_QUEUE}:*")));
+        assert!(patterns.contains(&CLOSED_MATCHES.to_string()));
+        assert!(patterns.contains(&format!("{INDEX}:*")));
+    }
+}
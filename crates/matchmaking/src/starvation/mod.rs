@@ -0,0 +1,296 @@
+//! Starved queues: regions and difficulties where a player waited past the starvation SLA
+//! without a match. The worker checks the queue analytics of every tick, alerts when a queue
+//! starts starving and, when [`STARVATION_RELAXATION_VAR`] enables them, relaxes the tuning of
+//! the starved regions until their queues recover:
+//!
+//! - `cross_region` moves the players waiting past the SLA to the fallback region of the region,
+//!   or to the busiest region of the tick when none is configured.
+//! - `bot_fill` closes the open matches whose host waited past the SLA with the players they
+//!   have, the game fills the empty slots with bots.
+
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    str::FromStr,
+};
+
+use crate::{analytics::TickStats, regions::tuning::RegionTunings};
+
+/// Env var with the longest wait before a queue starves, in seconds
+pub const STARVATION_SLA_VAR: &str = "STARVATION_SLA_SECS";
+pub const DEFAULT_STARVATION_SLA_SECS: i64 = 300;
+/// Env var with the comma separated [`Relaxation`]s of starved regions, none by default
+pub const STARVATION_RELAXATION_VAR: &str = "STARVATION_RELAXATION";
+
+#[derive(Debug, thiserror::Error)]
+pub enum Error {
+    #[error("invalid starvation policy: {0}")]
+    Invalid(String),
+}
+
+/// Relaxation applied to the tuning of a starved region
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Relaxation {
+    CrossRegion,
+    BotFill,
+}
+
+impl Relaxation {
+    pub const fn as_str(self) -> &'static str {
+        match self {
+            Self::CrossRegion => "cross_region",
+            Self::BotFill => "bot_fill",
+        }
+    }
+}
+
+impl FromStr for Relaxation {
+    type Err = Error;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value.trim() {
+            "cross_region" => Ok(Self::CrossRegion),
+            "bot_fill" => Ok(Self::BotFill),
+            _ => Err(Error::Invalid(format!("unknown relaxation `{value}`"))),
+        }
+    }
+}
+
+/// When a queue starves and how starved regions are relaxed
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct StarvationPolicy {
+    pub sla_secs: i64,
+    pub relaxations: BTreeSet<Relaxation>,
+}
+
+impl Default for StarvationPolicy {
+    fn default() -> Self {
+        Self {
+            sla_secs: DEFAULT_STARVATION_SLA_SECS,
+            relaxations: BTreeSet::new(),
+        }
+    }
+}
+
+impl StarvationPolicy {
+    /// Policy of [`STARVATION_SLA_VAR`] and [`STARVATION_RELAXATION_VAR`], unset vars only alert
+    /// after [`DEFAULT_STARVATION_SLA_SECS`]
+    pub fn from_env() -> Result<Self, Error> {
+        let sla_secs =
+            match std::env::var(STARVATION_SLA_VAR) {
+                Ok(value) => value.parse().ok().filter(|secs| *secs > 0).ok_or_else(|| {
+                    Error::Invalid(format!("`{STARVATION_SLA_VAR}` is not valid"))
+                })?,
+                Err(_) => DEFAULT_STARVATION_SLA_SECS,
+            };
+        let relaxations = match std::env::var(STARVATION_RELAXATION_VAR) {
+            Ok(value) => parse_relaxations(&value)?,
+            Err(_) => BTreeSet::new(),
+        };
+
+        Ok(Self {
+            sla_secs,
+            relaxations,
+        })
+    }
+
+    /// Relaxes the tuning of the regions of `starvation`, loaded tunings are relaxed again on
+    /// every run until the queues recover
+    pub fn relax(&self, tunings: &mut RegionTunings, starvation: &Starvation) {
+        for region in starvation.regions() {
+            // playlist queues share the tuning of their region
+            let tuning = tunings.0.entry(region.to_string()).or_default();
+            if self.relaxations.contains(&Relaxation::CrossRegion) {
+                if tuning.fallback_region.is_none() {
+                    tuning.fallback_region = starvation.busiest_region.clone();
+                }
+                tuning.fallback_after_secs = tuning.fallback_after_secs.min(self.sla_secs);
+            }
+            if self.relaxations.contains(&Relaxation::BotFill) {
+                tuning.min_players = Some(1);
+                tuning.close_after_secs = tuning.close_after_secs.min(self.sla_secs);
+            }
+        }
+    }
+
+    /// Names of the relaxations, e.g. `cross_region,bot_fill`, as the reason of the alerts
+    pub fn reason(&self) -> String {
+        self.relaxations
+            .iter()
+            .map(|relaxation| relaxation.as_str())
+            .collect::<Vec<_>>()
+            .join(",")
+    }
+}
+
+/// Queue of a region at a difficulty whose longest wait is past the SLA
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct StarvedQueue {
+    pub region: String,
+    pub difficulty: i32,
+    pub players: u64,
+    pub longest_wait_secs: i64,
+}
+
+impl StarvedQueue {
+    /// Target of the alerts, e.g. `CAN:2`
+    pub fn key(&self) -> String {
+        format!("{}:{}", self.region, self.difficulty)
+    }
+}
+
+/// Starved queues of a tick
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Starvation {
+    pub queues: Vec<StarvedQueue>,
+    /// Region with the most queued players that is not starving, where cross-region relaxation
+    /// moves the players of regions without a fallback
+    pub busiest_region: Option<String>,
+}
+
+impl Starvation {
+    /// Starving regions, playlist queues count for their region
+    pub fn regions(&self) -> BTreeSet<&str> {
+        self.queues
+            .iter()
+            .map(|queue| base_region(&queue.region))
+            .collect()
+    }
+
+    /// Whether the queue of `region` at `difficulty` starved in this tick
+    pub fn starves(&self, region: &str, difficulty: i32) -> bool {
+        self.queues
+            .iter()
+            .any(|queue| queue.region == region && queue.difficulty == difficulty)
+    }
+
+    /// Whether the queue `key` starved in this tick, see [`StarvedQueue::key`]
+    pub fn contains(&self, key: &str) -> bool {
+        self.queues.iter().any(|queue| queue.key() == key)
+    }
+}
+
+/// Queues of `stats` where a player waited more than `sla_secs`
+pub fn detect(stats: &TickStats, sla_secs: i64) -> Starvation {
+    let queues: Vec<StarvedQueue> = stats
+        .queues
+        .iter()
+        .filter(|queue| queue.players > 0 && queue.longest_wait_secs > sla_secs)
+        .map(|queue| StarvedQueue {
+            region: queue.region.clone(),
+            difficulty: queue.difficulty,
+            players: queue.players,
+            longest_wait_secs: queue.longest_wait_secs,
+        })
+        .collect();
+    let starved: BTreeSet<&str> = queues
+        .iter()
+        .map(|queue| base_region(&queue.region))
+        .collect();
+    let busiest_region = busiest_region(stats, &starved);
+
+    Starvation {
+        queues,
+        busiest_region,
+    }
+}
+
+pub fn parse_relaxations(value: &str) -> Result<BTreeSet<Relaxation>, Error> {
+    value
+        .split(',')
+        .filter(|relaxation| !relaxation.trim().is_empty())
+        .map(Relaxation::from_str)
+        .collect()
+}
+
+fn base_region(queue_region: &str) -> &str {
+    queue_region.split(':').next().unwrap_or(queue_region)
+}
+
+fn busiest_region(stats: &TickStats, starved: &BTreeSet<&str>) -> Option<String> {
+    let mut players = BTreeMap::<&str, u64>::new();
+    for queue in &stats.queues {
+        let region = base_region(&queue.region);
+        if !starved.contains(region) {
+            *players.entry(region).or_default() += queue.players;
+        }
+    }
+
+    players
+        .into_iter()
+        .max_by_key(|(_, players)| *players)
+        .map(|(region, _)| region.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{analytics::QueueStats, regions::tuning::RegionTuning};
+
+    fn queue(region: &str, players: u64, longest_wait_secs: i64) -> QueueStats {
+        QueueStats {
+            region: region.to_string(),
+            difficulty: 1,
+            players,
+            average_wait_secs: longest_wait_secs as f64 / 2.,
+            longest_wait_secs,
+            skill_spread: 0.,
+        }
+    }
+
+    #[test]
+    fn queues_past_the_sla_starve() {
+        let stats = TickStats {
+            at: 1000,
+            matches_formed: 0,
+            queues: vec![
+                queue("SA", 2, 400),
+                queue("SA:event", 1, 900),
+                queue("US", 40, 60),
+                queue("CAN", 5, 30),
+            ],
+        };
+
+        let starvation = detect(&stats, 300);
+
+        assert_eq!(starvation.queues.len(), 2);
+        assert!(starvation.contains("SA:1") && starvation.contains("SA:event:1"));
+        assert_eq!(starvation.regions(), BTreeSet::from(["SA"]));
+        assert_eq!(starvation.busiest_region.as_deref(), Some("US"));
+        assert!(detect(&stats, 1000).queues.is_empty());
+    }
+
+    #[test]
+    fn starved_regions_are_relaxed() {
+        let starvation = Starvation {
+            queues: vec![StarvedQueue {
+                region: "SA".to_string(),
+                difficulty: 1,
+                players: 2,
+                longest_wait_secs: 400,
+            }],
+            busiest_region: Some("US".to_string()),
+        };
+        let mut tunings = RegionTunings(BTreeMap::from([(
+            "US".to_string(),
+            RegionTuning::default(),
+        )]));
+        let alerts_only = StarvationPolicy::default();
+        let policy = StarvationPolicy {
+            sla_secs: 120,
+            relaxations: parse_relaxations("cross_region, bot_fill").unwrap(),
+        };
+
+        alerts_only.relax(&mut tunings, &starvation);
+        assert_eq!(tunings.get("SA"), RegionTuning::default());
+        policy.relax(&mut tunings, &starvation);
+
+        let relaxed = tunings.get("SA");
+        assert_eq!(relaxed.fallback_region.as_deref(), Some("US"));
+        assert_eq!(relaxed.fallback_after_secs, 120);
+        assert_eq!(relaxed.min_players, Some(1));
+        assert_eq!(relaxed.close_after_secs, 120);
+        assert_eq!(tunings.get("US"), RegionTuning::default());
+        assert_eq!(policy.reason(), "cross_region,bot_fill");
+        assert!(parse_relaxations("cross_region,bots").is_err());
+    }
+}
@@ -0,0 +1,224 @@
+//! In-memory [`QueueStore`] and [`MatchStore`]. Nothing expires: the `ttl` of the writes is
+//! ignored. Queue entries sharing a join time rank by player id, Redis ranks them by their bytes.
+
+use std::{
+    collections::HashMap,
+    sync::{Mutex, MutexGuard, PoisonError},
+};
+
+use uuid::Uuid;
+
+use super::{Error, MatchStore, QueueRank, QueueStore};
+use crate::{
+    lifecycle::MatchState,
+    rpc::{Match, QueuedPlayer, player_queue_key},
+};
+
+#[derive(Debug, Default)]
+struct State {
+    players: HashMap<Uuid, QueuedPlayer>,
+    /// Entries of every queue key, by join time
+    queues: HashMap<String, Vec<(i64, Uuid)>>,
+    matches: HashMap<Uuid, Match>,
+    /// Open matches of every region
+    open: HashMap<String, Vec<Uuid>>,
+    forming: HashMap<Uuid, Uuid>,
+    player_matches: HashMap<Uuid, Uuid>,
+    states: HashMap<Uuid, MatchState>,
+}
+
+#[derive(Debug, Default)]
+pub struct MemoryStore {
+    state: Mutex<State>,
+}
+
+impl MemoryStore {
+    fn state(&self) -> MutexGuard<'_, State> {
+        self.state.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    /// Stores `player` and queues it by join time, in place of a previous entry
+    pub fn enqueue(&self, player: &QueuedPlayer) {
+        let mut state = self.state();
+        if let Some(previous) = state.players.insert(player.player_id, player.clone()) {
+            state.dequeue(&previous);
+        }
+        let queue = state.queues.entry(player_queue_key(player)).or_default();
+        queue.push((player.join_time, player.player_id));
+        queue.sort_unstable();
+    }
+}
+
+impl State {
+    fn dequeue(&mut self, player: &QueuedPlayer) {
+        if let Some(queue) = self.queues.get_mut(&player_queue_key(player)) {
+            queue.retain(|(_, player_id)| *player_id != player.player_id);
+        }
+    }
+}
+
+#[tonic::async_trait]
+impl QueueStore for MemoryStore {
+    async fn player(&self, player_id: &Uuid) -> Result<Option<QueuedPlayer>, Error> {
+        Ok(self.state().players.get(player_id).cloned())
+    }
+
+    async fn queue_rank(&self, player_id: &Uuid) -> Result<Option<QueueRank>, Error> {
+        let state = self.state();
+        let Some(player) = state.players.get(player_id) else {
+            return Ok(None);
+        };
+        let queue = state
+            .queues
+            .get(&player_queue_key(player))
+            .map(Vec::as_slice)
+            .unwrap_or_default();
+
+        Ok(Some(QueueRank {
+            rank: queue
+                .iter()
+                .position(|(_, queued)| queued == player_id)
+                .map(|rank| rank as u32),
+            size: queue.len() as u32,
+        }))
+    }
+}
+
+#[tonic::async_trait]
+impl MatchStore for MemoryStore {
+    async fn open_match(&self, a_match: &Match, _ttl: u64) -> Result<(), Error> {
+        let mut state = self.state();
+        let match_state = a_match.state();
+        state.states.insert(a_match.id, match_state);
+        for player in &a_match.players {
+            state.forming.insert(player.player_id, a_match.id);
+            if match_state.is_terminal() {
+                state.player_matches.remove(&player.player_id);
+            } else {
+                state.player_matches.insert(player.player_id, a_match.id);
+            }
+        }
+        let open = state.open.entry(a_match.region.clone()).or_default();
+        if !open.contains(&a_match.id) {
+            open.push(a_match.id);
+        }
+        state.matches.insert(a_match.id, a_match.clone());
+
+        Ok(())
+    }
+
+    async fn get_match(&self, match_id: &Uuid) -> Result<Option<Match>, Error> {
+        Ok(self.state().matches.get(match_id).cloned())
+    }
+
+    async fn open_matches(&self, region: &str) -> Result<Vec<Match>, Error> {
+        let state = self.state();
+        let mut matches: Vec<Match> = state
+            .open
+            .get(region)
+            .into_iter()
+            .flatten()
+            .filter_map(|match_id| state.matches.get(match_id).cloned())
+            .collect();
+        matches.sort_by_key(|open| (open.players.len(), open.id));
+
+        Ok(matches)
+    }
+
+    async fn forming_match(&self, player_id: &Uuid) -> Result<Option<Uuid>, Error> {
+        Ok(self.state().forming.get(player_id).copied())
+    }
+
+    async fn match_players(&self, match_id: &Uuid) -> Result<Option<usize>, Error> {
+        Ok(self
+            .state()
+            .matches
+            .get(match_id)
+            .map(|a_match| a_match.players.len()))
+    }
+
+    async fn player_match(&self, player_id: &Uuid) -> Result<Option<Uuid>, Error> {
+        Ok(self.state().player_matches.get(player_id).copied())
+    }
+
+    async fn match_state(&self, match_id: &Uuid) -> Result<Option<MatchState>, Error> {
+        Ok(self.state().states.get(match_id).copied())
+    }
+
+    async fn remove_match(&self, a_match: &Match) -> Result<(), Error> {
+        let mut state = self.state();
+        state.matches.remove(&a_match.id);
+        state.states.remove(&a_match.id);
+        if let Some(open) = state.open.get_mut(&a_match.region) {
+            open.retain(|match_id| *match_id != a_match.id);
+        }
+        for player in &a_match.players {
+            state.forming.remove(&player.player_id);
+            state.player_matches.remove(&player.player_id);
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use skillratings::mhth::MhthRating;
+
+    use super::*;
+    use crate::rpc::matchmaking::Player;
+
+    fn queued(join_time: i64) -> QueuedPlayer {
+        let player: QueuedPlayer = (
+            Uuid::new_v4(),
+            Player {
+                region: "CAN".to_string(),
+                ..Default::default()
+            },
+            MhthRating::default(),
+        )
+            .into();
+
+        player.joined_at(join_time)
+    }
+
+    #[tokio::test]
+    async fn queue_ranks_by_join_time() {
+        let store = MemoryStore::default();
+        let (first, second) = (queued(10), queued(20));
+        store.enqueue(&second);
+        store.enqueue(&first);
+        store.enqueue(&second);
+
+        let rank = store.queue_rank(&second.player_id).await.unwrap();
+
+        assert_eq!(
+            rank,
+            Some(QueueRank {
+                rank: Some(1),
+                size: 2
+            })
+        );
+        assert_eq!(store.queue_rank(&Uuid::new_v4()).await.unwrap(), None);
+    }
+
+    #[tokio::test]
+    async fn matches_are_indexed_until_removed() {
+        let store = MemoryStore::default();
+        let (host, guest) = (queued(10), queued(20));
+        let a_match = Match::host(&host, std::slice::from_ref(&guest)).unwrap();
+
+        store.open_match(&a_match, 7200).await.unwrap();
+        let open = store.open_matches("CAN").await.unwrap();
+        let forming = store.forming_match(&guest.player_id).await.unwrap();
+        let state = store.match_state(&a_match.id).await.unwrap();
+        store.remove_match(&a_match).await.unwrap();
+
+        assert_eq!(open, vec![a_match.clone()]);
+        assert_eq!(forming, Some(a_match.id));
+        assert_eq!(state, Some(MatchState::Forming));
+        assert_eq!(store.get_match(&a_match.id).await.unwrap(), None);
+        assert_eq!(store.player_match(&guest.player_id).await.unwrap(), None);
+        assert!(store.open_matches("CAN").await.unwrap().is_empty());
+    }
+}
@@ -0,0 +1,675 @@
+//! Redis data model. Queued players and forming matches are hashes: the encoded value lives in
+//! the [`DATA`] field and the fields the hot paths check are kept next to it, so e.g. the size of
+//! a forming match is read without decoding its players. Two index hashes map every matched
+//! player to its match ([`player_match_index`]) and every match to its [`MatchState`]
+//! ([`match_state_index`]), their fields expire with the matches.
+//!
+//! The memory and latency impact is measured in `docs/data_model.md`. The hash fields are written
+//! with `HSETEX`, added in Redis 8.0, see [`check_server_version`].
+//!
+//! [`QueueStore`] and [`MatchStore`] wrap the queue reads and match operations of the server and
+//! worker, [`RedisStore`] runs them on Redis and [`MemoryStore`] in memory, for tests and local
+//! runs without a Redis. The pipelines mixing them with other keys still use the helpers of this
+//! module directly.
+
+use std::fmt::Debug;
+
+use redis::{
+    AsyncCommands, ErrorKind, Pipeline, RedisError, RedisResult, aio::MultiplexedConnection,
+};
+use uuid::Uuid;
+
+use crate::{
+    codec,
+    lifecycle::MatchState,
+    namespace,
+    rpc::{
+        Match, QueuedPlayer, forming_match_key, match_id_key, open_matches_key,
+        player_create_match_key, player_key, player_queue_key, player_raid_key, player_versus_key,
+        server::MATCH_LIFETIME,
+    },
+};
+
+mod memory;
+
+pub use memory::MemoryStore;
+
+pub const INDEX: &str = "index";
+/// Encoded [`QueuedPlayer`] or [`Match`]
+pub const DATA: &str = "data";
+pub const REGION: &str = "region";
+/// Seconds since the game epoch the player joined
+pub const JOIN_TIME: &str = "join_time";
+pub const HOST: &str = "host";
+/// Players of the match
+pub const PLAYERS: &str = "players";
+pub const STATE: &str = "state";
+/// Oldest Redis with `HSETEX`, as `(major, minor)`
+pub const MIN_SERVER_VERSION: (u32, u32) = (8, 0);
+
+/// Match id of every matched player
+pub fn player_match_index() -> String {
+    namespace::key(format_args!("{INDEX}:player_match"))
+}
+
+/// [`MatchState`] of every match
+pub fn match_state_index() -> String {
+    namespace::key(format_args!("{INDEX}:match_state"))
+}
+
+/// Fails when the Redis server is older than [`MIN_SERVER_VERSION`], so a missing `HSETEX` is
+/// reported at startup rather than by the first queued player
+pub async fn check_server_version(conn: &mut MultiplexedConnection) -> RedisResult<()> {
+    let info: String = redis::cmd("INFO").arg("server").query_async(conn).await?;
+    match parse_server_version(&info) {
+        Some(version) if version >= MIN_SERVER_VERSION => Ok(()),
+        version => Err(RedisError::from((
+            ErrorKind::ClientError,
+            "unsupported Redis version",
+            format!(
+                "found {version:?}, `HSETEX` requires {}.{}+",
+                MIN_SERVER_VERSION.0, MIN_SERVER_VERSION.1
+            ),
+        ))),
+    }
+}
+
+/// `(major, minor)` of the `redis_version` line of `INFO server`
+fn parse_server_version(info: &str) -> Option<(u32, u32)> {
+    let version = info
+        .lines()
+        .find_map(|line| line.trim().strip_prefix("redis_version:"))?;
+    let mut parts = version.split('.').map(str::parse::<u32>);
+
+    Some((parts.next()?.ok()?, parts.next()?.ok()?))
+}
+
+fn player_fields(player: &QueuedPlayer, encoded: &[u8]) -> [(&'static str, Vec<u8>); 3] {
+    [
+        (DATA, encoded.to_vec()),
+        (REGION, player.region.clone().into_bytes()),
+        (JOIN_TIME, player.join_time.to_string().into_bytes()),
+    ]
+}
+
+fn match_fields(a_match: &Match) -> [(&'static str, Vec<u8>); 5] {
+    [
+        (DATA, codec::encode(a_match)),
+        (REGION, a_match.region.clone().into_bytes()),
+        (HOST, a_match.host_id.to_string().into_bytes()),
+        (PLAYERS, a_match.players.len().to_string().into_bytes()),
+        (STATE, state_name(a_match.state()).as_bytes().to_vec()),
+    ]
+}
+
+/// [`MatchState`] of a [`state_name`]
+pub fn parse_state(name: &str) -> Option<MatchState> {
+    [
+        MatchState::Forming,
+        MatchState::Ready,
+        MatchState::Starting,
+        MatchState::Active,
+        MatchState::Completed,
+        MatchState::Aborted,
+    ]
+    .into_iter()
+    .find(|state| state_name(*state) == name)
+}
+
+pub const fn state_name(state: MatchState) -> &'static str {
+    match state {
+        MatchState::Forming => "forming",
+        MatchState::Ready => "ready",
+        MatchState::Starting => "starting",
+        MatchState::Active => "active",
+        MatchState::Completed => "completed",
+        MatchState::Aborted => "aborted",
+    }
+}
+
+/// Writes the hash of `player`, `encoded` with [`codec::encode`], expiring in `ttl` seconds
+pub fn put_player(pipe: &mut Pipeline, player: &QueuedPlayer, encoded: &[u8], ttl: u64) {
+    let key = player_key(&player.player_id);
+    pipe.hset_multiple(&key, &player_fields(player, encoded))
+        .ignore()
+        .expire(&key, ttl as i64)
+        .ignore();
+}
+
+/// Rewrites the hash of a queued player and keeps its expiry, nothing when the player left
+pub fn update_player(pipe: &mut Pipeline, player: &QueuedPlayer, encoded: &[u8]) {
+    let fields = player_fields(player, encoded);
+    let cmd = pipe
+        .cmd("HSETEX")
+        .arg(player_key(&player.player_id))
+        .arg("FXX")
+        .arg("FIELDS")
+        .arg(fields.len());
+    for (field, value) in &fields {
+        cmd.arg(*field).arg(value);
+    }
+    cmd.ignore();
+}
+
+/// Removes the entries of a player from `queue_keys` by the encoded value of its hash, the bytes
+/// it was queued with. Re-encoding the player gives other bytes once the keys rotated or its
+/// version was bumped, so it cannot be used to remove its entries
+const DEQUEUE: &str = r"
+    local data = redis.call('HGET', KEYS[1], ARGV[1])
+    if not data then
+        return 0
+    end
+    local removed = 0
+    for i = 2, #KEYS do
+        removed = removed + redis.call('ZREM', KEYS[i], data)
+    end
+    return removed
+";
+
+/// Removes the entries of the player from `queue_keys`, see [`DEQUEUE`]. Nothing when its hash
+/// expired, the stale entries cleanup removes those
+pub fn dequeue(pipe: &mut Pipeline, player_id: &Uuid, queue_keys: &[String]) {
+    pipe.cmd("EVAL")
+        .arg(DEQUEUE)
+        .arg(1 + queue_keys.len())
+        .arg(player_key(player_id))
+        .arg(queue_keys)
+        .arg(DATA)
+        .ignore();
+}
+
+/// Removes a player queued with the bytes in `ARGV[2]`: its hash `KEYS[1]`, its raid entry in
+/// `KEYS[2]` and its entries in the other queues of `KEYS`. Nothing when the player rejoined
+/// since the bytes were read, its hash then holds other bytes
+const REMOVE_PLAYER: &str = r"
+    if redis.call('HGET', KEYS[1], ARGV[1]) ~= ARGV[2] then
+        return 0
+    end
+    redis.call('DEL', KEYS[1])
+    if #KEYS > 1 then
+        redis.call('ZREM', KEYS[2], ARGV[3])
+    end
+    for i = 3, #KEYS do
+        redis.call('ZREM', KEYS[i], ARGV[2])
+    end
+    return 1
+";
+
+/// Removes the hash of `player_id` and its entries from every queue, returns whether it was
+/// queued. Undecodable entries only lose their hash, the stale entries cleanup drops them from
+/// the queues. A player rejoining meanwhile stays queued, see [`REMOVE_PLAYER`]
+pub async fn remove_player(
+    conn: &mut MultiplexedConnection,
+    player_id: &Uuid,
+) -> RedisResult<bool> {
+    let Some(data) = player_data(conn, player_id).await? else {
+        return Ok(false);
+    };
+    let mut keys = vec![player_key(player_id)];
+    if let Ok(player) = codec::decode::<QueuedPlayer>(&data) {
+        keys.extend([
+            player_raid_key(&player),
+            player_queue_key(&player),
+            player_create_match_key(&player),
+            player_versus_key(&player),
+        ]);
+    }
+    redis::cmd("EVAL")
+        .arg(REMOVE_PLAYER)
+        .arg(keys.len())
+        .arg(keys)
+        .arg(DATA)
+        .arg(data)
+        .arg(player_id)
+        .query_async(conn)
+        .await
+        .map(|removed: i64| removed == 1)
+}
+
+/// Encoded queued player, see [`put_player`]
+pub async fn player_data(
+    conn: &mut MultiplexedConnection,
+    player_id: &Uuid,
+) -> RedisResult<Option<Vec<u8>>> {
+    conn.hget(player_key(player_id), DATA).await
+}
+
+/// Encoded queued players, in the order of `player_ids`
+pub async fn players_data(
+    conn: &mut MultiplexedConnection,
+    player_ids: &[Uuid],
+) -> RedisResult<Vec<Option<Vec<u8>>>> {
+    let mut pipe = redis::pipe();
+    for player_id in player_ids {
+        pipe.hget(player_key(player_id), DATA);
+    }
+
+    pipe.query_async(conn).await
+}
+
+/// Writes the hash of `a_match` expiring in `ttl` seconds and indexes it
+pub fn put_match(pipe: &mut Pipeline, a_match: &Match, ttl: u64) {
+    let key = match_id_key(&a_match.id);
+    pipe.hset_multiple(&key, &match_fields(a_match))
+        .ignore()
+        .expire(&key, ttl as i64)
+        .ignore();
+    index_match(pipe, a_match);
+}
+
+/// Encoded match, see [`put_match`]
+pub async fn match_data(
+    conn: &mut MultiplexedConnection,
+    match_id: &Uuid,
+) -> RedisResult<Option<Vec<u8>>> {
+    conn.hget(match_id_key(match_id), DATA).await
+}
+
+/// Encoded matches, in the order of `match_ids`
+pub async fn matches_data(
+    conn: &mut MultiplexedConnection,
+    match_ids: &[Uuid],
+) -> RedisResult<Vec<Option<Vec<u8>>>> {
+    let mut pipe = redis::pipe();
+    for match_id in match_ids {
+        pipe.hget(match_id_key(match_id), DATA);
+    }
+
+    pipe.query_async(conn).await
+}
+
+/// Players of a stored match, `None` when it expired
+pub async fn match_players(
+    conn: &mut MultiplexedConnection,
+    match_id: &Uuid,
+) -> RedisResult<Option<usize>> {
+    conn.hget(match_id_key(match_id), PLAYERS).await
+}
+
+/// Deletes the hash of `a_match` and its index fields
+pub fn remove_match(pipe: &mut Pipeline, a_match: &Match) {
+    pipe.del(match_id_key(&a_match.id))
+        .ignore()
+        .hdel(match_state_index(), a_match.id)
+        .ignore();
+    let player_ids: Vec<Uuid> = a_match.players.iter().map(|p| p.player_id).collect();
+    unindex_players(pipe, &player_ids);
+}
+
+/// Indexes the state of `a_match`, and its players until it completed or aborted. The fields
+/// expire with the matches.
+pub fn index_match(pipe: &mut Pipeline, a_match: &Match) {
+    let state = a_match.state();
+    hset_expiring(
+        pipe,
+        match_state_index(),
+        &[(a_match.id, state_name(state))],
+    );
+    if state.is_terminal() {
+        let player_ids: Vec<Uuid> = a_match.players.iter().map(|p| p.player_id).collect();
+        unindex_players(pipe, &player_ids);
+        return;
+    }
+    let players: Vec<(Uuid, Uuid)> = a_match
+        .players
+        .iter()
+        .map(|player| (player.player_id, a_match.id))
+        .collect();
+    if !players.is_empty() {
+        hset_expiring(pipe, player_match_index(), &players);
+    }
+}
+
+/// Drops `player_ids` from the player index, once they left their match
+pub fn unindex_players(pipe: &mut Pipeline, player_ids: &[Uuid]) {
+    if !player_ids.is_empty() {
+        pipe.hdel(player_match_index(), player_ids).ignore();
+    }
+}
+
+fn hset_expiring<F: redis::ToRedisArgs, V: redis::ToRedisArgs>(
+    pipe: &mut Pipeline,
+    key: String,
+    fields: &[(F, V)],
+) {
+    let cmd = pipe
+        .cmd("HSETEX")
+        .arg(key)
+        .arg("EX")
+        .arg(*MATCH_LIFETIME)
+        .arg("FIELDS")
+        .arg(fields.len());
+    for (field, value) in fields {
+        cmd.arg(field).arg(value);
+    }
+    cmd.ignore();
+}
+
+/// Match `player_id` was last matched into, see [`index_match`]
+pub async fn player_match(
+    conn: &mut MultiplexedConnection,
+    player_id: &Uuid,
+) -> RedisResult<Option<Uuid>> {
+    conn.hget(player_match_index(), player_id).await
+}
+
+/// Indexed state of `match_id`, `None` once the match expired
+pub async fn match_state(
+    conn: &mut MultiplexedConnection,
+    match_id: &Uuid,
+) -> RedisResult<Option<String>> {
+    conn.hget(match_state_index(), match_id).await
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum Error {
+    #[error(transparent)]
+    Redis(#[from] RedisError),
+    #[error(transparent)]
+    BitcodeDeser(#[from] codec::Error),
+}
+
+/// Rank of a queued player in its queue
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct QueueRank {
+    /// 0 for the longest waiting player, `None` when the entry left the queue
+    pub rank: Option<u32>,
+    pub size: u32,
+}
+
+/// Rank of the stored entry of `player_id` in its queue, `None` when the player is not queued
+pub async fn queue_rank(
+    conn: &mut MultiplexedConnection,
+    player_id: &Uuid,
+) -> Result<Option<QueueRank>, Error> {
+    let Some(data) = player_data(conn, player_id).await? else {
+        return Ok(None);
+    };
+    let Ok(player) = codec::decode::<QueuedPlayer>(&data) else {
+        return Ok(None);
+    };
+    let queue_key = player_queue_key(&player);
+    // the queue holds the stored bytes, re-encoding would miss sealed entries
+    let (rank, size): (Option<u32>, u32) = redis::pipe()
+        .zrank(&queue_key, &data)
+        .zcard(&queue_key)
+        .query_async(conn)
+        .await?;
+
+    Ok(Some(QueueRank { rank, size }))
+}
+
+/// Writes `a_match` expiring in `ttl` seconds, pencils its players in and lists it in the open
+/// matches of its region
+pub fn open_match(pipe: &mut Pipeline, a_match: &Match, ttl: u64) {
+    put_match(pipe, a_match, ttl);
+    for player in &a_match.players {
+        pipe.set_ex(forming_match_key(&player.player_id), a_match.id, ttl)
+            .ignore();
+    }
+    pipe.zadd(
+        open_matches_key(&a_match.region),
+        a_match.id,
+        a_match.players.len(),
+    )
+    .ignore();
+}
+
+/// Open matches of `region` by players, drops the expired ones
+pub async fn open_matches(
+    conn: &mut MultiplexedConnection,
+    region: &str,
+) -> Result<Vec<Match>, Error> {
+    let region_key = open_matches_key(&region.to_string());
+    let match_ids: Vec<Uuid> = conn.zrange(&region_key, 0, -1).await?;
+    if match_ids.is_empty() {
+        return Ok(Vec::new());
+    }
+    let data = matches_data(conn, &match_ids).await?;
+
+    let mut matches = Vec::new();
+    for (match_id, data) in match_ids.iter().zip(data) {
+        match data.and_then(|bits| codec::decode::<Match>(&bits).ok()) {
+            Some(open) => matches.push(open),
+            // expired match data
+            None => conn.zrem(&region_key, match_id).await?,
+        }
+    }
+
+    Ok(matches)
+}
+
+/// Match `player_id` is pencilled into, see [`open_match`]
+pub async fn forming_match(
+    conn: &mut MultiplexedConnection,
+    player_id: &Uuid,
+) -> RedisResult<Option<Uuid>> {
+    conn.get(forming_match_key(player_id)).await
+}
+
+/// Queued players and their queues. Players join with [`crate::rpc::enqueue`], routed by their
+/// join mode, and leave with [`remove_player`]
+#[tonic::async_trait]
+pub trait QueueStore: Debug + Send + Sync {
+    async fn player(&self, player_id: &Uuid) -> Result<Option<QueuedPlayer>, Error>;
+    /// `None` when the player is not queued
+    async fn queue_rank(&self, player_id: &Uuid) -> Result<Option<QueueRank>, Error>;
+}
+
+/// Forming matches, their open slots and indexes
+#[tonic::async_trait]
+pub trait MatchStore: Debug + Send + Sync {
+    /// Stores `a_match` expiring in `ttl` seconds, pencils its players in and lists it in the open
+    /// matches of its region
+    async fn open_match(&self, a_match: &Match, ttl: u64) -> Result<(), Error>;
+    async fn get_match(&self, match_id: &Uuid) -> Result<Option<Match>, Error>;
+    /// Open matches of `region` by players, drops the expired ones
+    async fn open_matches(&self, region: &str) -> Result<Vec<Match>, Error>;
+    /// Match `player_id` is pencilled into
+    async fn forming_match(&self, player_id: &Uuid) -> Result<Option<Uuid>, Error>;
+    /// Players of a stored match, `None` when it expired
+    async fn match_players(&self, match_id: &Uuid) -> Result<Option<usize>, Error>;
+    /// Match `player_id` was last matched into, see [`index_match`]
+    async fn player_match(&self, player_id: &Uuid) -> Result<Option<Uuid>, Error>;
+    async fn match_state(&self, match_id: &Uuid) -> Result<Option<MatchState>, Error>;
+    /// Drops `a_match`, its open slot and its indexes
+    async fn remove_match(&self, a_match: &Match) -> Result<(), Error>;
+}
+
+/// [`QueueStore`] and [`MatchStore`] of a Redis connection, running the helpers of this module
+#[derive(Debug, Clone)]
+pub struct RedisStore {
+    conn: MultiplexedConnection,
+}
+
+impl RedisStore {
+    pub const fn new(conn: MultiplexedConnection) -> Self {
+        Self { conn }
+    }
+}
+
+#[tonic::async_trait]
+impl QueueStore for RedisStore {
+    async fn player(&self, player_id: &Uuid) -> Result<Option<QueuedPlayer>, Error> {
+        Ok(player_data(&mut self.conn.clone(), player_id)
+            .await?
+            .map(|data| codec::decode(&data))
+            .transpose()?)
+    }
+
+    async fn queue_rank(&self, player_id: &Uuid) -> Result<Option<QueueRank>, Error> {
+        queue_rank(&mut self.conn.clone(), player_id).await
+    }
+}
+
+#[tonic::async_trait]
+impl MatchStore for RedisStore {
+    async fn open_match(&self, a_match: &Match, ttl: u64) -> Result<(), Error> {
+        let mut pipe = redis::pipe();
+        pipe.atomic();
+        open_match(&mut pipe, a_match, ttl);
+
+        Ok(pipe.query_async(&mut self.conn.clone()).await?)
+    }
+
+    async fn get_match(&self, match_id: &Uuid) -> Result<Option<Match>, Error> {
+        Ok(match_data(&mut self.conn.clone(), match_id)
+            .await?
+            .map(|data| codec::decode(&data))
+            .transpose()?)
+    }
+
+    async fn open_matches(&self, region: &str) -> Result<Vec<Match>, Error> {
+        open_matches(&mut self.conn.clone(), region).await
+    }
+
+    async fn forming_match(&self, player_id: &Uuid) -> Result<Option<Uuid>, Error> {
+        Ok(forming_match(&mut self.conn.clone(), player_id).await?)
+    }
+
+    async fn match_players(&self, match_id: &Uuid) -> Result<Option<usize>, Error> {
+        Ok(match_players(&mut self.conn.clone(), match_id).await?)
+    }
+
+    async fn player_match(&self, player_id: &Uuid) -> Result<Option<Uuid>, Error> {
+        Ok(player_match(&mut self.conn.clone(), player_id).await?)
+    }
+
+    async fn match_state(&self, match_id: &Uuid) -> Result<Option<MatchState>, Error> {
+        Ok(match_state(&mut self.conn.clone(), match_id)
+            .await?
+            .as_deref()
+            .and_then(parse_state))
+    }
+
+    async fn remove_match(&self, a_match: &Match) -> Result<(), Error> {
+        let mut pipe = redis::pipe();
+        pipe.atomic()
+            .zrem(open_matches_key(&a_match.region), a_match.id)
+            .ignore();
+        remove_match(&mut pipe, a_match);
+        for player in &a_match.players {
+            pipe.del(forming_match_key(&player.player_id)).ignore();
+        }
+
+        Ok(pipe.query_async(&mut self.conn.clone()).await?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use skillratings::mhth::MhthRating;
+
+    use super::*;
+    use crate::{
+        rpc::matchmaking::Player,
+        test_support::{create_redis, redis_client},
+    };
+
+    #[test]
+    fn server_versions_are_parsed() {
+        let info = "# Server\r\nredis_version:8.2.1\r\nredis_mode:standalone\r\n";
+
+        assert_eq!(parse_server_version(info), Some((8, 2)));
+        assert!(parse_server_version("redis_version:7.4.2").unwrap() < MIN_SERVER_VERSION);
+        assert_eq!(parse_server_version("# Server"), None);
+    }
+
+    #[tokio::test]
+    async fn players_and_matches_are_indexed_hashes() {
+        let container = create_redis(6379).await;
+        let host = container.get_host().await.unwrap();
+        let port = container.get_host_port_ipv4(6379).await.unwrap();
+        let client = redis_client(host.to_string(), port);
+        let mut conn = client.get_multiplexed_async_connection().await.unwrap();
+        let queued = |join_mode| -> QueuedPlayer {
+            (
+                Uuid::new_v4(),
+                Player {
+                    join_mode,
+                    region: "CAN".to_string(),
+                    ..Default::default()
+                },
+                MhthRating::default(),
+            )
+                .into()
+        };
+        let (match_host, guest) = (queued(0), queued(1));
+        let mut a_match = Match::host(&match_host, &[]).unwrap();
+        a_match.players.push(guest.clone());
+
+        let mut pipe = redis::pipe();
+        put_player(&mut pipe, &guest, &codec::encode(&guest), 200);
+        put_match(&mut pipe, &a_match, 200);
+        pipe.query_async(&mut conn).await.map(|_: ()| ()).unwrap();
+        let moved = QueuedPlayer {
+            region: "EU".to_string(),
+            ..guest.clone()
+        };
+        let mut pipe = redis::pipe();
+        update_player(&mut pipe, &moved, &codec::encode(&moved));
+        update_player(&mut pipe, &match_host, &codec::encode(&match_host));
+        pipe.query_async(&mut conn).await.map(|_: ()| ()).unwrap();
+
+        let stored = player_data(&mut conn, &guest.player_id).await.unwrap();
+        let region: String = conn
+            .hget(player_key(&guest.player_id), REGION)
+            .await
+            .unwrap();
+        let ttl: i64 = conn.ttl(player_key(&guest.player_id)).await.unwrap();
+        let left: bool = conn
+            .exists(player_key(&match_host.player_id))
+            .await
+            .unwrap();
+        let players = match_players(&mut conn, &a_match.id).await.unwrap();
+        let indexed = player_match(&mut conn, &guest.player_id).await.unwrap();
+        let state = match_state(&mut conn, &a_match.id).await.unwrap();
+        let mut pipe = redis::pipe();
+        remove_match(&mut pipe, &a_match);
+        pipe.query_async(&mut conn).await.map(|_: ()| ()).unwrap();
+        let removed = matches_data(&mut conn, &[a_match.id]).await.unwrap();
+        let unindexed = player_match(&mut conn, &guest.player_id).await.unwrap();
+
+        assert_eq!(stored, Some(codec::encode(&moved)));
+        assert_eq!(region, "EU");
+        assert!(ttl > 0);
+        assert!(!left);
+        assert_eq!(players, Some(2));
+        assert_eq!(indexed, Some(a_match.id));
+        assert_eq!(state.as_deref(), Some("forming"));
+        assert_eq!(removed, vec![None]);
+        assert_eq!(unindexed, None);
+    }
+
+    #[tokio::test]
+    async fn players_are_dequeued_by_their_stored_bytes() {
+        let container = create_redis(6379).await;
+        let host = container.get_host().await.unwrap();
+        let port = container.get_host_port_ipv4(6379).await.unwrap();
+        let client = redis_client(host.to_string(), port);
+        let mut conn = client.get_multiplexed_async_connection().await.unwrap();
+        let player: QueuedPlayer = (
+            Uuid::new_v4(),
+            Player {
+                region: "CAN".to_string(),
+                ..Default::default()
+            },
+            MhthRating::default(),
+        )
+            .into();
+        // bytes sealed with a rotated key or written by an older version
+        let stored = b"sealed-with-a-rotated-key".to_vec();
+
+        let mut pipe = redis::pipe();
+        put_player(&mut pipe, &player, &stored, 200);
+        pipe.zadd(player_queue_key(&player), &stored, 1).ignore();
+        pipe.query_async(&mut conn).await.map(|_: ()| ()).unwrap();
+        let mut pipe = redis::pipe();
+        dequeue(&mut pipe, &player.player_id, &[player_queue_key(&player)]);
+        pipe.query_async(&mut conn).await.map(|_: ()| ()).unwrap();
+        let queue: Vec<Vec<u8>> = conn.zrange(player_queue_key(&player), 0, -1).await.unwrap();
+
+        assert_ne!(codec::encode(&player), stored);
+        assert!(queue.is_empty());
+    }
+}
@@ -0,0 +1,37 @@
+//! Fixtures shared by the tests
+
+use testcontainers::{
+    ContainerAsync, GenericImage, ImageExt,
+    core::{IntoContainerPort, WaitFor},
+    runners::AsyncRunner,
+};
+
+/// Starts a Redis container exposing `port`, it stops when dropped
+pub async fn create_redis(port: u16) -> ContainerAsync<GenericImage> {
+    GenericImage::new("redis", "8.2.1-bookworm")
+        .with_exposed_port(port.tcp())
+        .with_wait_for(WaitFor::message_on_stdout("Ready to accept connections"))
+        .with_env_var("REDIS_PASSWORD", "super-secret-password")
+        .with_env_var("REDIS_USER", "redis_mms_admin")
+        .start()
+        .await
+        .expect("Failed to start Redis")
+}
+
+pub fn redis_client(host: String, port: u16) -> redis::Client {
+    redis::Client::open(format!("redis://{host}:{port}")).unwrap()
+}
+
+/// Starts a Postgres container exposing `port`, it stops when dropped
+#[cfg(feature = "postgres")]
+pub async fn create_postgres(port: u16) -> ContainerAsync<GenericImage> {
+    GenericImage::new("postgres", "17-bookworm")
+        .with_exposed_port(port.tcp())
+        .with_wait_for(WaitFor::message_on_stderr(
+            "database system is ready to accept connections",
+        ))
+        .with_env_var("POSTGRES_PASSWORD", "password")
+        .start()
+        .await
+        .expect("Failed to start Postgres")
+}
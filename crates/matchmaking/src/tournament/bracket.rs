@@ -0,0 +1,356 @@
+use bitcode::{Decode, Encode};
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+use crate::{
+    rpc::matchmaking::{self, BracketSide},
+    tournament::Error,
+};
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, Encode, Decode, PartialEq, Eq)]
+pub enum Slot {
+    /// Waiting for the result of a previous match
+    Pending,
+    /// No party will ever fill this slot
+    Bye,
+    Party(Uuid),
+}
+
+impl Slot {
+    pub const fn party(&self) -> Option<Uuid> {
+        match self {
+            Self::Party(id) => Some(*id),
+            Self::Pending | Self::Bye => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, Encode, Decode, PartialEq)]
+pub struct BracketMatch {
+    pub side: i32,
+    pub round: u32,
+    pub slots: [Slot; 2],
+    pub winner: Option<Slot>,
+    /// Match started through the matchmaking pipeline
+    pub match_id: Option<Uuid>,
+    /// Bracket match index and slot the winner moves to
+    pub winner_to: Option<(usize, usize)>,
+    /// Bracket match index and slot the loser moves to, eliminated when `None`
+    pub loser_to: Option<(usize, usize)>,
+}
+
+impl BracketMatch {
+    fn new(side: BracketSide, round: u32) -> Self {
+        Self {
+            side: side.into(),
+            round,
+            slots: [Slot::Pending; 2],
+            winner: None,
+            match_id: None,
+            winner_to: None,
+            loser_to: None,
+        }
+    }
+
+    pub fn parties(&self) -> Option<[Uuid; 2]> {
+        Some([self.slots[0].party()?, self.slots[1].party()?])
+    }
+}
+
+/// Elimination bracket, the last match is the final
+#[derive(Debug, Clone, Default, Serialize, Deserialize, Encode, Decode, PartialEq)]
+pub struct Bracket {
+    pub matches: Vec<BracketMatch>,
+}
+
+impl Bracket {
+    pub fn single_elimination(parties: &[Uuid]) -> Result<Self, Error> {
+        let (mut bracket, _) = Self::winners_bracket(parties)?;
+        bracket.resolve_byes();
+
+        Ok(bracket)
+    }
+
+    /// Losers of the winners bracket drop to a losers bracket,
+    /// whose winner plays the winners bracket winner in the grand final.
+    pub fn double_elimination(parties: &[Uuid]) -> Result<Self, Error> {
+        let (mut bracket, winners_rounds) = Self::winners_bracket(parties)?;
+        let rounds = winners_rounds.len();
+        let winners_final = winners_rounds[rounds - 1];
+        let grand_final = if rounds == 1 {
+            let grand_final = bracket.push(BracketSide::GrandFinal, 1, 1);
+            bracket.matches[winners_final].loser_to = Some((grand_final, 1));
+            grand_final
+        } else {
+            let losers_rounds: Vec<usize> = (1..=2 * (rounds - 1))
+                .map(|round| {
+                    let count = 1 << (rounds - round.div_ceil(2) - 1);
+                    bracket.push(BracketSide::Losers, round as u32, count)
+                })
+                .collect();
+            for j in 0..1 << (rounds - 1) {
+                bracket.matches[winners_rounds[0] + j].loser_to =
+                    Some((losers_rounds[0] + j / 2, j % 2));
+            }
+            for (round, window) in losers_rounds.windows(2).enumerate() {
+                let [previous, current] = [window[0], window[1]];
+                // `round + 2` is the losers round starting at `current`
+                let count = 1 << (rounds - (round + 2).div_ceil(2) - 1);
+                if (round + 2) % 2 == 0 {
+                    let dropping = winners_rounds[(round + 2) / 2];
+                    for j in 0..count {
+                        bracket.matches[previous + j].winner_to = Some((current + j, 0));
+                        bracket.matches[dropping + j].loser_to = Some((current + j, 1));
+                    }
+                } else {
+                    for j in 0..count * 2 {
+                        bracket.matches[previous + j].winner_to = Some((current + j / 2, j % 2));
+                    }
+                }
+            }
+            let grand_final = bracket.push(BracketSide::GrandFinal, 1, 1);
+            bracket.matches[losers_rounds[losers_rounds.len() - 1]].winner_to =
+                Some((grand_final, 1));
+            grand_final
+        };
+        bracket.matches[winners_final].winner_to = Some((grand_final, 0));
+        bracket.resolve_byes();
+
+        Ok(bracket)
+    }
+
+    /// Returns the bracket and the index of the first match of each winners round
+    fn winners_bracket(parties: &[Uuid]) -> Result<(Self, Vec<usize>), Error> {
+        if parties.len() < 2 {
+            return Err(Error::NotEnoughParties);
+        }
+        let size = parties.len().next_power_of_two();
+        let rounds = size.trailing_zeros();
+        let mut bracket = Self::default();
+        let starts: Vec<usize> = (1..=rounds)
+            .map(|round| bracket.push(BracketSide::Winners, round, size >> round))
+            .collect();
+        for (round, start) in starts.iter().enumerate().skip(1) {
+            let previous = starts[round - 1];
+            for j in 0..size >> round {
+                bracket.matches[previous + j].winner_to = Some((start + j / 2, j % 2));
+            }
+        }
+
+        // Top seeds face the byes
+        let seeds: Vec<Slot> = (0..size)
+            .map(|seed| parties.get(seed).copied().map_or(Slot::Bye, Slot::Party))
+            .collect();
+        for j in 0..size / 2 {
+            bracket.matches[j].slots = [seeds[j], seeds[size - 1 - j]];
+        }
+
+        Ok((bracket, starts))
+    }
+
+    fn push(&mut self, side: BracketSide, round: u32, count: usize) -> usize {
+        let start = self.matches.len();
+        self.matches
+            .extend((0..count).map(|_| BracketMatch::new(side, round)));
+        start
+    }
+
+    /// Matches with both parties known that were not scheduled yet
+    pub fn ready(&self) -> impl Iterator<Item = usize> + '_ {
+        self.matches
+            .iter()
+            .enumerate()
+            .filter(|(_, m)| m.winner.is_none() && m.match_id.is_none() && m.parties().is_some())
+            .map(|(index, _)| index)
+    }
+
+    pub fn next_ready(&self) -> Option<usize> {
+        self.ready().next()
+    }
+
+    pub fn champion(&self) -> Option<Uuid> {
+        self.matches.last()?.winner?.party()
+    }
+
+    pub fn report(&mut self, index: usize, winner: Uuid) -> Result<(), Error> {
+        let bracket_match = self
+            .matches
+            .get(index)
+            .ok_or(Error::UnknownBracketMatch(index))?;
+        if bracket_match.winner.is_some() {
+            return Err(Error::AlreadyDecided(index));
+        }
+        let parties = bracket_match
+            .parties()
+            .ok_or(Error::UnknownBracketMatch(index))?;
+        let loser = match parties {
+            [first, second] if first == winner => second,
+            [first, second] if second == winner => first,
+            _ => return Err(Error::NotInBracketMatch(winner)),
+        };
+        self.decide(index, Slot::Party(winner), Slot::Party(loser));
+        self.resolve_byes();
+
+        Ok(())
+    }
+
+    fn decide(&mut self, index: usize, winner: Slot, loser: Slot) {
+        let bracket_match = &mut self.matches[index];
+        bracket_match.winner = Some(winner);
+        let (winner_to, loser_to) = (bracket_match.winner_to, bracket_match.loser_to);
+        if let Some((next, slot)) = winner_to {
+            self.matches[next].slots[slot] = winner;
+        }
+        if let Some((next, slot)) = loser_to {
+            self.matches[next].slots[slot] = loser;
+        }
+    }
+
+    /// Advances parties facing a bye until every undecided match waits for a result
+    fn resolve_byes(&mut self) {
+        while let Some((index, winner)) =
+            self.matches
+                .iter()
+                .enumerate()
+                .find_map(|(index, m)| match (m.winner, m.slots) {
+                    (None, [Slot::Bye, other] | [other, Slot::Bye]) if other != Slot::Pending => {
+                        Some((index, other))
+                    }
+                    _ => None,
+                })
+        {
+            self.decide(index, winner, Slot::Bye);
+        }
+    }
+}
+
+impl From<(usize, &BracketMatch)> for matchmaking::BracketMatch {
+    fn from((index, value): (usize, &BracketMatch)) -> Self {
+        Self {
+            index: index as u32,
+            side: value.side,
+            round: value.round,
+            party_ids: value
+                .slots
+                .iter()
+                .map(|slot| slot.party().map(|id| id.to_string()).unwrap_or_default())
+                .collect(),
+            winner_party_id: value
+                .winner
+                .and_then(|winner| winner.party())
+                .map(|id| id.to_string())
+                .unwrap_or_default(),
+            match_id: value.match_id.map(|id| id.to_string()).unwrap_or_default(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn single_elimination_of_four() {
+        let parties = parties(4);
+        let mut bracket = Bracket::single_elimination(&parties).unwrap();
+
+        assert_eq!(bracket.matches.len(), 3);
+        assert_eq!(bracket.ready().collect::<Vec<_>>(), vec![0, 1]);
+        assert_eq!(bracket.matches[0].parties(), Some([parties[0], parties[3]]));
+
+        bracket.report(0, parties[3]).unwrap();
+        bracket.report(1, parties[1]).unwrap();
+        assert_eq!(bracket.ready().collect::<Vec<_>>(), vec![2]);
+        bracket.report(2, parties[1]).unwrap();
+
+        assert_eq!(bracket.champion(), Some(parties[1]));
+    }
+
+    #[test]
+    fn byes_advance_top_seeds() {
+        let parties = parties(3);
+        let bracket = Bracket::single_elimination(&parties).unwrap();
+
+        assert_eq!(bracket.matches[0].winner, Some(Slot::Party(parties[0])));
+        assert_eq!(
+            bracket.matches[2].slots,
+            [Slot::Party(parties[0]), Slot::Pending]
+        );
+        assert_eq!(bracket.ready().collect::<Vec<_>>(), vec![1]);
+    }
+
+    #[test]
+    fn report_validates_winner() {
+        let parties = parties(2);
+        let mut bracket = Bracket::single_elimination(&parties).unwrap();
+
+        assert!(matches!(
+            bracket.report(0, Uuid::new_v4()),
+            Err(Error::NotInBracketMatch(_))
+        ));
+        bracket.report(0, parties[0]).unwrap();
+        assert!(matches!(
+            bracket.report(0, parties[0]),
+            Err(Error::AlreadyDecided(0))
+        ));
+        assert!(Bracket::single_elimination(&parties[..1]).is_err());
+    }
+
+    #[test]
+    fn double_elimination_of_two() {
+        let parties = parties(2);
+        let mut bracket = Bracket::double_elimination(&parties).unwrap();
+
+        bracket.report(0, parties[0]).unwrap();
+        assert_eq!(bracket.matches[1].parties(), Some([parties[0], parties[1]]));
+        bracket.report(1, parties[1]).unwrap();
+
+        assert_eq!(bracket.champion(), Some(parties[1]));
+    }
+
+    #[test]
+    fn double_elimination_of_four() {
+        let parties = parties(4);
+        let mut bracket = Bracket::double_elimination(&parties).unwrap();
+        // 3 winners, 2 losers and the grand final
+        assert_eq!(bracket.matches.len(), 6);
+
+        bracket.report(0, parties[0]).unwrap();
+        bracket.report(1, parties[1]).unwrap();
+        // losers round 1: both first round losers
+        assert_eq!(bracket.matches[3].parties(), Some([parties[3], parties[2]]));
+        bracket.report(3, parties[2]).unwrap();
+        bracket.report(2, parties[0]).unwrap();
+        // losers final: losers round winner against the winners final loser
+        assert_eq!(bracket.matches[4].parties(), Some([parties[2], parties[1]]));
+        bracket.report(4, parties[2]).unwrap();
+        assert_eq!(bracket.matches[5].parties(), Some([parties[0], parties[2]]));
+        assert_eq!(bracket.champion(), None);
+        bracket.report(5, parties[2]).unwrap();
+
+        assert_eq!(bracket.champion(), Some(parties[2]));
+    }
+
+    #[test]
+    fn double_elimination_with_byes() {
+        let parties = parties(5);
+        let mut bracket = Bracket::double_elimination(&parties).unwrap();
+        // 7 winners, 6 losers and the grand final
+        assert_eq!(bracket.matches.len(), 14);
+
+        let mut reported = 0;
+        while let Some(index) = bracket.next_ready() {
+            let [first, _] = bracket.matches[index].parties().unwrap();
+            bracket.report(index, first).unwrap();
+            reported += 1;
+        }
+
+        assert!(bracket.champion().is_some());
+        // every party but the champion lost twice, one win per loss
+        assert_eq!(reported, 2 * (parties.len() - 1));
+    }
+
+    fn parties(count: usize) -> Vec<Uuid> {
+        (0..count).map(|_| Uuid::new_v4()).collect()
+    }
+}
@@ -0,0 +1,628 @@
+use std::sync::LazyLock;
+
+use bitcode::{Decode, Encode};
+use redis::{AsyncCommands, RedisError, Script, aio::MultiplexedConnection};
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+use crate::{
+    codec,
+    nakama::{
+        self,
+        endpoints::{
+            CreateTournamentRequest, JoinTournamentRequest, LEADERBOARD_OPERATOR_INCR,
+            WriteTournamentRecordRequest,
+        },
+    },
+    namespace,
+    rpc::{
+        Match,
+        matchmaking::{BracketFormat, TournamentResponse, TournamentStatus},
+    },
+};
+
+pub mod bracket;
+
+pub use bracket::{Bracket, Slot};
+
+pub const TOURNAMENT_KEY: &str = "tournament";
+pub const ACTIVE_TOURNAMENTS: &str = "tournaments:active";
+/// Tournaments open for registration, scored by the time their registration closes
+pub const REGISTRATION_TOURNAMENTS: &str = "tournaments:registration";
+/// Tournaments are kept for a week
+pub const TOURNAMENT_TTL: u64 = 604_800;
+/// Loads of a tournament [`update_tournament`] tries before giving up on concurrent changes
+const UPDATE_ATTEMPTS: usize = 3;
+
+/// Saves the tournament `ARGV[3]` in `KEYS[1]` for `ARGV[4]` seconds and indexes it, when
+/// `ARGV[1]` is `1` only if `KEYS[1]` still holds `ARGV[2]`. `ARGV[6]` is `1` for running
+/// tournaments, indexed in `KEYS[2]`, and `ARGV[7]` the time their registration closes at,
+/// indexed in `KEYS[3]`, empty when it is not open. Returns whether it was saved.
+static SAVE_TOURNAMENT: LazyLock<Script> = LazyLock::new(|| {
+    Script::new(
+        r"
+        if ARGV[1] == '1' and redis.call('GET', KEYS[1]) ~= ARGV[2] then
+            return 0
+        end
+        redis.call('SET', KEYS[1], ARGV[3], 'EX', ARGV[4])
+        if ARGV[6] == '1' then
+            redis.call('SADD', KEYS[2], ARGV[5])
+        else
+            redis.call('SREM', KEYS[2], ARGV[5])
+        end
+        if ARGV[7] ~= '' then
+            redis.call('ZADD', KEYS[3], ARGV[7], ARGV[5])
+        else
+            redis.call('ZREM', KEYS[3], ARGV[5])
+        end
+        return 1
+        ",
+    )
+});
+
+#[derive(Debug, thiserror::Error)]
+pub enum Error {
+    #[error("tournament `{0}` not found")]
+    NotFound(Uuid),
+    #[error("tournament registration is closed")]
+    RegistrationClosed,
+    #[error("tournament is not running")]
+    NotRunning,
+    #[error("party `{0}` is already registered")]
+    AlreadyRegistered(Uuid),
+    #[error("Party is too large for a tournament match, MAX CAPACITY: {max}")]
+    PartyTooLarge { max: usize },
+    #[error("a tournament needs at least 2 parties")]
+    NotEnoughParties,
+    #[error("match `{0}` is not a tournament match")]
+    NotTournamentMatch(Uuid),
+    #[error("bracket match `{0}` is not ready")]
+    UnknownBracketMatch(usize),
+    #[error("bracket match `{0}` already has a winner")]
+    AlreadyDecided(usize),
+    #[error("party `{0}` is not playing this bracket match")]
+    NotInBracketMatch(Uuid),
+    #[error("player `{0}` is not the match host")]
+    NotHost(Uuid),
+    #[error("registration cannot stay open longer than {max} seconds")]
+    RegistrationTooLong { max: u64 },
+    #[error("tournament `{0}` kept changing, retry")]
+    Changed(Uuid),
+    #[error(transparent)]
+    Redis(#[from] RedisError),
+    #[error(transparent)]
+    BitcodeDeser(#[from] codec::Error),
+    #[error(transparent)]
+    Nakama(#[from] nakama::Error),
+}
+
+impl From<Error> for tonic::Status {
+    fn from(value: Error) -> Self {
+        match value {
+            Error::NotFound(_) | Error::NotTournamentMatch(_) => Self::not_found(value.to_string()),
+            Error::RegistrationClosed
+            | Error::NotRunning
+            | Error::NotEnoughParties
+            | Error::PartyTooLarge { .. }
+            | Error::UnknownBracketMatch(_) => Self::failed_precondition(value.to_string()),
+            Error::AlreadyRegistered(_) | Error::AlreadyDecided(_) => {
+                Self::already_exists(value.to_string())
+            }
+            Error::NotInBracketMatch(_) | Error::RegistrationTooLong { .. } => {
+                Self::invalid_argument(value.to_string())
+            }
+            Error::Changed(_) => Self::aborted(value.to_string()),
+            Error::NotHost(_) => Self::permission_denied(value.to_string()),
+            Error::Redis(_) | Error::BitcodeDeser(_) => Self::internal("Failed to load tournament"),
+            Error::Nakama(_) => Self::unavailable("Failed to reach the Nakama tournament"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, Encode, Decode, PartialEq)]
+pub struct Tournament {
+    pub id: Uuid,
+    pub name: String,
+    pub region: String,
+    pub difficulty: i32,
+    pub format: i32,
+    pub status: i32,
+    /// Registered parties, in seeding order
+    pub parties: Vec<Uuid>,
+    pub bracket: Bracket,
+    /// Nakama tournament mirroring the bracket, its players join it and winners score in it
+    pub nakama_id: Option<String>,
+    /// Seconds since the game epoch the registration closes at, `None` until started by an admin
+    pub registration_closes_at: Option<i64>,
+}
+
+impl codec::Versioned for Tournament {}
+
+impl Tournament {
+    /// Both parties of a bracket match share a single match
+    pub const MAX_PARTY_SIZE: usize = Match::MAX_PLAYERS / 2;
+
+    pub fn new(name: String, region: String, difficulty: i32, format: BracketFormat) -> Self {
+        Self {
+            id: Uuid::new_v4(),
+            name,
+            region,
+            difficulty,
+            format: format.into(),
+            status: TournamentStatus::Registration.into(),
+            parties: Vec::new(),
+            bracket: Bracket::default(),
+            nakama_id: None,
+            registration_closes_at: None,
+        }
+    }
+
+    /// Mirrors the tournament in the Nakama tournament `nakama_id`
+    pub fn with_nakama_id(mut self, nakama_id: String) -> Self {
+        self.nakama_id = Some(nakama_id).filter(|id| !id.is_empty());
+        self
+    }
+
+    /// Closes the registration `registration_secs` after `now`, `0` keeps it open until started.
+    /// Fails past [`TOURNAMENT_TTL`], the tournament would expire before its registration closes
+    pub fn with_registration_window(
+        mut self,
+        now: i64,
+        registration_secs: u64,
+    ) -> Result<Self, Error> {
+        if registration_secs > TOURNAMENT_TTL {
+            return Err(Error::RegistrationTooLong {
+                max: TOURNAMENT_TTL,
+            });
+        }
+        self.registration_closes_at =
+            (registration_secs > 0).then(|| now.saturating_add_unsigned(registration_secs));
+        Ok(self)
+    }
+
+    pub fn register(&mut self, party_id: Uuid, party_size: usize) -> Result<(), Error> {
+        if self.status() != TournamentStatus::Registration {
+            return Err(Error::RegistrationClosed);
+        }
+        if self.parties.contains(&party_id) {
+            return Err(Error::AlreadyRegistered(party_id));
+        }
+        if party_size > Self::MAX_PARTY_SIZE {
+            return Err(Error::PartyTooLarge {
+                max: Self::MAX_PARTY_SIZE,
+            });
+        }
+        self.parties.push(party_id);
+
+        Ok(())
+    }
+
+    /// Closes the registration and generates the bracket
+    pub fn start(&mut self) -> Result<(), Error> {
+        if self.status() != TournamentStatus::Registration {
+            return Err(Error::RegistrationClosed);
+        }
+        self.bracket = match self.format() {
+            BracketFormat::SingleElimination => Bracket::single_elimination(&self.parties)?,
+            BracketFormat::DoubleElimination => Bracket::double_elimination(&self.parties)?,
+        };
+        self.status = TournamentStatus::Running.into();
+
+        Ok(())
+    }
+
+    /// Closes the registration once its window ended at `now`: the bracket starts, or the
+    /// tournament finishes without champion when less than 2 parties registered. Returns
+    /// whether the registration closed.
+    pub fn close_registration(&mut self, now: i64) -> Result<bool, Error> {
+        let ended = self
+            .registration_closes_at
+            .is_some_and(|closes_at| closes_at <= now);
+        if !ended || self.status() != TournamentStatus::Registration {
+            return Ok(false);
+        }
+        match self.start() {
+            Ok(()) => {}
+            Err(Error::NotEnoughParties) => self.status = TournamentStatus::Finished.into(),
+            Err(err) => return Err(err),
+        }
+
+        Ok(true)
+    }
+
+    pub fn report(&mut self, index: usize, winner: Uuid) -> Result<(), Error> {
+        if self.status() != TournamentStatus::Running {
+            return Err(Error::NotRunning);
+        }
+        self.bracket.report(index, winner)?;
+        if self.bracket.champion().is_some() {
+            self.status = TournamentStatus::Finished.into();
+        }
+
+        Ok(())
+    }
+
+    /// Nakama tournament to create, scores add up the bracket wins of each player
+    pub fn nakama_tournament(&self) -> Option<CreateTournamentRequest> {
+        Some(CreateTournamentRequest {
+            tournament_id: self.nakama_id.clone()?,
+            title: self.name.clone(),
+            operator: LEADERBOARD_OPERATOR_INCR.to_string(),
+            join_required: true,
+            duration_secs: TOURNAMENT_TTL,
+        })
+    }
+
+    /// Joins the `members` of a registered party to the Nakama tournament
+    pub fn nakama_join(&self, members: &[Uuid]) -> Option<JoinTournamentRequest> {
+        Some(JoinTournamentRequest {
+            tournament_id: self.nakama_id.clone()?,
+            user_ids: members.iter().map(Uuid::to_string).collect(),
+        })
+    }
+
+    /// A point in the Nakama tournament for each of the `members` of a bracket match winner
+    pub fn nakama_win_records(&self, members: &[Uuid]) -> Vec<WriteTournamentRecordRequest> {
+        let Some(tournament_id) = &self.nakama_id else {
+            return Vec::new();
+        };
+
+        members
+            .iter()
+            .map(|member| WriteTournamentRecordRequest {
+                tournament_id: tournament_id.clone(),
+                owner_id: member.to_string(),
+                score: 1,
+            })
+            .collect()
+    }
+
+    pub fn status(&self) -> TournamentStatus {
+        TournamentStatus::try_from(self.status).unwrap_or_default()
+    }
+
+    pub fn format(&self) -> BracketFormat {
+        BracketFormat::try_from(self.format).unwrap_or_default()
+    }
+}
+
+impl From<&Tournament> for TournamentResponse {
+    fn from(value: &Tournament) -> Self {
+        Self {
+            tournament_id: value.id.to_string(),
+            name: value.name.clone(),
+            format: value.format,
+            status: value.status,
+            party_ids: value.parties.iter().map(Uuid::to_string).collect(),
+            matches: value
+                .bracket
+                .matches
+                .iter()
+                .enumerate()
+                .map(Into::into)
+                .collect(),
+            champion_party_id: value
+                .bracket
+                .champion()
+                .map(|id| id.to_string())
+                .unwrap_or_default(),
+            nakama_tournament_id: value.nakama_id.clone().unwrap_or_default(),
+            registration_closes_at: value.registration_closes_at.unwrap_or_default(),
+        }
+    }
+}
+
+pub fn tournament_key(tournament_id: &Uuid) -> String {
+    namespace::key(format_args!("{TOURNAMENT_KEY}:{tournament_id}"))
+}
+
+pub fn tournament_match_key(match_id: &Uuid) -> String {
+    namespace::key(format_args!("{TOURNAMENT_KEY}:match:{match_id}"))
+}
+
+pub fn active_tournaments_key() -> String {
+    namespace::key(ACTIVE_TOURNAMENTS)
+}
+
+pub fn registration_tournaments_key() -> String {
+    namespace::key(REGISTRATION_TOURNAMENTS)
+}
+
+pub async fn get_tournament(
+    conn: &mut MultiplexedConnection,
+    tournament_id: &Uuid,
+) -> Result<Tournament, Error> {
+    Ok(load_tournament(conn, tournament_id).await?.0)
+}
+
+/// The tournament and its stored bytes, to save it only if it did not change since
+async fn load_tournament(
+    conn: &mut MultiplexedConnection,
+    tournament_id: &Uuid,
+) -> Result<(Tournament, Vec<u8>), Error> {
+    let data: Option<Vec<u8>> = conn.get(tournament_key(tournament_id)).await?;
+    let data = data.ok_or(Error::NotFound(*tournament_id))?;
+
+    Ok((codec::decode(&data)?, data))
+}
+
+/// Saves the tournament, running tournaments and registration windows are indexed for the worker
+pub async fn save_tournament(
+    conn: &mut MultiplexedConnection,
+    tournament: &Tournament,
+) -> Result<(), Error> {
+    store_tournament(conn, None, tournament).await?;
+
+    Ok(())
+}
+
+/// Saves the tournament like [`save_tournament`] if it still holds `previous`
+async fn store_tournament(
+    conn: &mut MultiplexedConnection,
+    previous: Option<&[u8]>,
+    tournament: &Tournament,
+) -> Result<bool, Error> {
+    let closes_at = tournament
+        .registration_closes_at
+        .filter(|_| tournament.status() == TournamentStatus::Registration)
+        .map(|closes_at| closes_at.to_string())
+        .unwrap_or_default();
+    let running = tournament.status() == TournamentStatus::Running;
+
+    Ok(SAVE_TOURNAMENT
+        .key(tournament_key(&tournament.id))
+        .key(active_tournaments_key())
+        .key(registration_tournaments_key())
+        .arg(u8::from(previous.is_some()))
+        .arg(previous.unwrap_or_default())
+        .arg(codec::encode(tournament))
+        .arg(TOURNAMENT_TTL)
+        .arg(tournament.id)
+        .arg(u8::from(running))
+        .arg(closes_at)
+        .invoke_async(conn)
+        .await?)
+}
+
+/// Loads the tournament, applies `update` and saves it if no one saved it meanwhile, from a new
+/// load otherwise. Returns the saved tournament and the result of `update`, nothing is saved
+/// when `update` fails.
+pub async fn update_tournament<T>(
+    conn: &mut MultiplexedConnection,
+    tournament_id: &Uuid,
+    mut update: impl FnMut(&mut Tournament) -> Result<T, Error>,
+) -> Result<(Tournament, T), Error> {
+    for _ in 0..UPDATE_ATTEMPTS {
+        let (mut tournament, previous) = load_tournament(conn, tournament_id).await?;
+        let updated = update(&mut tournament)?;
+        if store_tournament(conn, Some(&previous), &tournament).await? {
+            return Ok((tournament, updated));
+        }
+    }
+
+    Err(Error::Changed(*tournament_id))
+}
+
+/// Drops a tournament from the registration index, e.g. once it expired
+pub async fn forget_registration(
+    conn: &mut MultiplexedConnection,
+    tournament_id: &Uuid,
+) -> Result<(), Error> {
+    conn.zrem(registration_tournaments_key(), tournament_id)
+        .await
+        .map_err(Error::from)
+}
+
+pub async fn active_tournaments(conn: &mut MultiplexedConnection) -> Result<Vec<Uuid>, Error> {
+    Ok(conn.smembers(active_tournaments_key()).await?)
+}
+
+/// Tournaments whose registration window ended at `now`
+pub async fn registration_ended(
+    conn: &mut MultiplexedConnection,
+    now: i64,
+) -> Result<Vec<Uuid>, Error> {
+    Ok(conn
+        .zrangebyscore(registration_tournaments_key(), i64::MIN, now)
+        .await?)
+}
+
+/// Links a started match to its tournament bracket match
+pub async fn link_match(
+    conn: &mut MultiplexedConnection,
+    match_id: &Uuid,
+    tournament_id: &Uuid,
+    index: usize,
+) -> Result<(), Error> {
+    conn.set_ex(
+        tournament_match_key(match_id),
+        codec::encode(&(*tournament_id, index as u64)),
+        TOURNAMENT_TTL,
+    )
+    .await
+    .map_err(Error::from)
+}
+
+/// Tournament and bracket match index of a started match
+pub async fn match_bracket(
+    conn: &mut MultiplexedConnection,
+    match_id: &Uuid,
+) -> Result<(Uuid, usize), Error> {
+    let data: Option<Vec<u8>> = conn.get(tournament_match_key(match_id)).await?;
+    let (tournament_id, index): (Uuid, u64) =
+        codec::decode(&data.ok_or(Error::NotTournamentMatch(*match_id))?)?;
+
+    Ok((tournament_id, index as usize))
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+    use crate::test_support::{create_redis, redis_client};
+
+    #[test]
+    fn registration_rules() {
+        let mut tournament = Tournament::new(
+            "Weekend cup".to_string(),
+            "CAN".to_string(),
+            1,
+            BracketFormat::SingleElimination,
+        );
+        let party_id = Uuid::new_v4();
+
+        tournament.register(party_id, 2).unwrap();
+        assert!(matches!(
+            tournament.register(party_id, 2),
+            Err(Error::AlreadyRegistered(_))
+        ));
+        assert!(matches!(
+            tournament.register(Uuid::new_v4(), 3),
+            Err(Error::PartyTooLarge { max: 2 })
+        ));
+        assert!(matches!(tournament.start(), Err(Error::NotEnoughParties)));
+
+        tournament.register(Uuid::new_v4(), 1).unwrap();
+        tournament.start().unwrap();
+        assert_eq!(tournament.status(), TournamentStatus::Running);
+        assert!(matches!(
+            tournament.register(Uuid::new_v4(), 1),
+            Err(Error::RegistrationClosed)
+        ));
+
+        tournament.report(0, party_id).unwrap();
+        assert_eq!(tournament.status(), TournamentStatus::Finished);
+        let response = TournamentResponse::from(&tournament);
+        assert_eq!(response.champion_party_id, party_id.to_string());
+    }
+
+    #[test]
+    fn registration_closes_when_the_window_ends() {
+        let new = || {
+            Tournament::new(
+                "Weekend cup".to_string(),
+                "CAN".to_string(),
+                1,
+                BracketFormat::SingleElimination,
+            )
+            .with_registration_window(1_000, 600)
+            .unwrap()
+        };
+        let mut tournament = new().with_nakama_id("weekend-cup".to_string());
+        tournament.register(Uuid::new_v4(), 1).unwrap();
+        tournament.register(Uuid::new_v4(), 1).unwrap();
+
+        assert!(!tournament.close_registration(1_599).unwrap());
+        assert!(tournament.close_registration(1_600).unwrap());
+        assert_eq!(tournament.status(), TournamentStatus::Running);
+        assert!(!tournament.close_registration(1_700).unwrap());
+        let response = TournamentResponse::from(&tournament);
+        assert_eq!(response.nakama_tournament_id, "weekend-cup");
+        assert_eq!(response.registration_closes_at, 1_600);
+
+        let members = [Uuid::new_v4(), Uuid::new_v4()];
+        let join = tournament.nakama_join(&members).unwrap();
+        assert_eq!(join.tournament_id, "weekend-cup");
+        assert_eq!(
+            join.user_ids,
+            vec![members[0].to_string(), members[1].to_string()]
+        );
+        let records = tournament.nakama_win_records(&members);
+        assert_eq!(records.len(), 2);
+        assert_eq!(records[1].owner_id, members[1].to_string());
+        assert_eq!(records[1].score, 1);
+        assert_eq!(
+            tournament.nakama_tournament().unwrap().operator,
+            LEADERBOARD_OPERATOR_INCR
+        );
+
+        let mut deserted = new();
+        deserted.register(Uuid::new_v4(), 1).unwrap();
+        assert!(deserted.close_registration(1_600).unwrap());
+        assert_eq!(deserted.status(), TournamentStatus::Finished);
+        assert!(deserted.bracket.champion().is_none());
+        // manual tournaments wait for an admin
+        let mut manual = Tournament::new(
+            "Weekend cup".to_string(),
+            "CAN".to_string(),
+            1,
+            BracketFormat::SingleElimination,
+        )
+        .with_registration_window(1_000, 0)
+        .unwrap();
+        assert!(!manual.close_registration(i64::MAX).unwrap());
+        assert!(manual.nakama_tournament().is_none());
+        assert!(manual.nakama_win_records(&members).is_empty());
+        assert!(matches!(
+            new().with_registration_window(1_000, TOURNAMENT_TTL + 1),
+            Err(Error::RegistrationTooLong { .. })
+        ));
+    }
+
+    #[tokio::test]
+    async fn save_and_link_tournament() {
+        let container = create_redis(6379).await;
+        let host = container.get_host().await.unwrap();
+        let port = container.get_host_port_ipv4(6379).await.unwrap();
+        let client = redis_client(host.to_string(), port);
+        let mut conn = client.get_multiplexed_async_connection().await.unwrap();
+        let mut tournament = Tournament::new(
+            "Weekend cup".to_string(),
+            "CAN".to_string(),
+            1,
+            BracketFormat::DoubleElimination,
+        );
+        let open = Tournament::new(
+            "Night cup".to_string(),
+            "CAN".to_string(),
+            1,
+            BracketFormat::SingleElimination,
+        )
+        .with_registration_window(100, 60)
+        .unwrap();
+        save_tournament(&mut conn, &open).await.unwrap();
+        tournament.register(Uuid::new_v4(), 1).unwrap();
+        tournament.register(Uuid::new_v4(), 1).unwrap();
+        tournament.start().unwrap();
+        let match_id = Uuid::new_v4();
+
+        save_tournament(&mut conn, &tournament).await.unwrap();
+        link_match(&mut conn, &match_id, &tournament.id, 1)
+            .await
+            .unwrap();
+        let loaded = get_tournament(&mut conn, &tournament.id).await.unwrap();
+        let active = active_tournaments(&mut conn).await.unwrap();
+        let linked = match_bracket(&mut conn, &match_id).await.unwrap();
+        let missing = match_bracket(&mut conn, &Uuid::new_v4()).await;
+        let still_open = registration_ended(&mut conn, 159).await.unwrap();
+        let ended = registration_ended(&mut conn, 160).await.unwrap();
+        // a save in between makes the update load the tournament again
+        let mut attempts = 0;
+        let (updated, ()) = update_tournament(&mut conn, &open.id, |open| {
+            attempts += 1;
+            open.register(Uuid::new_v4(), 1)
+        })
+        .await
+        .unwrap();
+        let mut stale = updated.clone();
+        stale.register(Uuid::new_v4(), 1).unwrap();
+        let (_, previous) = load_tournament(&mut conn, &open.id).await.unwrap();
+        save_tournament(&mut conn, &stale).await.unwrap();
+        let overwritten = store_tournament(&mut conn, Some(&previous), &updated)
+            .await
+            .unwrap();
+        forget_registration(&mut conn, &open.id).await.unwrap();
+        let forgotten = registration_ended(&mut conn, 160).await.unwrap();
+
+        assert_eq!(loaded, tournament);
+        assert_eq!(active, vec![tournament.id]);
+        assert_eq!(linked, (tournament.id, 1));
+        assert!(matches!(missing, Err(Error::NotTournamentMatch(_))));
+        assert!(still_open.is_empty());
+        assert_eq!(ended, vec![open.id]);
+        assert_eq!(attempts, 1);
+        assert_eq!(updated.parties.len(), 1);
+        assert!(!overwritten);
+        assert!(forgotten.is_empty());
+    }
+}
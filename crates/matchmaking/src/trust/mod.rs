@@ -0,0 +1,131 @@
+use std::sync::Arc;
+
+use chrono::{DateTime, Utc};
+use redis::{RedisError, aio::MultiplexedConnection};
+use uuid::Uuid;
+
+use crate::{
+    nakama::{self, Authenticated, NakamaClient},
+    penalty, reports,
+};
+
+/// Trust lost per report received
+pub const REPORT_PENALTY: f64 = 0.05;
+pub const MAX_REPORT_PENALTY: f64 = 0.5;
+/// Trust lost per abandon in the current abandon window
+pub const ABANDON_PENALTY: f64 = 0.1;
+pub const MAX_ABANDON_PENALTY: f64 = 0.3;
+/// Accounts reach full trust after this many days
+pub const TRUSTED_ACCOUNT_DAYS: i64 = 30;
+/// Trust of a brand new account without reports nor abandons
+pub const NEW_ACCOUNT_TRUST: f64 = 0.5;
+
+#[derive(Debug, thiserror::Error)]
+pub enum Error {
+    #[error(transparent)]
+    Redis(#[from] RedisError),
+    #[error(transparent)]
+    Nakama(#[from] nakama::Error),
+}
+
+impl From<Error> for tonic::Status {
+    fn from(value: Error) -> Self {
+        match value {
+            Error::Redis(_) => Self::internal("Failed to load player trust"),
+            Error::Nakama(_) => Self::internal("Nakama API failed"),
+        }
+    }
+}
+
+/// Player behavior used to derive its trust
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Behavior {
+    pub reports: u64,
+    pub abandons: u64,
+    pub account_age_days: i64,
+}
+
+impl Behavior {
+    /// Trust factor between `0.0` (griefer) and `1.0` (trusted)
+    pub fn trust(&self) -> f64 {
+        let report_penalty = (self.reports as f64 * REPORT_PENALTY).min(MAX_REPORT_PENALTY);
+        let abandon_penalty = (self.abandons as f64 * ABANDON_PENALTY).min(MAX_ABANDON_PENALTY);
+        let age = self.account_age_days.clamp(0, TRUSTED_ACCOUNT_DAYS) as f64;
+        let age_factor =
+            NEW_ACCOUNT_TRUST + (1. - NEW_ACCOUNT_TRUST) * age / TRUSTED_ACCOUNT_DAYS as f64;
+
+        ((1. - report_penalty - abandon_penalty) * age_factor).clamp(0., 1.)
+    }
+}
+
+/// Loads reports and abandons from Redis and the account age from Nakama
+pub async fn behavior(
+    conn: &mut MultiplexedConnection,
+    nakama_client: &NakamaClient<Authenticated>,
+    http_client: Arc<reqwest::Client>,
+    player_id: &Uuid,
+) -> Result<Behavior, Error> {
+    let reports = reports::total(conn, player_id).await?;
+    let abandons = penalty::abandons(conn, player_id).await?;
+    let created_at = nakama_client
+        .get_account_created_at(http_client, &player_id.to_string())
+        .await?;
+
+    Ok(Behavior {
+        reports,
+        abandons,
+        account_age_days: account_age_days(created_at, Utc::now()),
+    })
+}
+
+/// Accounts without creation time are considered new
+pub fn account_age_days(created_at: Option<DateTime<Utc>>, now: DateTime<Utc>) -> i64 {
+    created_at.map_or(0, |created_at| (now - created_at).num_days())
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::Duration;
+
+    use super::*;
+
+    #[test]
+    fn veteran_without_reports_is_trusted() {
+        let behavior = Behavior {
+            account_age_days: 365,
+            ..Default::default()
+        };
+
+        assert_eq!(behavior.trust(), 1.);
+    }
+
+    #[test]
+    fn new_account_is_half_trusted() {
+        assert_eq!(Behavior::default().trust(), NEW_ACCOUNT_TRUST);
+    }
+
+    #[test]
+    fn reports_and_abandons_lower_trust() {
+        let behavior = Behavior {
+            reports: 4,
+            abandons: 1,
+            account_age_days: 365,
+        };
+        assert!((behavior.trust() - 0.7).abs() < f64::EPSILON);
+
+        let griefer = Behavior {
+            reports: 100,
+            abandons: 100,
+            account_age_days: 365,
+        };
+        assert!((griefer.trust() - 0.2).abs() < f64::EPSILON);
+    }
+
+    #[test]
+    fn age_from_creation_time() {
+        let now = Utc::now();
+
+        assert_eq!(account_age_days(None, now), 0);
+        assert_eq!(account_age_days(Some(now - Duration::days(3)), now), 3);
+    }
+}
@@ -0,0 +1,214 @@
+//! Bounds of the fields of a join request. Out-of-range values would otherwise end up in the
+//! queue keys and pools, e.g. a negative party mode shards a queue no worker reads. Rejections are
+//! `InvalidArgument` with an `ErrorInfo` naming the field and a `BadRequest` violation.
+
+use std::collections::HashMap;
+
+use tonic::Code;
+use tonic_types::{ErrorDetails, FieldViolation, StatusExt};
+
+use super::ERROR_DOMAIN;
+use crate::rpc::{
+    Match,
+    matchmaking::{InputDevice, JoinMode, PartyMode, Player, QueueType, VoiceChat},
+};
+
+/// Highest ping accepted, in milliseconds
+pub const MAX_PING: i32 = 1_000;
+
+#[derive(Debug, Clone, PartialEq, Eq, thiserror::Error)]
+pub enum Error {
+    #[error("ping {0} is outside 0..={MAX_PING}")]
+    Ping(i32),
+    #[error("difficulty {difficulty} is outside 0..={max}")]
+    Difficulty { difficulty: i32, max: i32 },
+    #[error("unknown `{field}` {value}")]
+    UnknownVariant { field: &'static str, value: i32 },
+    #[error("region `{0}` is not served")]
+    Region(String),
+    #[error("party of {size} players, at most {max}")]
+    PartySize { size: usize, max: usize },
+}
+
+impl Error {
+    /// `ErrorInfo` reason of the rejection
+    pub const fn reason(&self) -> &'static str {
+        match self {
+            Self::Ping(_) => "PING_OUT_OF_RANGE",
+            Self::Difficulty { .. } => "DIFFICULTY_OUT_OF_RANGE",
+            Self::UnknownVariant { .. } => "UNKNOWN_ENUM_VALUE",
+            Self::Region(_) => "REGION_NOT_SERVED",
+            Self::PartySize { .. } => "PARTY_TOO_LARGE",
+        }
+    }
+
+    /// Request field of the rejection
+    pub const fn field(&self) -> &'static str {
+        match self {
+            Self::Ping(_) => "ping",
+            Self::Difficulty { .. } => "difficulty",
+            Self::UnknownVariant { field, .. } => field,
+            Self::Region(_) => "region",
+            Self::PartySize { .. } => "party_member_id",
+        }
+    }
+}
+
+impl From<Error> for tonic::Status {
+    fn from(value: Error) -> Self {
+        let mut metadata = HashMap::from([("field".to_string(), value.field().to_string())]);
+        match &value {
+            Error::Ping(_) => {
+                metadata.insert("max".to_string(), MAX_PING.to_string());
+            }
+            Error::Difficulty { max, .. } => {
+                metadata.insert("max".to_string(), max.to_string());
+            }
+            Error::PartySize { max, .. } => {
+                metadata.insert("max".to_string(), max.to_string());
+            }
+            Error::UnknownVariant { .. } | Error::Region(_) => {}
+        }
+        let mut details = ErrorDetails::with_error_info(value.reason(), ERROR_DOMAIN, metadata);
+        details.set_bad_request(vec![FieldViolation::new(value.field(), value.to_string())]);
+
+        Self::with_error_details(Code::InvalidArgument, value.to_string(), details)
+    }
+}
+
+/// Checks the ping, enums and party size of `player`, and its difficulty up to `max_difficulty`
+pub fn check_player(player: &Player, max_difficulty: i32) -> Result<(), Error> {
+    if !(0..=MAX_PING).contains(&player.ping) {
+        return Err(Error::Ping(player.ping));
+    }
+    if !(0..=max_difficulty).contains(&player.difficulty) {
+        return Err(Error::Difficulty {
+            difficulty: player.difficulty,
+            max: max_difficulty,
+        });
+    }
+    let unknown = |field, value| Err(Error::UnknownVariant { field, value });
+    if JoinMode::try_from(player.join_mode).is_err() {
+        return unknown("join_mode", player.join_mode);
+    }
+    if PartyMode::try_from(player.party_mode).is_err() {
+        return unknown("party_mode", player.party_mode);
+    }
+    if InputDevice::try_from(player.input_device).is_err() {
+        return unknown("input_device", player.input_device);
+    }
+    if QueueType::try_from(player.queue_type).is_err() {
+        return unknown("queue_type", player.queue_type);
+    }
+    if VoiceChat::try_from(player.voice_chat).is_err() {
+        return unknown("voice_chat", player.voice_chat);
+    }
+    let size = player.party_member_id.len() + 1;
+    if size > Match::MAX_PLAYERS {
+        return Err(Error::PartySize {
+            size,
+            max: Match::MAX_PLAYERS,
+        });
+    }
+
+    Ok(())
+}
+
+/// Checks the region the player resolved to is one of the `served` regions, any non-empty region
+/// is served when none are registered
+pub fn check_region(region: &str, served: &[String]) -> Result<(), Error> {
+    let is_served = if served.is_empty() {
+        !region.trim().is_empty()
+    } else {
+        served.iter().any(|served| served == region)
+    };
+    if !is_served {
+        return Err(Error::Region(region.to_string()));
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn player() -> Player {
+        Player {
+            region: "EU".to_string(),
+            ping: 40,
+            difficulty: 2,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn out_of_range_fields_are_rejected() {
+        assert_eq!(check_player(&player(), 5), Ok(()));
+        assert_eq!(
+            check_player(
+                &Player {
+                    ping: 1_001,
+                    ..player()
+                },
+                5
+            ),
+            Err(Error::Ping(1_001))
+        );
+        assert_eq!(
+            check_player(
+                &Player {
+                    difficulty: 6,
+                    ..player()
+                },
+                5
+            ),
+            Err(Error::Difficulty {
+                difficulty: 6,
+                max: 5
+            })
+        );
+        assert_eq!(
+            check_player(
+                &Player {
+                    party_mode: -1,
+                    ..player()
+                },
+                5
+            ),
+            Err(Error::UnknownVariant {
+                field: "party_mode",
+                value: -1
+            })
+        );
+        assert_eq!(
+            check_player(
+                &Player {
+                    party_member_id: vec![String::new(); Match::MAX_PLAYERS],
+                    ..player()
+                },
+                5
+            ),
+            Err(Error::PartySize {
+                size: Match::MAX_PLAYERS + 1,
+                max: Match::MAX_PLAYERS
+            })
+        );
+    }
+
+    #[test]
+    fn rejections_carry_the_field() {
+        let served = ["EU".to_string(), "US".to_string()];
+        assert_eq!(check_region("US", &served), Ok(()));
+        assert_eq!(check_region("MOON", &[]), Ok(()));
+        assert_eq!(check_region("", &[]), Err(Error::Region(String::new())));
+
+        let status = tonic::Status::from(check_region("MOON", &served).unwrap_err());
+        let info = status.get_details_error_info().unwrap();
+        let violations = status.get_details_bad_request().unwrap().field_violations;
+        assert_eq!(status.code(), Code::InvalidArgument);
+        assert_eq!(info.reason, "REGION_NOT_SERVED");
+        assert_eq!(info.metadata["field"], "region");
+        assert_eq!(violations[0].field, "region");
+    }
+}
@@ -0,0 +1,311 @@
+//! Checks run before a player joins the queue. Deployments plug in their anti-cheat through
+//! [`JoinValidator`], the default [`NakamaValidator`] reads the anti-cheat flag the game backend
+//! stores in Nakama and rejects clients older than [`MIN_CLIENT_VERSION_VAR`]. Rejections carry
+//! an `ErrorInfo` whose `reason` clients map to a message.
+
+use std::{collections::HashMap, fmt::Debug, sync::Arc};
+
+use serde::{Deserialize, Serialize};
+use tonic::{Code, Request};
+use tonic_types::{ErrorDetails, StatusExt};
+use uuid::Uuid;
+
+use crate::nakama::{self, Authenticated, NakamaClient};
+
+pub mod input;
+
+/// Nakama storage of the anti-cheat verdict of a player, written by the anti-cheat service
+pub const ANTI_CHEAT_COLLECTION: &str = "anticheat";
+pub const ANTI_CHEAT_KEY: &str = "status";
+/// Version of the client build, sent by clients on every request
+pub const CLIENT_VERSION_HEADER: &str = "x-client-version";
+/// Env var with the oldest client version allowed to queue, every version queues when unset
+pub const MIN_CLIENT_VERSION_VAR: &str = "MIN_CLIENT_VERSION";
+/// `ErrorInfo` domain of the rejections
+pub const ERROR_DOMAIN: &str = "matchmaking";
+
+#[derive(Debug, thiserror::Error)]
+pub enum Error {
+    #[error("player is flagged by anti-cheat: {0}")]
+    Flagged(String),
+    #[error("client version `{version}` is older than `{minimum}`")]
+    OutdatedClient { version: String, minimum: String },
+    #[error("invalid client version `{0}`")]
+    InvalidVersion(String),
+    #[error(transparent)]
+    Nakama(#[from] nakama::Error),
+}
+
+impl Error {
+    /// `ErrorInfo` reason of the rejection
+    pub const fn reason(&self) -> &'static str {
+        match self {
+            Self::Flagged(_) => "ANTI_CHEAT_FLAGGED",
+            Self::OutdatedClient { .. } => "CLIENT_OUTDATED",
+            Self::InvalidVersion(_) => "CLIENT_VERSION_INVALID",
+            Self::Nakama(_) => "VALIDATION_UNAVAILABLE",
+        }
+    }
+}
+
+impl From<Error> for tonic::Status {
+    fn from(value: Error) -> Self {
+        let (code, metadata) = match &value {
+            Error::Flagged(reason) => (
+                Code::PermissionDenied,
+                HashMap::from([("detail".to_string(), reason.clone())]),
+            ),
+            Error::OutdatedClient { minimum, .. } => (
+                Code::FailedPrecondition,
+                HashMap::from([("minimum_version".to_string(), minimum.clone())]),
+            ),
+            Error::InvalidVersion(_) => (Code::InvalidArgument, HashMap::new()),
+            Error::Nakama(_) => (Code::Unavailable, HashMap::new()),
+        };
+        let message = match value {
+            Error::Nakama(_) => "Failed to validate player".to_string(),
+            _ => value.to_string(),
+        };
+
+        Self::with_error_details(
+            code,
+            message,
+            ErrorDetails::with_error_info(value.reason(), ERROR_DOMAIN, metadata),
+        )
+    }
+}
+
+/// Player asking to join the queue
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct JoinAttempt<'a> {
+    pub player_id: Uuid,
+    /// [`CLIENT_VERSION_HEADER`] of the request
+    pub client_version: Option<&'a str>,
+}
+
+impl<'a> JoinAttempt<'a> {
+    pub fn new<T>(request: &'a Request<T>, player_id: Uuid) -> Self {
+        Self {
+            player_id,
+            client_version: request
+                .metadata()
+                .get(CLIENT_VERSION_HEADER)
+                .and_then(|version| version.to_str().ok()),
+        }
+    }
+}
+
+/// Validation of the players joining the queue, see [`NakamaValidator`]
+#[tonic::async_trait]
+pub trait JoinValidator: Debug + Send + Sync {
+    async fn validate(&self, attempt: &JoinAttempt<'_>) -> Result<(), Error>;
+}
+
+/// Lets every player join, for deployments without anti-cheat
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct NoValidation;
+
+#[tonic::async_trait]
+impl JoinValidator for NoValidation {
+    async fn validate(&self, _: &JoinAttempt<'_>) -> Result<(), Error> {
+        Ok(())
+    }
+}
+
+/// Verdict stored by the anti-cheat service, players without one are not flagged
+#[derive(Debug, Clone, Default, Serialize, Deserialize, PartialEq, Eq)]
+pub struct AntiCheatStatus {
+    #[serde(default)]
+    pub flagged: bool,
+    /// Shown to the player
+    #[serde(default)]
+    pub reason: String,
+}
+
+/// Numeric `major.minor.patch` version, missing parts are `0`
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
+pub struct ClientVersion(Vec<u64>);
+
+impl std::str::FromStr for ClientVersion {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut parts: Vec<u64> = s
+            .trim()
+            .split('.')
+            .map(str::parse)
+            .collect::<Result<_, _>>()
+            .map_err(|_| Error::InvalidVersion(s.to_string()))?;
+        while parts.len() > 1 && parts.last() == Some(&0) {
+            parts.pop();
+        }
+
+        Ok(Self(parts))
+    }
+}
+
+impl std::fmt::Display for ClientVersion {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let parts: Vec<String> = self.0.iter().map(u64::to_string).collect();
+        write!(f, "{}", parts.join("."))
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct NakamaValidator {
+    pub nakama_client: Arc<NakamaClient<Authenticated>>,
+    pub http_client: Arc<reqwest::Client>,
+    pub min_client_version: Option<ClientVersion>,
+}
+
+impl NakamaValidator {
+    /// Reads [`MIN_CLIENT_VERSION_VAR`]
+    pub fn from_env(
+        nakama_client: Arc<NakamaClient<Authenticated>>,
+        http_client: Arc<reqwest::Client>,
+    ) -> Result<Self, Error> {
+        let min_client_version = std::env::var(MIN_CLIENT_VERSION_VAR)
+            .ok()
+            .map(|version| version.parse())
+            .transpose()?;
+
+        Ok(Self {
+            nakama_client,
+            http_client,
+            min_client_version,
+        })
+    }
+
+    fn check_version(&self, version: Option<&str>) -> Result<(), Error> {
+        let Some(minimum) = &self.min_client_version else {
+            return Ok(());
+        };
+        let outdated = || Error::OutdatedClient {
+            version: version.unwrap_or_default().to_string(),
+            minimum: minimum.to_string(),
+        };
+        let version: ClientVersion = version.ok_or_else(outdated)?.parse()?;
+        if version < *minimum {
+            return Err(outdated());
+        }
+
+        Ok(())
+    }
+}
+
+#[tonic::async_trait]
+impl JoinValidator for NakamaValidator {
+    async fn validate(&self, attempt: &JoinAttempt<'_>) -> Result<(), Error> {
+        self.check_version(attempt.client_version)?;
+        let status: Option<AntiCheatStatus> = self
+            .nakama_client
+            .read_storage(
+                self.http_client.clone(),
+                ANTI_CHEAT_COLLECTION,
+                ANTI_CHEAT_KEY,
+                &attempt.player_id.to_string(),
+            )
+            .await?;
+        match status {
+            Some(status) if status.flagged => Err(Error::Flagged(status.reason)),
+            _ => Ok(()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use httpmock::{Method::GET, MockServer};
+    use serde_json::json;
+    use tonic_types::StatusExt;
+
+    use super::*;
+
+    fn validator(port: u16, min_client_version: Option<&str>) -> NakamaValidator {
+        NakamaValidator {
+            nakama_client: Arc::new(auth_client(port)),
+            http_client: Arc::new(reqwest::Client::new()),
+            min_client_version: min_client_version.map(|version| version.parse().unwrap()),
+        }
+    }
+
+    #[test]
+    fn versions_compare_numerically() {
+        let version = |version: &str| version.parse::<ClientVersion>().unwrap();
+
+        assert!(version("1.10.0") > version("1.9.3"));
+        assert_eq!(version("2.0"), version("2"));
+        assert_eq!(version("1.4.0").to_string(), "1.4");
+        assert!("1.x".parse::<ClientVersion>().is_err());
+    }
+
+    #[test]
+    fn outdated_clients_are_rejected_with_the_minimum() {
+        let validator = validator(666, Some("1.4.0"));
+
+        assert!(validator.check_version(Some("1.4.1")).is_ok());
+        let status = tonic::Status::from(validator.check_version(Some("1.3.9")).unwrap_err());
+        let info = status.get_details_error_info().unwrap();
+        assert_eq!(status.code(), Code::FailedPrecondition);
+        assert_eq!(info.reason, "CLIENT_OUTDATED");
+        assert_eq!(info.metadata["minimum_version"], "1.4");
+        assert!(matches!(
+            validator.check_version(None),
+            Err(Error::OutdatedClient { .. })
+        ));
+    }
+
+    #[tokio::test]
+    async fn flagged_players_are_rejected() {
+        let server = MockServer::start_async().await;
+        let flagged_id = Uuid::new_v4();
+        let clean_id = Uuid::new_v4();
+        server
+            .mock_async(|when, then| {
+                when.method(GET).path(format!(
+                    "/v2/console/storage/{ANTI_CHEAT_COLLECTION}/{ANTI_CHEAT_KEY}/{flagged_id}"
+                ));
+                then.status(200)
+                    .header("content-type", "application/json")
+                    .json_body(json!({ "value": "{\"flagged\":true,\"reason\":\"speed hack\"}" }));
+            })
+            .await;
+        server
+            .mock_async(|when, then| {
+                when.method(GET).path(format!(
+                    "/v2/console/storage/{ANTI_CHEAT_COLLECTION}/{ANTI_CHEAT_KEY}/{clean_id}"
+                ));
+                then.status(404);
+            })
+            .await;
+        let validator = validator(server.address().port(), None);
+        let attempt = |player_id| JoinAttempt {
+            player_id,
+            client_version: None,
+        };
+
+        let flagged = validator.validate(&attempt(flagged_id)).await.unwrap_err();
+        let status = tonic::Status::from(flagged);
+
+        assert_eq!(status.code(), Code::PermissionDenied);
+        assert_eq!(
+            status.get_details_error_info().unwrap().reason,
+            "ANTI_CHEAT_FLAGGED"
+        );
+        assert!(validator.validate(&attempt(clean_id)).await.is_ok());
+    }
+
+    fn auth_client(port: u16) -> NakamaClient<Authenticated> {
+        NakamaClient {
+            username: "username".to_string(),
+            password: "password".to_string(),
+            token: Some("super_random_token".to_string()),
+            url: format!("http://127.0.0.1:{port}"),
+            server_key_name: "defaultkey".to_string(),
+            server_key_value: "server_key".to_string(),
+            encryption_key: "encryption_key".to_string(),
+            legacy_until: None,
+            _state: std::marker::PhantomData::<Authenticated>,
+        }
+    }
+}
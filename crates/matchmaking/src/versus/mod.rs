@@ -0,0 +1,167 @@
+//! Two-team PvP matches. Players queued with [`JoinMode::Versus`] are grouped by skill into
+//! two opposing teams of `versus_team_size`, a match is only formed when its quality reaches
+//! `versus_min_quality`, see [`crate::config`].
+
+use skillratings::mhth::{MhthConfig, MhthRating, expected_team_vs_environment};
+use uuid::Uuid;
+
+use crate::{
+    balance,
+    lifecycle::Lifecycle,
+    rpc::{Match, MatchKind, QueuedPlayer, matchmaking::JoinMode},
+};
+
+/// Largest team a PvP match can have
+pub const MAX_TEAM_SIZE: usize = balance::MAX_BALANCED_PLAYERS / 2;
+
+impl QueuedPlayer {
+    pub fn is_versus(&self) -> bool {
+        self.join_mode == i32::from(JoinMode::Versus)
+    }
+}
+
+/// How even a match between the teams is, `1.0` when both teams are expected to win as often
+pub fn quality(first: &[MhthRating], second: &[MhthRating]) -> f64 {
+    let (first, second) = expected_team_vs_environment(first, second, &MhthConfig::new());
+
+    1. - (first - second).abs()
+}
+
+/// Two teams of `team_size` keeping every party together, with their quality
+pub fn teams(players: &[QueuedPlayer], team_size: usize) -> Option<([Vec<Uuid>; 2], f64)> {
+    if team_size == 0 || players.len() != 2 * team_size {
+        return None;
+    }
+    let [first, second] = balance::best_split(players, &balance::parties(players))?;
+    if first.len() != team_size {
+        return None;
+    }
+    let ratings = |team: &[usize]| -> Vec<MhthRating> {
+        team.iter().map(|i| players[*i].skillrating).collect()
+    };
+    let ids = |team: Vec<usize>| -> Vec<Uuid> {
+        team.into_iter().map(|i| players[i].player_id).collect()
+    };
+    let quality = quality(&ratings(&first), &ratings(&second));
+
+    Some(([ids(first), ids(second)], quality))
+}
+
+/// Players with an input device all share it, or every player waited past their input pool
+/// threshold, see [`Match::is_input_fit`]
+pub fn same_input(players: &[QueuedPlayer], now: i64) -> bool {
+    let mut devices = players
+        .iter()
+        .map(|player| player.input_device)
+        .filter(|device| *device != 0);
+    let first = devices.next();
+
+    devices.all(|device| Some(device) == first)
+        || players
+            .iter()
+            .all(|player| now - player.join_time >= player.params.input_pool_secs)
+}
+
+impl Match {
+    /// PvP match between `teams`, hosted by the longest waiting player
+    pub fn versus(players: Vec<QueuedPlayer>, teams: [Vec<Uuid>; 2], now: i64) -> Option<Self> {
+        let host = players.iter().min_by_key(|player| player.join_time)?;
+
+        Some(Self {
+            id: Uuid::new_v4(),
+            region: host.region.clone(),
+            host_id: host.player_id,
+            playlist: host.playlist.clone(),
+            difficulty: host.difficulty,
+            settings: host.match_settings.clone(),
+            voice_chat: 0,
+            experiments: host.experiments.clone(),
+            params: host.params,
+            lifecycle: Lifecycle::forming(now),
+            nakama_match_id: None,
+            game_server: None,
+            challenge: None,
+            datacenter: None,
+            kind: MatchKind::Versus {
+                team_size: teams[0].len(),
+            },
+            squads: teams.into(),
+            players,
+        })
+    }
+
+    pub const fn is_versus(&self) -> bool {
+        matches!(self.kind, MatchKind::Versus { .. })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::rpc::matchmaking::{InputDevice, Player};
+
+    fn player(rating: f64) -> QueuedPlayer {
+        (
+            Uuid::new_v4(),
+            Player {
+                join_mode: JoinMode::Versus.into(),
+                ..Default::default()
+            },
+            MhthRating {
+                rating,
+                ..Default::default()
+            },
+        )
+            .into()
+    }
+
+    #[test]
+    fn even_teams_have_full_quality() {
+        let players = vec![player(30.), player(20.), player(30.), player(20.)];
+
+        let (teams, quality) = teams(&players, 2).unwrap();
+
+        assert_eq!(teams[0].len(), 2);
+        assert_eq!(teams[1].len(), 2);
+        assert!((quality - 1.).abs() < 1e-9);
+    }
+
+    #[test]
+    fn stacked_party_lowers_quality() {
+        let mut players = vec![player(40.), player(40.), player(10.), player(10.)];
+        players[1].party_ids = vec![players[0].player_id.to_string()];
+
+        let (teams, quality) = teams(&players, 2).unwrap();
+
+        assert!(teams[0].contains(&players[0].player_id));
+        assert!(teams[0].contains(&players[1].player_id));
+        assert!(quality < 0.5);
+    }
+
+    #[test]
+    fn mixed_inputs_wait_for_the_threshold() {
+        let mut players = vec![player(25.), player(25.), player(25.)];
+        players[0].input_device = InputDevice::Controller.into();
+        players[1].input_device = InputDevice::Controller.into();
+        assert!(same_input(&players, 0));
+
+        players[2].input_device = InputDevice::MouseKeyboard.into();
+        assert!(!same_input(&players, 0));
+
+        let threshold = players[0].params.input_pool_secs;
+        players[0].join_time = -threshold;
+        assert!(!same_input(&players, threshold - 1));
+        assert!(same_input(&players, threshold));
+    }
+
+    #[test]
+    fn parties_larger_than_a_team_cannot_play() {
+        let mut players = vec![player(25.), player(25.), player(25.), player(25.)];
+        let host = players[0].player_id.to_string();
+        players[1].party_ids = vec![host.clone()];
+        players[2].party_ids = vec![host];
+
+        assert!(teams(&players, 2).is_none());
+        assert!(teams(&players[..3], 2).is_none());
+    }
+}
@@ -124,7 +124,7 @@ impl LikelihoodFactor {
             .update_message(self.id, Gaussian::with_pi_tau(a * msg.pi, a * msg.tau))
     }
 
-    fn calc_a(&self, gaussian: Gaussian) -> f64 {
+    const fn calc_a(&self, gaussian: Gaussian) -> f64 {
         self.variance.mul_add(gaussian.pi, 1.0).recip()
     }
 }
@@ -1441,7 +1441,7 @@ pub fn expected_score_rating_period(
 /// assert!((new_rank.round() - 0.0).abs() < f64::EPSILON);
 /// assert!((older_rank.round() - 37.0).abs() < f64::EPSILON);
 /// ```
-pub fn get_rank(player: &TrueSkillRating) -> f64 {
+pub const fn get_rank(player: &TrueSkillRating) -> f64 {
     player.uncertainty.mul_add(-3.0, player.rating)
 }
 
@@ -1717,15 +1717,13 @@ fn build_rating_layer(
     starting_id: usize,
 ) -> Vec<PriorFactor> {
     let mut v = Vec::with_capacity(rating_vars.len());
-    let mut i = starting_id;
-    for (var, rating) in rating_vars.iter().zip(flattened_ratings) {
+    for (i, (var, rating)) in (starting_id..).zip(rating_vars.iter().zip(flattened_ratings)) {
         v.push(PriorFactor::new(
             i,
             Rc::clone(var),
             Gaussian::with_mu_sigma(rating.rating, rating.uncertainty),
             tau,
         ));
-        i += 1;
     }
 
     v
@@ -1739,15 +1737,13 @@ fn build_perf_layer(
 ) -> Vec<LikelihoodFactor> {
     let beta_sq = beta.powi(2);
     let mut v = Vec::with_capacity(rating_vars.len());
-    let mut i = starting_id;
-    for (rating_var, perf_var) in rating_vars.iter().zip(perf_vars) {
+    for (i, (rating_var, perf_var)) in (starting_id..).zip(rating_vars.iter().zip(perf_vars)) {
         v.push(LikelihoodFactor::new(
             i,
             Rc::clone(rating_var),
             Rc::clone(perf_var),
             beta_sq,
         ));
-        i += 1;
     }
 
     v
@@ -1760,8 +1756,7 @@ fn build_team_perf_layer(
     starting_id: usize,
 ) -> Vec<SumFactor> {
     let mut v = Vec::with_capacity(team_perf_vars.len());
-    let mut i = starting_id;
-    for (team, team_perf_var) in team_perf_vars.iter().enumerate() {
+    for (i, (team, team_perf_var)) in (starting_id..).zip(team_perf_vars.iter().enumerate()) {
         let start = if team > 0 { team_sizes[team - 1] } else { 0 };
 
         let end = team_sizes[team];
@@ -1774,7 +1769,6 @@ fn build_team_perf_layer(
             child_perf_vars,
             coeffs,
         ));
-        i += 1;
     }
 
     v
@@ -1786,15 +1780,13 @@ fn build_team_diff_layer(
     starting_id: usize,
 ) -> Vec<SumFactor> {
     let mut v = Vec::with_capacity(team_diff_vars.len());
-    let mut i = starting_id;
-    for (team, team_diff_var) in team_diff_vars.iter().enumerate() {
+    for (i, (team, team_diff_var)) in (starting_id..).zip(team_diff_vars.iter().enumerate()) {
         v.push(SumFactor::new(
             i,
             Rc::clone(team_diff_var),
             team_perf_vars[team..(team + 2)].to_vec(),
             vec![1.0, -1.0],
         ));
-        i += 1;
     }
 
     v
@@ -1810,8 +1802,7 @@ fn build_trunc_layer(
     starting_id: usize,
 ) -> Vec<TruncateFactor> {
     let mut v = Vec::with_capacity(team_diff_vars.len());
-    let mut i = starting_id;
-    for (x, team_diff_var) in team_diff_vars.iter().enumerate() {
+    for (i, (x, team_diff_var)) in (starting_id..).zip(team_diff_vars.iter().enumerate()) {
         let size = sorted_teams_and_ranks[x..(x + 2)]
             .iter()
             .map(|v| v.0.len() as f64)
@@ -1831,7 +1822,6 @@ fn build_trunc_layer(
             w_func,
             draw_margin,
         ));
-        i += 1;
     }
 
     v